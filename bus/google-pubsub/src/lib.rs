@@ -2,44 +2,108 @@ mod schemas;
 
 use google_cloud_googleapis::pubsub::v1::PubsubMessage;
 use google_cloud_pubsub::client::Client;
+use graph::components::bus::BackendBusMetrics;
 use graph::components::bus::Bus;
 use graph::components::bus::BusError;
 use graph::components::bus::BusMessage;
+use graph::components::bus::BusPayload;
+use graph::components::bus::CriticalDelivery;
+use graph::components::bus::CriticalTypes;
+use graph::components::metrics::usage::usage_tracker;
+use graph::components::store::EntityType;
 use graph::prelude::async_trait;
+use graph::prelude::futures03::stream::{FuturesUnordered, StreamExt};
 use graph::prelude::serde_json::to_string;
+use graph::prelude::BlockNumber;
 use graph::prelude::Logger;
+use graph::prelude::ENV_VARS;
+use graph::slog::crit;
+use graph::slog::debug;
 use graph::slog::error;
 use graph::slog::warn;
 use graph::tokio::sync::mpsc::UnboundedReceiver;
+use graph::util::backoff::ExponentialBackoff;
+use graph_bus_types::{
+    BlockMarker, BlockModifications, BlockSummary, EntityModification, Envelope, LifecycleEvent,
+    Payload, PlainText,
+};
 use schemas::Demo;
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::string::String;
+use std::time::{Duration, Instant};
+
+const CRITICAL_RETRY_BASE: Duration = Duration::from_secs(1);
+const CRITICAL_RETRY_CEILING: Duration = Duration::from_secs(60);
 
 pub struct GooglePubSub {
     logger: Logger,
     client: Client,
+    critical: Option<CriticalDelivery>,
+    metrics: Option<BackendBusMetrics>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct GraphNodeBusMessage {
-    topic: String,
-    data: Vec<String>,
-}
+fn plain_text(msg: BusMessage) -> Result<PlainText, BusError> {
+    let value = match msg.payload {
+        BusPayload::PlainText(value) => value,
+        _ => return Err(BusError::BadMessage("not a plain text message".to_owned())),
+    };
+
+    let topic = value
+        .iter()
+        .nth(0)
+        .ok_or(BusError::BadMessage("No topic found".to_owned()))?
+        .to_owned();
+
+    let data = value[1..].to_owned();
 
-impl TryFrom<BusMessage> for GraphNodeBusMessage {
-    type Error = BusError;
-    fn try_from(msg: BusMessage) -> Result<Self, Self::Error> {
-        let topic = msg
-            .value
-            .iter()
-            .nth(0)
-            .ok_or(BusError::BadMessage("No topic found".to_owned()))?
-            .to_owned();
+    Ok(PlainText { topic, data })
+}
 
-        let data = msg.value[1..].to_owned();
+/// The topic for a message that graph-node generates itself and the
+/// message as a JSON envelope
+fn envelope(msg: BusMessage) -> Result<(&'static str, Vec<u8>), BusError> {
+    let (topic, payload) = match msg.payload {
+        BusPayload::Modifications {
+            block,
+            modifications,
+        } => (
+            ENV_VARS.bus_modifications_topic.as_str(),
+            Payload::Modifications(BlockModifications {
+                block: BlockMarker::from(&block),
+                modifications: modifications.iter().map(EntityModification::from).collect(),
+            }),
+        ),
+        BusPayload::BlockSummary { block, counts } => (
+            ENV_VARS.bus_block_summaries_topic.as_str(),
+            Payload::BlockSummary(BlockSummary::new(&block, &counts)),
+        ),
+        BusPayload::Synced { block } => (
+            ENV_VARS.bus_lifecycle_topic.as_str(),
+            Payload::Lifecycle(LifecycleEvent::synced(&block)),
+        ),
+        BusPayload::Stuck { block, report } => (
+            ENV_VARS.bus_lifecycle_topic.as_str(),
+            Payload::Lifecycle(LifecycleEvent::stuck(block.as_ref(), report)),
+        ),
+        BusPayload::PlainText(_) => {
+            return Err(BusError::BadMessage("not a generated message".to_owned()))
+        }
+    };
+    let envelope = Envelope::new(msg.subgraph_id, payload);
+    let data = to_string(&envelope)
+        .map_err(|e| BusError::SendModificationError(e.to_string()))?
+        .into_bytes();
+    Ok((topic, data))
+}
 
-        let result = GraphNodeBusMessage { topic, data };
-        Ok(result)
+/// The number of the block that a generated message is about
+fn payload_block(payload: &BusPayload) -> BlockNumber {
+    match payload {
+        BusPayload::Modifications { block, .. }
+        | BusPayload::BlockSummary { block, .. }
+        | BusPayload::Synced { block } => block.number,
+        BusPayload::Stuck { block, .. } => block.as_ref().map_or(0, |block| block.number),
+        BusPayload::PlainText(_) => 0,
     }
 }
 
@@ -47,7 +111,12 @@ impl TryFrom<BusMessage> for GraphNodeBusMessage {
 impl Bus for GooglePubSub {
     async fn new(_: String, logger: Logger) -> GooglePubSub {
         let client = Client::default().await.unwrap();
-        GooglePubSub { client, logger }
+        GooglePubSub {
+            client,
+            logger,
+            critical: None,
+            metrics: None,
+        }
     }
 
     fn get_name(&self) -> &str {
@@ -55,7 +124,8 @@ impl Bus for GooglePubSub {
     }
 
     async fn send_plain_text(&self, bus_msg: BusMessage) -> Result<(), BusError> {
-        let message = GraphNodeBusMessage::try_from(bus_msg)?;
+        let usage = usage_tracker(&bus_msg.subgraph_id);
+        let message = plain_text(bus_msg)?;
 
         warn!(self.logger, "Message received"; "msg" => format!("{:?}", message));
 
@@ -70,6 +140,7 @@ impl Bus for GooglePubSub {
         let publisher = topic.new_publisher(None);
         let mut msg = PubsubMessage::default();
         msg.data = self.parse_data(message)?;
+        usage.bus_bytes(msg.data.len());
 
         let awaiter = publisher.publish(msg).await;
 
@@ -82,27 +153,302 @@ impl Bus for GooglePubSub {
     }
 
     async fn start(&self, mut receiver: UnboundedReceiver<BusMessage>) -> () {
-        while let Some(data) = receiver.recv().await {
+        // The messages of each deployment are sent in order and one at a
+        // time, but independently of those of other deployments, so that
+        // retrying the critical changes of one deployment does not hold up
+        // the others. The queue of a deployment exists while one of its
+        // messages is being sent
+        let mut queues: HashMap<String, VecDeque<BusMessage>> = HashMap::new();
+        let mut sending = FuturesUnordered::new();
+        let mut receiving = true;
+        loop {
+            graph::tokio::select! {
+                msg = receiver.recv(), if receiving => match msg {
+                    Some(msg) => match queues.get_mut(&msg.subgraph_id) {
+                        Some(queue) => queue.push_back(msg),
+                        None => {
+                            queues.insert(msg.subgraph_id.clone(), VecDeque::new());
+                            sending.push(self.send(msg));
+                        }
+                    },
+                    None => receiving = false,
+                },
+                Some(deployment) = sending.next() => {
+                    match queues.get_mut(&deployment).and_then(VecDeque::pop_front) {
+                        Some(msg) => sending.push(self.send(msg)),
+                        None => {
+                            queues.remove(&deployment);
+                        }
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+}
+
+impl GooglePubSub {
+    /// Track the delivery of messages with changes to critical entity
+    /// types with `critical`
+    pub fn with_critical_delivery(mut self, critical: CriticalDelivery) -> Self {
+        self.critical = Some(critical);
+        self
+    }
+
+    /// Record how long serializing and publishing generated messages takes
+    /// with `metrics`
+    pub fn with_bus_metrics(mut self, metrics: BackendBusMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Send `bus_msg` and return the deployment it belongs to. Failures
+    /// are logged
+    async fn send(&self, bus_msg: BusMessage) -> String {
+        let deployment = bus_msg.subgraph_id.clone();
+        let result = if let BusPayload::PlainText(value) = &bus_msg.payload {
             warn!(
                 self.logger,
                 "Sending to Bus";
-                "subgraph_id" => &data.subgraph_id,
-                "value" => format!("{:?}", data.value),
+                "subgraph_id" => &deployment,
+                "value" => format!("{:?}", value),
+            );
+            self.send_plain_text(bus_msg).await
+        } else {
+            let critical = bus_msg
+                .critical
+                .clone()
+                .map(|critical| (critical.in_payload(&bus_msg.payload), critical))
+                .filter(|(entity_types, _)| !entity_types.is_empty());
+            match critical {
+                Some((entity_types, critical)) => {
+                    self.send_critical(bus_msg, critical, entity_types).await;
+                    Ok(())
+                }
+                None => self.send_envelope(bus_msg).await,
+            }
+        };
+
+        if let Err(err) = result {
+            error!(
+                self.logger,
+                "Failed sending to Bus";
+                "reason" => format!("{:?}", err)
+            );
+        }
+        deployment
+    }
+
+    /// Publish messages that graph-node generates itself, rather than
+    /// those sent by mappings, as JSON envelopes to their configured topic
+    async fn send_envelope(&self, bus_msg: BusMessage) -> Result<(), BusError> {
+        let usage = usage_tracker(&bus_msg.subgraph_id);
+        let deployment = bus_msg.subgraph_id.clone();
+        let block = payload_block(&bus_msg.payload);
+        let (topic, data, serialization_time) = self.serialize(bus_msg)?;
+        usage.bus_bytes(data.len());
+        self.publish_batch(&deployment, block, topic, data, serialization_time)
+            .await
+    }
+
+    /// Turn a generated message into its topic and envelope, and record
+    /// how long that took
+    fn serialize(
+        &self,
+        bus_msg: BusMessage,
+    ) -> Result<(&'static str, Vec<u8>, Duration), BusError> {
+        let deployment = bus_msg.subgraph_id.clone();
+        let start = Instant::now();
+        let (topic, data) = envelope(bus_msg)?;
+        let serialization_time = start.elapsed();
+        if let Some(metrics) = &self.metrics {
+            metrics.serialized(&deployment, serialization_time, data.len());
+        }
+        Ok((topic, data, serialization_time))
+    }
+
+    /// Publish a serialized batch of generated messages of `deployment`
+    /// and record how long that took. Batches that took longer than
+    /// `GRAPH_BUS_SLOW_BATCH_LOG_THRESHOLD_MS` to serialize and publish
+    /// are logged
+    async fn publish_batch(
+        &self,
+        deployment: &str,
+        block: BlockNumber,
+        topic: &str,
+        data: Vec<u8>,
+        serialization_time: Duration,
+    ) -> Result<(), BusError> {
+        let bytes = data.len();
+        let start = Instant::now();
+        let result = self.publish(topic, data).await;
+        let publish_time = start.elapsed();
+        if let Some(metrics) = &self.metrics {
+            metrics.published(deployment, publish_time);
+        }
+        if serialization_time + publish_time > ENV_VARS.bus_slow_batch_log_threshold {
+            debug!(
+                self.logger,
+                "Slow batch of generated messages";
+                "subgraph_id" => deployment,
+                "block" => block,
+                "topic" => topic,
+                "bytes" => bytes,
+                "serialization_ms" => serialization_time.as_millis(),
+                "publish_ms" => publish_time.as_millis(),
             );
+        }
+        result
+    }
+
+    /// Publish a message with changes to `entity_types`, which are
+    /// critical types in `critical`. Failures are retried with backoff; if
+    /// all retries fail, the message is dead-lettered
+    async fn send_critical(
+        &self,
+        bus_msg: BusMessage,
+        critical: CriticalTypes,
+        entity_types: BTreeSet<EntityType>,
+    ) {
+        let deployment = bus_msg.subgraph_id.clone();
+        let block = payload_block(&bus_msg.payload);
+        let usage = usage_tracker(&deployment);
+        let (topic, data, serialization_time) = match self.serialize(bus_msg) {
+            Ok(serialized) => serialized,
+            Err(err) => {
+                self.dead_letter(&deployment, &critical, &entity_types, block, None, err)
+                    .await;
+                return;
+            }
+        };
+
+        let mut backoff = ExponentialBackoff::new(CRITICAL_RETRY_BASE, CRITICAL_RETRY_CEILING);
+        loop {
+            usage.bus_bytes(data.len());
+            let result = self
+                .publish_batch(&deployment, block, topic, data.clone(), serialization_time)
+                .await;
+            match result {
+                Ok(()) => {
+                    if let Some(delivery) = &self.critical {
+                        if let Err(err) = delivery
+                            .confirmed(&deployment, &critical, &entity_types, block)
+                            .await
+                        {
+                            error!(
+                                self.logger,
+                                "Failed to record the delivery of critical changes";
+                                "subgraph_id" => &deployment,
+                                "block" => block,
+                                "reason" => err.to_string(),
+                            );
+                        }
+                    }
+                    return;
+                }
+                Err(err) if (backoff.attempt as usize) < ENV_VARS.bus_critical_retries => {
+                    warn!(
+                        self.logger,
+                        "Failed sending critical changes to Bus, retrying";
+                        "subgraph_id" => &deployment,
+                        "block" => block,
+                        "attempt" => backoff.attempt + 1,
+                        "reason" => err.to_string(),
+                    );
+                    backoff.sleep_async().await;
+                }
+                Err(err) => {
+                    self.dead_letter(
+                        &deployment,
+                        &critical,
+                        &entity_types,
+                        block,
+                        Some(data),
+                        err,
+                    )
+                    .await;
+                    return;
+                }
+            }
+        }
+    }
 
-            if let Err(err) = self.send_plain_text(data).await {
-                error!(
+    /// Give up on delivering the changes of `deployment` to `entity_types`
+    /// in `block`. The message is published to the dead letter topic if
+    /// one is configured
+    async fn dead_letter(
+        &self,
+        deployment: &str,
+        critical: &CriticalTypes,
+        entity_types: &BTreeSet<EntityType>,
+        block: BlockNumber,
+        data: Option<Vec<u8>>,
+        err: BusError,
+    ) {
+        let names: Vec<_> = entity_types.iter().map(|t| t.as_str()).collect();
+        crit!(
+            self.logger,
+            "Dead-lettering critical changes that could not be sent to Bus";
+            "subgraph_id" => deployment,
+            "block" => block,
+            "entity_types" => names.join(","),
+            "reason" => err.to_string(),
+        );
+        if let Some(delivery) = &self.critical {
+            if let Err(err) = delivery
+                .dead_lettered(deployment, critical, entity_types, block)
+                .await
+            {
+                crit!(
+                    self.logger,
+                    "Failed to record that critical changes were dead-lettered";
+                    "subgraph_id" => deployment,
+                    "block" => block,
+                    "entity_types" => names.join(","),
+                    "reason" => err.to_string(),
+                );
+            }
+        }
+        if let (Some(topic), Some(data)) = (&ENV_VARS.bus_dead_letter_topic, data) {
+            if let Err(err) = self.publish(topic, data).await {
+                crit!(
                     self.logger,
-                    "Failed sending to Bus";
-                    "reason" => format!("{:?}", err)
+                    "Failed sending critical changes to the dead letter topic";
+                    "subgraph_id" => deployment,
+                    "block" => block,
+                    "topic" => topic,
+                    "reason" => err.to_string(),
                 );
             }
         }
     }
-}
 
-impl GooglePubSub {
-    fn parse_data(&self, message: GraphNodeBusMessage) -> Result<Vec<u8>, BusError> {
+    /// Publish `data` to `topic` and wait for the broker to confirm it
+    async fn publish(&self, topic: &str, data: Vec<u8>) -> Result<(), BusError> {
+        let topic = self.client.topic(topic);
+        if !topic
+            .exists(None, None)
+            .await
+            .map_err(|e| BusError::SendModificationError(e.to_string()))?
+        {
+            return Err(BusError::NoRoutingDefinition);
+        }
+
+        let publisher = topic.new_publisher(None);
+        let mut msg = PubsubMessage::default();
+        msg.data = data;
+
+        publisher
+            .publish(msg)
+            .await
+            .get(None)
+            .await
+            .map_err(|e| BusError::SendModificationError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    fn parse_data(&self, message: PlainText) -> Result<Vec<u8>, BusError> {
         let topic = message.topic.as_str();
         match topic {
             "demo" => {
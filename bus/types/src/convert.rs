@@ -0,0 +1,214 @@
+//! Conversions from graph-node's internal types into the bus types
+
+use graph::components::bus::EntityCounts;
+use graph::components::store::{EntityModification as StoreModification, EntityType};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
+use graph::prelude::{BlockPtr, Entity, Value as StoreValue};
+use std::collections::BTreeMap;
+
+use crate::{
+    BlockMarker, BlockSummary, DeploymentState, EntityModification, EntityTypeCounts, FailureCode,
+    LifecycleEvent, Value,
+};
+
+impl From<&BlockPtr> for BlockMarker {
+    fn from(ptr: &BlockPtr) -> Self {
+        BlockMarker {
+            number: ptr.number,
+            hash: format!("0x{}", ptr.hash_hex()),
+        }
+    }
+}
+
+impl From<&StoreValue> for Value {
+    fn from(value: &StoreValue) -> Self {
+        match value {
+            StoreValue::String(s) => Value::String(s.clone()),
+            StoreValue::Int(i) => Value::Int(*i),
+            StoreValue::BigDecimal(d) => Value::BigDecimal(d.to_string()),
+            StoreValue::Bool(b) => Value::Bool(*b),
+            StoreValue::List(values) => Value::List(values.iter().map(Value::from).collect()),
+            StoreValue::Null => Value::Null,
+            StoreValue::Bytes(bytes) => Value::Bytes(bytes.to_string()),
+            StoreValue::BigInt(n) => Value::BigInt(n.to_string()),
+        }
+    }
+}
+
+fn data(entity: &Entity) -> BTreeMap<String, Value> {
+    entity
+        .clone()
+        .sorted()
+        .into_iter()
+        .map(|(name, value)| (name, Value::from(&value)))
+        .collect()
+}
+
+impl From<&StoreModification> for EntityModification {
+    fn from(modification: &StoreModification) -> Self {
+        let key = modification.entity_ref();
+        let entity_type = key.entity_type.to_string();
+        let entity_id = key.entity_id.to_string();
+        match modification {
+            StoreModification::Insert { data: entity, .. } => EntityModification::Insert {
+                entity_type,
+                entity_id,
+                data: data(entity),
+            },
+            StoreModification::Overwrite { data: entity, .. } => EntityModification::Overwrite {
+                entity_type,
+                entity_id,
+                data: data(entity),
+            },
+            StoreModification::Remove { .. } => EntityModification::Remove {
+                entity_type,
+                entity_id,
+            },
+        }
+    }
+}
+
+impl BlockSummary {
+    pub fn new(block: &BlockPtr, counts: &BTreeMap<EntityType, EntityCounts>) -> Self {
+        let entities = counts
+            .iter()
+            .map(|(entity_type, counts)| EntityTypeCounts {
+                entity_type: entity_type.to_string(),
+                inserts: counts.inserts,
+                updates: counts.updates,
+                removes: counts.removes,
+            })
+            .collect();
+        BlockSummary {
+            block: BlockMarker::from(block),
+            entities,
+        }
+    }
+}
+
+impl From<SubgraphErrorCode> for FailureCode {
+    fn from(code: SubgraphErrorCode) -> Self {
+        match code {
+            SubgraphErrorCode::Unknown => FailureCode::Unknown,
+            SubgraphErrorCode::HandlerError => FailureCode::HandlerError,
+            SubgraphErrorCode::WasmTrap => FailureCode::WasmTrap,
+            SubgraphErrorCode::Timeout => FailureCode::Timeout,
+            SubgraphErrorCode::StoreUnavailable => FailureCode::StoreUnavailable,
+            SubgraphErrorCode::StoreError => FailureCode::StoreError,
+            SubgraphErrorCode::ProviderError => FailureCode::ProviderError,
+            SubgraphErrorCode::ManifestInvalid => FailureCode::ManifestInvalid,
+            SubgraphErrorCode::Incompatible => FailureCode::Incompatible,
+            SubgraphErrorCode::CopyFailed => FailureCode::CopyFailed,
+            SubgraphErrorCode::InvariantViolated => FailureCode::InvariantViolated,
+        }
+    }
+}
+
+impl LifecycleEvent {
+    /// The event for a deployment that reached the chain head for the
+    /// first time at `block`
+    pub fn synced(block: &BlockPtr) -> Self {
+        LifecycleEvent {
+            state: DeploymentState::Synced,
+            block: Some(BlockMarker::from(block)),
+            message: None,
+            code: None,
+        }
+    }
+
+    /// The event for a deployment that has been stuck at `block` with a
+    /// description of what it was doing in `report`
+    pub fn stuck(block: Option<&BlockPtr>, report: String) -> Self {
+        LifecycleEvent {
+            state: DeploymentState::Stuck,
+            block: block.map(BlockMarker::from),
+            message: Some(report),
+            code: None,
+        }
+    }
+
+    /// The event for a deployment that failed with `error`
+    pub fn failed(error: &SubgraphError) -> Self {
+        LifecycleEvent {
+            state: DeploymentState::Failed,
+            block: error.block_ptr.as_ref().map(BlockMarker::from),
+            message: Some(error.message.clone()),
+            code: Some(FailureCode::from(error.code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::components::store::EntityKey;
+    use graph::data::store::scalar::Bytes;
+    use graph::prelude::{BigDecimal, BigInt};
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn convert_modification() {
+        let key = EntityKey::data("Swap".to_string(), "0x01".to_string());
+        let entity = Entity::from(vec![
+            ("id", StoreValue::from("0x01")),
+            ("amount", StoreValue::BigInt(BigInt::from(1_000_000u64))),
+            (
+                "price",
+                StoreValue::BigDecimal(BigDecimal::from_str("1.5").unwrap()),
+            ),
+            ("owner", StoreValue::Bytes(Bytes::from(vec![0xdeu8, 0xad]))),
+            ("tags", StoreValue::List(vec![StoreValue::Null])),
+        ]);
+
+        let modification = EntityModification::from(&StoreModification::Insert {
+            key: key.clone(),
+            data: entity,
+        });
+        let expected = EntityModification::Insert {
+            entity_type: "Swap".to_string(),
+            entity_id: "0x01".to_string(),
+            data: BTreeMap::from_iter(vec![
+                ("id".to_string(), Value::String("0x01".to_string())),
+                ("amount".to_string(), Value::BigInt("1000000".to_string())),
+                ("price".to_string(), Value::BigDecimal("1.5".to_string())),
+                ("owner".to_string(), Value::Bytes("0xdead".to_string())),
+                ("tags".to_string(), Value::List(vec![Value::Null])),
+            ]),
+        };
+        assert_eq!(expected, modification);
+
+        let modification = EntityModification::from(&StoreModification::Remove { key });
+        assert_eq!(
+            EntityModification::Remove {
+                entity_type: "Swap".to_string(),
+                entity_id: "0x01".to_string(),
+            },
+            modification
+        );
+    }
+
+    #[test]
+    fn convert_block_summary() {
+        let block = BlockPtr::from((graph::prelude::web3::types::H256::zero(), 7i32));
+        let counts = BTreeMap::from_iter(vec![(
+            EntityType::from("Swap"),
+            EntityCounts {
+                inserts: 2,
+                updates: 1,
+                removes: 0,
+            },
+        )]);
+        let summary = BlockSummary::new(&block, &counts);
+        assert_eq!(7, summary.block.number);
+        assert_eq!(
+            vec![EntityTypeCounts {
+                entity_type: "Swap".to_string(),
+                inserts: 2,
+                updates: 1,
+                removes: 0,
+            }],
+            summary.entities
+        );
+    }
+}
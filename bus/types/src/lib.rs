@@ -0,0 +1,366 @@
+//! Types for the messages that graph-node publishes to a message bus.
+//!
+//! Every message is an [`Envelope`] serialized as JSON. Consumers can
+//! depend on this crate to parse messages without pulling in all of
+//! graph-node; conversions from graph-node's internal types are only
+//! available with the `graph` feature.
+//!
+//! The wire format is versioned with [`FORMAT_VERSION`], which is sent in
+//! every envelope. Changes that existing consumers can not parse, like
+//! removing or renaming fields or variants, or changing how a value is
+//! encoded, require bumping `FORMAT_VERSION` and the major version of this
+//! crate. Adding optional fields or new variants to the `#[non_exhaustive]`
+//! enums only requires a minor version bump. The fixtures in
+//! `tests/fixtures` are messages in the current format and must keep
+//! parsing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "graph")]
+mod convert;
+
+/// The version of the wire format described by the types in this crate
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The envelope around every message published to the bus
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The version of the wire format; see [`FORMAT_VERSION`]
+    pub version: u32,
+    /// The IPFS hash of the deployment that caused the message
+    pub deployment: String,
+    pub payload: Payload,
+}
+
+impl Envelope {
+    pub fn new(deployment: impl Into<String>, payload: Payload) -> Self {
+        Envelope {
+            version: FORMAT_VERSION,
+            deployment: deployment.into(),
+            payload,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum Payload {
+    /// A message sent by a mapping with `bus.send`
+    PlainText(PlainText),
+    /// The entity changes caused by a block
+    Modifications(BlockModifications),
+    /// A change in the lifecycle of the deployment
+    Lifecycle(LifecycleEvent),
+    /// How many entities of each type a block changed
+    BlockSummary(BlockSummary),
+}
+
+/// A message sent by a mapping with `bus.send`. The first argument of
+/// `bus.send` is the topic, the remaining arguments are the data
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlainText {
+    pub topic: String,
+    pub data: Vec<String>,
+}
+
+/// A block, identified by its number and its hash
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockMarker {
+    pub number: i32,
+    /// The block hash as a hex string with a `0x` prefix
+    pub hash: String,
+}
+
+/// All entity changes that a deployment made when processing `block`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockModifications {
+    pub block: BlockMarker,
+    pub modifications: Vec<EntityModification>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum EntityModification {
+    /// A new entity was created
+    #[serde(rename_all = "camelCase")]
+    Insert {
+        entity_type: String,
+        entity_id: String,
+        data: BTreeMap<String, Value>,
+    },
+    /// An existing entity was replaced by `data`
+    #[serde(rename_all = "camelCase")]
+    Overwrite {
+        entity_type: String,
+        entity_id: String,
+        data: BTreeMap<String, Value>,
+    },
+    /// An entity was removed
+    #[serde(rename_all = "camelCase")]
+    Remove {
+        entity_type: String,
+        entity_id: String,
+    },
+}
+
+impl EntityModification {
+    pub fn entity_type(&self) -> &str {
+        match self {
+            EntityModification::Insert { entity_type, .. }
+            | EntityModification::Overwrite { entity_type, .. }
+            | EntityModification::Remove { entity_type, .. } => entity_type,
+        }
+    }
+
+    pub fn entity_id(&self) -> &str {
+        match self {
+            EntityModification::Insert { entity_id, .. }
+            | EntityModification::Overwrite { entity_id, .. }
+            | EntityModification::Remove { entity_id, .. } => entity_id,
+        }
+    }
+}
+
+/// A compact alternative to [`BlockModifications`] that only contains the
+/// number of changes per entity type caused by `block`. Entity types that
+/// the block did not change are omitted; a summary without any entity types
+/// is sent for blocks without changes if the node is configured to do so
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub block: BlockMarker,
+    pub entities: Vec<EntityTypeCounts>,
+}
+
+/// The number of changes to entities of one type
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityTypeCounts {
+    pub entity_type: String,
+    pub inserts: u32,
+    pub updates: u32,
+    pub removes: u32,
+}
+
+/// The value of an entity attribute. Numbers that do not fit into an
+/// `i32` are encoded as decimal strings, and bytes as hex strings with a
+/// `0x` prefix. This is the same encoding that graph-node uses for its
+/// own `Value` type.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum Value {
+    String(String),
+    Int(i32),
+    BigDecimal(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Null,
+    Bytes(String),
+    BigInt(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum DeploymentState {
+    /// The deployment started indexing on a node
+    Started,
+    /// The deployment reached the chain head for the first time
+    Synced,
+    /// The deployment failed and stopped indexing
+    Failed,
+    /// The deployment was stopped on a node
+    Stopped,
+    /// The deployment stopped advancing although its chain kept moving
+    Stuck,
+}
+
+/// A stable classification of why a deployment failed, the same as the
+/// error codes in graph-node's indexing status. Consumers should match on
+/// the code rather than on the message. Codes that this version of the
+/// crate does not know parse as `Unknown`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum FailureCode {
+    #[serde(other)]
+    Unknown,
+    /// A handler failed deterministically, e.g., by calling `abort`
+    HandlerError,
+    /// The execution of WASM code trapped
+    WasmTrap,
+    /// A handler hit the handler timeout or the host wait timeout
+    Timeout,
+    StoreUnavailable,
+    /// The store reported an error other than being unavailable
+    StoreError,
+    /// A request to a chain provider failed
+    ProviderError,
+    /// The manifest can not be parsed
+    ManifestInvalid,
+    /// The deployment changed incompatibly since it was first started
+    Incompatible,
+    /// Copying or grafting the data of another deployment failed
+    CopyFailed,
+    /// A strict invariant from the manifest was violated
+    InvariantViolated,
+}
+
+/// A change in the lifecycle of a deployment
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LifecycleEvent {
+    pub state: DeploymentState,
+    /// The latest block the deployment had processed at the time of the
+    /// event, if it had processed any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<BlockMarker>,
+    /// Additional explanation, e.g., the error for a failed deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// What caused a failed deployment to fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<FailureCode>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(envelope: Envelope) {
+        let json = serde_json::to_string(&envelope).unwrap();
+        let parsed: Envelope = serde_json::from_str(&json).unwrap();
+        assert_eq!(envelope, parsed, "round trip through {}", json);
+    }
+
+    fn block() -> BlockMarker {
+        BlockMarker {
+            number: 17,
+            hash: "0x8f1b".to_string(),
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::PlainText(PlainText {
+                topic: "demo".to_string(),
+                data: vec!["swap".to_string(), "12".to_string()],
+            }),
+        ));
+    }
+
+    #[test]
+    fn modifications() {
+        let data = BTreeMap::from_iter(vec![
+            ("id".to_string(), Value::String("0x01".to_string())),
+            ("count".to_string(), Value::Int(-3)),
+            (
+                "amount".to_string(),
+                Value::BigInt("123456789012345678901234".into()),
+            ),
+            ("price".to_string(), Value::BigDecimal("1.25".to_string())),
+            ("active".to_string(), Value::Bool(true)),
+            ("owner".to_string(), Value::Bytes("0xdeadbeef".to_string())),
+            ("parent".to_string(), Value::Null),
+            (
+                "tags".to_string(),
+                Value::List(vec![Value::String("a".to_string()), Value::Null]),
+            ),
+        ]);
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::Modifications(BlockModifications {
+                block: block(),
+                modifications: vec![
+                    EntityModification::Insert {
+                        entity_type: "Swap".to_string(),
+                        entity_id: "0x01".to_string(),
+                        data: data.clone(),
+                    },
+                    EntityModification::Overwrite {
+                        entity_type: "Swap".to_string(),
+                        entity_id: "0x01".to_string(),
+                        data,
+                    },
+                    EntityModification::Remove {
+                        entity_type: "Mint".to_string(),
+                        entity_id: "0x02".to_string(),
+                    },
+                ],
+            }),
+        ));
+    }
+
+    #[test]
+    fn lifecycle() {
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::Lifecycle(LifecycleEvent {
+                state: DeploymentState::Started,
+                block: None,
+                message: None,
+                code: None,
+            }),
+        ));
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::Lifecycle(LifecycleEvent {
+                state: DeploymentState::Failed,
+                block: Some(block()),
+                message: Some("handler failed".to_string()),
+                code: Some(FailureCode::HandlerError),
+            }),
+        ));
+    }
+
+    #[test]
+    fn unknown_failure_code() {
+        let json = r#"{"state":"failed","code":"some_future_code"}"#;
+        let event: LifecycleEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(Some(FailureCode::Unknown), event.code);
+    }
+
+    #[test]
+    fn block_summary() {
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::BlockSummary(BlockSummary {
+                block: block(),
+                entities: vec![EntityTypeCounts {
+                    entity_type: "Swap".to_string(),
+                    inserts: 2,
+                    updates: 1,
+                    removes: 0,
+                }],
+            }),
+        ));
+        round_trip(Envelope::new(
+            "QmTest",
+            Payload::BlockSummary(BlockSummary {
+                block: block(),
+                entities: vec![],
+            }),
+        ));
+    }
+
+    #[test]
+    fn wire_format() {
+        // Spot check the encoding; the fixtures cover it more fully
+        let envelope = Envelope::new(
+            "QmTest",
+            Payload::Modifications(BlockModifications {
+                block: block(),
+                modifications: vec![EntityModification::Remove {
+                    entity_type: "Mint".to_string(),
+                    entity_id: "0x02".to_string(),
+                }],
+            }),
+        );
+        assert_eq!(
+            r#"{"version":1,"deployment":"QmTest","payload":{"kind":"modifications","block":{"number":17,"hash":"0x8f1b"},"modifications":[{"op":"remove","entityType":"Mint","entityId":"0x02"}]}}"#,
+            serde_json::to_string(&envelope).unwrap()
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Check that the messages in `tests/fixtures` still parse, and that
+//! serializing them again produces the same JSON. A failure here means
+//! that the wire format changed in a way that affects consumers
+
+use std::fs;
+use std::path::PathBuf;
+
+use graph_bus_types::{Envelope, FORMAT_VERSION};
+
+fn fixtures() -> Vec<(PathBuf, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .map(|path| {
+            let json = fs::read_to_string(&path).unwrap();
+            (path, json)
+        })
+        .collect();
+    fixtures.sort();
+    assert!(
+        !fixtures.is_empty(),
+        "no fixtures found in {}",
+        dir.display()
+    );
+    fixtures
+}
+
+#[test]
+fn fixtures_round_trip() {
+    for (path, json) in fixtures() {
+        let envelope: Envelope = serde_json::from_str(&json)
+            .unwrap_or_else(|e| panic!("failed to parse {}: {}", path.display(), e));
+        assert_eq!(FORMAT_VERSION, envelope.version, "{}", path.display());
+
+        let expected: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let actual = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(expected, actual, "{}", path.display());
+    }
+}
@@ -151,6 +151,7 @@ impl Blockchain for Chain {
         _subgraph_current_block: Option<BlockPtr>,
         _filter: Arc<Self::TriggerFilter>,
         _unified_api_version: UnifiedMappingApiVersion,
+        _reorg_threshold: Option<BlockNumber>,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         panic!("Arweave does not support polling block stream")
     }
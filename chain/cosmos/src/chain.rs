@@ -149,6 +149,7 @@ impl Blockchain for Chain {
         _subgraph_start_block: Option<BlockPtr>,
         _filter: Arc<Self::TriggerFilter>,
         _unified_api_version: UnifiedMappingApiVersion,
+        _reorg_threshold: Option<BlockNumber>,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         panic!("Cosmos does not support polling block stream")
     }
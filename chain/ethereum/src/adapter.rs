@@ -9,9 +9,10 @@ use itertools::Itertools;
 use prost::Message;
 use prost_types::Any;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
+use std::sync::RwLock;
 use thiserror::Error;
 use tiny_keccak::keccak256;
 use web3::types::{Address, Log, H256};
@@ -19,7 +20,10 @@ use web3::types::{Address, Log, H256};
 use graph::prelude::*;
 use graph::{
     blockchain as bc,
-    components::metrics::{CounterVec, GaugeVec, HistogramVec},
+    components::metrics::{
+        usage::{usage_tracker, UsageTracker},
+        CounterVec, GaugeVec, HistogramVec,
+    },
     petgraph::{self, graphmap::GraphMap},
 };
 
@@ -28,6 +32,8 @@ const COMBINED_FILTER_TYPE_URL: &str =
 
 use crate::capabilities::NodeCapabilities;
 use crate::data_source::{BlockHandlerFilter, DataSource};
+use crate::latency::{CallClass, ProviderLatencies};
+use crate::quota::{ProviderBudget, ProviderQuota};
 use crate::{Chain, Mapping, ENV_VARS};
 
 pub type EventSignature = H256;
@@ -731,6 +737,14 @@ pub struct ProviderEthRpcMetrics {
     request_duration: Box<HistogramVec>,
     errors: Box<CounterVec>,
     status: Box<GaugeVec>,
+    requests: Box<CounterVec>,
+    units: Box<GaugeVec>,
+    projected_units: Box<GaugeVec>,
+    /// The request accounting for each provider, keyed by its label
+    quotas: Arc<RwLock<HashMap<String, Arc<ProviderQuota>>>>,
+    /// How quickly each provider answers each class of requests
+    latencies: Arc<ProviderLatencies>,
+    routed: Box<CounterVec>,
 }
 
 impl ProviderEthRpcMetrics {
@@ -758,23 +772,143 @@ impl ProviderEthRpcMetrics {
                 vec![String::from("provider")],
             )
             .unwrap();
+        let requests = registry
+            .new_counter_vec(
+                "eth_rpc_requests",
+                "Counts eth rpc requests",
+                vec![
+                    String::from("network"),
+                    String::from("method"),
+                    String::from("provider"),
+                ],
+            )
+            .unwrap();
+        let units = registry
+            .new_gauge_vec(
+                "eth_rpc_units_consumed",
+                "The units that eth rpc requests cost since the node started",
+                vec![String::from("network"), String::from("provider")],
+            )
+            .unwrap();
+        let projected_units = registry
+            .new_gauge_vec(
+                "eth_rpc_units_projected_monthly",
+                "The units that eth rpc requests will cost in a month at the current rate",
+                vec![String::from("network"), String::from("provider")],
+            )
+            .unwrap();
+        let routed = registry
+            .new_counter_vec(
+                "eth_rpc_routed_requests",
+                "Counts the requests that latency routing sent to each provider",
+                vec![String::from("class"), String::from("provider")],
+            )
+            .unwrap();
         Self {
             request_duration,
             errors,
             status,
+            requests,
+            units,
+            projected_units,
+            quotas: Arc::new(RwLock::new(HashMap::new())),
+            latencies: Arc::new(ProviderLatencies::default()),
+            routed,
         }
     }
 
+    /// Account for requests to `provider` for `network` with the cost of
+    /// each method taken from `costs` and warn when they are projected to
+    /// exceed `budget`
+    pub fn track_provider(
+        &self,
+        logger: &Logger,
+        provider: &str,
+        network: &str,
+        costs: BTreeMap<String, f64>,
+        budget: Option<ProviderBudget>,
+    ) {
+        let quota = ProviderQuota::new(logger.clone(), provider, network, costs, budget);
+        self.quotas
+            .write()
+            .unwrap()
+            .insert(provider.to_string(), Arc::new(quota));
+    }
+
     pub fn observe_request(&self, duration: f64, method: &str, provider: &str) {
         self.request_duration
             .with_label_values(&[method, provider])
             .observe(duration);
+        self.count_request(method, provider);
+    }
+
+    /// Count a request for `method` that was sent to `provider`, including
+    /// each attempt when it is retried
+    pub fn count_request(&self, method: &str, provider: &str) {
+        let quota = self.quotas.read().unwrap().get(provider).cloned();
+        let network = quota.as_ref().map(|quota| quota.network()).unwrap_or("");
+        self.requests
+            .with_label_values(&[network, method, provider])
+            .inc();
+        if let Some(quota) = &quota {
+            let usage = quota.record(method);
+            self.units
+                .with_label_values(&[network, provider])
+                .set(usage.units);
+            self.projected_units
+                .with_label_values(&[network, provider])
+                .set(usage.projected);
+        }
     }
 
     pub fn add_error(&self, method: &str, provider: &str) {
         self.errors.with_label_values(&[method, provider]).inc();
     }
 
+    /// Record that a successful request for `method` to `provider` took
+    /// `duration` seconds. Only methods that are routed by latency are
+    /// tracked
+    pub fn observe_latency(&self, duration: f64, method: &str, provider: &str) {
+        if let Some(class) = CallClass::for_method(method) {
+            self.latencies.observe(provider, class, duration);
+        }
+    }
+
+    /// Record that a request for `method` to `provider` failed or timed
+    /// out. It counts as a request that took as long as the JSON-RPC
+    /// timeout. Only methods that are routed by latency are tracked
+    pub fn observe_failure(&self, method: &str, provider: &str) {
+        if let Some(class) = CallClass::for_method(method) {
+            self.latencies.observe_failure(
+                provider,
+                class,
+                ENV_VARS.json_rpc_timeout.as_secs_f64(),
+            );
+        }
+    }
+
+    /// The average latency of `provider` for requests of `class`
+    pub fn latency(&self, provider: &str, class: CallClass) -> Option<f64> {
+        self.latencies.latency(provider, class)
+    }
+
+    /// Whether the last request of `class` to `provider` failed
+    pub fn is_failing(&self, provider: &str, class: CallClass) -> bool {
+        self.latencies.is_failing(provider, class)
+    }
+
+    /// Whether to probe `provider` with a request of `class`
+    pub fn try_probe(&self, provider: &str, class: CallClass) -> bool {
+        self.latencies.try_probe(provider, class)
+    }
+
+    /// Count that latency routing sent a request of `class` to `provider`
+    pub fn count_routed(&self, class: CallClass, provider: &str) {
+        self.routed
+            .with_label_values(&[class.as_str(), provider])
+            .inc();
+    }
+
     pub fn set_status(&self, status: ProviderStatus, provider: &str) {
         self.status
             .with_label_values(&[provider])
@@ -787,6 +921,7 @@ pub struct SubgraphEthRpcMetrics {
     request_duration: GaugeVec,
     errors: CounterVec,
     deployment: String,
+    usage: Arc<UsageTracker>,
 }
 
 impl SubgraphEthRpcMetrics {
@@ -809,6 +944,7 @@ impl SubgraphEthRpcMetrics {
             request_duration,
             errors,
             deployment: subgraph_hash.into(),
+            usage: usage_tracker(subgraph_hash),
         }
     }
 
@@ -816,6 +952,7 @@ impl SubgraphEthRpcMetrics {
         self.request_duration
             .with_label_values(&[&self.deployment, method, provider])
             .set(duration);
+        self.usage.provider_call(method);
     }
 
     pub fn add_error(&self, method: &str, provider: &str) {
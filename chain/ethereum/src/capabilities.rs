@@ -1,11 +1,12 @@
 use anyhow::Error;
 use graph::impl_slog_value;
+use graph::prelude::BlockNumber;
 use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::fmt;
 use std::str::FromStr;
 
-use crate::DataSource;
+use crate::{DataSource, ENV_VARS};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct NodeCapabilities {
@@ -68,6 +69,23 @@ impl fmt::Display for NodeCapabilities {
 
 impl_slog_value!(NodeCapabilities, "{}");
 
+impl NodeCapabilities {
+    /// The capabilities a provider needs to answer requests about `block`
+    /// when the chain head is at `head`. Full nodes keep the state for the
+    /// most recent blocks, and an archive node is therefore only needed
+    /// for blocks further behind the head than that, or when the head is
+    /// not known
+    pub fn for_block(&self, block: BlockNumber, head: Option<BlockNumber>) -> NodeCapabilities {
+        let near_head = head
+            .map(|head| head - block < ENV_VARS.full_node_state_blocks)
+            .unwrap_or(false);
+        NodeCapabilities {
+            archive: self.archive && !near_head,
+            traces: self.traces,
+        }
+    }
+}
+
 impl graph::blockchain::NodeCapabilities<crate::Chain> for NodeCapabilities {
     fn from_data_sources(data_sources: &[DataSource]) -> Self {
         NodeCapabilities {
@@ -4,7 +4,7 @@ use graph::blockchain::{BlockchainKind, TriggersAdapterSelector};
 use graph::data::subgraph::UnifiedMappingApiVersion;
 use graph::firehose::{FirehoseEndpoint, FirehoseEndpoints, ForkStep};
 use graph::prelude::{EthereumBlock, EthereumCallCache, LightEthereumBlock, LightEthereumBlockExt};
-use graph::slog::debug;
+use graph::slog::{debug, warn};
 use graph::{
     blockchain::{
         block_stream::{
@@ -20,28 +20,35 @@ use graph::{
     components::store::DeploymentLocator,
     firehose,
     prelude::{
-        async_trait, o, serde_json as json, BlockNumber, ChainStore, EthereumBlockWithCalls,
-        Future01CompatExt, Logger, LoggerFactory, MetricsRegistry, NodeId,
+        async_trait, o, serde_json as json, BlockHash, BlockNumber, ChainStore, DeploymentHash,
+        EthereumBlockWithCalls, Future01CompatExt, Logger, LoggerFactory, MetricsRegistry, NodeId,
     },
 };
 use prost::Message;
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use crate::data_source::DataSourceTemplate;
 use crate::data_source::UnresolvedDataSourceTemplate;
 use crate::{
     adapter::EthereumAdapter as _,
+    capabilities::NodeCapabilities,
     codec,
     data_source::{DataSource, UnresolvedDataSource},
     ethereum_adapter::{
         blocks_with_triggers, get_calls, parse_block_triggers, parse_call_triggers,
         parse_log_triggers,
     },
-    SubgraphEthRpcMetrics, TriggerFilter, ENV_VARS,
+    trigger::{EthereumBlockTriggerType, EthereumTrigger},
+    trigger_verification::{TriggerVerificationMetrics, TriggerVerifier},
+    CallClass, SubgraphEthRpcMetrics, TriggerFilter, ENV_VARS,
+};
+use crate::{
+    network::{EthereumNetworkAdapters, ProviderRanking},
+    EthereumAdapter,
 };
-use crate::{network::EthereumNetworkAdapters, EthereumAdapter};
 use graph::blockchain::block_stream::{BlockStream, BlockStreamBuilder, FirehoseCursor};
 
 /// Celo Mainnet: 42220, Testnet Alfajores: 44787, Testnet Baklava: 62320
@@ -133,6 +140,7 @@ impl BlockRefetcher<Chain> for EthereumBlockRefetcher {
 
 pub struct EthereumAdapterSelector {
     logger_factory: LoggerFactory,
+    network: String,
     adapters: Arc<EthereumNetworkAdapters>,
     firehose_endpoints: Arc<FirehoseEndpoints>,
     registry: Arc<dyn MetricsRegistry>,
@@ -142,6 +150,7 @@ pub struct EthereumAdapterSelector {
 impl EthereumAdapterSelector {
     pub fn new(
         logger_factory: LoggerFactory,
+        network: String,
         adapters: Arc<EthereumNetworkAdapters>,
         firehose_endpoints: Arc<FirehoseEndpoints>,
         registry: Arc<dyn MetricsRegistry>,
@@ -149,12 +158,52 @@ impl EthereumAdapterSelector {
     ) -> Self {
         Self {
             logger_factory,
+            network,
             adapters,
             firehose_endpoints,
             registry,
             chain_store,
         }
     }
+
+    /// A verifier for the triggers from `eth_adapter` if trigger
+    /// verification is on for the network and there is another provider
+    /// to verify against
+    fn trigger_verifier(
+        &self,
+        logger: &Logger,
+        eth_adapter: &EthereumAdapter,
+        capabilities: &crate::capabilities::NodeCapabilities,
+        ethrpc_metrics: &Arc<SubgraphEthRpcMetrics>,
+        unified_api_version: &UnifiedMappingApiVersion,
+    ) -> Option<TriggerVerifier> {
+        let rate = ENV_VARS.trigger_verification.rate(&self.network);
+        if rate <= 0.0 {
+            return None;
+        }
+        let secondary = match self
+            .adapters
+            .other_than(eth_adapter.provider(), capabilities)
+        {
+            Some(secondary) => secondary,
+            None => {
+                warn!(logger, "Trigger verification is on, but there is no other provider to verify triggers against";
+                      "provider" => eth_adapter.provider());
+                return None;
+            }
+        };
+        Some(TriggerVerifier {
+            logger: logger.cheap_clone(),
+            network: self.network.clone(),
+            rate,
+            primary: eth_adapter.provider().to_string(),
+            secondary,
+            chain_store: self.chain_store.cheap_clone(),
+            ethrpc_metrics: ethrpc_metrics.cheap_clone(),
+            unified_api_version: unified_api_version.clone(),
+            metrics: TriggerVerificationMetrics::new(self.registry.cheap_clone()),
+        })
+    }
 }
 
 impl TriggersAdapterSelector<Chain> for EthereumAdapterSelector {
@@ -169,27 +218,48 @@ impl TriggersAdapterSelector<Chain> for EthereumAdapterSelector {
             .subgraph_logger(loc)
             .new(o!("component" => "BlockStream"));
 
-        let eth_adapter = if capabilities.traces && self.firehose_endpoints.len() > 0 {
+        let (eth_adapter, required_capabilities) = if capabilities.traces
+            && self.firehose_endpoints.len() > 0
+        {
             debug!(logger, "Removing 'traces' capability requirement for adapter as FirehoseBlockStream will provide the traces");
             let adjusted_capabilities = crate::capabilities::NodeCapabilities {
                 archive: capabilities.archive,
                 traces: false,
             };
 
-            self.adapters
-                .call_or_cheapest(Some(&adjusted_capabilities))?
+            (
+                self.adapters
+                    .call_or_cheapest(Some(&adjusted_capabilities))?,
+                adjusted_capabilities,
+            )
         } else {
-            self.adapters.cheapest_with(capabilities)?
+            (self.adapters.cheapest_with(capabilities)?, *capabilities)
         };
 
         let ethrpc_metrics = Arc::new(SubgraphEthRpcMetrics::new(self.registry.clone(), &loc.hash));
+        let verifier = self.trigger_verifier(
+            &logger,
+            &eth_adapter,
+            capabilities,
+            &ethrpc_metrics,
+            &unified_api_version,
+        );
+
+        // Trigger verification compares against a provider other than
+        // `eth_adapter`, and requests therefore must not be routed to others
+        let adapters =
+            (ENV_VARS.latency_routing && verifier.is_none()).then(|| self.adapters.cheap_clone());
 
         let adapter = TriggersAdapter {
             logger: logger.clone(),
+            deployment: loc.hash.cheap_clone(),
             ethrpc_metrics,
             eth_adapter,
+            adapters,
+            required_capabilities,
             chain_store: self.chain_store.cheap_clone(),
             unified_api_version,
+            verifier,
         };
         Ok(Arc::new(adapter))
     }
@@ -265,6 +335,12 @@ impl Chain {
     pub fn cheapest_adapter(&self) -> Arc<EthereumAdapter> {
         self.eth_adapters.cheapest().unwrap()
     }
+
+    /// How latency routing currently ranks the providers of this chain for
+    /// each class of requests
+    pub fn provider_rankings(&self) -> Vec<ProviderRanking> {
+        self.eth_adapters.rankings()
+    }
 }
 
 #[async_trait]
@@ -329,6 +405,7 @@ impl Blockchain for Chain {
         subgraph_current_block: Option<BlockPtr>,
         filter: Arc<Self::TriggerFilter>,
         unified_api_version: UnifiedMappingApiVersion,
+        reorg_threshold: Option<BlockNumber>,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         let requirements = filter.node_capabilities();
         let adapter = self
@@ -354,8 +431,13 @@ impl Blockchain for Chain {
         // some events appear only in eth_getLogs but not in transaction receipts.
         // See also ca0edc58-0ec5-4c89-a7dd-2241797f5e50.
         let chain_id = self.eth_adapters.cheapest().unwrap().chain_id().await?;
+        // Deployments can lower the threshold, but only as far as the
+        // finality of the chain allows
         let reorg_threshold = match CELO_CHAIN_IDS.contains(&chain_id) {
-            false => self.reorg_threshold,
+            false => match (reorg_threshold, self.reorg_threshold_range()) {
+                (Some(threshold), Some(range)) => threshold.clamp(*range.start(), *range.end()),
+                _ => self.reorg_threshold,
+            },
             true => 0,
         };
 
@@ -415,6 +497,14 @@ impl Blockchain for Chain {
     fn is_firehose_supported(&self) -> bool {
         ENV_VARS.is_firehose_preferred && self.firehose_endpoints.len() > 0
     }
+
+    fn reorg_threshold_range(&self) -> Option<RangeInclusive<BlockNumber>> {
+        Some(
+            ENV_VARS
+                .min_reorg_thresholds
+                .range(&self.name, self.reorg_threshold),
+        )
+    }
 }
 
 /// This is used in `EthereumAdapter::triggers_in_block`, called when re-processing a block for
@@ -505,10 +595,79 @@ pub struct DummyDataSourceTemplate;
 
 pub struct TriggersAdapter {
     logger: Logger,
+    /// The deployment whose triggers this adapter loads
+    deployment: DeploymentHash,
     ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
     chain_store: Arc<dyn ChainStore>,
     eth_adapter: Arc<EthereumAdapter>,
+    /// All adapters for the network when requests are routed by latency;
+    /// otherwise, all requests go to `eth_adapter`
+    adapters: Option<Arc<EthereumNetworkAdapters>>,
+    /// The capabilities the deployment needs from a provider
+    required_capabilities: NodeCapabilities,
     unified_api_version: UnifiedMappingApiVersion,
+    /// Compares the triggers for a sample of blocks with another provider
+    verifier: Option<TriggerVerifier>,
+}
+
+impl TriggersAdapter {
+    /// A triggers adapter outside of a running chain, for tools that scan
+    /// blocks for triggers themselves
+    pub fn new(
+        logger: Logger,
+        deployment: DeploymentHash,
+        ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        eth_adapter: Arc<EthereumAdapter>,
+        unified_api_version: UnifiedMappingApiVersion,
+    ) -> Self {
+        TriggersAdapter {
+            logger,
+            deployment,
+            ethrpc_metrics,
+            chain_store,
+            eth_adapter,
+            adapters: None,
+            required_capabilities: NodeCapabilities {
+                archive: false,
+                traces: false,
+            },
+            unified_api_version,
+            verifier: None,
+        }
+    }
+
+    /// The adapter for requests of `class` about the block with number
+    /// `block` and hash `hash`: with latency routing, the fastest one with
+    /// the capabilities needed for `block`, and `eth_adapter` otherwise
+    async fn adapter_for(
+        &self,
+        class: CallClass,
+        block: BlockNumber,
+        hash: Option<&BlockHash>,
+    ) -> Arc<EthereumAdapter> {
+        let adapters = match &self.adapters {
+            Some(adapters) => adapters,
+            None => return self.eth_adapter.cheap_clone(),
+        };
+        let head = self
+            .chain_store
+            .cheap_clone()
+            .cached_head_ptr()
+            .await
+            .ok()
+            .flatten()
+            .map(|ptr| ptr.number);
+        adapters
+            .fastest_with(
+                &self.required_capabilities.for_block(block, head),
+                class,
+                &self.deployment,
+                block,
+                hash,
+            )
+            .unwrap_or_else(|_| self.eth_adapter.cheap_clone())
+    }
 }
 
 #[async_trait]
@@ -519,8 +678,8 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
         to: BlockNumber,
         filter: &TriggerFilter,
     ) -> Result<Vec<BlockWithTriggers<Chain>>, Error> {
-        blocks_with_triggers(
-            self.eth_adapter.clone(),
+        let blocks = blocks_with_triggers(
+            self.adapter_for(CallClass::Logs, from, None).await,
             self.logger.clone(),
             self.chain_store.clone(),
             self.ethrpc_metrics.clone(),
@@ -529,7 +688,11 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
             filter,
             self.unified_api_version.clone(),
         )
-        .await
+        .await?;
+        if let Some(verifier) = &self.verifier {
+            verifier.verify(from, to, &blocks, filter).await;
+        }
+        Ok(blocks)
     }
 
     async fn triggers_in_block(
@@ -538,8 +701,12 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
         block: BlockFinality,
         filter: &TriggerFilter,
     ) -> Result<BlockWithTriggers<Chain>, Error> {
+        let hash = block.ptr().hash;
+        let eth_adapter = self
+            .adapter_for(CallClass::Blocks, block.number(), Some(&hash))
+            .await;
         let block = get_calls(
-            self.eth_adapter.as_ref(),
+            eth_adapter.as_ref(),
             logger.clone(),
             self.ethrpc_metrics.clone(),
             filter.requires_traces(),
@@ -551,7 +718,8 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
             BlockFinality::Final(_) => {
                 let block_number = block.number() as BlockNumber;
                 let blocks = blocks_with_triggers(
-                    self.eth_adapter.clone(),
+                    self.adapter_for(CallClass::Logs, block_number, Some(&hash))
+                        .await,
                     logger.clone(),
                     self.chain_store.clone(),
                     self.ethrpc_metrics.clone(),
@@ -562,6 +730,11 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                 )
                 .await?;
                 assert!(blocks.len() == 1);
+                if let Some(verifier) = &self.verifier {
+                    verifier
+                        .verify(block_number, block_number, &blocks, filter)
+                        .await;
+                }
                 Ok(blocks.into_iter().next().unwrap())
             }
             BlockFinality::NonFinal(full_block) => {
@@ -572,13 +745,18 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                 ));
                 triggers.append(&mut parse_call_triggers(&filter.call, full_block)?);
                 triggers.append(&mut parse_block_triggers(&filter.block, full_block));
+                // The block and the triggers in it come from the block
+                // cache, and we do not know which provider put the block
+                // there; crediting the provider that we use for calls would
+                // be wrong
                 Ok(BlockWithTriggers::new(block, triggers, logger))
             }
         }
     }
 
     async fn is_on_main_chain(&self, ptr: BlockPtr) -> Result<bool, Error> {
-        self.eth_adapter
+        self.adapter_for(CallClass::Blocks, ptr.number, Some(&ptr.hash))
+            .await
             .is_on_main_chain(&self.logger, ptr.clone())
             .await
     }
@@ -608,7 +786,8 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
         use graph::prelude::LightEthereumBlockExt;
 
         let blocks = self
-            .eth_adapter
+            .adapter_for(CallClass::Blocks, block.number, Some(&block.hash))
+            .await
             .load_blocks(
                 self.logger.cheap_clone(),
                 self.chain_store.cheap_clone(),
@@ -621,6 +800,13 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
 
         Ok(blocks[0].parent_ptr())
     }
+
+    fn block_finalizer_trigger(&self, block: &BlockFinality) -> Option<EthereumTrigger> {
+        Some(EthereumTrigger::Block(
+            block.ptr(),
+            EthereumBlockTriggerType::Finalize,
+        ))
+    }
 }
 
 pub struct FirehoseMapper {}
@@ -8,7 +8,7 @@ use graph::prelude::ethabi::StateMutability;
 use graph::prelude::futures03::future::try_join;
 use graph::prelude::futures03::stream::FuturesOrdered;
 use graph::prelude::{Link, SubgraphManifestValidationError};
-use graph::slog::{o, trace};
+use graph::slog::{o, trace, FnValue};
 use std::str::FromStr;
 use std::sync::Arc;
 use tiny_keccak::{keccak256, Keccak};
@@ -28,7 +28,9 @@ use graph::{
 use graph::data::subgraph::{calls_host_fn, DataSourceContext, Source};
 
 use crate::chain::Chain;
-use crate::trigger::{EthereumBlockTriggerType, EthereumTrigger, MappingTrigger};
+use crate::trigger::{
+    EthereumBlockTriggerType, EthereumTrigger, MappingTrigger, TriggerTransaction,
+};
 
 // The recommended kind is `ethereum`, `ethereum/contract` is accepted for backwards compatibility.
 const ETHEREUM_KINDS: &[&str] = &["ethereum/contract", "ethereum"];
@@ -47,6 +49,9 @@ pub struct DataSource {
     pub mapping: Mapping,
     pub context: Arc<Option<DataSourceContext>>,
     pub creation_block: Option<BlockNumber>,
+    /// The position of this data source among the data sources created in
+    /// its creation block, see `DataSourceTemplateInfo::creation_seq`
+    pub creation_seq: Option<u32>,
     pub contract_abi: Arc<MappingABI>,
 }
 
@@ -57,6 +62,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             params,
             context,
             creation_block,
+            creation_seq,
         } = info;
         let template = template.into_onchain().ok_or(anyhow!(
             "Cannot create onchain data source from offchain template"
@@ -95,6 +101,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             mapping: template.mapping,
             context: Arc::new(context),
             creation_block: Some(creation_block),
+            creation_seq: Some(creation_seq),
             contract_abi,
         })
     }
@@ -150,6 +157,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             // The creation block is ignored for detection duplicate data sources.
             // Contract ABI equality is implicit in `mapping.abis` equality.
             creation_block: _,
+            creation_seq: _,
             contract_abi: _,
             start_block: _,
         } = self;
@@ -166,6 +174,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             && mapping.event_handlers == other.mapping.event_handlers
             && mapping.call_handlers == other.mapping.call_handlers
             && mapping.block_handlers == other.mapping.block_handlers
+            && mapping.block_finalizer == other.mapping.block_finalizer
             && context == &other.context
     }
 
@@ -180,6 +189,7 @@ impl blockchain::DataSource<Chain> for DataSource {
                 .as_ref()
                 .map(|ctx| serde_json::to_value(&ctx).unwrap()),
             creation_block: self.creation_block,
+            creation_seq: self.creation_seq,
             done_at: None,
             causality_region: CausalityRegion::ONCHAIN,
         }
@@ -194,6 +204,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             param,
             context,
             creation_block,
+            creation_seq,
             done_at,
             causality_region,
         } = stored;
@@ -220,6 +231,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             mapping: template.mapping.clone(),
             context: Arc::new(context),
             creation_block,
+            creation_seq,
             contract_abi,
         })
     }
@@ -286,6 +298,17 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn runtime(&self) -> Option<Arc<Vec<u8>>> {
         Some(self.mapping.runtime.cheap_clone())
     }
+
+    fn declared_entities(&self) -> Option<&[String]> {
+        Some(&self.mapping.entities)
+    }
+
+    fn handler_count(&self) -> usize {
+        self.mapping.block_handlers.len()
+            + self.mapping.call_handlers.len()
+            + self.mapping.event_handlers.len()
+            + self.mapping.block_finalizer.iter().count()
+    }
 }
 
 impl DataSource {
@@ -314,6 +337,7 @@ impl DataSource {
             mapping,
             context: Arc::new(context),
             creation_block,
+            creation_seq: None,
             contract_abi,
         })
     }
@@ -372,6 +396,15 @@ impl DataSource {
                 .iter()
                 .find(move |handler| handler.filter == Some(BlockHandlerFilter::Call))
                 .cloned(),
+            EthereumBlockTriggerType::Finalize => {
+                self.mapping
+                    .block_finalizer
+                    .as_ref()
+                    .map(|finalizer| MappingBlockHandler {
+                        handler: finalizer.handler.clone(),
+                        filter: None,
+                    })
+            }
         }
     }
 
@@ -516,8 +549,10 @@ impl DataSource {
             EthereumTrigger::Call(call) => &call.to,
             EthereumTrigger::Log(log, _) => &log.address,
 
-            // Unfiltered block triggers match any data source address.
-            EthereumTrigger::Block(_, EthereumBlockTriggerType::Every) => return true,
+            // Unfiltered block triggers and block finalizers match any data
+            // source address.
+            EthereumTrigger::Block(_, EthereumBlockTriggerType::Every)
+            | EthereumTrigger::Block(_, EthereumBlockTriggerType::Finalize) => return true,
         };
 
         ds_address == *trigger_address
@@ -628,30 +663,32 @@ impl DataSource {
                 // in which case we pass a dummy transaction to the mappings.
                 // See also ca0edc58-0ec5-4c89-a7dd-2241797f5e50.
                 let transaction = if log.transaction_hash != block.hash {
-                    block
-                        .transaction_for_log(log)
+                    TriggerTransaction::in_block(block, log.transaction_hash)
                         .context("Found no transaction for event")?
                 } else {
                     // Infer some fields from the log and fill the rest with zeros.
-                    Transaction {
+                    TriggerTransaction::Detached(Arc::new(Transaction {
                         hash: log.transaction_hash.unwrap(),
                         block_hash: block.hash,
                         block_number: block.number,
                         transaction_index: log.transaction_index,
                         from: Some(H160::zero()),
                         ..Transaction::default()
-                    }
+                    }))
                 };
 
+                // The extras are only formatted if they are actually logged
+                let address = log.address;
+                let transaction_hash = transaction.hash;
                 let logging_extras = Arc::new(o! {
-                    "signature" => event_handler.event.to_string(),
-                    "address" => format!("{}", &log.address),
-                    "transaction" => format!("{}", &transaction.hash),
+                    "signature" => event_handler.event,
+                    "address" => FnValue(move |_| format!("{}", address)),
+                    "transaction" => FnValue(move |_| format!("{}", transaction_hash)),
                 });
                 Ok(Some(TriggerWithHandler::<Chain>::new_with_logging_extras(
                     MappingTrigger::Log {
                         block: block.cheap_clone(),
-                        transaction: Arc::new(transaction),
+                        transaction,
                         log: log.cheap_clone(),
                         params,
                         receipt: receipt.clone(),
@@ -748,15 +785,14 @@ impl DataSource {
                     })
                     .collect::<Vec<_>>();
 
-                let transaction = Arc::new(
-                    block
-                        .transaction_for_call(call)
-                        .context("Found no transaction for call")?,
-                );
+                let transaction = TriggerTransaction::in_block(block, call.transaction_hash)
+                    .context("Found no transaction for call")?;
+                let to = call.to;
+                let transaction_hash = transaction.hash;
                 let logging_extras = Arc::new(o! {
-                    "function" => handler.function.to_string(),
-                    "to" => format!("{}", &call.to),
-                    "transaction" => format!("{}", &transaction.hash),
+                    "function" => handler.function,
+                    "to" => FnValue(move |_| format!("{}", to)),
+                    "transaction" => FnValue(move |_| format!("{}", transaction_hash)),
                 });
                 Ok(Some(TriggerWithHandler::<Chain>::new_with_logging_extras(
                     MappingTrigger::Call {
@@ -890,6 +926,8 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub block_finalizer: Option<MappingBlockFinalizer>,
     pub file: Link,
 }
 
@@ -903,6 +941,7 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub block_finalizer: Option<MappingBlockFinalizer>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -947,6 +986,7 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            block_finalizer,
             file: link,
         } = self;
 
@@ -980,6 +1020,7 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            block_finalizer,
             runtime,
             link,
         })
@@ -1034,6 +1075,14 @@ pub enum BlockHandlerFilter {
     Call,
 }
 
+/// A handler that runs once after all other handlers of the deployment in
+/// every block that has at least one trigger for the deployment. It sees
+/// the entities that the other handlers wrote in the block
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingBlockFinalizer {
+    pub handler: String,
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct MappingCallHandler {
     pub function: String,
@@ -1,7 +1,10 @@
 use envconfig::Envconfig;
 use graph::env::EnvVarBoolean;
 use graph::prelude::{envconfig, lazy_static, BlockNumber};
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::time::Duration;
 
 lazy_static! {
@@ -99,6 +102,37 @@ pub struct EnvVars {
     // set this env var to false to make it ignore the empty response,
     // then subgraph can retry to call rpc if it needs
     pub allow_eth_call_empty_response_cache: bool,
+    /// The fraction of blocks per network for which the triggers are also
+    /// fetched from a second provider and compared with the triggers from
+    /// the provider that is used for indexing.
+    ///
+    /// Set by the environment variable `GRAPH_ETHEREUM_TRIGGER_VERIFICATION`
+    /// as a comma-separated list of `network=rate`, for example
+    /// `mainnet=0.01`. Off for all networks by default.
+    pub trigger_verification: TriggerVerificationRates,
+    /// The smallest reorg threshold that deployments on a network may use
+    /// instead of `reorg_threshold`, i.e., how deep reorgs on the network
+    /// can be at most given its finality guarantees.
+    ///
+    /// Set by the environment variable `GRAPH_ETHEREUM_MIN_REORG_THRESHOLD`
+    /// as a comma-separated list of `network=blocks`, for example
+    /// `arbitrum-one=20`. Deployments on networks that are not listed can
+    /// not lower their reorg threshold.
+    pub min_reorg_thresholds: MinReorgThresholds,
+    /// Whether requests for logs, calls and blocks are sent to the provider
+    /// that has been fastest for that kind of request among those with
+    /// sufficient capabilities instead of to the provider that was chosen
+    /// when the deployment started.
+    ///
+    /// Set by the flag `GRAPH_ETHEREUM_LATENCY_ROUTING`. Off by default.
+    pub latency_routing: bool,
+    /// How many blocks behind the chain head a full node still has the
+    /// state for. With latency routing, requests for blocks that are
+    /// closer to the head than this do not need an archive node.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_ETHEREUM_FULL_NODE_STATE_BLOCKS`. The default value is 128.
+    pub full_node_state_blocks: BlockNumber,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -146,6 +180,10 @@ impl From<Inner> for EnvVars {
             genesis_block_number: x.genesis_block_number,
             fetch_final_blocks_only: x.fetch_final_blocks_only.0,
             allow_eth_call_empty_response_cache: x.allow_eth_call_empty_response_cache.0,
+            trigger_verification: x.trigger_verification,
+            min_reorg_thresholds: x.min_reorg_thresholds,
+            latency_routing: x.latency_routing.0,
+            full_node_state_blocks: x.full_node_state_blocks,
         }
     }
 }
@@ -205,4 +243,98 @@ struct Inner {
     fetch_final_blocks_only: EnvVarBoolean,
     #[envconfig(from = "ALLOW_ETH_CALL_EMPTY_RESPONSE_CACHE", default = "true")]
     allow_eth_call_empty_response_cache: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_TRIGGER_VERIFICATION", default = "")]
+    trigger_verification: TriggerVerificationRates,
+    #[envconfig(from = "GRAPH_ETHEREUM_MIN_REORG_THRESHOLD", default = "")]
+    min_reorg_thresholds: MinReorgThresholds,
+    #[envconfig(from = "GRAPH_ETHEREUM_LATENCY_ROUTING", default = "false")]
+    latency_routing: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_FULL_NODE_STATE_BLOCKS", default = "128")]
+    full_node_state_blocks: BlockNumber,
+}
+
+/// The sampling rates for trigger verification by network
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TriggerVerificationRates(HashMap<String, f64>);
+
+impl TriggerVerificationRates {
+    /// The fraction of blocks on `network` whose triggers are verified;
+    /// 0 if verification is off for the network
+    pub fn rate(&self, network: &str) -> f64 {
+        self.0.get(network).copied().unwrap_or(0.0)
+    }
+}
+
+impl FromStr for TriggerVerificationRates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rates = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (network, rate) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected `network=rate` but got `{}`", entry))?;
+            let rate: f64 = rate
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid rate `{}` for network `{}`", rate, network))?;
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!(
+                    "the rate for network `{}` must be between 0 and 1 but is {}",
+                    network, rate
+                ));
+            }
+            rates.insert(network.trim().to_string(), rate);
+        }
+        Ok(TriggerVerificationRates(rates))
+    }
+}
+
+/// The smallest reorg threshold that deployments may use by network
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MinReorgThresholds(HashMap<String, BlockNumber>);
+
+impl MinReorgThresholds {
+    /// The reorg thresholds that deployments on `network` may use when the
+    /// reorg threshold of the network is `reorg_threshold`
+    pub fn range(
+        &self,
+        network: &str,
+        reorg_threshold: BlockNumber,
+    ) -> RangeInclusive<BlockNumber> {
+        let min = self
+            .0
+            .get(network)
+            .copied()
+            .unwrap_or(reorg_threshold)
+            .min(reorg_threshold);
+        min..=reorg_threshold
+    }
+}
+
+impl FromStr for MinReorgThresholds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut thresholds = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (network, blocks) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected `network=blocks` but got `{}`", entry))?;
+            let blocks: BlockNumber = blocks.trim().parse().map_err(|_| {
+                format!(
+                    "invalid reorg threshold `{}` for network `{}`",
+                    blocks, network
+                )
+            })?;
+            if blocks < 0 {
+                return Err(format!(
+                    "the reorg threshold for network `{}` can not be negative",
+                    network
+                ));
+            }
+            thresholds.insert(network.trim().to_string(), blocks);
+        }
+        Ok(MinReorgThresholds(thresholds))
+    }
 }
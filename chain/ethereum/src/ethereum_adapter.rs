@@ -52,6 +52,7 @@ use crate::{
         EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumLogFilter,
         ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     },
+    latency::CallClass,
     transport::Transport,
     trigger::{EthereumBlockTriggerType, EthereumTrigger},
     TriggerFilter, ENV_VARS,
@@ -93,11 +94,82 @@ impl CheapClone for EthereumAdapter {
     }
 }
 
+/// Times a request that is routed by latency. The latency is recorded
+/// when the request succeeds; if it fails, or the timer is dropped because
+/// the request timed out, a failure is recorded instead
+struct RequestTimer {
+    metrics: Arc<ProviderEthRpcMetrics>,
+    provider: String,
+    method: &'static str,
+    start: Instant,
+    succeeded: bool,
+}
+
+impl RequestTimer {
+    fn succeeded(mut self) {
+        self.succeeded = true;
+        self.metrics.observe_latency(
+            self.start.elapsed().as_secs_f64(),
+            self.method,
+            &self.provider,
+        );
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        if !self.succeeded {
+            self.metrics.observe_failure(self.method, &self.provider);
+        }
+    }
+}
+
 impl EthereumAdapter {
     pub fn is_call_only(&self) -> bool {
         self.call_only
     }
 
+    /// Count a request for `method` to this adapter's provider
+    fn count_request(&self, method: &str) {
+        self.metrics.count_request(method, &self.provider);
+    }
+
+    /// Start timing a request for `method` to this adapter's provider
+    fn request_timer(&self, method: &'static str) -> RequestTimer {
+        RequestTimer {
+            metrics: self.metrics.cheap_clone(),
+            provider: self.provider.clone(),
+            method,
+            start: Instant::now(),
+            succeeded: false,
+        }
+    }
+
+    /// The average latency of this adapter's provider for requests of
+    /// `class`, or `None` if it has not answered any yet
+    pub fn latency(&self, class: CallClass) -> Option<f64> {
+        self.metrics.latency(&self.provider, class)
+    }
+
+    /// Whether the last request of `class` to this adapter's provider
+    /// failed or timed out
+    pub fn is_failing(&self, class: CallClass) -> bool {
+        self.metrics.is_failing(&self.provider, class)
+    }
+
+    /// Whether latency routing should send a request of `class` to this
+    /// adapter's provider to find out whether it answers again, or how
+    /// fast it is if it has not answered yet
+    pub(crate) fn try_probe(&self, class: CallClass) -> bool {
+        self.metrics.try_probe(&self.provider, class)
+    }
+
+    /// Count that latency routing chose this adapter for a request of
+    /// `class`
+    pub(crate) fn count_routed(&self, class: CallClass) {
+        self.metrics.count_routed(class, &self.provider);
+    }
+
     pub async fn new(
         logger: Logger,
         provider: String,
@@ -260,6 +332,7 @@ impl EthereumAdapter {
 
                 async move {
                     let start = Instant::now();
+                    let timer = eth_adapter.request_timer("eth_getLogs");
 
                     // Create a log filter
                     let log_filter: Filter = FilterBuilder::default()
@@ -277,6 +350,8 @@ impl EthereumAdapter {
                     if result.is_err() {
                         provider_metrics.add_error("eth_getLogs", &provider);
                         subgraph_metrics.add_error("eth_getLogs", &provider);
+                    } else {
+                        timer.succeeded();
                     }
                     result
                 }
@@ -437,7 +512,7 @@ impl EthereumAdapter {
         call_data: Bytes,
         block_ptr: BlockPtr,
     ) -> impl Future<Item = Bytes, Error = EthereumContractCallError> + Send {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
 
         // Ganache does not support calls by block hash.
         // See https://github.com/trufflesuite/ganache-cli/issues/973
@@ -456,7 +531,7 @@ impl EthereumAdapter {
             .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
             .run(move || {
                 let call_data = call_data.clone();
-                let web3 = web3.cheap_clone();
+                let eth = eth.cheap_clone();
 
                 async move {
                     let req = CallRequest {
@@ -471,7 +546,9 @@ impl EthereumAdapter {
                         max_priority_fee_per_gas: None,
                         transaction_type: None,
                     };
-                    let result = web3.eth().call(req, Some(block_id)).boxed().await;
+                    eth.count_request("eth_call");
+                    let timer = eth.request_timer("eth_call");
+                    let result = eth.web3.eth().call(req, Some(block_id)).boxed().await;
 
                     // Try to check if the call was reverted. The JSON-RPC response for reverts is
                     // not standardized, so we have ad-hoc checks for each Ethereum client.
@@ -530,7 +607,7 @@ impl EthereumAdapter {
                         }
                     };
 
-                    match result {
+                    let result = match result {
                         // A successful response.
                         Ok(bytes) => Ok(bytes),
 
@@ -579,7 +656,13 @@ impl EthereumAdapter {
 
                         // The error was not identified as a revert.
                         Err(err) => Err(EthereumContractCallError::Web3Error(err)),
+                    };
+
+                    // A revert is a proper answer from the provider
+                    if matches!(result, Ok(_) | Err(EthereumContractCallError::Revert(_))) {
+                        timer.succeeded();
                     }
+                    result
                 }
             })
             .map_err(|e| e.into_inner().unwrap_or(EthereumContractCallError::Timeout))
@@ -593,21 +676,27 @@ impl EthereumAdapter {
         logger: Logger,
         ids: Vec<H256>,
     ) -> impl Stream<Item = Arc<LightEthereumBlock>, Error = Error> + Send {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
 
         stream::iter_ok::<_, Error>(ids.into_iter().map(move |hash| {
-            let web3 = web3.clone();
+            let eth = eth.cheap_clone();
             retry(format!("load block {}", hash), &logger)
                 .limit(ENV_VARS.request_retries)
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    Box::pin(web3.eth().block_with_txs(BlockId::Hash(hash)))
+                    eth.count_request("eth_getBlockByHash");
+                    let timer = eth.request_timer("eth_getBlockByHash");
+                    Box::pin(eth.web3.eth().block_with_txs(BlockId::Hash(hash)))
                         .compat()
                         .from_err::<Error>()
                         .and_then(move |block| {
-                            block.map(Arc::new).ok_or_else(|| {
+                            let block = block.map(Arc::new).ok_or_else(|| {
                                 anyhow::anyhow!("Ethereum node did not find block {:?}", hash)
-                            })
+                            });
+                            if block.is_ok() {
+                                timer.succeeded();
+                            }
+                            block
                         })
                         .compat()
                 })
@@ -626,17 +715,19 @@ impl EthereumAdapter {
         logger: Logger,
         block_nums: Vec<BlockNumber>,
     ) -> impl Stream<Item = BlockPtr, Error = Error> + Send {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
 
         stream::iter_ok::<_, Error>(block_nums.into_iter().map(move |block_num| {
-            let web3 = web3.clone();
+            let eth = eth.cheap_clone();
             retry(format!("load block ptr {}", block_num), &logger)
                 .no_limit()
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    let web3 = web3.clone();
+                    let eth = eth.cheap_clone();
                     async move {
-                        let block = web3
+                        eth.count_request("eth_getBlockByNumber");
+                        let block = eth
+                            .web3
                             .eth()
                             .block(BlockId::Number(Web3BlockNumber::Number(block_num.into())))
                             .boxed()
@@ -842,11 +933,13 @@ impl EthereumAdapter {
     ) -> Result<Option<web3::types::Block<H256>>, web3::Error> {
         let transport = self.web3.transport();
         let params = vec![Value::String("finalized".to_string()), Value::Bool(false)];
+        self.count_request("eth_blockNumber");
         let result = transport.execute("eth_blockNumber", params).await?;
 
         let block_number_hex = result.as_str().unwrap().strip_prefix("0x").unwrap();
         let block_number = i64::from_str_radix(block_number_hex, 16).unwrap() as u64;
         let block_number = web3::types::BlockNumber::Number(U64([block_number]));
+        self.count_request("eth_getBlockByNumber");
         let result = self.web3.eth().block(BlockId::Number(block_number)).await?;
 
         return Ok(result);
@@ -855,6 +948,7 @@ impl EthereumAdapter {
     async fn get_latest_available_block(
         &self,
     ) -> Result<Option<web3::types::Block<H256>>, web3::Error> {
+        self.count_request("eth_getBlockByNumber");
         let block = self
             .web3
             .eth()
@@ -995,15 +1089,17 @@ impl EthereumAdapterTrait for EthereumAdapter {
         &self,
         logger: &Logger,
     ) -> Box<dyn Future<Item = LightEthereumBlock, Error = IngestorError> + Send + Unpin> {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
         Box::new(
             retry("eth_getBlockByNumber(latest) with txs RPC call", logger)
                 .no_limit()
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    let web3 = web3.cheap_clone();
+                    let eth = eth.cheap_clone();
                     async move {
-                        let block_opt = web3
+                        eth.count_request("eth_getBlockByNumber");
+                        let block_opt = eth
+                            .web3
                             .eth()
                             .block_with_txs(Web3BlockNumber::Latest.into())
                             .await
@@ -1047,7 +1143,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
         logger: &Logger,
         block_hash: H256,
     ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
         let logger = logger.clone();
         let retry_log_message = format!(
             "eth_getBlockByHash RPC call for block hash {:?}",
@@ -1058,7 +1154,8 @@ impl EthereumAdapterTrait for EthereumAdapter {
                 .limit(ENV_VARS.request_retries)
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    Box::pin(web3.eth().block_with_txs(BlockId::Hash(block_hash)))
+                    eth.count_request("eth_getBlockByHash");
+                    Box::pin(eth.web3.eth().block_with_txs(BlockId::Hash(block_hash)))
                         .compat()
                         .from_err()
                         .compat()
@@ -1078,7 +1175,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
         logger: &Logger,
         block_number: BlockNumber,
     ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
         let logger = logger.clone();
         let retry_log_message = format!(
             "eth_getBlockByNumber RPC call for block number {}",
@@ -1089,9 +1186,11 @@ impl EthereumAdapterTrait for EthereumAdapter {
                 .no_limit()
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    let web3 = web3.cheap_clone();
+                    let eth = eth.cheap_clone();
                     async move {
-                        web3.eth()
+                        eth.count_request("eth_getBlockByNumber");
+                        eth.web3
+                            .eth()
                             .block_with_txs(BlockId::Number(block_number.into()))
                             .await
                             .map_err(Error::from)
@@ -1133,7 +1232,11 @@ impl EthereumAdapterTrait for EthereumAdapter {
         }
         let hashes: Vec<_> = block.transactions.iter().map(|txn| txn.hash).collect();
         let receipts_future = if ENV_VARS.fetch_receipts_in_batches {
-            // Deprecated batching retrieval of transaction receipts.
+            // Deprecated batching retrieval of transaction receipts. Providers
+            // bill each request in a batch separately
+            for _ in &hashes {
+                self.count_request("eth_getTransactionReceipt");
+            }
             fetch_transaction_receipts_in_batch_with_retry(web3, hashes, block_hash, logger).boxed()
         } else {
             let hash_stream = graph::tokio_stream::iter(hashes);
@@ -1190,7 +1293,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
         logger: &Logger,
         block_number: BlockNumber,
     ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
-        let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
         let retry_log_message = format!(
             "eth_getBlockByNumber RPC call for block number {}",
             block_number
@@ -1200,9 +1303,11 @@ impl EthereumAdapterTrait for EthereumAdapter {
                 .no_limit()
                 .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
                 .run(move || {
-                    let web3 = web3.cheap_clone();
+                    let eth = eth.cheap_clone();
                     async move {
-                        web3.eth()
+                        eth.count_request("eth_getBlockByNumber");
+                        eth.web3
+                            .eth()
                             .block(BlockId::Number(block_number.into()))
                             .await
                             .map(|block_opt| block_opt.and_then(|block| block.hash))
@@ -1496,16 +1601,20 @@ pub(crate) async fn blocks_with_triggers(
     triggers_by_block.entry(to).or_insert(Vec::new());
 
     let logger2 = logger.cheap_clone();
+    // The triggers were all fetched from `adapter`, even if `load_blocks`
+    // finds the blocks themselves in the block cache
+    let provider = adapter.provider().to_string();
 
     let blocks = adapter
         .load_blocks(logger.cheap_clone(), chain_store.clone(), block_hashes)
         .and_then(
             move |block| match triggers_by_block.remove(&(block.number() as BlockNumber)) {
-                Some(triggers) => Ok(BlockWithTriggers::new(
-                    BlockFinality::Final(block),
-                    triggers,
-                    &logger2,
-                )),
+                Some(triggers) => {
+                    Ok(
+                        BlockWithTriggers::new(BlockFinality::Final(block), triggers, &logger2)
+                            .with_provider(&provider),
+                    )
+                }
                 None => Err(anyhow!(
                     "block {} not found in `triggers_by_block`",
                     block.block_ptr()
@@ -0,0 +1,286 @@
+//! Track how quickly each provider answers different kinds of requests so
+//! that requests can be sent to the provider that has recently been the
+//! fastest for their kind. Latencies are kept as an exponentially weighted
+//! moving average over the requests to a provider, where a request that
+//! failed or timed out counts with a penalty. Since routing does not send
+//! requests to providers that are failing or have not answered yet,
+//! those are probed with a single request from time to time.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+/// How much weight the latest request has in the average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// How long to wait before probing a provider that has not answered a
+/// request yet, or that failed once
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The longest time between probes of a provider that keeps failing; the
+/// time doubles with each consecutive failure up to this
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// The kinds of requests that are routed by latency. Providers often have
+/// very different latencies for them; e.g., an archive node may be fine
+/// for `eth_getLogs` but slow for `eth_call`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CallClass {
+    Logs,
+    Calls,
+    Blocks,
+}
+
+impl CallClass {
+    pub const ALL: [CallClass; 3] = [CallClass::Logs, CallClass::Calls, CallClass::Blocks];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallClass::Logs => "logs",
+            CallClass::Calls => "calls",
+            CallClass::Blocks => "blocks",
+        }
+    }
+
+    /// The class of requests for the JSON-RPC `method`, or `None` if
+    /// requests for `method` are not routed by latency
+    pub fn for_method(method: &str) -> Option<CallClass> {
+        match method {
+            "eth_getLogs" => Some(CallClass::Logs),
+            "eth_call" => Some(CallClass::Calls),
+            "eth_getBlockByHash" | "eth_getBlockByNumber" => Some(CallClass::Blocks),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CallClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CallClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CallClass::ALL
+            .iter()
+            .find(|class| class.as_str() == s)
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown call class `{}`, expected one of logs, calls, blocks",
+                    s
+                )
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Latency {
+    /// The average latency in seconds; `None` if the provider has only
+    /// been probed but not answered yet
+    average: Option<f64>,
+    /// Whether the last request failed
+    failing: bool,
+    /// How many requests in a row failed
+    failures: u32,
+    /// When the provider can be probed again if it is failing or has not
+    /// answered yet
+    next_probe: Instant,
+}
+
+/// How long to wait for the next probe after `failures` requests in a row
+/// failed
+fn probe_interval(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    (PROBE_INTERVAL * 2u32.pow(doublings)).min(MAX_PROBE_INTERVAL)
+}
+
+/// The average latency in seconds of each provider for each class of
+/// requests
+#[derive(Default)]
+pub struct ProviderLatencies {
+    latencies: RwLock<HashMap<(String, CallClass), Latency>>,
+}
+
+impl ProviderLatencies {
+    fn update(&self, provider: &str, class: CallClass, duration: f64, failing: bool, now: Instant) {
+        let mut latencies = self.latencies.write().unwrap();
+        let latency = latencies
+            .entry((provider.to_string(), class))
+            .or_insert(Latency {
+                average: None,
+                failing,
+                failures: 0,
+                next_probe: now,
+            });
+        latency.average = Some(match latency.average {
+            Some(average) => LATENCY_WEIGHT * duration + (1.0 - LATENCY_WEIGHT) * average,
+            None => duration,
+        });
+        latency.failing = failing;
+        if failing {
+            latency.failures += 1;
+            latency.next_probe = now + probe_interval(latency.failures);
+        } else {
+            latency.failures = 0;
+        }
+    }
+
+    fn try_probe_at(&self, provider: &str, class: CallClass, now: Instant) -> bool {
+        let mut latencies = self.latencies.write().unwrap();
+        match latencies.get_mut(&(provider.to_string(), class)) {
+            None => {
+                latencies.insert(
+                    (provider.to_string(), class),
+                    Latency {
+                        average: None,
+                        failing: false,
+                        failures: 0,
+                        next_probe: now + PROBE_INTERVAL,
+                    },
+                );
+                true
+            }
+            Some(latency) if latency.average.is_some() && !latency.failing => false,
+            Some(latency) if now >= latency.next_probe => {
+                latency.next_probe = now + probe_interval(latency.failures);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Record that a successful request of `class` to `provider` took
+    /// `duration` seconds
+    pub fn observe(&self, provider: &str, class: CallClass, duration: f64) {
+        self.update(provider, class, duration, false, Instant::now())
+    }
+
+    /// Record that a request of `class` to `provider` failed or timed out;
+    /// it counts as a request that took `penalty` seconds, and the
+    /// provider counts as failing until it answers a request successfully
+    pub fn observe_failure(&self, provider: &str, class: CallClass, penalty: f64) {
+        self.update(provider, class, penalty, true, Instant::now())
+    }
+
+    /// Whether a request of `class` should be sent to `provider` to probe
+    /// it because it is failing or has not answered a request yet. A
+    /// provider that has not answered is probed once every
+    /// `PROBE_INTERVAL`, and one that is failing less often the longer it
+    /// keeps failing. Each call that returns `true` counts as a probe
+    pub fn try_probe(&self, provider: &str, class: CallClass) -> bool {
+        self.try_probe_at(provider, class, Instant::now())
+    }
+
+    /// The average latency of `provider` for `class`, or `None` if no
+    /// request of that class has been sent to `provider` yet
+    pub fn latency(&self, provider: &str, class: CallClass) -> Option<f64> {
+        self.latencies
+            .read()
+            .unwrap()
+            .get(&(provider.to_string(), class))
+            .and_then(|latency| latency.average)
+    }
+
+    /// Whether the last request of `class` to `provider` failed
+    pub fn is_failing(&self, provider: &str, class: CallClass) -> bool {
+        self.latencies
+            .read()
+            .unwrap()
+            .get(&(provider.to_string(), class))
+            .map(|latency| latency.failing)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_average() {
+        let latencies = ProviderLatencies::default();
+        assert_eq!(None, latencies.latency("p1", CallClass::Calls));
+
+        latencies.observe("p1", CallClass::Calls, 1.0);
+        assert_eq!(Some(1.0), latencies.latency("p1", CallClass::Calls));
+        latencies.observe("p1", CallClass::Calls, 2.0);
+        let avg = latencies.latency("p1", CallClass::Calls).unwrap();
+        assert!((avg - 1.2).abs() < 1e-9);
+
+        // Classes and providers are tracked separately
+        assert_eq!(None, latencies.latency("p1", CallClass::Logs));
+        assert_eq!(None, latencies.latency("p2", CallClass::Calls));
+    }
+
+    #[test]
+    fn failures() {
+        let latencies = ProviderLatencies::default();
+
+        latencies.observe_failure("p1", CallClass::Calls, 10.0);
+        assert_eq!(Some(10.0), latencies.latency("p1", CallClass::Calls));
+        assert!(latencies.is_failing("p1", CallClass::Calls));
+
+        // A failure is a penalty in the average, and the provider recovers
+        // with its next successful request
+        latencies.observe("p1", CallClass::Calls, 0.0);
+        let avg = latencies.latency("p1", CallClass::Calls).unwrap();
+        assert!((avg - 8.0).abs() < 1e-9);
+        assert!(!latencies.is_failing("p1", CallClass::Calls));
+        assert!(!latencies.is_failing("p2", CallClass::Calls));
+    }
+
+    #[test]
+    fn probes() {
+        let latencies = ProviderLatencies::default();
+        let start = Instant::now();
+        let after = |secs| start + Duration::from_secs(secs);
+
+        // A provider that has not answered yet is probed once per interval
+        assert!(latencies.try_probe_at("p1", CallClass::Calls, start));
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(1)));
+        assert_eq!(None, latencies.latency("p1", CallClass::Calls));
+        assert!(latencies.try_probe_at("p1", CallClass::Calls, after(30)));
+
+        // A provider that answers is not probed
+        latencies.update("p1", CallClass::Calls, 1.0, false, after(31));
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(100)));
+
+        // A failing provider is probed less often the longer it fails
+        latencies.update("p1", CallClass::Calls, 10.0, true, after(100));
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(129)));
+        assert!(latencies.try_probe_at("p1", CallClass::Calls, after(130)));
+        latencies.update("p1", CallClass::Calls, 10.0, true, after(140));
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(199)));
+        assert!(latencies.try_probe_at("p1", CallClass::Calls, after(200)));
+        for _ in 0..20 {
+            latencies.update("p1", CallClass::Calls, 10.0, true, after(1000));
+        }
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(1599)));
+        assert!(latencies.try_probe_at("p1", CallClass::Calls, after(1600)));
+
+        // Once it answers again, it is back to normal
+        latencies.update("p1", CallClass::Calls, 1.0, false, after(1601));
+        assert!(!latencies.is_failing("p1", CallClass::Calls));
+        assert!(!latencies.try_probe_at("p1", CallClass::Calls, after(5000)));
+    }
+
+    #[test]
+    fn parse_call_class() {
+        for class in CallClass::ALL {
+            assert_eq!(class, class.as_str().parse().unwrap());
+        }
+        assert!("traces".parse::<CallClass>().is_err());
+        assert_eq!(Some(CallClass::Logs), CallClass::for_method("eth_getLogs"));
+        assert_eq!(None, CallClass::for_method("net_version"));
+    }
+}
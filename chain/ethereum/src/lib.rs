@@ -5,11 +5,17 @@ mod data_source;
 mod env;
 mod ethereum_adapter;
 mod ingestor;
+mod latency;
+mod quota;
+mod replay;
 pub mod runtime;
 mod transport;
+mod trigger_verification;
 
 pub use self::capabilities::NodeCapabilities;
 pub use self::ethereum_adapter::EthereumAdapter;
+pub use self::latency::CallClass;
+pub use self::quota::ProviderBudget;
 pub use self::runtime::RuntimeAdapter;
 pub use self::transport::Transport;
 pub use env::ENV_VARS;
@@ -29,6 +35,7 @@ pub use crate::adapter::{
 pub use crate::chain::Chain;
 pub use crate::network::EthereumNetworks;
 pub use ingestor::BlockIngestor;
+pub use replay::replay_host_fns;
 
 #[cfg(test)]
 mod tests;
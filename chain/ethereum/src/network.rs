@@ -2,14 +2,18 @@ use anyhow::{anyhow, bail, Context};
 use graph::cheap_clone::CheapClone;
 use graph::prelude::rand::{self, seq::IteratorRandom};
 use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 pub use graph::impl_slog_value;
-use graph::prelude::Error;
+use graph::prelude::{BlockHash, BlockNumber, DeploymentHash, Error};
 
 use crate::adapter::EthereumAdapter as _;
 use crate::capabilities::NodeCapabilities;
+use crate::latency::CallClass;
 use crate::EthereumAdapter;
 
 #[derive(Clone)]
@@ -29,10 +33,120 @@ impl EthereumNetworkAdapter {
     }
 }
 
+/// Where latency routing would send requests of `class` to `provider`
+#[derive(Clone, Debug)]
+pub struct ProviderRanking {
+    pub class: CallClass,
+    /// The position of the provider among all providers for `class`,
+    /// starting at 1 for the one that requests go to when capabilities do
+    /// not rule it out
+    pub rank: usize,
+    pub provider: String,
+    pub capabilities: NodeCapabilities,
+    /// The average latency in seconds, `None` if the provider has not
+    /// answered a request of `class` yet
+    pub latency: Option<f64>,
+    /// Whether the last request of `class` to the provider failed
+    pub failing: bool,
+    pub pinned: bool,
+}
+
+/// How many locks the sticky routes are spread over so that deployments
+/// rarely wait for each other when they route a request
+const STICKY_SHARDS: usize = 16;
+
+/// How long a routing decision is remembered. The related requests for a
+/// block are made within moments of each other, and this makes the routes
+/// of deployments that stopped go away
+const STICKY_ROUTE_TTL: Duration = Duration::from_secs(60);
+
+/// The block and provider of the last routing decision for a class of
+/// requests of a deployment
+#[derive(Clone, Debug)]
+struct StickyRoute {
+    block: BlockNumber,
+    /// `None` for requests about a range of final blocks
+    hash: Option<BlockHash>,
+    provider: String,
+    /// When the route was last used
+    used: Instant,
+}
+
+type StickyKey = (DeploymentHash, CallClass);
+
+struct StickyShard {
+    routes: HashMap<StickyKey, StickyRoute>,
+    /// When routes that expired were last removed
+    swept: Instant,
+}
+
+impl StickyShard {
+    /// The route for `key` if it has not expired
+    fn get(&self, key: &StickyKey, now: Instant) -> Option<&StickyRoute> {
+        self.routes
+            .get(key)
+            .filter(|route| now.duration_since(route.used) < STICKY_ROUTE_TTL)
+    }
+
+    fn insert(&mut self, key: StickyKey, route: StickyRoute, now: Instant) {
+        if now.duration_since(self.swept) >= STICKY_ROUTE_TTL {
+            self.routes
+                .retain(|_, route| now.duration_since(route.used) < STICKY_ROUTE_TTL);
+            self.swept = now;
+        }
+        self.routes.insert(key, route);
+    }
+}
+
+/// The last routing decision for each deployment and class, spread over
+/// `STICKY_SHARDS` locks by deployment
+struct StickyRoutes {
+    shards: Vec<Mutex<StickyShard>>,
+}
+
+impl Default for StickyRoutes {
+    fn default() -> Self {
+        let now = Instant::now();
+        let shards = (0..STICKY_SHARDS)
+            .map(|_| {
+                Mutex::new(StickyShard {
+                    routes: HashMap::new(),
+                    swept: now,
+                })
+            })
+            .collect();
+        StickyRoutes { shards }
+    }
+}
+
+impl StickyRoutes {
+    fn shard(&self, key: &StickyKey) -> MutexGuard<'_, StickyShard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.shards.len();
+        self.shards[shard].lock().unwrap()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().routes.len())
+            .sum()
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct EthereumNetworkAdapters {
     pub adapters: Vec<EthereumNetworkAdapter>,
     pub call_only_adapters: Vec<EthereumNetworkAdapter>,
+    /// The provider that requests of a class are sent to whenever it has
+    /// sufficient capabilities, regardless of its latency
+    pins: HashMap<CallClass, String>,
+    /// The last routing decision for each deployment and class so that
+    /// related requests of a deployment for the same block go to the same
+    /// provider
+    sticky: Arc<StickyRoutes>,
 }
 
 impl EthereumNetworkAdapters {
@@ -75,6 +189,23 @@ impl EthereumNetworkAdapters {
             })
     }
 
+    /// Select randomly an adapter with sufficient capabilities whose
+    /// provider is not `provider`, regardless of its cost. This is used to
+    /// check the responses of `provider` against another provider
+    pub fn other_than(
+        &self,
+        provider: &str,
+        required_capabilities: &NodeCapabilities,
+    ) -> Option<Arc<EthereumAdapter>> {
+        self.adapters
+            .iter()
+            .filter(|adapter| &adapter.capabilities >= required_capabilities)
+            .filter(|adapter| adapter.adapter.provider() != provider)
+            .filter(|adapter| Arc::strong_count(&adapter.adapter) < adapter.limit)
+            .map(|adapter| adapter.adapter.cheap_clone())
+            .choose(&mut rand::thread_rng())
+    }
+
     pub fn cheapest(&self) -> Option<Arc<EthereumAdapter>> {
         // EthereumAdapters are sorted by their NodeCapabilities when the EthereumNetworks
         // struct is instantiated so they do not need to be sorted here
@@ -102,6 +233,152 @@ impl EthereumNetworkAdapters {
         }
     }
 
+    /// Send requests of `class` to `provider` whenever it has sufficient
+    /// capabilities for them
+    pub fn pin(&mut self, class: CallClass, provider: &str) {
+        self.pins.insert(class, provider.to_string());
+    }
+
+    /// All adapters that can serve requests of `class`, cheapest first
+    fn adapters_for(&self, class: CallClass) -> impl Iterator<Item = &EthereumNetworkAdapter> + '_ {
+        let call_only: &[EthereumNetworkAdapter] = match class {
+            CallClass::Calls => self.call_only_adapters.as_slice(),
+            CallClass::Logs | CallClass::Blocks => &[],
+        };
+        call_only.iter().chain(self.adapters.iter())
+    }
+
+    /// The adapters for `class`, in the order in which latency routing
+    /// prefers them: a pinned provider first, then providers whose last
+    /// request succeeded by increasing latency, then providers that have
+    /// not answered a request of `class` yet, and providers whose last
+    /// request failed last
+    fn ranked(&self, class: CallClass) -> Vec<&EthereumNetworkAdapter> {
+        let pinned = self.pins.get(&class);
+        let tier = |adapter: &EthereumNetworkAdapter| {
+            if Some(adapter.adapter.provider()) == pinned.map(String::as_str) {
+                0
+            } else if adapter.adapter.is_failing(class) {
+                3
+            } else if adapter.adapter.latency(class).is_none() {
+                2
+            } else {
+                1
+            }
+        };
+        let mut adapters: Vec<_> = self.adapters_for(class).collect();
+        // The sort is stable, and ties therefore go to the cheaper adapter
+        adapters.sort_by(|a, b| {
+            let a_latency = a.adapter.latency(class).unwrap_or(0.0);
+            let b_latency = b.adapter.latency(class).unwrap_or(0.0);
+            tier(a)
+                .cmp(&tier(b))
+                .then(a_latency.partial_cmp(&b_latency).unwrap_or(Ordering::Equal))
+        });
+        adapters
+    }
+
+    /// The adapter for a request of `class` that is not bound by an
+    /// earlier routing decision among the adapters that are `capable`: a
+    /// pinned provider, otherwise a provider that has not answered yet or
+    /// is failing if it is due for a probe, and otherwise the provider that
+    /// `ranked` prefers
+    fn choose(
+        &self,
+        class: CallClass,
+        capable: impl Fn(&&EthereumNetworkAdapter) -> bool,
+    ) -> Option<&EthereumNetworkAdapter> {
+        let candidates: Vec<_> = self.ranked(class).into_iter().filter(capable).collect();
+        let preferred = *candidates.first()?;
+        if self.pins.get(&class).map(String::as_str) == Some(preferred.adapter.provider()) {
+            return Some(preferred);
+        }
+        candidates
+            .into_iter()
+            .filter(|adapter| {
+                adapter.adapter.is_failing(class) || adapter.adapter.latency(class).is_none()
+            })
+            .find(|adapter| adapter.adapter.try_probe(class))
+            .or(Some(preferred))
+    }
+
+    /// Select the adapter for a request of `class` that `deployment`
+    /// makes about the block with number `block` and hash `hash`; the hash
+    /// is `None` for requests about a range of final blocks. Only adapters
+    /// with sufficient capabilities are considered, and `choose` picks one
+    /// of them. All requests of `class` that `deployment` makes about the
+    /// same block go to the same provider as long as it remains capable; a
+    /// block with the same number but a different hash, e.g., after a
+    /// reorg, is routed afresh
+    pub fn fastest_with(
+        &self,
+        required_capabilities: &NodeCapabilities,
+        class: CallClass,
+        deployment: &DeploymentHash,
+        block: BlockNumber,
+        hash: Option<&BlockHash>,
+    ) -> Result<Arc<EthereumAdapter>, Error> {
+        let capable = |adapter: &&EthereumNetworkAdapter| {
+            &adapter.capabilities >= required_capabilities
+                && Arc::strong_count(&adapter.adapter) < adapter.limit
+        };
+
+        let key = (deployment.cheap_clone(), class);
+        let now = Instant::now();
+        let mut sticky = self.sticky.shard(&key);
+        let adapter = match sticky.get(&key, now) {
+            Some(route) if route.block == block && route.hash.as_ref() == hash => self
+                .adapters_for(class)
+                .filter(capable)
+                .find(|adapter| adapter.adapter.provider() == route.provider.as_str()),
+            _ => None,
+        };
+        let adapter = match adapter {
+            Some(adapter) => adapter,
+            None => self.choose(class, capable).with_context(|| {
+                anyhow!(
+                    "A matching Ethereum network with {:?} was not found.",
+                    required_capabilities
+                )
+            })?,
+        };
+        sticky.insert(
+            key,
+            StickyRoute {
+                block,
+                hash: hash.cloned(),
+                provider: adapter.adapter.provider().to_string(),
+                used: now,
+            },
+            now,
+        );
+
+        adapter.adapter.count_routed(class);
+        Ok(adapter.adapter.cheap_clone())
+    }
+
+    /// The current ranking of the providers for each class of requests
+    pub fn rankings(&self) -> Vec<ProviderRanking> {
+        CallClass::ALL
+            .iter()
+            .flat_map(|class| {
+                self.ranked(*class)
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, adapter)| ProviderRanking {
+                        class: *class,
+                        rank: i + 1,
+                        provider: adapter.adapter.provider().to_string(),
+                        capabilities: adapter.capabilities,
+                        latency: adapter.adapter.latency(*class),
+                        failing: adapter.adapter.is_failing(*class),
+                        pinned: self.pins.get(class).map(String::as_str)
+                            == Some(adapter.adapter.provider()),
+                    })
+            })
+            .collect()
+    }
+
     pub fn call_only_adapter(&self) -> anyhow::Result<Option<Arc<EthereumAdapter>>> {
         if self.call_only_adapters.is_empty() {
             return Ok(None);
@@ -162,6 +439,14 @@ impl EthereumNetworks {
         }
     }
 
+    /// Send requests of `class` on network `name` to `provider` whenever
+    /// it has sufficient capabilities for them
+    pub fn pin(&mut self, name: &str, class: CallClass, provider: &str) {
+        if let Some(adapters) = self.networks.get_mut(name) {
+            adapters.pin(class, provider);
+        }
+    }
+
     pub fn extend(&mut self, other_networks: EthereumNetworks) {
         self.networks.extend(other_networks.networks);
     }
@@ -211,15 +496,22 @@ impl EthereumNetworks {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Instant;
 
-    use graph::{prelude::MetricsRegistry, tokio, url::Url};
+    use graph::{
+        prelude::{BlockHash, BlockNumber, DeploymentHash, MetricsRegistry},
+        tokio,
+        url::Url,
+    };
     use graph_mock::MockMetricsRegistry;
     use http::HeaderMap;
 
-    use crate::{EthereumAdapter, EthereumNetworks, ProviderEthRpcMetrics, Transport};
+    use crate::adapter::EthereumAdapter as _;
+    use crate::{CallClass, EthereumAdapter, EthereumNetworks, ProviderEthRpcMetrics, Transport};
 
-    use super::NodeCapabilities;
+    use super::{NodeCapabilities, StickyRoute, StickyShard, STICKY_ROUTE_TTL};
 
     #[test]
     fn ethereum_capabilities_comparison() {
@@ -375,4 +667,161 @@ mod tests {
             assert_eq!(adapter.is_call_only(), false);
         }
     }
+
+    #[test]
+    fn sticky_routes_expire() {
+        let start = Instant::now();
+        let route = |used| StickyRoute {
+            block: 1,
+            hash: None,
+            provider: "p1".to_string(),
+            used,
+        };
+        let key = |name: &str| (DeploymentHash::new(name).unwrap(), CallClass::Calls);
+        let mut shard = StickyShard {
+            routes: HashMap::new(),
+            swept: start,
+        };
+
+        shard.insert(key("QmStopped"), route(start), start);
+        assert!(shard.get(&key("QmStopped"), start).is_some());
+
+        // An expired route is ignored, and removed with the next insert
+        let later = start + STICKY_ROUTE_TTL;
+        assert!(shard.get(&key("QmStopped"), later).is_none());
+        shard.insert(key("QmRunning"), route(later), later);
+        assert_eq!(1, shard.routes.len());
+        assert!(shard.get(&key("QmRunning"), later).is_some());
+    }
+
+    #[tokio::test]
+    async fn fastest_with_routes_by_latency() {
+        let chain = "mainnet".to_string();
+        let logger = graph::log::logger(true);
+        let mock_registry: Arc<dyn MetricsRegistry> = Arc::new(MockMetricsRegistry::new());
+        let transport =
+            Transport::new_rpc(Url::parse("http://127.0.0.1").unwrap(), HeaderMap::new());
+        let provider_metrics = Arc::new(ProviderEthRpcMetrics::new(mock_registry.clone()));
+
+        let archive = NodeCapabilities {
+            archive: true,
+            traces: false,
+        };
+        let full = NodeCapabilities {
+            archive: false,
+            traces: false,
+        };
+
+        let mut ethereum_networks = EthereumNetworks::new();
+        for (provider, capabilities) in [("archive", archive), ("full", full)] {
+            let adapter = EthereumAdapter::new(
+                logger.clone(),
+                provider.to_string(),
+                "http://127.0.0.1",
+                transport.clone(),
+                provider_metrics.clone(),
+                true,
+                false,
+            )
+            .await;
+            ethereum_networks.insert(chain.clone(), capabilities, Arc::new(adapter), 10);
+        }
+        ethereum_networks.sort();
+        let adapters = ethereum_networks.networks.get(&chain).unwrap().clone();
+
+        let deployment = DeploymentHash::new("QmDeployment").unwrap();
+        let other = DeploymentHash::new("QmOther").unwrap();
+        let hash = |n: u8| BlockHash::from(vec![n; 32]);
+        let route_for = |deployment: &DeploymentHash,
+                         capabilities: &NodeCapabilities,
+                         block: BlockNumber,
+                         hash: &BlockHash| {
+            adapters
+                .fastest_with(
+                    capabilities,
+                    CallClass::Calls,
+                    deployment,
+                    block,
+                    Some(hash),
+                )
+                .unwrap()
+                .provider()
+                .to_string()
+        };
+        let route = |capabilities: &NodeCapabilities, block: BlockNumber| {
+            route_for(&deployment, capabilities, block, &hash(block as u8))
+        };
+
+        // A provider that has not answered yet gets one request to probe
+        // it, and otherwise comes after the providers that answered
+        provider_metrics.observe_latency(1.0, "eth_call", "archive");
+        assert_eq!("full", route(&full, 7));
+        assert_eq!("archive", route(&full, 8));
+
+        provider_metrics.observe_latency(0.1, "eth_call", "full");
+        // Capabilities take precedence over latency
+        assert_eq!("archive", route(&archive, 9));
+        assert_eq!("full", route(&full, 10));
+
+        // The full node becomes slower, but requests for the same block
+        // stay with it; a different block hash, e.g., after a reorg, or a
+        // different deployment is routed afresh
+        for _ in 0..20 {
+            provider_metrics.observe_latency(5.0, "eth_call", "full");
+        }
+        assert_eq!("full", route(&full, 10));
+        assert_eq!("archive", route_for(&deployment, &full, 10, &hash(42)));
+        assert_eq!("archive", route_for(&other, &full, 10, &hash(10)));
+        assert_eq!("archive", route(&full, 11));
+
+        // A failing provider comes last even if it was faster, and stops
+        // failing once it answers again
+        let archive_failing = || {
+            adapters
+                .rankings()
+                .into_iter()
+                .any(|ranking| ranking.provider == "archive" && ranking.failing)
+        };
+        provider_metrics.observe_failure("eth_call", "archive");
+        assert!(archive_failing());
+        assert_eq!("full", route(&full, 12));
+        provider_metrics.observe_latency(0.1, "eth_call", "archive");
+        assert!(!archive_failing());
+
+        // A pin overrides the latency as long as capabilities allow it
+        let mut pinned = adapters.clone();
+        pinned.pin(CallClass::Calls, "full");
+        let pinned_route = |capabilities: &NodeCapabilities, block: BlockNumber| {
+            pinned
+                .fastest_with(
+                    capabilities,
+                    CallClass::Calls,
+                    &deployment,
+                    block,
+                    Some(&hash(block as u8)),
+                )
+                .unwrap()
+                .provider()
+                .to_string()
+        };
+        assert_eq!("full", pinned_route(&full, 14));
+        assert_eq!("archive", pinned_route(&archive, 15));
+
+        let rankings: Vec<_> = pinned
+            .rankings()
+            .into_iter()
+            .filter(|ranking| ranking.class == CallClass::Calls)
+            .map(|ranking| (ranking.rank, ranking.provider, ranking.pinned))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, "full".to_string(), true),
+                (2, "archive".to_string(), false)
+            ],
+            rankings
+        );
+
+        // Every deployment and class has at most one sticky route
+        assert_eq!(2, adapters.sticky.len());
+    }
 }
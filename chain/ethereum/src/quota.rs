@@ -0,0 +1,206 @@
+//! Account for the requests that are sent to a provider in the units the
+//! provider bills them in. Each method has a cost in units, configured per
+//! provider, and the units consumed since the node started are projected
+//! to a monthly figure. When a provider has a budget and the projection
+//! exceeds it, warnings are logged and, if the budget says so, deployments
+//! that are catching up on the provider's network get less processing
+//! time.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::components::metrics::provider_budget::set_over_budget;
+use graph::prelude::{info, warn, Logger};
+use serde::{Deserialize, Serialize};
+
+/// The cost of a request whose method has no configured cost
+pub const DEFAULT_REQUEST_COST: f64 = 1.0;
+
+/// The length of the month that usage is projected to
+const MONTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Usage is projected as if at least this much time has passed since the
+/// node started so that a burst of requests right after starting does not
+/// look like it will blow the budget
+const MIN_PROJECTION_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// How often to repeat the warning that a provider is over its budget
+const WARNING_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// A soft limit for the units a provider should use per month
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ProviderBudget {
+    pub monthly_units: f64,
+    /// Whether to give deployments that are catching up on the provider's
+    /// network less processing time while the provider is over budget
+    #[serde(default)]
+    pub deprioritize_backfills: bool,
+}
+
+/// The units a provider used since the node started, and how many it will
+/// use in a month at that rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Usage {
+    pub units: f64,
+    pub projected: f64,
+}
+
+#[derive(Default)]
+struct QuotaState {
+    units: f64,
+    over_budget: bool,
+    last_warning: Option<Instant>,
+}
+
+pub(crate) struct ProviderQuota {
+    logger: Logger,
+    provider: String,
+    network: String,
+    costs: BTreeMap<String, f64>,
+    budget: Option<ProviderBudget>,
+    started: Instant,
+    state: Mutex<QuotaState>,
+}
+
+impl ProviderQuota {
+    pub fn new(
+        logger: Logger,
+        provider: &str,
+        network: &str,
+        costs: BTreeMap<String, f64>,
+        budget: Option<ProviderBudget>,
+    ) -> Self {
+        ProviderQuota {
+            logger,
+            provider: provider.to_string(),
+            network: network.to_string(),
+            costs,
+            budget,
+            started: Instant::now(),
+            state: Mutex::new(QuotaState::default()),
+        }
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    /// Account for a request for `method` and return the resulting usage
+    pub fn record(&self, method: &str) -> Usage {
+        self.record_at(method, Instant::now())
+    }
+
+    fn record_at(&self, method: &str, now: Instant) -> Usage {
+        let cost = self
+            .costs
+            .get(method)
+            .copied()
+            .unwrap_or(DEFAULT_REQUEST_COST);
+
+        let mut state = self.state.lock().unwrap();
+        state.units += cost;
+        let projected = project(state.units, now.saturating_duration_since(self.started));
+        if let Some(budget) = &self.budget {
+            self.check_budget(&mut state, budget, projected, now);
+        }
+        Usage {
+            units: state.units,
+            projected,
+        }
+    }
+
+    fn check_budget(
+        &self,
+        state: &mut QuotaState,
+        budget: &ProviderBudget,
+        projected: f64,
+        now: Instant,
+    ) {
+        let over = projected > budget.monthly_units;
+        if over != state.over_budget {
+            state.over_budget = over;
+            if budget.deprioritize_backfills {
+                set_over_budget(&self.network, &self.provider, over);
+            }
+            if !over {
+                info!(self.logger, "Provider is back within its monthly request budget";
+                      "projected_monthly_units" => projected,
+                      "monthly_budget" => budget.monthly_units);
+                state.last_warning = None;
+            }
+        }
+
+        let warn_again = state.last_warning.map_or(true, |last| {
+            now.saturating_duration_since(last) >= WARNING_INTERVAL
+        });
+        if over && warn_again {
+            warn!(self.logger, "Provider is projected to exceed its monthly request budget";
+                  "units" => state.units,
+                  "projected_monthly_units" => projected,
+                  "monthly_budget" => budget.monthly_units,
+                  "deprioritize_backfills" => budget.deprioritize_backfills);
+            state.last_warning = Some(now);
+        }
+    }
+}
+
+/// The units that will be used in a month if `units` were used in
+/// `elapsed`
+fn project(units: f64, elapsed: Duration) -> f64 {
+    units * MONTH.as_secs_f64() / elapsed.max(MIN_PROJECTION_WINDOW).as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::components::metrics::provider_budget::network_over_budget;
+    use graph::prelude::o;
+    use graph::slog::Discard;
+
+    use super::*;
+
+    fn quota(network: &str, budget: Option<ProviderBudget>) -> ProviderQuota {
+        let costs = BTreeMap::from_iter([
+            ("eth_getLogs".to_string(), 75.0),
+            ("eth_call".to_string(), 26.0),
+        ]);
+        ProviderQuota::new(Logger::root(Discard, o!()), "p1", network, costs, budget)
+    }
+
+    #[test]
+    fn costs() {
+        let quota = quota("quota-costs", None);
+        let start = quota.started;
+        quota.record_at("eth_getLogs", start);
+        quota.record_at("eth_call", start);
+        let usage = quota.record_at("eth_getBlockByNumber", start);
+        assert_eq!(102.0, usage.units);
+        // Projections never assume less than an hour has passed
+        assert_eq!(102.0 * 30.0 * 24.0, usage.projected);
+
+        let usage = quota.record_at("eth_call", start + MONTH / 2);
+        assert_eq!(128.0, usage.units);
+        assert_eq!(256.0, usage.projected);
+    }
+
+    #[test]
+    fn budget() {
+        let network = "quota-budget";
+        let budget = ProviderBudget {
+            monthly_units: 100_000.0,
+            deprioritize_backfills: true,
+        };
+        let quota = quota(network, Some(budget));
+        let start = quota.started;
+
+        // 75 units an hour project to 54000 units a month
+        quota.record_at("eth_getLogs", start);
+        assert!(!network_over_budget(network));
+        // 150 units an hour project to 108000 units a month
+        quota.record_at("eth_getLogs", start);
+        assert!(network_over_budget(network));
+        // Two hours later, the rate is half that
+        quota.record_at("eth_getBlockByNumber", start + 2 * MIN_PROJECTION_WINDOW);
+        assert!(!network_over_budget(network));
+    }
+}
@@ -0,0 +1,409 @@
+//! How Ethereum triggers are stored in trigger files. Blocks and
+//! transactions use the JSON-RPC representation; the decoded parameters of
+//! events and calls are stored with their ABI type so that they decode to
+//! exactly the tokens that the mapping originally received.
+
+use std::sync::Arc;
+
+use graph::blockchain::replay::ReplayableTrigger;
+use graph::blockchain::HostFn;
+use graph::prelude::ethabi::{Address, LogParam, Token};
+use graph::prelude::web3::types::{Bytes, Log, Transaction, TransactionReceipt, H256, U256};
+use graph::prelude::{anyhow, serde_json, BlockNumber, Deserialize, EthereumCall, Serialize};
+use graph::runtime::HostExportError;
+
+use crate::trigger::{MappingTrigger, TriggerTransaction};
+
+// ETHDEP: This should be defined in only one place.
+type LightEthereumBlock = graph::prelude::web3::types::Block<Transaction>;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ReplayTrigger {
+    Log {
+        transaction: ReplayTransaction,
+        log: Log,
+        params: Vec<ReplayParam>,
+        receipt: Option<TransactionReceipt>,
+    },
+    Call {
+        transaction: ReplayTransaction,
+        call: ReplayCall,
+        inputs: Vec<ReplayParam>,
+        outputs: Vec<ReplayParam>,
+    },
+    Block,
+}
+
+/// A transaction that is part of the block is stored as its position in
+/// the block
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ReplayTransaction {
+    InBlock(usize),
+    Detached(Transaction),
+}
+
+impl ReplayTransaction {
+    fn new(transaction: &TriggerTransaction) -> Self {
+        match transaction {
+            TriggerTransaction::InBlock(_, idx) => ReplayTransaction::InBlock(*idx),
+            TriggerTransaction::Detached(transaction) => {
+                ReplayTransaction::Detached(transaction.as_ref().clone())
+            }
+        }
+    }
+
+    fn into_transaction(
+        self,
+        block: &Arc<LightEthereumBlock>,
+    ) -> Result<TriggerTransaction, anyhow::Error> {
+        match self {
+            ReplayTransaction::InBlock(idx) if idx < block.transactions.len() => {
+                Ok(TriggerTransaction::InBlock(block.clone(), idx))
+            }
+            ReplayTransaction::InBlock(idx) => Err(anyhow::anyhow!(
+                "the block has no transaction at index {}",
+                idx
+            )),
+            ReplayTransaction::Detached(transaction) => {
+                Ok(TriggerTransaction::Detached(Arc::new(transaction)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplayCall {
+    from: Address,
+    to: Address,
+    value: U256,
+    gas_used: U256,
+    input: Bytes,
+    output: Bytes,
+    block_number: BlockNumber,
+    block_hash: H256,
+    transaction_hash: Option<H256>,
+    transaction_index: u64,
+}
+
+impl From<&EthereumCall> for ReplayCall {
+    fn from(call: &EthereumCall) -> Self {
+        ReplayCall {
+            from: call.from,
+            to: call.to,
+            value: call.value,
+            gas_used: call.gas_used,
+            input: call.input.clone(),
+            output: call.output.clone(),
+            block_number: call.block_number,
+            block_hash: call.block_hash,
+            transaction_hash: call.transaction_hash,
+            transaction_index: call.transaction_index,
+        }
+    }
+}
+
+impl From<ReplayCall> for EthereumCall {
+    fn from(call: ReplayCall) -> Self {
+        EthereumCall {
+            from: call.from,
+            to: call.to,
+            value: call.value,
+            gas_used: call.gas_used,
+            input: call.input,
+            output: call.output,
+            block_number: call.block_number,
+            block_hash: call.block_hash,
+            transaction_hash: call.transaction_hash,
+            transaction_index: call.transaction_index,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ReplayParam {
+    name: String,
+    value: ReplayToken,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ReplayToken {
+    Address(Address),
+    FixedBytes(Bytes),
+    Bytes(Bytes),
+    Int(U256),
+    Uint(U256),
+    Bool(bool),
+    String(String),
+    FixedArray(Vec<ReplayToken>),
+    Array(Vec<ReplayToken>),
+    Tuple(Vec<ReplayToken>),
+}
+
+impl From<&Token> for ReplayToken {
+    fn from(token: &Token) -> Self {
+        let tokens = |tokens: &[Token]| tokens.iter().map(ReplayToken::from).collect();
+        match token {
+            Token::Address(address) => ReplayToken::Address(*address),
+            Token::FixedBytes(bytes) => ReplayToken::FixedBytes(Bytes(bytes.clone())),
+            Token::Bytes(bytes) => ReplayToken::Bytes(Bytes(bytes.clone())),
+            Token::Int(value) => ReplayToken::Int(*value),
+            Token::Uint(value) => ReplayToken::Uint(*value),
+            Token::Bool(value) => ReplayToken::Bool(*value),
+            Token::String(value) => ReplayToken::String(value.clone()),
+            Token::FixedArray(values) => ReplayToken::FixedArray(tokens(values)),
+            Token::Array(values) => ReplayToken::Array(tokens(values)),
+            Token::Tuple(values) => ReplayToken::Tuple(tokens(values)),
+        }
+    }
+}
+
+impl From<ReplayToken> for Token {
+    fn from(token: ReplayToken) -> Self {
+        let tokens = |tokens: Vec<ReplayToken>| tokens.into_iter().map(Token::from).collect();
+        match token {
+            ReplayToken::Address(address) => Token::Address(address),
+            ReplayToken::FixedBytes(bytes) => Token::FixedBytes(bytes.0),
+            ReplayToken::Bytes(bytes) => Token::Bytes(bytes.0),
+            ReplayToken::Int(value) => Token::Int(value),
+            ReplayToken::Uint(value) => Token::Uint(value),
+            ReplayToken::Bool(value) => Token::Bool(value),
+            ReplayToken::String(value) => Token::String(value),
+            ReplayToken::FixedArray(values) => Token::FixedArray(tokens(values)),
+            ReplayToken::Array(values) => Token::Array(tokens(values)),
+            ReplayToken::Tuple(values) => Token::Tuple(tokens(values)),
+        }
+    }
+}
+
+fn replay_params(params: &[LogParam]) -> Vec<ReplayParam> {
+    params
+        .iter()
+        .map(|param| ReplayParam {
+            name: param.name.clone(),
+            value: ReplayToken::from(&param.value),
+        })
+        .collect()
+}
+
+fn log_params(params: Vec<ReplayParam>) -> Vec<LogParam> {
+    params
+        .into_iter()
+        .map(|param| LogParam {
+            name: param.name,
+            value: Token::from(param.value),
+        })
+        .collect()
+}
+
+/// The host functions of Ethereum mappings when triggers are replayed.
+/// There is no provider then, so contract calls always fail
+pub fn replay_host_fns() -> Vec<HostFn> {
+    vec![HostFn {
+        name: "ethereum.call",
+        func: Arc::new(|_ctx, _wasm_ptr| {
+            Err(HostExportError::Unknown(anyhow::anyhow!(
+                "contract calls are not available when replaying triggers"
+            )))
+        }),
+    }]
+}
+
+impl ReplayableTrigger for MappingTrigger {
+    type Block = LightEthereumBlock;
+
+    fn replay_block(&self) -> &LightEthereumBlock {
+        match self {
+            MappingTrigger::Log { block, .. }
+            | MappingTrigger::Call { block, .. }
+            | MappingTrigger::Block { block } => block,
+        }
+    }
+
+    fn to_replay(&self) -> Result<serde_json::Value, anyhow::Error> {
+        let trigger = match self {
+            MappingTrigger::Log {
+                block: _,
+                transaction,
+                log,
+                params,
+                receipt,
+            } => ReplayTrigger::Log {
+                transaction: ReplayTransaction::new(transaction),
+                log: log.as_ref().clone(),
+                params: replay_params(params),
+                receipt: receipt.as_ref().map(|receipt| receipt.as_ref().clone()),
+            },
+            MappingTrigger::Call {
+                block: _,
+                transaction,
+                call,
+                inputs,
+                outputs,
+            } => ReplayTrigger::Call {
+                transaction: ReplayTransaction::new(transaction),
+                call: ReplayCall::from(call.as_ref()),
+                inputs: replay_params(inputs),
+                outputs: replay_params(outputs),
+            },
+            MappingTrigger::Block { block: _ } => ReplayTrigger::Block,
+        };
+        Ok(serde_json::to_value(trigger)?)
+    }
+
+    fn from_replay(
+        block: &Arc<LightEthereumBlock>,
+        trigger: serde_json::Value,
+    ) -> Result<Self, anyhow::Error> {
+        let trigger = match serde_json::from_value(trigger)? {
+            ReplayTrigger::Log {
+                transaction,
+                log,
+                params,
+                receipt,
+            } => MappingTrigger::Log {
+                block: block.clone(),
+                transaction: transaction.into_transaction(block)?,
+                log: Arc::new(log),
+                params: log_params(params),
+                receipt: receipt.map(Arc::new),
+            },
+            ReplayTrigger::Call {
+                transaction,
+                call,
+                inputs,
+                outputs,
+            } => MappingTrigger::Call {
+                block: block.clone(),
+                transaction: transaction.into_transaction(block)?,
+                call: Arc::new(EthereumCall::from(call)),
+                inputs: log_params(inputs),
+                outputs: log_params(outputs),
+            },
+            ReplayTrigger::Block => MappingTrigger::Block {
+                block: block.clone(),
+            },
+        };
+        Ok(trigger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::web3::types::H160;
+
+    use super::*;
+
+    fn block() -> Arc<LightEthereumBlock> {
+        let mut block = LightEthereumBlock::default();
+        block.number = Some(7.into());
+        block.hash = Some(H256::repeat_byte(1));
+        block.transactions.push(Transaction {
+            hash: H256::repeat_byte(2),
+            ..Default::default()
+        });
+        Arc::new(block)
+    }
+
+    fn round_trip(trigger: &MappingTrigger) -> MappingTrigger {
+        let stored = serde_json::to_value(trigger.replay_block()).unwrap();
+        let block: Arc<LightEthereumBlock> = Arc::new(serde_json::from_value(stored).unwrap());
+        let value = trigger.to_replay().unwrap();
+        let value: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
+        MappingTrigger::from_replay(&block, value).unwrap()
+    }
+
+    #[test]
+    fn log_trigger() {
+        let block = block();
+        let params = vec![
+            LogParam {
+                name: "sender".to_string(),
+                value: Token::Address(H160::repeat_byte(3)),
+            },
+            LogParam {
+                name: "amounts".to_string(),
+                value: Token::Array(vec![Token::Int(U256::MAX), Token::Uint(U256::from(42))]),
+            },
+            LogParam {
+                name: "info".to_string(),
+                value: Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::String("swap".to_string()),
+                    Token::Bytes(vec![1, 2, 3]),
+                    Token::FixedBytes(vec![4; 32]),
+                    Token::FixedArray(vec![Token::Uint(1.into()), Token::Uint(2.into())]),
+                ]),
+            },
+        ];
+        let trigger = MappingTrigger::Log {
+            block: block.clone(),
+            transaction: TriggerTransaction::in_block(&block, Some(H256::repeat_byte(2))).unwrap(),
+            log: Arc::new(Log::default()),
+            params: params.clone(),
+            receipt: None,
+        };
+
+        match round_trip(&trigger) {
+            MappingTrigger::Log {
+                block: replayed,
+                transaction,
+                params: replayed_params,
+                receipt,
+                ..
+            } => {
+                assert_eq!(block.as_ref(), replayed.as_ref());
+                assert_eq!(H256::repeat_byte(2), transaction.hash);
+                assert_eq!(params, replayed_params);
+                assert!(receipt.is_none());
+            }
+            trigger => panic!("expected a log trigger but got {:?}", trigger),
+        }
+    }
+
+    #[test]
+    fn call_and_block_triggers() {
+        let block = block();
+        let call = EthereumCall {
+            from: H160::repeat_byte(5),
+            to: H160::repeat_byte(6),
+            input: Bytes(vec![7, 8]),
+            block_number: 7,
+            ..Default::default()
+        };
+        let detached = Transaction {
+            hash: H256::repeat_byte(9),
+            ..Default::default()
+        };
+        let trigger = MappingTrigger::Call {
+            block: block.clone(),
+            transaction: TriggerTransaction::Detached(Arc::new(detached)),
+            call: Arc::new(call.clone()),
+            inputs: vec![],
+            outputs: vec![LogParam {
+                name: "ok".to_string(),
+                value: Token::Bool(true),
+            }],
+        };
+        match round_trip(&trigger) {
+            MappingTrigger::Call {
+                transaction,
+                call: replayed,
+                outputs,
+                ..
+            } => {
+                assert_eq!(H256::repeat_byte(9), transaction.hash);
+                assert_eq!(&call, replayed.as_ref());
+                assert_eq!(1, outputs.len());
+            }
+            trigger => panic!("expected a call trigger but got {:?}", trigger),
+        }
+
+        let trigger = MappingTrigger::Block { block };
+        assert!(matches!(round_trip(&trigger), MappingTrigger::Block { .. }));
+    }
+}
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Instant};
 use crate::data_source::MappingABI;
 use crate::ENV_VARS;
 use crate::{
-    capabilities::NodeCapabilities, network::EthereumNetworkAdapters, Chain, DataSource,
+    capabilities::NodeCapabilities, network::EthereumNetworkAdapters, CallClass, Chain, DataSource,
     EthereumAdapter, EthereumAdapterTrait, EthereumContractCall, EthereumContractCallError,
 };
 use anyhow::{Context, Error};
@@ -15,7 +15,7 @@ use graph::{
     cheap_clone::CheapClone,
     prelude::{
         ethabi::{self, Address, Token},
-        EthereumCallCache, Future01CompatExt,
+        ChainStore, DeploymentHash, EthereumCallCache, Future01CompatExt,
     },
     runtime::{asc_get, asc_new, AscPtr, HostExportError},
     semver::Version,
@@ -40,20 +40,37 @@ pub const ETHEREUM_CALL: Gas = Gas::new(5_000_000_000);
 pub struct RuntimeAdapter {
     pub eth_adapters: Arc<EthereumNetworkAdapters>,
     pub call_cache: Arc<dyn EthereumCallCache>,
+    /// Used to find the chain head when routing calls by latency
+    pub chain_store: Arc<dyn ChainStore>,
 }
 
 impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
     fn host_fns(&self, ds: &DataSource) -> Result<Vec<HostFn>, Error> {
         let abis = ds.mapping.abis.clone();
         let call_cache = self.call_cache.cheap_clone();
-        let eth_adapter = self.eth_adapters.call_or_cheapest(Some(&NodeCapabilities {
+        let capabilities = NodeCapabilities {
             archive: ds.mapping.requires_archive()?,
             traces: false,
-        }))?;
+        };
+        let eth_adapter = self.eth_adapters.call_or_cheapest(Some(&capabilities))?;
+        let eth_adapters = self.eth_adapters.cheap_clone();
+        let chain_store = self.chain_store.cheap_clone();
 
         let ethereum_call = HostFn {
             name: "ethereum.call",
             func: Arc::new(move |ctx, wasm_ptr| {
+                let eth_adapter = if ENV_VARS.latency_routing {
+                    route_call(
+                        &eth_adapters,
+                        &chain_store,
+                        &capabilities,
+                        &ctx.deployment,
+                        &ctx.block_ptr,
+                    )
+                    .unwrap_or_else(|_| eth_adapter.cheap_clone())
+                } else {
+                    eth_adapter.cheap_clone()
+                };
                 ethereum_call(&eth_adapter, call_cache.cheap_clone(), ctx, wasm_ptr, &abis)
                     .map(|ptr| ptr.wasm_ptr())
             }),
@@ -63,6 +80,29 @@ impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
     }
 }
 
+/// The fastest adapter for an `eth_call` that `deployment` makes at
+/// `block_ptr`. Calls close to the chain head do not need an archive node
+/// even if the data source requires one for older blocks
+fn route_call(
+    eth_adapters: &EthereumNetworkAdapters,
+    chain_store: &Arc<dyn ChainStore>,
+    capabilities: &NodeCapabilities,
+    deployment: &DeploymentHash,
+    block_ptr: &BlockPtr,
+) -> Result<Arc<EthereumAdapter>, Error> {
+    let head = graph::block_on(chain_store.cheap_clone().cached_head_ptr())
+        .ok()
+        .flatten()
+        .map(|ptr| ptr.number);
+    eth_adapters.fastest_with(
+        &capabilities.for_block(block_ptr.number, head),
+        CallClass::Calls,
+        deployment,
+        block_ptr.number,
+        Some(&block_ptr.hash),
+    )
+}
+
 /// function ethereum.call(call: SmartContractCall): Array<Token> | null
 fn ethereum_call(
     eth_adapter: &EthereumAdapter,
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use graph::{
     blockchain::{block_stream::BlockWithTriggers, BlockPtr},
     prelude::{
-        web3::types::{Address, Bytes, Log, H160, H256, U64},
+        web3::types::{Address, Bytes, Log, Transaction, H160, H256, U64},
         EthereumCall, LightEthereumBlock,
     },
     slog::{self, o, Logger},
@@ -11,7 +11,9 @@ use graph::{
 
 use crate::{
     chain::BlockFinality,
-    trigger::{EthereumBlockTriggerType, EthereumTrigger},
+    trigger::{
+        EthereumBlockTriggerType, EthereumTransactionData, EthereumTrigger, TriggerTransaction,
+    },
 };
 
 #[test]
@@ -208,3 +210,119 @@ fn test_trigger_dedup() {
         vec![log1, log2, call1, log3, call2, call3, block2, block1]
     );
 }
+
+#[test]
+fn test_block_finalizer_matching() {
+    use graph::blockchain::{Block as _, DataSource as _};
+    use graph::prelude::{ethabi::Contract, Link};
+
+    use crate::data_source::{MappingBlockFinalizer, MappingBlockHandler};
+    use crate::{DataSource, Mapping, MappingABI};
+
+    fn data_source(block_finalizer: Option<&str>) -> DataSource {
+        DataSource {
+            kind: String::from("ethereum/contract"),
+            name: String::from("Token"),
+            manifest_idx: 0,
+            network: Some(String::from("mainnet")),
+            address: Some(Address::random()),
+            start_block: 0,
+            mapping: Mapping {
+                kind: String::from("ethereum/events"),
+                api_version: semver::Version::new(0, 0, 7),
+                language: String::from("wasm/assemblyscript"),
+                entities: vec![],
+                abis: vec![],
+                event_handlers: vec![],
+                call_handlers: vec![],
+                block_handlers: vec![MappingBlockHandler {
+                    handler: String::from("handleBlock"),
+                    filter: None,
+                }],
+                block_finalizer: block_finalizer.map(|handler| MappingBlockFinalizer {
+                    handler: handler.to_string(),
+                }),
+                runtime: Arc::new(vec![]),
+                link: Link {
+                    link: String::from("link"),
+                },
+            },
+            context: Default::default(),
+            creation_block: None,
+            creation_seq: None,
+            contract_abi: Arc::new(MappingABI {
+                name: String::from("Token"),
+                contract: Contract::load(&b"[]"[..]).unwrap(),
+            }),
+        }
+    }
+
+    let logger = Logger::root(slog::Discard, o!());
+    let mut b: LightEthereumBlock = Default::default();
+    b.number = Some(Default::default());
+    b.hash = Some(Default::default());
+    let block = Arc::new(BlockFinality::Final(Arc::new(b)));
+    let finalize = EthereumTrigger::Block(block.ptr(), EthereumBlockTriggerType::Finalize);
+
+    // The finalizer trigger runs the finalizer regardless of the data
+    // source's address, and not the ordinary block handler
+    let ds = data_source(Some("finalizeBlock"));
+    let trigger = ds
+        .match_and_decode(&finalize, &block, &logger)
+        .unwrap()
+        .unwrap();
+    assert_eq!("finalizeBlock", trigger.handler_name());
+    assert_eq!(2, ds.handler_count());
+
+    // Data sources without a finalizer ignore the trigger
+    let ds = data_source(None);
+    assert!(ds
+        .match_and_decode(&finalize, &block, &logger)
+        .unwrap()
+        .is_none());
+}
+
+fn block_with_transactions(transactions: usize, input_len: usize) -> Arc<LightEthereumBlock> {
+    let transactions = (0..transactions)
+        .map(|i| Transaction {
+            hash: H256::from_low_u64_be(i as u64 + 1),
+            transaction_index: Some(U64::from(i)),
+            from: Some(H160::zero()),
+            input: Bytes(vec![7; input_len]),
+            ..Transaction::default()
+        })
+        .collect();
+    Arc::new(LightEthereumBlock {
+        transactions,
+        ..LightEthereumBlock::default()
+    })
+}
+
+#[test]
+fn test_trigger_transaction_in_block() {
+    let block = block_with_transactions(3, 4);
+
+    let tx = TriggerTransaction::in_block(&block, Some(H256::from_low_u64_be(2))).unwrap();
+    assert!(matches!(tx, TriggerTransaction::InBlock(_, 1)));
+    assert_eq!(block.transactions[1], *tx);
+    // Debug output is the same as for the transaction itself
+    assert_eq!(format!("{:?}", block.transactions[1]), format!("{:?}", tx));
+
+    assert!(TriggerTransaction::in_block(&block, Some(H256::from_low_u64_be(9))).is_none());
+    assert!(TriggerTransaction::in_block(&block, None).is_none());
+}
+
+#[test]
+fn test_trigger_transaction_input_is_shared() {
+    let block = block_with_transactions(3, 1024);
+    let tx = TriggerTransaction::in_block(&block, Some(H256::from_low_u64_be(2))).unwrap();
+
+    // The data that is written to the WASM heap reads the input from the
+    // transaction in the block instead of copying it
+    let data = EthereumTransactionData::from(&tx);
+    assert_eq!(block.transactions[1].input.0.as_slice(), &*data.input);
+    assert!(std::ptr::eq(
+        block.transactions[1].input.0.as_ptr(),
+        data.input.as_ptr()
+    ));
+}
@@ -6,6 +6,7 @@ use web3::RequestId;
 
 use graph::prelude::*;
 use graph::url::Url;
+use graph::util::proxy::{Proxies, ProxyTarget};
 use std::future::Future;
 
 /// Abstraction over the different web3 transports.
@@ -39,8 +40,15 @@ impl Transport {
     /// Note: JSON-RPC over HTTP doesn't always support subscribing to new
     /// blocks (one such example is Infura's HTTP endpoint).
     pub fn new_rpc(rpc: Url, headers: ::http::HeaderMap) -> Self {
+        Self::new_rpc_with_proxies(rpc, headers, &ENV_VARS.proxies)
+    }
+
+    /// Creates a JSON-RPC over HTTP transport that connects through the
+    /// proxy that `proxies` sets for providers.
+    pub fn new_rpc_with_proxies(rpc: Url, headers: ::http::HeaderMap, proxies: &Proxies) -> Self {
         // Unwrap: This only fails if something is wrong with the system's TLS config.
-        let client = reqwest::Client::builder()
+        let client = proxies
+            .http_client(ProxyTarget::Providers)
             .default_headers(headers)
             .pool_max_idle_per_host(ENV_VARS.pool_max_idle_per_host)
             .pool_idle_timeout(ENV_VARS.pool_idle_time_out)
@@ -88,3 +96,36 @@ impl web3::BatchTransport for Transport {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::web3::Transport as _;
+    use graph::tokio;
+    use graph::url::Url;
+    use graph::util::proxy::{fixture::RecordingProxy, Proxies, ProxySetting};
+
+    use super::Transport;
+
+    #[tokio::test]
+    async fn rpc_uses_proxy() {
+        let proxy = RecordingProxy::start(r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#).await;
+        let proxies = Proxies {
+            providers: ProxySetting::Proxy(proxy.url()),
+            ..Default::default()
+        };
+
+        // The host does not resolve, the request can only succeed through
+        // the proxy
+        let transport = Transport::new_rpc_with_proxies(
+            Url::parse("http://rpc.invalid:8545").unwrap(),
+            ::http::HeaderMap::new(),
+            &proxies,
+        );
+        let block = transport.execute("eth_blockNumber", vec![]).await.unwrap();
+        assert_eq!("0x1", block);
+        assert_eq!(
+            vec!["POST http://rpc.invalid:8545/ HTTP/1.1".to_string()],
+            proxy.requests()
+        );
+    }
+}
@@ -8,7 +8,6 @@ use graph::prelude::ethabi::ethereum_types::U128;
 use graph::prelude::ethabi::ethereum_types::U256;
 use graph::prelude::ethabi::ethereum_types::U64;
 use graph::prelude::ethabi::Address;
-use graph::prelude::ethabi::Bytes;
 use graph::prelude::ethabi::LogParam;
 use graph::prelude::web3::types::Block;
 use graph::prelude::web3::types::Log;
@@ -41,17 +40,60 @@ use crate::runtime::abi::AscEthereumTransaction_0_0_6;
 // ETHDEP: This should be defined in only one place.
 type LightEthereumBlock = Block<Transaction>;
 
+/// The transaction of a trigger. A transaction that is part of the block
+/// is referenced by its position in the block rather than copied out of
+/// it, so that creating a trigger and sending it to the mapping thread
+/// does not copy the transaction and its input data.
+#[derive(Clone)]
+pub enum TriggerTransaction {
+    InBlock(Arc<LightEthereumBlock>, usize),
+    /// A transaction that is not part of the block, like the dummy
+    /// transaction for Celo epoch rewards
+    Detached(Arc<Transaction>),
+}
+
+impl TriggerTransaction {
+    /// The transaction in `block` with `hash`, if there is one
+    pub fn in_block(block: &Arc<LightEthereumBlock>, hash: Option<H256>) -> Option<Self> {
+        let hash = hash?;
+        block
+            .transactions
+            .iter()
+            .position(|tx| tx.hash == hash)
+            .map(|idx| TriggerTransaction::InBlock(block.cheap_clone(), idx))
+    }
+}
+
+impl Deref for TriggerTransaction {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        match self {
+            TriggerTransaction::InBlock(block, idx) => &block.transactions[*idx],
+            TriggerTransaction::Detached(transaction) => transaction,
+        }
+    }
+}
+
+impl CheapClone for TriggerTransaction {}
+
+impl std::fmt::Debug for TriggerTransaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.deref(), f)
+    }
+}
+
 pub enum MappingTrigger {
     Log {
         block: Arc<LightEthereumBlock>,
-        transaction: Arc<Transaction>,
+        transaction: TriggerTransaction,
         log: Arc<Log>,
         params: Vec<LogParam>,
         receipt: Option<Arc<TransactionReceipt>>,
     },
     Call {
         block: Arc<LightEthereumBlock>,
-        transaction: Arc<Transaction>,
+        transaction: TriggerTransaction,
         call: Arc<EthereumCall>,
         inputs: Vec<LogParam>,
         outputs: Vec<LogParam>,
@@ -65,17 +107,17 @@ pub enum MappingTrigger {
 impl std::fmt::Debug for MappingTrigger {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         #[derive(Debug)]
-        enum MappingTriggerWithoutBlock {
+        enum MappingTriggerWithoutBlock<'a> {
             Log {
-                _transaction: Arc<Transaction>,
-                _log: Arc<Log>,
-                _params: Vec<LogParam>,
+                _transaction: &'a Transaction,
+                _log: &'a Log,
+                _params: &'a [LogParam],
             },
             Call {
-                _transaction: Arc<Transaction>,
-                _call: Arc<EthereumCall>,
-                _inputs: Vec<LogParam>,
-                _outputs: Vec<LogParam>,
+                _transaction: &'a Transaction,
+                _call: &'a EthereumCall,
+                _inputs: &'a [LogParam],
+                _outputs: &'a [LogParam],
             },
             Block,
         }
@@ -88,9 +130,9 @@ impl std::fmt::Debug for MappingTrigger {
                 params,
                 receipt: _,
             } => MappingTriggerWithoutBlock::Log {
-                _transaction: transaction.cheap_clone(),
-                _log: log.cheap_clone(),
-                _params: params.clone(),
+                _transaction: transaction,
+                _log: log,
+                _params: params,
             },
             MappingTrigger::Call {
                 block: _,
@@ -99,10 +141,10 @@ impl std::fmt::Debug for MappingTrigger {
                 inputs,
                 outputs,
             } => MappingTriggerWithoutBlock::Call {
-                _transaction: transaction.cheap_clone(),
-                _call: call.cheap_clone(),
-                _inputs: inputs.clone(),
-                _outputs: outputs.clone(),
+                _transaction: transaction,
+                _call: call,
+                _inputs: inputs,
+                _outputs: outputs,
             },
             MappingTrigger::Block { block: _ } => MappingTriggerWithoutBlock::Block,
         };
@@ -128,7 +170,7 @@ impl ToAscPtr for MappingTrigger {
                 let api_version = heap.api_version();
                 let ethereum_event_data = EthereumEventData {
                     block: EthereumBlockData::from(block.as_ref()),
-                    transaction: EthereumTransactionData::from(transaction.deref()),
+                    transaction: EthereumTransactionData::from(&transaction),
                     address: log.address,
                     log_index: log.log_index.unwrap_or(U256::zero()),
                     transaction_log_index: log.log_index.unwrap_or(U256::zero()),
@@ -179,7 +221,7 @@ impl ToAscPtr for MappingTrigger {
                     to: call.to,
                     from: call.from,
                     block: EthereumBlockData::from(block.as_ref()),
-                    transaction: EthereumTransactionData::from(transaction.deref()),
+                    transaction: EthereumTransactionData::from(&transaction),
                     inputs,
                     outputs,
                 };
@@ -246,6 +288,9 @@ impl Eq for EthereumTrigger {}
 pub enum EthereumBlockTriggerType {
     Every,
     WithCallTo(Address),
+    /// Runs the block finalizers of the data sources after all other
+    /// triggers of the block were processed
+    Finalize,
 }
 
 impl EthereumTrigger {
@@ -386,12 +431,12 @@ pub struct EthereumTransactionData {
     pub value: U256,
     pub gas_limit: U256,
     pub gas_price: U256,
-    pub input: Bytes,
+    pub input: TransactionInput,
     pub nonce: U256,
 }
 
-impl From<&'_ Transaction> for EthereumTransactionData {
-    fn from(tx: &Transaction) -> EthereumTransactionData {
+impl From<&'_ TriggerTransaction> for EthereumTransactionData {
+    fn from(tx: &TriggerTransaction) -> EthereumTransactionData {
         // unwrap: this is always `Some` for txns that have been mined
         //         (see https://github.com/tomusdrw/rust-web3/pull/407)
         let from = tx.from.unwrap();
@@ -403,12 +448,27 @@ impl From<&'_ Transaction> for EthereumTransactionData {
             value: tx.value,
             gas_limit: tx.gas,
             gas_price: tx.gas_price.unwrap_or(U256::zero()), // EIP-1559 made this optional.
-            input: tx.input.0.clone(),
+            input: TransactionInput(tx.cheap_clone()),
             nonce: tx.nonce,
         }
     }
 }
 
+/// The input data of a trigger's transaction. It is read from the
+/// transaction, and therefore from the block, when it is written to the
+/// WASM heap, so that the input data of a transaction is not copied for
+/// each of its triggers.
+#[derive(Clone, Debug)]
+pub struct TransactionInput(TriggerTransaction);
+
+impl Deref for TransactionInput {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0.input.0
+    }
+}
+
 /// An Ethereum event logged from a specific contract address and block.
 #[derive(Debug, Clone)]
 pub struct EthereumEventData {
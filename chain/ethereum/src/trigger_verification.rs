@@ -0,0 +1,484 @@
+//! Check the triggers that the provider used for indexing returns against a
+//! second provider. Providers sometimes disagree about the same block: one
+//! of them drops logs, returns them in a different order, or returns
+//! different receipts. Since the triggers determine what the mappings do,
+//! such differences make indexing non-deterministic, and they are hard to
+//! track down after the fact.
+//!
+//! For a sample of the blocks whose triggers are scanned, the triggers are
+//! also fetched from another provider for the same network. Both sets are
+//! reduced to what matters to the mappings, so that differences in how
+//! providers encode the same data do not count, and then compared. Any
+//! difference is counted in a metric, logged, and recorded in the
+//! `trigger_divergence` table. Indexing always continues with the triggers
+//! from the primary provider.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use graph::blockchain::block_stream::BlockWithTriggers;
+use graph::blockchain::BlockPtr;
+use graph::cheap_clone::CheapClone;
+use graph::data::subgraph::UnifiedMappingApiVersion;
+use graph::prelude::rand::{self, Rng};
+use graph::prelude::web3::types::{Address, TransactionReceipt, H256, U256, U64};
+use graph::prelude::{debug, warn, BlockNumber, ChainStore, CounterVec, Logger, MetricsRegistry};
+
+use crate::adapter::EthereumAdapter as _;
+use crate::ethereum_adapter::blocks_with_triggers;
+use crate::trigger::EthereumTrigger;
+use crate::{Chain, EthereumAdapter, SubgraphEthRpcMetrics, TriggerFilter};
+
+pub(crate) struct TriggerVerificationMetrics {
+    blocks: CounterVec,
+    divergences: CounterVec,
+}
+
+impl TriggerVerificationMetrics {
+    pub fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        let labels = ["network", "provider", "secondary"];
+        let blocks = registry
+            .global_counter_vec(
+                "ethereum_trigger_verification_blocks",
+                "The number of blocks whose triggers were compared with a second provider",
+                &[&labels[..], &["result"]].concat(),
+            )
+            .expect("failed to create `ethereum_trigger_verification_blocks` counter");
+        let divergences = registry
+            .global_counter_vec(
+                "ethereum_trigger_divergences",
+                "The number of differences between the triggers of two providers",
+                &[&labels[..], &["kind"]].concat(),
+            )
+            .expect("failed to create `ethereum_trigger_divergences` counter");
+        TriggerVerificationMetrics {
+            blocks,
+            divergences,
+        }
+    }
+}
+
+/// Verifies the triggers of one triggers adapter
+pub(crate) struct TriggerVerifier {
+    pub logger: Logger,
+    pub network: String,
+    /// The fraction of blocks to verify
+    pub rate: f64,
+    /// The name of the provider used for indexing
+    pub primary: String,
+    pub secondary: Arc<EthereumAdapter>,
+    pub chain_store: Arc<dyn ChainStore>,
+    pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+    pub unified_api_version: UnifiedMappingApiVersion,
+    pub metrics: TriggerVerificationMetrics,
+}
+
+impl TriggerVerifier {
+    /// Compare the triggers in `blocks`, which the primary provider
+    /// returned for the range `[from, to]`, with the triggers of the
+    /// secondary provider for a sample of the blocks in the range
+    pub async fn verify(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+        blocks: &[BlockWithTriggers<Chain>],
+        filter: &TriggerFilter,
+    ) {
+        let sample: Vec<_> = {
+            let mut rng = rand::thread_rng();
+            (from..=to).filter(|_| rng.gen_bool(self.rate)).collect()
+        };
+        for number in sample {
+            let primary = blocks.iter().find(|block| block.ptr().number == number);
+            self.verify_block(number, primary, filter).await;
+        }
+    }
+
+    async fn verify_block(
+        &self,
+        number: BlockNumber,
+        primary: Option<&BlockWithTriggers<Chain>>,
+        filter: &TriggerFilter,
+    ) {
+        let secondary = match blocks_with_triggers(
+            self.secondary.cheap_clone(),
+            self.logger.cheap_clone(),
+            self.chain_store.cheap_clone(),
+            self.ethrpc_metrics.cheap_clone(),
+            number,
+            number,
+            filter,
+            self.unified_api_version.clone(),
+        )
+        .await
+        {
+            Ok(blocks) => blocks.into_iter().next(),
+            Err(e) => {
+                debug!(self.logger, "Failed to get triggers from the secondary provider";
+                       "block_number" => number,
+                       "secondary" => self.secondary.provider(),
+                       "error" => e.to_string());
+                self.count_block("error");
+                return;
+            }
+        };
+
+        if let (Some(primary), Some(secondary)) = (primary, &secondary) {
+            // The secondary provider is behind or on a different fork;
+            // that is not a difference in the triggers
+            if primary.ptr() != secondary.ptr() {
+                debug!(self.logger, "Providers disagree about the block hash, not comparing triggers";
+                       "block_number" => number,
+                       "primary_hash" => primary.ptr().hash_hex(),
+                       "secondary_hash" => secondary.ptr().hash_hex());
+                self.count_block("skipped");
+                return;
+            }
+        }
+
+        let block = match primary.or(secondary.as_ref()) {
+            Some(block) => block.ptr(),
+            None => {
+                self.count_block("match");
+                return;
+            }
+        };
+        let no_triggers = vec![];
+        let divergences = compare(
+            primary.map_or(&no_triggers, |block| &block.trigger_data),
+            secondary
+                .as_ref()
+                .map_or(&no_triggers, |block| &block.trigger_data),
+        );
+        if divergences.is_empty() {
+            self.count_block("match");
+            return;
+        }
+
+        self.count_block("mismatch");
+        for divergence in divergences {
+            self.report(&block, &divergence).await;
+        }
+    }
+
+    async fn report(&self, block: &BlockPtr, divergence: &Divergence) {
+        let secondary = self.secondary.provider();
+        let details = divergence.to_string();
+        self.metrics
+            .divergences
+            .with_label_values(&[&self.network, &self.primary, secondary, divergence.kind()])
+            .inc();
+        warn!(self.logger, "Providers returned different triggers, continuing with the triggers from the primary provider";
+              "block" => block.to_string(),
+              "primary" => &self.primary,
+              "secondary" => secondary,
+              "kind" => divergence.kind(),
+              "details" => &details);
+        if let Err(e) = self
+            .chain_store
+            .record_trigger_divergence(block, &self.primary, secondary, divergence.kind(), &details)
+            .await
+        {
+            warn!(self.logger, "Failed to record the difference in triggers";
+                  "block" => block.to_string(),
+                  "error" => e.to_string());
+        }
+    }
+
+    fn count_block(&self, result: &str) {
+        self.metrics
+            .blocks
+            .with_label_values(&[
+                &self.network,
+                &self.primary,
+                self.secondary.provider(),
+                result,
+            ])
+            .inc();
+    }
+}
+
+/// What identifies a trigger regardless of the provider it came from.
+/// Fields that providers fill in differently without changing what the
+/// mappings see, like `removed` or `log_type` of logs, are left out
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum TriggerKey {
+    Block(String),
+    Call {
+        transaction: Option<H256>,
+        from: Address,
+        to: Address,
+        value: U256,
+        input: Vec<u8>,
+        output: Vec<u8>,
+    },
+    Log {
+        transaction: Option<H256>,
+        address: Address,
+        topics: Vec<H256>,
+        data: Vec<u8>,
+    },
+}
+
+impl TriggerKey {
+    /// The key of `trigger` and where in the block it happened
+    fn of(trigger: &EthereumTrigger) -> (TriggerKey, Option<U256>) {
+        match trigger {
+            EthereumTrigger::Block(_, trigger_type) => {
+                (TriggerKey::Block(format!("{:?}", trigger_type)), None)
+            }
+            EthereumTrigger::Call(call) => {
+                let key = TriggerKey::Call {
+                    transaction: call.transaction_hash,
+                    from: call.from,
+                    to: call.to,
+                    value: call.value,
+                    input: call.input.0.clone(),
+                    output: call.output.0.clone(),
+                };
+                (key, Some(U256::from(call.transaction_index)))
+            }
+            EthereumTrigger::Log(log, _) => {
+                let key = TriggerKey::Log {
+                    transaction: log.transaction_hash,
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.0.clone(),
+                };
+                (key, log.log_index)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TriggerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn tx(hash: &Option<H256>) -> String {
+            hash.map(|hash| format!("{:?}", hash))
+                .unwrap_or_else(|| "unknown".to_string())
+        }
+
+        match self {
+            TriggerKey::Block(trigger_type) => write!(f, "block trigger {}", trigger_type),
+            TriggerKey::Call {
+                transaction,
+                from,
+                to,
+                ..
+            } => write!(
+                f,
+                "call from {:?} to {:?} in transaction {}",
+                from,
+                to,
+                tx(transaction)
+            ),
+            TriggerKey::Log {
+                transaction,
+                address,
+                topics,
+                ..
+            } => write!(
+                f,
+                "log from {:?} with topic0 {} in transaction {}",
+                address,
+                topics
+                    .first()
+                    .map(|topic| format!("{:?}", topic))
+                    .unwrap_or_else(|| "none".to_string()),
+                tx(transaction)
+            ),
+        }
+    }
+}
+
+/// The parts of a receipt that mappings can see
+#[derive(Debug, PartialEq)]
+struct ReceiptSummary {
+    status: Option<U64>,
+    gas_used: Option<U256>,
+    cumulative_gas_used: U256,
+    contract_address: Option<Address>,
+    logs: usize,
+}
+
+impl From<&TransactionReceipt> for ReceiptSummary {
+    fn from(receipt: &TransactionReceipt) -> Self {
+        ReceiptSummary {
+            status: receipt.status,
+            gas_used: receipt.gas_used,
+            cumulative_gas_used: receipt.cumulative_gas_used,
+            contract_address: receipt.contract_address,
+            logs: receipt.logs.len(),
+        }
+    }
+}
+
+/// A difference between the triggers of the primary and the secondary
+/// provider for the same block
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Divergence {
+    MissingInPrimary(String),
+    MissingInSecondary(String),
+    /// Both providers returned the same triggers, but in a different order
+    /// or at different positions in the block
+    Order,
+    /// The providers returned different receipts for a transaction
+    Receipt(H256),
+}
+
+impl Divergence {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Divergence::MissingInPrimary(_) => "missing_in_primary",
+            Divergence::MissingInSecondary(_) => "missing_in_secondary",
+            Divergence::Order => "order",
+            Divergence::Receipt(_) => "receipt",
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::MissingInPrimary(trigger) => {
+                write!(f, "only the secondary provider returned the {}", trigger)
+            }
+            Divergence::MissingInSecondary(trigger) => {
+                write!(f, "only the primary provider returned the {}", trigger)
+            }
+            Divergence::Order => write!(
+                f,
+                "the providers returned the same triggers in a different order"
+            ),
+            Divergence::Receipt(tx) => write!(
+                f,
+                "the providers returned different receipts for transaction {:?}",
+                tx
+            ),
+        }
+    }
+}
+
+/// Compare the triggers that two providers returned for the same block.
+/// Both lists must be sorted the way `BlockWithTriggers` sorts them
+pub(crate) fn compare(
+    primary: &[EthereumTrigger],
+    secondary: &[EthereumTrigger],
+) -> Vec<Divergence> {
+    let primary_keys: Vec<_> = primary.iter().map(TriggerKey::of).collect();
+    let secondary_keys: Vec<_> = secondary.iter().map(TriggerKey::of).collect();
+
+    let counts = |keys: &[(TriggerKey, Option<U256>)]| {
+        let mut counts: BTreeMap<TriggerKey, usize> = BTreeMap::new();
+        for (key, _) in keys {
+            *counts.entry(key.clone()).or_default() += 1;
+        }
+        counts
+    };
+    let primary_counts = counts(&primary_keys);
+    let secondary_counts = counts(&secondary_keys);
+
+    let mut divergences = Vec::new();
+    for (key, count) in &secondary_counts {
+        if primary_counts.get(key).copied().unwrap_or(0) < *count {
+            divergences.push(Divergence::MissingInPrimary(key.to_string()));
+        }
+    }
+    for (key, count) in &primary_counts {
+        if secondary_counts.get(key).copied().unwrap_or(0) < *count {
+            divergences.push(Divergence::MissingInSecondary(key.to_string()));
+        }
+    }
+    // Only look at the order if both have the same triggers, otherwise a
+    // missing trigger would also be reported as a different order
+    if divergences.is_empty() && primary_keys != secondary_keys {
+        divergences.push(Divergence::Order);
+    }
+
+    let receipts = |triggers: &[EthereumTrigger]| {
+        triggers
+            .iter()
+            .filter_map(|trigger| match trigger {
+                EthereumTrigger::Log(_, Some(receipt)) => Some((
+                    receipt.transaction_hash,
+                    ReceiptSummary::from(receipt.as_ref()),
+                )),
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    let secondary_receipts = receipts(secondary);
+    for (tx, receipt) in receipts(primary) {
+        if matches!(secondary_receipts.get(&tx), Some(other) if other != &receipt) {
+            divergences.push(Divergence::Receipt(tx));
+        }
+    }
+
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::web3::types::{Bytes, Log, H160};
+
+    use super::*;
+
+    fn log(tx: u64, log_index: u64, data: u8, receipt: Option<u64>) -> EthereumTrigger {
+        let tx = H256::from_low_u64_be(tx);
+        let log = Log {
+            address: H160::from_low_u64_be(1),
+            topics: vec![H256::from_low_u64_be(2)],
+            data: Bytes(vec![data]),
+            block_hash: Some(H256::zero()),
+            block_number: Some(U64::one()),
+            transaction_hash: Some(tx),
+            transaction_index: Some(U64::zero()),
+            log_index: Some(log_index.into()),
+            transaction_log_index: None,
+            log_type: None,
+            removed: Some(false),
+        };
+        let receipt = receipt.map(|gas_used| {
+            Arc::new(TransactionReceipt {
+                transaction_hash: tx,
+                gas_used: Some(gas_used.into()),
+                ..Default::default()
+            })
+        });
+        EthereumTrigger::Log(Arc::new(log), receipt)
+    }
+
+    #[test]
+    fn compare_triggers() {
+        let triggers = vec![log(1, 0, 1, Some(21000)), log(1, 1, 2, Some(21000))];
+        assert_eq!(Vec::<Divergence>::new(), compare(&triggers, &triggers));
+
+        // Fields that don't matter to mappings are ignored
+        let mut encoded = triggers.clone();
+        if let EthereumTrigger::Log(log, receipt) = &encoded[0] {
+            let mut log = log.as_ref().clone();
+            log.log_type = Some("mined".to_string());
+            log.transaction_log_index = Some(0.into());
+            encoded[0] = EthereumTrigger::Log(Arc::new(log), receipt.clone());
+        }
+        assert_eq!(Vec::<Divergence>::new(), compare(&triggers, &encoded));
+
+        let missing = vec![log(1, 0, 1, Some(21000))];
+        let divergences = compare(&triggers, &missing);
+        assert_eq!(1, divergences.len());
+        assert_eq!("missing_in_secondary", divergences[0].kind());
+        let divergences = compare(&missing, &triggers);
+        assert_eq!(1, divergences.len());
+        assert_eq!("missing_in_primary", divergences[0].kind());
+
+        let reordered = vec![log(1, 0, 2, Some(21000)), log(1, 1, 1, Some(21000))];
+        assert_eq!(vec![Divergence::Order], compare(&triggers, &reordered));
+
+        let receipts = vec![log(1, 0, 1, Some(30000)), log(1, 1, 2, Some(30000))];
+        assert_eq!(
+            vec![Divergence::Receipt(H256::from_low_u64_be(1))],
+            compare(&triggers, &receipts)
+        );
+    }
+}
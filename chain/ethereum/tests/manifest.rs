@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
 use graph::data::subgraph::{SPEC_VERSION_0_0_4, SPEC_VERSION_0_0_7};
 use graph::data_source::DataSourceTemplate;
 use graph::prelude::{
@@ -214,6 +214,8 @@ specVersion: 0.0.2
             block_ptr: Some(test_store::BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         // Fails the base subgraph at block 1 (and advances the pointer).
@@ -323,6 +325,8 @@ specVersion: 0.0.2
             block_ptr: Some(test_store::BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         test_store::transact_errors(
@@ -505,6 +509,40 @@ schema:
     });
 }
 
+#[test]
+fn declared_entities_feature_causes_no_feature_validation_errors() {
+    const YAML: &str = "
+specVersion: 0.0.4
+features:
+  - declaredEntities
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+";
+    test_store::run_test_sequentially(|store| async move {
+        let store = store.subgraph_store();
+        let unvalidated = resolve_unvalidated(YAML).await;
+        assert!(unvalidated
+            .validate(store.clone(), true)
+            .await
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::FeatureValidationError(_)
+                )
+            })
+            .is_none());
+
+        let manifest = resolve_manifest(YAML, SPEC_VERSION_0_0_4).await;
+        assert!(manifest
+            .features
+            .contains(&SubgraphFeature::DeclaredEntities))
+    });
+}
+
 #[test]
 fn declared_full_text_search_feature_causes_no_feature_validation_errors() {
     const YAML: &str = "
@@ -258,6 +258,14 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn runtime(&self) -> Option<Arc<Vec<u8>>> {
         Some(self.mapping.runtime.cheap_clone())
     }
+
+    fn declared_entities(&self) -> Option<&[String]> {
+        Some(&self.mapping.entities)
+    }
+
+    fn handler_count(&self) -> usize {
+        self.mapping.block_handlers.len() + self.mapping.receipt_handlers.len()
+    }
 }
 
 impl DataSource {
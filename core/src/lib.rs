@@ -7,6 +7,7 @@ mod subgraph;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
+    load_dynamic_data_sources, RunnerShutdown, SafeModeConfig, SafeModeRampUp,
     SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar, SubgraphRunner,
-    SubgraphTriggerProcessor,
+    SubgraphTriggerProcessor, SyncedAction, SyncedActionKind,
 };
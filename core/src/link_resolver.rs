@@ -1,3 +1,4 @@
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -12,7 +13,7 @@ use lru_time_cache::LruCache;
 use serde_json::Value;
 
 use graph::{
-    ipfs_client::{IpfsClient, StatApi},
+    ipfs_client::{CatVerifiedError, CidFile, IpfsClient, StatApi},
     prelude::{LinkResolver as LinkResolverTrait, *},
 };
 
@@ -36,6 +37,24 @@ fn retry_policy<I: Send + Sync>(
     .no_timeout() // The timeout should be set in the internal future.
 }
 
+/// Like `retry_policy`, but for downloads that are verified against their
+/// CID. Content that does not match is not retried since asking the same
+/// client again will most likely return the same content.
+fn verified_retry_policy<I: Send + Sync>(
+    always_retry: bool,
+    op: &'static str,
+    logger: &Logger,
+) -> RetryConfigNoTimeout<I, CatVerifiedError> {
+    retry(op, logger)
+        .no_limit()
+        .when(move |res: &Result<_, CatVerifiedError>| match res {
+            Ok(_) => false,
+            Err(CatVerifiedError::Fetch(e)) => always_retry || !(e.is_status() || e.is_timeout()),
+            Err(_) => false,
+        })
+        .no_timeout()
+}
+
 /// The IPFS APIs don't have a quick "do you have the file" function. Instead, we
 /// just rely on whether an API times out. That makes sense for IPFS, but not for
 /// our application. We want to be able to quickly select from a potential list
@@ -129,6 +148,85 @@ impl LinkResolver {
     }
 }
 
+impl LinkResolver {
+    /// Download `file` from `client` and verify its content against its
+    /// CID. If the content does not match, try the other clients. If none
+    /// of them returns matching content, fail if strict verification is
+    /// turned on, and otherwise use the content from `client` anyway.
+    async fn cat_verified(
+        &self,
+        logger: &Logger,
+        file: CidFile,
+        client: Arc<IpfsClient>,
+    ) -> Result<Vec<u8>, Error> {
+        let max_file_size = self.env_vars.mappings.max_ipfs_file_bytes as u64;
+        let timeout = self.timeout;
+        let others = self
+            .clients
+            .iter()
+            .filter(|other| !Arc::ptr_eq(other, &client))
+            .cloned();
+
+        let mut mismatch = None;
+        for other in std::iter::once(client.cheap_clone()).chain(others) {
+            let req_file = file.clone();
+            let res = verified_retry_policy(self.retry, "ipfs.cat", logger)
+                .run(move || {
+                    let file = req_file.clone();
+                    let client = other.cheap_clone();
+                    async move {
+                        client
+                            .cat_verified(&file, max_file_size, timeout)
+                            .await
+                            .map(|data| data.to_vec())
+                    }
+                })
+                .await;
+            match res {
+                Ok(data) => return Ok(data),
+                Err(CatVerifiedError::Mismatch(cid)) => {
+                    warn!(logger, "IPFS node returned content that does not match its CID";
+                        "file" => file.to_string(),
+                        "block" => cid.to_string());
+                    mismatch = Some(cid);
+                }
+                Err(CatVerifiedError::Unsupported(reason)) => {
+                    debug!(logger, "Using IPFS file that can not be verified";
+                        "file" => file.to_string(),
+                        "reason" => reason);
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(cid) = mismatch {
+            if self.env_vars.mappings.ipfs_strict_verification_cat {
+                return Err(CatVerifiedError::Mismatch(cid).into());
+            }
+        }
+        self.cat_unverified(logger, &file.to_string(), client).await
+    }
+
+    async fn cat_unverified(
+        &self,
+        logger: &Logger,
+        path: &str,
+        client: Arc<IpfsClient>,
+    ) -> Result<Vec<u8>, Error> {
+        let req_path = path.to_owned();
+        let timeout = self.timeout;
+        let data = retry_policy(self.retry, "ipfs.cat", logger)
+            .run(move || {
+                let path = req_path.clone();
+                let client = client.clone();
+                async move { Ok(client.cat_all(&path, timeout).await?.to_vec()) }
+            })
+            .await?;
+        Ok(data)
+    }
+}
+
 impl Debug for LinkResolver {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("LinkResolver")
@@ -184,15 +282,10 @@ impl LinkResolverTrait for LinkResolver {
         let max_file_size = self.env_vars.mappings.max_ipfs_file_bytes;
         restrict_file_size(&path, size, max_file_size)?;
 
-        let req_path = path.clone();
-        let timeout = self.timeout;
-        let data = retry_policy(self.retry, "ipfs.cat", logger)
-            .run(move || {
-                let path = req_path.clone();
-                let client = client.clone();
-                async move { Ok(client.cat_all(&path, timeout).await?.to_vec()) }
-            })
-            .await?;
+        let data = match CidFile::from_str(&path) {
+            Ok(file) => self.cat_verified(logger, file, client).await?,
+            Err(_) => self.cat_unverified(logger, &path, client).await?,
+        };
 
         // The size reported by `files/stat` is not guaranteed to be exact, so check the limit again.
         restrict_file_size(&path, data.len() as u64, max_file_size)?;
@@ -282,6 +375,7 @@ impl LinkResolverTrait for LinkResolver {
                                 Ok(v) => Ok(Async::Ready(Some(JsonStreamValue {
                                     value: v,
                                     line: count,
+                                    bytes: line_bytes.len(),
                                 }))),
                                 Err(e) => {
                                     // Adjust the line number in the serde error. This
@@ -332,3 +332,97 @@ fn global_counters_are_shared() {
     };
     check_counters(&registry, "pool", const_labels);
 }
+
+#[test]
+fn deployment_histograms_have_labels_and_buckets() {
+    use graph::components::store::DeploymentId;
+    use graph::log;
+    use graph::prometheus::proto::MetricFamily;
+
+    let logger = log::logger(false);
+    let prom_reg = Arc::new(Registry::new());
+    let registry = MetricsRegistry::new(logger, prom_reg.clone());
+    let deployment = DeploymentLocator {
+        id: DeploymentId(1),
+        hash: DeploymentHash::new("QmHistogram").unwrap(),
+        name: Some("histogram/test".to_string()),
+    };
+
+    fn family<'a>(families: &'a [MetricFamily], name: &str) -> Option<&'a MetricFamily> {
+        families.iter().find(|family| family.get_name() == name)
+    }
+
+    fn labels(family: &MetricFamily) -> Vec<Vec<(String, String)>> {
+        family
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn buckets(family: &MetricFamily) -> Vec<f64> {
+        family.get_metric()[0]
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .map(|bucket| bucket.get_upper_bound())
+            .collect()
+    }
+
+    let histogram = registry
+        .new_deployment_histogram("test_histogram", "help", &deployment, vec![0.5, 2.0])
+        .unwrap();
+    histogram.observe(1.0);
+    let histograms = registry
+        .new_deployment_histogram_vec(
+            "test_histogram_vec",
+            "help",
+            &deployment,
+            vec![String::from("handler")],
+            vec![0.1, 1.0, 10.0],
+        )
+        .unwrap();
+    histograms
+        .with_label_values(&["handleTransfer"])
+        .observe(5.0);
+
+    let families = prom_reg.gather();
+    let deployment_labels = vec![
+        ("deployment".to_string(), "QmHistogram".to_string()),
+        ("name".to_string(), "histogram/test".to_string()),
+    ];
+
+    let single = family(&families, "test_histogram").unwrap();
+    assert_eq!(vec![deployment_labels.clone()], labels(single));
+    assert_eq!(vec![0.5, 2.0], buckets(single));
+    assert_eq!(1, single.get_metric()[0].get_histogram().get_sample_count());
+
+    let vec = family(&families, "test_histogram_vec").unwrap();
+    let mut vec_labels = deployment_labels;
+    vec_labels.push(("handler".to_string(), "handleTransfer".to_string()));
+    vec_labels.sort();
+    assert_eq!(vec![vec_labels], labels(vec));
+    assert_eq!(vec![0.1, 1.0, 10.0], buckets(vec));
+
+    // Unregistering removes the histograms, and registering them again
+    // when the deployment is restarted works
+    registry.unregister(histogram);
+    registry.unregister(histograms);
+    let families = prom_reg.gather();
+    assert!(family(&families, "test_histogram").is_none());
+    assert!(family(&families, "test_histogram_vec").is_none());
+
+    registry
+        .new_deployment_histogram("test_histogram", "help", &deployment, vec![0.5, 2.0])
+        .unwrap()
+        .observe(1.0);
+    let families = prom_reg.gather();
+    assert!(family(&families, "test_histogram").is_some());
+    assert_eq!(0.0, registry.register_errors.get());
+}
@@ -1,73 +1,106 @@
-use anyhow::{anyhow, Error};
+use anyhow::anyhow;
+use async_trait::async_trait;
 use bytes::Bytes;
-use futures::future::BoxFuture;
 use graph::{
-    ipfs_client::{CidFile, IpfsClient, StatApi},
-    prelude::CheapClone,
+    ipfs_client::{CatVerifiedError, CidFile, IpfsClient, StatApi},
+    slog::{warn, Logger},
 };
 use std::time::Duration;
-use tower::{buffer::Buffer, ServiceBuilder, ServiceExt};
+
+use super::{polling_service, PollingError, PollingService, PollingServiceHandle};
 
 const CLOUDFLARE_TIMEOUT: u16 = 524;
 const GATEWAY_TIMEOUT: u16 = 504;
 
-pub type IpfsService = Buffer<CidFile, BoxFuture<'static, Result<Option<Bytes>, Error>>>;
+pub type IpfsService = PollingServiceHandle<CidFile, Bytes>;
 
+/// Create a service that fetches files from IPFS and verifies their content
+/// against their CID. Files are fetched from the first client in `clients`;
+/// the other clients are only used when the first one returns content that
+/// does not match. If no client returns matching content, the service fails
+/// with `CatVerifiedError::Mismatch` when `strict_verification` is set, and
+/// returns the unverified content from the first client otherwise.
 pub fn ipfs_service(
-    client: IpfsClient,
+    clients: Vec<IpfsClient>,
     max_file_size: u64,
     timeout: Duration,
     concurrency_and_rate_limit: u16,
+    strict_verification: bool,
+    logger: Logger,
 ) -> IpfsService {
     let ipfs = IpfsServiceInner {
-        client,
+        clients,
         max_file_size,
         timeout,
+        strict_verification,
+        logger,
     };
 
-    let svc = ServiceBuilder::new()
-        .rate_limit(concurrency_and_rate_limit.into(), Duration::from_secs(1))
-        .concurrency_limit(concurrency_and_rate_limit as usize)
-        .service_fn(move |req| ipfs.cheap_clone().call_inner(req))
-        .boxed();
-
-    // The `Buffer` makes it so the rate and concurrency limit are shared among clones.
-    Buffer::new(svc, 1)
+    polling_service(ipfs, concurrency_and_rate_limit)
 }
 
-#[derive(Clone)]
 struct IpfsServiceInner {
-    client: IpfsClient,
+    clients: Vec<IpfsClient>,
     max_file_size: u64,
     timeout: Duration,
+    strict_verification: bool,
+    logger: Logger,
 }
 
-impl CheapClone for IpfsServiceInner {
-    fn cheap_clone(&self) -> Self {
-        Self {
-            client: self.client.cheap_clone(),
-            max_file_size: self.max_file_size,
-            timeout: self.timeout,
-        }
+#[async_trait]
+impl PollingService<CidFile> for IpfsServiceInner {
+    type Response = Bytes;
+
+    async fn fetch(&self, req: CidFile) -> Result<Option<Bytes>, PollingError> {
+        self.call_inner(req).await.map_err(|e| match e {
+            CatVerifiedError::Mismatch(_) => PollingError::Mismatch(e.into()),
+            _ => PollingError::Other(e.into()),
+        })
     }
 }
 
 impl IpfsServiceInner {
-    async fn call_inner(self, req: CidFile) -> Result<Option<Bytes>, Error> {
-        let CidFile { cid, path } = req;
-        let multihash = cid.hash().code();
+    async fn call_inner(&self, req: CidFile) -> Result<Option<Bytes>, CatVerifiedError> {
+        let multihash = req.cid.hash().code();
         if !SAFE_MULTIHASHES.contains(&multihash) {
-            return Err(anyhow!("CID multihash {} is not allowed", multihash));
+            return Err(anyhow!("CID multihash {} is not allowed", multihash).into());
         }
 
-        let cid_str = match path {
-            Some(path) => format!("{}/{}", cid, path),
-            None => cid.to_string(),
-        };
+        let mut mismatch = None;
+        for (i, client) in self.clients.iter().enumerate() {
+            match self.fetch_from(client, &req).await {
+                Err(CatVerifiedError::Mismatch(cid)) => {
+                    warn!(self.logger, "IPFS node returned content that does not match its CID";
+                        "file" => req.to_string(),
+                        "block" => cid.to_string(),
+                        "ipfs_node" => i);
+                    mismatch = Some(cid);
+                }
+                Err(CatVerifiedError::Unsupported(reason)) => {
+                    warn!(self.logger, "Using IPFS file that can not be verified";
+                        "file" => req.to_string(),
+                        "reason" => reason);
+                    return self.cat_unverified(client, &req).await;
+                }
+                res => return res,
+            }
+        }
 
-        let size = match self
-            .client
-            .stat_size(StatApi::Files, cid_str.clone(), self.timeout)
+        match mismatch {
+            Some(cid) if self.strict_verification => Err(CatVerifiedError::Mismatch(cid)),
+            // `mismatch` is only set if there is at least one client
+            Some(_) => self.cat_unverified(&self.clients[0], &req).await,
+            None => Err(anyhow!("no IPFS clients are configured").into()),
+        }
+    }
+
+    async fn fetch_from(
+        &self,
+        client: &IpfsClient,
+        req: &CidFile,
+    ) -> Result<Option<Bytes>, CatVerifiedError> {
+        let size = match client
+            .stat_size(StatApi::Files, req.to_string(), self.timeout)
             .await
         {
             Ok(size) => size,
@@ -81,15 +114,26 @@ impl IpfsServiceInner {
         if size > self.max_file_size {
             return Err(anyhow!(
                 "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
-                cid_str,
+                req,
                 self.max_file_size,
                 size
-            ));
+            )
+            .into());
         }
 
-        Ok(self
-            .client
-            .cat_all(&cid_str, self.timeout)
+        Ok(client
+            .cat_verified(req, self.max_file_size, self.timeout)
+            .await
+            .map(Some)?)
+    }
+
+    async fn cat_unverified(
+        &self,
+        client: &IpfsClient,
+        req: &CidFile,
+    ) -> Result<Option<Bytes>, CatVerifiedError> {
+        Ok(client
+            .cat_all(&req.to_string(), self.timeout)
             .await
             .map(Some)?)
     }
@@ -124,7 +168,7 @@ mod test {
     use tower::ServiceExt;
 
     use cid::Cid;
-    use graph::{ipfs_client::IpfsClient, tokio};
+    use graph::{ipfs_client::IpfsClient, log, tokio};
 
     use uuid::Uuid;
 
@@ -144,7 +188,14 @@ mod test {
         let cid = Cid::from_str(&ipfs_folder.hash).unwrap();
         let file = "random.txt".to_string();
 
-        let svc = super::ipfs_service(local, 100000, Duration::from_secs(5), 10);
+        let svc = super::ipfs_service(
+            vec![local],
+            100000,
+            Duration::from_secs(5),
+            10,
+            true,
+            log::discard(),
+        );
 
         let content = svc
             .oneshot(super::CidFile {
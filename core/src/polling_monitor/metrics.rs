@@ -3,14 +3,21 @@ use std::sync::Arc;
 use graph::{
     components::store::DeploymentLocator,
     prelude::MetricsRegistry,
-    prometheus::{Counter, Gauge},
+    prometheus::{Counter, Gauge, Histogram},
 };
 
+/// Metrics of a polling monitor, whatever the service it polls. Errors that are a
+/// `PollingError::Mismatch` are counted as `verification_failures`, all others as `errors`
+#[derive(Clone)]
 pub struct PollingMonitorMetrics {
     pub requests: Counter,
     pub errors: Counter,
     pub not_found: Counter,
+    pub verification_failures: Counter,
     pub queue_depth: Gauge,
+    /// How long each request to the service being polled took, whatever
+    /// its outcome
+    pub request_duration: Box<Histogram>,
 }
 
 impl PollingMonitorMetrics {
@@ -36,6 +43,13 @@ impl PollingMonitorMetrics {
                 deployment,
             )
             .unwrap();
+        let verification_failures = registry
+            .new_deployment_counter(
+                "polling_monitor_verification_failures",
+                "counts responses whose content did not match the requested object",
+                deployment,
+            )
+            .unwrap();
         let queue_depth = registry
             .new_deployment_gauge(
                 "polling_monitor_queue_depth",
@@ -43,21 +57,44 @@ impl PollingMonitorMetrics {
                 deployment,
             )
             .unwrap();
+        let request_duration = registry
+            .new_deployment_histogram(
+                "polling_monitor_request_duration",
+                "measures how long requests to the service being polled take",
+                deployment,
+                vec![0.05, 0.2, 0.5, 1.0, 5.0, 30.0, 120.0],
+            )
+            .unwrap();
         Self {
             requests,
             errors,
             not_found,
+            verification_failures,
             queue_depth,
+            request_duration,
         }
     }
 
+    pub fn unregister(&self, registry: Arc<dyn MetricsRegistry>) {
+        registry.unregister(Box::new(self.requests.clone()));
+        registry.unregister(Box::new(self.errors.clone()));
+        registry.unregister(Box::new(self.not_found.clone()));
+        registry.unregister(Box::new(self.verification_failures.clone()));
+        registry.unregister(Box::new(self.queue_depth.clone()));
+        registry.unregister(self.request_duration.clone());
+    }
+
     #[cfg(test)]
     pub(crate) fn mock() -> Self {
+        use graph::prometheus::HistogramOpts;
+
         Self {
             requests: Counter::new("x", " ").unwrap(),
             errors: Counter::new("y", " ").unwrap(),
             not_found: Counter::new("z", " ").unwrap(),
+            verification_failures: Counter::new("v", " ").unwrap(),
             queue_depth: Gauge::new("w", " ").unwrap(),
+            request_duration: Box::new(Histogram::with_opts(HistogramOpts::new("u", " ")).unwrap()),
         }
     }
 }
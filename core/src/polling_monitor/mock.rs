@@ -0,0 +1,106 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use graph::prelude::tokio;
+
+use super::{PollingError, PollingService};
+
+/// A response of a `MockPollingService`
+#[derive(Clone, Debug)]
+pub enum MockResponse<Res> {
+    Found(Res),
+    NotFound,
+    /// Fail with `PollingError::Other` and this message
+    Error(String),
+    /// Fail with `PollingError::Mismatch` and this message
+    Mismatch(String),
+}
+
+struct Scripted<Res> {
+    delay: Duration,
+    response: MockResponse<Res>,
+}
+
+/// An in-memory `PollingService` for tests. Each request gets the next
+/// response that was scripted for it with `respond`, after the scripted
+/// delay; requests without scripted responses are not found. Clones share
+/// their script and the log of requests.
+pub struct MockPollingService<Req, Res> {
+    script: Arc<Mutex<HashMap<Req, VecDeque<Scripted<Res>>>>>,
+    requests: Arc<Mutex<Vec<Req>>>,
+}
+
+impl<Req, Res> Clone for MockPollingService<Req, Res> {
+    fn clone(&self) -> Self {
+        Self {
+            script: self.script.clone(),
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+impl<Req: Clone + Eq + Hash, Res> MockPollingService<Req, Res> {
+    pub fn new() -> Self {
+        Self {
+            script: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answer the next request for `req` that does not have a response yet
+    /// with `response`, after waiting for `delay`
+    pub fn respond(&self, req: Req, delay: Duration, response: MockResponse<Res>) {
+        self.script
+            .lock()
+            .unwrap()
+            .entry(req)
+            .or_default()
+            .push_back(Scripted { delay, response });
+    }
+
+    /// The requests that the service received so far, in order
+    pub fn requests(&self) -> Vec<Req> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl<Req: Clone + Eq + Hash, Res> Default for MockPollingService<Req, Res> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl<Req, Res> PollingService<Req> for MockPollingService<Req, Res>
+where
+    Req: Clone + Eq + Hash + Send + Sync + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+
+    async fn fetch(&self, req: Req) -> Result<Option<Res>, PollingError> {
+        self.requests.lock().unwrap().push(req.clone());
+        let scripted = self
+            .script
+            .lock()
+            .unwrap()
+            .get_mut(&req)
+            .and_then(|responses| responses.pop_front());
+        let Scripted { delay, response } = match scripted {
+            Some(scripted) => scripted,
+            None => return Ok(None),
+        };
+
+        tokio::time::sleep(delay).await;
+        match response {
+            MockResponse::Found(res) => Ok(Some(res)),
+            MockResponse::NotFound => Ok(None),
+            MockResponse::Error(msg) => Err(PollingError::Other(anyhow!(msg).into())),
+            MockResponse::Mismatch(msg) => Err(PollingError::Mismatch(anyhow!(msg).into())),
+        }
+    }
+}
@@ -1,12 +1,14 @@
 mod ipfs_service;
 mod metrics;
+mod mock;
+mod service;
 
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::hash::Hash;
 use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::future::BoxFuture;
 use futures::stream::StreamExt;
@@ -14,15 +16,17 @@ use futures::{stream, Future, FutureExt, TryFutureExt};
 use graph::cheap_clone::CheapClone;
 use graph::parking_lot::Mutex;
 use graph::prelude::tokio;
-use graph::prometheus::{Counter, Gauge};
+use graph::prometheus::{Counter, Gauge, Histogram};
 use graph::slog::{debug, Logger};
 use graph::util::monitored::MonitoredVecDeque as VecDeque;
 use tokio::sync::{mpsc, watch};
 use tower::retry::backoff::{Backoff, ExponentialBackoff, ExponentialBackoffMaker, MakeBackoff};
 use tower::util::rng::HasherRng;
-use tower::{Service, ServiceExt};
+use tower::{BoxError, Service, ServiceExt};
 
 pub use self::metrics::PollingMonitorMetrics;
+pub use self::mock::{MockPollingService, MockResponse};
+pub use self::service::{polling_service, PollingError, PollingService, PollingServiceHandle};
 pub use ipfs_service::{ipfs_service, IpfsService};
 
 const MIN_BACKOFF: Duration = Duration::from_secs(5);
@@ -95,7 +99,11 @@ impl<T> Queue<T> {
 /// of error, the object id is pushed to the back of the queue to be polled again.
 ///
 /// The service returns the request ID along with errors or responses. The response is an
-/// `Option`, to represent the object not being found.
+/// `Option`, to represent the object not being found. Services are usually a
+/// [`PollingService`] wrapped with [`polling_service`]. Errors that are a
+/// `PollingError::Mismatch` mean that the service returned content that does not match
+/// the object; they are counted separately from other errors but are otherwise retried the
+/// same way.
 pub fn spawn_monitor<ID, S, E, Res: Send + 'static>(
     service: S,
     response_sender: mpsc::Sender<(ID, Res)>,
@@ -105,10 +113,13 @@ pub fn spawn_monitor<ID, S, E, Res: Send + 'static>(
 where
     S: Service<ID, Response = Option<Res>, Error = E> + Send + 'static,
     ID: Display + Clone + Default + Eq + Send + Sync + Hash + 'static,
-    E: Display + Send + 'static,
+    E: Into<BoxError> + Send + 'static,
     S::Future: Send,
 {
-    let service = ReturnRequest { service };
+    let service = ReturnRequest {
+        service,
+        request_duration: metrics.request_duration.clone(),
+    };
     let (queue, queue_woken) = Queue::new(metrics.queue_depth.clone(), metrics.requests.clone());
 
     let cancel_check = response_sender.clone();
@@ -167,10 +178,14 @@ where
 
                     // Error polling, log it and push the id to the back of the queue.
                     Err((id, e)) => {
+                        let e: BoxError = e.into();
                         debug!(logger, "error polling";
                                     "error" => format!("{:#}", e),
                                     "object_id" => id.to_string());
-                        metrics.errors.inc();
+                        match e.downcast_ref::<PollingError>() {
+                            Some(PollingError::Mismatch(_)) => metrics.verification_failures.inc(),
+                            _ => metrics.errors.inc(),
+                        }
 
                         // Requests that return errors could mean there is a permanent issue with
                         // fetching the given item, or could signal the endpoint is overloaded.
@@ -203,8 +218,11 @@ impl<ID> PollingMonitor<ID> {
     }
 }
 
+/// Returns the request along with the response or error, and measures how
+/// long the request took
 struct ReturnRequest<S> {
     service: S,
+    request_duration: Box<Histogram>,
 }
 
 impl<S, Req> Service<Req> for ReturnRequest<S>
@@ -225,8 +243,11 @@ where
 
     fn call(&mut self, req: Req) -> Self::Future {
         let req1 = req.clone();
+        let request_duration = self.request_duration.clone();
+        let start = Instant::now();
         self.service
             .call(req.clone())
+            .inspect(move |_| request_duration.observe(start.elapsed().as_secs_f64()))
             .map_ok(move |x| (req, x))
             .map_err(move |e| (req1, e))
             .boxed()
@@ -329,6 +350,71 @@ mod tests {
         assert_eq!(rx.recv().await, Some(("req-1", "res-1")));
     }
 
+    #[tokio::test]
+    async fn polling_monitor_verification_failure() {
+        let (svc, mut handle) = mock::pair();
+        let (tx, mut rx) = mpsc::channel(10);
+        let metrics = PollingMonitorMetrics::mock();
+        let errors = metrics.errors.clone();
+        let verification_failures = metrics.verification_failures.clone();
+        let monitor = spawn_monitor(svc, tx, log::discard(), metrics);
+
+        // Content that does not match is not delivered, and the object is polled again.
+        monitor.monitor("req-0");
+        let req = handle.next_request().await.unwrap().1;
+        req.send_error(PollingError::Mismatch(anyhow!("mismatch").into()));
+        send_response(&mut handle, Some("res-0")).await;
+        assert_eq!(rx.recv().await, Some(("req-0", "res-0")));
+        assert_eq!(verification_failures.get(), 1.0);
+        assert_eq!(errors.get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn polling_monitor_mock_service() {
+        let mock = MockPollingService::new();
+        let (tx, mut rx) = mpsc::channel(10);
+        let metrics = PollingMonitorMetrics::mock();
+        let not_found = metrics.not_found.clone();
+        let errors = metrics.errors.clone();
+        let verification_failures = metrics.verification_failures.clone();
+        let monitor = spawn_monitor(
+            polling_service(mock.clone(), 10),
+            tx,
+            log::discard(),
+            metrics,
+        );
+
+        // A slow response for `req-0` does not hold up `req-1`
+        mock.respond(
+            "req-0",
+            Duration::from_millis(50),
+            MockResponse::Found("res-0"),
+        );
+        mock.respond("req-1", Duration::ZERO, MockResponse::Found("res-1"));
+        monitor.monitor("req-1");
+        monitor.monitor("req-0");
+        assert_eq!(rx.recv().await, Some(("req-1", "res-1")));
+        assert_eq!(rx.recv().await, Some(("req-0", "res-0")));
+
+        // Objects that are not found are polled again right away
+        mock.respond("req-2", Duration::ZERO, MockResponse::NotFound);
+        mock.respond("req-2", Duration::ZERO, MockResponse::Found("res-2"));
+        monitor.monitor("req-2");
+        assert_eq!(rx.recv().await, Some(("req-2", "res-2")));
+        assert_eq!(not_found.get(), 1.0);
+        assert_eq!(mock.requests(), vec!["req-0", "req-1", "req-2", "req-2"]);
+
+        // Errors are counted by kind
+        tokio::time::pause();
+        mock.respond("req-3", Duration::ZERO, MockResponse::Error("e".into()));
+        mock.respond("req-3", Duration::ZERO, MockResponse::Mismatch("m".into()));
+        mock.respond("req-3", Duration::ZERO, MockResponse::Found("res-3"));
+        monitor.monitor("req-3");
+        assert_eq!(rx.recv().await, Some(("req-3", "res-3")));
+        assert_eq!(errors.get(), 1.0);
+        assert_eq!(verification_failures.get(), 1.0);
+    }
+
     #[tokio::test]
     async fn polling_monitor_cancelation() {
         // Cancelation on receiver drop, no pending request.
@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use graph::cheap_clone::CheapClone;
+use tower::{buffer::Buffer, BoxError, ServiceBuilder, ServiceExt};
+
+/// The source of the objects that a polling monitor waits for, such as
+/// files on IPFS.
+#[async_trait]
+pub trait PollingService<Req>: Send + Sync + 'static {
+    type Response: Send + 'static;
+
+    /// Fetch the object `req`. Returns `Ok(None)` if the object was not
+    /// found, for example because fetching it timed out. Both objects that
+    /// were not found and errors are polled again, errors after a backoff.
+    async fn fetch(&self, req: Req) -> Result<Option<Self::Response>, PollingError>;
+}
+
+/// An error from polling for an object
+#[derive(Debug)]
+pub enum PollingError {
+    /// The service returned content that does not match the requested
+    /// object
+    Mismatch(BoxError),
+    Other(BoxError),
+}
+
+impl fmt::Display for PollingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PollingError::Mismatch(e) | PollingError::Other(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for PollingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PollingError::Mismatch(e) | PollingError::Other(e) => e.source(),
+        }
+    }
+}
+
+/// A `PollingService` as a tower service. Clones share the concurrency and
+/// rate limit of the service.
+pub type PollingServiceHandle<Req, Res> =
+    Buffer<Req, BoxFuture<'static, Result<Option<Res>, PollingError>>>;
+
+/// Turn `service` into a tower service that makes at most
+/// `concurrency_and_rate_limit` requests at the same time and per second.
+pub fn polling_service<Req, S>(
+    service: S,
+    concurrency_and_rate_limit: u16,
+) -> PollingServiceHandle<Req, S::Response>
+where
+    Req: Send + 'static,
+    S: PollingService<Req>,
+{
+    let service = Arc::new(service);
+
+    let svc = ServiceBuilder::new()
+        .rate_limit(concurrency_and_rate_limit.into(), Duration::from_secs(1))
+        .concurrency_limit(concurrency_and_rate_limit as usize)
+        .service_fn(move |req| {
+            let service = service.cheap_clone();
+            async move { service.fetch(req).await }.boxed()
+        })
+        .boxed();
+
+    // The `Buffer` makes it so the rate and concurrency limit are shared among clones.
+    Buffer::new(svc, 1)
+}
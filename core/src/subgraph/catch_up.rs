@@ -0,0 +1,443 @@
+//! Share the block processing capacity of the node between deployments
+//! that are catching up with the chain head.
+//!
+//! Only `concurrency` deployments that are behind the chain head can
+//! process a block at the same time. When more want to, they wait for a
+//! [`CatchUpPermit`] and take turns so that each deployment gets
+//! processing time in proportion to its priority. The priority grows with
+//! the rate of queries the deployment receives, since users care about the
+//! deployments they query; no deployment's priority falls below a minimum
+//! share of the highest priority so that every deployment makes progress.
+//! Deployments on a network whose provider is over its request budget get
+//! only that minimum share.
+//!
+//! The query rates come from all query nodes through
+//! [`graph::components::metrics::query_rate`], since index nodes usually do
+//! not serve queries themselves.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use graph::components::bus::DeploymentSelection;
+use graph::components::metrics::provider_budget::network_over_budget;
+use graph::components::metrics::query_rate::query_rate;
+use graph::env::EnvVars;
+use graph::prelude::{CheapClone, DeploymentHash, GaugeVec, MetricsRegistry};
+use tokio::sync::oneshot;
+
+pub struct CatchUpConfig {
+    /// The number of deployments that can process blocks at the same time;
+    /// 0 means no limit
+    pub concurrency: usize,
+    /// How much one query per second adds to the base priority of 1
+    pub query_weight: f64,
+    /// The smallest priority, as a fraction of the highest priority of the
+    /// deployments that compete for a permit
+    pub min_share: f64,
+    /// Deployments that always have the highest priority
+    pub pinned: DeploymentSelection,
+}
+
+impl CatchUpConfig {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        CatchUpConfig {
+            concurrency: env_vars.catch_up_concurrency,
+            query_weight: env_vars.catch_up_query_weight,
+            min_share: env_vars.catch_up_min_share.clamp(0.0, 1.0),
+            pinned: env_vars.catch_up_pinned.clone(),
+        }
+    }
+}
+
+struct Waiter {
+    deployment: DeploymentHash,
+    sender: oneshot::Sender<CatchUpPermit>,
+}
+
+/// The lowest priority a deployment can have; keeps deployments on networks
+/// that are over budget from being starved when the minimum share is 0
+const MIN_PRIORITY: f64 = 0.001;
+
+type RateFn = Box<dyn Fn(&str) -> f64 + Send + Sync>;
+type OverBudgetFn = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    /// The number of permits that are currently held
+    held: usize,
+    /// The processing time each deployment received, divided by its
+    /// priority. The waiting deployment with the smallest value gets the
+    /// next permit
+    virtual_time: HashMap<DeploymentHash, f64>,
+    /// The network of each deployment
+    networks: HashMap<DeploymentHash, String>,
+    /// The virtual time of the deployment that got the last permit.
+    /// Deployments that were idle are moved up to it so that they can't
+    /// claim the time they did not use
+    now: f64,
+    waiting: Vec<Waiter>,
+}
+
+pub struct CatchUpScheduler {
+    config: CatchUpConfig,
+    query_rate: RateFn,
+    over_budget: OverBudgetFn,
+    priority: Box<GaugeVec>,
+    state: Mutex<State>,
+}
+
+impl CatchUpScheduler {
+    pub fn new(config: CatchUpConfig, registry: Arc<dyn MetricsRegistry>) -> Self {
+        let priority = registry
+            .new_gauge_vec(
+                "deployment_catch_up_priority",
+                "The priority of a deployment that is catching up with the chain head",
+                vec![String::from("deployment")],
+            )
+            .expect("failed to create `deployment_catch_up_priority` gauge");
+        Self::with_query_rate(
+            config,
+            Box::new(query_rate),
+            Box::new(network_over_budget),
+            priority,
+        )
+    }
+
+    fn with_query_rate(
+        config: CatchUpConfig,
+        query_rate: RateFn,
+        over_budget: OverBudgetFn,
+        priority: Box<GaugeVec>,
+    ) -> Self {
+        CatchUpScheduler {
+            config,
+            query_rate,
+            over_budget,
+            priority,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Wait until `deployment`, which indexes `network`, may process a
+    /// block. Returns `None` if the number of deployments processing blocks
+    /// is not limited. The deployment may process blocks until the permit
+    /// is dropped
+    pub async fn acquire(
+        self: &Arc<Self>,
+        deployment: &DeploymentHash,
+        network: &str,
+    ) -> Option<CatchUpPermit> {
+        if self.config.concurrency == 0 {
+            return None;
+        }
+
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if !state.networks.contains_key(deployment) {
+                state
+                    .networks
+                    .insert(deployment.clone(), network.to_string());
+            }
+            let now = state.now;
+            let time = state.virtual_time.entry(deployment.clone()).or_insert(now);
+            *time = time.max(now);
+
+            if state.held < self.config.concurrency && state.waiting.is_empty() {
+                state.held += 1;
+                return Some(CatchUpPermit::new(self.cheap_clone(), deployment.clone()));
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.push(Waiter {
+                deployment: deployment.clone(),
+                sender,
+            });
+            receiver
+        };
+
+        // `release` only drops the sender after sending a permit
+        receiver.await.ok()
+    }
+
+    /// Forget `deployment` once it stopped running on this node. A
+    /// deployment that is started again competes from the current virtual
+    /// time like a deployment that was idle
+    pub fn remove(&self, deployment: &DeploymentHash) {
+        let mut state = self.state.lock().unwrap();
+        if state.waiting.iter().any(|w| &w.deployment == deployment) {
+            return;
+        }
+        state.virtual_time.remove(deployment);
+        state.networks.remove(deployment);
+        let _ = self.priority.remove_label_values(&[deployment.as_str()]);
+    }
+
+    /// The priority of each of `deployments`
+    fn priorities(
+        &self,
+        networks: &HashMap<DeploymentHash, String>,
+        deployments: &[&DeploymentHash],
+    ) -> Vec<f64> {
+        let raw: Vec<_> = deployments
+            .iter()
+            .map(|deployment| {
+                let over_budget = networks
+                    .get(*deployment)
+                    .map_or(false, |network| (self.over_budget)(network));
+                if over_budget {
+                    // Falls to the minimum share below
+                    0.0
+                } else {
+                    1.0 + self.config.query_weight * (self.query_rate)(deployment.as_str())
+                }
+            })
+            .collect();
+        let highest = raw.iter().cloned().fold(1.0, f64::max);
+
+        deployments
+            .iter()
+            .zip(raw)
+            .map(|(deployment, raw)| {
+                let priority = if self.config.pinned.contains(deployment.as_str()) {
+                    highest
+                } else {
+                    raw.max(self.config.min_share * highest).max(MIN_PRIORITY)
+                };
+                self.priority
+                    .with_label_values(&[deployment.as_str()])
+                    .set(priority);
+                priority
+            })
+            .collect()
+    }
+
+    fn release(self: &Arc<Self>, deployment: &DeploymentHash, held_secs: f64) {
+        let next = {
+            let mut state = self.state.lock().unwrap();
+
+            let mut contenders: Vec<_> = state.waiting.iter().map(|w| &w.deployment).collect();
+            contenders.push(deployment);
+            let priorities = self.priorities(&state.networks, &contenders);
+            let priority = priorities[priorities.len() - 1];
+            if let Some(time) = state.virtual_time.get_mut(deployment) {
+                *time += held_secs / priority;
+            }
+
+            // The waiter with the smallest virtual time goes next; among
+            // equals, the one that has waited longest
+            let next = state
+                .waiting
+                .iter()
+                .enumerate()
+                .map(|(idx, waiter)| (idx, state.virtual_time[&waiter.deployment]))
+                .fold(None, |min: Option<(usize, f64)>, (idx, time)| match min {
+                    Some((_, min_time)) if min_time <= time => min,
+                    _ => Some((idx, time)),
+                });
+            match next {
+                Some((idx, time)) => {
+                    state.now = state.now.max(time);
+                    Some(state.waiting.remove(idx))
+                }
+                None => {
+                    state.held -= 1;
+                    None
+                }
+            }
+        };
+
+        // Hand the permit over without holding the lock. If the waiter is
+        // gone, dropping the permit that comes back releases it again
+        if let Some(waiter) = next {
+            let permit = CatchUpPermit::new(self.cheap_clone(), waiter.deployment);
+            let _ = waiter.sender.send(permit);
+        }
+    }
+}
+
+/// Allows a deployment to process blocks while it is held
+pub struct CatchUpPermit {
+    scheduler: Arc<CatchUpScheduler>,
+    deployment: DeploymentHash,
+    start: Instant,
+}
+
+impl CatchUpPermit {
+    fn new(scheduler: Arc<CatchUpScheduler>, deployment: DeploymentHash) -> Self {
+        CatchUpPermit {
+            scheduler,
+            deployment,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for CatchUpPermit {
+    fn drop(&mut self) {
+        self.scheduler
+            .release(&self.deployment, self.start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::Opts;
+    use graph::prometheus::core::Collector;
+
+    fn scheduler(
+        concurrency: usize,
+        pinned: &str,
+        query_rate: fn(&str) -> f64,
+    ) -> Arc<CatchUpScheduler> {
+        scheduler_with_budget(concurrency, pinned, query_rate, |_| false)
+    }
+
+    fn scheduler_with_budget(
+        concurrency: usize,
+        pinned: &str,
+        query_rate: fn(&str) -> f64,
+        over_budget: fn(&str) -> bool,
+    ) -> Arc<CatchUpScheduler> {
+        let config = CatchUpConfig {
+            concurrency,
+            query_weight: 1.0,
+            min_share: 0.1,
+            pinned: pinned.parse().unwrap(),
+        };
+        let priority = GaugeVec::new(Opts::new("priority", "priority"), &["deployment"]).unwrap();
+        Arc::new(CatchUpScheduler::with_query_rate(
+            config,
+            Box::new(query_rate),
+            Box::new(over_budget),
+            Box::new(priority),
+        ))
+    }
+
+    fn hash(s: &str) -> DeploymentHash {
+        DeploymentHash::new(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unlimited() {
+        let scheduler = scheduler(0, "", |_| 0.0);
+        assert!(scheduler.acquire(&hash("QmA"), "mainnet").await.is_none());
+    }
+
+    #[test]
+    fn priorities() {
+        let rate = |deployment: &str| match deployment {
+            "QmBusy" => 99.0,
+            "QmSome" => 1.0,
+            _ => 0.0,
+        };
+        let scheduler = scheduler(1, "QmPinned", rate);
+        let (busy, some, idle, pinned) = (
+            hash("QmBusy"),
+            hash("QmSome"),
+            hash("QmIdle"),
+            hash("QmPinned"),
+        );
+        let networks = HashMap::new();
+        assert_eq!(
+            vec![100.0, 10.0, 10.0, 100.0],
+            scheduler.priorities(&networks, &[&busy, &some, &idle, &pinned])
+        );
+        // Without competition from busy deployments, the minimum share
+        // does not matter
+        assert_eq!(
+            vec![2.0, 1.0],
+            scheduler.priorities(&networks, &[&some, &idle])
+        );
+    }
+
+    #[test]
+    fn over_budget() {
+        let rate = |deployment: &str| match deployment {
+            "QmBusy" => 9.0,
+            _ => 0.0,
+        };
+        let scheduler =
+            scheduler_with_budget(1, "QmPinned", rate, |network| network == "expensive");
+        let (busy, idle, pinned) = (hash("QmBusy"), hash("QmIdle"), hash("QmPinned"));
+        let networks = HashMap::from_iter([
+            (busy.clone(), "expensive".to_string()),
+            (idle.clone(), "cheap".to_string()),
+            (pinned.clone(), "expensive".to_string()),
+        ]);
+        // Queries don't help deployments on a network that is over budget,
+        // but pinned deployments keep their priority
+        assert_eq!(
+            vec![0.1, 1.0, 1.0],
+            scheduler.priorities(&networks, &[&busy, &idle, &pinned])
+        );
+    }
+
+    #[tokio::test]
+    async fn turns() {
+        let scheduler = scheduler(1, "", |_| 0.0);
+        let (a, b) = (hash("QmA"), hash("QmB"));
+
+        let permit = scheduler.acquire(&a, "mainnet").await.unwrap();
+        let waiting = {
+            let scheduler = scheduler.cheap_clone();
+            let b = b.clone();
+            tokio::spawn(async move { scheduler.acquire(&b, "mainnet").await })
+        };
+        // Let `b` start waiting
+        while scheduler.state.lock().unwrap().waiting.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        drop(permit);
+
+        let permit = waiting.await.unwrap().unwrap();
+        assert_eq!(b, permit.deployment);
+        assert_eq!(1, scheduler.state.lock().unwrap().held);
+        drop(permit);
+        assert_eq!(0, scheduler.state.lock().unwrap().held);
+    }
+
+    #[tokio::test]
+    async fn remove() {
+        let scheduler = scheduler(1, "", |_| 0.0);
+        let a = hash("QmA");
+
+        let permit = scheduler.acquire(&a, "mainnet").await.unwrap();
+        drop(permit);
+        assert!(scheduler
+            .state
+            .lock()
+            .unwrap()
+            .virtual_time
+            .contains_key(&a));
+        assert_eq!(1, scheduler.priority.collect()[0].get_metric().len());
+
+        scheduler.remove(&a);
+        let state = scheduler.state.lock().unwrap();
+        assert!(state.virtual_time.is_empty());
+        assert!(state.networks.is_empty());
+        assert!(scheduler.priority.collect()[0].get_metric().is_empty());
+    }
+
+    #[tokio::test]
+    async fn abandoned_wait() {
+        let scheduler = scheduler(1, "", |_| 0.0);
+
+        let permit = scheduler.acquire(&hash("QmA"), "mainnet").await.unwrap();
+        let waiting = {
+            let scheduler = scheduler.cheap_clone();
+            tokio::spawn(async move { scheduler.acquire(&hash("QmB"), "mainnet").await })
+        };
+        while scheduler.state.lock().unwrap().waiting.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        waiting.abort();
+        let _ = waiting.await;
+        drop(permit);
+
+        // The permit that was handed to the abandoned waiter is released
+        assert_eq!(0, scheduler.state.lock().unwrap().held);
+        assert!(scheduler.acquire(&hash("QmC"), "mainnet").await.is_some());
+    }
+}
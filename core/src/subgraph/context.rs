@@ -6,6 +6,7 @@ use bytes::Bytes;
 use graph::{
     blockchain::Blockchain,
     components::{
+        metrics::usage::{usage_tracker, UsageTracker},
         store::{DeploymentId, DeploymentLocator, SubgraphFork},
         subgraph::{MappingError, SharedProofOfIndexing},
     },
@@ -158,6 +159,8 @@ impl<C: Blockchain, T: RuntimeHostBuilder<C>> IndexingContext<C, T> {
 pub struct OffchainMonitor {
     ipfs_monitor: PollingMonitor<CidFile>,
     ipfs_monitor_rx: mpsc::Receiver<(CidFile, Bytes)>,
+    usage: Arc<UsageTracker>,
+    metrics: PollingMonitorMetrics,
 }
 
 impl OffchainMonitor {
@@ -168,18 +171,22 @@ impl OffchainMonitor {
         ipfs_service: IpfsService,
     ) -> Self {
         let (ipfs_monitor_tx, ipfs_monitor_rx) = mpsc::channel(10);
-        let ipfs_monitor = spawn_monitor(
-            ipfs_service,
-            ipfs_monitor_tx,
-            logger,
-            PollingMonitorMetrics::new(registry, deployment),
-        );
+        let metrics = PollingMonitorMetrics::new(registry, deployment);
+        let ipfs_monitor = spawn_monitor(ipfs_service, ipfs_monitor_tx, logger, metrics.clone());
         Self {
             ipfs_monitor,
             ipfs_monitor_rx,
+            usage: usage_tracker(deployment.hash.as_str()),
+            metrics,
         }
     }
 
+    /// The metrics of the monitor that polls IPFS, so that they can be
+    /// unregistered when the deployment stops
+    pub fn metrics(&self) -> &PollingMonitorMetrics {
+        &self.metrics
+    }
+
     fn add_source(&mut self, source: offchain::Source) -> Result<(), Error> {
         match source {
             offchain::Source::Ipfs(cid_file) => self.ipfs_monitor.monitor(cid_file),
@@ -193,10 +200,13 @@ impl OffchainMonitor {
         let mut triggers = vec![];
         loop {
             match self.ipfs_monitor_rx.try_recv() {
-                Ok((cid_file, data)) => triggers.push(offchain::TriggerData {
-                    source: offchain::Source::Ipfs(cid_file),
-                    data: Arc::new(data),
-                }),
+                Ok((cid_file, data)) => {
+                    self.usage.ipfs_bytes(data.len());
+                    triggers.push(offchain::TriggerData {
+                        source: offchain::Source::Ipfs(cid_file),
+                        data: Arc::new(data),
+                    })
+                }
                 Err(TryRecvError::Disconnected) => {
                     anyhow::bail!("ipfs monitor unexpectedly terminated")
                 }
@@ -6,8 +6,10 @@ use graph::{
         DataSourceTemplate,
     },
     prelude::*,
+    runtime::CompileProfile,
 };
 use std::collections::HashMap;
+use std::time::Instant;
 
 use super::OffchainMonitor;
 
@@ -30,6 +32,9 @@ pub struct SubgraphInstance<C: Blockchain, T: RuntimeHostBuilder<C>> {
 
     /// This manages the sequence of causality regions for the subgraph.
     causality_region_seq: CausalityRegionSeq,
+
+    /// The profile with which the modules of the subgraph are compiled
+    compile_profile: CompileProfile,
 }
 
 impl<T, C> SubgraphInstance<C, T>
@@ -44,6 +49,7 @@ where
         host_metrics: Arc<HostMetrics>,
         offchain_monitor: &mut OffchainMonitor,
         causality_region_seq: CausalityRegionSeq,
+        compile_profile: CompileProfile,
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
@@ -58,11 +64,14 @@ where
             templates,
             host_metrics,
             causality_region_seq,
+            compile_profile,
         };
 
         // Create a new runtime host for each data source in the subgraph manifest;
         // we use the same order here as in the subgraph manifest to make the
         // event processing behavior predictable
+        let start = Instant::now();
+        let mut pending = Vec::with_capacity(manifest.data_sources.len());
         for ds in manifest.data_sources {
             // TODO: This is duplicating code from `IndexingContext::add_dynamic_data_source` and
             // `SubgraphInstance::add_dynamic_data_source`. Ideally this should be refactored into
@@ -81,13 +90,41 @@ where
                 }
             }
 
-            let host = this.new_host(logger.cheap_clone(), ds, module_bytes)?;
-            this.hosts.push(Arc::new(host));
+            let sender = this.mapping_request_sender(logger.cheap_clone(), module_bytes)?;
+            pending.push((ds, sender));
         }
 
+        let hosts = this.build_hosts(pending)?;
+        info!(logger, "Built runtime hosts";
+            "count" => hosts.len(),
+            "time_ms" => start.elapsed().as_millis());
+        this.hosts.extend(hosts.into_iter().map(Arc::new));
+
         Ok(this)
     }
 
+    /// Return the channel to the thread that runs the mapping in
+    /// `module_bytes`, spawning the thread if there is none yet
+    fn mapping_request_sender(
+        &mut self,
+        logger: Logger,
+        module_bytes: &Arc<Vec<u8>>,
+    ) -> Result<Sender<T::Req>, Error> {
+        let module_hash = tiny_keccak::keccak256(module_bytes.as_ref());
+        if let Some(sender) = self.module_cache.get(&module_hash) {
+            return Ok(sender.clone());
+        }
+        let sender = T::spawn_mapping(
+            module_bytes.as_ref(),
+            logger,
+            self.subgraph_id.clone(),
+            self.host_metrics.cheap_clone(),
+            self.compile_profile,
+        )?;
+        self.module_cache.insert(module_hash, sender.clone());
+        Ok(sender)
+    }
+
     // module_bytes is the same as data_source.runtime().unwrap(), this is to ensure that this
     // function is only called for data_sources for which data_source.runtime().is_some() is true.
     fn new_host(
@@ -96,21 +133,15 @@ where
         data_source: DataSource<C>,
         module_bytes: &Arc<Vec<u8>>,
     ) -> Result<T::Host, Error> {
-        let mapping_request_sender = {
-            let module_hash = tiny_keccak::keccak256(module_bytes.as_ref());
-            if let Some(sender) = self.module_cache.get(&module_hash) {
-                sender.clone()
-            } else {
-                let sender = T::spawn_mapping(
-                    module_bytes.as_ref(),
-                    logger,
-                    self.subgraph_id.clone(),
-                    self.host_metrics.cheap_clone(),
-                )?;
-                self.module_cache.insert(module_hash, sender.clone());
-                sender
-            }
-        };
+        let mapping_request_sender = self.mapping_request_sender(logger, module_bytes)?;
+        self.build_host(data_source, mapping_request_sender)
+    }
+
+    fn build_host(
+        &self,
+        data_source: DataSource<C>,
+        mapping_request_sender: Sender<T::Req>,
+    ) -> Result<T::Host, Error> {
         self.host_builder.build(
             self.network.clone(),
             self.subgraph_id.clone(),
@@ -121,6 +152,70 @@ where
         )
     }
 
+    /// Build the hosts for `pending` on several threads. Manifests can
+    /// have thousands of data sources, and building their hosts one after
+    /// the other makes starting the subgraph slow. The hosts are returned
+    /// in the same order as `pending`
+    fn build_hosts(
+        &self,
+        pending: Vec<(DataSource<C>, Sender<T::Req>)>,
+    ) -> Result<Vec<T::Host>, Error> {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        if threads <= 1 || pending.len() < 2 * threads {
+            return pending
+                .into_iter()
+                .map(|(ds, sender)| self.build_host(ds, sender))
+                .collect();
+        }
+
+        let chunk_size = (pending.len() + threads - 1) / threads;
+        let mut chunks = Vec::with_capacity(threads);
+        let mut pending = pending.into_iter();
+        loop {
+            let chunk: Vec<_> = pending.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push(chunk);
+        }
+
+        let host_builder = &self.host_builder;
+        let network = &self.network;
+        let subgraph_id = &self.subgraph_id;
+        let templates = &self.templates;
+        let host_metrics = &self.host_metrics;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .into_iter()
+                            .map(|(ds, sender)| {
+                                host_builder.build(
+                                    network.clone(),
+                                    subgraph_id.clone(),
+                                    ds,
+                                    templates.cheap_clone(),
+                                    sender,
+                                    host_metrics.cheap_clone(),
+                                )
+                            })
+                            .collect::<Result<Vec<_>, Error>>()
+                    })
+                })
+                .collect();
+
+            let mut hosts = Vec::new();
+            for handle in handles {
+                hosts.extend(handle.join().unwrap_or_else(std::panic::resume_unwind)?);
+            }
+            Ok(hosts)
+        })
+    }
+
     pub(super) fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
@@ -1,5 +1,6 @@
-use graph::data::subgraph::schema::SubgraphError;
-use graph::prelude::{thiserror, Error, StoreError};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
+use graph::prelude::{thiserror, web3, Error, StoreError};
+use graph_runtime_wasm::trap_error_code;
 
 #[derive(thiserror::Error, Debug)]
 pub enum BlockProcessingError {
@@ -19,6 +20,49 @@ impl BlockProcessingError {
     pub fn is_deterministic(&self) -> bool {
         matches!(self, BlockProcessingError::Deterministic(_))
     }
+
+    /// Whether the error was caused by the store being unavailable, in
+    /// which case processing should be retried once the store is
+    /// reachable again
+    pub fn is_store_unavailable(&self) -> bool {
+        match self {
+            BlockProcessingError::Unknown(e) => StoreError::is_unavailable(e),
+            BlockProcessingError::Deterministic(_) | BlockProcessingError::Canceled => false,
+        }
+    }
+
+    /// The code under which a failure caused by this error is recorded
+    pub fn code(&self) -> SubgraphErrorCode {
+        match self {
+            BlockProcessingError::Unknown(e) => error_code(e),
+            BlockProcessingError::Deterministic(e) => e.code,
+            BlockProcessingError::Canceled => SubgraphErrorCode::Unknown,
+        }
+    }
+}
+
+/// Classify `error` by the first cause in its chain that we recognize
+fn error_code(error: &Error) -> SubgraphErrorCode {
+    if StoreError::is_unavailable(error) {
+        return SubgraphErrorCode::StoreUnavailable;
+    }
+    if let Some(code) = trap_error_code(error) {
+        return code;
+    }
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(StoreError::InvariantViolated(_)) = cause.downcast_ref::<StoreError>() {
+                Some(SubgraphErrorCode::InvariantViolated)
+            } else if cause.is::<StoreError>() {
+                Some(SubgraphErrorCode::StoreError)
+            } else if cause.is::<web3::Error>() {
+                Some(SubgraphErrorCode::ProviderError)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(SubgraphErrorCode::Unknown)
 }
 
 impl From<StoreError> for BlockProcessingError {
@@ -26,3 +70,52 @@ impl From<StoreError> for BlockProcessingError {
         BlockProcessingError::Unknown(e.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        let unavailable = Error::from(StoreError::DatabaseUnavailable).context("writing block");
+        assert_eq!(
+            SubgraphErrorCode::StoreUnavailable,
+            BlockProcessingError::Unknown(unavailable).code()
+        );
+
+        let store = Error::from(StoreError::ConstraintViolation("oops".to_string()));
+        assert_eq!(
+            SubgraphErrorCode::StoreError,
+            BlockProcessingError::Unknown(store).code()
+        );
+
+        let invariant = Error::from(StoreError::InvariantViolated(SubgraphError::default()))
+            .context("Failed to transact block operations");
+        assert_eq!(
+            SubgraphErrorCode::InvariantViolated,
+            BlockProcessingError::Unknown(invariant).code()
+        );
+
+        let provider = Error::from(web3::Error::Unreachable).context("eth_call failed");
+        assert_eq!(
+            SubgraphErrorCode::ProviderError,
+            BlockProcessingError::Unknown(provider).code()
+        );
+
+        assert_eq!(
+            SubgraphErrorCode::Unknown,
+            BlockProcessingError::Unknown(anyhow!("something else")).code()
+        );
+
+        let error = SubgraphError {
+            code: SubgraphErrorCode::HandlerError,
+            ..Default::default()
+        };
+        assert_eq!(
+            SubgraphErrorCode::HandlerError,
+            BlockProcessingError::Deterministic(error).code()
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use graph::blockchain::{Blockchain, RuntimeAdapter};
+use graph::data::subgraph::fingerprint::CompatibilityFingerprint;
+use graph::prelude::{anyhow::Error, BigDecimal, SubgraphManifest, ENV_VARS};
+use graph::runtime::gas::GAS_SCHEDULE_VERSION;
+use graph_runtime_wasm::ExperimentalFeatures;
+
+/// The hash of the host exports that the mappings of `manifest` are
+/// linked with, including the host fns that `runtime_adapter` provides
+/// for its data sources
+pub fn host_export_hash<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+    runtime_adapter: &dyn RuntimeAdapter<C>,
+) -> Result<String, Error> {
+    let api_versions: Vec<_> = manifest.api_versions().collect();
+    let mut host_fns = Vec::new();
+    for ds in manifest
+        .data_sources
+        .iter()
+        .filter_map(|ds| ds.as_onchain())
+    {
+        host_fns.extend(runtime_adapter.host_fns(ds)?);
+    }
+    let experimental_features = ExperimentalFeatures {
+        allow_non_deterministic_ipfs: ENV_VARS.mappings.allow_non_deterministic_ipfs,
+    };
+    Ok(graph_runtime_wasm::host_export_surface_hash(
+        &api_versions,
+        &host_fns,
+        &experimental_features,
+    ))
+}
+
+/// Compute the compatibility fingerprint of `manifest` as it would be
+/// indexed by this node. Only settings that can change the results of
+/// indexing go into the fingerprint
+pub fn compatibility_fingerprint<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+    host_export_hash: &str,
+) -> CompatibilityFingerprint {
+    let mut fingerprint = CompatibilityFingerprint::default();
+
+    let mut api_versions: Vec<_> = manifest.api_versions().collect();
+    api_versions.sort();
+    api_versions.dedup();
+    let api_versions: Vec<_> = api_versions.iter().map(ToString::to_string).collect();
+    let features: Vec<_> = manifest.features.iter().map(ToString::to_string).collect();
+
+    fingerprint.set("spec_version", &manifest.spec_version);
+    fingerprint.set("api_versions", api_versions.join(","));
+    fingerprint.set("features", features.join(","));
+    fingerprint.set("gas_schedule", GAS_SCHEDULE_VERSION);
+    fingerprint.set("host_exports", host_export_hash);
+    fingerprint.set(
+        "big_decimal",
+        format!(
+            "digits={},exp={}..{}",
+            BigDecimal::MAX_SIGNFICANT_DIGITS,
+            BigDecimal::MIN_EXP,
+            BigDecimal::MAX_EXP
+        ),
+    );
+    fingerprint.set(
+        "allow_non_deterministic_ipfs",
+        ENV_VARS.mappings.allow_non_deterministic_ipfs,
+    );
+    fingerprint.set("max_ipfs_file_bytes", ENV_VARS.mappings.max_ipfs_file_bytes);
+    fingerprint.set(
+        "max_ipfs_map_file_size",
+        ENV_VARS.mappings.max_ipfs_map_file_size,
+    );
+
+    fingerprint
+}
@@ -1,16 +1,22 @@
 use graph::{
     blockchain::{Blockchain, TriggersAdapter},
     components::{
+        bus::{BusMessage, CriticalTypes},
         store::{DeploymentLocator, SubgraphFork, WritableStore},
         subgraph::ProofOfIndexingVersion,
     },
-    data::subgraph::{SubgraphFeature, UnifiedMappingApiVersion},
+    data::subgraph::{invariant::Invariant, SubgraphFeature, UnifiedMappingApiVersion},
     data_source::DataSourceTemplate,
     prelude::BlockNumber,
+    tokio::sync::mpsc::UnboundedSender,
 };
 use std::collections::BTreeSet;
 use std::sync::Arc;
 
+use super::catch_up::CatchUpScheduler;
+use super::offchain_limit::OffchainLimiter;
+use super::synced_actions::SyncedActions;
+
 pub struct IndexingInputs<C: Blockchain> {
     pub deployment: DeploymentLocator,
     pub features: BTreeSet<SubgraphFeature>,
@@ -28,4 +34,31 @@ pub struct IndexingInputs<C: Blockchain> {
 
     // Correspondence between data source or template position in the manifest and name.
     pub manifest_idx_and_name: Vec<(u32, String)>,
+
+    /// Where to send the messages about processed blocks that the
+    /// deployment publishes; `None` if no bus is configured
+    pub bus_sender: Option<UnboundedSender<BusMessage>>,
+
+    /// The entity types whose delivery to the bus needs to be verifiable;
+    /// `None` if the deployment has none
+    pub bus_critical: Option<CriticalTypes>,
+
+    /// Decides when the deployment may process blocks while it is
+    /// catching up with the chain head
+    pub catch_up: Arc<CatchUpScheduler>,
+
+    /// Decides when the deployment may process offchain triggers
+    pub offchain_limit: Arc<OffchainLimiter>,
+
+    /// What to do when the deployment reaches the chain head for the
+    /// first time
+    pub synced_actions: Arc<SyncedActions>,
+
+    /// The invariants from the manifest, which the store checks with
+    /// every block it writes
+    pub invariants: Vec<Invariant>,
+
+    /// The reorg threshold that the deployment set for itself instead of
+    /// the one of its chain, if any
+    pub reorg_threshold: Option<BlockNumber>,
 }
@@ -1,7 +1,13 @@
+use super::catch_up::{CatchUpConfig, CatchUpScheduler};
 use super::context::OffchainMonitor;
+use super::offchain_limit::{OffchainLimitConfig, OffchainLimiter};
+use super::poi_reference::{PoiReferenceChecker, PoiReferenceConfig};
+use super::synced_actions::{SyncedAction, SyncedActions};
+use super::watchdog::{StuckWatchdog, StuckWatchdogConfig};
 use super::SubgraphTriggerProcessor;
 use crate::polling_monitor::IpfsService;
 use crate::subgraph::context::{IndexingContext, SharedInstanceKeepAliveMap};
+use crate::subgraph::fingerprint::{compatibility_fingerprint, host_export_hash};
 use crate::subgraph::inputs::IndexingInputs;
 use crate::subgraph::loader::load_dynamic_data_sources;
 use crate::subgraph::runner::SubgraphRunner;
@@ -9,18 +15,30 @@ use graph::blockchain::block_stream::BlockStreamMetrics;
 use graph::blockchain::Blockchain;
 use graph::blockchain::NodeCapabilities;
 use graph::blockchain::{BlockchainKind, TriggerFilter};
-use graph::components::bus::BusMessage;
+use graph::components::bus::{BusRouter, CriticalTypes};
 use graph::components::subgraph::ProofOfIndexingVersion;
-use graph::data::subgraph::{UnresolvedSubgraphManifest, SPEC_VERSION_0_0_6};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
+use graph::data::subgraph::{
+    SubgraphFeature, SubgraphManifestResolveError, UnresolvedSubgraphManifest, SPEC_VERSION_0_0_6,
+};
 use graph::data_source::causality_region::CausalityRegionSeq;
 use graph::env::EnvVars;
 use graph::prelude::{SubgraphInstanceManager as SubgraphInstanceManagerTrait, *};
-use graph::{blockchain::BlockchainMap, components::store::DeploymentLocator};
+use graph::util::backoff::ExponentialBackoff;
+use graph::{
+    blockchain::BlockchainMap,
+    components::store::{DeploymentLocator, EntityType, WritableStore},
+};
 use graph_runtime_wasm::module::ToAscPtr;
 use graph_runtime_wasm::RuntimeHostBuilder;
-use tokio::sync::mpsc::UnboundedSender;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 use tokio::task;
 
+const STORE_UNAVAILABLE_RETRY_BASE: Duration = Duration::from_secs(1);
+const STORE_UNAVAILABLE_RETRY_CEIL: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct SubgraphInstanceManager<S: SubgraphStore> {
     logger_factory: LoggerFactory,
@@ -33,7 +51,40 @@ pub struct SubgraphInstanceManager<S: SubgraphStore> {
     ipfs_service: IpfsService,
     static_filters: bool,
     env_vars: Arc<EnvVars>,
-    bus_sender: Option<UnboundedSender<BusMessage>>,
+    bus_router: Arc<BusRouter>,
+    catch_up: Arc<CatchUpScheduler>,
+    offchain_limit: Arc<OffchainLimiter>,
+    poi_reference: Option<Arc<PoiReferenceChecker>>,
+    stuck_watchdog: Option<Arc<StuckWatchdog>>,
+    synced_actions: Arc<SyncedActions>,
+    /// The number of subgraph runners that have not exited yet
+    running: Arc<AtomicUsize>,
+}
+
+/// Stops all subgraph runners of a node when it shuts down
+#[derive(Clone, Default)]
+pub struct RunnerShutdown {
+    instances: SharedInstanceKeepAliveMap,
+    running: Arc<AtomicUsize>,
+}
+
+impl RunnerShutdown {
+    /// Stop all subgraph runners and wait up to `timeout` for them to
+    /// write what they hold to the store and exit. Return whether all of
+    /// them exited in time
+    pub async fn stop_all(&self, timeout: Duration) -> bool {
+        // Dropping the cancel guards stops the block streams, and with
+        // them the runners
+        self.instances.write().unwrap().clear();
+        let deadline = Instant::now() + timeout;
+        while self.running.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        true
+    }
 }
 
 #[async_trait]
@@ -49,77 +100,98 @@ impl<S: SubgraphStore> SubgraphInstanceManagerTrait for SubgraphInstanceManager<
         let instance_manager = self.cheap_clone();
         let manager_metrics = instance_manager.manager_metrics.clone();
 
-        let subgraph_start_future = async move {
-            match BlockchainKind::from_manifest(&manifest)? {
-                BlockchainKind::Arweave => {
-                    let runner = instance_manager
-                        .build_subgraph_runner::<graph_chain_arweave::Chain>(
-                            logger.clone(),
-                            self.env_vars.cheap_clone(),
-                            loc.clone(),
-                            manifest,
-                            stop_block,
-                            Box::new(SubgraphTriggerProcessor {}),
-                        )
-                        .await?;
-
-                    self.start_subgraph_inner(logger, loc, runner).await
-                }
-                BlockchainKind::Ethereum => {
-                    let runner = instance_manager
-                        .build_subgraph_runner::<graph_chain_ethereum::Chain>(
-                            logger.clone(),
-                            self.env_vars.cheap_clone(),
-                            loc.clone(),
-                            manifest,
-                            stop_block,
-                            Box::new(SubgraphTriggerProcessor {}),
-                        )
-                        .await?;
-
-                    self.start_subgraph_inner(logger, loc, runner).await
-                }
-                BlockchainKind::Near => {
-                    let runner = instance_manager
-                        .build_subgraph_runner::<graph_chain_near::Chain>(
-                            logger.clone(),
-                            self.env_vars.cheap_clone(),
-                            loc.clone(),
-                            manifest,
-                            stop_block,
-                            Box::new(SubgraphTriggerProcessor {}),
-                        )
-                        .await?;
-
-                    self.start_subgraph_inner(logger, loc, runner).await
-                }
-                BlockchainKind::Cosmos => {
-                    let runner = instance_manager
-                        .build_subgraph_runner::<graph_chain_cosmos::Chain>(
-                            logger.clone(),
-                            self.env_vars.cheap_clone(),
-                            loc.clone(),
-                            manifest,
-                            stop_block,
-                            Box::new(SubgraphTriggerProcessor {}),
-                        )
-                        .await?;
-
-                    self.start_subgraph_inner(logger, loc, runner).await
-                }
-                BlockchainKind::Substreams => {
-                    let runner = instance_manager
-                        .build_subgraph_runner::<graph_chain_substreams::Chain>(
-                            logger.clone(),
-                            self.env_vars.cheap_clone(),
-                            loc.cheap_clone(),
-                            manifest,
-                            stop_block,
-                            Box::new(graph_chain_substreams::TriggerProcessor::new(loc.clone())),
-                        )
-                        .await?;
-
-                    self.start_subgraph_inner(logger, loc, runner).await
+        // Starting a subgraph reads from the store. If the store is not
+        // available, for example during a database failover, we try again
+        // later rather than giving up on the subgraph
+        let subgraph_start = move || {
+            let instance_manager = instance_manager.cheap_clone();
+            let logger = logger.clone();
+            let loc = loc.clone();
+            let manifest = manifest.clone();
+            async move {
+                match BlockchainKind::from_manifest(&manifest)? {
+                    BlockchainKind::Arweave => {
+                        let runner = instance_manager
+                            .build_subgraph_runner::<graph_chain_arweave::Chain>(
+                                logger.clone(),
+                                instance_manager.env_vars.cheap_clone(),
+                                loc.clone(),
+                                manifest,
+                                stop_block,
+                                Box::new(SubgraphTriggerProcessor {}),
+                            )
+                            .await?;
+
+                        instance_manager
+                            .start_subgraph_inner(logger, loc, runner)
+                            .await
+                    }
+                    BlockchainKind::Ethereum => {
+                        let runner = instance_manager
+                            .build_subgraph_runner::<graph_chain_ethereum::Chain>(
+                                logger.clone(),
+                                instance_manager.env_vars.cheap_clone(),
+                                loc.clone(),
+                                manifest,
+                                stop_block,
+                                Box::new(SubgraphTriggerProcessor {}),
+                            )
+                            .await?;
+
+                        instance_manager
+                            .start_subgraph_inner(logger, loc, runner)
+                            .await
+                    }
+                    BlockchainKind::Near => {
+                        let runner = instance_manager
+                            .build_subgraph_runner::<graph_chain_near::Chain>(
+                                logger.clone(),
+                                instance_manager.env_vars.cheap_clone(),
+                                loc.clone(),
+                                manifest,
+                                stop_block,
+                                Box::new(SubgraphTriggerProcessor {}),
+                            )
+                            .await?;
+
+                        instance_manager
+                            .start_subgraph_inner(logger, loc, runner)
+                            .await
+                    }
+                    BlockchainKind::Cosmos => {
+                        let runner = instance_manager
+                            .build_subgraph_runner::<graph_chain_cosmos::Chain>(
+                                logger.clone(),
+                                instance_manager.env_vars.cheap_clone(),
+                                loc.clone(),
+                                manifest,
+                                stop_block,
+                                Box::new(SubgraphTriggerProcessor {}),
+                            )
+                            .await?;
+
+                        instance_manager
+                            .start_subgraph_inner(logger, loc, runner)
+                            .await
+                    }
+                    BlockchainKind::Substreams => {
+                        let runner = instance_manager
+                            .build_subgraph_runner::<graph_chain_substreams::Chain>(
+                                logger.clone(),
+                                instance_manager.env_vars.cheap_clone(),
+                                loc.cheap_clone(),
+                                manifest,
+                                stop_block,
+                                Box::new(graph_chain_substreams::TriggerProcessor::new(
+                                    loc.clone(),
+                                )),
+                            )
+                            .await?;
+
+                        instance_manager
+                            .start_subgraph_inner(logger, loc, runner)
+                            .await
+                    }
                 }
             }
         };
@@ -130,14 +202,30 @@ impl<S: SubgraphStore> SubgraphInstanceManagerTrait for SubgraphInstanceManager<
         // hours. Running it in the background makes sure the instance
         // manager does not hang because of that work.
         graph::spawn(async move {
-            match subgraph_start_future.await {
-                Ok(()) => manager_metrics.subgraph_count.inc(),
-                Err(err) => error!(
-                    err_logger,
-                    "Failed to start subgraph";
-                    "error" => format!("{:#}", err),
-                    "code" => LogCode::SubgraphStartFailure
-                ),
+            let mut backoff =
+                ExponentialBackoff::new(STORE_UNAVAILABLE_RETRY_BASE, STORE_UNAVAILABLE_RETRY_CEIL);
+            loop {
+                match subgraph_start().await {
+                    Ok(()) => manager_metrics.subgraph_count.inc(),
+                    Err(err) if StoreError::is_unavailable(&err) => {
+                        warn!(
+                            err_logger,
+                            "Store unavailable while starting subgraph, will retry";
+                            "error" => format!("{:#}", err),
+                            "attempt" => backoff.attempt,
+                            "retry_delay_ms" => backoff.delay().as_millis()
+                        );
+                        backoff.sleep_async().await;
+                        continue;
+                    }
+                    Err(err) => error!(
+                        err_logger,
+                        "Failed to start subgraph";
+                        "error" => format!("{:#}", err),
+                        "code" => LogCode::SubgraphStartFailure
+                    ),
+                }
+                break;
             }
         });
     }
@@ -172,10 +260,28 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         link_resolver: Arc<dyn LinkResolver>,
         ipfs_service: IpfsService,
         static_filters: bool,
-        bus_sender: Option<UnboundedSender<BusMessage>>,
+        bus_router: Arc<BusRouter>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphInstanceManager", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
+        let catch_up = Arc::new(CatchUpScheduler::new(
+            CatchUpConfig::from_env(&env_vars),
+            metrics_registry.cheap_clone(),
+        ));
+        let offchain_limit = Arc::new(OffchainLimiter::new(
+            OffchainLimitConfig::from_env(&env_vars),
+            metrics_registry.cheap_clone(),
+        ));
+        let poi_reference = PoiReferenceConfig::from_env(&env_vars).map(|config| {
+            Arc::new(PoiReferenceChecker::new(
+                config,
+                metrics_registry.cheap_clone(),
+            ))
+        });
+        let stuck_watchdog = StuckWatchdogConfig::from_env(&env_vars)
+            .map(|config| Arc::new(StuckWatchdog::new(config, metrics_registry.cheap_clone())));
+
+        let synced_actions = Arc::new(SyncedActions::new(vec![], subgraph_store.clone()));
 
         SubgraphInstanceManager {
             logger_factory,
@@ -190,10 +296,31 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             ipfs_service,
             static_filters,
             env_vars,
-            bus_sender,
+            bus_router,
+            catch_up,
+            offchain_limit,
+            poi_reference,
+            stuck_watchdog,
+            synced_actions,
+            running: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    /// Make it possible to stop the runners that this manager starts with
+    /// `shutdown`
+    pub fn with_shutdown(mut self, shutdown: &RunnerShutdown) -> Self {
+        self.instances = shutdown.instances.cheap_clone();
+        self.running = shutdown.running.cheap_clone();
+        self
+    }
+
+    /// Run `actions` when a deployment reaches the chain head for the
+    /// first time
+    pub fn with_synced_actions(mut self, actions: Vec<SyncedAction>) -> Self {
+        self.synced_actions = Arc::new(SyncedActions::new(actions, self.subgraph_store.clone()));
+        self
+    }
+
     pub async fn build_subgraph_runner<C>(
         &self,
         logger: Logger,
@@ -217,7 +344,8 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             .await?;
 
         let raw_yaml = serde_yaml::to_string(&manifest).unwrap();
-        let manifest = UnresolvedSubgraphManifest::parse(deployment.hash.cheap_clone(), manifest)?;
+        let manifest = UnresolvedSubgraphManifest::parse(deployment.hash.cheap_clone(), manifest);
+        let manifest = fail_if_invalid(store.as_ref(), &deployment.hash, manifest).await?;
 
         // Allow for infinite retries for subgraph definition files.
         let link_resolver = Arc::from(self.link_resolver.with_retries());
@@ -242,13 +370,16 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
 
         info!(logger, "Resolve subgraph files using IPFS");
 
-        let mut manifest = manifest
+        let manifest = manifest
             .resolve(&link_resolver, &logger, ENV_VARS.max_spec_version.clone())
-            .await?;
+            .await;
+        let mut manifest = fail_if_invalid(store.as_ref(), &deployment.hash, manifest).await?;
 
         info!(logger, "Successfully resolved subgraph files using IPFS");
 
         let manifest_idx_and_name: Vec<(u32, String)> = manifest.template_idx_and_name().collect();
+        // The size of the manifest without any dynamic data sources
+        let manifest_size = manifest.size();
 
         // Start the subgraph deployment before reading dynamic data
         // sources; if the subgraph is a graft or a copy, starting it will
@@ -256,6 +387,49 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         // that is done
         store.start_subgraph_deployment(&logger).await?;
 
+        let network = manifest.network_name();
+
+        let chain = self
+            .chains
+            .get::<C>(network.clone())
+            .with_context(|| format!("no chain configured for network {}", network))?
+            .clone();
+
+        let host_export_hash = host_export_hash(&manifest, chain.runtime_adapter().as_ref())?;
+        store.record_node_version(host_export_hash.clone()).await?;
+        store
+            .record_bus_backends(self.bus_router.backends_for(&deployment).to_vec())
+            .await?;
+
+        let fingerprint = compatibility_fingerprint(&manifest, &host_export_hash);
+        if let Some(recorded) = store.check_compatibility(fingerprint.clone()).await? {
+            let changes = fingerprint.changes_from(&recorded).join(", ");
+            if ENV_VARS.strict_compatibility {
+                let message = format!(
+                    "the compatibility fingerprint of the deployment changed since it was \
+                     first started ({}); run `graphman compat ack {}` to accept the change",
+                    changes, deployment.hash
+                );
+                store
+                    .fail_subgraph(SubgraphError {
+                        subgraph_id: deployment.hash.clone(),
+                        message: message.clone(),
+                        block_ptr: store.block_ptr(),
+                        handler: None,
+                        deterministic: false,
+                        code: SubgraphErrorCode::Incompatible,
+                        provider: None,
+                    })
+                    .await?;
+                return Err(anyhow!(message));
+            }
+            warn!(
+                logger,
+                "The compatibility fingerprint of the deployment changed since it was first started";
+                "changes" => changes
+            );
+        }
+
         // Dynamic data sources are loaded by appending them to the manifest.
         //
         // Refactor: Preferrably we'd avoid any mutation of the manifest.
@@ -287,13 +461,6 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             .filter_map(|d| d.as_onchain().cloned())
             .collect::<Vec<_>>();
         let required_capabilities = C::NodeCapabilities::from_data_sources(&onchain_data_sources);
-        let network = manifest.network_name();
-
-        let chain = self
-            .chains
-            .get::<C>(network.clone())
-            .with_context(|| format!("no chain configured for network {}", network))?
-            .clone();
 
         // if static_filters is enabled, build a minimal filter with the static data sources and
         // add the necessary filters based on templates.
@@ -357,6 +524,7 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             &deployment,
             stopwatch_metrics.clone(),
         ));
+        subgraph_metrics.set_manifest_size(manifest_size);
 
         let block_stream_metrics = Arc::new(BlockStreamMetrics::new(
             registry.cheap_clone(),
@@ -378,14 +546,50 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         let deployment_head = store.block_ptr().map(|ptr| ptr.number).unwrap_or(0) as f64;
         block_stream_metrics.deployment_head.set(deployment_head);
 
+        // Mappings can not load immutable entity types with a retention
+        // policy since which of their rows still exist depends on when the
+        // retention job ran; the rows of mutable entity types that it
+        // removes are never current and therefore never loaded
+        let retained_entity_types: BTreeSet<_> = store
+            .start_entity_retention()
+            .await?
+            .into_iter()
+            .map(|retention| EntityType::new(retention.entity_type))
+            .filter(|entity_type| manifest.schema.is_immutable(entity_type))
+            .collect();
+
+        // The manifest can not have such invariants, but `graphman retention`
+        // can add a policy after the deployment was created; the aggregates
+        // the invariant needs can not be kept up to date when current rows
+        // are removed
+        for invariant in &manifest.invariants {
+            if let Some(entity_type) = invariant
+                .entity_types()
+                .find(|entity_type| retained_entity_types.contains(&EntityType::from(*entity_type)))
+            {
+                return Err(anyhow!(
+                    "the invariant `{}` uses the immutable entity type `{}` which has a \
+                     retention policy; remove the policy with `graphman retention`",
+                    invariant.name,
+                    entity_type
+                ));
+            }
+        }
+
+        let bus_sender = self.bus_router.publisher(&deployment);
         let host_builder = graph_runtime_wasm::RuntimeHostBuilder::new(
             chain.runtime_adapter(),
             self.link_resolver.cheap_clone(),
             subgraph_store.ens_lookup(),
-            self.bus_sender.clone(),
+            bus_sender.clone(),
+            manifest
+                .features
+                .contains(&SubgraphFeature::DeclaredEntities),
+            Arc::new(retained_entity_types),
         );
 
         let features = manifest.features.clone();
+        let invariants = manifest.invariants.clone();
         let unified_api_version = manifest.unified_mapping_api_version()?;
         let poi_version = if manifest.spec_version.ge(&SPEC_VERSION_0_0_6) {
             ProofOfIndexingVersion::Fast
@@ -396,6 +600,19 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         let causality_region_seq =
             CausalityRegionSeq::from_current(store.causality_region_curr_val().await?);
 
+        let reorg_threshold = store.reorg_threshold().await?;
+
+        let bus_critical = CriticalTypes::new(
+            store.bus_critical_entity_types().await?,
+            store.cheap_clone(),
+        );
+
+        let compile_profile = store
+            .compile_profile()
+            .await?
+            .unwrap_or(env_vars.mappings.compile_profile);
+        host_metrics.wasm_performance.reset();
+
         let instance = super::context::instance::SubgraphInstance::from_manifest(
             &logger,
             manifest,
@@ -403,8 +620,34 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             host_metrics.clone(),
             &mut offchain_monitor,
             causality_region_seq,
+            compile_profile,
         )?;
 
+        if let Some((profile, compile_time)) = host_metrics.wasm_performance.compilation() {
+            info!(logger, "Compiled WASM modules";
+                "profile" => profile.as_str(),
+                "time_ms" => compile_time.as_millis());
+            store.record_compilation(profile, compile_time).await?;
+        }
+
+        if let Some(poi_reference) = &self.poi_reference {
+            poi_reference.spawn(
+                logger.cheap_clone(),
+                deployment.clone(),
+                Arc::downgrade(&store),
+            );
+        }
+        if let Some(stuck_watchdog) = &self.stuck_watchdog {
+            stuck_watchdog.spawn(
+                logger.cheap_clone(),
+                deployment.clone(),
+                Arc::downgrade(&store),
+                chain.chain_store(),
+                start_blocks.iter().copied().min().unwrap_or(0),
+                bus_sender.clone(),
+            );
+        }
+
         let inputs = IndexingInputs {
             deployment: deployment.clone(),
             features,
@@ -420,6 +663,13 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             poi_version,
             network,
             manifest_idx_and_name,
+            bus_sender,
+            bus_critical,
+            catch_up: self.catch_up.cheap_clone(),
+            offchain_limit: self.offchain_limit.cheap_clone(),
+            synced_actions: self.synced_actions.cheap_clone(),
+            invariants,
+            reorg_threshold,
         };
 
         // The subgraph state tracks the state of the subgraph instance over time
@@ -457,6 +707,10 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
     {
         let registry = self.metrics_registry.cheap_clone();
         let subgraph_metrics_unregister = runner.metrics.subgraph.cheap_clone();
+        let host_metrics_unregister = runner.metrics.host.cheap_clone();
+        let polling_metrics_unregister = runner.polling_monitor_metrics();
+        let running = self.running.cheap_clone();
+        running.fetch_add(1, Ordering::SeqCst);
 
         // Keep restarting the subgraph until it terminates. The subgraph
         // will usually only run once, but is restarted whenever a block
@@ -479,9 +733,41 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
                     format!("{:#}", e)
                 );
             }
-            subgraph_metrics_unregister.unregister(registry);
+            running.fetch_sub(1, Ordering::SeqCst);
+            subgraph_metrics_unregister.unregister(registry.cheap_clone());
+            host_metrics_unregister.unregister(registry.cheap_clone());
+            polling_metrics_unregister.unregister(registry);
         });
 
         Ok(())
     }
 }
+
+/// Pass `manifest` through, but mark the deployment as failed if the
+/// manifest is invalid so that the failure shows up in the indexing status
+/// and not just in the logs. Errors resolving the manifest, which are
+/// often caused by IPFS or the network, do not fail the deployment so that
+/// it can be started again once the files can be resolved
+async fn fail_if_invalid<M>(
+    store: &dyn WritableStore,
+    deployment: &DeploymentHash,
+    manifest: Result<M, SubgraphManifestResolveError>,
+) -> anyhow::Result<M> {
+    let e = match manifest {
+        Ok(manifest) => return Ok(manifest),
+        Err(e) if !e.is_invalid_manifest() => return Err(e.into()),
+        Err(e) => e,
+    };
+    store
+        .fail_subgraph(SubgraphError {
+            subgraph_id: deployment.clone(),
+            message: format!("{:#}", e).replace('\n', "\t"),
+            block_ptr: store.block_ptr(),
+            handler: None,
+            deterministic: false,
+            code: SubgraphErrorCode::ManifestInvalid,
+            provider: None,
+        })
+        .await?;
+    Err(e.into())
+}
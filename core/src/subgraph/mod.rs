@@ -1,17 +1,27 @@
+mod catch_up;
 mod context;
 mod error;
+mod fingerprint;
 mod inputs;
 mod instance_manager;
 mod loader;
+mod offchain_limit;
+mod poi_reference;
 mod provider;
 mod registrar;
 mod runner;
+mod safe_mode;
 mod state;
 mod stream;
+mod synced_actions;
 mod trigger_processor;
+mod watchdog;
 
-pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::instance_manager::{RunnerShutdown, SubgraphInstanceManager};
+pub use self::loader::load_dynamic_data_sources;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
 pub use self::runner::SubgraphRunner;
+pub use self::safe_mode::{SafeModeConfig, SafeModeRampUp};
+pub use self::synced_actions::{SyncedAction, SyncedActionKind};
 pub use self::trigger_processor::*;
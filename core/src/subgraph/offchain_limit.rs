@@ -0,0 +1,249 @@
+//! Limit how many offchain triggers, like those of file data sources, the
+//! node processes at the same time across all deployments.
+//!
+//! When a popular IPFS file becomes available, the file data sources of
+//! many deployments can fire at once. Only `concurrency` offchain triggers
+//! are processed at the same time; deployments wait for a permit in the
+//! order in which they asked for it. A deployment keeps its permit for at
+//! most `share` triggers in a row and then lines up again behind the
+//! deployments that are waiting, so that a deployment with many triggers
+//! can not hold up the others. Onchain triggers never need a permit.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::env::EnvVars;
+use graph::prelude::{Gauge, Histogram, MetricsRegistry};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub struct OffchainLimitConfig {
+    /// The number of offchain triggers that can be processed at the same
+    /// time; 0 means no limit
+    pub concurrency: usize,
+    /// The number of offchain triggers a deployment can process in a row
+    /// before it has to let waiting deployments go first
+    pub share: usize,
+}
+
+impl OffchainLimitConfig {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        OffchainLimitConfig {
+            concurrency: env_vars.offchain_trigger_concurrency,
+            share: env_vars.offchain_trigger_share,
+        }
+    }
+}
+
+pub struct OffchainLimiter {
+    share: usize,
+    /// `None` if the number of offchain triggers is not limited
+    semaphore: Option<Arc<Semaphore>>,
+    executing: Gauge,
+    wait_time: Box<Histogram>,
+}
+
+impl OffchainLimiter {
+    pub fn new(config: OffchainLimitConfig, registry: Arc<dyn MetricsRegistry>) -> Self {
+        let executing = registry
+            .global_gauge(
+                "offchain_triggers_executing",
+                "The number of offchain triggers that are being processed",
+                Default::default(),
+            )
+            .expect("failed to create `offchain_triggers_executing` gauge");
+        let wait_time = registry
+            .new_histogram(
+                "offchain_trigger_permit_wait_secs",
+                "How long deployments waited for permission to process offchain triggers",
+                vec![0.001, 0.01, 0.1, 1.0, 10.0, 60.0],
+            )
+            .expect("failed to create `offchain_trigger_permit_wait_secs` histogram");
+        Self::with_metrics(config, executing, wait_time)
+    }
+
+    fn with_metrics(
+        config: OffchainLimitConfig,
+        executing: Gauge,
+        wait_time: Box<Histogram>,
+    ) -> Self {
+        let semaphore = match config.concurrency {
+            0 => None,
+            concurrency => Some(Arc::new(Semaphore::new(concurrency))),
+        };
+        OffchainLimiter {
+            share: config.share.max(1),
+            semaphore,
+            executing,
+            wait_time,
+        }
+    }
+
+    /// Start processing a batch of offchain triggers for one deployment.
+    /// The turn holds on to a permit between triggers; dropping it lets
+    /// other deployments have the permit
+    pub fn turn(self: &Arc<Self>) -> OffchainTurn {
+        OffchainTurn {
+            limiter: self.clone(),
+            permit: None,
+            used: 0,
+        }
+    }
+}
+
+/// The offchain triggers that one deployment processes in a block
+pub struct OffchainTurn {
+    limiter: Arc<OffchainLimiter>,
+    permit: Option<OwnedSemaphorePermit>,
+    /// How many triggers were processed with `permit`
+    used: usize,
+}
+
+impl OffchainTurn {
+    /// Wait until the deployment may process its next offchain trigger.
+    /// The trigger counts as being processed until the returned guard is
+    /// dropped
+    pub async fn execute(&mut self) -> Executing {
+        let limiter = self.limiter.clone();
+        if let Some(semaphore) = &limiter.semaphore {
+            if self.used >= limiter.share {
+                // Line up behind the deployments that are waiting
+                self.permit = None;
+            }
+            if self.permit.is_none() {
+                let start = Instant::now();
+                let permit = semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the offchain trigger semaphore is never closed");
+                limiter.wait_time.observe(start.elapsed().as_secs_f64());
+                self.permit = Some(permit);
+                self.used = 0;
+            }
+            self.used += 1;
+        }
+        limiter.executing.inc();
+        Executing {
+            executing: limiter.executing.clone(),
+        }
+    }
+}
+
+/// Counts an offchain trigger as being processed while it is alive
+pub struct Executing {
+    executing: Gauge,
+}
+
+impl Drop for Executing {
+    fn drop(&mut self) {
+        self.executing.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use graph::prelude::{HistogramOpts, Opts};
+
+    use super::*;
+
+    fn limiter(concurrency: usize, share: usize) -> Arc<OffchainLimiter> {
+        let config = OffchainLimitConfig { concurrency, share };
+        let executing = Gauge::with_opts(Opts::new("executing", "executing")).unwrap();
+        let wait_time = Histogram::with_opts(HistogramOpts::new("wait", "wait")).unwrap();
+        Arc::new(OffchainLimiter::with_metrics(
+            config,
+            executing,
+            Box::new(wait_time),
+        ))
+    }
+
+    /// Simulate `deployments` deployments whose `triggers` file triggers
+    /// all arrive at the same time. Returns the most triggers that were
+    /// processed at the same time and the deployments in the order in
+    /// which their triggers were processed
+    async fn simulate(
+        limiter: Arc<OffchainLimiter>,
+        deployments: usize,
+        triggers: usize,
+    ) -> (usize, Vec<usize>) {
+        let current = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let tasks: Vec<_> = (0..deployments)
+            .map(|deployment| {
+                let limiter = limiter.clone();
+                let current = current.clone();
+                let most = most.clone();
+                let order = order.clone();
+                tokio::spawn(async move {
+                    let mut turn = limiter.turn();
+                    for _ in 0..triggers {
+                        let _executing = turn.execute().await;
+                        let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        order.lock().unwrap().push(deployment);
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                        current.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(0, limiter.executing.get() as usize);
+        let order = order.lock().unwrap().clone();
+        (most.load(Ordering::SeqCst), order)
+    }
+
+    #[tokio::test]
+    async fn cap_holds() {
+        let (most, order) = simulate(limiter(3, 2), 20, 5).await;
+        assert!(most <= 3, "{} triggers were processed at once", most);
+        assert_eq!(100, order.len());
+    }
+
+    #[tokio::test]
+    async fn unlimited() {
+        let (most, order) = simulate(limiter(0, 2), 10, 3).await;
+        assert!(most > 1);
+        assert_eq!(30, order.len());
+    }
+
+    #[tokio::test]
+    async fn deployments_take_turns() {
+        const SHARE: usize = 2;
+
+        let (most, order) = simulate(limiter(1, SHARE), 4, 10).await;
+        assert_eq!(1, most);
+
+        // While other deployments still have triggers, no deployment
+        // processes more than its share of triggers in a row
+        let mut remaining = vec![10; 4];
+        let mut run = 0;
+        let mut last = None;
+        for deployment in order {
+            run = if last == Some(deployment) { run + 1 } else { 1 };
+            last = Some(deployment);
+            remaining[deployment] -= 1;
+            let others_waiting = remaining
+                .iter()
+                .enumerate()
+                .any(|(other, count)| other != deployment && *count > 0);
+            if others_waiting {
+                assert!(
+                    run <= SHARE,
+                    "deployment {} processed {} triggers in a row",
+                    deployment,
+                    run
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Compare the PoI of running deployments with the PoI that reference
+//! indexers report for the same block.
+//!
+//! Every `interval`, the checker for a deployment asks each reference
+//! indexer for the public PoI at the block that the deployment had reached
+//! at the previous check, so that the block has been written and the
+//! reference indexers had time to reach it, too. When enough of them
+//! report a different PoI, the divergence is logged, exported as a metric
+//! and recorded in the store. Nothing here ever affects indexing; errors
+//! are only logged.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use graph::components::store::{DeploymentLocator, WritableStore};
+use graph::data::subgraph::status::PoiDivergence;
+use graph::env::{EnvVars, ENV_VARS};
+use graph::prelude::{
+    anyhow, async_trait, hex, info, reqwest, serde_json, warn, BlockNumber, BlockPtr, CheapClone,
+    CounterVec, DeploymentHash, Error, GaugeVec, Logger, MetricsRegistry,
+};
+use graph::util::proxy::ProxyTarget;
+
+/// Never wait longer than `interval * 2^MAX_BACKOFF_EXPONENT` before asking
+/// an endpoint that failed again
+const MAX_BACKOFF_EXPONENT: u32 = 6;
+
+pub struct PoiReferenceConfig {
+    pub endpoints: Vec<String>,
+    pub interval: Duration,
+    /// The number of reference indexers that must report a different PoI;
+    /// 0 means a majority of the endpoints
+    pub quorum: usize,
+}
+
+impl PoiReferenceConfig {
+    /// Return `None` if no reference indexers are configured
+    pub fn from_env(env_vars: &EnvVars) -> Option<Self> {
+        if env_vars.poi_reference_endpoints.is_empty() {
+            return None;
+        }
+        Some(PoiReferenceConfig {
+            endpoints: env_vars.poi_reference_endpoints.clone(),
+            interval: env_vars.poi_reference_interval,
+            quorum: env_vars.poi_reference_quorum,
+        })
+    }
+}
+
+/// Somewhere to get the public PoI of a deployment from
+#[async_trait]
+trait PoiSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The public PoI of `deployment` at `block`, or `None` if the source
+    /// does not have it
+    async fn public_poi(
+        &self,
+        deployment: &DeploymentHash,
+        block: BlockNumber,
+    ) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// The index node API of another indexer
+struct IndexNodePoiSource {
+    url: String,
+    client: reqwest::Client,
+}
+
+const PUBLIC_POI_QUERY: &str = "query ($requests: [PublicProofOfIndexingRequest!]!) { \
+                                  publicProofsOfIndexing(requests: $requests) { proofOfIndexing } \
+                                }";
+
+#[async_trait]
+impl PoiSource for IndexNodePoiSource {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn public_poi(
+        &self,
+        deployment: &DeploymentHash,
+        block: BlockNumber,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let body = serde_json::json!({
+            "query": PUBLIC_POI_QUERY,
+            "variables": {
+                "requests": [{
+                    "deployment": deployment.as_str(),
+                    "blockNumber": block.to_string(),
+                }]
+            }
+        });
+        let response: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if let Some(errors) = response.get("errors") {
+            return Err(anyhow!("the reference indexer returned errors: {}", errors));
+        }
+        let poi = response
+            .pointer("/data/publicProofsOfIndexing/0/proofOfIndexing")
+            .and_then(|poi| poi.as_str());
+        match poi {
+            None => Ok(None),
+            Some(poi) => Ok(Some(hex::decode(poi.trim_start_matches("0x"))?)),
+        }
+    }
+}
+
+/// The outcome of comparing the local PoI with the reference indexers
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Verdict {
+    Agree,
+    /// At least a quorum of the reference indexers reported a different
+    /// PoI; `reference` is the one that most of them reported
+    Diverged {
+        reference: Vec<u8>,
+    },
+    /// Too few reference indexers answered to tell
+    Undecided,
+}
+
+/// Compare `local` with the PoIs that the reference indexers reported.
+/// Indexers that did not answer do not count towards the quorum
+fn verdict(local: &[u8], answers: &[Vec<u8>], endpoints: usize, quorum: usize) -> Verdict {
+    let quorum = match quorum {
+        0 => endpoints / 2 + 1,
+        quorum => quorum,
+    };
+
+    let mut differing: HashMap<&[u8], usize> = HashMap::new();
+    for answer in answers.iter().filter(|answer| answer.as_slice() != local) {
+        *differing.entry(answer.as_slice()).or_default() += 1;
+    }
+    let disagree: usize = differing.values().sum();
+
+    if disagree >= quorum {
+        // Among equal counts, pick the smallest PoI so that the verdict
+        // does not depend on the order of the answers
+        let reference = differing
+            .into_iter()
+            .max_by(|(poi1, count1), (poi2, count2)| count1.cmp(count2).then(poi2.cmp(poi1)))
+            .map(|(poi, _)| poi.to_vec())
+            .unwrap();
+        Verdict::Diverged { reference }
+    } else if answers.len() - disagree >= quorum {
+        Verdict::Agree
+    } else {
+        Verdict::Undecided
+    }
+}
+
+/// Tracks failures of an endpoint so that it is asked less often while it
+/// keeps failing
+#[derive(Default)]
+struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    fn ready(&self, now: Instant) -> bool {
+        self.retry_at.map_or(true, |retry_at| now >= retry_at)
+    }
+
+    fn failed(&mut self, now: Instant, interval: Duration) {
+        self.failures += 1;
+        self.retry_at = Some(now + interval * (1 << self.failures.min(MAX_BACKOFF_EXPONENT)));
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+pub struct PoiReferenceChecker {
+    config: PoiReferenceConfig,
+    sources: Vec<Box<dyn PoiSource>>,
+    backoff: Mutex<HashMap<String, Backoff>>,
+    divergence: Box<GaugeVec>,
+    errors: Box<CounterVec>,
+}
+
+impl PoiReferenceChecker {
+    pub fn new(config: PoiReferenceConfig, registry: Arc<dyn MetricsRegistry>) -> Self {
+        let divergence = registry
+            .new_gauge_vec(
+                "deployment_poi_divergence",
+                "Whether the PoI of a deployment differs from the reference indexers",
+                vec![String::from("deployment")],
+            )
+            .expect("failed to create `deployment_poi_divergence` gauge");
+        let errors = registry
+            .new_counter_vec(
+                "deployment_poi_reference_errors",
+                "Counts failed requests to a reference indexer for PoIs",
+                vec![String::from("deployment"), String::from("endpoint")],
+            )
+            .expect("failed to create `deployment_poi_reference_errors` counter");
+        let client = ENV_VARS
+            .proxies
+            .http_client(ProxyTarget::Other)
+            .build()
+            .expect("failed to create the client for reference indexers");
+        let sources = config
+            .endpoints
+            .iter()
+            .map(|url| {
+                Box::new(IndexNodePoiSource {
+                    url: url.clone(),
+                    client: client.clone(),
+                }) as Box<dyn PoiSource>
+            })
+            .collect();
+
+        PoiReferenceChecker {
+            config,
+            sources,
+            backoff: Mutex::new(HashMap::new()),
+            divergence,
+            errors,
+        }
+    }
+
+    /// Compare the PoI of `deployment` with the reference indexers until
+    /// `store` is dropped, i.e., until the deployment is stopped
+    pub fn spawn(
+        self: &Arc<Self>,
+        logger: Logger,
+        deployment: DeploymentLocator,
+        store: Weak<dyn WritableStore>,
+    ) {
+        let checker = self.cheap_clone();
+        graph::spawn(async move {
+            let mut previous: Option<BlockPtr> = None;
+            loop {
+                tokio::time::sleep(checker.config.interval).await;
+
+                let store = match store.upgrade() {
+                    Some(store) => store,
+                    None => break,
+                };
+                let current = store.block_ptr();
+                // Check the block the deployment had reached at the
+                // previous check, unless it has not moved since then
+                if let Some(block) = previous
+                    .take()
+                    .filter(|block| Some(block) != current.as_ref())
+                {
+                    if let Err(e) = checker.check(&logger, &deployment, &*store, block).await {
+                        warn!(logger, "Failed to compare the PoI with the reference indexers";
+                            "error" => format!("{:#}", e));
+                    }
+                }
+                previous = current;
+            }
+            checker
+                .divergence
+                .remove_label_values(&[deployment.hash.as_str()])
+                .ok();
+        });
+    }
+
+    async fn check(
+        &self,
+        logger: &Logger,
+        deployment: &DeploymentLocator,
+        store: &dyn WritableStore,
+        block: BlockPtr,
+    ) -> Result<(), Error> {
+        let local = match store.public_proof_of_indexing(block.clone()).await? {
+            Some(local) => local,
+            None => return Ok(()),
+        };
+        let answers = self
+            .reference_pois(logger, &deployment.hash, block.number)
+            .await;
+
+        match verdict(
+            &local,
+            &answers,
+            self.config.endpoints.len(),
+            self.config.quorum,
+        ) {
+            Verdict::Agree => {
+                self.set_diverged(&deployment.hash, false);
+                store.record_poi_divergence(None).await?;
+            }
+            Verdict::Diverged { reference } => {
+                self.set_diverged(&deployment.hash, true);
+                warn!(logger, "The PoI differs from the reference indexers";
+                    "block" => format!("{}", block),
+                    "local_poi" => format!("0x{}", hex::encode(local)),
+                    "reference_poi" => format!("0x{}", hex::encode(&reference)));
+                let divergence = PoiDivergence {
+                    block: block.number,
+                    local_poi: local.to_vec(),
+                    reference_poi: reference,
+                };
+                store.record_poi_divergence(Some(divergence)).await?;
+            }
+            Verdict::Undecided => {}
+        }
+        Ok(())
+    }
+
+    /// The PoIs that the reference indexers that are not backing off
+    /// report for `deployment` at `block`
+    async fn reference_pois(
+        &self,
+        logger: &Logger,
+        deployment: &DeploymentHash,
+        block: BlockNumber,
+    ) -> Vec<Vec<u8>> {
+        let mut answers = Vec::new();
+        for source in &self.sources {
+            let ready = self
+                .backoff
+                .lock()
+                .unwrap()
+                .entry(source.name().to_string())
+                .or_default()
+                .ready(Instant::now());
+            if !ready {
+                continue;
+            }
+
+            let result = source.public_poi(deployment, block).await;
+            let mut backoff = self.backoff.lock().unwrap();
+            let backoff = backoff.entry(source.name().to_string()).or_default();
+            match result {
+                Ok(poi) => {
+                    backoff.succeeded();
+                    answers.extend(poi);
+                }
+                Err(e) => {
+                    backoff.failed(Instant::now(), self.config.interval);
+                    self.errors
+                        .with_label_values(&[deployment.as_str(), source.name()])
+                        .inc();
+                    info!(logger, "Failed to get the PoI from a reference indexer";
+                        "endpoint" => source.name(),
+                        "failures" => backoff.failures,
+                        "error" => format!("{:#}", e));
+                }
+            }
+        }
+        answers
+    }
+
+    fn set_diverged(&self, deployment: &DeploymentHash, diverged: bool) {
+        self.divergence
+            .with_label_values(&[deployment.as_str()])
+            .set(if diverged { 1.0 } else { 0.0 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdicts() {
+        let local = vec![1u8];
+        let (a, b) = (vec![2u8], vec![3u8]);
+
+        // With a majority of three endpoints
+        assert_eq!(
+            Verdict::Agree,
+            verdict(&local, &[local.clone(), local.clone(), local.clone()], 3, 0)
+        );
+        assert_eq!(
+            Verdict::Agree,
+            verdict(&local, &[local.clone(), local.clone(), a.clone()], 3, 0)
+        );
+        assert_eq!(
+            Verdict::Diverged {
+                reference: a.clone()
+            },
+            verdict(&local, &[a.clone(), a.clone(), local.clone()], 3, 0)
+        );
+        assert_eq!(
+            Verdict::Diverged {
+                reference: a.clone()
+            },
+            verdict(&local, &[b.clone(), a.clone(), a.clone()], 3, 0)
+        );
+        // Ties go to the smallest PoI
+        assert_eq!(
+            Verdict::Diverged {
+                reference: a.clone()
+            },
+            verdict(&local, &[b.clone(), a.clone()], 3, 0)
+        );
+        // Endpoints that did not answer do not count
+        assert_eq!(
+            Verdict::Undecided,
+            verdict(&local, &[local.clone(), a.clone()], 3, 0)
+        );
+        assert_eq!(Verdict::Undecided, verdict(&local, &[], 3, 0));
+
+        // With an explicit quorum
+        assert_eq!(
+            Verdict::Diverged {
+                reference: a.clone()
+            },
+            verdict(&local, &[a.clone(), local.clone(), local.clone()], 3, 1)
+        );
+        assert_eq!(Verdict::Agree, verdict(&local, &[local.clone()], 3, 1));
+    }
+
+    #[test]
+    fn backoff() {
+        let interval = Duration::from_secs(10);
+        let now = Instant::now();
+        let mut backoff = Backoff::default();
+        assert!(backoff.ready(now));
+
+        backoff.failed(now, interval);
+        assert!(!backoff.ready(now + Duration::from_secs(19)));
+        assert!(backoff.ready(now + Duration::from_secs(20)));
+
+        for _ in 0..100 {
+            backoff.failed(now, interval);
+        }
+        assert!(backoff.ready(now + interval * (1 << MAX_BACKOFF_EXPONENT)));
+
+        backoff.succeeded();
+        assert!(backoff.ready(now));
+    }
+}
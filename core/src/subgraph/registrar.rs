@@ -2,6 +2,7 @@ use std::collections::HashSet;
 use std::time::Instant;
 
 use async_trait::async_trait;
+use graph::blockchain::validate_reorg_threshold;
 use graph::blockchain::Blockchain;
 use graph::blockchain::BlockchainKind;
 use graph::blockchain::BlockchainMap;
@@ -13,6 +14,8 @@ use graph::prelude::{
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
 
+use super::safe_mode::SafeModeRampUp;
+
 pub struct SubgraphRegistrar<P, S, SM> {
     logger: Logger,
     logger_factory: LoggerFactory,
@@ -24,6 +27,7 @@ pub struct SubgraphRegistrar<P, S, SM> {
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
+    safe_mode: Option<Arc<SafeModeRampUp>>,
 }
 
 impl<P, S, SM> SubgraphRegistrar<P, S, SM>
@@ -58,9 +62,17 @@ where
             node_id,
             version_switching_mode,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
+            safe_mode: None,
         }
     }
 
+    /// Start the assigned deployments in waves with `safe_mode` instead of
+    /// all at once
+    pub fn with_safe_mode(mut self, safe_mode: SafeModeRampUp) -> Self {
+        self.safe_mode = Some(Arc::new(safe_mode));
+        self
+    }
+
     pub fn start(&self) -> impl Future<Item = (), Error = Error> {
         let logger_clone1 = self.logger.clone();
         let logger_clone2 = self.logger.clone();
@@ -214,10 +226,35 @@ where
         let provider = self.provider.clone();
         let logger = self.logger.clone();
         let node_id = self.node_id.clone();
+        let store = self.store.clone();
+        let safe_mode = self.safe_mode.clone();
 
         future::result(self.store.assignments(&self.node_id))
             .map_err(|e| anyhow!("Error querying subgraph assignments: {}", e))
             .and_then(move |deployments| {
+                // In safe mode, the ramp-up runs in the background so that
+                // assignment events are processed in the meantime; that
+                // makes it possible to start deployments out of turn with
+                // `graphman force-start`
+                if let Some(safe_mode) = safe_mode {
+                    let deployments =
+                        Vec::from_iter(HashSet::<DeploymentLocator>::from_iter(deployments));
+                    graph::spawn(async move {
+                        safe_mode
+                            .run(deployments, |deployment| {
+                                start_assigned_subgraph(
+                                    deployment,
+                                    store.clone(),
+                                    node_id.clone(),
+                                    provider.clone(),
+                                    logger.clone(),
+                                )
+                            })
+                            .await
+                    });
+                    return future::Either::A(future::ok(()));
+                }
+
                 // This operation should finish only after all subgraphs are
                 // started. We wait for the spawned tasks to complete by giving
                 // each a `sender` and waiting for all of them to be dropped, so
@@ -234,11 +271,11 @@ where
                     );
                 }
                 drop(sender);
-                receiver.collect().then(move |_| {
+                future::Either::B(receiver.collect().then(move |_| {
                     info!(logger, "Started all assigned subgraphs";
                                   "count" => deployments_len, "node_id" => &node_id);
                     future::ok(())
-                })
+                }))
             })
     }
 }
@@ -494,6 +531,25 @@ async fn start_subgraph(
     }
 }
 
+/// Start `deployment` if it is still assigned to `node_id`; it might have
+/// been reassigned or removed while it waited for its turn
+async fn start_assigned_subgraph(
+    deployment: DeploymentLocator,
+    store: Arc<impl SubgraphStore>,
+    node_id: NodeId,
+    provider: Arc<impl SubgraphAssignmentProviderTrait>,
+    logger: Logger,
+) {
+    match store.assigned_node(&deployment) {
+        Ok(Some(node)) if node == node_id => start_subgraph(deployment, provider, logger).await,
+        Ok(_) => debug!(logger, "Deployment is no longer assigned to this node, not starting it";
+                        "subgraph_id" => deployment.hash.to_string()),
+        Err(e) => error!(logger, "Failed to check the assignment of a deployment";
+                         "subgraph_id" => deployment.hash.to_string(),
+                         "error" => e.to_string()),
+    }
+}
+
 /// Resolves the subgraph's earliest block
 async fn resolve_start_block(
     manifest: &SubgraphManifest<impl Blockchain>,
@@ -578,6 +634,14 @@ async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
         .map_err(SubgraphRegistrarError::NetworkNotSupported)?
         .cheap_clone();
 
+    if let Some(reorg_threshold) = manifest.reorg_threshold {
+        validate_reorg_threshold(reorg_threshold, chain.reorg_threshold_range()).map_err(|e| {
+            SubgraphRegistrarError::ManifestValidationError(vec![
+                SubgraphManifestValidationError::ReorgThresholdInvalid(e),
+            ])
+        })?;
+    }
+
     let logger = logger.clone();
     let store = store.clone();
     let deployment_store = store.clone();
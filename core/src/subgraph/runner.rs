@@ -1,12 +1,23 @@
+use crate::polling_monitor::PollingMonitorMetrics;
 use crate::subgraph::context::IndexingContext;
 use crate::subgraph::error::BlockProcessingError;
 use crate::subgraph::inputs::IndexingInputs;
 use crate::subgraph::state::IndexingState;
 use crate::subgraph::stream::new_block_stream;
+use crate::subgraph::synced_actions::{SyncedAction, SyncedEvent};
 use atomic_refcell::AtomicRefCell;
 use graph::blockchain::block_stream::{BlockStreamEvent, BlockWithTriggers, FirehoseCursor};
 use graph::blockchain::{Block, Blockchain, DataSource as _, TriggerFilter as _};
+use graph::components::bus::{BusMessage, BusPayload, EntityCounts};
+use graph::components::metrics::usage::{remove_usage_tracker, usage_tracker};
 use graph::components::store::{EmptyStore, EntityKey, StoredDynamicDataSource};
+use graph::components::subgraph::activity::{remove_deployment_activity, StreamState};
+use graph::components::subgraph::handler_entity_types::handler_entity_types;
+use graph::components::subgraph::handler_stats::{
+    handler_stats_tracker, remove_handler_stats_tracker,
+};
+use graph::components::subgraph::mapping_terminations::remove_mapping_terminations;
+use graph::components::subgraph::wasm_performance::remove_wasm_performance;
 use graph::components::{
     store::ModificationsAndCache,
     subgraph::{MappingError, PoICausalityRegion, ProofOfIndexing, SharedProofOfIndexing},
@@ -22,13 +33,17 @@ use graph::data_source::{
 use graph::env::EnvVars;
 use graph::prelude::*;
 use graph::util::{backoff::ExponentialBackoff, lfu_cache::LfuCache};
+use std::collections::BTreeSet;
 use std::env;
+use std::fmt;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 const MINUTE: Duration = Duration::from_secs(60);
 
 const SKIP_PTR_UPDATES_THRESHOLD: Duration = Duration::from_secs(60 * 5);
+const STORE_UNAVAILABLE_RETRY_BASE: Duration = Duration::from_secs(1);
+const STORE_UNAVAILABLE_RETRY_CEIL: Duration = Duration::from_secs(30);
 
 pub struct SubgraphRunner<C, T>
 where
@@ -65,7 +80,12 @@ where
                     (MINUTE * 2).min(env_vars.subgraph_error_retry_ceil),
                     env_vars.subgraph_error_retry_ceil,
                 ),
+                store_backoff: ExponentialBackoff::new(
+                    STORE_UNAVAILABLE_RETRY_BASE,
+                    STORE_UNAVAILABLE_RETRY_CEIL,
+                ),
                 entity_lfu_cache: LfuCache::new(),
+                last_usage_flush: Instant::now(),
             },
             logger,
             metrics,
@@ -91,21 +111,57 @@ where
         Ok(())
     }
 
+    /// The metrics of the monitor that polls for offchain data
+    pub fn polling_monitor_metrics(&self) -> PollingMonitorMetrics {
+        self.ctx.offchain_monitor.metrics().clone()
+    }
+
     #[cfg(debug_assertions)]
     pub fn context(&self) -> &IndexingContext<C, T> {
         &self.ctx
     }
 
     #[cfg(debug_assertions)]
-    pub async fn run_for_test(self, break_on_restart: bool) -> Result<Self, Error> {
-        self.run_inner(break_on_restart).await
+    pub async fn run_for_test(mut self, break_on_restart: bool) -> Result<Self, Error> {
+        let result = self.run_inner(break_on_restart).await;
+        // Breaking on a restart leaves the runner ready to run again
+        if !break_on_restart || result.is_err() {
+            self.release_observations(result.is_err()).await;
+        }
+        result.map(|()| self)
     }
 
-    pub async fn run(self) -> Result<Self, Error> {
-        self.run_inner(false).await
+    pub async fn run(mut self) -> Result<Self, Error> {
+        let result = self.run_inner(false).await;
+        self.release_observations(result.is_err()).await;
+        result.map(|()| self)
     }
 
-    async fn run_inner(mut self, break_on_restart: bool) -> Result<Self, Error> {
+    /// Forget the per-deployment trackers once the runner exits, however
+    /// it exits, so that they do not accumulate for deployments that no
+    /// longer run on this node. When the runner failed, what the trackers
+    /// hold has not been written yet and we try to write it first
+    async fn release_observations(&mut self, failed: bool) {
+        if failed {
+            self.flush_observations().await;
+        }
+        let deployment = self.inputs.deployment.hash.as_str();
+        let dropped = remove_usage_tracker(deployment);
+        if !dropped.is_empty() {
+            warn!(self.logger, "Dropping usage that could not be written";
+                  "resources" => dropped.iter().count());
+        }
+        remove_wasm_performance(deployment);
+        remove_mapping_terminations(deployment);
+        remove_deployment_activity(deployment);
+        let dropped = remove_handler_stats_tracker(deployment);
+        if !dropped.is_empty() {
+            warn!(self.logger, "Dropping handler statistics that could not be written";
+                  "handlers" => dropped.len());
+        }
+    }
+
+    async fn run_inner(&mut self, break_on_restart: bool) -> Result<(), Error> {
         // If a subgraph failed for deterministic reasons, before start indexing, we first
         // revert the deployment head. It should lead to the same result since the error was
         // deterministic.
@@ -130,8 +186,32 @@ where
             }
         }
 
+        // Continue the handler statistics from where they were left off
+        // before the deployment was last stopped
+        match self.inputs.store.handler_stats().await {
+            Ok(report) => handler_stats_tracker(self.inputs.deployment.hash.as_str()).load(report),
+            Err(e) => {
+                warn!(self.logger, "Failed to load handler statistics"; "error" => e.to_string())
+            }
+        }
+
+        // Run the synced actions that did not run to completion before the
+        // deployment was last stopped, for the block at which it became
+        // synced
+        match self.inputs.store.synced_actions_pending() {
+            Ok(Some(pending)) => self.run_synced_actions(pending.block, pending.done),
+            Ok(None) => {}
+            Err(e) => {
+                warn!(self.logger, "Failed to check for pending synced actions"; "error" => e.to_string())
+            }
+        }
+
         loop {
             debug!(self.logger, "Starting or restarting subgraph");
+            self.metrics
+                .host
+                .activity
+                .set_stream_state(StreamState::Starting);
 
             let block_stream_canceler = CancelGuard::new();
             let block_stream_cancel_handle = block_stream_canceler.handle();
@@ -156,26 +236,45 @@ where
             loop {
                 let event = {
                     let _section = self.metrics.stream.stopwatch.start_section("scan_blocks");
+                    self.metrics
+                        .host
+                        .activity
+                        .set_stream_state(StreamState::WaitingForBlocks);
 
                     block_stream.next().await
                 };
 
                 // TODO: move cancel handle to the Context
                 // This will require some code refactor in how the BlockStream is created
-                match self
+                let action = match self
                     .handle_stream_event(event, &block_stream_cancel_handle)
-                    .await?
+                    .await
                 {
-                    Action::Continue => continue,
+                    Ok(action) => action,
+                    // Work outside of processing the block itself, like
+                    // checking the chain head, also needs the store
+                    Err(e) if StoreError::is_unavailable(&e) => self.wait_for_store(&e).await?,
+                    Err(e) => return Err(e),
+                };
+                match action {
+                    Action::Continue => {
+                        if self.state.last_usage_flush.elapsed() >= ENV_VARS.usage_flush_interval {
+                            self.flush_observations().await;
+                        }
+                        continue;
+                    }
                     Action::Stop => {
                         info!(self.logger, "Stopping subgraph");
                         self.inputs.store.flush().await?;
-                        return Ok(self);
+                        self.flush_observations().await;
+                        self.inputs.catch_up.remove(&self.inputs.deployment.hash);
+                        return Ok(());
                     }
                     Action::Restart if break_on_restart => {
                         info!(self.logger, "Stopping subgraph on break");
                         self.inputs.store.flush().await?;
-                        return Ok(self);
+                        self.flush_observations().await;
+                        return Ok(());
                     }
                     Action::Restart => break,
                 };
@@ -183,6 +282,179 @@ where
         }
     }
 
+    /// Run the actions for a deployment that became synced at `block_ptr`
+    /// whose keys are not in `done` in the background. The store records
+    /// each action as soon as it ran, and forgets about the synced actions
+    /// of the deployment once all of them ran. Until then, the actions that
+    /// did not run yet are pending and run when the deployment is
+    /// restarted, so that each action runs once
+    fn run_synced_actions(&self, block_ptr: BlockPtr, done: BTreeSet<String>) {
+        let logger = self.logger.cheap_clone();
+        let store = self.inputs.store.cheap_clone();
+        let actions = self.inputs.synced_actions.cheap_clone();
+        let bus_sender = self.inputs.bus_sender.clone();
+        let event = SyncedEvent {
+            deployment: self.inputs.deployment.clone(),
+            network: self.inputs.network.clone(),
+            block: block_ptr,
+        };
+        graph::spawn(async move {
+            let ran = |action: &SyncedAction| {
+                if let Err(e) = store.synced_action_done(action.kind.key()) {
+                    warn!(logger, "Failed to record that a synced action ran";
+                          "action" => action.kind.key(),
+                          "error" => e.to_string());
+                }
+            };
+            actions
+                .run(&logger, &event, &done, bus_sender.as_ref(), ran)
+                .await;
+            if let Err(e) = store.synced_actions_done() {
+                warn!(logger, "Failed to record that the synced actions ran";
+                      "error" => e.to_string());
+            }
+        });
+    }
+
+    /// Build the messages about the changes `mods` in `block_ptr` that the
+    /// deployment is configured to publish to the bus
+    fn bus_payloads(&self, block_ptr: &BlockPtr, mods: &[EntityModification]) -> Vec<BusPayload> {
+        let mut payloads = Vec::new();
+        if self.inputs.bus_sender.is_none() {
+            return payloads;
+        }
+        let deployment = self.inputs.deployment.hash.as_str();
+
+        if ENV_VARS.bus_block_summaries.contains(deployment) {
+            let counts = EntityCounts::from_modifications(mods);
+            if !counts.is_empty() || ENV_VARS.bus_empty_block_summaries {
+                payloads.push(BusPayload::BlockSummary {
+                    block: block_ptr.clone(),
+                    counts,
+                });
+            }
+        }
+        if ENV_VARS.bus_modifications.contains(deployment) {
+            let modifications: Vec<_> = mods
+                .iter()
+                .filter(|modification| !modification.entity_ref().entity_type.is_poi())
+                .cloned()
+                .collect();
+            if !modifications.is_empty() {
+                payloads.push(BusPayload::Modifications {
+                    block: block_ptr.clone(),
+                    modifications,
+                });
+            }
+        }
+        payloads
+    }
+
+    fn send_bus_payloads(&self, logger: &Logger, payloads: Vec<BusPayload>) {
+        if let Some(sender) = &self.inputs.bus_sender {
+            for payload in payloads {
+                let msg = BusMessage {
+                    subgraph_id: self.inputs.deployment.hash.to_string(),
+                    payload,
+                    critical: self.inputs.bus_critical.clone(),
+                };
+                if sender.send(msg).is_err() {
+                    warn!(logger, "Bus is not running, dropping message about block");
+                }
+            }
+        }
+    }
+
+    /// Write the usage of shared infrastructure, the entity types that
+    /// handlers wrote, and the handler statistics that accumulated since
+    /// the last flush to the store. Failing to write them is not a reason
+    /// to stop indexing; they are kept and written with the next flush
+    async fn flush_observations(&mut self) {
+        self.state.last_usage_flush = Instant::now();
+        let deployment = self.inputs.deployment.hash.as_str();
+
+        let tracker = usage_tracker(deployment);
+        let usage = tracker.take();
+        if !usage.is_empty() {
+            if let Err(e) = self.inputs.store.record_usage(usage.clone()).await {
+                warn!(self.logger, "Failed to record usage"; "error" => e.to_string());
+                tracker.restore(usage);
+            }
+        }
+
+        let tracker = handler_entity_types(deployment);
+        let writes = tracker.take();
+        if !writes.is_empty() {
+            if let Err(e) = self
+                .inputs
+                .store
+                .record_handler_entity_types(writes.clone())
+                .await
+            {
+                warn!(self.logger, "Failed to record the entity types handlers wrote";
+                      "error" => e.to_string());
+                tracker.restore(writes);
+            }
+        }
+
+        let tracker = handler_stats_tracker(deployment);
+        let stats = tracker.take();
+        if !stats.is_empty() {
+            match self.inputs.store.record_handler_stats(stats.clone()).await {
+                Ok(report) => tracker.load(report),
+                Err(e) => {
+                    warn!(self.logger, "Failed to record handler statistics";
+                          "error" => e.to_string());
+                    tracker.restore(stats);
+                }
+            }
+        }
+    }
+
+    /// Pause processing after `error`, which was caused by the store being
+    /// unavailable, until the store is reachable again. Before resuming,
+    /// the block pointer is reloaded from the store so that processing
+    /// continues after the last block that was actually written.
+    async fn wait_for_store(&mut self, error: &impl fmt::Display) -> Result<Action, Error> {
+        self.metrics
+            .host
+            .activity
+            .set_stream_state(StreamState::WaitingForStore);
+        let message = format!("{:#}", error).replace('\n', "\t");
+        warn!(self.logger, "Store unavailable, pausing subgraph until it is reachable again";
+            "error" => message,
+            "attempt" => self.state.store_backoff.attempt,
+            "retry_delay_ms" => self.state.store_backoff.delay().as_millis());
+
+        let start = Instant::now();
+        self.state.store_backoff.sleep_async().await;
+
+        // The store retries reading the block pointer until the database
+        // is reachable again
+        let block_ptr = self.inputs.store.block_ptr();
+        let store_ptr = self.inputs.store.reload_block_ptr().await?;
+        if store_ptr != block_ptr {
+            warn!(self.logger, "Block pointer in the store differs from the one in memory, resuming from the store";
+                "store_ptr" => store_ptr.as_ref().map(|ptr| ptr.to_string()),
+                "block_ptr" => block_ptr.as_ref().map(|ptr| ptr.to_string()));
+            let first_unwritten = store_ptr.as_ref().map_or(0, |ptr| ptr.number + 1);
+            self.revert_state(first_unwritten)?;
+        }
+
+        info!(self.logger, "Store reachable again, resuming subgraph";
+            "paused_ms" => start.elapsed().as_millis());
+
+        // Cancel the stream for real
+        self.ctx
+            .instances
+            .write()
+            .unwrap()
+            .remove(&self.inputs.deployment.id);
+
+        // And restart the subgraph
+        Ok(Action::Restart)
+    }
+
     /// Processes a block and returns the updated context and a boolean flag indicating
     /// whether new dynamic data sources have been added to the subgraph.
     async fn process_block(
@@ -192,6 +464,7 @@ where
         firehose_cursor: FirehoseCursor,
     ) -> Result<Action, BlockProcessingError> {
         let triggers = block.trigger_data;
+        let block_provider = block.provider;
         let block = Arc::new(block.block);
         let block_ptr = block.ptr();
 
@@ -257,7 +530,8 @@ where
 
         // If new data sources have been created, and static filters are not in use, it is necessary
         // to restart the block stream with the new filters.
-        let needs_restart = block_state.has_created_data_sources() && !self.inputs.static_filters;
+        let created_data_sources = block_state.has_created_data_sources();
+        let mut needs_restart = created_data_sources && !self.inputs.static_filters;
 
         // This loop will:
         // 1. Instantiate created data sources.
@@ -267,6 +541,12 @@ where
         // Note that this algorithm processes data sources spawned on the same block _breadth
         // first_ on the tree implied by the parent-child relationship between data sources. Only a
         // very contrived subgraph would be able to observe this.
+        //
+        // Within one round of the loop, the order in which handlers run is fixed so that the PoI
+        // does not depend on anything but the block: the triggers are processed in the order of
+        // the block, and for each trigger the new data sources are processed in the order in
+        // which they were created, i.e., by their `creation_seq` (see
+        // `BlockState::drain_created_data_sources`).
         while block_state.has_created_data_sources() {
             // Instantiate dynamic data sources, removing them from the block state.
             let (data_sources, runtime_hosts) =
@@ -314,7 +594,9 @@ where
             self.persist_dynamic_data_sources(&mut block_state, data_sources);
 
             // Process the triggers in each host in the same order the
-            // corresponding data sources have been created.
+            // corresponding data sources have been created. `runtime_hosts`
+            // is in that order since `create_dynamic_data_sources` keeps
+            // the order of the data sources it is given.
             for trigger in triggers {
                 block_state = self
                     .ctx
@@ -344,6 +626,57 @@ where
             }
         }
 
+        // Run the block finalizers after all other triggers of the block,
+        // including those of data sources created in this block, so they
+        // see everything the other handlers wrote. Blocks in which no
+        // trigger matched a handler don't run them; candidate triggers
+        // that no data source handles don't count.
+        let has_run_handlers = block_state.has_run_handlers();
+        let finalizer_trigger = self
+            .inputs
+            .triggers_adapter
+            .block_finalizer_trigger(&block)
+            .filter(|_| has_run_handlers);
+        if let Some(trigger) = finalizer_trigger {
+            block_state = match self
+                .ctx
+                .process_trigger(
+                    &logger,
+                    &block,
+                    &TriggerData::Onchain(trigger),
+                    block_state,
+                    &proof_of_indexing,
+                    &causality_region,
+                    &self.inputs.debug_fork,
+                    &self.metrics.subgraph,
+                )
+                .await
+            {
+                Ok(block_state) => block_state,
+                Err(MappingError::PossibleReorg(e)) if !created_data_sources => {
+                    info!(logger,
+                        "Possible reorg detected in block finalizer, retrying";
+                        "error" => format!("{:#}", e),
+                    );
+                    return Ok(Action::Restart);
+                }
+                Err(MappingError::PossibleReorg(e)) | Err(MappingError::Unknown(e)) => {
+                    return Err(BlockProcessingError::Unknown(
+                        e.context("failed to process block finalizer"),
+                    ));
+                }
+            };
+
+            // Data sources that block finalizers create start processing
+            // triggers with the next block
+            if block_state.has_created_data_sources() {
+                let (data_sources, _) =
+                    self.create_dynamic_data_sources(block_state.drain_created_data_sources())?;
+                self.persist_dynamic_data_sources(&mut block_state, data_sources);
+                needs_restart = needs_restart || !self.inputs.static_filters;
+            }
+        }
+
         let has_errors = block_state.has_errors();
         let is_non_fatal_errors_active = self
             .inputs
@@ -433,7 +766,11 @@ where
 
         let first_error = deterministic_errors.first().cloned();
 
-        store
+        // Build the bus messages before `mods` is handed to the store, but
+        // only send them once the changes have been written
+        let bus_payloads = self.bus_payloads(&block_ptr, &mods);
+
+        let written = store
             .transact_block_operations(
                 block_ptr,
                 firehose_cursor,
@@ -443,9 +780,28 @@ where
                 deterministic_errors,
                 self.inputs.manifest_idx_and_name.clone(),
                 processed_data_sources,
+                block_provider,
+                self.inputs.invariants.clone(),
             )
-            .await
-            .context("Failed to transact block operations")?;
+            .await;
+        // A violation of a strict invariant has to fail the deployment at
+        // this block, and not at a later one when the queued write fails
+        let has_strict_invariants = self.inputs.invariants.iter().any(|i| i.strict);
+        let written = match written {
+            Ok(()) if has_strict_invariants => store.flush().await,
+            written => written,
+        };
+        match written {
+            Ok(()) => {}
+            Err(StoreError::InvariantViolated(error)) => {
+                return Err(BlockProcessingError::Deterministic(error));
+            }
+            Err(e) => {
+                return Err(Error::from(e)
+                    .context("Failed to transact block operations")
+                    .into());
+            }
+        }
 
         // For subgraphs with `nonFatalErrors` feature disabled, we consider
         // any error as fatal.
@@ -459,6 +815,8 @@ where
             return Err(BlockProcessingError::Deterministic(first_error.unwrap()));
         }
 
+        self.send_bus_payloads(&logger, bus_payloads);
+
         let elapsed = start.elapsed().as_secs_f64();
         self.metrics
             .subgraph
@@ -520,6 +878,9 @@ where
         Ok(block_state)
     }
 
+    /// Instantiate the `created_data_sources` and return the new data
+    /// sources and their hosts in the order of `created_data_sources`,
+    /// leaving out the ones that are ignored or that already have a host.
     fn create_dynamic_data_sources(
         &mut self,
         created_data_sources: Vec<DataSourceTemplateInfo<C>>,
@@ -700,7 +1061,12 @@ where
         let mut mods = vec![];
         let mut processed_data_sources = vec![];
 
+        // The number of offchain triggers that the node processes at the
+        // same time is limited across all deployments
+        let mut turn = self.inputs.offchain_limit.turn();
         for trigger in triggers {
+            let _executing = turn.execute().await;
+
             // Using an `EmptyStore` and clearing the cache for each trigger is a makeshift way to
             // get causality region isolation.
             let schema = self.inputs.store.input_schema();
@@ -792,6 +1158,8 @@ where
         cancel_handle: &CancelHandle,
     ) -> Result<Action, Error> {
         let block_ptr = block.ptr();
+        let block_provider = block.provider.clone();
+        self.metrics.host.activity.block_received(block_ptr.number);
         self.metrics
             .stream
             .deployment_head
@@ -815,11 +1183,28 @@ where
                 1000,
             )
         {
+            // The block is not written, but it did not change anything
+            // either; consumers of empty summaries must still see it
+            if ENV_VARS.bus_empty_block_summaries {
+                let payloads = self.bus_payloads(&block_ptr, &[]);
+                self.send_bus_payloads(&self.logger, payloads);
+            }
             return Ok(Action::Continue);
         } else {
             self.state.skip_ptr_updates_timer = Instant::now();
         }
 
+        // Deployments that are catching up take turns processing blocks; the
+        // permit is released once the block is processed
+        let _permit = if self.state.synced {
+            None
+        } else {
+            self.inputs
+                .catch_up
+                .acquire(&self.inputs.deployment.hash, &self.inputs.network)
+                .await
+        };
+
         let start = Instant::now();
 
         let res = self.process_block(cancel_handle, block, cursor).await;
@@ -845,7 +1230,9 @@ where
                     // Updating the sync status is an one way operation.
                     // This state change exists: not synced -> synced
                     // This state change does NOT: synced -> not synced
-                    self.inputs.store.deployment_synced()?;
+                    if self.inputs.store.deployment_synced(block_ptr.clone())? {
+                        self.run_synced_actions(block_ptr.clone(), BTreeSet::new());
+                    }
 
                     // Stop trying to update the sync status.
                     self.state.synced = true;
@@ -872,6 +1259,8 @@ where
                     }
                 }
 
+                self.state.store_backoff.reset();
+
                 if let Some(stop_block) = &self.inputs.stop_block {
                     if block_ptr.number >= *stop_block {
                         info!(self.logger, "stop block reached for subgraph");
@@ -898,6 +1287,14 @@ where
                 return Ok(Action::Stop);
             }
 
+            // Don't fail the subgraph when the store is unavailable, e.g.,
+            // during a database failover; pause instead until the store is
+            // reachable again.
+            Err(e) if e.is_store_unavailable() => {
+                self.revert_state(block_ptr.block_number())?;
+                return self.wait_for_store(&e).await;
+            }
+
             // Handle unexpected stream errors by marking the subgraph as failed.
             Err(e) => {
                 self.metrics.stream.deployment_failed.set(1.0);
@@ -913,6 +1310,10 @@ where
                     block_ptr: Some(block_ptr),
                     handler: None,
                     deterministic,
+                    code: e.code(),
+                    // Non-deterministic errors are often caused by bad data
+                    // from a provider; record which one served the block
+                    provider: if deterministic { None } else { block_provider },
                 };
 
                 match deterministic {
@@ -991,6 +1392,10 @@ where
         }
 
         info!(&self.logger, "Reverting block to get back to main chain"; "subgraph_ptr" => &subgraph_ptr, "revert_to_ptr" => &revert_to_ptr);
+        self.metrics
+            .host
+            .activity
+            .set_stream_state(StreamState::Reverting(revert_to_ptr.number));
 
         if let Err(e) = self
             .inputs
@@ -1033,6 +1438,7 @@ where
             "Block stream produced a non-fatal error";
             "error" => format!("{}", err),
         );
+        self.metrics.host.activity.stream_error(err.to_string());
 
         Ok(Action::Continue)
     }
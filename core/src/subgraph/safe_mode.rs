@@ -0,0 +1,300 @@
+//! Start the deployments assigned to a node in waves rather than all at
+//! once. When a node that runs hundreds of deployments restarts after a
+//! crash, starting all of them at the same time overwhelms the providers
+//! and the store, and the node can run out of memory before it reaches a
+//! steady state.
+//!
+//! Each wave starts the deployments that are most valuable to have
+//! running: first the ones that are close to the chain head, since they
+//! serve current data, and among those the ones that receive the most
+//! queries. Queries are usually served by other nodes, so the rates are
+//! the ones that query nodes persisted in the store, which survive a
+//! restart of this node. Since they keep changing while the ramp-up is
+//! running, deployments are ranked again before each wave. The ramp-up stops when the node uses too much memory or the store
+//! responds too slowly; the deployments that were not started yet can be
+//! started with `graphman force-start`.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::components::metrics::query_rate::{query_rate, QUERY_RATE_HALF_LIFE};
+use graph::components::store::{DeploymentLocator, StatusStore};
+use graph::data::subgraph::status::{self, Filter};
+use graph::env::EnvVars;
+use graph::prelude::futures03::future::join_all;
+use graph::prelude::{info, o, tokio, warn, BlockNumber, Gauge, Logger, MetricsRegistry};
+use graph::util::mem::resident_memory;
+
+/// Deployments that are at most this many blocks behind the chain head
+/// are started before all others
+const NEAR_HEAD_BLOCKS: BlockNumber = 100;
+
+/// Query rates that were reported longer ago than this are ignored. This
+/// is longer than the reporting interval so that the rates that were
+/// reported shortly before this node went down still count
+const QUERY_RATE_MAX_AGE: Duration = Duration::from_secs(QUERY_RATE_HALF_LIFE.as_secs() * 2);
+
+pub struct SafeModeConfig {
+    /// The number of deployments that are started at the same time
+    pub wave_size: usize,
+    /// How long to wait after one wave before starting the next one
+    pub wave_delay: Duration,
+    /// Stop when the node uses more than this many bytes of memory; 0
+    /// means no limit
+    pub max_memory: u64,
+    /// Stop when looking up the status of the remaining deployments takes
+    /// longer than this; zero means no limit
+    pub max_store_latency: Duration,
+}
+
+impl SafeModeConfig {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        SafeModeConfig {
+            wave_size: env_vars.safe_mode_wave_size,
+            wave_delay: env_vars.safe_mode_wave_delay,
+            max_memory: env_vars.safe_mode_max_memory_mb * 1024 * 1024,
+            max_store_latency: env_vars.safe_mode_max_store_latency,
+        }
+    }
+}
+
+/// A deployment waiting for its turn, with what its turn depends on
+struct Candidate {
+    deployment: DeploymentLocator,
+    /// How far the deployment is behind the chain head; `None` if that is
+    /// not known
+    blocks_behind: Option<BlockNumber>,
+    query_rate: f64,
+}
+
+impl Candidate {
+    fn near_head(&self) -> bool {
+        matches!(self.blocks_behind, Some(behind) if behind <= NEAR_HEAD_BLOCKS)
+    }
+
+    /// Whether `self` should be started before `other`
+    fn cmp_turn(&self, other: &Candidate) -> Ordering {
+        other
+            .near_head()
+            .cmp(&self.near_head())
+            .then_with(|| {
+                other
+                    .query_rate
+                    .partial_cmp(&self.query_rate)
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| {
+                let behind = |c: &Candidate| c.blocks_behind.unwrap_or(BlockNumber::MAX);
+                behind(self).cmp(&behind(other))
+            })
+    }
+}
+
+pub struct SafeModeRampUp {
+    logger: Logger,
+    config: SafeModeConfig,
+    status_store: Arc<dyn StatusStore>,
+    started: Box<Gauge>,
+    total: Box<Gauge>,
+}
+
+impl SafeModeRampUp {
+    pub fn new(
+        logger: &Logger,
+        config: SafeModeConfig,
+        status_store: Arc<dyn StatusStore>,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Self {
+        let started = registry
+            .new_gauge(
+                "safe_mode_deployments_started",
+                "The number of deployments that the safe mode ramp-up started",
+                Default::default(),
+            )
+            .expect("failed to create `safe_mode_deployments_started` gauge");
+        let total = registry
+            .new_gauge(
+                "safe_mode_deployments_total",
+                "The number of deployments that the safe mode ramp-up should start",
+                Default::default(),
+            )
+            .expect("failed to create `safe_mode_deployments_total` gauge");
+        SafeModeRampUp {
+            logger: logger.new(o!("component" => "SafeModeRampUp")),
+            config,
+            status_store,
+            started,
+            total,
+        }
+    }
+
+    /// Start `deployments` in waves by calling `start` for each of them,
+    /// and wait for all starts of a wave to finish before the next wave
+    pub async fn run<S, F>(&self, deployments: Vec<DeploymentLocator>, start: S)
+    where
+        S: Fn(DeploymentLocator) -> F,
+        F: Future<Output = ()>,
+    {
+        let total = deployments.len();
+        self.total.set(total as f64);
+        self.started.set(0.0);
+        info!(self.logger, "Starting deployments in safe mode";
+              "deployments" => total,
+              "wave_size" => self.config.wave_size,
+              "wave_delay_s" => self.config.wave_delay.as_secs());
+
+        let mut started = 0;
+        let mut remaining = deployments;
+        while !remaining.is_empty() {
+            let lookup_start = Instant::now();
+            let mut candidates = self.candidates(remaining);
+            let latency = lookup_start.elapsed();
+
+            if started > 0 {
+                if let Some(reason) = self.overloaded(latency) {
+                    warn!(self.logger, "Stopping the safe mode ramp-up, use `graphman force-start` to start the remaining deployments";
+                          "reason" => reason,
+                          "started" => started,
+                          "not_started" => candidates.len());
+                    return;
+                }
+            }
+
+            candidates.sort_by(Candidate::cmp_turn);
+            let rest = candidates.split_off(self.config.wave_size.min(candidates.len()));
+            let wave: Vec<_> = candidates.into_iter().map(|c| c.deployment).collect();
+            let wave_len = wave.len();
+            join_all(wave.into_iter().map(&start)).await;
+
+            started += wave_len;
+            self.started.set(started as f64);
+            info!(self.logger, "Started a wave of deployments";
+                  "started" => started,
+                  "total" => total);
+
+            remaining = rest.into_iter().map(|c| c.deployment).collect();
+            if !remaining.is_empty() {
+                tokio::time::sleep(self.config.wave_delay).await;
+            }
+        }
+        info!(self.logger, "Started all deployments in safe mode"; "deployments" => total);
+    }
+
+    /// Look up how far behind the chain head each deployment is. If that
+    /// fails, the deployments are only ranked by their query rate
+    fn candidates(&self, deployments: Vec<DeploymentLocator>) -> Vec<Candidate> {
+        let rates = self.query_rates();
+        let ids = deployments.iter().map(|d| d.id).collect();
+        let infos = match self.status_store.status(Filter::DeploymentIds(ids)) {
+            Ok(infos) => infos,
+            Err(e) => {
+                warn!(self.logger, "Failed to look up deployment status for the safe mode ramp-up";
+                      "error" => e.to_string());
+                vec![]
+            }
+        };
+        deployments
+            .into_iter()
+            .map(|deployment| {
+                let blocks_behind = infos
+                    .iter()
+                    .find(|info| info.id == deployment.id)
+                    .and_then(blocks_behind);
+                let reported = rates.get(deployment.hash.as_str()).copied();
+                let query_rate = reported
+                    .unwrap_or(0.0)
+                    .max(query_rate(deployment.hash.as_str()));
+                Candidate {
+                    deployment,
+                    blocks_behind,
+                    query_rate,
+                }
+            })
+            .collect()
+    }
+
+    /// The query rates that query nodes persisted in the store. If they
+    /// can't be read, only the rates of queries that this node served count
+    fn query_rates(&self) -> HashMap<String, f64> {
+        match self.status_store.query_rates(QUERY_RATE_MAX_AGE) {
+            Ok(rates) => rates,
+            Err(e) => {
+                warn!(self.logger, "Failed to look up query rates for the safe mode ramp-up";
+                      "error" => e.to_string());
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Why starting more deployments would be unsafe, if it is
+    fn overloaded(&self, store_latency: Duration) -> Option<String> {
+        if !self.config.max_store_latency.is_zero() && store_latency > self.config.max_store_latency
+        {
+            return Some(format!(
+                "the store took {}ms to respond, more than the limit of {}ms",
+                store_latency.as_millis(),
+                self.config.max_store_latency.as_millis()
+            ));
+        }
+        if self.config.max_memory > 0 {
+            if let Some(memory) = resident_memory() {
+                if memory > self.config.max_memory {
+                    return Some(format!(
+                        "the node uses {}MB of memory, more than the limit of {}MB",
+                        memory / (1024 * 1024),
+                        self.config.max_memory / (1024 * 1024)
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn blocks_behind(info: &status::Info) -> Option<BlockNumber> {
+    let chain = info.chains.first()?;
+    let head = chain.chain_head_block.as_ref()?.number();
+    let latest = chain
+        .latest_block
+        .as_ref()
+        .map(|block| block.number())
+        .unwrap_or(0);
+    Some((head - latest).max(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::components::store::DeploymentId;
+    use graph::prelude::DeploymentHash;
+
+    use super::*;
+
+    fn candidate(id: i32, blocks_behind: Option<BlockNumber>, query_rate: f64) -> Candidate {
+        let hash = DeploymentHash::new(format!("Qm{}", id)).unwrap();
+        Candidate {
+            deployment: DeploymentLocator::new(DeploymentId(id), hash, None),
+            blocks_behind,
+            query_rate,
+        }
+    }
+
+    #[test]
+    fn turn_order() {
+        let mut candidates = vec![
+            candidate(1, Some(1_000_000), 50.0),
+            candidate(2, None, 0.0),
+            candidate(3, Some(10), 0.0),
+            candidate(4, Some(50), 2.0),
+            candidate(5, Some(5_000), 0.0),
+            candidate(6, Some(500), 0.0),
+        ];
+        candidates.sort_by(Candidate::cmp_turn);
+        let order: Vec<_> = candidates.iter().map(|c| c.deployment.id.0).collect();
+        // Near the head first, by query rate, then the rest by query rate
+        // and how far behind they are
+        assert_eq!(vec![4, 3, 1, 6, 5, 2], order);
+    }
+}
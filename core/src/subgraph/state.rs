@@ -12,6 +12,8 @@ pub struct IndexingState {
     pub synced: bool,
     /// Backoff used for the retry mechanism on non-deterministic errors
     pub backoff: ExponentialBackoff,
+    /// Backoff used when waiting for the store to become available again
+    pub store_backoff: ExponentialBackoff,
     /// Related to field above `backoff`
     ///
     /// Resets to `Instant::now` every time:
@@ -19,4 +21,7 @@ pub struct IndexingState {
     /// - Or the subgraph has triggers for the block
     pub skip_ptr_updates_timer: Instant,
     pub entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// When the usage of shared infrastructure was last written to the
+    /// store
+    pub last_usage_flush: Instant,
 }
@@ -0,0 +1,451 @@
+//! Actions that run when a deployment reaches the chain head for the first
+//! time, e.g., to tell the systems that route queries that the deployment
+//! can now serve current data.
+//!
+//! Marking the deployment as synced also records in the same transaction
+//! that its actions are pending, together with the block at which it
+//! became synced. Each action is recorded as done as soon as it ran, and a
+//! runner that starts a deployment with pending actions runs only those
+//! that are not done yet, for the recorded block. An action that the node
+//! went down in the middle of runs again with exactly the same event: the
+//! `label` and `reassign` actions are idempotent, webhook requests carry
+//! the same idempotency key every time, and bus messages are identical
+//! since they only consist of the deployment and the block, so that
+//! receivers can discard the repetition. That way, each action takes effect
+//! exactly once even if the node goes down right around the time the
+//! deployment becomes synced. Actions never affect indexing: they run in
+//! the background, each with its own timeout, and failures are only
+//! logged.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::components::bus::{BusMessage, BusPayload};
+use graph::components::store::{DeploymentLocator, SubgraphStore};
+use graph::env::ENV_VARS;
+use graph::prelude::futures03::future::join_all;
+use graph::prelude::{
+    anyhow, info, reqwest, serde_json, tokio, warn, BlockPtr, Error, Logger, NodeId, StoreError,
+};
+use graph::tokio::sync::mpsc::UnboundedSender;
+use graph::util::proxy::ProxyTarget;
+
+/// What to do when a deployment becomes synced
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncedActionKind {
+    /// Publish a lifecycle message to the bus backends of the deployment
+    Bus,
+    /// Send the details of the deployment as JSON in a POST request to
+    /// `url`
+    Webhook { url: String },
+    /// Assign the deployment to `node`, like `graphman reassign`
+    Reassign { node: NodeId },
+    /// Put `label` on the deployment, like `graphman label add`
+    Label { label: String },
+}
+
+impl SyncedActionKind {
+    fn name(&self) -> &'static str {
+        match self {
+            SyncedActionKind::Bus => "bus",
+            SyncedActionKind::Webhook { .. } => "webhook",
+            SyncedActionKind::Reassign { .. } => "reassign",
+            SyncedActionKind::Label { .. } => "label",
+        }
+    }
+
+    /// The key under which the store records that the action ran. Two
+    /// actions have the same key only if they do the same thing
+    pub fn key(&self) -> String {
+        match self {
+            SyncedActionKind::Bus => "bus".to_string(),
+            SyncedActionKind::Webhook { url } => format!("webhook:{}", url),
+            SyncedActionKind::Reassign { node } => format!("reassign:{}", node),
+            SyncedActionKind::Label { label } => format!("label:{}", label),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncedAction {
+    pub kind: SyncedActionKind,
+    /// Give up on the action if it takes longer than this
+    pub timeout: Duration,
+}
+
+/// A deployment that reached the chain head for the first time
+#[derive(Clone, Debug)]
+pub struct SyncedEvent {
+    pub deployment: DeploymentLocator,
+    pub network: String,
+    /// The block at which the deployment became synced
+    pub block: BlockPtr,
+}
+
+impl SyncedEvent {
+    /// The same for every time the actions for this event run, so that
+    /// receivers can recognize actions that ran again after a restart
+    pub fn idempotency_key(&self) -> String {
+        format!("{}-{}", self.deployment.hash, self.block.hash_hex())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "idempotency_key": self.idempotency_key(),
+            "deployment": self.deployment.hash.as_str(),
+            "id": self.deployment.id.0,
+            "network": self.network,
+            "block": {
+                "number": self.block.number,
+                "hash": format!("0x{}", self.block.hash_hex()),
+            },
+        })
+    }
+}
+
+/// The part of the subgraph store that the `reassign` and `label` actions
+/// use
+pub trait SyncedActionStore: Send + Sync + 'static {
+    fn reassign(&self, deployment: &DeploymentLocator, node: &NodeId) -> Result<(), StoreError>;
+
+    fn label(&self, deployment: &DeploymentLocator, label: &str) -> Result<(), StoreError>;
+}
+
+impl<S: SubgraphStore> SyncedActionStore for S {
+    fn reassign(&self, deployment: &DeploymentLocator, node: &NodeId) -> Result<(), StoreError> {
+        self.reassign_subgraph(deployment, node)
+    }
+
+    fn label(&self, deployment: &DeploymentLocator, label: &str) -> Result<(), StoreError> {
+        self.label_deployment(deployment, label)
+    }
+}
+
+pub struct SyncedActions {
+    actions: Vec<SyncedAction>,
+    store: Arc<dyn SyncedActionStore>,
+    client: reqwest::Client,
+}
+
+impl SyncedActions {
+    pub fn new(actions: Vec<SyncedAction>, store: Arc<dyn SyncedActionStore>) -> Self {
+        let client = ENV_VARS
+            .proxies
+            .http_client(ProxyTarget::Other)
+            .build()
+            .expect("failed to create the client for synced webhooks");
+        SyncedActions {
+            actions,
+            store,
+            client,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Run the actions for `event` whose keys are not in `done` at the same
+    /// time and wait until each of them finished or timed out. Each action
+    /// is passed to `ran` as soon as it finished, whether it succeeded or
+    /// not. `bus_sender` is where the deployment publishes to the bus, if
+    /// it does
+    pub async fn run<F>(
+        &self,
+        logger: &Logger,
+        event: &SyncedEvent,
+        done: &BTreeSet<String>,
+        bus_sender: Option<&UnboundedSender<BusMessage>>,
+        ran: F,
+    ) where
+        F: Fn(&SyncedAction) + Sync,
+    {
+        let ran = &ran;
+        let pending = self
+            .actions
+            .iter()
+            .filter(|action| !done.contains(&action.kind.key()));
+        join_all(pending.map(|action| async move {
+            let res = match tokio::time::timeout(
+                action.timeout,
+                self.run_action(&action.kind, event, bus_sender),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(_) => Err(anyhow!("timed out after {}ms", action.timeout.as_millis())),
+            };
+            match res {
+                Ok(()) => info!(logger, "Ran action for synced deployment";
+                                "action" => action.kind.name()),
+                Err(e) => warn!(logger, "Action for synced deployment failed";
+                                "action" => action.kind.name(),
+                                "error" => format!("{:#}", e)),
+            }
+            ran(action);
+        }))
+        .await;
+    }
+
+    async fn run_action(
+        &self,
+        kind: &SyncedActionKind,
+        event: &SyncedEvent,
+        bus_sender: Option<&UnboundedSender<BusMessage>>,
+    ) -> Result<(), Error> {
+        match kind {
+            SyncedActionKind::Bus => {
+                let sender =
+                    bus_sender.ok_or_else(|| anyhow!("the deployment has no bus backends"))?;
+                let msg = BusMessage {
+                    subgraph_id: event.deployment.hash.to_string(),
+                    payload: BusPayload::Synced {
+                        block: event.block.clone(),
+                    },
+                    critical: None,
+                };
+                sender
+                    .send(msg)
+                    .map_err(|_| anyhow!("the bus is not running"))
+            }
+            SyncedActionKind::Webhook { url } => {
+                self.client
+                    .post(url)
+                    .header("Idempotency-Key", event.idempotency_key())
+                    .json(&event.to_json())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(())
+            }
+            SyncedActionKind::Reassign { node } => {
+                let store = self.store.clone();
+                let deployment = event.deployment.clone();
+                let node = node.clone();
+                tokio::task::spawn_blocking(move || store.reassign(&deployment, &node)).await??;
+                Ok(())
+            }
+            SyncedActionKind::Label { label } => {
+                let store = self.store.clone();
+                let deployment = event.deployment.clone();
+                let label = label.clone();
+                tokio::task::spawn_blocking(move || store.label(&deployment, &label)).await??;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    use graph::components::store::DeploymentId;
+    use graph::prelude::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use graph::prelude::tokio::net::TcpListener;
+    use graph::prelude::web3::types::H256;
+    use graph::prelude::{o, DeploymentHash};
+    use graph::slog::Discard;
+    use graph::tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    /// Records what the `reassign` and `label` actions did
+    #[derive(Default)]
+    struct Calls(Mutex<Vec<(DeploymentId, String)>>);
+
+    impl Calls {
+        fn take(&self) -> Vec<(DeploymentId, String)> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl SyncedActionStore for Calls {
+        fn reassign(
+            &self,
+            deployment: &DeploymentLocator,
+            node: &NodeId,
+        ) -> Result<(), StoreError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((deployment.id, format!("reassign {}", node)));
+            Ok(())
+        }
+
+        fn label(&self, deployment: &DeploymentLocator, label: &str) -> Result<(), StoreError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((deployment.id, format!("label {}", label)));
+            Ok(())
+        }
+    }
+
+    /// A webhook that records the bodies of the requests it receives
+    async fn webhook(bodies: Arc<Mutex<Vec<serde_json::Value>>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let bodies = bodies.clone();
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    while !head.ends_with(b"\r\n\r\n") {
+                        head.push(conn.read_u8().await.unwrap());
+                    }
+                    let head = String::from_utf8(head).unwrap().to_lowercase();
+                    let len: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .unwrap()
+                        .trim()
+                        .parse()
+                        .unwrap();
+                    let mut body = vec![0u8; len];
+                    conn.read_exact(&mut body).await.unwrap();
+                    bodies
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_slice(&body).unwrap());
+                    conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    fn event() -> SyncedEvent {
+        let hash = DeploymentHash::new("QmSynced").unwrap();
+        SyncedEvent {
+            deployment: DeploymentLocator::new(DeploymentId(7), hash, None),
+            network: "mainnet".to_string(),
+            block: BlockPtr::new(H256::from_low_u64_be(42).into(), 42),
+        }
+    }
+
+    fn action(kind: SyncedActionKind) -> SyncedAction {
+        SyncedAction {
+            kind,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Run `actions` for `event` and return the keys of the actions that
+    /// ran
+    async fn run(
+        actions: &SyncedActions,
+        event: &SyncedEvent,
+        done: &BTreeSet<String>,
+        bus_sender: Option<&UnboundedSender<BusMessage>>,
+    ) -> BTreeSet<String> {
+        let logger = Logger::root(Discard, o!());
+        let ran = Mutex::new(BTreeSet::new());
+        actions
+            .run(&logger, event, done, bus_sender, |action| {
+                ran.lock().unwrap().insert(action.kind.key());
+            })
+            .await;
+        ran.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn each_action_fires_once() {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let addr = webhook(bodies.clone()).await;
+        let calls = Arc::new(Calls::default());
+        let node = NodeId::new("index_node_1").unwrap();
+        let kinds = vec![
+            SyncedActionKind::Bus,
+            SyncedActionKind::Webhook {
+                url: format!("http://{}/synced", addr),
+            },
+            SyncedActionKind::Reassign { node: node.clone() },
+            SyncedActionKind::Label {
+                label: "live".to_string(),
+            },
+        ];
+        let actions =
+            SyncedActions::new(kinds.iter().cloned().map(action).collect(), calls.clone());
+        let (sender, mut receiver) = unbounded_channel();
+
+        let event = event();
+        let ran = run(&actions, &event, &BTreeSet::new(), Some(&sender)).await;
+        assert_eq!(
+            kinds.iter().map(|kind| kind.key()).collect::<BTreeSet<_>>(),
+            ran
+        );
+
+        let msg = receiver.try_recv().unwrap();
+        assert_eq!("QmSynced", msg.subgraph_id);
+        assert!(matches!(msg.payload, BusPayload::Synced { block } if block.number == 42));
+        assert!(receiver.try_recv().is_err());
+
+        {
+            let bodies = bodies.lock().unwrap();
+            assert_eq!(1, bodies.len());
+            assert_eq!(event.to_json(), bodies[0]);
+            assert_eq!("QmSynced", bodies[0]["deployment"]);
+            assert_eq!(
+                event.idempotency_key().as_str(),
+                bodies[0]["idempotency_key"]
+            );
+        }
+
+        assert_eq!(
+            vec![
+                (DeploymentId(7), "reassign index_node_1".to_string()),
+                (DeploymentId(7), "label live".to_string())
+            ],
+            calls.take()
+        );
+
+        // Actions that are done do not run again, e.g., after a restart
+        // that happened while only some of them had run
+        let done = ran.iter().take(2).cloned().collect::<BTreeSet<_>>();
+        let rerun = run(&actions, &event, &done, Some(&sender)).await;
+        assert_eq!(
+            ran.difference(&done).cloned().collect::<BTreeSet<_>>(),
+            rerun
+        );
+        let calls = calls.take();
+        let messages = std::iter::from_fn(|| receiver.try_recv().ok()).count();
+        let requests = bodies.lock().unwrap().len() - 1;
+        assert_eq!(rerun.len(), calls.len() + messages + requests);
+        assert!(run(&actions, &event, &ran, Some(&sender)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failures_do_not_stop_other_actions() {
+        // Accept connections but never answer so the webhook times out
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let calls = Arc::new(Calls::default());
+        let actions = SyncedActions::new(
+            vec![
+                SyncedAction {
+                    kind: SyncedActionKind::Webhook {
+                        url: format!("http://{}/synced", addr),
+                    },
+                    timeout: Duration::from_millis(100),
+                },
+                // No bus backends
+                action(SyncedActionKind::Bus),
+                action(SyncedActionKind::Reassign {
+                    node: NodeId::new("index_node_1").unwrap(),
+                }),
+            ],
+            calls.clone(),
+        );
+
+        // Failed actions are done, too; they are not tried again
+        let ran = run(&actions, &event(), &BTreeSet::new(), None).await;
+        assert_eq!(3, ran.len());
+        assert_eq!(1, calls.take().len());
+        drop(listener);
+    }
+}
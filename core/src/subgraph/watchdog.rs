@@ -0,0 +1,447 @@
+//! Detect deployments that stopped advancing although their chain keeps
+//! moving, e.g., because the provider does not return their blocks, the
+//! mapping hangs, or a lock in the store blocks their writes.
+//!
+//! The watchdog of a deployment compares the head of the deployment with
+//! the head of its chain. When the deployment stayed at the same block for
+//! `timeout` while the chain head moved, and the deployment is not waiting
+//! for a good reason, like a start block that the chain has not reached
+//! yet, it is stuck: the watchdog logs what the deployment was doing as a
+//! single report, counts it in the `stuck_deployments` gauge, and can
+//! publish a lifecycle message to the bus. Nothing here ever affects
+//! indexing.
+
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use graph::components::bus::{BusMessage, BusPayload};
+use graph::components::store::{DeploymentLocator, WritableStore};
+use graph::components::subgraph::activity::{deployment_activity, ActivitySnapshot, StreamState};
+use graph::data::subgraph::schema::SubgraphHealth;
+use graph::env::EnvVars;
+use graph::prelude::{
+    info, tokio, warn, BlockNumber, ChainStore, CheapClone, Gauge, Logger, MetricsRegistry,
+};
+use graph::tokio::sync::mpsc::UnboundedSender;
+
+/// Never check more often than this
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Never check less often than this
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct StuckWatchdogConfig {
+    /// How long the deployment head may stay at the same block while the
+    /// chain head moves
+    pub timeout: Duration,
+    /// Whether to publish a lifecycle message when a deployment is stuck
+    pub bus_events: bool,
+}
+
+impl StuckWatchdogConfig {
+    /// Return `None` if the detection is turned off
+    pub fn from_env(env_vars: &EnvVars) -> Option<Self> {
+        if env_vars.stuck_deployment_timeout.is_zero() {
+            return None;
+        }
+        Some(StuckWatchdogConfig {
+            timeout: env_vars.stuck_deployment_timeout,
+            bus_events: env_vars.stuck_deployment_bus_events,
+        })
+    }
+
+    fn check_interval(&self) -> Duration {
+        (self.timeout / 4).clamp(MIN_CHECK_INTERVAL, MAX_CHECK_INTERVAL)
+    }
+}
+
+/// What the watchdog learned about a deployment at one check
+struct Observation {
+    head: Option<BlockNumber>,
+    chain_head: Option<BlockNumber>,
+    stream_state: StreamState,
+}
+
+/// Since when the deployment head has been at the same block
+struct Progress {
+    head: Option<BlockNumber>,
+    /// The chain head when the deployment head last moved or the
+    /// deployment last had a reason to wait
+    chain_head: Option<BlockNumber>,
+    since: Instant,
+}
+
+impl Progress {
+    fn new(observation: &Observation, now: Instant) -> Self {
+        Progress {
+            head: observation.head,
+            chain_head: observation.chain_head,
+            since: now,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    /// The deployment head moved since the last check
+    Moved,
+    /// The deployment head did not move, but it has nothing to do
+    Waiting(&'static str),
+    /// The deployment head did not move for a while, but not long enough
+    /// to consider the deployment stuck
+    Stalled,
+    Stuck,
+}
+
+fn assess(
+    progress: &Progress,
+    observation: &Observation,
+    start_block: BlockNumber,
+    timeout: Duration,
+    now: Instant,
+) -> Verdict {
+    if observation.head != progress.head {
+        return Verdict::Moved;
+    }
+    let chain_head = match observation.chain_head {
+        Some(chain_head) => chain_head,
+        None => return Verdict::Waiting("the chain head is not known"),
+    };
+    if chain_head < start_block {
+        return Verdict::Waiting("the chain has not reached the start block");
+    }
+    if observation.head.map_or(false, |head| head >= chain_head) {
+        return Verdict::Waiting("the deployment is at the chain head");
+    }
+    if observation.stream_state == StreamState::WaitingForStore {
+        return Verdict::Waiting("the deployment is paused until the store is available");
+    }
+    if progress.chain_head.map_or(false, |then| chain_head <= then) {
+        return Verdict::Stalled;
+    }
+    if now.duration_since(progress.since) >= timeout {
+        Verdict::Stuck
+    } else {
+        Verdict::Stalled
+    }
+}
+
+/// Describe `snapshot` in one line for the bus message
+fn describe(
+    head: Option<BlockNumber>,
+    chain_head: Option<BlockNumber>,
+    stalled: Duration,
+    snapshot: &ActivitySnapshot,
+) -> String {
+    fn block(number: Option<BlockNumber>) -> String {
+        number.map_or_else(|| "none".to_string(), |number| number.to_string())
+    }
+
+    let mut report = format!(
+        "head {} behind chain head {} for {}s; stream {} for {}s",
+        block(head),
+        block(chain_head),
+        stalled.as_secs(),
+        snapshot.stream_state,
+        snapshot.stream_state_age.as_secs()
+    );
+    match snapshot.last_block_age {
+        Some(age) => report.push_str(&format!("; last block {}s ago", age.as_secs())),
+        None => report.push_str("; no block received"),
+    }
+    if let Some((handler, age)) = &snapshot.last_trigger {
+        report.push_str(&format!(
+            "; last trigger {} {}s ago",
+            handler,
+            age.as_secs()
+        ));
+    }
+    report.push_str(&format!("; {} mapping requests", snapshot.mapping_requests));
+    match &snapshot.write {
+        Some((request, age)) => report.push_str(&format!(
+            "; store executing `{}` for {}s with {} queued",
+            request,
+            age.as_secs(),
+            snapshot.write_queue
+        )),
+        None => report.push_str("; store idle"),
+    }
+    if let Some((error, age)) = snapshot.stream_errors.last() {
+        report.push_str(&format!(
+            "; last stream error {}s ago: {}",
+            age.as_secs(),
+            error
+        ));
+    }
+    report
+}
+
+pub struct StuckWatchdog {
+    config: StuckWatchdogConfig,
+    stuck: Box<Gauge>,
+}
+
+impl StuckWatchdog {
+    pub fn new(config: StuckWatchdogConfig, registry: Arc<dyn MetricsRegistry>) -> Self {
+        let stuck = registry
+            .new_gauge(
+                "stuck_deployments",
+                "The number of deployments whose head stopped advancing while the chain head moved",
+                Default::default(),
+            )
+            .expect("failed to create `stuck_deployments` gauge");
+        StuckWatchdog { config, stuck }
+    }
+
+    /// Watch `deployment` until `store` is dropped, i.e., until the
+    /// deployment is stopped. `start_block` is the lowest start block of
+    /// the data sources in the manifest. If configured, a lifecycle message
+    /// is published with `bus_sender` when the deployment gets stuck
+    pub fn spawn(
+        self: &Arc<Self>,
+        logger: Logger,
+        deployment: DeploymentLocator,
+        store: Weak<dyn WritableStore>,
+        chain_store: Arc<dyn ChainStore>,
+        start_block: BlockNumber,
+        bus_sender: Option<UnboundedSender<BusMessage>>,
+    ) {
+        let watchdog = self.cheap_clone();
+        let bus_sender = bus_sender.filter(|_| self.config.bus_events);
+        let activity = deployment_activity(deployment.hash.as_str());
+        graph::spawn(async move {
+            let mut progress: Option<Progress> = None;
+            let mut stuck = false;
+            loop {
+                tokio::time::sleep(watchdog.config.check_interval()).await;
+
+                let store = match store.upgrade() {
+                    Some(store) => store,
+                    None => break,
+                };
+                let head = store.block_ptr();
+                let chain_head = match chain_store.cheap_clone().cached_head_ptr().await {
+                    Ok(chain_head) => chain_head,
+                    Err(e) => {
+                        warn!(logger, "Failed to get the chain head to check whether the deployment is stuck";
+                              "error" => format!("{:#}", e));
+                        continue;
+                    }
+                };
+                let observation = Observation {
+                    head: head.as_ref().map(|ptr| ptr.number),
+                    chain_head: chain_head.as_ref().map(|ptr| ptr.number),
+                    stream_state: activity.snapshot().stream_state,
+                };
+                let now = Instant::now();
+                let current = match &progress {
+                    Some(progress) => progress,
+                    None => {
+                        progress = Some(Progress::new(&observation, now));
+                        continue;
+                    }
+                };
+
+                match assess(
+                    current,
+                    &observation,
+                    start_block,
+                    watchdog.config.timeout,
+                    now,
+                ) {
+                    Verdict::Moved | Verdict::Waiting(_) => {
+                        if stuck {
+                            info!(logger, "Deployment is no longer stuck";
+                                  "head" => observation.head,
+                                  "chain_head" => observation.chain_head);
+                            watchdog.stuck.dec();
+                            stuck = false;
+                        }
+                        progress = Some(Progress::new(&observation, now));
+                    }
+                    Verdict::Stalled | Verdict::Stuck if stuck => {
+                        // A deployment that fails while it is stuck is no
+                        // longer counted, like one that failed before it
+                        // got stuck
+                        if let Ok(SubgraphHealth::Failed) = store.health().await {
+                            info!(logger, "Stuck deployment failed");
+                            watchdog.stuck.dec();
+                            stuck = false;
+                        }
+                    }
+                    Verdict::Stalled => {}
+                    Verdict::Stuck => {
+                        // A failed deployment does not advance until it is
+                        // restarted, which is reported elsewhere
+                        if let Ok(SubgraphHealth::Failed) = store.health().await {
+                            continue;
+                        }
+                        let stalled = now.duration_since(current.since);
+                        let snapshot = activity.snapshot();
+                        watchdog.report(&logger, &observation, stalled, &snapshot);
+                        if let Some(sender) = &bus_sender {
+                            let msg = BusMessage {
+                                subgraph_id: deployment.hash.to_string(),
+                                payload: BusPayload::Stuck {
+                                    block: head,
+                                    report: describe(
+                                        observation.head,
+                                        observation.chain_head,
+                                        stalled,
+                                        &snapshot,
+                                    ),
+                                },
+                                critical: None,
+                            };
+                            if sender.send(msg).is_err() {
+                                warn!(logger, "Failed to publish that the deployment is stuck since the bus is not running");
+                            }
+                        }
+                        watchdog.stuck.inc();
+                        stuck = true;
+                    }
+                }
+            }
+            if stuck {
+                watchdog.stuck.dec();
+            }
+        });
+    }
+
+    fn report(
+        &self,
+        logger: &Logger,
+        observation: &Observation,
+        stalled: Duration,
+        snapshot: &ActivitySnapshot,
+    ) {
+        let stream_errors = snapshot
+            .stream_errors
+            .iter()
+            .map(|(error, age)| format!("{}s ago: {}", age.as_secs(), error))
+            .collect::<Vec<_>>()
+            .join("; ");
+        warn!(logger, "Deployment is stuck: its head did not advance while the chain head moved";
+            "head" => observation.head,
+            "chain_head" => observation.chain_head,
+            "stalled_secs" => stalled.as_secs(),
+            "stream_state" => snapshot.stream_state.to_string(),
+            "stream_state_secs" => snapshot.stream_state_age.as_secs(),
+            "last_block_secs_ago" => snapshot.last_block_age.map(|age| age.as_secs()),
+            "last_trigger_handler" => snapshot.last_trigger.as_ref().map(|(handler, _)| handler.clone()),
+            "last_trigger_secs_ago" => snapshot.last_trigger.as_ref().map(|(_, age)| age.as_secs()),
+            "mapping_requests" => snapshot.mapping_requests,
+            "store_request" => snapshot.write.as_ref().map(|(request, _)| request.clone()),
+            "store_request_secs" => snapshot.write.as_ref().map(|(_, age)| age.as_secs()),
+            "store_queue" => snapshot.write_queue,
+            "stream_errors" => stream_errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(600);
+
+    fn observation(head: Option<BlockNumber>, chain_head: Option<BlockNumber>) -> Observation {
+        Observation {
+            head,
+            chain_head,
+            stream_state: StreamState::WaitingForBlocks,
+        }
+    }
+
+    fn progress(
+        head: Option<BlockNumber>,
+        chain_head: Option<BlockNumber>,
+        ago: Duration,
+    ) -> (Progress, Instant) {
+        let now = Instant::now();
+        let progress = Progress {
+            head,
+            chain_head,
+            since: now - ago,
+        };
+        (progress, now)
+    }
+
+    #[test]
+    fn stuck_only_when_chain_moves() {
+        // Stuck at 100 for longer than the timeout while the chain moved
+        let (p, now) = progress(Some(100), Some(200), TIMEOUT * 2);
+        let obs = observation(Some(100), Some(250));
+        assert_eq!(Verdict::Stuck, assess(&p, &obs, 0, TIMEOUT, now));
+
+        // Not for long enough yet
+        let (p, now) = progress(Some(100), Some(200), TIMEOUT / 2);
+        assert_eq!(Verdict::Stalled, assess(&p, &obs, 0, TIMEOUT, now));
+
+        // The chain did not move either
+        let (p, now) = progress(Some(100), Some(250), TIMEOUT * 2);
+        assert_eq!(Verdict::Stalled, assess(&p, &obs, 0, TIMEOUT, now));
+
+        // The deployment moved
+        let (p, now) = progress(Some(90), Some(200), TIMEOUT * 2);
+        assert_eq!(Verdict::Moved, assess(&p, &obs, 0, TIMEOUT, now));
+    }
+
+    #[test]
+    fn waiting_deployments_are_not_stuck() {
+        // The start block is in the future
+        let (p, now) = progress(None, Some(200), TIMEOUT * 2);
+        let obs = observation(None, Some(250));
+        assert!(matches!(
+            assess(&p, &obs, 1_000, TIMEOUT, now),
+            Verdict::Waiting(_)
+        ));
+        // ... but once the chain passed it, the deployment has to move
+        assert_eq!(Verdict::Stuck, assess(&p, &obs, 220, TIMEOUT, now));
+
+        // At the chain head
+        let (p, now) = progress(Some(250), Some(200), TIMEOUT * 2);
+        let obs = observation(Some(250), Some(250));
+        assert!(matches!(
+            assess(&p, &obs, 0, TIMEOUT, now),
+            Verdict::Waiting(_)
+        ));
+
+        // Paused until the store is available
+        let (p, now) = progress(Some(100), Some(200), TIMEOUT * 2);
+        let obs = Observation {
+            stream_state: StreamState::WaitingForStore,
+            ..observation(Some(100), Some(250))
+        };
+        assert!(matches!(
+            assess(&p, &obs, 0, TIMEOUT, now),
+            Verdict::Waiting(_)
+        ));
+
+        // The chain head is not known
+        let obs = observation(Some(100), None);
+        assert!(matches!(
+            assess(&p, &obs, 0, TIMEOUT, now),
+            Verdict::Waiting(_)
+        ));
+    }
+
+    #[test]
+    fn describe_snapshot() {
+        let snapshot = ActivitySnapshot {
+            stream_state: StreamState::ProcessingBlock(101),
+            stream_state_age: Duration::from_secs(700),
+            last_block_age: Some(Duration::from_secs(700)),
+            last_trigger: Some(("handleSwap".to_string(), Duration::from_secs(690))),
+            mapping_requests: 1,
+            write: Some(("write block 100".to_string(), Duration::from_secs(650))),
+            write_queue: 2,
+            stream_errors: vec![("provider timed out".to_string(), Duration::from_secs(800))],
+        };
+        assert_eq!(
+            "head 100 behind chain head 250 for 720s; stream processing_block:101 for 700s; \
+             last block 700s ago; last trigger handleSwap 690s ago; 1 mapping requests; \
+             store executing `write block 100` for 650s with 2 queued; \
+             last stream error 800s ago: provider timed out",
+            describe(Some(100), Some(250), Duration::from_secs(720), &snapshot)
+        );
+    }
+}
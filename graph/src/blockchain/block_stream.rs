@@ -172,6 +172,9 @@ impl AsRef<Option<String>> for FirehoseCursor {
 pub struct BlockWithTriggers<C: Blockchain> {
     pub block: C::Block,
     pub trigger_data: Vec<C::TriggerData>,
+    /// The label of the provider that served the block and its trigger
+    /// data; `None` if the chain does not track providers
+    pub provider: Option<String>,
 }
 
 impl<C: Blockchain> Clone for BlockWithTriggers<C>
@@ -182,6 +185,7 @@ where
         Self {
             block: self.block.clone(),
             trigger_data: self.trigger_data.clone(),
+            provider: self.provider.clone(),
         }
     }
 }
@@ -215,9 +219,17 @@ impl<C: Blockchain> BlockWithTriggers<C> {
         Self {
             block,
             trigger_data,
+            provider: None,
         }
     }
 
+    /// Attribute the block and its trigger data to the provider with label
+    /// `provider`
+    pub fn with_provider(mut self, provider: &str) -> Self {
+        self.provider = Some(provider.to_string());
+        self
+    }
+
     pub fn trigger_count(&self) -> usize {
         self.trigger_data.len()
     }
@@ -270,6 +282,13 @@ pub trait TriggersAdapter<C: Blockchain>: Send + Sync {
 
     /// Get pointer to parent of `block`. This is called when reverting `block`.
     async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error>;
+
+    /// The trigger that runs the block finalizers of the data sources after
+    /// all other triggers of `block` were processed. Returns `None` for
+    /// chains that do not support block finalizers.
+    fn block_finalizer_trigger(&self, _block: &C::Block) -> Option<C::TriggerData> {
+        None
+    }
 }
 
 #[async_trait]
@@ -317,6 +317,7 @@ impl Blockchain for MockBlockchain {
         _subgraph_current_block: Option<BlockPtr>,
         _filter: std::sync::Arc<Self::TriggerFilter>,
         _unified_api_version: crate::data::subgraph::UnifiedMappingApiVersion,
+        _reorg_threshold: Option<crate::components::store::BlockNumber>,
     ) -> Result<Box<dyn block_stream::BlockStream<Self>>, anyhow::Error> {
         todo!()
     }
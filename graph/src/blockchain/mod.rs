@@ -8,6 +8,7 @@ pub mod firehose_block_ingestor;
 pub mod firehose_block_stream;
 pub mod mock;
 pub mod polling_block_stream;
+pub mod replay;
 pub mod substreams_block_stream;
 mod types;
 
@@ -17,7 +18,7 @@ use crate::{
     components::store::{DeploymentLocator, StoredDynamicDataSource},
     data::subgraph::UnifiedMappingApiVersion,
     data_source,
-    prelude::DataSourceContext,
+    prelude::{DataSourceContext, DeploymentHash},
     runtime::{gas::GasCounter, AscHeap, HostExportError},
 };
 use crate::{
@@ -36,6 +37,7 @@ use std::{
     any::Any,
     collections::HashMap,
     fmt::{self, Debug},
+    ops::RangeInclusive,
     str::FromStr,
     sync::Arc,
 };
@@ -173,6 +175,8 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         subgraph_current_block: Option<BlockPtr>,
         filter: Arc<Self::TriggerFilter>,
         unified_api_version: UnifiedMappingApiVersion,
+        // The reorg threshold that the deployment set for itself, if any
+        reorg_threshold: Option<BlockNumber>,
     ) -> Result<Box<dyn BlockStream<Self>>, Error>;
 
     fn chain_store(&self) -> Arc<dyn ChainStore>;
@@ -194,6 +198,47 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
     fn runtime_adapter(&self) -> Arc<dyn RuntimeAdapter<Self>>;
 
     fn is_firehose_supported(&self) -> bool;
+
+    /// The reorg thresholds that deployments on this chain may use instead
+    /// of the reorg threshold of the chain: from the smallest one that the
+    /// finality guarantees of the chain allow up to the reorg threshold of
+    /// the chain itself. `None` if deployments on this chain can not
+    /// change their reorg threshold
+    fn reorg_threshold_range(&self) -> Option<RangeInclusive<BlockNumber>> {
+        None
+    }
+}
+
+/// Check that a deployment may use `reorg_threshold` instead of the reorg
+/// threshold of its chain, where `range` is what the chain allows (see
+/// `Blockchain::reorg_threshold_range`)
+pub fn validate_reorg_threshold(
+    reorg_threshold: BlockNumber,
+    range: Option<RangeInclusive<BlockNumber>>,
+) -> Result<(), Error> {
+    let range = match range {
+        Some(range) => range,
+        None => {
+            return Err(anyhow!(
+                "the chain does not allow subgraphs to change the reorg threshold"
+            ))
+        }
+    };
+    if reorg_threshold > *range.end() {
+        return Err(anyhow!(
+            "the reorg threshold {} is larger than the reorg threshold {} of the chain; it can only be lowered",
+            reorg_threshold,
+            range.end()
+        ));
+    }
+    if reorg_threshold < *range.start() {
+        return Err(anyhow!(
+            "the reorg threshold {} is smaller than {}, the smallest reorg threshold that the finality of the chain allows",
+            reorg_threshold,
+            range.start()
+        ));
+    }
+    Ok(())
 }
 
 #[derive(Error, Debug)]
@@ -255,6 +300,19 @@ pub trait DataSource<C: Blockchain>: 'static + Sized + Send + Sync + Clone {
     fn api_version(&self) -> semver::Version;
     fn runtime(&self) -> Option<Arc<Vec<u8>>>;
 
+    /// The entity types listed under `entities` in the manifest for this
+    /// data source, if data sources of this kind have such a list.
+    fn declared_entities(&self) -> Option<&[String]> {
+        None
+    }
+
+    /// The number of handlers that the manifest declares for this data
+    /// source. Data sources of kinds that do not have a list of handlers
+    /// count as one handler.
+    fn handler_count(&self) -> usize {
+        1
+    }
+
     /// Checks if `trigger` matches this data source, and if so decodes it into a `MappingTrigger`.
     /// A return of `Ok(None)` mean the trigger does not match.
     ///
@@ -320,6 +378,7 @@ pub trait TriggerData {
 
 pub struct HostFnCtx<'a> {
     pub logger: Logger,
+    pub deployment: DeploymentHash,
     pub block_ptr: BlockPtr,
     pub heap: &'a mut dyn AscHeap,
     pub gas: GasCounter,
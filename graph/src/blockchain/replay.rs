@@ -0,0 +1,296 @@
+//! A portable file of decoded triggers, so that the mappings of a
+//! deployment can be run against real triggers without a node, a provider
+//! or a database.
+//!
+//! `graphman triggers export` writes the triggers that the data sources of
+//! a deployment matched in a range of blocks, and `graph-node test-run`
+//! runs a mapping against them. The file itself does not depend on the
+//! chain; each chain decides how its blocks and triggers are represented
+//! through [`ReplayableTrigger`].
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Error};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{BlockHash, BlockPtr};
+use crate::data::store::scalar::Bytes;
+use crate::prelude::{BlockNumber, DataSourceContext};
+
+/// The version of the file format that this node writes. Files with a
+/// higher version can not be read
+pub const TRIGGER_FILE_VERSION: u32 = 1;
+
+/// A mapping trigger that can be written to a trigger file and read back.
+/// The block of the trigger is stored once for all triggers in the block
+pub trait ReplayableTrigger: Sized {
+    type Block: Serialize + DeserializeOwned;
+
+    /// The block that the trigger happened in
+    fn replay_block(&self) -> &Self::Block;
+
+    /// The trigger without its block
+    fn to_replay(&self) -> Result<serde_json::Value, Error>;
+
+    /// Reassemble a trigger that `to_replay` produced within `block`
+    fn from_replay(block: &Arc<Self::Block>, trigger: serde_json::Value) -> Result<Self, Error>;
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TriggerFile {
+    pub version: u32,
+    /// The hash of the deployment whose triggers were exported
+    pub deployment: String,
+    /// The kind of chain, like `ethereum`
+    pub chain: String,
+    pub network: String,
+    /// The spec version of the manifest, which decides how the proof of
+    /// indexing is computed
+    pub spec_version: String,
+    /// The GraphQL schema of the deployment
+    pub schema: String,
+    /// The data sources that handle the triggers
+    pub data_sources: Vec<ReplayDataSource>,
+    /// The blocks in the exported range that have triggers, in order
+    pub blocks: Vec<ReplayBlock>,
+    /// The versions of graph-node that indexed the exported range, oldest
+    /// first. Files from nodes that did not record versions have none
+    #[serde(default)]
+    pub node_versions: Vec<ReplayNodeVersion>,
+}
+
+/// What the mappings can learn about the data source that handles a
+/// trigger
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayDataSource {
+    pub name: String,
+    pub address: Option<Bytes>,
+    pub api_version: String,
+    pub context: Option<DataSourceContext>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayBlock {
+    pub number: BlockNumber,
+    pub hash: String,
+    /// The block in the chain's representation
+    pub data: serde_json::Value,
+    pub triggers: Vec<ReplayTrigger>,
+}
+
+impl ReplayBlock {
+    pub fn ptr(&self) -> Result<BlockPtr, Error> {
+        let hash = BlockHash::from_str(&self.hash)
+            .with_context(|| format!("invalid hash for block {}", self.number))?;
+        Ok(BlockPtr::new(hash, self.number))
+    }
+}
+
+/// A version of graph-node that indexed some of the exported blocks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayNodeVersion {
+    pub git_commit_hash: String,
+    pub crate_version: String,
+    pub host_export_hash: String,
+    pub start_block: Option<BlockNumber>,
+    pub end_block: Option<BlockNumber>,
+}
+
+/// A trigger and the handler of the data source that it was matched to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayTrigger {
+    /// The position of the data source in the data sources of the file;
+    /// data sources created from the same template share their name
+    pub data_source: usize,
+    pub handler: String,
+    /// The trigger in the chain's representation
+    pub trigger: serde_json::Value,
+}
+
+impl TriggerFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read(path)
+            .with_context(|| format!("failed to read trigger file {}", path.display()))?;
+        Self::from_slice(&contents)
+            .with_context(|| format!("invalid trigger file {}", path.display()))
+    }
+
+    pub fn from_slice(contents: &[u8]) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        // Check the version before anything else so that files from newer
+        // nodes fail with a clear message
+        let Version { version } = serde_json::from_slice(contents)?;
+        if version > TRIGGER_FILE_VERSION {
+            return Err(anyhow!(
+                "the trigger file has version {} but this node only understands versions up to {}",
+                version,
+                TRIGGER_FILE_VERSION
+            ));
+        }
+        Ok(serde_json::from_slice(contents)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let contents = serde_json::to_vec(self)?;
+        fs::write(path, contents)
+            .with_context(|| format!("failed to write trigger file {}", path.display()))
+    }
+
+    pub fn has_data_source(&self, name: &str) -> bool {
+        self.data_sources.iter().any(|ds| ds.name == name)
+    }
+
+    /// Reasons why proofs of indexing from replaying the file with a node
+    /// whose host exports hash to `host_export_hash` might not match the
+    /// ones of the deployment, or might not match each other across the
+    /// exported range
+    pub fn version_warnings(&self, host_export_hash: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut builds: Vec<_> = self
+            .node_versions
+            .iter()
+            .map(|version| (&version.git_commit_hash, &version.host_export_hash))
+            .collect();
+        builds.sort();
+        builds.dedup();
+        if builds.len() > 1 {
+            let ranges: Vec<_> = self
+                .node_versions
+                .iter()
+                .map(|version| {
+                    format!(
+                        "{} ({}) for blocks {}..{}",
+                        version.crate_version,
+                        version.git_commit_hash,
+                        version
+                            .start_block
+                            .map_or("start".to_string(), |block| block.to_string()),
+                        version
+                            .end_block
+                            .map_or("head".to_string(), |block| block.to_string())
+                    )
+                })
+                .collect();
+            warnings.push(format!(
+                "the exported blocks were indexed by different builds of graph-node: {}",
+                ranges.join(", ")
+            ));
+        }
+        for version in &self.node_versions {
+            if version.host_export_hash != host_export_hash {
+                warnings.push(format!(
+                    "graph-node {} ({}) indexed some of the exported blocks with different \
+                     host exports than this node has",
+                    version.crate_version, version.git_commit_hash
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity;
+
+    fn file() -> TriggerFile {
+        TriggerFile {
+            version: TRIGGER_FILE_VERSION,
+            deployment: "QmReplay".to_string(),
+            chain: "ethereum".to_string(),
+            network: "mainnet".to_string(),
+            spec_version: "0.0.5".to_string(),
+            schema: "type Swap @entity { id: ID! }".to_string(),
+            data_sources: vec![ReplayDataSource {
+                name: "Pool".to_string(),
+                address: Some(Bytes::from(vec![0xab; 20].as_slice())),
+                api_version: "0.0.7".to_string(),
+                context: Some(entity! { fee: 3000 }),
+            }],
+            blocks: vec![ReplayBlock {
+                number: 7,
+                hash: format!("0x{}", "12".repeat(32)),
+                data: serde_json::json!({ "number": "0x7" }),
+                triggers: vec![ReplayTrigger {
+                    data_source: 0,
+                    handler: "handleSwap".to_string(),
+                    trigger: serde_json::json!({ "kind": "block" }),
+                }],
+            }],
+            node_versions: vec![],
+        }
+    }
+
+    fn node_version(git_commit_hash: &str, host_export_hash: &str) -> ReplayNodeVersion {
+        ReplayNodeVersion {
+            git_commit_hash: git_commit_hash.to_string(),
+            crate_version: "0.30.0".to_string(),
+            host_export_hash: host_export_hash.to_string(),
+            start_block: None,
+            end_block: None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = file();
+        let contents = serde_json::to_vec(&file).unwrap();
+        assert_eq!(file, TriggerFile::from_slice(&contents).unwrap());
+        assert_eq!(7, file.blocks[0].ptr().unwrap().number);
+        assert!(file.has_data_source("Pool"));
+        assert!(!file.has_data_source("Factory"));
+    }
+
+    #[test]
+    fn version_warnings() {
+        let mut file = file();
+        assert!(file.version_warnings("h1").is_empty());
+
+        file.node_versions = vec![node_version("c1", "h1")];
+        assert!(file.version_warnings("h1").is_empty());
+        assert_eq!(1, file.version_warnings("h2").len());
+
+        let mut second = node_version("c2", "h1");
+        second.start_block = Some(5);
+        file.node_versions[0].end_block = Some(4);
+        file.node_versions.push(second);
+        let warnings = file.version_warnings("h1");
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].contains("different builds"));
+        assert!(warnings[0].contains("blocks start..4"));
+        assert!(warnings[0].contains("blocks 5..head"));
+    }
+
+    #[test]
+    fn older_file_without_versions() {
+        let mut contents = serde_json::to_value(file()).unwrap();
+        contents.as_object_mut().unwrap().remove("nodeVersions");
+        let file = TriggerFile::from_slice(&serde_json::to_vec(&contents).unwrap()).unwrap();
+        assert!(file.node_versions.is_empty());
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut file = file();
+        file.version = TRIGGER_FILE_VERSION + 1;
+        let contents = serde_json::to_vec(&file).unwrap();
+        let err = TriggerFile::from_slice(&contents).unwrap_err();
+        assert!(err.to_string().contains("only understands versions"));
+    }
+}
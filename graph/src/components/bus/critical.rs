@@ -0,0 +1,194 @@
+//! Track the delivery of messages that contain changes to entity types
+//! that a deployment marks as critical.
+//!
+//! Consumers like reconciliation systems need to know up to which block
+//! the changes to some entity types were delivered. The critical entity
+//! types of a deployment are set with graphman and stored with the
+//! deployment, and the runner attaches them as [`CriticalTypes`] to the
+//! messages it publishes. Bus backends always wait for the broker to
+//! confirm messages with critical changes, retry them more often than
+//! other messages, and report each confirmation and each message they
+//! gave up on to their [`CriticalDelivery`], which records them with the
+//! deployment. The indexing status reports up to which block the changes
+//! to each critical type were delivered.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use prometheus::{CounterVec, GaugeVec};
+
+use crate::components::metrics::MetricsRegistry;
+use crate::components::store::{EntityType, StoreError, WritableStore};
+use crate::prelude::BlockNumber;
+
+use super::BusPayload;
+
+/// The critical entity types of a deployment, and the store in which the
+/// delivery of changes to them is recorded
+#[derive(Clone)]
+pub struct CriticalTypes {
+    entity_types: Arc<BTreeSet<EntityType>>,
+    store: Arc<dyn WritableStore>,
+}
+
+impl CriticalTypes {
+    /// The critical types `entity_types` of the deployment of `store`;
+    /// `None` if there are none
+    pub fn new(entity_types: BTreeSet<EntityType>, store: Arc<dyn WritableStore>) -> Option<Self> {
+        if entity_types.is_empty() {
+            return None;
+        }
+        Some(CriticalTypes {
+            entity_types: Arc::new(entity_types),
+            store,
+        })
+    }
+
+    /// The critical entity types that `payload` contains changes for
+    pub fn in_payload(&self, payload: &BusPayload) -> BTreeSet<EntityType> {
+        critical_in_payload(&self.entity_types, payload)
+    }
+}
+
+fn critical_in_payload(
+    critical: &BTreeSet<EntityType>,
+    payload: &BusPayload,
+) -> BTreeSet<EntityType> {
+    match payload {
+        BusPayload::PlainText(_) | BusPayload::Synced { .. } | BusPayload::Stuck { .. } => {
+            BTreeSet::new()
+        }
+        BusPayload::Modifications { modifications, .. } => modifications
+            .iter()
+            .map(|modification| &modification.entity_ref().entity_type)
+            .filter(|entity_type| critical.contains(*entity_type))
+            .cloned()
+            .collect(),
+        BusPayload::BlockSummary { counts, .. } => counts
+            .keys()
+            .filter(|entity_type| critical.contains(*entity_type))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Records the delivery of messages with critical changes for one backend
+pub struct CriticalDelivery {
+    backend: String,
+    last_confirmed: GaugeVec,
+    dead_letters: CounterVec,
+}
+
+impl CriticalDelivery {
+    pub fn new(backend: &str, registry: Arc<dyn MetricsRegistry>) -> Self {
+        let last_confirmed = registry
+            .global_gauge_vec(
+                "bus_critical_last_confirmed_block",
+                "The block up to which the broker confirmed all changes to a critical entity type",
+                &["deployment", "entity_type", "backend"],
+            )
+            .expect("failed to create `bus_critical_last_confirmed_block` gauge");
+        let dead_letters = registry
+            .global_counter_vec(
+                "bus_critical_dead_letters",
+                "The number of messages with changes to a critical entity type that were given up on",
+                &["deployment", "entity_type", "backend"],
+            )
+            .expect("failed to create `bus_critical_dead_letters` counter");
+        CriticalDelivery {
+            backend: backend.to_string(),
+            last_confirmed,
+            dead_letters,
+        }
+    }
+
+    /// Record that the broker confirmed the changes of `deployment` to
+    /// `entity_types` of `critical` in `block`
+    pub async fn confirmed(
+        &self,
+        deployment: &str,
+        critical: &CriticalTypes,
+        entity_types: &BTreeSet<EntityType>,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        let confirmed = critical
+            .store
+            .record_bus_critical_delivery(self.backend.clone(), entity_types.clone(), block, true)
+            .await?;
+        for (entity_type, block) in confirmed {
+            if let Some(block) = block {
+                self.last_confirmed
+                    .with_label_values(&[deployment, entity_type.as_str(), &self.backend])
+                    .set(block as f64);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that the changes of `deployment` to `entity_types` of
+    /// `critical` in `block` could not be delivered
+    pub async fn dead_lettered(
+        &self,
+        deployment: &str,
+        critical: &CriticalTypes,
+        entity_types: &BTreeSet<EntityType>,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        for entity_type in entity_types {
+            self.dead_letters
+                .with_label_values(&[deployment, entity_type.as_str(), &self.backend])
+                .inc();
+        }
+        critical
+            .store
+            .record_bus_critical_delivery(self.backend.clone(), entity_types.clone(), block, false)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::components::bus::EntityCounts;
+    use crate::components::store::{EntityKey, EntityModification};
+    use crate::prelude::{BlockPtr, Entity};
+
+    #[test]
+    fn critical_types_in_payload() {
+        let critical: BTreeSet<_> = vec![EntityType::from("Swap")].into_iter().collect();
+        let block = BlockPtr::from((web3::types::H256::zero(), 7 as BlockNumber));
+        let modifications = vec![
+            EntityModification::Insert {
+                key: EntityKey::data("Swap".to_string(), "1".to_string()),
+                data: Entity::new(),
+            },
+            EntityModification::Insert {
+                key: EntityKey::data("Mint".to_string(), "1".to_string()),
+                data: Entity::new(),
+            },
+        ];
+        let counts = EntityCounts::from_modifications(&modifications);
+
+        let payload = BusPayload::Modifications {
+            block: block.clone(),
+            modifications,
+        };
+        assert_eq!(critical, critical_in_payload(&critical, &payload));
+        assert!(critical_in_payload(&BTreeSet::new(), &payload).is_empty());
+
+        let payload = BusPayload::BlockSummary {
+            block: block.clone(),
+            counts,
+        };
+        assert_eq!(critical, critical_in_payload(&critical, &payload));
+
+        let payload = BusPayload::BlockSummary {
+            block,
+            counts: BTreeMap::new(),
+        };
+        assert!(critical_in_payload(&critical, &payload).is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+//! Measure where the time that bus backends spend on generated messages
+//! goes: serializing batches of entity changes, or publishing them to the
+//! broker and waiting for its confirmation.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use prometheus::HistogramVec;
+
+use crate::components::metrics::MetricsRegistry;
+
+/// The histograms of all backends. Create them once and hand each backend
+/// its own [`BackendBusMetrics`]
+pub struct BusMetrics {
+    serialization_time: HistogramVec,
+    serialized_bytes: HistogramVec,
+    publish_time: HistogramVec,
+}
+
+impl BusMetrics {
+    pub fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        let labels = vec!["deployment".to_string(), "backend".to_string()];
+        let serialization_time = registry
+            .new_histogram_vec(
+                "bus_serialization_secs",
+                "How long serializing a batch of generated bus messages took",
+                labels.clone(),
+                vec![0.0001, 0.001, 0.01, 0.1, 1.0, 10.0],
+            )
+            .expect("failed to create `bus_serialization_secs` histogram");
+        let serialized_bytes = registry
+            .new_histogram_vec(
+                "bus_serialized_bytes",
+                "The size of serialized batches of generated bus messages",
+                labels.clone(),
+                vec![1e3, 1e4, 1e5, 1e6, 1e7, 1e8],
+            )
+            .expect("failed to create `bus_serialized_bytes` histogram");
+        let publish_time = registry
+            .new_histogram_vec(
+                "bus_publish_secs",
+                "How long publishing a batch of generated bus messages until the broker confirmed it took",
+                labels,
+                vec![0.001, 0.01, 0.1, 1.0, 10.0, 60.0],
+            )
+            .expect("failed to create `bus_publish_secs` histogram");
+        Self::with_metrics(*serialization_time, *serialized_bytes, *publish_time)
+    }
+
+    fn with_metrics(
+        serialization_time: HistogramVec,
+        serialized_bytes: HistogramVec,
+        publish_time: HistogramVec,
+    ) -> Self {
+        BusMetrics {
+            serialization_time,
+            serialized_bytes,
+            publish_time,
+        }
+    }
+
+    pub fn for_backend(&self, backend: &str) -> BackendBusMetrics {
+        BackendBusMetrics {
+            backend: backend.to_string(),
+            serialization_time: self.serialization_time.clone(),
+            serialized_bytes: self.serialized_bytes.clone(),
+            publish_time: self.publish_time.clone(),
+        }
+    }
+}
+
+/// The histograms of one backend
+#[derive(Clone)]
+pub struct BackendBusMetrics {
+    backend: String,
+    serialization_time: HistogramVec,
+    serialized_bytes: HistogramVec,
+    publish_time: HistogramVec,
+}
+
+impl BackendBusMetrics {
+    /// Record that serializing a batch of `deployment` took `duration` and
+    /// produced `bytes` bytes
+    pub fn serialized(&self, deployment: &str, duration: Duration, bytes: usize) {
+        let labels = [deployment, self.backend.as_str()];
+        self.serialization_time
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+        self.serialized_bytes
+            .with_label_values(&labels)
+            .observe(bytes as f64);
+    }
+
+    /// Record that publishing a batch of `deployment` took `duration`,
+    /// whether the broker confirmed it or not
+    pub fn published(&self, deployment: &str, duration: Duration) {
+        self.publish_time
+            .with_label_values(&[deployment, self.backend.as_str()])
+            .observe(duration.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::HistogramOpts;
+
+    use super::*;
+
+    #[test]
+    fn backends_share_histograms() {
+        let labels = &["deployment", "backend"];
+        let histogram = |name: &str| HistogramVec::new(HistogramOpts::new(name, name), labels);
+        let metrics = BusMetrics::with_metrics(
+            histogram("serialization").unwrap(),
+            histogram("bytes").unwrap(),
+            histogram("publish").unwrap(),
+        );
+
+        let primary = metrics.for_backend("primary");
+        let secondary = metrics.for_backend("secondary");
+        primary.serialized("QmA", Duration::from_millis(2), 1500);
+        primary.serialized("QmA", Duration::from_millis(4), 500);
+        secondary.published("QmA", Duration::from_millis(30));
+
+        let bytes = metrics
+            .serialized_bytes
+            .with_label_values(&["QmA", "primary"]);
+        assert_eq!(2, bytes.get_sample_count());
+        assert_eq!(2000.0, bytes.get_sample_sum());
+        let publish = |backend| {
+            metrics
+                .publish_time
+                .with_label_values(&["QmA", backend])
+                .get_sample_count()
+        };
+        assert_eq!(0, publish("primary"));
+        assert_eq!(1, publish("secondary"));
+    }
+}
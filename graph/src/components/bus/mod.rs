@@ -1,5 +1,11 @@
+pub mod critical;
 pub mod err;
+pub mod metrics;
+pub mod routing;
 pub mod traits;
 
+pub use critical::*;
 pub use err::*;
+pub use metrics::*;
+pub use routing::*;
 pub use traits::*;
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Error};
+use regex::Regex;
+
+use crate::components::store::DeploymentLocator;
+use crate::tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use super::BusMessage;
+
+/// Which deployments a route applies to
+#[derive(Clone, Debug)]
+pub enum DeploymentMatcher {
+    /// Deployments with one of these hashes
+    Hashes(HashSet<String>),
+    /// Deployments whose subgraph name matches the regular expression in
+    /// its entirety; construct it with [`DeploymentMatcher::name`], which
+    /// anchors the expression
+    Name(Regex),
+}
+
+impl DeploymentMatcher {
+    /// Match the deployments whose entire subgraph name matches `pattern`
+    pub fn name(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern)).map(DeploymentMatcher::Name)
+    }
+
+    fn matches(&self, deployment: &DeploymentLocator) -> bool {
+        match self {
+            DeploymentMatcher::Hashes(hashes) => hashes.contains(deployment.hash.as_str()),
+            DeploymentMatcher::Name(regex) => deployment
+                .name
+                .as_deref()
+                .map_or(false, |name| regex.is_match(name)),
+        }
+    }
+}
+
+/// Send the messages of the deployments that `matcher` matches to the
+/// bus backends named in `backends`
+#[derive(Clone, Debug)]
+pub struct BusRoute {
+    pub matcher: DeploymentMatcher,
+    pub backends: Vec<String>,
+}
+
+/// Decides which of the configured bus backends each deployment publishes
+/// to. The first route that matches a deployment wins; deployments that no
+/// route matches publish to the `default` backends, which may be empty to
+/// not publish at all
+#[derive(Debug, Default)]
+pub struct BusRouter {
+    backends: BTreeMap<String, UnboundedSender<BusMessage>>,
+    routes: Vec<BusRoute>,
+    default: Vec<String>,
+}
+
+impl BusRouter {
+    /// Create a router. `backends` maps the name of each backend to the
+    /// sender for the messages the backend should publish. Fails if a route
+    /// or the default refers to a backend that does not exist
+    pub fn new(
+        backends: BTreeMap<String, UnboundedSender<BusMessage>>,
+        routes: Vec<BusRoute>,
+        default: Vec<String>,
+    ) -> Result<Self, Error> {
+        for (i, route) in routes.iter().enumerate() {
+            for name in &route.backends {
+                if !backends.contains_key(name) {
+                    return Err(anyhow!("unknown bus backend `{}` in bus route {}", name, i));
+                }
+            }
+        }
+        for name in &default {
+            if !backends.contains_key(name) {
+                return Err(anyhow!("unknown default bus backend `{}`", name));
+            }
+        }
+        Ok(Self {
+            backends,
+            routes,
+            default,
+        })
+    }
+
+    /// The names of the backends that `deployment` publishes to
+    pub fn backends_for(&self, deployment: &DeploymentLocator) -> &[String] {
+        self.routes
+            .iter()
+            .find(|route| route.matcher.matches(deployment))
+            .map(|route| route.backends.as_slice())
+            .unwrap_or(self.default.as_slice())
+    }
+
+    /// Return the sender that `deployment` should use to publish messages,
+    /// or `None` if it does not publish to any backend. If the deployment
+    /// publishes to several backends, each message is copied to all of
+    /// them
+    pub fn publisher(&self, deployment: &DeploymentLocator) -> Option<UnboundedSender<BusMessage>> {
+        let mut senders: Vec<_> = self
+            .backends_for(deployment)
+            .iter()
+            .filter_map(|name| self.backends.get(name).cloned())
+            .collect();
+
+        match senders.len() {
+            0 => None,
+            1 => senders.pop(),
+            _ => {
+                let (sender, mut receiver) = unbounded_channel::<BusMessage>();
+                crate::spawn(async move {
+                    while let Some(msg) = receiver.recv().await {
+                        for sender in &senders {
+                            // A backend that went away is not a reason to
+                            // stop publishing to the others
+                            let _ = sender.send(msg.clone());
+                        }
+                    }
+                });
+                Some(sender)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::bus::BusPayload;
+    use crate::components::store::DeploymentId;
+    use crate::prelude::DeploymentHash;
+
+    fn locator(hash: &str, name: Option<&str>) -> DeploymentLocator {
+        DeploymentLocator::new(
+            DeploymentId::new(1),
+            DeploymentHash::new(hash).unwrap(),
+            name.map(str::to_string),
+        )
+    }
+
+    fn backends(names: &[&str]) -> BTreeMap<String, UnboundedSender<BusMessage>> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), unbounded_channel().0))
+            .collect()
+    }
+
+    #[test]
+    fn routes() {
+        let routes = vec![
+            BusRoute {
+                matcher: DeploymentMatcher::Hashes(HashSet::from(["QmA".to_string()])),
+                backends: vec!["rabbit".to_string()],
+            },
+            BusRoute {
+                matcher: DeploymentMatcher::name("uniswap/.*").unwrap(),
+                backends: vec!["rabbit".to_string(), "pubsub".to_string()],
+            },
+        ];
+        let router = BusRouter::new(
+            backends(&["rabbit", "pubsub"]),
+            routes.clone(),
+            vec!["pubsub".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(&["rabbit"], router.backends_for(&locator("QmA", None)));
+        assert_eq!(
+            &["rabbit", "pubsub"],
+            router.backends_for(&locator("QmB", Some("uniswap/v3")))
+        );
+        assert_eq!(
+            &["pubsub"],
+            router.backends_for(&locator("QmC", Some("other")))
+        );
+        // The name has to match entirely
+        assert_eq!(
+            &["pubsub"],
+            router.backends_for(&locator("QmD", Some("fork/uniswap/v3")))
+        );
+
+        // Without a default, unmatched deployments do not publish
+        let router = BusRouter::new(backends(&["rabbit", "pubsub"]), routes, vec![]).unwrap();
+        assert!(router.backends_for(&locator("QmC", None)).is_empty());
+        assert!(router.publisher(&locator("QmC", None)).is_none());
+    }
+
+    #[test]
+    fn name_matches_entirely() {
+        // The leftmost match of `a|ab` in `ab` is `a`, but the whole name
+        // still matches
+        let matcher = DeploymentMatcher::name("a|ab").unwrap();
+        assert!(matcher.matches(&locator("QmA", Some("ab"))));
+        assert!(matcher.matches(&locator("QmA", Some("a"))));
+        assert!(!matcher.matches(&locator("QmA", Some("abc"))));
+        assert!(!matcher.matches(&locator("QmA", Some("xab"))));
+        assert!(!matcher.matches(&locator("QmA", None)));
+    }
+
+    #[test]
+    fn unknown_backends() {
+        let route = BusRoute {
+            matcher: DeploymentMatcher::Hashes(HashSet::from(["QmA".to_string()])),
+            backends: vec!["kafka".to_string()],
+        };
+        assert!(BusRouter::new(backends(&["pubsub"]), vec![route], vec![]).is_err());
+        assert!(BusRouter::new(backends(&["pubsub"]), vec![], vec!["kafka".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn fan_out() {
+        let (rabbit, mut rabbit_rx) = unbounded_channel();
+        let (pubsub, mut pubsub_rx) = unbounded_channel();
+        let backends = BTreeMap::from([
+            ("rabbit".to_string(), rabbit),
+            ("pubsub".to_string(), pubsub),
+        ]);
+        let router = BusRouter::new(
+            backends,
+            vec![],
+            vec!["rabbit".to_string(), "pubsub".to_string()],
+        )
+        .unwrap();
+
+        let publisher = router.publisher(&locator("QmA", None)).unwrap();
+        publisher
+            .send(BusMessage {
+                subgraph_id: "QmA".to_string(),
+                payload: BusPayload::PlainText(vec!["topic".to_string()]),
+                critical: None,
+            })
+            .unwrap();
+
+        for rx in [&mut rabbit_rx, &mut pubsub_rx] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!("QmA", msg.subgraph_id);
+        }
+    }
+}
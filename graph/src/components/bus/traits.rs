@@ -1,11 +1,116 @@
+use super::critical::CriticalTypes;
 use super::err::BusError;
-use crate::prelude::Logger;
+use crate::components::store::{EntityModification, EntityType};
+use crate::prelude::{BlockPtr, Logger};
 use crate::tokio::sync::mpsc::UnboundedReceiver;
 use async_trait::async_trait;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
 
+#[derive(Clone)]
 pub struct BusMessage {
     pub subgraph_id: String,
-    pub value: Vec<String>,
+    pub payload: BusPayload,
+    /// The critical entity types of the deployment; `None` if it has none
+    /// or the message can not contain entity changes
+    pub critical: Option<CriticalTypes>,
+}
+
+#[derive(Clone)]
+pub enum BusPayload {
+    /// The arguments of a call to `bus.send` from a mapping; the first one
+    /// is the topic
+    PlainText(Vec<String>),
+    /// All entity changes that the deployment made in `block`
+    Modifications {
+        block: BlockPtr,
+        modifications: Vec<EntityModification>,
+    },
+    /// How many entities of each type the deployment changed in `block`
+    BlockSummary {
+        block: BlockPtr,
+        counts: BTreeMap<EntityType, EntityCounts>,
+    },
+    /// The deployment reached the chain head for the first time at `block`
+    Synced { block: BlockPtr },
+    /// The deployment stopped advancing at `block` although its chain
+    /// kept moving; `report` describes what it was doing
+    Stuck {
+        block: Option<BlockPtr>,
+        report: String,
+    },
+}
+
+/// The number of changes of each kind to entities of one type
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EntityCounts {
+    pub inserts: u32,
+    pub updates: u32,
+    pub removes: u32,
+}
+
+impl EntityCounts {
+    /// Count the changes in `modifications` by entity type. Changes to the
+    /// PoI are internal to graph-node and not counted
+    pub fn from_modifications(
+        modifications: &[EntityModification],
+    ) -> BTreeMap<EntityType, EntityCounts> {
+        let mut counts: BTreeMap<EntityType, EntityCounts> = BTreeMap::new();
+        for modification in modifications {
+            let entity_type = &modification.entity_ref().entity_type;
+            if entity_type.is_poi() {
+                continue;
+            }
+            let entry = counts.entry(entity_type.clone()).or_default();
+            match modification {
+                EntityModification::Insert { .. } => entry.inserts += 1,
+                EntityModification::Overwrite { .. } => entry.updates += 1,
+                EntityModification::Remove { .. } => entry.removes += 1,
+            }
+        }
+        counts
+    }
+}
+
+/// Which deployments a bus feature is enabled for. Parsed from an empty
+/// string for none, `*` for all, or a comma-separated list of deployment
+/// hashes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeploymentSelection {
+    None,
+    All,
+    Only(HashSet<String>),
+}
+
+impl DeploymentSelection {
+    pub fn contains(&self, deployment: &str) -> bool {
+        match self {
+            DeploymentSelection::None => false,
+            DeploymentSelection::All => true,
+            DeploymentSelection::Only(deployments) => deployments.contains(deployment),
+        }
+    }
+}
+
+impl FromStr for DeploymentSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(DeploymentSelection::None);
+        }
+        if s == "*" {
+            return Ok(DeploymentSelection::All);
+        }
+        let deployments = s
+            .split(',')
+            .map(str::trim)
+            .filter(|deployment| !deployment.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(DeploymentSelection::Only(deployments))
+    }
 }
 
 #[async_trait]
@@ -18,3 +123,69 @@ pub trait Bus: Send + Sync + 'static {
 
     async fn start(&self, mut receiver: UnboundedReceiver<BusMessage>) -> ();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::store::EntityKey;
+    use crate::data::subgraph::schema::POI_OBJECT;
+    use crate::prelude::Entity;
+
+    #[test]
+    fn count_modifications() {
+        let key =
+            |entity_type: &str, id: &str| EntityKey::data(entity_type.to_string(), id.to_string());
+        let modifications = vec![
+            EntityModification::Insert {
+                key: key("Swap", "1"),
+                data: Entity::new(),
+            },
+            EntityModification::Insert {
+                key: key("Swap", "2"),
+                data: Entity::new(),
+            },
+            EntityModification::Overwrite {
+                key: key("Mint", "1"),
+                data: Entity::new(),
+            },
+            EntityModification::Remove {
+                key: key("Swap", "3"),
+            },
+            EntityModification::Overwrite {
+                key: key(POI_OBJECT, "ethereum/mainnet"),
+                data: Entity::new(),
+            },
+        ];
+        let counts = EntityCounts::from_modifications(&modifications);
+        assert_eq!(2, counts.len());
+        assert_eq!(
+            EntityCounts {
+                inserts: 2,
+                updates: 0,
+                removes: 1
+            },
+            counts[&EntityType::from("Swap")]
+        );
+        assert_eq!(
+            EntityCounts {
+                inserts: 0,
+                updates: 1,
+                removes: 0
+            },
+            counts[&EntityType::from("Mint")]
+        );
+        assert!(EntityCounts::from_modifications(&[]).is_empty());
+    }
+
+    #[test]
+    fn parse_selection() {
+        let parse = |s: &str| DeploymentSelection::from_str(s).unwrap();
+        assert_eq!(DeploymentSelection::None, parse(""));
+        assert_eq!(DeploymentSelection::All, parse("*"));
+        let selection = parse("QmA, QmB,");
+        assert!(selection.contains("QmA"));
+        assert!(selection.contains("QmB"));
+        assert!(!selection.contains("QmC"));
+        assert!(!parse("").contains("QmA"));
+    }
+}
@@ -12,10 +12,11 @@ use std::fmt::Debug;
 
 /// The values that `json_stream` returns. The struct contains the deserialized
 /// JSON value from the input stream, together with the line number from which
-/// the value was read.
+/// the value was read and the number of bytes of that line.
 pub struct JsonStreamValue {
     pub value: Value,
     pub line: usize,
+    pub bytes: usize,
 }
 
 pub type JsonValueStream =
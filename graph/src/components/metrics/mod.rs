@@ -13,6 +13,15 @@ pub mod stopwatch;
 /// Aggregates over individual values.
 pub mod aggregate;
 
+/// Usage of shared infrastructure per deployment.
+pub mod usage;
+
+/// Query rates per deployment.
+pub mod query_rate;
+
+/// Providers that exceeded their request budget.
+pub mod provider_budget;
+
 fn deployment_labels(deployment: &DeploymentLocator) -> HashMap<String, String> {
     labels! {
         String::from("deployment") => deployment.hash.to_string(),
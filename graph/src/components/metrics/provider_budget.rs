@@ -0,0 +1,53 @@
+//! Keep track of the networks whose providers have exceeded their request
+//! budget. The adapters of a chain report with [`set_over_budget`] when the
+//! projected usage of one of their providers goes above or below its
+//! budget, and the indexer uses [`network_over_budget`] to give backfills
+//! on such networks less processing time.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref OVER_BUDGET: Mutex<HashMap<String, BTreeSet<String>>> = Mutex::new(HashMap::new());
+}
+
+/// Record whether `provider` for `network` is over its budget
+pub fn set_over_budget(network: &str, provider: &str, over: bool) {
+    let mut networks = OVER_BUDGET.lock().unwrap();
+    if over {
+        networks
+            .entry(network.to_string())
+            .or_default()
+            .insert(provider.to_string());
+    } else if let Some(providers) = networks.get_mut(network) {
+        providers.remove(provider);
+        if providers.is_empty() {
+            networks.remove(network);
+        }
+    }
+}
+
+/// Whether any provider for `network` is over its budget
+pub fn network_over_budget(network: &str) -> bool {
+    OVER_BUDGET.lock().unwrap().contains_key(network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn over_budget() {
+        // Use a network name no other test uses since the state is global
+        let network = "provider-budget-test";
+        assert!(!network_over_budget(network));
+        set_over_budget(network, "p1", true);
+        set_over_budget(network, "p2", true);
+        set_over_budget(network, "p1", false);
+        assert!(network_over_budget(network));
+        set_over_budget(network, "p2", false);
+        assert!(!network_over_budget(network));
+    }
+}
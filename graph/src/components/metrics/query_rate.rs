@@ -0,0 +1,172 @@
+//! Estimate how many queries per second each deployment receives. The query
+//! server records every query it executes for a deployment with
+//! [`record_query`], and the indexer uses [`query_rate`] to give lagging
+//! deployments that users are actively querying priority over idle
+//! backfills.
+//!
+//! Queries are usually served by different nodes than the ones that index
+//! the deployments. Every node therefore periodically writes the rates it
+//! took from [`local_query_rates`] to the store, and reads the rates of all
+//! query nodes back into [`set_reported_query_rates`].
+//!
+//! The estimate decays exponentially, so that a deployment that stops
+//! receiving queries loses its priority over a few half-lives.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+/// The time after which a query only counts half as much towards the rate
+pub const QUERY_RATE_HALF_LIFE: Duration = Duration::from_secs(300);
+
+/// How often nodes write their query rates to the store and read the rates
+/// of all query nodes from it
+pub const QUERY_RATE_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Rates below this are too small to matter and are forgotten
+const NEGLIGIBLE_RATE: f64 = 1e-4;
+
+lazy_static! {
+    static ref RATES: Mutex<HashMap<String, DecayingCount>> = Mutex::new(HashMap::new());
+    static ref REPORTED: Mutex<HashMap<String, f64>> = Mutex::new(HashMap::new());
+}
+
+/// Record that a query for `deployment` was executed
+pub fn record_query(deployment: &str) {
+    let now = Instant::now();
+    let mut rates = RATES.lock().unwrap();
+    match rates.get_mut(deployment) {
+        Some(count) => count.add(now),
+        None => {
+            let mut count = DecayingCount::new(now);
+            count.add(now);
+            rates.insert(deployment.to_string(), count);
+        }
+    }
+}
+
+/// The estimated number of queries per second that `deployment` receives
+/// across all query nodes. The reported rates include this node's rate as
+/// of the last report, which is used until the first report was read
+pub fn query_rate(deployment: &str) -> f64 {
+    let local = RATES
+        .lock()
+        .unwrap()
+        .get(deployment)
+        .map(|count| count.rate(Instant::now()))
+        .unwrap_or(0.0);
+    let reported = REPORTED
+        .lock()
+        .unwrap()
+        .get(deployment)
+        .copied()
+        .unwrap_or(0.0);
+    local.max(reported)
+}
+
+/// The rates of the queries this node executed, for reporting them to the
+/// store. Deployments whose rate became negligible are forgotten
+pub fn local_query_rates() -> Vec<(String, f64)> {
+    let now = Instant::now();
+    let mut rates = RATES.lock().unwrap();
+    rates.retain(|_, count| count.rate(now) >= NEGLIGIBLE_RATE);
+    rates
+        .iter()
+        .map(|(deployment, count)| (deployment.clone(), count.rate(now)))
+        .collect()
+}
+
+/// Replace the rates that all query nodes reported
+pub fn set_reported_query_rates(rates: HashMap<String, f64>) {
+    *REPORTED.lock().unwrap() = rates;
+}
+
+/// A count of events where each event's contribution halves every
+/// `QUERY_RATE_HALF_LIFE`
+#[derive(Clone, Copy, Debug)]
+struct DecayingCount {
+    value: f64,
+    updated: Instant,
+}
+
+impl DecayingCount {
+    fn new(now: Instant) -> Self {
+        DecayingCount {
+            value: 0.0,
+            updated: now,
+        }
+    }
+
+    /// The time constant of the exponential decay
+    fn tau() -> f64 {
+        QUERY_RATE_HALF_LIFE.as_secs_f64() / std::f64::consts::LN_2
+    }
+
+    fn decayed(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.value * (-elapsed / Self::tau()).exp()
+    }
+
+    fn add(&mut self, now: Instant) {
+        self.value = self.decayed(now) + 1.0;
+        self.updated = now;
+    }
+
+    /// For events that happen at a constant rate `r`, the count converges
+    /// to `r * tau`
+    fn rate(&self, now: Instant) -> f64 {
+        self.decayed(now) / Self::tau()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decaying_count() {
+        let start = Instant::now();
+        let mut count = DecayingCount::new(start);
+        assert_eq!(0.0, count.rate(start));
+
+        // One query per second for a long time converges to a rate of 1
+        let mut now = start;
+        for _ in 0..10_000 {
+            now += Duration::from_secs(1);
+            count.add(now);
+        }
+        assert!((count.rate(now) - 1.0).abs() < 0.01);
+
+        // Without queries, the rate halves every half-life
+        let later = now + QUERY_RATE_HALF_LIFE;
+        assert!((count.rate(later) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn record_and_read() {
+        assert_eq!(0.0, query_rate("QmQueryRateTest"));
+        record_query("QmQueryRateTest");
+        record_query("QmQueryRateTest");
+        assert!(query_rate("QmQueryRateTest") > 0.0);
+        assert_eq!(0.0, query_rate("QmQueryRateOther"));
+        assert!(local_query_rates()
+            .iter()
+            .any(|(deployment, rate)| deployment == "QmQueryRateTest" && *rate > 0.0));
+    }
+
+    #[test]
+    fn reported_rates() {
+        set_reported_query_rates(HashMap::from_iter([(
+            "QmQueryRateReported".to_string(),
+            2.5,
+        )]));
+        assert_eq!(2.5, query_rate("QmQueryRateReported"));
+        assert!(!local_query_rates()
+            .iter()
+            .any(|(deployment, _)| deployment == "QmQueryRateReported"));
+        set_reported_query_rates(HashMap::new());
+        assert_eq!(0.0, query_rate("QmQueryRateReported"));
+    }
+}
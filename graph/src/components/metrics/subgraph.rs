@@ -2,7 +2,8 @@ use prometheus::Counter;
 
 use crate::blockchain::block_stream::BlockStreamMetrics;
 use crate::components::store::DeploymentLocator;
-use crate::prelude::{Gauge, Histogram, HostMetrics, MetricsRegistry};
+use crate::data::subgraph::ManifestSize;
+use crate::prelude::{Gauge, GaugeVec, Histogram, HostMetrics, MetricsRegistry};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -16,6 +17,7 @@ pub struct SubgraphInstanceMetrics {
 
     pub stopwatch: StopwatchMetrics,
     trigger_processing_duration: Box<Histogram>,
+    manifest_size: Box<GaugeVec>,
 }
 
 impl SubgraphInstanceMetrics {
@@ -65,6 +67,15 @@ impl SubgraphInstanceMetrics {
             )
             .expect("failed to create firehose_connection_errors counter");
 
+        let manifest_size = registry
+            .new_deployment_gauge_vec(
+                "deployment_manifest_size",
+                "The number of data sources, templates, and handlers in the manifest of a subgraph deployment",
+                &deployment,
+                vec![String::from("kind")],
+            )
+            .expect("failed to create `deployment_manifest_size` gauge");
+
         Self {
             block_trigger_count,
             block_processing_duration,
@@ -72,6 +83,24 @@ impl SubgraphInstanceMetrics {
             block_ops_transaction_duration,
             firehose_connection_errors,
             stopwatch,
+            manifest_size,
+        }
+    }
+
+    pub fn set_manifest_size(&self, size: ManifestSize) {
+        let ManifestSize {
+            data_sources,
+            templates,
+            handlers,
+        } = size;
+        for (kind, count) in [
+            ("data_sources", data_sources),
+            ("templates", templates),
+            ("handlers", handlers),
+        ] {
+            self.manifest_size
+                .with_label_values(&[kind])
+                .set(count as f64);
         }
     }
 
@@ -84,6 +113,7 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.manifest_size.clone());
     }
 }
 
@@ -0,0 +1,184 @@
+//! Track how much of the infrastructure that is shared between deployments
+//! each deployment uses, so that the cost of running a node can be
+//! attributed to individual deployments.
+//!
+//! The components that use shared infrastructure on behalf of a deployment
+//! add to the deployment's [`UsageTracker`], which they get from
+//! [`usage_tracker`]. The subgraph runner periodically takes the
+//! accumulated [`Usage`] from the tracker and writes it to the store, which
+//! aggregates it into hourly buckets.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+/// The prefix for the resource names of calls to RPC providers; the
+/// method of the call is appended to it
+pub const PROVIDER_CALLS: &str = "provider_calls:";
+/// Bytes fetched from IPFS
+pub const IPFS_BYTES: &str = "ipfs_bytes";
+/// Microseconds spent writing changes to the store
+pub const STORE_WRITE_US: &str = "store_write_us";
+/// Microseconds spent running handlers in WASM
+pub const WASM_CPU_US: &str = "wasm_cpu_us";
+/// Bytes published to the message bus
+pub const BUS_BYTES: &str = "bus_bytes";
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<UsageTracker>>> = Mutex::new(HashMap::new());
+}
+
+/// Return the tracker for `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn usage_tracker(deployment: &str) -> Arc<UsageTracker> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Forget the tracker for `deployment` once the deployment stopped
+/// running on this node, so that trackers of deployments that are no
+/// longer running here do not accumulate. Return the usage that the
+/// tracker still held and that was therefore never written
+pub fn remove_usage_tracker(deployment: &str) -> Usage {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .remove(deployment)
+        .map(|tracker| tracker.take())
+        .unwrap_or_default()
+}
+
+/// The amount of each resource that a deployment used, keyed by the
+/// resource names defined in this module
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage(BTreeMap<String, u64>);
+
+impl Usage {
+    pub fn add(&mut self, resource: &str, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        match self.0.get_mut(resource) {
+            Some(total) => *total = total.saturating_add(amount),
+            None => {
+                self.0.insert(resource.to_string(), amount);
+            }
+        }
+    }
+
+    pub fn get(&self, resource: &str) -> u64 {
+        self.0.get(resource).copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0
+            .iter()
+            .map(|(resource, amount)| (resource.as_str(), *amount))
+    }
+}
+
+/// Accumulates the usage of one deployment until it is taken with
+/// [`UsageTracker::take`]
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    usage: Mutex<Usage>,
+}
+
+impl UsageTracker {
+    fn add(&self, resource: &str, amount: u64) {
+        self.usage.lock().unwrap().add(resource, amount);
+    }
+
+    pub fn provider_call(&self, method: &str) {
+        self.add(&format!("{}{}", PROVIDER_CALLS, method), 1);
+    }
+
+    pub fn ipfs_bytes(&self, bytes: usize) {
+        self.add(IPFS_BYTES, bytes as u64);
+    }
+
+    pub fn store_write(&self, duration: Duration) {
+        self.add(STORE_WRITE_US, duration.as_micros() as u64);
+    }
+
+    pub fn wasm_cpu(&self, duration: Duration) {
+        self.add(WASM_CPU_US, duration.as_micros() as u64);
+    }
+
+    pub fn bus_bytes(&self, bytes: usize) {
+        self.add(BUS_BYTES, bytes as u64);
+    }
+
+    /// Return the usage accumulated since the last call and reset it
+    pub fn take(&self) -> Usage {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+
+    /// Add `usage` back, for example, because writing it to the store
+    /// failed
+    pub fn restore(&self, usage: Usage) {
+        let mut current = self.usage.lock().unwrap();
+        for (resource, amount) in usage.iter() {
+            current.add(resource, amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_and_restore() {
+        let tracker = usage_tracker("QmUsageTest");
+        tracker.provider_call("eth_getLogs");
+        tracker.provider_call("eth_getLogs");
+        tracker.ipfs_bytes(100);
+        tracker.store_write(Duration::from_millis(7));
+        tracker.wasm_cpu(Duration::from_micros(10));
+        tracker.bus_bytes(0);
+
+        // The tracker is shared
+        usage_tracker("QmUsageTest").bus_bytes(5);
+
+        let usage = tracker.take();
+        assert_eq!(2, usage.get("provider_calls:eth_getLogs"));
+        assert_eq!(100, usage.get(IPFS_BYTES));
+        assert_eq!(7_000, usage.get(STORE_WRITE_US));
+        // Durations below a millisecond are not lost
+        assert_eq!(10, usage.get(WASM_CPU_US));
+        assert_eq!(5, usage.get(BUS_BYTES));
+        // Amounts of zero are not recorded
+        assert_eq!(5, usage.iter().count());
+        assert!(tracker.take().is_empty());
+
+        tracker.ipfs_bytes(1);
+        tracker.restore(usage);
+        let usage = tracker.take();
+        assert_eq!(101, usage.get(IPFS_BYTES));
+        assert_eq!(2, usage.get("provider_calls:eth_getLogs"));
+    }
+
+    #[test]
+    fn remove() {
+        let tracker = usage_tracker("QmUsageRemoveTest");
+        tracker.ipfs_bytes(10);
+
+        let dropped = remove_usage_tracker("QmUsageRemoveTest");
+        assert_eq!(10, dropped.get(IPFS_BYTES));
+        assert!(!Arc::ptr_eq(&tracker, &usage_tracker("QmUsageRemoveTest")));
+
+        assert!(remove_usage_tracker("QmUsageRemoveTest").is_empty());
+        assert!(remove_usage_tracker("QmUsageUnknown").is_empty());
+    }
+}
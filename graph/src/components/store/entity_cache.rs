@@ -1,9 +1,11 @@
 use anyhow::anyhow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 
 use crate::components::store::{self as s, Entity, EntityKey, EntityOp, EntityOperation};
+use crate::data::store::EntityValidation;
+use crate::data::value::Word;
 use crate::prelude::{Schema, ENV_VARS};
 use crate::util::lfu_cache::LfuCache;
 
@@ -113,6 +115,49 @@ impl EntityCache {
         Ok(entity)
     }
 
+    /// The changes in `updates` and `handler_updates` to entities that
+    /// `query` could match, with the entity each change results in, or
+    /// `None` if it removes the entity. Pass them to `load_related_page`
+    pub fn related_changes(
+        &mut self,
+        query: &s::RelatedEntityQuery,
+    ) -> Result<BTreeMap<EntityKey, Option<Entity>>, s::QueryExecutionError> {
+        let changed: Vec<_> = self
+            .updates
+            .keys()
+            .chain(self.handler_updates.keys())
+            .filter(|key| {
+                key.entity_type == query.entity_type
+                    && key.causality_region == query.causality_region
+            })
+            .cloned()
+            .collect();
+        let mut changes = BTreeMap::new();
+        for key in changed {
+            let entity = self.get(&key)?;
+            changes.insert(key, entity);
+        }
+        Ok(changes)
+    }
+
+    /// Get the first `limit` entities, sorted by their id, that match
+    /// `query` and come after the entity with id `after`. The `changes`
+    /// from `related_changes` override the entities in the store, which
+    /// makes it possible to read all pages as of the time the changes were
+    /// taken
+    pub fn load_related_page(
+        &self,
+        query: &s::RelatedEntityQuery,
+        changes: &BTreeMap<EntityKey, Option<Entity>>,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, s::QueryExecutionError> {
+        let page = query.page(after, limit, changes, |after, limit| {
+            self.store.get_related(query, after, limit)
+        })?;
+        Ok(page)
+    }
+
     pub fn remove(&mut self, key: EntityKey) {
         self.entity_op(key, EntityOp::Remove);
     }
@@ -122,7 +167,18 @@ impl EntityCache {
     /// with existing data. The entity will be validated against the
     /// subgraph schema, and any errors will result in an `Err` being
     /// returned.
-    pub fn set(&mut self, key: EntityKey, mut entity: Entity) -> Result<(), anyhow::Error> {
+    pub fn set(&mut self, key: EntityKey, entity: Entity) -> Result<(), anyhow::Error> {
+        self.set_with(key, entity, EntityValidation::Legacy)
+    }
+
+    /// Like `set`, but validate the entity with the checks that
+    /// `validation` selects
+    pub fn set_with(
+        &mut self,
+        key: EntityKey,
+        mut entity: Entity,
+        validation: EntityValidation,
+    ) -> Result<(), anyhow::Error> {
         fn check_id(key: &EntityKey, prev_id: &str) -> Result<(), anyhow::Error> {
             if prev_id != key.entity_id.as_str() {
                 Err(anyhow!(
@@ -151,7 +207,7 @@ impl EntityCache {
             }
         }
 
-        let is_valid = entity.validate(&self.schema, &key).is_ok();
+        let is_valid = entity.validate_with(&self.schema, &key, validation).is_ok();
 
         self.entity_op(key.clone(), EntityOp::Update(entity));
 
@@ -166,7 +222,7 @@ impl EntityCache {
                     key.entity_id
                 )
             })?;
-            entity.validate(&self.schema, &key)?;
+            entity.validate_with(&self.schema, &key, validation)?;
         }
 
         Ok(())
@@ -211,6 +267,19 @@ impl EntityCache {
         }
     }
 
+    /// The number of entities that have been changed, including changes
+    /// made by the currently executing handler
+    pub fn changes(&self) -> usize {
+        self.updates.len() + self.handler_updates.len()
+    }
+
+    /// Evict entities that were loaded from the store until the cache of
+    /// loaded entities weighs at most `max_weight`. Changes that have not
+    /// been written yet are not affected
+    pub fn evict_current(&mut self, max_weight: usize) {
+        self.current.evict(max_weight);
+    }
+
     /// Return the changes that have been made via `set` and `remove` as
     /// `EntityModification`, making sure to only produce one when a change
     /// to the current state is actually needed.
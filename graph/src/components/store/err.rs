@@ -1,7 +1,8 @@
 use super::{BlockNumber, DeploymentHash, DeploymentSchemaVersion};
+use crate::data::subgraph::schema::SubgraphError;
 use crate::prelude::QueryExecutionError;
 use anyhow::{anyhow, Error};
-use diesel::result::Error as DieselError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use thiserror::Error;
 use tokio::task::JoinError;
 
@@ -40,6 +41,11 @@ pub enum StoreError {
     ConstraintViolation(String),
     #[error("deployment not found: {0}")]
     DeploymentNotFound(String),
+    #[error(
+        "deployment {0} is being removed; it can only be deployed again once \
+         the removal has finished"
+    )]
+    DeploymentBeingRemoved(String),
     #[error("shard not found: {0} (this usually indicates a misconfiguration)")]
     UnknownShard(String),
     #[error("Fulltext search not yet deterministic")]
@@ -62,6 +68,11 @@ pub enum StoreError {
         DeploymentSchemaVersion::LATEST
     )]
     UnsupportedDeploymentSchemaVersion(i32),
+    /// A strict invariant from the manifest does not hold for the changes
+    /// of a block. They were not written, and the deployment failed with
+    /// the error at that block
+    #[error("{0}")]
+    InvariantViolated(SubgraphError),
 }
 
 // Convenience to report a constraint violation
@@ -75,15 +86,57 @@ macro_rules! constraint_violation {
     }}
 }
 
+/// Messages from Postgres and libpq that indicate that the connection to
+/// the database was lost or that the database can not serve requests at
+/// the moment, for example, because it is restarting or failing over to a
+/// replica. Like the checks in the connection pool, this only works when
+/// Postgres uses an English locale
+const CONNECTION_ERRORS: &[&str] = &[
+    "server closed the connection unexpectedly",
+    "terminating connection due to administrator command",
+    "the database system is shutting down",
+    "the database system is starting up",
+    "the database system is in recovery mode",
+    "could not connect to server",
+    "connection refused",
+    "connection reset by peer",
+    "no connection to the server",
+    // After a failover, connections to the old primary may still be
+    // open while it has become a read-only replica
+    "cannot execute INSERT in a read-only transaction",
+    "cannot execute UPDATE in a read-only transaction",
+    "cannot execute DELETE in a read-only transaction",
+];
+
+impl StoreError {
+    /// Return `true` if `error` was caused by the database being
+    /// unavailable, i.e., if there is a `StoreError::DatabaseUnavailable`
+    /// anywhere in its chain of causes
+    pub fn is_unavailable(error: &Error) -> bool {
+        error
+            .chain()
+            .any(|cause| match cause.downcast_ref::<StoreError>() {
+                Some(StoreError::DatabaseUnavailable) => true,
+                Some(StoreError::Unknown(inner)) => Self::is_unavailable(inner),
+                _ => false,
+            })
+    }
+}
+
 impl From<DieselError> for StoreError {
     fn from(e: DieselError) -> Self {
-        // When the error is caused by a closed connection, treat the error
-        // as 'database unavailable'. When this happens during indexing, the
+        // When the error is caused by a lost connection or a database that
+        // is temporarily not accepting requests, treat the error as
+        // 'database unavailable'. When this happens during indexing, the
         // indexing machinery will retry in that case rather than fail the
         // subgraph
-        if let DieselError::DatabaseError(_, info) = &e {
+        if let DieselError::DatabaseError(kind, info) = &e {
+            if matches!(kind, DatabaseErrorKind::UnableToSendCommand) {
+                return StoreError::DatabaseUnavailable;
+            }
+
             let err_msg = info.message();
-            if err_msg.contains("server closed the connection unexpectedly") {
+            if CONNECTION_ERRORS.iter().any(|msg| err_msg.contains(msg)) {
                 return StoreError::DatabaseUnavailable;
             }
 
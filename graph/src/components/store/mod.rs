@@ -149,6 +149,94 @@ impl EntityKey {
     }
 }
 
+/// A query for all entities of type `entity_type` in `causality_region`
+/// whose attribute `field` is equal to `value`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelatedEntityQuery {
+    pub entity_type: EntityType,
+    pub field: String,
+    pub value: Value,
+    pub causality_region: CausalityRegion,
+}
+
+impl RelatedEntityQuery {
+    /// Whether the entity stored under `key` is part of the result of this
+    /// query
+    pub fn matches(&self, key: &EntityKey, entity: &Entity) -> bool {
+        key.entity_type == self.entity_type
+            && key.causality_region == self.causality_region
+            && entity.get(&self.field) == Some(&self.value)
+    }
+
+    /// The key of the entity with id `id` in the result of this query
+    pub fn key(&self, id: Word) -> EntityKey {
+        EntityKey {
+            entity_type: self.entity_type.clone(),
+            entity_id: id,
+            causality_region: self.causality_region,
+        }
+    }
+
+    /// Whether `key` comes after the entity with id `after` when the result
+    /// is ordered by id. Without `after`, every key does
+    pub fn is_after(key: &EntityKey, after: Option<&Word>) -> bool {
+        after.map_or(true, |after| key.entity_id.as_str() > after.as_str())
+    }
+
+    /// Return the first `limit` entities of the result, ordered by id, that
+    /// come after the entity with id `after`. The entities are read from
+    /// the store with `fetch`, which must return the first `limit` matching
+    /// entities after the id it is passed; `changes` are changes that were
+    /// not written to the store yet and override what it returns. Only as
+    /// many entities as are needed for the page are read from the store
+    pub fn page<E>(
+        &self,
+        after: Option<&Word>,
+        limit: usize,
+        changes: &BTreeMap<EntityKey, Option<Entity>>,
+        mut fetch: impl FnMut(Option<&Word>, usize) -> Result<BTreeMap<EntityKey, Entity>, E>,
+    ) -> Result<BTreeMap<EntityKey, Entity>, E> {
+        let mut page = BTreeMap::new();
+        let mut cursor = after.cloned();
+        loop {
+            let fetched = fetch(cursor.as_ref(), limit)?;
+            // If the store returned fewer entities than it was asked for, it
+            // has none after them, and `upper` is `None`
+            let upper = if fetched.len() < limit {
+                None
+            } else {
+                fetched.keys().next_back().map(|key| key.entity_id.clone())
+            };
+            let in_range = |key: &EntityKey| {
+                Self::is_after(key, cursor.as_ref())
+                    && upper
+                        .as_ref()
+                        .map_or(true, |upper| key.entity_id.as_str() <= upper.as_str())
+            };
+
+            page.extend(
+                fetched
+                    .into_iter()
+                    .filter(|(key, _)| !changes.contains_key(key)),
+            );
+            page.extend(changes.iter().filter_map(|(key, entity)| match entity {
+                Some(entity) if in_range(key) && self.matches(key, entity) => {
+                    Some((key.clone(), entity.clone()))
+                }
+                _ => None,
+            }));
+
+            // `page` has all entities up to `upper`; changes can have hidden
+            // some of the entities the store returned
+            match upper {
+                Some(upper) if page.len() < limit => cursor = Some(upper),
+                _ => break,
+            }
+        }
+        Ok(page.into_iter().take(limit).collect())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Child {
     pub attr: Attribute,
@@ -860,10 +948,24 @@ pub struct StoredDynamicDataSource {
     pub param: Option<Bytes>,
     pub context: Option<serde_json::Value>,
     pub creation_block: Option<BlockNumber>,
+    /// The position of the data source among the data sources created in
+    /// `creation_block`. `None` for data sources from the manifest and for
+    /// data sources that were stored before the position was recorded
+    pub creation_seq: Option<u32>,
     pub done_at: Option<i32>,
     pub causality_region: CausalityRegion,
 }
 
+/// The synced actions of a deployment that became synced but whose actions
+/// did not all run to completion yet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingSyncedActions {
+    /// The block at which the deployment became synced
+    pub block: BlockPtr,
+    /// The keys of the actions that ran to completion
+    pub done: BTreeSet<String>,
+}
+
 /// An internal identifer for the specific instance of a deployment. The
 /// identifier only has meaning in the context of a specific instance of
 /// graph-node. Only store code should ever construct or consume it; all
@@ -1142,6 +1244,15 @@ impl ReadStore for EmptyStore {
         Ok(BTreeMap::new())
     }
 
+    fn get_related(
+        &self,
+        _query: &RelatedEntityQuery,
+        _after: Option<&Word>,
+        _limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        Ok(BTreeMap::new())
+    }
+
     fn input_schema(&self) -> Arc<Schema> {
         self.schema.cheap_clone()
     }
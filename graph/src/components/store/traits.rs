@@ -1,14 +1,21 @@
+use chrono::{DateTime, Utc};
 use web3::types::{Address, H256};
 
 use super::*;
 use crate::blockchain::block_stream::FirehoseCursor;
+use crate::components::metrics::usage::Usage;
 use crate::components::server::index_node::VersionInfo;
+use crate::components::subgraph::handler_entity_types::HandlerWrite;
 use crate::components::transaction_receipt;
 use crate::components::versions::ApiVersion;
 use crate::data::query::Trace;
-use crate::data::subgraph::status;
+use crate::data::subgraph::fingerprint::CompatibilityFingerprint;
+use crate::data::subgraph::invariant::Invariant;
+use crate::data::subgraph::retention::EntityRetention;
+use crate::data::subgraph::status::{self, PoiDivergence};
 use crate::data::value::Word;
 use crate::data::{query::QueryTarget, subgraph::schema::*};
+use crate::runtime::CompileProfile;
 
 pub trait SubscriptionManager: Send + Sync + 'static {
     /// Subscribe to changes for specific subgraphs and entities.
@@ -94,6 +101,14 @@ pub trait SubgraphStore: Send + Sync + 'static {
 
     fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError>;
 
+    /// Put `label` on the deployment; labeling it again with the same label
+    /// does nothing
+    fn label_deployment(
+        &self,
+        deployment: &DeploymentLocator,
+        label: &str,
+    ) -> Result<(), StoreError>;
+
     fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError>;
 
     /// Return `true` if a subgraph `name` exists, regardless of whether the
@@ -110,6 +125,27 @@ pub trait SubgraphStore: Send + Sync + 'static {
         block_number: BlockNumber,
     ) -> Result<Vec<EntityOperation>, StoreError>;
 
+    /// Return the number of rows and the block at which each entity type
+    /// of the deployment was last modified, ordered by entity type. Unless
+    /// `exact` is `true`, row counts are Postgres' estimates, and the last
+    /// modification is only reported where the writer recorded it. Results
+    /// are cached for a short while
+    fn entity_type_stats(
+        &self,
+        subgraph_id: &DeploymentHash,
+        exact: bool,
+    ) -> Result<Vec<status::EntityTypeStats>, StoreError>;
+
+    /// Return which provider served each block of the deployment between
+    /// `from` and `to`, both inclusive, ordered by block number. Providers
+    /// are only remembered for the most recent blocks
+    fn block_providers(
+        &self,
+        subgraph_id: &DeploymentHash,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<status::BlockProvider>, StoreError>;
+
     /// Return the GraphQL schema supplied by the user
     fn input_schema(&self, subgraph_id: &DeploymentHash) -> Result<Arc<Schema>, StoreError>;
 
@@ -177,6 +213,15 @@ pub trait ReadStore: Send + Sync + 'static {
         keys: BTreeSet<EntityKey>,
     ) -> Result<BTreeMap<EntityKey, Entity>, StoreError>;
 
+    /// Look up the first `limit` entities, ordered by id, that match
+    /// `query` and whose id comes after `after`, as of the latest block
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError>;
+
     fn input_schema(&self) -> Arc<Schema>;
 }
 
@@ -193,6 +238,15 @@ impl<T: ?Sized + ReadStore> ReadStore for Arc<T> {
         (**self).get_many(keys)
     }
 
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        (**self).get_related(query, after, limit)
+    }
+
     fn input_schema(&self) -> Arc<Schema> {
         (**self).input_schema()
     }
@@ -214,6 +268,102 @@ pub trait WritableStore: ReadStore {
     /// Start an existing subgraph deployment.
     async fn start_subgraph_deployment(&self, logger: &Logger) -> Result<(), StoreError>;
 
+    /// Record that the deployment is indexed by this version of graph-node
+    /// with a host export surface whose hash is `host_export_hash` from
+    /// now on. Nothing is written if both are the same as the ones that
+    /// were recorded most recently for the deployment.
+    async fn record_node_version(&self, host_export_hash: String) -> Result<(), StoreError>;
+
+    /// Compare `current` with the compatibility fingerprint that was
+    /// recorded for the deployment, and record `current` if there is none
+    /// yet. If the two differ, return the recorded fingerprint; the
+    /// difference needs to be acknowledged with `graphman compat ack`
+    /// before the recorded fingerprint is replaced.
+    async fn check_compatibility(
+        &self,
+        current: CompatibilityFingerprint,
+    ) -> Result<Option<CompatibilityFingerprint>, StoreError>;
+
+    /// The profile with which the WASM modules of the deployment should be
+    /// compiled; `None` if the deployment uses the default of the node
+    async fn compile_profile(&self) -> Result<Option<CompileProfile>, StoreError>;
+
+    /// Record that the WASM modules of the deployment were compiled with
+    /// `profile` and that compiling them took `compile_time`
+    async fn record_compilation(
+        &self,
+        profile: CompileProfile,
+        compile_time: Duration,
+    ) -> Result<(), StoreError>;
+
+    /// The reorg threshold that the deployment uses instead of the one of
+    /// its chain; `None` if it uses the one of its chain
+    async fn reorg_threshold(&self) -> Result<Option<BlockNumber>, StoreError>;
+
+    /// Put the retention policies of the deployment, including the ones
+    /// that were set since it was last started, into effect and return
+    /// them. Policies that are set while the deployment runs are only
+    /// applied once this is called again
+    async fn start_entity_retention(&self) -> Result<Vec<EntityRetention>, StoreError>;
+
+    /// Add `usage` to the usage of shared infrastructure that is recorded
+    /// for the deployment for the current hour
+    async fn record_usage(&self, usage: Usage) -> Result<(), StoreError>;
+
+    /// Add `stats` to the cumulative statistics of the handlers of the
+    /// deployment and return the resulting totals
+    async fn record_handler_stats(
+        &self,
+        stats: Vec<status::HandlerStats>,
+    ) -> Result<status::HandlerStatsReport, StoreError>;
+
+    /// The cumulative statistics of the handlers of the deployment as of
+    /// the last time they were written
+    async fn handler_stats(&self) -> Result<status::HandlerStatsReport, StoreError>;
+
+    /// Record that handlers wrote entity types. Writes that were recorded
+    /// before are ignored
+    async fn record_handler_entity_types(
+        &self,
+        writes: Vec<HandlerWrite>,
+    ) -> Result<(), StoreError>;
+
+    /// Record the names of the bus backends that the deployment publishes
+    /// to, replacing the ones that were recorded before
+    async fn record_bus_backends(&self, backends: Vec<String>) -> Result<(), StoreError>;
+
+    /// The entity types of the deployment whose delivery to the bus needs
+    /// to be verifiable
+    async fn bus_critical_entity_types(&self) -> Result<BTreeSet<EntityType>, StoreError>;
+
+    /// Record that `backend` delivered the changes of the deployment to
+    /// `entity_types` in `block`, or, if `delivered` is `false`, that it
+    /// gave up on them. Return up to which block the changes to each of
+    /// `entity_types` were delivered to `backend` afterwards
+    async fn record_bus_critical_delivery(
+        &self,
+        backend: String,
+        entity_types: BTreeSet<EntityType>,
+        block: BlockNumber,
+        delivered: bool,
+    ) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError>;
+
+    /// Record that the PoI of the deployment diverged from the PoI that
+    /// reference indexers report. An earlier divergence that was recorded
+    /// before is kept. With `None`, remove the recorded divergence
+    async fn record_poi_divergence(
+        &self,
+        divergence: Option<PoiDivergence>,
+    ) -> Result<(), StoreError>;
+
+    /// The public PoI of the deployment at `block`, i.e., the PoI that
+    /// does not depend on the indexer. Returns `None` if the deployment
+    /// has not written `block` to the database yet
+    async fn public_proof_of_indexing(
+        &self,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError>;
+
     /// Revert the entity changes from a single block atomically in the store, and update the
     /// subgraph block pointer to `block_ptr_to`.
     ///
@@ -248,6 +398,11 @@ pub trait WritableStore: ReadStore {
     /// subgraph block pointer to `block_ptr_to`, and update the firehose cursor to `firehose_cursor`
     ///
     /// `block_ptr_to` must point to a child block of the current subgraph block pointer.
+    /// `block_provider` is the label of the provider that served the block, if it is known.
+    /// `invariants` are checked against the data of the deployment with the changes applied.
+    /// If a strict one is violated, only the PoI of the block is written, the deployment fails
+    /// at the block, and `StoreError::InvariantViolated` is returned, possibly from a later call
+    /// if the write is queued
     async fn transact_block_operations(
         &self,
         block_ptr_to: BlockPtr,
@@ -258,12 +413,28 @@ pub trait WritableStore: ReadStore {
         deterministic_errors: Vec<SubgraphError>,
         manifest_idx_and_name: Vec<(u32, String)>,
         offchain_to_remove: Vec<StoredDynamicDataSource>,
+        block_provider: Option<String>,
+        invariants: Vec<Invariant>,
     ) -> Result<(), StoreError>;
 
-    /// The deployment `id` finished syncing, mark it as synced in the database
-    /// and promote it to the current version in the subgraphs where it was the
-    /// pending version so far
-    fn deployment_synced(&self) -> Result<(), StoreError>;
+    /// The deployment `id` finished syncing at `block_ptr`, mark it as
+    /// synced in the database and promote it to the current version in the
+    /// subgraphs where it was the pending version so far. Return `true` if
+    /// this call marked the deployment as synced, and `false` if it already
+    /// was synced. When it returns `true`, the synced actions of the
+    /// deployment are pending until `synced_actions_done` is called
+    fn deployment_synced(&self, block_ptr: BlockPtr) -> Result<bool, StoreError>;
+
+    /// The synced actions of the deployment if it became synced but they
+    /// did not all run to completion yet, e.g., because the node went down
+    /// first
+    fn synced_actions_pending(&self) -> Result<Option<PendingSyncedActions>, StoreError>;
+
+    /// Record that the synced action with `key` ran to completion
+    fn synced_action_done(&self, key: String) -> Result<(), StoreError>;
+
+    /// Record that all synced actions of the deployment ran
+    fn synced_actions_done(&self) -> Result<(), StoreError>;
 
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
@@ -288,6 +459,12 @@ pub trait WritableStore: ReadStore {
 
     /// Wait for the background writer to finish processing its queue
     async fn flush(&self) -> Result<(), StoreError>;
+
+    /// Wait for the background writer to finish processing its queue,
+    /// then reload the block pointer and cursor from the database and make
+    /// them the ones returned by `block_ptr` and `block_cursor`. Returns
+    /// the block pointer from the database
+    async fn reload_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
 }
 
 #[async_trait]
@@ -419,6 +596,18 @@ pub trait ChainStore: Send + Sync + 'static {
 
     /// Clears call cache of the chain for the given `from` and `to` block number.
     async fn clear_call_cache(&self, from: Option<i32>, to: Option<i32>) -> Result<(), Error>;
+
+    /// Record that the providers `primary` and `secondary` returned
+    /// different triggers for `block`. The `kind` of the difference is one
+    /// of a few fixed strings, and `details` describes it for humans.
+    async fn record_trigger_divergence(
+        &self,
+        block: &BlockPtr,
+        primary: &str,
+        secondary: &str,
+        kind: &str,
+        details: &str,
+    ) -> Result<(), StoreError>;
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
@@ -457,6 +646,10 @@ pub trait QueryStore: Send + Sync {
 
     async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
 
+    /// The head of the chain of the deployment, from the in-memory cache
+    /// of the chain head if possible
+    async fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
+
     async fn block_number(&self, block_hash: &BlockHash)
         -> Result<Option<BlockNumber>, StoreError>;
 
@@ -493,6 +686,23 @@ pub trait StatusStore: Send + Sync + 'static {
 
     fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError>;
 
+    /// The hourly usage of shared infrastructure by `deployments` for the
+    /// hours that start in `[from, to)`. If `deployments` is empty, return
+    /// the usage of all deployments
+    fn usage(
+        &self,
+        deployments: Vec<String>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<status::UsageRecord>, StoreError>;
+
+    /// The total query rate of each deployment across all query nodes that
+    /// reported their rates to the store in the last `max_age`
+    fn query_rates(
+        &self,
+        max_age: Duration,
+    ) -> Result<std::collections::HashMap<String, f64>, StoreError>;
+
     /// Support for the explorer-specific API
     fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError>;
 
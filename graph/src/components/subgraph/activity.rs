@@ -0,0 +1,284 @@
+//! What each running deployment was doing most recently, to diagnose
+//! deployments that stopped advancing. The runner, the runtime and the
+//! store record their progress here as they go; recording is cheap since
+//! it only updates a few fields. The watchdog for stuck deployments takes
+//! a [`ActivitySnapshot`] when it suspects that a deployment is stuck.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use crate::prelude::BlockNumber;
+
+/// How many of the most recent block stream errors are kept
+const MAX_STREAM_ERRORS: usize = 5;
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<DeploymentActivity>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Return the activity of `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn deployment_activity(deployment: &str) -> Arc<DeploymentActivity> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Forget the activity of `deployment` once the deployment stopped
+/// running on this node
+pub fn remove_deployment_activity(deployment: &str) {
+    TRACKERS.lock().unwrap().remove(deployment);
+}
+
+/// What the runner of a deployment is doing with its block stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamState {
+    /// The block stream is being created
+    Starting,
+    /// The runner waits for the next event from the block stream
+    WaitingForBlocks,
+    /// The runner processes the block with this number
+    ProcessingBlock(BlockNumber),
+    /// The runner reverts to the block with this number
+    Reverting(BlockNumber),
+    /// The runner paused until the store is available again
+    WaitingForStore,
+}
+
+impl fmt::Display for StreamState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamState::Starting => write!(f, "starting"),
+            StreamState::WaitingForBlocks => write!(f, "waiting_for_blocks"),
+            StreamState::ProcessingBlock(number) => write!(f, "processing_block:{}", number),
+            StreamState::Reverting(number) => write!(f, "reverting_to:{}", number),
+            StreamState::WaitingForStore => write!(f, "waiting_for_store"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    stream_state: StreamState,
+    stream_state_since: Instant,
+    last_block_at: Option<Instant>,
+    /// The handler that ran last and when it finished
+    last_trigger: Option<(String, Instant)>,
+    /// The store request that is being executed and when that started
+    write: Option<(String, Instant)>,
+    /// The number of store requests that were queued, including the one
+    /// that is being executed, when the last one started
+    write_queue: usize,
+    stream_errors: VecDeque<(String, Instant)>,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner {
+            stream_state: StreamState::Starting,
+            stream_state_since: Instant::now(),
+            last_block_at: None,
+            last_trigger: None,
+            write: None,
+            write_queue: 0,
+            stream_errors: VecDeque::new(),
+        }
+    }
+}
+
+/// The most recent activity of one deployment
+#[derive(Debug, Default)]
+pub struct DeploymentActivity {
+    inner: Mutex<Inner>,
+    /// The number of triggers that were sent to the mapping and are
+    /// waiting for it or being processed by it
+    mapping_requests: AtomicUsize,
+}
+
+impl DeploymentActivity {
+    pub fn set_stream_state(&self, state: StreamState) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.stream_state != state {
+            inner.stream_state = state;
+            inner.stream_state_since = Instant::now();
+        }
+    }
+
+    /// Record that the block stream delivered the block with `number`,
+    /// which the runner is about to process
+    pub fn block_received(&self, number: BlockNumber) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_block_at = Some(Instant::now());
+        inner.stream_state = StreamState::ProcessingBlock(number);
+        inner.stream_state_since = Instant::now();
+    }
+
+    /// Record that the block stream produced `error`
+    pub fn stream_error(&self, error: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.stream_errors.len() == MAX_STREAM_ERRORS {
+            inner.stream_errors.pop_front();
+        }
+        inner.stream_errors.push_back((error, Instant::now()));
+    }
+
+    /// Record that a trigger is sent to the mapping. The trigger counts as
+    /// pending until the returned guard is dropped
+    pub fn mapping_request(self: &Arc<Self>) -> MappingRequest {
+        self.mapping_requests.fetch_add(1, Ordering::SeqCst);
+        MappingRequest {
+            activity: self.clone(),
+        }
+    }
+
+    /// Record that `handler` finished processing a trigger
+    pub fn trigger_processed(&self, handler: &str) {
+        self.inner.lock().unwrap().last_trigger = Some((handler.to_string(), Instant::now()));
+    }
+
+    /// Record that the store started executing `request` while `queued`
+    /// requests, including this one, were waiting to be executed
+    pub fn write_started(&self, request: String, queued: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.write = Some((request, Instant::now()));
+        inner.write_queue = queued;
+    }
+
+    pub fn write_finished(&self) {
+        self.inner.lock().unwrap().write = None;
+    }
+
+    pub fn snapshot(&self) -> ActivitySnapshot {
+        let inner = self.inner.lock().unwrap();
+        ActivitySnapshot {
+            stream_state: inner.stream_state,
+            stream_state_age: inner.stream_state_since.elapsed(),
+            last_block_age: inner.last_block_at.map(|at| at.elapsed()),
+            last_trigger: inner
+                .last_trigger
+                .as_ref()
+                .map(|(handler, at)| (handler.clone(), at.elapsed())),
+            mapping_requests: self.mapping_requests.load(Ordering::SeqCst),
+            write: inner
+                .write
+                .as_ref()
+                .map(|(request, at)| (request.clone(), at.elapsed())),
+            write_queue: inner.write_queue,
+            stream_errors: inner
+                .stream_errors
+                .iter()
+                .map(|(error, at)| (error.clone(), at.elapsed()))
+                .collect(),
+        }
+    }
+}
+
+/// A trigger that was sent to the mapping; see
+/// [`DeploymentActivity::mapping_request`]
+pub struct MappingRequest {
+    activity: Arc<DeploymentActivity>,
+}
+
+impl Drop for MappingRequest {
+    fn drop(&mut self) {
+        self.activity
+            .mapping_requests
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The activity of a deployment at one point in time. Durations are how
+/// long ago something happened
+#[derive(Clone, Debug)]
+pub struct ActivitySnapshot {
+    pub stream_state: StreamState,
+    /// How long the runner has been in `stream_state`
+    pub stream_state_age: Duration,
+    /// When the block stream last delivered a block
+    pub last_block_age: Option<Duration>,
+    /// The handler that ran last and when it finished
+    pub last_trigger: Option<(String, Duration)>,
+    /// The number of triggers that the mapping has not finished yet
+    pub mapping_requests: usize,
+    /// The store request that is being executed and for how long
+    pub write: Option<(String, Duration)>,
+    /// The number of queued store requests when the last one started
+    pub write_queue: usize,
+    /// The most recent block stream errors, oldest first
+    pub stream_errors: Vec<(String, Duration)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let activity = deployment_activity("QmActivityTest");
+        let snapshot = activity.snapshot();
+        assert_eq!(StreamState::Starting, snapshot.stream_state);
+        assert_eq!(None, snapshot.last_block_age);
+
+        activity.set_stream_state(StreamState::WaitingForBlocks);
+        activity.block_received(12);
+        activity.trigger_processed("handleTransfer");
+        let request = deployment_activity("QmActivityTest").mapping_request();
+        activity.write_started("write block 12".to_string(), 3);
+        for i in 0..MAX_STREAM_ERRORS + 2 {
+            activity.stream_error(format!("error {}", i));
+        }
+
+        let snapshot = activity.snapshot();
+        assert_eq!(StreamState::ProcessingBlock(12), snapshot.stream_state);
+        assert!(snapshot.last_block_age.is_some());
+        assert_eq!(
+            Some("handleTransfer"),
+            snapshot.last_trigger.as_ref().map(|(h, _)| h.as_str())
+        );
+        assert_eq!(1, snapshot.mapping_requests);
+        assert_eq!(
+            Some("write block 12"),
+            snapshot.write.as_ref().map(|(r, _)| r.as_str())
+        );
+        assert_eq!(3, snapshot.write_queue);
+        // Only the most recent errors are kept
+        let errors: Vec<_> = snapshot
+            .stream_errors
+            .iter()
+            .map(|(e, _)| e.as_str())
+            .collect();
+        assert_eq!(
+            vec!["error 2", "error 3", "error 4", "error 5", "error 6"],
+            errors
+        );
+
+        drop(request);
+        activity.write_finished();
+        let snapshot = activity.snapshot();
+        assert_eq!(0, snapshot.mapping_requests);
+        assert!(snapshot.write.is_none());
+    }
+
+    #[test]
+    fn remove() {
+        let activity = deployment_activity("QmActivityRemove");
+        activity.block_received(12);
+
+        // A deployment that starts again after it was removed starts with
+        // no activity
+        remove_deployment_activity("QmActivityRemove");
+        assert!(!TRACKERS.lock().unwrap().contains_key("QmActivityRemove"));
+        let snapshot = deployment_activity("QmActivityRemove").snapshot();
+        assert_eq!(StreamState::Starting, snapshot.stream_state);
+        assert_eq!(None, snapshot.last_block_age);
+    }
+}
@@ -0,0 +1,142 @@
+//! Observe which entity types each handler of a deployment writes. The
+//! `entities` list in the manifest is not enforced for onchain data
+//! sources, so it is often wrong; the observed entity types tell subgraph
+//! authors what their handlers actually do.
+//!
+//! The store host exports add to the deployment's [`HandlerEntityTypes`],
+//! which they get from [`handler_entity_types`]. The subgraph runner
+//! periodically takes the newly observed entity types and writes them to
+//! the store.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+
+/// The maximum number of pairs of handler and entity type that are
+/// tracked for a deployment, across all of its handlers. The number of
+/// handlers and entity types is bounded by the manifest and the schema,
+/// but we don't want a runaway subgraph to make us use unbounded amounts
+/// of memory
+pub const MAX_HANDLER_ENTITY_TYPES: usize = 10_000;
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<HandlerEntityTypes>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Return the tracker for `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn handler_entity_types(deployment: &str) -> Arc<HandlerEntityTypes> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// An entity type that a handler was observed writing. Handlers are
+/// identified by their data source since different data sources can have
+/// handlers with the same name
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HandlerWrite {
+    pub data_source: String,
+    pub handler: String,
+    pub entity_type: String,
+}
+
+impl HandlerWrite {
+    pub fn new(data_source: &str, handler: &str, entity_type: &str) -> Self {
+        HandlerWrite {
+            data_source: data_source.to_string(),
+            handler: handler.to_string(),
+            entity_type: entity_type.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// All writes that were observed
+    observed: HashSet<HandlerWrite>,
+    /// The writes that were observed since the last call to `take`
+    unflushed: BTreeSet<HandlerWrite>,
+}
+
+/// Accumulates the entity types that each handler of one deployment
+/// writes
+#[derive(Debug, Default)]
+pub struct HandlerEntityTypes {
+    inner: Mutex<Inner>,
+}
+
+impl HandlerEntityTypes {
+    /// Record that `handler` of `data_source` wrote an entity of type
+    /// `entity_type`. Only writes that the store accepted should be
+    /// recorded
+    pub fn observe(&self, data_source: &str, handler: &str, entity_type: &str) {
+        let write = HandlerWrite::new(data_source, handler, entity_type);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.observed.contains(&write) || inner.observed.len() >= MAX_HANDLER_ENTITY_TYPES {
+            return;
+        }
+        inner.observed.insert(write.clone());
+        inner.unflushed.insert(write);
+    }
+
+    /// Return the writes that were observed for the first time since the
+    /// last call
+    pub fn take(&self) -> Vec<HandlerWrite> {
+        std::mem::take(&mut self.inner.lock().unwrap().unflushed)
+            .into_iter()
+            .collect()
+    }
+
+    /// Add `writes` back, for example, because writing them to the store
+    /// failed
+    pub fn restore(&self, writes: Vec<HandlerWrite>) {
+        self.inner.lock().unwrap().unflushed.extend(writes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe_take_and_restore() {
+        let tracker = handler_entity_types("QmHandlerEntityTypesTest");
+        tracker.observe("Token", "handleTransfer", "Account");
+        tracker.observe("Token", "handleTransfer", "Transfer");
+        tracker.observe("Token", "handleTransfer", "Account");
+        // Handlers with the same name in different data sources are kept
+        // apart
+        tracker.observe("Pool", "handleTransfer", "Account");
+        // The tracker is shared
+        handler_entity_types("QmHandlerEntityTypesTest").observe(
+            "Token",
+            "handleApproval",
+            "Account",
+        );
+
+        let writes = tracker.take();
+        assert_eq!(
+            vec![
+                HandlerWrite::new("Pool", "handleTransfer", "Account"),
+                HandlerWrite::new("Token", "handleApproval", "Account"),
+                HandlerWrite::new("Token", "handleTransfer", "Account"),
+                HandlerWrite::new("Token", "handleTransfer", "Transfer"),
+            ],
+            writes
+        );
+        assert!(tracker.take().is_empty());
+
+        // Entity types that were observed before are not reported again
+        tracker.observe("Token", "handleTransfer", "Transfer");
+        assert!(tracker.take().is_empty());
+
+        tracker.restore(writes);
+        assert_eq!(4, tracker.take().len());
+    }
+}
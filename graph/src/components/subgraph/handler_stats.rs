@@ -0,0 +1,258 @@
+//! Cumulative statistics of each handler of a deployment: how often it
+//! ran, how long and how much gas that took, and how often it failed. The
+//! statistics are kept in the store so that they survive restarts and can
+//! be compared over weeks.
+//!
+//! The runtime records each handler run with the deployment's
+//! [`HandlerStatsTracker`], which it gets from [`handler_stats_tracker`].
+//! The subgraph runner loads the stored statistics when it starts and
+//! periodically writes what was recorded since the last write, which the
+//! store adds to the stored statistics. When the runner stops, it writes
+//! what is left and removes the tracker with
+//! [`remove_handler_stats_tracker`]. When the node shuts down, it stops
+//! the runners and then writes what runners that did not stop in time
+//! recorded with [`take_handler_stats`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::data::subgraph::status::{HandlerStats, HandlerStatsReport};
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<HandlerStatsTracker>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Return the tracker for `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn handler_stats_tracker(deployment: &str) -> Arc<HandlerStatsTracker> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// The current statistics for `deployment`; `None` unless this node
+/// loaded them from the store because it runs the deployment
+pub fn handler_stats_report(deployment: &str) -> Option<HandlerStatsReport> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .get(deployment)
+        .and_then(|tracker| tracker.report())
+}
+
+/// Forget the tracker for `deployment` once the deployment stopped
+/// running on this node. Return the statistics that the tracker still
+/// held and that were therefore never written
+pub fn remove_handler_stats_tracker(deployment: &str) -> Vec<HandlerStats> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .remove(deployment)
+        .map(|tracker| tracker.take())
+        .unwrap_or_default()
+}
+
+/// Take the statistics that were recorded since they were last taken for
+/// all deployments that have any, by deployment
+pub fn take_handler_stats() -> Vec<(String, Vec<HandlerStats>)> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(deployment, tracker)| (deployment.clone(), tracker.take()))
+        .filter(|(_, stats)| !stats.is_empty())
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Whether the stored statistics were loaded
+    loaded: bool,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// The stored statistics plus the ones in `unflushed`, by handler
+    totals: BTreeMap<String, HandlerStats>,
+    /// The statistics that were recorded since the last call to `take`
+    unflushed: BTreeMap<String, HandlerStats>,
+}
+
+fn add_to(stats: &mut BTreeMap<String, HandlerStats>, other: &HandlerStats) {
+    stats
+        .entry(other.handler.clone())
+        .or_insert_with(|| HandlerStats {
+            handler: other.handler.clone(),
+            ..HandlerStats::default()
+        })
+        .add(other);
+}
+
+/// Accumulates the statistics of the handlers of one deployment
+#[derive(Debug, Default)]
+pub struct HandlerStatsTracker {
+    inner: Mutex<Inner>,
+}
+
+impl HandlerStatsTracker {
+    /// Record that a run of `handler` took `duration` and used `gas`
+    pub fn record(&self, handler: &str, duration: Duration, gas: u64, failed: bool) {
+        let run = HandlerStats {
+            handler: handler.to_string(),
+            invocations: 1,
+            execution_time: duration,
+            gas,
+            errors: failed as u64,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        add_to(&mut inner.totals, &run);
+        add_to(&mut inner.unflushed, &run);
+    }
+
+    /// Replace the totals with the `stored` statistics plus the ones that
+    /// were recorded but not taken yet
+    pub fn load(&self, stored: HandlerStatsReport) {
+        let mut inner = self.inner.lock().unwrap();
+        let mut totals = BTreeMap::new();
+        for stats in stored.handlers.iter().chain(inner.unflushed.values()) {
+            add_to(&mut totals, stats);
+        }
+        inner.loaded = true;
+        inner.since = stored.since;
+        inner.totals = totals;
+    }
+
+    /// Return the statistics recorded since the last call and reset them
+    pub fn take(&self) -> Vec<HandlerStats> {
+        std::mem::take(&mut self.inner.lock().unwrap().unflushed)
+            .into_values()
+            .collect()
+    }
+
+    /// Add `stats` back, for example, because writing them to the store
+    /// failed
+    pub fn restore(&self, stats: Vec<HandlerStats>) {
+        let mut inner = self.inner.lock().unwrap();
+        for stats in &stats {
+            add_to(&mut inner.unflushed, stats);
+        }
+    }
+
+    /// The total statistics; `None` if they were never loaded
+    pub fn report(&self) -> Option<HandlerStatsReport> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.loaded {
+            return None;
+        }
+        Some(HandlerStatsReport {
+            since: inner.since,
+            handlers: inner.totals.values().cloned().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(handler: &str, invocations: u64, ms: u64, gas: u64, errors: u64) -> HandlerStats {
+        HandlerStats {
+            handler: handler.to_string(),
+            invocations,
+            execution_time: Duration::from_millis(ms),
+            gas,
+            errors,
+        }
+    }
+
+    #[test]
+    fn record_load_and_take() {
+        let tracker = handler_stats_tracker("QmHandlerStatsTest");
+        tracker.record("handleTransfer", Duration::from_millis(5), 100, false);
+        // Nothing is reported until the stored statistics are loaded
+        assert_eq!(None, tracker.report());
+
+        let since = chrono::Utc::now();
+        tracker.load(HandlerStatsReport {
+            since: Some(since),
+            handlers: vec![stats("handleTransfer", 10, 50, 1000, 1)],
+        });
+        handler_stats_tracker("QmHandlerStatsTest").record(
+            "handleApproval",
+            Duration::from_millis(2),
+            7,
+            true,
+        );
+        let report = handler_stats_report("QmHandlerStatsTest").unwrap();
+        assert_eq!(Some(since), report.since);
+        assert_eq!(
+            vec![
+                stats("handleApproval", 1, 2, 7, 1),
+                stats("handleTransfer", 11, 55, 1100, 1),
+            ],
+            report.handlers
+        );
+
+        // Only what was recorded since the last take is taken
+        let taken = tracker.take();
+        assert_eq!(
+            vec![
+                stats("handleApproval", 1, 2, 7, 1),
+                stats("handleTransfer", 1, 5, 100, 0),
+            ],
+            taken
+        );
+        assert!(tracker.take().is_empty());
+
+        // Loading again keeps what was restored after a failed write
+        tracker.restore(taken);
+        tracker.load(HandlerStatsReport::default());
+        assert_eq!(
+            vec![
+                stats("handleApproval", 1, 2, 7, 1),
+                stats("handleTransfer", 1, 5, 100, 0),
+            ],
+            tracker.report().unwrap().handlers
+        );
+    }
+
+    #[test]
+    fn take_all() {
+        let tracker = handler_stats_tracker("QmHandlerStatsTakeAll");
+        tracker.record("handleTransfer", Duration::from_millis(5), 100, false);
+
+        let taken: Vec<_> = take_handler_stats()
+            .into_iter()
+            .filter(|(deployment, _)| deployment == "QmHandlerStatsTakeAll")
+            .collect();
+        assert_eq!(
+            vec![(
+                "QmHandlerStatsTakeAll".to_string(),
+                vec![stats("handleTransfer", 1, 5, 100, 0)]
+            )],
+            taken
+        );
+        assert!(tracker.take().is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        let tracker = handler_stats_tracker("QmHandlerStatsRemove");
+        tracker.record("handleTransfer", Duration::from_millis(5), 100, true);
+        tracker.load(HandlerStatsReport::default());
+
+        assert_eq!(
+            vec![stats("handleTransfer", 1, 5, 100, 1)],
+            remove_handler_stats_tracker("QmHandlerStatsRemove")
+        );
+        assert_eq!(None, handler_stats_report("QmHandlerStatsRemove"));
+        assert!(take_handler_stats()
+            .iter()
+            .all(|(deployment, _)| deployment != "QmHandlerStatsRemove"));
+        assert!(remove_handler_stats_tracker("QmHandlerStatsRemove").is_empty());
+    }
+}
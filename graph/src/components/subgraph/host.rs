@@ -6,12 +6,21 @@ use anyhow::Error;
 use async_trait::async_trait;
 use futures::sync::mpsc;
 
+use crate::components::metrics::usage::{usage_tracker, UsageTracker};
 use crate::components::store::DeploymentLocator;
 use crate::components::store::SubgraphFork;
+use crate::components::subgraph::activity::{deployment_activity, DeploymentActivity};
+use crate::components::subgraph::handler_entity_types::{handler_entity_types, HandlerEntityTypes};
+use crate::components::subgraph::handler_stats::{handler_stats_tracker, HandlerStatsTracker};
+use crate::components::subgraph::mapping_terminations::{
+    mapping_terminations, MappingTerminations, TerminationReason, TERMINATION_RATE_WINDOW,
+};
+use crate::components::subgraph::wasm_performance::{wasm_performance, WasmPerformance};
 use crate::data_source::{
     DataSource, DataSourceTemplate, MappingTrigger, TriggerData, TriggerWithHandler,
 };
 use crate::prelude::*;
+use crate::runtime::CompileProfile;
 use crate::{blockchain::Blockchain, components::subgraph::SharedProofOfIndexing};
 use crate::{components::metrics::HistogramVec, runtime::DeterministicHostError};
 
@@ -83,6 +92,17 @@ pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
     pub stopwatch: StopwatchMetrics,
+    pub usage: Arc<UsageTracker>,
+    pub handler_entity_types: Arc<HandlerEntityTypes>,
+    pub handler_stats: Arc<HandlerStatsTracker>,
+    pub activity: Arc<DeploymentActivity>,
+    terminations: Arc<MappingTerminations>,
+    deployment_terminations: CounterVec,
+    total_terminations: CounterVec,
+    wasm_compile_time: Box<HistogramVec>,
+    pub wasm_performance: Arc<WasmPerformance>,
+    ipfs_map_values: Counter,
+    ipfs_map_bytes: Counter,
 }
 
 impl HostMetrics {
@@ -109,10 +129,102 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
+        let deployment_terminations = registry
+            .global_deployment_counter_vec(
+                "deployment_mapping_terminations",
+                "Counts terminations of the mappings of a deployment, by reason",
+                deployment,
+                &["reason"],
+            )
+            .expect("failed to create `deployment_mapping_terminations` counter");
+        let total_terminations = registry
+            .global_counter_vec(
+                "mapping_terminations",
+                "Counts terminations of the mappings of all deployments, by reason",
+                &["reason"],
+            )
+            .expect("failed to create `mapping_terminations` counter");
+        let wasm_compile_time = registry
+            .new_deployment_histogram_vec(
+                "deployment_wasm_compile_time",
+                "Measures the time it takes to compile the WASM modules of a deployment",
+                deployment,
+                vec![String::from("profile")],
+                vec![0.1, 0.5, 1.0, 5.0, 30.0, 120.0],
+            )
+            .expect("failed to create `deployment_wasm_compile_time` histogram");
+        let ipfs_map_values = registry
+            .new_deployment_counter(
+                "deployment_ipfs_map_values",
+                "Counts the values that calls to `ipfs.map` passed to their callback",
+                deployment,
+            )
+            .expect("failed to create `deployment_ipfs_map_values` counter");
+        let ipfs_map_bytes = registry
+            .new_deployment_counter(
+                "deployment_ipfs_map_bytes",
+                "Counts the bytes of the files that calls to `ipfs.map` processed",
+                deployment,
+            )
+            .expect("failed to create `deployment_ipfs_map_bytes` counter");
         Self {
             handler_execution_time,
             host_fn_execution_time,
             stopwatch,
+            usage: usage_tracker(deployment.hash.as_str()),
+            handler_entity_types: handler_entity_types(deployment.hash.as_str()),
+            handler_stats: handler_stats_tracker(deployment.hash.as_str()),
+            activity: deployment_activity(deployment.hash.as_str()),
+            terminations: mapping_terminations(deployment.hash.as_str()),
+            deployment_terminations,
+            total_terminations,
+            wasm_compile_time,
+            wasm_performance: wasm_performance(deployment.hash.as_str()),
+            ipfs_map_values,
+            ipfs_map_bytes,
+        }
+    }
+
+    /// Unregister the metrics that belong to the deployment alone. The
+    /// counters of terminations are global and stay registered
+    pub fn unregister(&self, registry: Arc<dyn MetricsRegistry>) {
+        registry.unregister(self.handler_execution_time.clone());
+        registry.unregister(self.host_fn_execution_time.clone());
+        registry.unregister(self.wasm_compile_time.clone());
+        registry.unregister(Box::new(self.ipfs_map_values.clone()));
+        registry.unregister(Box::new(self.ipfs_map_bytes.clone()));
+    }
+
+    /// Record that `ipfs.map` passed a value that took up `bytes` in the
+    /// file to its callback
+    pub fn record_ipfs_map_value(&self, bytes: usize) {
+        self.ipfs_map_values.inc();
+        self.ipfs_map_bytes.inc_by(bytes as f64);
+    }
+
+    /// Record that compiling a WASM module with `profile` took `duration`
+    pub fn record_compile(&self, profile: CompileProfile, duration: Duration) {
+        self.wasm_compile_time
+            .with_label_values(&[profile.as_str()])
+            .observe(duration.as_secs_f64());
+        self.wasm_performance.record_compile(profile, duration);
+    }
+
+    /// Record that the execution of a mapping ended for `reason`, and warn
+    /// if abnormal terminations happen more often than
+    /// `GRAPH_MAPPING_MAX_TERMINATION_RATE` per minute
+    pub fn record_termination(&self, logger: &Logger, reason: TerminationReason) {
+        let label = reason.to_string();
+        self.deployment_terminations
+            .with_label_values(&[&label])
+            .inc();
+        self.total_terminations.with_label_values(&[&label]).inc();
+
+        let max_rate = ENV_VARS.mappings.max_termination_rate;
+        if let Some(rate) = self.terminations.record(&reason, Instant::now(), max_rate) {
+            warn!(logger, "Mappings terminated abnormally {} times in the last {} seconds", rate,
+                TERMINATION_RATE_WINDOW.as_secs();
+                "reason" => label);
         }
     }
 
@@ -171,11 +283,13 @@ pub trait RuntimeHostBuilder<C: Blockchain>: Clone + Send + Sync + 'static {
     ) -> Result<Self::Host, Error>;
 
     /// Spawn a mapping and return a channel for mapping requests. The sender should be able to be
-    /// cached and shared among mappings that use the same wasm file.
+    /// cached and shared among mappings that use the same wasm file. The module is compiled with
+    /// `compile_profile`.
     fn spawn_mapping(
         raw_module: &[u8],
         logger: Logger,
         subgraph_id: DeploymentHash,
         metrics: Arc<HostMetrics>,
+        compile_profile: CompileProfile,
     ) -> Result<mpsc::Sender<Self::Req>, anyhow::Error>;
 }
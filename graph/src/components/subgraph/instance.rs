@@ -13,6 +13,11 @@ pub struct DataSourceTemplateInfo<C: Blockchain> {
     pub params: Vec<String>,
     pub context: Option<DataSourceContext>,
     pub creation_block: BlockNumber,
+    /// The position of this data source among all data sources created in
+    /// the same block, in the order in which the handlers created them.
+    /// Assigned by `BlockState::push_created_data_source` and stored with
+    /// the data source so that it is loaded back in the same order
+    pub creation_seq: u32,
 }
 
 #[derive(Debug)]
@@ -32,6 +37,12 @@ pub struct BlockState<C: Blockchain> {
 
     // Marks whether a handler is currently executing.
     in_handler: bool,
+
+    // The `creation_seq` of the next data source created in this block.
+    next_creation_seq: u32,
+
+    // The number of handlers that ran with this state.
+    handlers_run: usize,
 }
 
 impl<C: Blockchain> BlockState<C> {
@@ -44,6 +55,8 @@ impl<C: Blockchain> BlockState<C> {
             handler_created_data_sources: Vec::new(),
             processed_data_sources: Vec::new(),
             in_handler: false,
+            next_creation_seq: 0,
+            handlers_run: 0,
         }
     }
 
@@ -58,35 +71,60 @@ impl<C: Blockchain> BlockState<C> {
             handler_created_data_sources,
             processed_data_sources,
             in_handler,
+            next_creation_seq,
+            handlers_run,
         } = self;
 
+        // The data sources in `other` were created after the ones in `self`,
+        // keep their relative order but number them after ours
+        let other_created = other.created_data_sources.into_iter().map(|mut ds| {
+            ds.creation_seq = *next_creation_seq;
+            *next_creation_seq += 1;
+            ds
+        });
         match in_handler {
-            true => handler_created_data_sources.extend(other.created_data_sources),
-            false => created_data_sources.extend(other.created_data_sources),
+            true => handler_created_data_sources.extend(other_created),
+            false => created_data_sources.extend(other_created),
         }
         deterministic_errors.extend(other.deterministic_errors);
         entity_cache.extend(other.entity_cache);
         processed_data_sources.extend(other.processed_data_sources);
         persisted_data_sources.extend(other.persisted_data_sources);
+        *handlers_run += other.handlers_run;
     }
 
     pub fn has_errors(&self) -> bool {
         !self.deterministic_errors.is_empty()
     }
 
+    /// Whether any trigger of the block matched a handler so far
+    pub fn has_run_handlers(&self) -> bool {
+        self.handlers_run > 0
+    }
+
     pub fn has_created_data_sources(&self) -> bool {
         assert!(!self.in_handler);
         !self.created_data_sources.is_empty()
     }
 
+    /// Remove the data sources created so far and return them in the order
+    /// in which they were created, i.e., ordered by `creation_seq`. Data
+    /// sources created by the same handler are in the order of the calls to
+    /// `dataSource.create`, and data sources created by earlier triggers
+    /// come before those created by later triggers. The runner instantiates
+    /// and processes the data sources in this order, which makes the order
+    /// of their handlers, and therefore the PoI, deterministic.
     pub fn drain_created_data_sources(&mut self) -> Vec<DataSourceTemplateInfo<C>> {
         assert!(!self.in_handler);
-        std::mem::take(&mut self.created_data_sources)
+        let mut created = std::mem::take(&mut self.created_data_sources);
+        created.sort_by_key(|ds| ds.creation_seq);
+        created
     }
 
     pub fn enter_handler(&mut self) {
         assert!(!self.in_handler);
         self.in_handler = true;
+        self.handlers_run += 1;
         self.entity_cache.enter_handler()
     }
 
@@ -106,8 +144,12 @@ impl<C: Blockchain> BlockState<C> {
         self.deterministic_errors.push(e);
     }
 
-    pub fn push_created_data_source(&mut self, ds: DataSourceTemplateInfo<C>) {
+    /// Remember that the current handler created `ds`. This overwrites the
+    /// `creation_seq` of `ds` with the next sequence number of this block
+    pub fn push_created_data_source(&mut self, mut ds: DataSourceTemplateInfo<C>) {
         assert!(self.in_handler);
+        ds.creation_seq = self.next_creation_seq;
+        self.next_creation_seq += 1;
         self.handler_created_data_sources.push(ds);
     }
 
@@ -0,0 +1,196 @@
+//! Count how often the execution of a deployment's mappings ends other
+//! than by returning from a handler: WASM traps, handler timeouts, running
+//! out of memory, and the termination of the thread that runs the
+//! mappings. Bursts of these usually mean that the deployment keeps
+//! getting restarted.
+//!
+//! The WASM runtime records each termination with the deployment's
+//! [`MappingTerminations`], which it gets from [`mapping_terminations`];
+//! the index node reports the counts in the indexing status.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+/// The window over which the rate of terminations is measured
+pub const TERMINATION_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<MappingTerminations>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Return the tracker for `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn mapping_terminations(deployment: &str) -> Arc<MappingTerminations> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Forget the tracker for `deployment` once the deployment stopped
+/// running on this node
+pub fn remove_mapping_terminations(deployment: &str) {
+    TRACKERS.lock().unwrap().remove(deployment);
+}
+
+/// The number of terminations of the mappings of `deployment` since it
+/// started running on this node, by reason; empty if this node is not
+/// running it
+pub fn termination_counts(deployment: &str) -> BTreeMap<String, u64> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .get(deployment)
+        .map(|tracker| tracker.counts())
+        .unwrap_or_default()
+}
+
+/// Why the execution of a mapping ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TerminationReason {
+    /// A WASM trap of the given kind, like `unreachable` or `host`
+    Trap(String),
+    /// The handler hit the handler or host wait timeout
+    Timeout,
+    /// The mapping ran out of memory
+    OutOfMemory,
+    /// The thread that runs the mappings stopped because the receiver of
+    /// its results went away
+    ChannelClosed,
+    /// The thread that runs the mappings stopped because the deployment
+    /// was stopped
+    Canceled,
+    /// The thread that runs the mappings panicked
+    Panic,
+}
+
+impl TerminationReason {
+    /// Whether this termination counts towards the rate of abnormal
+    /// terminations; a deployment that is stopped is not abnormal
+    fn is_abnormal(&self) -> bool {
+        !matches!(self, TerminationReason::Canceled)
+    }
+}
+
+impl fmt::Display for TerminationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerminationReason::Trap(kind) => write!(f, "trap:{}", kind),
+            TerminationReason::Timeout => write!(f, "timeout"),
+            TerminationReason::OutOfMemory => write!(f, "oom"),
+            TerminationReason::ChannelClosed => write!(f, "channel_closed"),
+            TerminationReason::Canceled => write!(f, "canceled"),
+            TerminationReason::Panic => write!(f, "panic"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The number of terminations, by reason
+    counts: BTreeMap<String, u64>,
+    /// When the abnormal terminations within the last
+    /// `TERMINATION_RATE_WINDOW` happened
+    recent: VecDeque<Instant>,
+    /// When we last warned about too many terminations
+    warned: Option<Instant>,
+}
+
+/// Counts the terminations of the mappings of one deployment
+#[derive(Debug, Default)]
+pub struct MappingTerminations {
+    inner: Mutex<Inner>,
+}
+
+impl MappingTerminations {
+    /// Record a termination for `reason` at `now`. Returns the number of
+    /// abnormal terminations within the last `TERMINATION_RATE_WINDOW` if
+    /// that exceeds `max_rate` and the caller should warn about it; that
+    /// happens at most once per window. A `max_rate` of 0 never warns
+    pub fn record(
+        &self,
+        reason: &TerminationReason,
+        now: Instant,
+        max_rate: usize,
+    ) -> Option<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.counts.entry(reason.to_string()).or_default() += 1;
+
+        if !reason.is_abnormal() {
+            return None;
+        }
+        inner.recent.push_back(now);
+        while let Some(first) = inner.recent.front() {
+            if now.saturating_duration_since(*first) <= TERMINATION_RATE_WINDOW {
+                break;
+            }
+            inner.recent.pop_front();
+        }
+
+        let rate = inner.recent.len();
+        let warned_recently = inner.warned.map_or(false, |warned| {
+            now.saturating_duration_since(warned) < TERMINATION_RATE_WINDOW
+        });
+        if max_rate == 0 || rate <= max_rate || warned_recently {
+            return None;
+        }
+        inner.warned = Some(now);
+        Some(rate)
+    }
+
+    /// The number of terminations that were recorded, by reason
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.inner.lock().unwrap().counts.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_rate() {
+        let tracker = MappingTerminations::default();
+        let start = Instant::now();
+        let trap = TerminationReason::Trap("unreachable".to_string());
+
+        assert_eq!(None, tracker.record(&trap, start, 2));
+        assert_eq!(None, tracker.record(&TerminationReason::Timeout, start, 2));
+        // Stopping the deployment is not abnormal
+        assert_eq!(None, tracker.record(&TerminationReason::Canceled, start, 2));
+        assert_eq!(Some(3), tracker.record(&trap, start, 2));
+        // Only warn once per window
+        assert_eq!(None, tracker.record(&trap, start, 2));
+
+        // Terminations that are older than the window do not count
+        let later = start + TERMINATION_RATE_WINDOW * 2;
+        assert_eq!(None, tracker.record(&trap, later, 2));
+
+        let counts = tracker.counts();
+        assert_eq!(Some(&4), counts.get("trap:unreachable"));
+        assert_eq!(Some(&1), counts.get("timeout"));
+        assert_eq!(Some(&1), counts.get("canceled"));
+    }
+
+    #[test]
+    fn same_tracker() {
+        mapping_terminations("QmMappingTerminationsTest").record(
+            &TerminationReason::Panic,
+            Instant::now(),
+            0,
+        );
+        let counts = termination_counts("QmMappingTerminationsTest");
+        assert_eq!(Some(&1), counts.get("panic"));
+        assert!(termination_counts("QmMappingTerminationsOther").is_empty());
+
+        remove_mapping_terminations("QmMappingTerminationsTest");
+        assert!(termination_counts("QmMappingTerminationsTest").is_empty());
+    }
+}
@@ -1,9 +1,14 @@
+pub mod activity;
+pub mod handler_entity_types;
+pub mod handler_stats;
 mod host;
 mod instance;
 mod instance_manager;
+pub mod mapping_terminations;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
+pub mod wasm_performance;
 
 pub use crate::prelude::Entity;
 
@@ -15,4 +20,4 @@ pub use self::proof_of_indexing::{
     ProofOfIndexingVersion, SharedProofOfIndexing,
 };
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::registrar::{SafeMode, SubgraphRegistrar, SubgraphVersionSwitchingMode};
@@ -28,6 +28,34 @@ impl FromStr for SubgraphVersionSwitchingMode {
     }
 }
 
+/// Whether a node starts its deployments in waves when it starts up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafeMode {
+    /// Start all deployments at once
+    Off,
+    /// Always start deployments in waves
+    On,
+    /// Start deployments in waves if the previous run of the node did not
+    /// shut down cleanly
+    Auto,
+}
+
+impl FromStr for SafeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "false" => Ok(SafeMode::Off),
+            "on" | "true" => Ok(SafeMode::On),
+            "auto" => Ok(SafeMode::Auto),
+            _ => Err(format!(
+                "invalid safe mode {:?}, must be one of off, on, or auto",
+                s
+            )),
+        }
+    }
+}
+
 /// Common trait for subgraph registrars.
 #[async_trait]
 pub trait SubgraphRegistrar: Send + Sync + 'static {
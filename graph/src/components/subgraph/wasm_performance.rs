@@ -0,0 +1,163 @@
+//! Track how long compiling the WASM modules of a deployment took and how
+//! long its handlers spend executing WASM afterwards, so that operators can judge
+//! whether a different compile profile pays off for the deployment.
+//!
+//! The runtime records each compilation and each handler run with the
+//! deployment's [`WasmPerformance`], which it gets from
+//! [`wasm_performance`]; the index node reports a summary in the indexing
+//! status.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::data::subgraph::status;
+use crate::runtime::CompileProfile;
+
+lazy_static! {
+    static ref TRACKERS: Mutex<HashMap<String, Arc<WasmPerformance>>> = Mutex::new(HashMap::new());
+}
+
+/// Return the tracker for `deployment`. All callers get the same tracker
+/// for the same deployment
+pub fn wasm_performance(deployment: &str) -> Arc<WasmPerformance> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .entry(deployment.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Forget the tracker for `deployment` once the deployment stopped
+/// running on this node
+pub fn remove_wasm_performance(deployment: &str) {
+    TRACKERS.lock().unwrap().remove(deployment);
+}
+
+/// The summary for `deployment`; `None` if this node is not running it or
+/// never compiled its modules
+pub fn wasm_performance_summary(deployment: &str) -> Option<status::WasmPerformance> {
+    TRACKERS
+        .lock()
+        .unwrap()
+        .get(deployment)
+        .and_then(|tracker| tracker.summary())
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The profile that the modules were last compiled with
+    profile: Option<CompileProfile>,
+    /// The total time spent compiling modules with `profile`
+    compile_time: Duration,
+    /// The number of handler runs since the modules were compiled with
+    /// `profile`
+    handler_count: u64,
+    /// The total time these handler runs spent executing WASM, without
+    /// waiting on host functions that perform I/O
+    handler_time: Duration,
+}
+
+/// Compile and handler times of one deployment
+#[derive(Debug, Default)]
+pub struct WasmPerformance {
+    inner: Mutex<Inner>,
+}
+
+impl WasmPerformance {
+    /// Forget everything that was recorded; called when the deployment
+    /// starts and its modules are compiled again
+    pub fn reset(&self) {
+        *self.inner.lock().unwrap() = Inner::default();
+    }
+
+    /// Record that compiling a module with `profile` took `duration`.
+    /// Compiling with a different profile than before starts over
+    pub fn record_compile(&self, profile: CompileProfile, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.profile != Some(profile) {
+            *inner = Inner {
+                profile: Some(profile),
+                ..Inner::default()
+            };
+        }
+        inner.compile_time += duration;
+    }
+
+    /// Record that a handler spent `duration` executing WASM
+    pub fn record_handler(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.handler_count += 1;
+        inner.handler_time += duration;
+    }
+
+    /// The profile that the modules were last compiled with and the total
+    /// time compiling them took
+    pub fn compilation(&self) -> Option<(CompileProfile, Duration)> {
+        let inner = self.inner.lock().unwrap();
+        inner.profile.map(|profile| (profile, inner.compile_time))
+    }
+
+    pub fn summary(&self) -> Option<status::WasmPerformance> {
+        let inner = self.inner.lock().unwrap();
+        let profile = inner.profile?;
+        let average_handler_time = if inner.handler_count == 0 {
+            None
+        } else {
+            let nanos = inner.handler_time.as_nanos() / inner.handler_count as u128;
+            Some(Duration::from_nanos(nanos as u64))
+        };
+        Some(status::WasmPerformance {
+            profile,
+            compile_time: inner.compile_time,
+            handler_count: inner.handler_count,
+            average_handler_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_and_handler_times() {
+        let tracker = WasmPerformance::default();
+        assert!(tracker.summary().is_none());
+
+        tracker.record_compile(CompileProfile::FastCompile, Duration::from_millis(10));
+        tracker.record_compile(CompileProfile::FastCompile, Duration::from_millis(20));
+        tracker.record_handler(Duration::from_millis(4));
+        tracker.record_handler(Duration::from_millis(8));
+        let summary = tracker.summary().unwrap();
+        assert_eq!(CompileProfile::FastCompile, summary.profile);
+        assert_eq!(Duration::from_millis(30), summary.compile_time);
+        assert_eq!(2, summary.handler_count);
+        assert_eq!(Some(Duration::from_millis(6)), summary.average_handler_time);
+
+        // A different profile starts over
+        tracker.record_compile(CompileProfile::Optimized, Duration::from_millis(50));
+        let summary = tracker.summary().unwrap();
+        assert_eq!(CompileProfile::Optimized, summary.profile);
+        assert_eq!(Duration::from_millis(50), summary.compile_time);
+        assert_eq!(0, summary.handler_count);
+        assert_eq!(None, summary.average_handler_time);
+        assert_eq!(
+            Some((CompileProfile::Optimized, Duration::from_millis(50))),
+            tracker.compilation()
+        );
+    }
+
+    #[test]
+    fn remove_tracker() {
+        let deployment = "QmWasmPerformanceRemoveTest";
+        wasm_performance(deployment).record_compile(CompileProfile::FastCompile, Duration::ZERO);
+        assert!(wasm_performance_summary(deployment).is_some());
+
+        remove_wasm_performance(deployment);
+        assert!(wasm_performance_summary(deployment).is_none());
+    }
+}
@@ -9,7 +9,7 @@ use std::string::FromUtf8Error;
 use std::sync::Arc;
 
 use crate::data::subgraph::*;
-use crate::prelude::q;
+use crate::prelude::{q, BlockNumber};
 use crate::{components::store::StoreError, prelude::CacheWeight};
 
 #[derive(Debug, Clone)]
@@ -75,6 +75,8 @@ pub enum QueryExecutionError {
     InvalidSubgraphManifest,
     ResultTooBig(usize, usize),
     DeploymentNotFound(String),
+    MinBlockNotReached(String, BlockNumber, BlockNumber), // (deployment, latest, min)
+    HistoryPruned(String, BlockNumber, BlockNumber),      // (deployment, earliest, requested)
 }
 
 impl QueryExecutionError {
@@ -131,7 +133,9 @@ impl QueryExecutionError {
             | InvalidSubgraphManifest
             | ValidationError(_, _)
             | ResultTooBig(_, _)
-            | DeploymentNotFound(_) => false,
+            | DeploymentNotFound(_)
+            | MinBlockNotReached(_, _, _)
+            | HistoryPruned(_, _, _) => false,
         }
     }
 }
@@ -275,7 +279,11 @@ impl fmt::Display for QueryExecutionError {
             SubgraphManifestResolveError(e) => write!(f, "failed to resolve subgraph manifest: {}", e),
             InvalidSubgraphManifest => write!(f, "invalid subgraph manifest file"),
             ResultTooBig(actual, limit) => write!(f, "the result size of {} is larger than the allowed limit of {}", actual, limit),
-            DeploymentNotFound(id_or_name) => write!(f, "deployment `{}` does not exist", id_or_name)
+            DeploymentNotFound(id_or_name) => write!(f, "deployment `{}` does not exist", id_or_name),
+            MinBlockNotReached(id, latest, min) => write!(f, "subgraph {} has only indexed up to block number {} \
+                           but the query requires at least block number {}", id, latest, min),
+            HistoryPruned(id, earliest, block) => write!(f, "history pruned below block {}: subgraph {} \
+                           no longer has data for block number {}", earliest, id, block),
         }
     }
 }
@@ -7,5 +7,5 @@ mod trace;
 pub use self::cache_status::CacheStatus;
 pub use self::error::{QueryError, QueryExecutionError};
 pub use self::query::{Query, QueryTarget, QueryVariables};
-pub use self::result::{QueryResult, QueryResults};
+pub use self::result::{IndexingProgress, QueryResult, QueryResults};
 pub use self::trace::Trace;
@@ -133,6 +133,9 @@ pub struct Query {
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
     pub trace: bool,
+    /// Whether to report the indexing progress of the deployment in the
+    /// `extensions` of the response
+    pub indexing_progress: bool,
     _force_use_of_new: (),
 }
 
@@ -161,6 +164,7 @@ impl Query {
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
             trace,
+            indexing_progress: false,
             _force_use_of_new: (),
         }
     }
@@ -1,6 +1,6 @@
 use super::error::{QueryError, QueryExecutionError};
 use crate::data::value::Object;
-use crate::prelude::{r, CacheWeight, DeploymentHash};
+use crate::prelude::{r, BlockNumber, CacheWeight, DeploymentHash};
 use http::header::{
     ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
     CONTENT_TYPE,
@@ -43,19 +43,48 @@ where
 
 pub type Data = Object;
 
+/// How far the deployment that answered a query has indexed. Sent to
+/// clients that ask for it as `extensions.indexing` in the response
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexingProgress {
+    /// The latest block that the deployment has processed
+    pub latest_block: BlockNumber,
+    /// The earliest block that can be queried; history before it was
+    /// pruned or never indexed
+    pub earliest_block: BlockNumber,
+    /// The head of the deployment's chain, if known
+    pub head_block: Option<BlockNumber>,
+    /// The number of seconds between the timestamp of `latest_block` and
+    /// the time the query was answered, if the timestamp is known
+    pub lag_seconds: Option<u64>,
+    pub health: &'static str,
+}
+
 #[derive(Debug)]
 /// A collection of query results that is serialized as a single result.
 pub struct QueryResults {
     results: Vec<Arc<QueryResult>>,
+    indexing: Option<IndexingProgress>,
 }
 
 impl QueryResults {
     pub fn empty() -> Self {
         QueryResults {
             results: Vec::new(),
+            indexing: None,
         }
     }
 
+    /// Report `progress` in the `extensions` of the response
+    pub fn set_indexing_progress(&mut self, progress: IndexingProgress) {
+        self.indexing = Some(progress);
+    }
+
+    pub fn indexing_progress(&self) -> Option<&IndexingProgress> {
+        self.indexing.as_ref()
+    }
+
     pub fn first(&self) -> Option<&Arc<QueryResult>> {
         self.results.first()
     }
@@ -107,6 +136,9 @@ impl Serialize for QueryResults {
         if first_trace.is_some() {
             len += 1;
         }
+        if self.indexing.is_some() {
+            len += 1;
+        }
         let mut state = serializer.serialize_struct("QueryResults", len)?;
 
         // Serialize data.
@@ -145,6 +177,15 @@ impl Serialize for QueryResults {
         if let Some(trace) = first_trace {
             state.serialize_field("trace", trace)?;
         }
+
+        if let Some(indexing) = &self.indexing {
+            #[derive(Serialize)]
+            struct SerExtensions<'a> {
+                indexing: &'a IndexingProgress,
+            }
+
+            state.serialize_field("extensions", &SerExtensions { indexing })?;
+        }
         state.end()
     }
 }
@@ -153,6 +194,7 @@ impl From<Data> for QueryResults {
     fn from(x: Data) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            indexing: None,
         }
     }
 }
@@ -161,13 +203,17 @@ impl From<QueryResult> for QueryResults {
     fn from(x: QueryResult) -> Self {
         QueryResults {
             results: vec![Arc::new(x)],
+            indexing: None,
         }
     }
 }
 
 impl From<Arc<QueryResult>> for QueryResults {
     fn from(x: Arc<QueryResult>) -> Self {
-        QueryResults { results: vec![x] }
+        QueryResults {
+            results: vec![x],
+            indexing: None,
+        }
     }
 }
 
@@ -175,6 +221,7 @@ impl From<QueryExecutionError> for QueryResults {
     fn from(x: QueryExecutionError) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            indexing: None,
         }
     }
 }
@@ -183,6 +230,7 @@ impl From<Vec<QueryExecutionError>> for QueryResults {
     fn from(x: Vec<QueryExecutionError>) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            indexing: None,
         }
     }
 }
@@ -199,7 +247,10 @@ impl QueryResults {
         http::Response::builder()
             .status(status_code)
             .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-            .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
+            .header(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                "Content-Type, User-Agent, X-GraphIndexingProgress",
+            )
             .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
             .header(CONTENT_TYPE, "application/json")
             .header(
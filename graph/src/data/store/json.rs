@@ -0,0 +1,251 @@
+//! The canonical JSON encoding of entity `Value`s that mappings use through
+//! the `value.toJSON` and `value.fromJSON` host exports.
+//!
+//! `Value::Null` is encoded as `null`; every other value is encoded as an
+//! object `{"data": <data>, "type": <variant>}` where `data` is
+//!
+//! - `Bool`: a JSON boolean
+//! - `Int`: a JSON number
+//! - `String`: a JSON string
+//! - `BigInt`: the decimal representation as a JSON string, e.g. `"-17"`
+//! - `BigDecimal`: the decimal representation of the normalized value as a
+//!   JSON string, e.g. `"1.5"`, never with trailing zeros or an exponent.
+//!   Decoding accepts an exponent, but not one that puts the value outside
+//!   of the range that `BigDecimal` supports
+//! - `Bytes`: lowercase hex with a `0x` prefix as a JSON string
+//! - `List`: a JSON array of the encoded elements
+//!
+//! Keys are always emitted in the order shown and without any whitespace,
+//! so that the encoding of a value is byte-for-byte stable. Since the
+//! encoding is visible to mappings, it must never change; the golden
+//! vectors in the tests below pin it down.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use serde_json::json;
+
+use super::scalar::{BigDecimal, BigInt, Bytes};
+use super::Value;
+
+/// Encode `value` with the canonical JSON encoding
+pub fn to_json(value: &Value) -> String {
+    to_json_value(value).to_string()
+}
+
+/// Decode a value that was encoded with the canonical JSON encoding. This
+/// fails for any input that `to_json` could not have produced, except that
+/// `BigDecimal` values are normalized and hex digits in `Bytes` may be
+/// uppercase
+pub fn from_json(json: &str) -> Result<Value, Error> {
+    let json: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| anyhow!("invalid JSON: {}", e))?;
+    from_json_value(&json)
+}
+
+fn to_json_value(value: &Value) -> serde_json::Value {
+    let (kind, data) = match value {
+        Value::Null => return serde_json::Value::Null,
+        Value::Bool(b) => ("Bool", json!(b)),
+        Value::Int(n) => ("Int", json!(n)),
+        Value::String(s) => ("String", json!(s)),
+        Value::BigInt(n) => ("BigInt", json!(n.to_string())),
+        Value::BigDecimal(n) => ("BigDecimal", json!(n.normalized().to_string())),
+        Value::Bytes(b) => ("Bytes", json!(b.to_string())),
+        Value::List(values) => (
+            "List",
+            serde_json::Value::Array(values.iter().map(to_json_value).collect()),
+        ),
+    };
+    json!({ "data": data, "type": kind })
+}
+
+fn from_json_value(json: &serde_json::Value) -> Result<Value, Error> {
+    let obj = match json {
+        serde_json::Value::Null => return Ok(Value::Null),
+        serde_json::Value::Object(obj) => obj,
+        _ => return Err(anyhow!("expected `null` or an object but got `{}`", json)),
+    };
+    if obj.len() != 2 {
+        return Err(anyhow!(
+            "expected an object with keys `data` and `type` but got `{}`",
+            json
+        ));
+    }
+    let kind = obj
+        .get("type")
+        .and_then(|kind| kind.as_str())
+        .ok_or_else(|| anyhow!("the `type` of `{}` must be a string", json))?;
+    let data = obj
+        .get("data")
+        .ok_or_else(|| anyhow!("`{}` does not have `data`", json))?;
+
+    let string = || {
+        data.as_str()
+            .ok_or_else(|| anyhow!("the data for a {} must be a string but is `{}`", kind, data))
+    };
+
+    let value = match kind {
+        "Bool" => data
+            .as_bool()
+            .map(Value::Bool)
+            .ok_or_else(|| anyhow!("the data for a Bool must be a boolean but is `{}`", data))?,
+        "Int" => data
+            .as_i64()
+            .and_then(|n| i32::try_from(n).ok())
+            .map(Value::Int)
+            .ok_or_else(|| anyhow!("the data for an Int must be an i32 but is `{}`", data))?,
+        "String" => Value::String(string()?.to_owned()),
+        "BigInt" => Value::BigInt(
+            BigInt::from_str(string()?).map_err(|e| anyhow!("invalid BigInt: {}", e))?,
+        ),
+        "BigDecimal" => {
+            let n = BigDecimal::from_str(string()?)
+                .map_err(|e| anyhow!("invalid BigDecimal: {}", e))?
+                .normalized();
+            // Mappings can not create values outside of this range, and
+            // values with huge exponents would be expensive to encode
+            let exp = -n.as_bigint_and_exponent().1;
+            let min_exp: i64 = BigDecimal::MIN_EXP.into();
+            let max_exp: i64 = BigDecimal::MAX_EXP.into();
+            if exp < min_exp || max_exp < exp {
+                return Err(anyhow!(
+                    "big decimal exponent `{}` is outside the `{}` to `{}` range",
+                    exp,
+                    min_exp,
+                    max_exp
+                ));
+            }
+            Value::BigDecimal(n)
+        }
+        "Bytes" => {
+            let s = string()?;
+            if !s.starts_with("0x") {
+                return Err(anyhow!("Bytes must start with `0x` but got `{}`", s));
+            }
+            Value::Bytes(Bytes::from_str(s).map_err(|e| anyhow!("invalid Bytes: {}", e))?)
+        }
+        "List" => Value::List(
+            data.as_array()
+                .ok_or_else(|| anyhow!("the data for a List must be an array but is `{}`", data))?
+                .iter()
+                .map(from_json_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(anyhow!("unknown value type `{}`", kind)),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{from_json, to_json};
+    use crate::data::store::scalar::{BigDecimal, BigInt, Bytes};
+    use crate::data::store::Value;
+
+    fn golden() -> Vec<(Value, &'static str)> {
+        vec![
+            (Value::Null, "null"),
+            (Value::Bool(true), r#"{"data":true,"type":"Bool"}"#),
+            (Value::Int(-42), r#"{"data":-42,"type":"Int"}"#),
+            (Value::Int(i32::MAX), r#"{"data":2147483647,"type":"Int"}"#),
+            (
+                Value::String("a \"quoted\" ☃".to_string()),
+                r#"{"data":"a \"quoted\" ☃","type":"String"}"#,
+            ),
+            (
+                Value::BigInt(BigInt::from_str("-123456789012345678901234567890").unwrap()),
+                r#"{"data":"-123456789012345678901234567890","type":"BigInt"}"#,
+            ),
+            (
+                Value::BigDecimal(BigDecimal::from_str("1.50").unwrap()),
+                r#"{"data":"1.5","type":"BigDecimal"}"#,
+            ),
+            (
+                Value::BigDecimal(BigDecimal::from_str("1200").unwrap()),
+                r#"{"data":"1200","type":"BigDecimal"}"#,
+            ),
+            (
+                Value::BigDecimal(BigDecimal::from_str("-0.000").unwrap()),
+                r#"{"data":"0","type":"BigDecimal"}"#,
+            ),
+            (
+                Value::Bytes(Bytes::from_str("0xDEADbeef").unwrap()),
+                r#"{"data":"0xdeadbeef","type":"Bytes"}"#,
+            ),
+            (
+                Value::Bytes(Bytes::from(&[][..])),
+                r#"{"data":"0x","type":"Bytes"}"#,
+            ),
+            (Value::List(vec![]), r#"{"data":[],"type":"List"}"#),
+            (
+                Value::List(vec![
+                    Value::Null,
+                    Value::Int(1),
+                    Value::List(vec![Value::String("x".to_string()), Value::Null]),
+                ]),
+                r#"{"data":[null,{"data":1,"type":"Int"},{"data":[{"data":"x","type":"String"},null],"type":"List"}],"type":"List"}"#,
+            ),
+        ]
+    }
+
+    #[test]
+    fn golden_vectors() {
+        for (value, json) in golden() {
+            assert_eq!(json, to_json(&value), "encoding {:?}", value);
+            assert_eq!(value, from_json(json).unwrap(), "decoding {}", json);
+        }
+    }
+
+    #[test]
+    fn decoding_normalizes() {
+        assert_eq!(
+            Value::BigDecimal(BigDecimal::from_str("1.5").unwrap()),
+            from_json(r#"{"type": "BigDecimal", "data": "1.5000"}"#).unwrap()
+        );
+        assert_eq!(
+            r#"{"data":"0xab","type":"Bytes"}"#,
+            to_json(&from_json(r#"{"data":"0xAB","type":"Bytes"}"#).unwrap())
+        );
+        // Exponents are fine as long as the value is in range
+        assert_eq!(
+            Value::BigDecimal(BigDecimal::from_str("1e6144").unwrap()),
+            from_json(r#"{"data":"1e6144","type":"BigDecimal"}"#).unwrap()
+        );
+        assert_eq!(
+            Value::BigDecimal(BigDecimal::from_str("1e-6143").unwrap()),
+            from_json(r#"{"data":"1e-6143","type":"BigDecimal"}"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_non_canonical_input() {
+        for json in [
+            "",
+            "1",
+            r#""x""#,
+            "[]",
+            r#"{"type":"Int"}"#,
+            r#"{"data":1,"type":"Int","extra":0}"#,
+            r#"{"data":1,"type":"Float"}"#,
+            r#"{"data":"1","type":"Int"}"#,
+            r#"{"data":2147483648,"type":"Int"}"#,
+            r#"{"data":1.5,"type":"Int"}"#,
+            r#"{"data":1,"type":"BigInt"}"#,
+            r#"{"data":"1.5","type":"BigInt"}"#,
+            r#"{"data":"abc","type":"BigDecimal"}"#,
+            r#"{"data":"1e6145","type":"BigDecimal"}"#,
+            r#"{"data":"10e6144","type":"BigDecimal"}"#,
+            r#"{"data":"1e-6144","type":"BigDecimal"}"#,
+            r#"{"data":"1e-9999999999","type":"BigDecimal"}"#,
+            r#"{"data":"deadbeef","type":"Bytes"}"#,
+            r#"{"data":"0xabc","type":"Bytes"}"#,
+            r#"{"data":"true","type":"Bool"}"#,
+            r#"{"data":[1],"type":"List"}"#,
+        ] {
+            assert!(from_json(json).is_err(), "accepted {}", json);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     components::store::{DeploymentLocator, EntityKey, EntityType},
     data::graphql::ObjectTypeExt,
-    prelude::{anyhow::Context, q, r, s, CacheWeight, QueryExecutionError, Schema},
+    prelude::{q, r, s, CacheWeight, QueryExecutionError, Schema},
     runtime::gas::{Gas, GasSizeOf},
 };
 use crate::{data::subgraph::DeploymentHash, prelude::EntityChange};
@@ -29,6 +29,9 @@ pub mod scalar;
 // Ethereum compatibility.
 pub mod ethereum;
 
+/// The canonical JSON encoding of values exposed to mappings.
+pub mod json;
+
 /// Filter subscriptions
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum SubscriptionFilter {
@@ -694,7 +697,17 @@ impl Entity {
     /// Validate that this entity matches the object type definition in the
     /// schema. An entity that passes these checks can be stored
     /// successfully in the subgraph's database schema
-    pub fn validate(&self, schema: &Schema, key: &EntityKey) -> Result<(), anyhow::Error> {
+    pub fn validate(&self, schema: &Schema, key: &EntityKey) -> Result<(), EntityValidationError> {
+        self.validate_with(schema, key, EntityValidation::Legacy)
+    }
+
+    /// Like `validate`, but with the checks that `validation` selects
+    pub fn validate_with(
+        &self,
+        schema: &Schema,
+        key: &EntityKey,
+        validation: EntityValidation,
+    ) -> Result<(), EntityValidationError> {
         fn scalar_value_type(schema: &Schema, field_type: &s::Type) -> ValueType {
             use s::TypeDefinition as t;
             match field_type {
@@ -739,6 +752,15 @@ impl Entity {
             }
         }
 
+        /// The enum type of `field_type` if it is an enum or a list of
+        /// enums
+        fn enum_type<'a>(schema: &'a Schema, field_type: &s::Type) -> Option<&'a s::EnumType> {
+            match schema.document.get_named_type(field_type.get_base_type()) {
+                Some(s::TypeDefinition::Enum(enum_type)) => Some(enum_type),
+                _ => None,
+            }
+        }
+
         if key.entity_type.is_poi() {
             // Users can't modify Poi entities, and therefore they do not
             // need to be validated. In addition, the schema has no object
@@ -749,16 +771,23 @@ impl Entity {
         let object_type = object_type_definitions
             .iter()
             .find(|object_type| key.entity_type.as_str() == object_type.name)
-            .with_context(|| {
-                format!(
-                    "Entity {}[{}]: unknown entity type `{}`",
-                    key.entity_type, key.entity_id, key.entity_type
-                )
+            .ok_or_else(|| EntityValidationError::UnknownEntityType {
+                entity_type: key.entity_type.to_string(),
+                entity_id: key.entity_id.to_string(),
             })?;
 
         for field in &object_type.fields {
             let is_derived = field.is_derived();
             match (self.get(&field.name), is_derived) {
+                (Some(Value::Null), false)
+                    if validation == EntityValidation::Strict && field.field_type.is_non_null() =>
+                {
+                    return Err(EntityValidationError::MissingValueForNonNullableField {
+                        entity_type: key.entity_type.to_string(),
+                        entity_id: key.entity_id.to_string(),
+                        field: field.name.clone(),
+                    });
+                }
                 (Some(value), false) => {
                     let scalar_type = scalar_value_type(schema, &field.field_type);
                     if field.field_type.is_list() {
@@ -768,50 +797,77 @@ impl Entity {
                         if let Value::List(elts) = value {
                             for (index, elt) in elts.iter().enumerate() {
                                 if !elt.is_assignable(&scalar_type, false) {
-                                    anyhow::bail!(
-                                        "Entity {}[{}]: field `{}` is of type {}, but the value `{}` \
-                                        contains a {} at index {}",
-                                        key.entity_type,
-                                        key.entity_id,
-                                        field.name,
-                                        &field.field_type,
-                                        value,
-                                        elt.type_name(),
-                                        index
+                                    return Err(
+                                        EntityValidationError::MismatchedElementTypeInList {
+                                            entity_type: key.entity_type.to_string(),
+                                            entity_id: key.entity_id.to_string(),
+                                            field: field.name.clone(),
+                                            expected_type: field.field_type.to_string(),
+                                            value: truncated(value),
+                                            actual_type: elt.type_name(),
+                                            index,
+                                        },
                                     );
                                 }
                             }
                         }
                     }
                     if !value.is_assignable(&scalar_type, field.field_type.is_list()) {
-                        anyhow::bail!(
-                            "Entity {}[{}]: the value `{}` for field `{}` must have type {} but has type {}",
-                            key.entity_type,
-                            key.entity_id,
-                            value,
-                            field.name,
-                            &field.field_type,
-                            value.type_name()
-                        );
+                        return Err(EntityValidationError::InvalidFieldType {
+                            entity_type: key.entity_type.to_string(),
+                            entity_id: key.entity_id.to_string(),
+                            field: field.name.clone(),
+                            expected_type: field.field_type.to_string(),
+                            value: truncated(value),
+                            actual_type: value.type_name(),
+                        });
+                    }
+                    let enum_type = match validation {
+                        EntityValidation::Strict => enum_type(schema, &field.field_type),
+                        EntityValidation::Legacy => None,
+                    };
+                    if let Some(enum_type) = enum_type {
+                        let elts: Vec<(Option<usize>, &Value)> = match value {
+                            Value::List(elts) => elts
+                                .iter()
+                                .enumerate()
+                                .map(|(index, elt)| (Some(index), elt))
+                                .collect(),
+                            value => vec![(None, value)],
+                        };
+                        for (index, elt) in elts {
+                            let name = match elt {
+                                Value::String(name) => name,
+                                _ => continue,
+                            };
+                            if !enum_type.values.iter().any(|v| &v.name == name) {
+                                return Err(EntityValidationError::InvalidEnumValue {
+                                    entity_type: key.entity_type.to_string(),
+                                    entity_id: key.entity_id.to_string(),
+                                    field: field.name.clone(),
+                                    enum_type: enum_type.name.clone(),
+                                    value: truncated(elt),
+                                    index,
+                                });
+                            }
+                        }
                     }
                 }
                 (None, false) => {
                     if field.field_type.is_non_null() {
-                        anyhow::bail!(
-                            "Entity {}[{}]: missing value for non-nullable field `{}`",
-                            key.entity_type,
-                            key.entity_id,
-                            field.name,
-                        );
+                        return Err(EntityValidationError::MissingValueForNonNullableField {
+                            entity_type: key.entity_type.to_string(),
+                            entity_id: key.entity_id.to_string(),
+                            field: field.name.clone(),
+                        });
                     }
                 }
                 (Some(_), true) => {
-                    anyhow::bail!(
-                        "Entity {}[{}]: field `{}` is derived and can not be set",
-                        key.entity_type,
-                        key.entity_id,
-                        field.name,
-                    );
+                    return Err(EntityValidationError::CannotSetDerivedField {
+                        entity_type: key.entity_type.to_string(),
+                        entity_id: key.entity_id.to_string(),
+                        field: field.name.clone(),
+                    });
                 }
                 (None, true) => {
                     // derived fields should not be set
@@ -822,6 +878,113 @@ impl Entity {
     }
 }
 
+/// Which checks `Entity::validate_with` performs. Mappings that were
+/// deployed before the strict checks existed keep the legacy checks, so
+/// that entities they could write before don't suddenly fail validation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntityValidation {
+    Legacy,
+    /// Also reject a `null` value for a non-nullable field and values of
+    /// enum fields that are not values of the enum. Used for mappings with
+    /// `apiVersion` 0.0.8 or later
+    Strict,
+}
+
+impl EntityValidation {
+    pub fn for_api_version(api_version: &semver::Version) -> Self {
+        if api_version >= &crate::data::subgraph::API_VERSION_0_0_8 {
+            EntityValidation::Strict
+        } else {
+            EntityValidation::Legacy
+        }
+    }
+}
+
+/// Values in validation errors are cut off after this many characters
+const MAX_VALIDATION_VALUE_LEN: usize = 256;
+
+/// Render `value` for an error message, cut off so that huge values do not
+/// blow up the message
+fn truncated(value: &Value) -> String {
+    let mut rendered = value.to_string();
+    if let Some((pos, _)) = rendered.char_indices().nth(MAX_VALIDATION_VALUE_LEN) {
+        rendered.truncate(pos);
+        rendered.push_str("...");
+    }
+    rendered
+}
+
+fn at_index(index: &Option<usize>) -> String {
+    index
+        .map(|index| format!(" at index {}", index))
+        .unwrap_or_default()
+}
+
+/// Why an entity does not match its object type in the schema. Each
+/// variant identifies the entity and the field that violates the schema,
+/// and, where there is one, the offending value, cut off after
+/// `MAX_VALIDATION_VALUE_LEN` characters
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum EntityValidationError {
+    #[error("Entity {entity_type}[{entity_id}]: unknown entity type `{entity_type}`")]
+    UnknownEntityType {
+        entity_type: String,
+        entity_id: String,
+    },
+    #[error("Entity {entity_type}[{entity_id}]: missing value for non-nullable field `{field}`")]
+    MissingValueForNonNullableField {
+        entity_type: String,
+        entity_id: String,
+        field: String,
+    },
+    #[error(
+        "Entity {entity_type}[{entity_id}]: field `{field}` is of type {expected_type}, \
+         but the value `{value}` contains a {actual_type} at index {index}"
+    )]
+    MismatchedElementTypeInList {
+        entity_type: String,
+        entity_id: String,
+        field: String,
+        expected_type: String,
+        value: String,
+        actual_type: String,
+        index: usize,
+    },
+    #[error(
+        "Entity {entity_type}[{entity_id}]: the value `{value}` for field `{field}` \
+         must have type {expected_type} but has type {actual_type}"
+    )]
+    InvalidFieldType {
+        entity_type: String,
+        entity_id: String,
+        field: String,
+        expected_type: String,
+        value: String,
+        actual_type: String,
+    },
+    /// The value, or the element at `index` of a list, is not one of the
+    /// values of the enum type of the field
+    #[error(
+        "Entity {entity_type}[{entity_id}]: the value `{value}`{} for field `{field}` \
+         is not a value of enum {enum_type}",
+        at_index(.index)
+    )]
+    InvalidEnumValue {
+        entity_type: String,
+        entity_id: String,
+        field: String,
+        enum_type: String,
+        value: String,
+        index: Option<usize>,
+    },
+    #[error("Entity {entity_type}[{entity_id}]: field `{field}` is derived and can not be set")]
+    CannotSetDerivedField {
+        entity_type: String,
+        entity_id: String,
+        field: String,
+    },
+}
+
 impl From<Entity> for BTreeMap<String, q::Value> {
     fn from(entity: Entity) -> BTreeMap<String, q::Value> {
         entity.0.into_iter().map(|(k, v)| (k, v.into())).collect()
@@ -911,6 +1074,10 @@ fn entity_validation() {
     }
 
     fn check(thing: Entity, errmsg: &str) {
+        check_with(thing, EntityValidation::Strict, errmsg)
+    }
+
+    fn check_with(thing: Entity, validation: EntityValidation, errmsg: &str) {
         const DOCUMENT: &str = "
       enum Color { red, yellow, blue }
       interface Stuff { id: ID!, name: String! }
@@ -922,6 +1089,7 @@ fn entity_validation() {
           id: ID!,
           name: String!,
           favorite_color: Color,
+          colors: [Color!],
           stuff: Stuff,
           things: [Thing!]!
           # Make sure we do not validate derived fields; it's ok
@@ -934,7 +1102,7 @@ fn entity_validation() {
         let id = thing.id().unwrap_or("none".to_owned());
         let key = EntityKey::data("Thing".to_owned(), id.clone());
 
-        let err = thing.validate(&schema, &key);
+        let err = thing.validate_with(&schema, &key, validation);
         if errmsg == "" {
             assert!(
                 err.is_ok(),
@@ -1002,6 +1170,92 @@ fn entity_validation() {
         thing,
         "Entity Thing[t8]: field `cruft` is derived and can not be set",
     );
+
+    let mut thing = make_thing("t9");
+    thing.set("favorite_color", "purple");
+    check_with(thing.clone(), EntityValidation::Legacy, "");
+    check(
+        thing,
+        "Entity Thing[t9]: the value `purple` for field `favorite_color` \
+         is not a value of enum Color",
+    );
+
+    let mut thing = make_thing("t10");
+    thing.set("colors", Value::from(vec!["red", "purple"]));
+    check_with(thing.clone(), EntityValidation::Legacy, "");
+    check(
+        thing,
+        "Entity Thing[t10]: the value `purple` at index 1 for field `colors` \
+         is not a value of enum Color",
+    );
+
+    let mut thing = make_thing("t11");
+    thing.set("colors", Value::from(vec!["red", "blue"]));
+    check(thing, "");
+
+    let mut thing = make_thing("t12");
+    thing.set(
+        "things",
+        Value::List(vec![Value::from(vec!["thing1"]), "thing2".into()]),
+    );
+    check(
+        thing,
+        "Entity Thing[t12]: field `things` is of type [Thing!]!, \
+         but the value `[[thing1], thing2]` contains a [String] at index 0",
+    );
+
+    let mut thing = make_thing("t13");
+    thing.set("name", Value::Null);
+    check_with(thing.clone(), EntityValidation::Legacy, "");
+    check(
+        thing,
+        "Entity Thing[t13]: missing value for non-nullable field `name`",
+    );
+
+    let mut thing = make_thing("t14");
+    thing.set("favorite_color", Value::Null);
+    check(thing, "");
+}
+
+#[test]
+fn entity_validation_error_fields() {
+    const DOCUMENT: &str = "type Thing @entity { id: ID!, count: Int! }";
+    let subgraph = DeploymentHash::new("doesntmatter").unwrap();
+    let schema =
+        crate::prelude::Schema::parse(DOCUMENT, subgraph).expect("Failed to parse test schema");
+
+    let mut thing = Entity::new();
+    thing.set("id", "t1");
+    thing.set("count", "x".repeat(1000));
+    let key = EntityKey::data("Thing".to_owned(), "t1".to_owned());
+    match thing.validate(&schema, &key) {
+        Err(EntityValidationError::InvalidFieldType {
+            entity_type,
+            entity_id,
+            field,
+            expected_type,
+            value,
+            actual_type,
+        }) => {
+            assert_eq!("Thing", entity_type);
+            assert_eq!("t1", entity_id);
+            assert_eq!("count", field);
+            assert_eq!("Int!", expected_type);
+            assert_eq!("String", actual_type);
+            // Long values are cut off
+            assert_eq!(format!("{}...", "x".repeat(256)), value);
+        }
+        other => panic!("expected an invalid field type but got {:?}", other),
+    }
+
+    let key = EntityKey::data("Nothing".to_owned(), "t1".to_owned());
+    assert_eq!(
+        Err(EntityValidationError::UnknownEntityType {
+            entity_type: "Nothing".to_owned(),
+            entity_id: "t1".to_owned(),
+        }),
+        thing.validate(&schema, &key)
+    );
 }
 
 #[test]
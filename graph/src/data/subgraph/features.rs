@@ -34,6 +34,9 @@ pub enum SubgraphFeature {
     FullTextSearch,
     #[serde(alias = "nonDeterministicIpfs")]
     IpfsOnEthereumContracts,
+    /// Writes to entity types that are not on the `entities` list of a
+    /// data source fail with a deterministic error
+    DeclaredEntities,
 }
 
 impl fmt::Display for SubgraphFeature {
@@ -86,6 +89,7 @@ pub fn detect_features<C: Blockchain>(
 ) -> Result<BTreeSet<SubgraphFeature>, InvalidMapping> {
     let features = vec![
         detect_non_fatal_errors(manifest),
+        detect_declared_entities(manifest),
         detect_grafting(manifest),
         detect_full_text_search(&manifest.schema),
         detect_ipfs_on_ethereum_contracts(manifest)?,
@@ -106,6 +110,19 @@ fn detect_non_fatal_errors<C: Blockchain>(
     }
 }
 
+fn detect_declared_entities<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+) -> Option<SubgraphFeature> {
+    if manifest
+        .features
+        .contains(&SubgraphFeature::DeclaredEntities)
+    {
+        Some(SubgraphFeature::DeclaredEntities)
+    } else {
+        None
+    }
+}
+
 fn detect_grafting<C: Blockchain>(manifest: &SubgraphManifest<C>) -> Option<SubgraphFeature> {
     manifest.graft.as_ref().map(|_| SubgraphFeature::Grafting)
 }
@@ -147,17 +164,19 @@ fn detect_ipfs_on_ethereum_contracts<C: Blockchain>(
 mod tests {
     use super::*;
     use SubgraphFeature::*;
-    const VARIANTS: [SubgraphFeature; 4] = [
+    const VARIANTS: [SubgraphFeature; 5] = [
         NonFatalErrors,
         Grafting,
         FullTextSearch,
         IpfsOnEthereumContracts,
+        DeclaredEntities,
     ];
-    const STRING: [&str; 4] = [
+    const STRING: [&str; 5] = [
         "nonFatalErrors",
         "grafting",
         "fullTextSearch",
         "ipfsOnEthereumContracts",
+        "declaredEntities",
     ];
 
     #[test]
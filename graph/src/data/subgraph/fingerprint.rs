@@ -0,0 +1,103 @@
+//! A fingerprint of everything in graph-node that affects the results of
+//! indexing a deployment, like the behavior of the mapping API versions,
+//! gas costs, and the host exports that mappings can call.
+//!
+//! The fingerprint of a deployment is recorded when the deployment is
+//! first started and compared with the current one every time the
+//! deployment is started again. A difference means that the deployment
+//! might now produce different results than it did before, for example,
+//! after graph-node was upgraded.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The settings that affect the semantics of indexing, as a map of
+/// setting names to their values
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompatibilityFingerprint(BTreeMap<String, String>);
+
+impl CompatibilityFingerprint {
+    pub fn set(&mut self, setting: &str, value: impl ToString) {
+        self.0.insert(setting.to_string(), value.to_string());
+    }
+
+    /// A hash over all settings and their values
+    pub fn hash(&self) -> String {
+        hex::encode(tiny_keccak::keccak256(self.description().as_bytes()))
+    }
+
+    /// A description of all settings with one `setting=value` line per
+    /// setting. The description can be turned back into a fingerprint with
+    /// `parse`
+    pub fn description(&self) -> String {
+        self.0
+            .iter()
+            .map(|(setting, value)| format!("{}={}", setting, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Parse a fingerprint from a string produced by `description`
+    pub fn parse(description: &str) -> Self {
+        let settings = description
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(setting, value)| (setting.to_string(), value.to_string()))
+            .collect();
+        CompatibilityFingerprint(settings)
+    }
+
+    /// Describe how the settings in `self` differ from the ones in `other`,
+    /// one entry per setting that differs
+    pub fn changes_from(&self, other: &CompatibilityFingerprint) -> Vec<String> {
+        const UNSET: &str = "<unset>";
+
+        let mut settings: Vec<_> = self.0.keys().chain(other.0.keys()).collect();
+        settings.sort();
+        settings.dedup();
+        settings
+            .into_iter()
+            .filter_map(|setting| {
+                let old = other.0.get(setting).map(String::as_str).unwrap_or(UNSET);
+                let new = self.0.get(setting).map(String::as_str).unwrap_or(UNSET);
+                (old != new).then(|| format!("{}: {} -> {}", setting, old, new))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for CompatibilityFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_compare() {
+        let mut old = CompatibilityFingerprint::default();
+        old.set("spec_version", "0.0.5");
+        old.set("gas_schedule", 1);
+        old.set("features", "");
+
+        let parsed = CompatibilityFingerprint::parse(&old.description());
+        assert_eq!(old, parsed);
+        assert_eq!(old.hash(), parsed.hash());
+        assert!(old.changes_from(&parsed).is_empty());
+
+        let mut new = old.clone();
+        new.set("gas_schedule", 2);
+        new.set("host_exports", "abc");
+        assert_ne!(old.hash(), new.hash());
+        assert_eq!(
+            vec![
+                "gas_schedule: 1 -> 2".to_string(),
+                "host_exports: <unset> -> abc".to_string()
+            ],
+            new.changes_from(&old)
+        );
+    }
+}
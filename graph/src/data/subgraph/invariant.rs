@@ -0,0 +1,512 @@
+//! Invariants that a subgraph declares in the `invariants` section of its
+//! manifest and that the node checks against the deployment's own data
+//! after writing a block:
+//!
+//! ```yaml
+//! invariants:
+//!   - name: supplyMatchesBalances
+//!     check: sum(Token.totalSupply) == sum(Account.balance)
+//!   - name: liquidityNotNegative
+//!     check: sum(Pool.liquidity) >= 0
+//!     strict: true
+//! ```
+//!
+//! A check compares two terms, each of them a number or an aggregate over
+//! the current entities of one type: `count(Type)`, or `sum`, `min` or
+//! `max` of a numeric field as in `max(Type.field)`. The store keeps each
+//! aggregate up to date with the changes of every block, so that checking
+//! an invariant does not depend on how many entities there are; `min` and
+//! `max` can therefore only be used for immutable entity types, whose
+//! entities are never changed or removed.
+//!
+//! Every invariant is checked with every block. A violated invariant is
+//! reported in the indexing status. If a `strict` invariant is violated,
+//! the changes of the block are not written and the deployment fails with
+//! a deterministic error
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error};
+use serde::Deserialize;
+
+use crate::data::graphql::{DocumentExt as _, ObjectTypeExt as _, TypeExt as _};
+use crate::prelude::{BigDecimal, Schema};
+
+/// How the values of an entity type are aggregated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+        }
+    }
+}
+
+/// One side of a check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Number(BigDecimal),
+    /// An aggregate over the current entities of `entity_type`; `field` is
+    /// `None` for `count`
+    Aggregate {
+        function: Aggregate,
+        entity_type: String,
+        field: Option<String>,
+    },
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Number(number) => write!(f, "{}", number),
+            Term::Aggregate {
+                function,
+                entity_type,
+                field: None,
+            } => write!(f, "{}({})", function.as_str(), entity_type),
+            Term::Aggregate {
+                function,
+                entity_type,
+                field: Some(field),
+            } => write!(f, "{}({}.{})", function.as_str(), entity_type, field),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn holds(&self, left: &BigDecimal, right: &BigDecimal) -> bool {
+        match self {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// A comparison between two terms
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub left: Term,
+    pub op: Comparison,
+    pub right: Term,
+}
+
+impl Check {
+    /// The aggregates the check needs
+    pub fn terms(&self) -> [&Term; 2] {
+        [&self.left, &self.right]
+    }
+
+    /// Describe why the check failed when its sides had the values `left`
+    /// and `right`
+    pub fn violation_message(&self, left: &BigDecimal, right: &BigDecimal) -> String {
+        format!(
+            "`{}` is {} and `{}` is {}",
+            self.left, left, self.right, right
+        )
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.left, self.op.as_str(), self.right)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(BigDecimal),
+    Op(Comparison),
+    Open,
+    Close,
+    Dot,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+        let token = match c {
+            c if c.is_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '.' => Token::Dot,
+            '=' | '!' | '<' | '>' => {
+                let eq = chars.get(pos + 1) == Some(&'=');
+                let op = match (c, eq) {
+                    ('=', true) => Comparison::Eq,
+                    ('!', true) => Comparison::Ne,
+                    ('<', true) => Comparison::Le,
+                    ('>', true) => Comparison::Ge,
+                    ('<', false) => Comparison::Lt,
+                    ('>', false) => Comparison::Gt,
+                    _ => bail!("unexpected `{}` at position {}", c, pos),
+                };
+                if eq {
+                    pos += 1;
+                }
+                Token::Op(op)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                pos += 1;
+                while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                    pos += 1;
+                }
+                let number: String = chars[start..pos].iter().collect();
+                let number = BigDecimal::from_str(&number)
+                    .map_err(|_| anyhow!("`{}` is not a number", number))?;
+                tokens.push(Token::Number(number));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_')
+                {
+                    pos += 1;
+                }
+                tokens.push(Token::Ident(chars[start..pos].iter().collect()));
+                continue;
+            }
+            _ => bail!("unexpected `{}` at position {}", c, pos),
+        };
+        tokens.push(token);
+        pos += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<Token, Error> {
+        self.tokens
+            .next()
+            .ok_or_else(|| anyhow!("the check ends too early"))
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), Error> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            _ => bail!("expected {}", what),
+        }
+    }
+
+    fn ident(&mut self, what: &str) -> Result<String, Error> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            _ => bail!("expected {}", what),
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
+        let name = match self.next()? {
+            Token::Number(number) => return Ok(Term::Number(number)),
+            Token::Ident(name) => name,
+            _ => bail!("expected a number or an aggregate"),
+        };
+        let function = Aggregate::parse(&name).ok_or_else(|| {
+            anyhow!(
+                "unknown aggregate `{}`, use one of count, sum, min or max",
+                name
+            )
+        })?;
+        self.expect(Token::Open, "`(`")?;
+        let entity_type = self.ident("an entity type")?;
+        let field = match function {
+            Aggregate::Count => None,
+            _ => {
+                self.expect(Token::Dot, "`.` and a field")?;
+                Some(self.ident("a field")?)
+            }
+        };
+        self.expect(Token::Close, "`)`")?;
+        Ok(Term::Aggregate {
+            function,
+            entity_type,
+            field,
+        })
+    }
+}
+
+impl FromStr for Check {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?.into_iter(),
+        };
+        let left = parser.term()?;
+        let op = match parser.next()? {
+            Token::Op(op) => op,
+            _ => bail!("expected one of ==, !=, <, <=, >, >="),
+        };
+        let right = parser.term()?;
+        if parser.tokens.next().is_some() {
+            bail!(
+                "unexpected input after `{} {} {}`",
+                left,
+                op.as_str(),
+                right
+            );
+        }
+        if matches!((&left, &right), (Term::Number(_), Term::Number(_))) {
+            bail!("at least one side must be an aggregate");
+        }
+        Ok(Check { left, op, right })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct UnresolvedInvariant {
+    name: String,
+    check: String,
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "UnresolvedInvariant")]
+pub struct Invariant {
+    pub name: String,
+    pub check: Check,
+    /// Whether a violation keeps the changes of the block from being
+    /// written and fails the deployment
+    pub strict: bool,
+}
+
+impl TryFrom<UnresolvedInvariant> for Invariant {
+    type Error = Error;
+
+    fn try_from(raw: UnresolvedInvariant) -> Result<Self, Self::Error> {
+        let check = raw
+            .check
+            .parse()
+            .map_err(|e| anyhow!("invalid check `{}`: {}", raw.check, e))?;
+        Ok(Invariant {
+            name: raw.name,
+            check,
+            strict: raw.strict,
+        })
+    }
+}
+
+impl Invariant {
+    /// The entity types whose entities the check aggregates
+    pub fn entity_types(&self) -> impl Iterator<Item = &str> {
+        self.check
+            .terms()
+            .into_iter()
+            .filter_map(|term| match term {
+                Term::Number(_) => None,
+                Term::Aggregate { entity_type, .. } => Some(entity_type.as_str()),
+            })
+    }
+
+    /// Check that the entity types in the check exist in `schema`, that
+    /// aggregated fields are `Int`, `BigInt` or `BigDecimal`, and that `min`
+    /// and `max` are only used for immutable entity types
+    pub fn validate(&self, schema: &Schema) -> Result<(), Error> {
+        for term in self.check.terms() {
+            let (function, entity_type, field) = match term {
+                Term::Number(_) => continue,
+                Term::Aggregate {
+                    function,
+                    entity_type,
+                    field,
+                } => (function, entity_type, field),
+            };
+            let object = schema
+                .document
+                .get_object_type_definition(entity_type)
+                .ok_or_else(|| anyhow!("entity type `{}` does not exist", entity_type))?;
+            if matches!(function, Aggregate::Min | Aggregate::Max) && !object.is_immutable() {
+                bail!(
+                    "`{}` can only be used for immutable entity types since it can not be \
+                     updated from the changes of a block",
+                    term
+                );
+            }
+            if let Some(field) = field {
+                let field_type = object
+                    .fields
+                    .iter()
+                    .find(|f| &f.name == field)
+                    .map(|f| &f.field_type)
+                    .ok_or_else(|| {
+                        anyhow!("entity type `{}` has no field `{}`", entity_type, field)
+                    })?;
+                if field_type.is_list()
+                    || !matches!(field_type.get_base_type(), "Int" | "BigInt" | "BigDecimal")
+                {
+                    bail!(
+                        "the field `{}.{}` must be an Int, BigInt or BigDecimal",
+                        entity_type,
+                        field
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The result of checking an invariant
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantOutcome {
+    Holds,
+    /// The values of the two sides of the check
+    Violated {
+        left: BigDecimal,
+        right: BigDecimal,
+    },
+    /// The check could not be evaluated because `min` or `max` of an
+    /// entity type without entities has no value
+    Skipped(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::DeploymentHash;
+
+    fn parse(yaml: &str) -> Result<Invariant, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn parse_checks() {
+        let invariant =
+            parse("name: supply\ncheck: sum(Token.totalSupply) == sum(Account.balance)").unwrap();
+        assert_eq!("supply", invariant.name);
+        assert!(!invariant.strict);
+        assert_eq!(
+            Check {
+                left: Term::Aggregate {
+                    function: Aggregate::Sum,
+                    entity_type: "Token".to_string(),
+                    field: Some("totalSupply".to_string()),
+                },
+                op: Comparison::Eq,
+                right: Term::Aggregate {
+                    function: Aggregate::Sum,
+                    entity_type: "Account".to_string(),
+                    field: Some("balance".to_string()),
+                },
+            },
+            invariant.check
+        );
+
+        let check: Check = "count(Pool)>=-1.5".parse().unwrap();
+        assert_eq!("count(Pool) >= -1.5", check.to_string());
+        let check: Check = "0 < max( Pool.fee )".parse().unwrap();
+        assert_eq!("0 < max(Pool.fee)", check.to_string());
+        assert!(
+            parse("name: pools\ncheck: count(Pool) > 0\nstrict: true")
+                .unwrap()
+                .strict
+        );
+
+        for invalid in [
+            "sum(Token.supply)",
+            "sum(Token.supply) = 1",
+            "avg(Token.supply) == 1",
+            "sum(Token) == 1",
+            "count(Token.supply) == 1",
+            "count(Token) == 1 + 1",
+            "1 == 1",
+            "count(Token) == 1.2.3",
+        ] {
+            assert!(invalid.parse::<Check>().is_err(), "{} is invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let one = BigDecimal::from(1);
+        let two = BigDecimal::from(2);
+        assert!(Comparison::Lt.holds(&one, &two));
+        assert!(Comparison::Le.holds(&two, &two));
+        assert!(!Comparison::Gt.holds(&one, &two));
+        assert!(Comparison::Ne.holds(&one, &two));
+        assert!(!Comparison::Eq.holds(&one, &two));
+    }
+
+    #[test]
+    fn validate_against_schema() {
+        const SCHEMA: &str = "type Token @entity { id: ID!, supply: BigInt!, name: String }
+                              type Account @entity { id: ID!, balances: [BigInt!]! }
+                              type Swap @entity(immutable: true) { id: ID!, amount: BigInt! }";
+        let schema = Schema::parse(SCHEMA, DeploymentHash::new("invariants").unwrap()).unwrap();
+
+        let invariant = |check: &str| parse(&format!("name: test\ncheck: {}", check)).unwrap();
+        assert!(invariant("sum(Token.supply) > count(Account)")
+            .validate(&schema)
+            .is_ok());
+        assert!(invariant("max(Swap.amount) < sum(Token.supply)")
+            .validate(&schema)
+            .is_ok());
+        assert!(invariant("max(Token.supply) > 0")
+            .validate(&schema)
+            .is_err());
+        assert!(invariant("count(Pool) > 0").validate(&schema).is_err());
+        assert!(invariant("sum(Token.total) > 0").validate(&schema).is_err());
+        assert!(invariant("max(Token.name) > 0").validate(&schema).is_err());
+        assert!(invariant("sum(Account.balances) > 0")
+            .validate(&schema)
+            .is_err());
+    }
+}
@@ -6,6 +6,9 @@ pub mod api_version;
 pub use api_version::*;
 
 pub mod features;
+pub mod fingerprint;
+pub mod invariant;
+pub mod retention;
 pub mod status;
 
 pub use features::{SubgraphFeature, SubgraphFeatureValidationError};
@@ -28,14 +31,16 @@ use crate::{
     blockchain::{BlockPtr, Blockchain, DataSource as _},
     components::{
         link_resolver::LinkResolver,
-        store::{StoreError, SubgraphStore},
+        store::{EntityType, StoreError, SubgraphStore},
     },
     data::{
         graphql::TryFromValue,
         query::QueryExecutionError,
         schema::{Schema, SchemaImportError, SchemaValidationError},
         store::Entity,
-        subgraph::features::validate_subgraph_features,
+        subgraph::{
+            features::validate_subgraph_features, invariant::Invariant, retention::EntityRetention,
+        },
     },
     data_source::{
         offchain::OFFCHAIN_KINDS, DataSource, DataSourceTemplate, UnresolvedDataSource,
@@ -344,6 +349,14 @@ pub enum SubgraphManifestValidationError {
     FeatureValidationError(#[from] SubgraphFeatureValidationError),
     #[error("data source {0} is invalid: {1}")]
     DataSourceValidation(String, Error),
+    #[error("subgraph has {1} {0}, but this node only accepts subgraphs with at most {2} {0}")]
+    ManifestTooLarge(&'static str, usize, usize),
+    #[error("the retention policy for {0} is invalid: {1}")]
+    RetentionInvalid(String, Error),
+    #[error("the invariant {0} is invalid: {1}")]
+    InvariantInvalid(String, Error),
+    #[error("the reorg threshold is invalid: {0}")]
+    ReorgThresholdInvalid(Error),
 }
 
 #[derive(Error, Debug)]
@@ -358,6 +371,53 @@ pub enum SubgraphManifestResolveError {
     ResolveError(#[from] anyhow::Error),
 }
 
+impl SubgraphManifestResolveError {
+    /// Whether the manifest itself is invalid. Errors resolving the
+    /// manifest can also be caused by IPFS or the network and are not
+    /// considered to make the manifest invalid
+    pub fn is_invalid_manifest(&self) -> bool {
+        use SubgraphManifestResolveError::*;
+
+        match self {
+            ParseError(_) | NonUtf8 | InvalidFormat => true,
+            ResolveError(_) => false,
+        }
+    }
+}
+
+/// The number of static data sources, templates, and handlers in the
+/// static data sources of a manifest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ManifestSize {
+    pub data_sources: usize,
+    pub templates: usize,
+    pub handlers: usize,
+}
+
+impl ManifestSize {
+    /// Return an error for each count that is larger than its limit
+    pub fn check_limits(
+        &self,
+        max_data_sources: Option<usize>,
+        max_templates: Option<usize>,
+        max_handlers: Option<usize>,
+    ) -> Vec<SubgraphManifestValidationError> {
+        [
+            ("data sources", self.data_sources, max_data_sources),
+            ("templates", self.templates, max_templates),
+            ("handlers", self.handlers, max_handlers),
+        ]
+        .into_iter()
+        .filter_map(|(kind, count, limit)| match limit {
+            Some(limit) if count > limit => Some(
+                SubgraphManifestValidationError::ManifestTooLarge(kind, count, limit),
+            ),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
 /// Data source contexts are conveniently represented as entities.
 pub type DataSourceContext = Entity;
 
@@ -490,6 +550,16 @@ pub struct BaseSubgraphManifest<C, S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    /// How long rows of some entity types are kept
+    #[serde(default)]
+    pub retention: Vec<EntityRetention>,
+    /// Checks that the node runs against the deployment's data
+    #[serde(default)]
+    pub invariants: Vec<Invariant>,
+    /// A reorg threshold that is lower than the one of the chain; blocks
+    /// that are older than that are considered final for this subgraph
+    #[serde(default)]
+    pub reorg_threshold: Option<BlockNumber>,
     #[serde(skip_serializing, default)]
     pub chain: PhantomData<C>,
 }
@@ -542,6 +612,12 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
             errors.push(SubgraphManifestValidationError::NoDataSources);
         }
 
+        errors.extend(self.0.size().check_limits(
+            ENV_VARS.manifest_max_data_sources,
+            ENV_VARS.manifest_max_templates,
+            ENV_VARS.manifest_max_handlers,
+        ));
+
         for ds in &self.0.data_sources {
             errors.extend(ds.validate().into_iter().map(|e| {
                 SubgraphManifestValidationError::DataSourceValidation(ds.name().to_owned(), e)
@@ -591,6 +667,51 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
             }
         }
 
+        let mut retained = BTreeSet::new();
+        for retention in &self.0.retention {
+            let entity_type = retention.entity_type.as_str();
+            let error = if !retained.insert(entity_type) {
+                Some(anyhow!("there is more than one policy for it"))
+            } else {
+                retention.validate(&self.0.schema).err()
+            };
+            if let Some(error) = error {
+                errors.push(SubgraphManifestValidationError::RetentionInvalid(
+                    entity_type.to_string(),
+                    error,
+                ));
+            }
+        }
+
+        let mut names = BTreeSet::new();
+        for invariant in &self.0.invariants {
+            // Removing current entities of immutable entity types would
+            // make the aggregates that are kept for the check wrong
+            let retained = invariant.entity_types().find(|entity_type| {
+                retained.contains(entity_type)
+                    && self
+                        .0
+                        .schema
+                        .is_immutable(&EntityType::new(entity_type.to_string()))
+            });
+            let error = if !names.insert(invariant.name.as_str()) {
+                Some(anyhow!("there is more than one invariant with that name"))
+            } else if let Some(entity_type) = retained {
+                Some(anyhow!(
+                    "it uses the immutable entity type `{}` which has a retention policy",
+                    entity_type
+                ))
+            } else {
+                invariant.validate(&self.0.schema).err()
+            };
+            if let Some(error) = error {
+                errors.push(SubgraphManifestValidationError::InvariantInvalid(
+                    invariant.name.clone(),
+                    error,
+                ));
+            }
+        }
+
         // Validate subgraph feature usage and declaration.
         if self.0.spec_version >= SPEC_VERSION_0_0_4 {
             if let Err(feature_validation_error) = validate_subgraph_features(&self.0) {
@@ -642,6 +763,14 @@ impl<C: Blockchain> SubgraphManifest<C> {
             .collect()
     }
 
+    pub fn size(&self) -> ManifestSize {
+        ManifestSize {
+            data_sources: self.data_sources.len(),
+            templates: self.templates.len(),
+            handlers: self.data_sources.iter().map(|ds| ds.handler_count()).sum(),
+        }
+    }
+
     pub fn api_versions(&self) -> impl Iterator<Item = semver::Version> + '_ {
         self.templates
             .iter()
@@ -706,6 +835,9 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            retention,
+            invariants,
+            reorg_threshold,
             chain,
         } = self;
 
@@ -777,6 +909,9 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            retention,
+            invariants,
+            reorg_threshold,
             chain,
         })
     }
@@ -802,6 +937,11 @@ pub struct DeploymentState {
     pub latest_block: BlockPtr,
     /// The earliest block that the subgraph has processed
     pub earliest_block_number: BlockNumber,
+    /// Whether the history before `earliest_block_number` was removed by
+    /// pruning, rather than never indexed
+    pub history_pruned: bool,
+    /// The health of the subgraph
+    pub health: schema::SubgraphHealth,
 }
 
 impl DeploymentState {
@@ -810,6 +950,11 @@ impl DeploymentState {
         self.latest_block.number > 0
     }
 
+    /// Whether the data for `block` was removed by pruning
+    pub fn block_pruned(&self, block: BlockNumber) -> bool {
+        self.history_pruned && block < self.earliest_block_number
+    }
+
     pub fn block_queryable(&self, block: BlockNumber) -> Result<(), String> {
         if block > self.latest_block.number {
             return Err(format!(
@@ -884,3 +1029,46 @@ fn test_display_vector() {
         format!("{}", manifest_validation_error)
     )
 }
+
+#[test]
+fn test_manifest_size_limits() {
+    let size = ManifestSize {
+        data_sources: 4000,
+        templates: 2,
+        handlers: 12000,
+    };
+
+    assert!(size.check_limits(None, None, None).is_empty());
+    assert!(size
+        .check_limits(Some(4000), Some(2), Some(12000))
+        .is_empty());
+
+    let errors: Vec<_> = size
+        .check_limits(Some(100), Some(10), Some(1000))
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(
+        vec![
+            "subgraph has 4000 data sources, but this node only accepts subgraphs \
+             with at most 100 data sources"
+                .to_string(),
+            "subgraph has 12000 handlers, but this node only accepts subgraphs \
+             with at most 1000 handlers"
+                .to_string(),
+        ],
+        errors
+    );
+}
+
+#[test]
+fn test_manifest_resolve_error_is_invalid_manifest() {
+    let parse_error = serde_yaml::from_str::<serde_yaml::Mapping>("[").unwrap_err();
+    assert!(SubgraphManifestResolveError::ParseError(parse_error).is_invalid_manifest());
+    assert!(SubgraphManifestResolveError::NonUtf8.is_invalid_manifest());
+    assert!(SubgraphManifestResolveError::InvalidFormat.is_invalid_manifest());
+    assert!(
+        !SubgraphManifestResolveError::ResolveError(anyhow::anyhow!("IPFS file not found"))
+            .is_invalid_manifest()
+    );
+}
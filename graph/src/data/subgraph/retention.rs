@@ -0,0 +1,243 @@
+//! Retention policies that let the node remove rows of entity types that
+//! are only needed for a while, for example event-log-style entities. They
+//! are declared in the `retention` section of the manifest:
+//!
+//! ```yaml
+//! retention:
+//!   - entity: Transfer
+//!     maxAge: 30d
+//!     timestampField: timestamp
+//!   - entity: Swap
+//!     maxBlocks: 100000
+//! ```
+//!
+//! and can be changed later with `graphman retention`
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Error};
+use serde::Deserialize;
+
+use crate::data::graphql::{DocumentExt as _, TypeExt as _};
+use crate::prelude::{BlockNumber, Schema};
+
+/// When rows of an entity type are old enough to be removed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RetentionRule {
+    /// Remove rows whose `field`, a timestamp in seconds since the epoch,
+    /// is more than `max_age` older than the newest `field` of the rows
+    /// that were written in final blocks. The age does not depend on the
+    /// wall clock, only on the data the deployment has indexed
+    Age { field: String, max_age: Duration },
+    /// Remove rows that were written more than this many blocks before the
+    /// deployment's latest block
+    Blocks(BlockNumber),
+}
+
+impl fmt::Display for RetentionRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetentionRule::Age { field, max_age } => {
+                write!(f, "{} older than {}", field, format_age(*max_age))
+            }
+            RetentionRule::Blocks(blocks) => write!(f, "more than {} blocks old", blocks),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct UnresolvedEntityRetention {
+    entity: String,
+    max_age: Option<String>,
+    timestamp_field: Option<String>,
+    max_blocks: Option<BlockNumber>,
+}
+
+/// The retention policy for one entity type
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "UnresolvedEntityRetention")]
+pub struct EntityRetention {
+    pub entity_type: String,
+    pub rule: RetentionRule,
+}
+
+impl TryFrom<UnresolvedEntityRetention> for EntityRetention {
+    type Error = Error;
+
+    fn try_from(raw: UnresolvedEntityRetention) -> Result<Self, Self::Error> {
+        let rule = match (raw.max_age, raw.timestamp_field, raw.max_blocks) {
+            (Some(max_age), Some(field), None) => RetentionRule::Age {
+                field,
+                max_age: parse_age(&max_age)?,
+            },
+            (None, None, Some(blocks)) if blocks > 0 => RetentionRule::Blocks(blocks),
+            (None, None, Some(blocks)) => {
+                bail!(
+                    "maxBlocks for `{}` must be positive, not {}",
+                    raw.entity,
+                    blocks
+                )
+            }
+            (Some(_), None, _) => bail!("maxAge for `{}` needs a timestampField", raw.entity),
+            _ => bail!(
+                "retention for `{}` needs either maxAge and timestampField or maxBlocks",
+                raw.entity
+            ),
+        };
+        Ok(EntityRetention {
+            entity_type: raw.entity,
+            rule,
+        })
+    }
+}
+
+impl EntityRetention {
+    /// Check that the entity type exists in `schema` and that the
+    /// timestamp field of an age rule is an `Int` or `BigInt`
+    pub fn validate(&self, schema: &Schema) -> Result<(), Error> {
+        let object = schema
+            .document
+            .get_object_type_definition(&self.entity_type)
+            .ok_or_else(|| anyhow!("entity type `{}` does not exist", self.entity_type))?;
+        if let RetentionRule::Age { field, .. } = &self.rule {
+            let field_type = object
+                .fields
+                .iter()
+                .find(|f| &f.name == field)
+                .map(|f| &f.field_type)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "entity type `{}` has no field `{}`",
+                        self.entity_type,
+                        field
+                    )
+                })?;
+            if field_type.is_list() || !matches!(field_type.get_base_type(), "Int" | "BigInt") {
+                bail!(
+                    "the timestamp field `{}.{}` must be an Int or BigInt",
+                    self.entity_type,
+                    field
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What applying a retention policy removed, or what it would remove in a
+/// dry run
+#[derive(Clone, Debug)]
+pub struct RetentionOutcome {
+    pub retention: EntityRetention,
+    /// The number of rows that were, or would be, removed
+    pub rows: usize,
+    /// The highest block at which one of these rows was written, or for
+    /// mutable entity types, the last block at which one of them was
+    /// current
+    pub newest_block: Option<BlockNumber>,
+    /// Whether time-travel queries for blocks that the deployment still
+    /// has history for could see these rows, i.e., whether the
+    /// deployment's earliest block is at or before `newest_block`
+    pub loses_history: bool,
+    /// Whether all rows that the policy matches were removed; `false` if
+    /// pruning was stopped early and should continue later
+    pub complete: bool,
+}
+
+/// Parse ages like `30d`, `12h`, `45m` or `3600s`; a number without unit
+/// is in seconds
+pub fn parse_age(age: &str) -> Result<Duration, Error> {
+    let age = age.trim();
+    let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => age.split_at(idx),
+        None => (age, "s"),
+    };
+    let number = u64::from_str(number).map_err(|_| anyhow!("invalid age `{}`", age))?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 24 * 60 * 60,
+        _ => bail!(
+            "invalid age `{}`, the unit must be one of s, m, h, or d",
+            age
+        ),
+    };
+    if secs == 0 {
+        bail!("the age `{}` must be positive", age);
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn format_age(age: Duration) -> String {
+    const DAY: u64 = 24 * 60 * 60;
+    let secs = age.as_secs();
+    match secs {
+        _ if secs % DAY == 0 => format!("{}d", secs / DAY),
+        _ if secs % 3600 == 0 => format!("{}h", secs / 3600),
+        _ if secs % 60 == 0 => format!("{}m", secs / 60),
+        _ => format!("{}s", secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::DeploymentHash;
+
+    fn parse(yaml: &str) -> Result<EntityRetention, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn parse_rules() {
+        let retention = parse("entity: Transfer\nmaxAge: 30d\ntimestampField: timestamp").unwrap();
+        assert_eq!("Transfer", retention.entity_type);
+        assert_eq!(
+            RetentionRule::Age {
+                field: "timestamp".to_string(),
+                max_age: Duration::from_secs(30 * 24 * 60 * 60)
+            },
+            retention.rule
+        );
+        assert_eq!("timestamp older than 30d", retention.rule.to_string());
+
+        let retention = parse("entity: Swap\nmaxBlocks: 1000").unwrap();
+        assert_eq!(RetentionRule::Blocks(1000), retention.rule);
+
+        assert!(parse("entity: Swap\nmaxAge: 1h").is_err());
+        assert!(parse("entity: Swap\nmaxBlocks: 0").is_err());
+        assert!(parse("entity: Swap\nmaxBlocks: 10\nmaxAge: 1h\ntimestampField: t").is_err());
+        assert!(parse("entity: Swap").is_err());
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(Duration::from_secs(90), parse_age("90").unwrap());
+        assert_eq!(Duration::from_secs(90 * 60), parse_age("90m").unwrap());
+        assert_eq!(Duration::from_secs(2 * 3600), parse_age("2h").unwrap());
+        assert!(parse_age("2w").is_err());
+        assert!(parse_age("0d").is_err());
+        assert!(parse_age("d").is_err());
+        assert_eq!("36h", format_age(parse_age("36h").unwrap()));
+    }
+
+    #[test]
+    fn validate_against_schema() {
+        const SCHEMA: &str =
+            "type Transfer @entity(immutable: true) { id: ID!, timestamp: BigInt!, note: String }";
+        let schema = Schema::parse(SCHEMA, DeploymentHash::new("retention").unwrap()).unwrap();
+
+        let valid = parse("entity: Transfer\nmaxAge: 1d\ntimestampField: timestamp").unwrap();
+        assert!(valid.validate(&schema).is_ok());
+        let wrong_type = parse("entity: Transfer\nmaxAge: 1d\ntimestampField: note").unwrap();
+        assert!(wrong_type.validate(&schema).is_err());
+        let no_field = parse("entity: Transfer\nmaxAge: 1d\ntimestampField: time").unwrap();
+        assert!(no_field.validate(&schema).is_err());
+        let no_type = parse("entity: Swap\nmaxBlocks: 10").unwrap();
+        assert!(no_type.validate(&schema).is_err());
+    }
+}
@@ -9,6 +9,7 @@ use std::collections::BTreeSet;
 use std::str::FromStr;
 use std::{fmt, fmt::Display};
 
+use super::retention::EntityRetention;
 use super::DeploymentHash;
 use crate::data::graphql::TryFromValue;
 use crate::data::store::Value;
@@ -108,6 +109,8 @@ pub struct DeploymentCreate {
     pub graft_base: Option<DeploymentHash>,
     pub graft_block: Option<BlockPtr>,
     pub debug_fork: Option<DeploymentHash>,
+    pub retention: Vec<EntityRetention>,
+    pub reorg_threshold: Option<BlockNumber>,
 }
 
 impl DeploymentCreate {
@@ -122,6 +125,8 @@ impl DeploymentCreate {
             graft_base: None,
             graft_block: None,
             debug_fork: None,
+            retention: source_manifest.retention.clone(),
+            reorg_threshold: source_manifest.reorg_threshold,
         }
     }
 
@@ -232,6 +237,93 @@ impl SubgraphManifestEntity {
     }
 }
 
+/// A stable, machine-readable classification of a `SubgraphError`. Tools
+/// should match on the code rather than on the message of an error, since
+/// messages change. The string form of a code never changes; new codes
+/// may be added
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SubgraphErrorCode {
+    /// The cause of the error was not classified. Errors that were
+    /// recorded before codes existed also have this code
+    #[default]
+    Unknown,
+    /// A handler failed deterministically, for example because it called
+    /// `abort` or tried to store an invalid entity
+    HandlerError,
+    /// The execution of WASM code trapped, for example because of an
+    /// out-of-bounds memory access or a division by zero
+    WasmTrap,
+    /// A handler hit the handler timeout or the host wait timeout
+    Timeout,
+    /// The store could not be reached
+    StoreUnavailable,
+    /// The store reported an error other than being unavailable
+    StoreError,
+    /// A request to a chain provider, for example an RPC call, failed
+    ProviderError,
+    /// The manifest of the deployment can not be parsed
+    ManifestInvalid,
+    /// The deployment changed incompatibly since it was first started
+    Incompatible,
+    /// Copying or grafting the data of another deployment failed
+    CopyFailed,
+    /// A strict invariant from the manifest was violated
+    InvariantViolated,
+}
+
+impl SubgraphErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        use SubgraphErrorCode::*;
+        match self {
+            Unknown => "unknown",
+            HandlerError => "handler_error",
+            WasmTrap => "wasm_trap",
+            Timeout => "timeout",
+            StoreUnavailable => "store_unavailable",
+            StoreError => "store_error",
+            ProviderError => "provider_error",
+            ManifestInvalid => "manifest_invalid",
+            Incompatible => "incompatible",
+            CopyFailed => "copy_failed",
+            InvariantViolated => "invariant_violated",
+        }
+    }
+}
+
+impl fmt::Display for SubgraphErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SubgraphErrorCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SubgraphErrorCode, Error> {
+        use SubgraphErrorCode::*;
+        match s {
+            "unknown" => Ok(Unknown),
+            "handler_error" => Ok(HandlerError),
+            "wasm_trap" => Ok(WasmTrap),
+            "timeout" => Ok(Timeout),
+            "store_unavailable" => Ok(StoreUnavailable),
+            "store_error" => Ok(StoreError),
+            "provider_error" => Ok(ProviderError),
+            "manifest_invalid" => Ok(ManifestInvalid),
+            "incompatible" => Ok(Incompatible),
+            "copy_failed" => Ok(CopyFailed),
+            "invariant_violated" => Ok(InvariantViolated),
+            _ => Err(anyhow!("failed to parse `{}` as SubgraphErrorCode", s)),
+        }
+    }
+}
+
+impl From<SubgraphErrorCode> for r::Value {
+    fn from(code: SubgraphErrorCode) -> r::Value {
+        r::Value::Enum(code.as_str().to_string())
+    }
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct SubgraphError {
     pub subgraph_id: DeploymentHash,
@@ -241,6 +333,15 @@ pub struct SubgraphError {
 
     // `true` if we are certain the error is deterministic. If in doubt, this is `false`.
     pub deterministic: bool,
+
+    /// What caused the error. The code is not part of the stable hash of
+    /// the error, so that errors keep the same id
+    pub code: SubgraphErrorCode,
+
+    /// The label of the provider that served the block at which the error
+    /// happened, if it is known. Like the code, it is not part of the
+    /// stable hash of the error
+    pub provider: Option<String>,
 }
 
 impl Display for SubgraphError {
@@ -1,10 +1,15 @@
 //! Support for the indexing status API
 
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
 use super::schema::{SubgraphError, SubgraphHealth};
 use crate::blockchain::BlockHash;
 use crate::components::store::{BlockNumber, DeploymentId};
 use crate::data::graphql::{object, IntoValue};
 use crate::prelude::{r, BlockPtr, Value};
+use crate::runtime::CompileProfile;
 
 pub enum Filter {
     /// Get all versions for the named subgraph
@@ -91,6 +96,421 @@ impl IntoValue for ChainInfo {
     }
 }
 
+/// A version of graph-node that indexed a range of blocks of a deployment
+#[derive(Debug)]
+pub struct NodeVersion {
+    pub git_commit_hash: String,
+    pub crate_version: String,
+    /// Hash of the host exports that were available to mappings
+    pub host_export_hash: String,
+    /// The first block indexed with this version; `None` if the version
+    /// indexed the deployment from its start block
+    pub start_block: Option<BlockNumber>,
+    /// The last block indexed with this version; `None` if the version is
+    /// still in use
+    pub end_block: Option<BlockNumber>,
+}
+
+impl IntoValue for NodeVersion {
+    fn into_value(self) -> r::Value {
+        let NodeVersion {
+            git_commit_hash,
+            crate_version,
+            host_export_hash,
+            start_block,
+            end_block,
+        } = self;
+        object! {
+            __typename: "NodeVersion",
+            gitCommitHash: git_commit_hash,
+            crateVersion: crate_version,
+            hostExportHash: host_export_hash,
+            startBlock: start_block,
+            endBlock: end_block,
+        }
+    }
+}
+
+/// The result of comparing the entities of one type in the source and the
+/// destination of a graft or copy once all data had been copied
+#[derive(Debug)]
+pub struct CopyIntegrity {
+    pub entity_type: String,
+    /// The number of entities visible at the graft block in the source
+    pub source_count: i64,
+    /// The number of entities visible at the graft block in the copy
+    pub destination_count: i64,
+    /// Checksums over the ids of the entities; only set if checksums were
+    /// enabled when the check ran
+    pub source_checksum: Option<String>,
+    pub destination_checksum: Option<String>,
+}
+
+impl CopyIntegrity {
+    pub fn matches(&self) -> bool {
+        self.source_count == self.destination_count
+            && self.source_checksum == self.destination_checksum
+    }
+}
+
+impl IntoValue for CopyIntegrity {
+    fn into_value(self) -> r::Value {
+        let matches = self.matches();
+        let CopyIntegrity {
+            entity_type,
+            source_count,
+            destination_count,
+            source_checksum,
+            destination_checksum,
+        } = self;
+        object! {
+            __typename: "CopyIntegrity",
+            entityType: entity_type,
+            sourceCount: format!("{}", source_count),
+            destinationCount: format!("{}", destination_count),
+            sourceChecksum: source_checksum,
+            destinationChecksum: destination_checksum,
+            matches: matches,
+        }
+    }
+}
+
+/// The entity types that a handler was observed writing
+#[derive(Debug)]
+pub struct HandlerWrites {
+    /// The data source of the handler; empty if the writes were observed
+    /// before data sources were recorded
+    pub data_source: String,
+    pub handler: String,
+    /// Sorted by name
+    pub entity_types: Vec<String>,
+}
+
+impl IntoValue for HandlerWrites {
+    fn into_value(self) -> r::Value {
+        let HandlerWrites {
+            data_source,
+            handler,
+            entity_types,
+        } = self;
+        object! {
+            __typename: "HandlerWrites",
+            dataSource: data_source,
+            handler: handler,
+            entityTypes: entity_types,
+        }
+    }
+}
+
+/// The bus backends that a deployment publishes to and the delivery of its
+/// critical entity types
+#[derive(Debug)]
+pub struct BusStatus {
+    /// The names of the backends from the node configuration; empty if
+    /// the deployment does not publish to the bus
+    pub backends: Vec<String>,
+    /// The delivery of the critical entity types of the deployment to
+    /// each backend
+    pub critical: Vec<CriticalTypeDelivery>,
+}
+
+impl IntoValue for BusStatus {
+    fn into_value(self) -> r::Value {
+        let BusStatus { backends, critical } = self;
+        object! {
+            __typename: "BusStatus",
+            backends: backends,
+            critical: critical,
+        }
+    }
+}
+
+/// Up to which block the changes to a critical entity type were delivered
+/// to one bus backend
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CriticalTypeDelivery {
+    pub entity_type: String,
+    pub backend: String,
+    /// The broker confirmed all changes to the type up to this block
+    pub last_confirmed_block: Option<BlockNumber>,
+    /// The earliest block whose changes to the type could not be
+    /// delivered and that was not acknowledged yet; `last_confirmed_block`
+    /// does not advance past it
+    pub dead_lettered_block: Option<BlockNumber>,
+}
+
+impl IntoValue for CriticalTypeDelivery {
+    fn into_value(self) -> r::Value {
+        let CriticalTypeDelivery {
+            entity_type,
+            backend,
+            last_confirmed_block,
+            dead_lettered_block,
+        } = self;
+        object! {
+            __typename: "CriticalTypeDelivery",
+            entityType: entity_type,
+            backend: backend,
+            lastConfirmedBlock: last_confirmed_block,
+            deadLetteredBlock: dead_lettered_block,
+        }
+    }
+}
+
+/// The earliest block at which the PoI of a deployment differed from the
+/// PoI that a quorum of reference indexers reported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoiDivergence {
+    pub block: BlockNumber,
+    pub local_poi: Vec<u8>,
+    /// The PoI that most of the disagreeing reference indexers reported
+    pub reference_poi: Vec<u8>,
+}
+
+impl IntoValue for PoiDivergence {
+    fn into_value(self) -> r::Value {
+        let PoiDivergence {
+            block,
+            local_poi,
+            reference_poi,
+        } = self;
+        object! {
+            __typename: "PoiDivergence",
+            block: block,
+            localProofOfIndexing: r::Value::from(Value::Bytes(local_poi.as_slice().into())),
+            referenceProofOfIndexing: r::Value::from(Value::Bytes(reference_poi.as_slice().into())),
+        }
+    }
+}
+
+/// An invariant from the manifest that did not hold for the deployment's
+/// data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    /// The name of the invariant
+    pub invariant: String,
+    /// The check that was violated
+    pub check: String,
+    /// The first block of the current run of blocks at which the check
+    /// failed
+    pub first_block: BlockNumber,
+    /// The last block at which the check failed
+    pub last_block: BlockNumber,
+    /// The values of both sides of the check at `last_block`
+    pub message: String,
+}
+
+impl IntoValue for InvariantViolation {
+    fn into_value(self) -> r::Value {
+        let InvariantViolation {
+            invariant,
+            check,
+            first_block,
+            last_block,
+            message,
+        } = self;
+        object! {
+            __typename: "InvariantViolation",
+            invariant: invariant,
+            check: check,
+            firstBlock: first_block,
+            lastBlock: last_block,
+            message: message,
+        }
+    }
+}
+
+/// How often the mappings of a deployment terminated for one reason
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappingTerminationCount {
+    /// One of the reasons from `components::subgraph::mapping_terminations`
+    pub reason: String,
+    pub count: u64,
+}
+
+impl IntoValue for MappingTerminationCount {
+    fn into_value(self) -> r::Value {
+        let MappingTerminationCount { reason, count } = self;
+        object! {
+            __typename: "MappingTermination",
+            reason: reason,
+            count: format!("{}", count),
+        }
+    }
+}
+
+/// How long compiling the WASM modules of a deployment took and how long
+/// its handlers spent executing WASM afterwards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmPerformance {
+    /// The profile that the modules were compiled with
+    pub profile: CompileProfile,
+    /// The total time it took to compile the modules
+    pub compile_time: Duration,
+    /// The number of handler runs since the modules were compiled
+    pub handler_count: u64,
+    /// The average time these handler runs spent executing WASM; `None`
+    /// if no handler ran
+    pub average_handler_time: Option<Duration>,
+}
+
+impl IntoValue for WasmPerformance {
+    fn into_value(self) -> r::Value {
+        let WasmPerformance {
+            profile,
+            compile_time,
+            handler_count,
+            average_handler_time,
+        } = self;
+        object! {
+            __typename: "WasmPerformance",
+            compileProfile: profile.as_str(),
+            compileTimeMs: compile_time.as_secs_f64() * 1000.0,
+            handlerCount: format!("{}", handler_count),
+            averageHandlerTimeMs: average_handler_time.map(|time| time.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+/// The cumulative statistics of the runs of one handler
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandlerStats {
+    pub handler: String,
+    pub invocations: u64,
+    /// The total time the runs took
+    pub execution_time: Duration,
+    pub gas: u64,
+    /// How many of the runs failed
+    pub errors: u64,
+}
+
+impl HandlerStats {
+    /// Add the counters of `other` to the ones of `self`
+    pub fn add(&mut self, other: &HandlerStats) {
+        self.invocations = self.invocations.saturating_add(other.invocations);
+        self.execution_time = self.execution_time.saturating_add(other.execution_time);
+        self.gas = self.gas.saturating_add(other.gas);
+        self.errors = self.errors.saturating_add(other.errors);
+    }
+}
+
+impl IntoValue for HandlerStats {
+    fn into_value(self) -> r::Value {
+        let HandlerStats {
+            handler,
+            invocations,
+            execution_time,
+            gas,
+            errors,
+        } = self;
+        object! {
+            __typename: "HandlerStats",
+            handler: handler,
+            invocations: format!("{}", invocations),
+            executionTimeMs: execution_time.as_secs_f64() * 1000.0,
+            gas: format!("{}", gas),
+            errors: format!("{}", errors),
+        }
+    }
+}
+
+/// The statistics of the handlers of a deployment and since when they
+/// were accumulated
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandlerStatsReport {
+    /// When the statistics were first recorded after the deployment was
+    /// created or the statistics were reset; `None` if none were recorded
+    pub since: Option<DateTime<Utc>>,
+    /// Sorted by handler
+    pub handlers: Vec<HandlerStats>,
+}
+
+/// How much of a shared resource a deployment used during one hour
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsageRecord {
+    /// The deployment hash
+    pub deployment: String,
+    /// The start of the hour
+    pub bucket: DateTime<Utc>,
+    /// One of the resource names from `components::metrics::usage`
+    pub resource: String,
+    pub amount: u64,
+}
+
+impl IntoValue for UsageRecord {
+    fn into_value(self) -> r::Value {
+        let UsageRecord {
+            deployment,
+            bucket,
+            resource,
+            amount,
+        } = self;
+        object! {
+            __typename: "DeploymentUsage",
+            deployment: deployment,
+            bucket: bucket.to_rfc3339(),
+            resource: resource,
+            amount: format!("{}", amount),
+        }
+    }
+}
+
+/// How many rows the table for an entity type has, and when the
+/// deployment last changed it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityTypeStats {
+    pub entity_type: String,
+    /// The number of rows, including the historical versions of entities
+    pub rows: u64,
+    /// Whether `rows` was counted or is Postgres' estimate
+    pub exact: bool,
+    /// The highest block at which an entity of this type was inserted,
+    /// updated or removed; `None` if no entity of this type was ever
+    /// written, or if that is not known and `exact` is `false`
+    pub last_modified_block: Option<BlockNumber>,
+}
+
+impl IntoValue for EntityTypeStats {
+    fn into_value(self) -> r::Value {
+        let EntityTypeStats {
+            entity_type,
+            rows,
+            exact,
+            last_modified_block,
+        } = self;
+        object! {
+            __typename: "EntityTypeStats",
+            entityType: entity_type,
+            rowCount: format!("{}", rows),
+            exact: exact,
+            lastModifiedBlock: last_modified_block,
+        }
+    }
+}
+
+/// The provider that served a block, and with it the trigger data, to a
+/// deployment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockProvider {
+    pub block_number: BlockNumber,
+    /// The label of the provider
+    pub provider: String,
+}
+
+impl IntoValue for BlockProvider {
+    fn into_value(self) -> r::Value {
+        let BlockProvider {
+            block_number,
+            provider,
+        } = self;
+        object! {
+            __typename: "BlockProvider",
+            blockNumber: block_number,
+            provider: provider,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Info {
     pub id: DeploymentId,
@@ -109,8 +529,64 @@ pub struct Info {
 
     pub entity_count: u64,
 
+    /// How long the deployment has been paused because its store is
+    /// unavailable; `None` if it is not paused or this node does not run
+    /// it
+    pub store_unavailable: Option<Duration>,
+
     /// ID of the Graph Node that the subgraph is indexed by.
     pub node: Option<String>,
+
+    /// The versions of graph-node that indexed the subgraph, from first
+    /// to last
+    pub node_versions: Vec<NodeVersion>,
+
+    /// The result of the integrity check after the subgraph was grafted or
+    /// copied, one entry per entity type; empty if the subgraph was not
+    /// grafted or copied
+    pub copy_integrity: Vec<CopyIntegrity>,
+
+    /// The entity types that each handler was observed writing, sorted by
+    /// handler
+    pub handler_entity_types: Vec<HandlerWrites>,
+
+    /// The bus backends that the deployment publishes to; `None` if the
+    /// deployment has not been started since that was recorded
+    pub bus_status: Option<BusStatus>,
+
+    /// Where the PoI of the deployment diverged from the reference
+    /// indexers; `None` if it agrees with them or was not compared
+    pub poi_divergence: Option<PoiDivergence>,
+
+    /// The invariants from the manifest that were violated at the last
+    /// block at which they were checked, sorted by name
+    pub invariant_violations: Vec<InvariantViolation>,
+
+    /// How often the mappings of the deployment terminated, by reason,
+    /// since the deployment started running on this node; empty unless
+    /// this node runs the deployment
+    pub mapping_terminations: Vec<MappingTerminationCount>,
+
+    /// How long compiling the WASM modules of the deployment took and how
+    /// fast its handlers run since; `None` unless this node runs the
+    /// deployment
+    pub wasm_performance: Option<WasmPerformance>,
+
+    /// The cumulative statistics of the handlers of the deployment. The
+    /// store reports them as of the last time they were written; the
+    /// index node replaces them with the current ones if it runs the
+    /// deployment
+    pub handler_stats: HandlerStatsReport,
+
+    /// The reorg threshold that the deployment uses. The store only knows
+    /// the threshold that the deployment set for itself, and `None` means
+    /// that it uses the one of its chain; the index node replaces that with
+    /// the effective threshold
+    pub reorg_threshold: Option<BlockNumber>,
+
+    /// The label of the provider that served the latest block that the
+    /// deployment processed; `None` if that was not recorded
+    pub latest_block_provider: Option<String>,
 }
 
 impl IntoValue for Info {
@@ -120,11 +596,23 @@ impl IntoValue for Info {
             subgraph,
             chains,
             entity_count,
+            store_unavailable,
             fatal_error,
             health,
             node,
             non_fatal_errors,
             synced,
+            node_versions,
+            copy_integrity,
+            handler_entity_types,
+            bus_status,
+            poi_divergence,
+            invariant_violations,
+            mapping_terminations,
+            wasm_performance,
+            handler_stats,
+            reorg_threshold,
+            latest_block_provider,
         } = self;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> r::Value {
@@ -134,6 +622,8 @@ impl IntoValue for Info {
                 block_ptr,
                 handler,
                 deterministic,
+                code,
+                provider,
             } = subgraph_error;
 
             object! {
@@ -147,6 +637,8 @@ impl IntoValue for Info {
                     hash: block_ptr.map(|x| r::Value::from(Value::Bytes(x.hash.into()))),
                 },
                 deterministic: deterministic,
+                code: code,
+                provider: provider,
             }
         }
 
@@ -165,7 +657,21 @@ impl IntoValue for Info {
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(|chain| chain.into_value()).collect::<Vec<_>>(),
             entityCount: format!("{}", entity_count),
+            storeUnavailable: store_unavailable.is_some(),
+            storeUnavailableSecs: store_unavailable.map(|paused| paused.as_secs()),
             node: node,
+            nodeVersions: node_versions,
+            copyIntegrity: copy_integrity,
+            handlerEntityTypes: handler_entity_types,
+            busStatus: bus_status,
+            poiDivergence: poi_divergence,
+            invariantViolations: invariant_violations,
+            mappingTerminations: mapping_terminations,
+            wasmPerformance: wasm_performance,
+            handlerStats: handler_stats.handlers,
+            handlerStatsSince: handler_stats.since.map(|since| since.to_rfc3339()),
+            reorgThreshold: reorg_threshold,
+            latestBlockProvider: latest_block_provider,
         }
     }
 }
@@ -151,6 +151,27 @@ impl<C: Blockchain> DataSource<C> {
         }
     }
 
+    /// The entity types listed under `entities` in the manifest, if the
+    /// data source has such a list
+    pub fn declared_entities(&self) -> Option<Vec<EntityType>> {
+        match self {
+            Self::Onchain(ds) => ds
+                .declared_entities()
+                .map(|entities| entities.iter().cloned().map(EntityType::new).collect()),
+            Self::Offchain(ds) => Some(ds.mapping.entities.clone()),
+        }
+    }
+
+    /// The number of handlers that the manifest declares for this data
+    /// source
+    pub fn handler_count(&self) -> usize {
+        match self {
+            Self::Onchain(ds) => ds.handler_count(),
+            // Offchain data sources have exactly one handler
+            Self::Offchain(_) => 1,
+        }
+    }
+
     pub fn match_and_decode(
         &self,
         trigger: &TriggerData<C>,
@@ -33,6 +33,9 @@ pub struct DataSource {
     pub mapping: Mapping,
     pub context: Arc<Option<DataSourceContext>>,
     pub creation_block: Option<BlockNumber>,
+    /// The position of this data source among the data sources created in
+    /// its creation block, see `DataSourceTemplateInfo::creation_seq`
+    pub creation_seq: Option<u32>,
     done_at: Arc<AtomicI32>,
     pub causality_region: CausalityRegion,
 }
@@ -46,6 +49,7 @@ impl DataSource {
         mapping: Mapping,
         context: Arc<Option<DataSourceContext>>,
         creation_block: Option<BlockNumber>,
+        creation_seq: Option<u32>,
         causality_region: CausalityRegion,
     ) -> Self {
         Self {
@@ -56,6 +60,7 @@ impl DataSource {
             mapping,
             context,
             creation_block,
+            creation_seq,
             done_at: Arc::new(AtomicI32::new(NOT_DONE_VALUE)),
             causality_region,
         }
@@ -119,6 +124,7 @@ impl DataSource {
             mapping: template.mapping,
             context: Arc::new(info.context),
             creation_block: Some(info.creation_block),
+            creation_seq: Some(info.creation_seq),
             done_at: Arc::new(AtomicI32::new(NOT_DONE_VALUE)),
             causality_region,
         })
@@ -161,6 +167,7 @@ impl DataSource {
             param: Some(param),
             context,
             creation_block: self.creation_block,
+            creation_seq: self.creation_seq,
             done_at,
             causality_region: self.causality_region,
         }
@@ -175,6 +182,7 @@ impl DataSource {
             param,
             context,
             creation_block,
+            creation_seq,
             done_at,
             causality_region,
         } = stored;
@@ -193,6 +201,7 @@ impl DataSource {
             mapping: template.mapping.clone(),
             context,
             creation_block,
+            creation_seq,
             done_at: Arc::new(AtomicI32::new(done_at.unwrap_or(NOT_DONE_VALUE))),
             causality_region,
         })
@@ -220,6 +229,7 @@ impl DataSource {
             // We want to deduplicate across done status or creation block.
             done_at: _,
             creation_block: _,
+            creation_seq: _,
 
             // The causality region is also ignored, to be able to detect duplicated file data
             // sources.
@@ -305,6 +315,7 @@ impl UnresolvedDataSource {
             mapping: self.mapping.resolve(resolver, logger).await?,
             context: Arc::new(None),
             creation_block: None,
+            creation_seq: None,
             done_at: Arc::new(AtomicI32::new(NOT_DONE_VALUE)),
             causality_region,
         })
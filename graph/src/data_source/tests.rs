@@ -86,6 +86,7 @@ fn new_datasource() -> offchain::DataSource {
         },
         Arc::new(None),
         Some(0),
+        Some(0),
         CausalityRegion::ONCHAIN.next(),
     )
 }
@@ -1,6 +1,7 @@
 use std::fmt;
 
 use super::*;
+use crate::runtime::CompileProfile;
 
 #[derive(Clone)]
 pub struct EnvVarsMapping {
@@ -21,11 +22,42 @@ pub struct EnvVarsMapping {
     /// Set by the environment variable `GRAPH_MAPPING_HANDLER_TIMEOUT`
     /// (expressed in seconds). No default is provided.
     pub timeout: Option<Duration>,
+    /// Set by the environment variable
+    /// `GRAPH_MAPPING_HANDLER_HOST_WAIT_TIMEOUT` (expressed in seconds). When
+    /// set, time a handler spends blocked on host function I/O does not
+    /// count towards `timeout` but towards this budget. No default is
+    /// provided.
+    pub host_wait_timeout: Option<Duration>,
+    /// The maximum number of pages that one handler invocation can read
+    /// with `store.loadRelatedPage`.
+    ///
+    /// Set by the environment variable `GRAPH_MAPPING_MAX_RELATED_PAGES`.
+    /// The default value is 1000.
+    pub max_related_pages: usize,
+    /// How many abnormal terminations of the mappings of a deployment per
+    /// minute are tolerated before a warning is logged; 0 turns the
+    /// warning off.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_MAPPING_MAX_TERMINATION_RATE`. The default value is 10.
+    pub max_termination_rate: usize,
     /// Maximum stack size for the WASM runtime.
     ///
     /// Set by the environment variable `GRAPH_RUNTIME_MAX_STACK_SIZE`
     /// (expressed in bytes). The default value is 512KiB.
     pub max_stack_size: usize,
+    /// The profile with which WASM modules of deployments that do not have
+    /// their own profile are compiled; see `graphman compile-profile`.
+    ///
+    /// Set by the environment variable `GRAPH_WASM_COMPILE_PROFILE`, either
+    /// `fast-compile` or `optimized`. The default value is `fast-compile`.
+    pub compile_profile: CompileProfile,
+    /// How many of the lines that a handler logged last are added to the
+    /// error when the handler fails; 0 turns that off.
+    ///
+    /// Set by the environment variable `GRAPH_MAPPING_RECENT_LOG_LINES`.
+    /// The default value is 5.
+    pub recent_log_lines: usize,
 
     /// Set by the environment variable `GRAPH_MAX_IPFS_CACHE_FILE_SIZE`
     /// (expressed in bytes). The default value is 1MiB.
@@ -43,6 +75,19 @@ pub struct EnvVarsMapping {
     /// Set by the environment variable `GRAPH_MAX_IPFS_MAP_FILE_SIZE_LIMIT`
     /// (expressed in bytes). The default value is 256MiB.
     pub max_ipfs_map_file_size: usize,
+    /// The number of values from an `ipfs.map` file after which the
+    /// entities that the callbacks loaded from the store are evicted from
+    /// the staged changes.
+    ///
+    /// Set by the environment variable `GRAPH_IPFS_MAP_CHUNK_SIZE`. The
+    /// default value is 1000.
+    pub ipfs_map_chunk_size: usize,
+    /// The maximum number of changed entities that the callbacks of one
+    /// `ipfs.map` call can hold in memory before the call fails.
+    ///
+    /// Set by the environment variable `GRAPH_IPFS_MAP_MAX_CHANGES`. The
+    /// default value is 100000.
+    pub ipfs_map_max_changes: usize,
     /// Sets the `ipfs.cat` file size limit.
     ///
     /// Set by the environment variable `GRAPH_MAX_IPFS_FILE_BYTES` (expressed in
@@ -57,6 +102,21 @@ pub struct EnvVarsMapping {
     /// Set by the flag `GRAPH_ALLOW_NON_DETERMINISTIC_IPFS`. Off by
     /// default.
     pub allow_non_deterministic_ipfs: bool,
+
+    /// Whether file data sources reject files whose content does not match
+    /// their CID. Mismatched content is never delivered to the mapping;
+    /// the file is retried later from all IPFS gateways instead.
+    ///
+    /// Set by the flag `GRAPH_IPFS_STRICT_VERIFICATION_FILE_DATA_SOURCES`.
+    /// On by default.
+    pub ipfs_strict_verification_file_data_sources: bool,
+    /// Whether `ipfs.cat` and other reads of IPFS files by CID fail when
+    /// the content does not match the CID. When this is off, such files
+    /// are used anyway, and only a warning is logged.
+    ///
+    /// Set by the flag `GRAPH_IPFS_STRICT_VERIFICATION_CAT`. Off by
+    /// default.
+    pub ipfs_strict_verification_cat: bool,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -74,15 +134,28 @@ impl From<InnerMappingHandlers> for EnvVarsMapping {
 
             max_api_version: x.max_api_version,
             timeout: x.mapping_handler_timeout_in_secs.map(Duration::from_secs),
+            host_wait_timeout: x
+                .mapping_handler_host_wait_timeout_in_secs
+                .map(Duration::from_secs),
+            max_related_pages: x.max_related_pages,
+            max_termination_rate: x.max_termination_rate,
             max_stack_size: x.runtime_max_stack_size.0 .0,
+            compile_profile: x.compile_profile,
+            recent_log_lines: x.recent_log_lines,
 
             max_ipfs_cache_file_size: x.max_ipfs_cache_file_size.0,
             max_ipfs_cache_size: x.max_ipfs_cache_size,
             ipfs_timeout: Duration::from_secs(x.ipfs_timeout_in_secs),
             max_ipfs_map_file_size: x.max_ipfs_map_file_size.0,
+            ipfs_map_chunk_size: x.ipfs_map_chunk_size,
+            ipfs_map_max_changes: x.ipfs_map_max_changes,
             max_ipfs_file_bytes: x.max_ipfs_file_bytes.0,
             ipfs_request_limit: x.ipfs_request_limit,
             allow_non_deterministic_ipfs: x.allow_non_deterministic_ipfs.0,
+            ipfs_strict_verification_file_data_sources: x
+                .ipfs_strict_verification_file_data_sources
+                .0,
+            ipfs_strict_verification_cat: x.ipfs_strict_verification_cat.0,
         }
     }
 }
@@ -97,8 +170,18 @@ pub struct InnerMappingHandlers {
     max_api_version: Version,
     #[envconfig(from = "GRAPH_MAPPING_HANDLER_TIMEOUT")]
     mapping_handler_timeout_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_MAPPING_HANDLER_HOST_WAIT_TIMEOUT")]
+    mapping_handler_host_wait_timeout_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_MAPPING_MAX_RELATED_PAGES", default = "1000")]
+    max_related_pages: usize,
+    #[envconfig(from = "GRAPH_MAPPING_MAX_TERMINATION_RATE", default = "10")]
+    max_termination_rate: usize,
     #[envconfig(from = "GRAPH_RUNTIME_MAX_STACK_SIZE", default = "")]
     runtime_max_stack_size: WithDefaultUsize<NoUnderscores<usize>, { 512 * 1024 }>,
+    #[envconfig(from = "GRAPH_WASM_COMPILE_PROFILE", default = "fast-compile")]
+    compile_profile: CompileProfile,
+    #[envconfig(from = "GRAPH_MAPPING_RECENT_LOG_LINES", default = "5")]
+    recent_log_lines: usize,
 
     // IPFS.
     #[envconfig(from = "GRAPH_MAX_IPFS_CACHE_FILE_SIZE", default = "")]
@@ -109,10 +192,21 @@ pub struct InnerMappingHandlers {
     ipfs_timeout_in_secs: u64,
     #[envconfig(from = "GRAPH_MAX_IPFS_MAP_FILE_SIZE", default = "")]
     max_ipfs_map_file_size: WithDefaultUsize<usize, { 256 * 1024 * 1024 }>,
+    #[envconfig(from = "GRAPH_IPFS_MAP_CHUNK_SIZE", default = "1000")]
+    ipfs_map_chunk_size: usize,
+    #[envconfig(from = "GRAPH_IPFS_MAP_MAX_CHANGES", default = "100000")]
+    ipfs_map_max_changes: usize,
     #[envconfig(from = "GRAPH_MAX_IPFS_FILE_BYTES", default = "")]
     max_ipfs_file_bytes: WithDefaultUsize<usize, { 256 * 1024 * 1024 }>,
     #[envconfig(from = "GRAPH_IPFS_REQUEST_LIMIT", default = "100")]
     ipfs_request_limit: u16,
     #[envconfig(from = "GRAPH_ALLOW_NON_DETERMINISTIC_IPFS", default = "false")]
     allow_non_deterministic_ipfs: EnvVarBoolean,
+    #[envconfig(
+        from = "GRAPH_IPFS_STRICT_VERIFICATION_FILE_DATA_SOURCES",
+        default = "true"
+    )]
+    ipfs_strict_verification_file_data_sources: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_IPFS_STRICT_VERIFICATION_CAT", default = "false")]
+    ipfs_strict_verification_cat: EnvVarBoolean,
 }
@@ -18,7 +18,12 @@ use self::graphql::*;
 use self::mappings::*;
 use self::store::*;
 use crate::{
-    components::subgraph::SubgraphVersionSwitchingMode, runtime::gas::CONST_MAX_GAS_PER_HANDLER,
+    components::{
+        bus::DeploymentSelection,
+        subgraph::{SafeMode, SubgraphVersionSwitchingMode},
+    },
+    runtime::gas::CONST_MAX_GAS_PER_HANDLER,
+    util::proxy::{Proxies, ProxySetting},
 };
 
 pub static UNSAFE_CONFIG: AtomicBool = AtomicBool::new(false);
@@ -167,6 +172,23 @@ pub struct EnvVars {
     pub poi_access_token: Option<String>,
     /// Set by the environment variable `GRAPH_SUBGRAPH_MAX_DATA_SOURCES`. Defaults to 1 billion.
     pub subgraph_max_data_sources: usize,
+    /// The maximum number of data sources in a manifest. Manifests with
+    /// more are rejected when they are deployed.
+    ///
+    /// Set by the environment variable `GRAPH_MANIFEST_MAX_DATA_SOURCES`.
+    /// No limit by default.
+    pub manifest_max_data_sources: Option<usize>,
+    /// The maximum number of templates in a manifest.
+    ///
+    /// Set by the environment variable `GRAPH_MANIFEST_MAX_TEMPLATES`. No
+    /// limit by default.
+    pub manifest_max_templates: Option<usize>,
+    /// The maximum number of handlers across all data sources in a
+    /// manifest.
+    ///
+    /// Set by the environment variable `GRAPH_MANIFEST_MAX_HANDLERS`. No
+    /// limit by default.
+    pub manifest_max_handlers: Option<usize>,
     /// Keep deterministic errors non-fatal even if the subgraph is pending.
     /// Used for testing Graph Node itself.
     ///
@@ -207,6 +229,54 @@ pub struct EnvVars {
     pub static_filters_threshold: usize,
     /// Message Bus service
     pub bus_url: Option<String>,
+    /// The deployments that publish all their entity changes to the bus
+    /// after each block.
+    ///
+    /// Set by the environment variable `GRAPH_BUS_MODIFICATIONS` to `*`
+    /// or a comma-separated list of deployment hashes. No deployment
+    /// publishes them by default.
+    pub bus_modifications: DeploymentSelection,
+    /// The deployments that publish a summary of how many entities of each
+    /// type they changed to the bus after each block.
+    ///
+    /// Set by the environment variable `GRAPH_BUS_BLOCK_SUMMARIES` in the
+    /// same format as `GRAPH_BUS_MODIFICATIONS`. No deployment publishes
+    /// them by default.
+    pub bus_block_summaries: DeploymentSelection,
+    /// Also publish summaries for blocks in which a deployment changed no
+    /// entities so that consumers can detect gaps.
+    ///
+    /// Set by the flag `GRAPH_BUS_EMPTY_BLOCK_SUMMARIES`. Off by default.
+    pub bus_empty_block_summaries: bool,
+    /// Set by the environment variable `GRAPH_BUS_MODIFICATIONS_TOPIC`. The
+    /// default value is `modifications`.
+    pub bus_modifications_topic: String,
+    /// Set by the environment variable `GRAPH_BUS_BLOCK_SUMMARIES_TOPIC`.
+    /// The default value is `block-summaries`.
+    pub bus_block_summaries_topic: String,
+    /// Set by the environment variable `GRAPH_BUS_LIFECYCLE_TOPIC`. The
+    /// default value is `lifecycle`.
+    pub bus_lifecycle_topic: String,
+    /// How often publishing a message with critical changes is retried
+    /// before it is dead-lettered.
+    ///
+    /// Set by the environment variable `GRAPH_BUS_CRITICAL_RETRIES`. The
+    /// default value is 10.
+    pub bus_critical_retries: usize,
+    /// The topic that messages with critical changes that could not be
+    /// delivered to their topic are published to.
+    ///
+    /// Set by the environment variable `GRAPH_BUS_DEAD_LETTER_TOPIC`. By
+    /// default, such messages are only logged.
+    pub bus_dead_letter_topic: Option<String>,
+    /// Batches of generated bus messages whose serialization and publishing
+    /// together take longer than this are logged with how long each part
+    /// took.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_BUS_SLOW_BATCH_LOG_THRESHOLD_MS` (expressed in milliseconds).
+    /// The default value is 1000ms.
+    pub bus_slow_batch_log_threshold: Duration,
 
     /// Set by the environment variable
     /// `POOL_MAX_IDLE_PER_HOST`. The default value is 20.
@@ -214,6 +284,131 @@ pub struct EnvVars {
     /// Set by the environment variable
     /// `POOL_IDLE_TIME_OUT`. The default value is 60s.
     pub pool_idle_time_out: Duration,
+    /// How often the usage of shared infrastructure by each deployment is
+    /// written to the database. Set by the environment variable
+    /// `GRAPH_USAGE_FLUSH_INTERVAL` (expressed in seconds). The default
+    /// value is 300s.
+    pub usage_flush_interval: Duration,
+    /// How many days of usage records to keep. Set by the environment
+    /// variable `GRAPH_USAGE_RETENTION_DAYS`. The default value is 90 days.
+    pub usage_retention_days: u32,
+    /// Refuse to start deployments whose compatibility fingerprint
+    /// changed since they were first started until the change is
+    /// acknowledged with `graphman compat ack`.
+    ///
+    /// Set by the flag `GRAPH_STRICT_COMPATIBILITY`. Off by default.
+    pub strict_compatibility: bool,
+    /// How many deployments that are catching up with the chain head can
+    /// process blocks at the same time. When more deployments want to
+    /// process blocks, they take turns according to their priority, which
+    /// is higher for deployments that receive queries.
+    ///
+    /// Set by the environment variable `GRAPH_CATCH_UP_CONCURRENCY`. The
+    /// default value of 0 turns this off.
+    pub catch_up_concurrency: usize,
+    /// How much one query per second adds to the catch-up priority of a
+    /// deployment; every deployment has a base priority of 1.
+    ///
+    /// Set by the environment variable `GRAPH_CATCH_UP_QUERY_WEIGHT`. The
+    /// default value is 1.
+    pub catch_up_query_weight: f64,
+    /// The smallest catch-up priority of any deployment, as a fraction of
+    /// the highest priority of the deployments it competes with. This
+    /// keeps deployments without queries from being starved.
+    ///
+    /// Set by the environment variable `GRAPH_CATCH_UP_MIN_SHARE`. The
+    /// default value is 0.1.
+    pub catch_up_min_share: f64,
+    /// Deployments that always get the highest catch-up priority.
+    ///
+    /// Set by the environment variable `GRAPH_CATCH_UP_PINNED` to `*` or a
+    /// comma-separated list of deployment hashes. No deployment is pinned
+    /// by default.
+    pub catch_up_pinned: DeploymentSelection,
+    /// How many offchain triggers, like those of file data sources, can be
+    /// processed at the same time across all deployments. Processing
+    /// onchain triggers is not limited by this.
+    ///
+    /// Set by the environment variable `GRAPH_OFFCHAIN_TRIGGER_CONCURRENCY`.
+    /// The default value of 0 turns this off.
+    pub offchain_trigger_concurrency: usize,
+    /// How many offchain triggers a deployment can process in a row before
+    /// it has to let other deployments that are waiting go first.
+    ///
+    /// Set by the environment variable `GRAPH_OFFCHAIN_TRIGGER_SHARE`. The
+    /// default value is 10.
+    pub offchain_trigger_share: usize,
+    /// The URLs of the index node GraphQL endpoints of reference indexers
+    /// whose public PoIs are compared with the PoIs of local deployments.
+    ///
+    /// Set by the environment variable `GRAPH_POI_REFERENCE_ENDPOINTS` to a
+    /// comma-separated list of URLs. Empty by default, which turns the
+    /// comparison off.
+    pub poi_reference_endpoints: Vec<String>,
+    /// How often the PoI of each deployment is compared with the reference
+    /// indexers.
+    ///
+    /// Set by the environment variable `GRAPH_POI_REFERENCE_INTERVAL`
+    /// (expressed in seconds). The default value is 600 seconds.
+    pub poi_reference_interval: Duration,
+    /// How many reference indexers must report a PoI that differs from the
+    /// local one before a deployment is considered to have diverged.
+    ///
+    /// Set by the environment variable `GRAPH_POI_REFERENCE_QUORUM`. The
+    /// default value of 0 means a majority of the reference indexers.
+    pub poi_reference_quorum: usize,
+    /// The proxies that outbound connections go through.
+    ///
+    /// Set by the environment variable `GRAPH_PROXY` for all connections,
+    /// and by `GRAPH_PROXY_IPFS`, `GRAPH_PROXY_PROVIDERS`,
+    /// `GRAPH_PROXY_FIREHOSE`, and `GRAPH_PROXY_BUS` for one kind of
+    /// destination. Each is a `http://`, `socks5://`, or `socks5h://` URL,
+    /// or `none` to not use a proxy. Unset by default.
+    pub proxies: Proxies,
+    /// Whether to start the deployments assigned to this node in waves
+    /// when it starts up: `off`, `on`, or `auto` to only do that when the
+    /// previous run of the node did not shut down cleanly.
+    ///
+    /// Set by the environment variable `GRAPH_SAFE_MODE`. The default value
+    /// is `off`.
+    pub safe_mode: SafeMode,
+    /// How many deployments to start in each wave in safe mode.
+    ///
+    /// Set by the environment variable `GRAPH_SAFE_MODE_WAVE_SIZE`. The
+    /// default value is 20.
+    pub safe_mode_wave_size: usize,
+    /// How long to wait between waves in safe mode.
+    ///
+    /// Set by the environment variable `GRAPH_SAFE_MODE_WAVE_DELAY`
+    /// (expressed in seconds). The default value is 30s.
+    pub safe_mode_wave_delay: Duration,
+    /// Stop starting deployments in safe mode when the node uses more than
+    /// this many megabytes of memory.
+    ///
+    /// Set by the environment variable `GRAPH_SAFE_MODE_MAX_MEMORY_MB`. The
+    /// default value of 0 means no limit.
+    pub safe_mode_max_memory_mb: u64,
+    /// Stop starting deployments in safe mode when a query for the status
+    /// of the remaining deployments takes longer than this.
+    ///
+    /// Set by the environment variable `GRAPH_SAFE_MODE_MAX_STORE_LATENCY_MS`
+    /// (expressed in milliseconds). The default value is 5000ms; 0 means no
+    /// limit.
+    pub safe_mode_max_store_latency: Duration,
+    /// How long the head of a deployment may stay at the same block while
+    /// the head of its chain moves before the deployment is considered
+    /// stuck.
+    ///
+    /// Set by the environment variable `GRAPH_STUCK_DEPLOYMENT_TIMEOUT`
+    /// (expressed in seconds). The default value is 900 seconds; 0 turns
+    /// the detection off.
+    pub stuck_deployment_timeout: Duration,
+    /// Whether to publish a lifecycle message to the bus backends of a
+    /// deployment when it is found to be stuck.
+    ///
+    /// Set by the environment variable `GRAPH_STUCK_DEPLOYMENT_BUS_EVENTS`.
+    /// Off by default.
+    pub stuck_deployment_bus_events: bool,
 }
 
 impl EnvVars {
@@ -260,6 +455,9 @@ impl EnvVars {
             kill_if_unresponsive: inner.kill_if_unresponsive.0,
             poi_access_token: inner.poi_access_token,
             subgraph_max_data_sources: inner.subgraph_max_data_sources.0,
+            manifest_max_data_sources: inner.manifest_max_data_sources,
+            manifest_max_templates: inner.manifest_max_templates,
+            manifest_max_handlers: inner.manifest_max_handlers,
             disable_fail_fast: inner.disable_fail_fast.0,
             subgraph_error_retry_ceil: Duration::from_secs(inner.subgraph_error_retry_ceil_in_secs),
             enable_select_by_specific_attributes: inner.enable_select_by_specific_attributes.0,
@@ -271,8 +469,53 @@ impl EnvVars {
             external_ws_base_url: inner.external_ws_base_url,
             static_filters_threshold: inner.static_filters_threshold,
             bus_url: inner.bus_url,
+            bus_modifications: inner.bus_modifications,
+            bus_block_summaries: inner.bus_block_summaries,
+            bus_empty_block_summaries: inner.bus_empty_block_summaries.0,
+            bus_modifications_topic: inner.bus_modifications_topic,
+            bus_block_summaries_topic: inner.bus_block_summaries_topic,
+            bus_lifecycle_topic: inner.bus_lifecycle_topic,
+            bus_critical_retries: inner.bus_critical_retries,
+            bus_dead_letter_topic: inner.bus_dead_letter_topic,
+            bus_slow_batch_log_threshold: Duration::from_millis(
+                inner.bus_slow_batch_log_threshold_in_ms,
+            ),
             pool_max_idle_per_host: inner.pool_max_idle_per_host,
             pool_idle_time_out: Duration::from_secs(inner.pool_idle_time_out),
+            usage_flush_interval: Duration::from_secs(inner.usage_flush_interval_in_secs),
+            usage_retention_days: inner.usage_retention_days,
+            strict_compatibility: inner.strict_compatibility.0,
+            catch_up_concurrency: inner.catch_up_concurrency,
+            catch_up_query_weight: inner.catch_up_query_weight,
+            catch_up_min_share: inner.catch_up_min_share,
+            catch_up_pinned: inner.catch_up_pinned,
+            offchain_trigger_concurrency: inner.offchain_trigger_concurrency,
+            offchain_trigger_share: inner.offchain_trigger_share.max(1),
+            poi_reference_endpoints: inner
+                .poi_reference_endpoints
+                .split(',')
+                .map(str::trim)
+                .filter(|endpoint| !endpoint.is_empty())
+                .map(str::to_string)
+                .collect(),
+            poi_reference_interval: Duration::from_secs(inner.poi_reference_interval_in_secs),
+            poi_reference_quorum: inner.poi_reference_quorum,
+            proxies: Proxies {
+                global: inner.proxy,
+                ipfs: inner.proxy_ipfs,
+                providers: inner.proxy_providers,
+                firehose: inner.proxy_firehose,
+                bus: inner.proxy_bus,
+            },
+            safe_mode: inner.safe_mode,
+            safe_mode_wave_size: inner.safe_mode_wave_size.max(1),
+            safe_mode_wave_delay: Duration::from_secs(inner.safe_mode_wave_delay_in_secs),
+            safe_mode_max_memory_mb: inner.safe_mode_max_memory_mb,
+            safe_mode_max_store_latency: Duration::from_millis(
+                inner.safe_mode_max_store_latency_in_ms,
+            ),
+            stuck_deployment_timeout: Duration::from_secs(inner.stuck_deployment_timeout_in_secs),
+            stuck_deployment_bus_events: inner.stuck_deployment_bus_events.0,
         })
     }
 
@@ -368,6 +611,12 @@ struct Inner {
     poi_access_token: Option<String>,
     #[envconfig(from = "GRAPH_SUBGRAPH_MAX_DATA_SOURCES", default = "1_000_000_000")]
     subgraph_max_data_sources: NoUnderscores<usize>,
+    #[envconfig(from = "GRAPH_MANIFEST_MAX_DATA_SOURCES")]
+    manifest_max_data_sources: Option<usize>,
+    #[envconfig(from = "GRAPH_MANIFEST_MAX_TEMPLATES")]
+    manifest_max_templates: Option<usize>,
+    #[envconfig(from = "GRAPH_MANIFEST_MAX_HANDLERS")]
+    manifest_max_handlers: Option<usize>,
     #[envconfig(from = "GRAPH_DISABLE_FAIL_FAST", default = "false")]
     disable_fail_fast: EnvVarBoolean,
     #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS", default = "1800")]
@@ -391,10 +640,76 @@ struct Inner {
     static_filters_threshold: usize,
     #[envconfig(from = "BUS_URL")]
     bus_url: Option<String>,
+    #[envconfig(from = "GRAPH_BUS_MODIFICATIONS", default = "")]
+    bus_modifications: DeploymentSelection,
+    #[envconfig(from = "GRAPH_BUS_BLOCK_SUMMARIES", default = "")]
+    bus_block_summaries: DeploymentSelection,
+    #[envconfig(from = "GRAPH_BUS_EMPTY_BLOCK_SUMMARIES", default = "false")]
+    bus_empty_block_summaries: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_BUS_MODIFICATIONS_TOPIC", default = "modifications")]
+    bus_modifications_topic: String,
+    #[envconfig(from = "GRAPH_BUS_BLOCK_SUMMARIES_TOPIC", default = "block-summaries")]
+    bus_block_summaries_topic: String,
+    #[envconfig(from = "GRAPH_BUS_LIFECYCLE_TOPIC", default = "lifecycle")]
+    bus_lifecycle_topic: String,
+    #[envconfig(from = "GRAPH_BUS_CRITICAL_RETRIES", default = "10")]
+    bus_critical_retries: usize,
+    #[envconfig(from = "GRAPH_BUS_DEAD_LETTER_TOPIC")]
+    bus_dead_letter_topic: Option<String>,
+    #[envconfig(from = "GRAPH_BUS_SLOW_BATCH_LOG_THRESHOLD_MS", default = "1000")]
+    bus_slow_batch_log_threshold_in_ms: u64,
     #[envconfig(from = "POOL_MAX_IDLE_PER_HOST", default = "20")]
     pub pool_max_idle_per_host: usize,
     #[envconfig(from = "POOL_IDLE_TIME_OUT", default = "60")]
     pub pool_idle_time_out: u64,
+    #[envconfig(from = "GRAPH_USAGE_FLUSH_INTERVAL", default = "300")]
+    usage_flush_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_USAGE_RETENTION_DAYS", default = "90")]
+    usage_retention_days: u32,
+    #[envconfig(from = "GRAPH_STRICT_COMPATIBILITY", default = "false")]
+    strict_compatibility: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_CATCH_UP_CONCURRENCY", default = "0")]
+    catch_up_concurrency: usize,
+    #[envconfig(from = "GRAPH_CATCH_UP_QUERY_WEIGHT", default = "1")]
+    catch_up_query_weight: f64,
+    #[envconfig(from = "GRAPH_CATCH_UP_MIN_SHARE", default = "0.1")]
+    catch_up_min_share: f64,
+    #[envconfig(from = "GRAPH_CATCH_UP_PINNED", default = "")]
+    catch_up_pinned: DeploymentSelection,
+    #[envconfig(from = "GRAPH_OFFCHAIN_TRIGGER_CONCURRENCY", default = "0")]
+    offchain_trigger_concurrency: usize,
+    #[envconfig(from = "GRAPH_OFFCHAIN_TRIGGER_SHARE", default = "10")]
+    offchain_trigger_share: usize,
+    #[envconfig(from = "GRAPH_POI_REFERENCE_ENDPOINTS", default = "")]
+    poi_reference_endpoints: String,
+    #[envconfig(from = "GRAPH_POI_REFERENCE_INTERVAL", default = "600")]
+    poi_reference_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_POI_REFERENCE_QUORUM", default = "0")]
+    poi_reference_quorum: usize,
+    #[envconfig(from = "GRAPH_PROXY", default = "")]
+    proxy: ProxySetting,
+    #[envconfig(from = "GRAPH_PROXY_IPFS", default = "")]
+    proxy_ipfs: ProxySetting,
+    #[envconfig(from = "GRAPH_PROXY_PROVIDERS", default = "")]
+    proxy_providers: ProxySetting,
+    #[envconfig(from = "GRAPH_PROXY_FIREHOSE", default = "")]
+    proxy_firehose: ProxySetting,
+    #[envconfig(from = "GRAPH_PROXY_BUS", default = "")]
+    proxy_bus: ProxySetting,
+    #[envconfig(from = "GRAPH_SAFE_MODE", default = "off")]
+    safe_mode: SafeMode,
+    #[envconfig(from = "GRAPH_SAFE_MODE_WAVE_SIZE", default = "20")]
+    safe_mode_wave_size: usize,
+    #[envconfig(from = "GRAPH_SAFE_MODE_WAVE_DELAY", default = "30")]
+    safe_mode_wave_delay_in_secs: u64,
+    #[envconfig(from = "GRAPH_SAFE_MODE_MAX_MEMORY_MB", default = "0")]
+    safe_mode_max_memory_mb: u64,
+    #[envconfig(from = "GRAPH_SAFE_MODE_MAX_STORE_LATENCY_MS", default = "5000")]
+    safe_mode_max_store_latency_in_ms: u64,
+    #[envconfig(from = "GRAPH_STUCK_DEPLOYMENT_TIMEOUT", default = "900")]
+    stuck_deployment_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_STUCK_DEPLOYMENT_BUS_EVENTS", default = "false")]
+    stuck_deployment_bus_events: EnvVarBoolean,
 }
 
 #[derive(Clone, Debug)]
@@ -94,6 +94,62 @@ pub struct EnvVarsStore {
     /// Set by `GRAPH_STORE_BATCH_TARGET_DURATION` (expressed in seconds).
     /// The default is 180s.
     pub batch_target_duration: Duration,
+
+    /// Whether to compare the entities in the source and destination of a
+    /// copy or graft once all data has been copied. Set by
+    /// `GRAPH_STORE_COPY_CHECK_INTEGRITY`. Enabled by default.
+    pub copy_check_integrity: bool,
+    /// Also compare checksums over the ids of the entities in each table
+    /// and not just their number. This is considerably slower than just
+    /// counting. Set by `GRAPH_STORE_COPY_CHECK_CHECKSUMS`. Disabled by
+    /// default.
+    pub copy_check_checksums: bool,
+    /// Skip the integrity check if the source has more entity versions
+    /// than this. Set by `GRAPH_STORE_COPY_CHECK_MAX_VERSIONS`. No default,
+    /// i.e., deployments of any size are checked.
+    pub copy_check_max_versions: Option<i64>,
+    /// Fail the destination of a copy if the integrity check finds more
+    /// than this many missing or extra entities. Set by
+    /// `GRAPH_STORE_COPY_CHECK_FAIL_THRESHOLD`. No default, i.e., the
+    /// results of the check are only recorded and logged.
+    pub copy_check_fail_threshold: Option<u64>,
+    /// How long to pause between checking tables so that the integrity
+    /// check does not overload the database. Set by
+    /// `GRAPH_STORE_COPY_CHECK_PAUSE` (expressed in milliseconds). The
+    /// default is 100ms.
+    pub copy_check_pause: Duration,
+
+    /// How often the retention policies of deployments are applied. Set
+    /// by `GRAPH_STORE_RETENTION_INTERVAL` (expressed in seconds). The
+    /// default is 3600s.
+    pub retention_interval: Duration,
+    /// How many rows to remove in one transaction when applying retention
+    /// policies. Set by `GRAPH_STORE_RETENTION_BATCH_SIZE`. The default is
+    /// 1000.
+    pub retention_batch_size: usize,
+    /// Stop applying retention policies to the deployments in a shard
+    /// while the average wait for a connection to it is longer than this,
+    /// so that pruning only happens while the database is not busy. Set
+    /// by `GRAPH_STORE_RETENTION_MAX_POOL_WAIT` (expressed in
+    /// milliseconds). The default is 100ms.
+    pub retention_max_pool_wait: Duration,
+    /// Refuse to remove rows that time-travel queries for blocks the
+    /// deployment still has history for can see, instead of just logging
+    /// a warning. Set by `GRAPH_STORE_RETENTION_STRICT_HISTORY`. Disabled
+    /// by default.
+    pub retention_strict_history: bool,
+
+    /// How long to pause between dropping two tables when removing a
+    /// deployment so that removals do not overload the database. Set by
+    /// `GRAPH_STORE_REMOVAL_PAUSE` (expressed in milliseconds). The default
+    /// is 500ms.
+    pub removal_pause: Duration,
+
+    /// For how many of the most recent blocks of each deployment to
+    /// remember which provider served them. Set by
+    /// `GRAPH_STORE_BLOCK_PROVIDER_HISTORY`. The default is 1000; 0 turns
+    /// recording providers off.
+    pub block_provider_history: i32,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -132,6 +188,17 @@ impl From<InnerStore> for EnvVarsStore {
             connection_idle_timeout: Duration::from_secs(x.connection_idle_timeout_in_secs),
             write_queue_size: x.write_queue_size,
             batch_target_duration: Duration::from_secs(x.batch_target_duration_in_secs),
+            copy_check_integrity: x.copy_check_integrity.0,
+            copy_check_checksums: x.copy_check_checksums.0,
+            copy_check_max_versions: x.copy_check_max_versions,
+            copy_check_fail_threshold: x.copy_check_fail_threshold,
+            copy_check_pause: Duration::from_millis(x.copy_check_pause_in_millis),
+            retention_interval: Duration::from_secs(x.retention_interval_in_secs),
+            retention_batch_size: x.retention_batch_size,
+            retention_max_pool_wait: Duration::from_millis(x.retention_max_pool_wait_in_millis),
+            retention_strict_history: x.retention_strict_history.0,
+            removal_pause: Duration::from_millis(x.removal_pause_in_millis),
+            block_provider_history: x.block_provider_history,
         }
     }
 }
@@ -179,4 +246,26 @@ pub struct InnerStore {
     write_queue_size: usize,
     #[envconfig(from = "GRAPH_STORE_BATCH_TARGET_DURATION", default = "180")]
     batch_target_duration_in_secs: u64,
+    #[envconfig(from = "GRAPH_STORE_COPY_CHECK_INTEGRITY", default = "true")]
+    copy_check_integrity: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_COPY_CHECK_CHECKSUMS", default = "false")]
+    copy_check_checksums: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_COPY_CHECK_MAX_VERSIONS")]
+    copy_check_max_versions: Option<i64>,
+    #[envconfig(from = "GRAPH_STORE_COPY_CHECK_FAIL_THRESHOLD")]
+    copy_check_fail_threshold: Option<u64>,
+    #[envconfig(from = "GRAPH_STORE_COPY_CHECK_PAUSE", default = "100")]
+    copy_check_pause_in_millis: u64,
+    #[envconfig(from = "GRAPH_STORE_RETENTION_INTERVAL", default = "3600")]
+    retention_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_STORE_RETENTION_BATCH_SIZE", default = "1000")]
+    retention_batch_size: usize,
+    #[envconfig(from = "GRAPH_STORE_RETENTION_MAX_POOL_WAIT", default = "100")]
+    retention_max_pool_wait_in_millis: u64,
+    #[envconfig(from = "GRAPH_STORE_RETENTION_STRICT_HISTORY", default = "false")]
+    retention_strict_history: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_REMOVAL_PAUSE", default = "500")]
+    removal_pause_in_millis: u64,
+    #[envconfig(from = "GRAPH_STORE_BLOCK_PROVIDER_HISTORY", default = "1000")]
+    block_provider_history: i32,
 }
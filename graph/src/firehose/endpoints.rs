@@ -4,9 +4,11 @@ use crate::{
     blockchain::BlockPtr,
     cheap_clone::CheapClone,
     components::store::BlockNumber,
+    env::ENV_VARS,
     firehose::decode_firehose_block,
     prelude::{anyhow, debug, info},
     substreams,
+    util::proxy::{Proxies, ProxyConnector, ProxyTarget},
 };
 
 use anyhow::bail;
@@ -17,7 +19,7 @@ use std::{collections::BTreeMap, fmt::Display, sync::Arc, time::Duration};
 use tonic::{
     codegen::CompressionEncoding,
     metadata::MetadataValue,
-    transport::{Channel, ClientTlsConfig},
+    transport::{Channel, ClientTlsConfig, Endpoint},
     Request,
 };
 
@@ -51,6 +53,16 @@ impl Display for FirehoseEndpoint {
     }
 }
 
+/// Open the connections of the channel to `endpoint` through the proxy
+/// that `proxies` sets for Firehose. Connections through a proxy ignore
+/// the TCP settings of `endpoint` since the connector opens them
+fn connect(endpoint: Endpoint, proxies: &Proxies) -> Channel {
+    match proxies.proxy(ProxyTarget::Firehose) {
+        Some(proxy) => endpoint.connect_with_connector_lazy(ProxyConnector::new(proxy.clone())),
+        None => endpoint.connect_lazy(),
+    }
+}
+
 impl FirehoseEndpoint {
     pub fn new<S: AsRef<str>>(
         provider: S,
@@ -99,9 +111,11 @@ impl FirehoseEndpoint {
             SubgraphLimit::NoTraffic => 0,
         };
 
+        let channel = connect(endpoint, &ENV_VARS.proxies);
+
         FirehoseEndpoint {
             provider: provider.as_ref().to_string(),
-            channel: endpoint.connect_lazy(),
+            channel,
             token,
             filters_enabled,
             compression_enabled,
@@ -427,9 +441,38 @@ impl FirehoseNetworks {
 mod test {
     use std::{mem, sync::Arc};
 
+    use tonic::transport::Channel;
+
     use crate::firehose::SubgraphLimit;
+    use crate::util::proxy::{fixture::RecordingProxy, Proxies, ProxySetting};
+
+    use super::{connect, firehose, FirehoseEndpoint, FirehoseEndpoints, SUBGRAPHS_PER_CONN};
 
-    use super::{FirehoseEndpoint, FirehoseEndpoints, SUBGRAPHS_PER_CONN};
+    #[tokio::test]
+    async fn firehose_uses_proxy() {
+        let proxy = RecordingProxy::start("").await;
+        let proxies = Proxies {
+            firehose: ProxySetting::Proxy(proxy.url()),
+            ..Default::default()
+        };
+
+        // The proxy refuses to open the tunnel, but has to be asked for
+        // it before anything is sent to the provider
+        let channel = connect(
+            Channel::from_static("http://firehose.invalid:10015"),
+            &proxies,
+        );
+        let mut client = firehose::fetch_client::FetchClient::new(channel);
+        let request = firehose::SingleBlockRequest {
+            transforms: vec![],
+            reference: None,
+        };
+        client.block(request).await.unwrap_err();
+        assert_eq!(
+            vec!["CONNECT firehose.invalid:10015 HTTP/1.1".to_string()],
+            proxy.requests()
+        );
+    }
 
     #[tokio::test]
     async fn firehose_endpoint_errors() {
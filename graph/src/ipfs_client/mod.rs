@@ -1,4 +1,6 @@
+use crate::env::ENV_VARS;
 use crate::prelude::CheapClone;
+use crate::util::proxy::{Proxies, ProxyTarget};
 use anyhow::anyhow;
 use anyhow::Error;
 use bytes::Bytes;
@@ -12,6 +14,10 @@ use std::fmt::Display;
 use std::time::Duration;
 use std::{str::FromStr, sync::Arc};
 
+mod verify;
+
+pub use verify::{verify_block, CatVerifiedError};
+
 /// Represents a file on Ipfs. This file can be the CID or a path within a folder CID.
 /// The path cannot have a prefix (ie CID/hello.json would be cid: CID path: "hello.json")
 #[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
@@ -124,15 +130,27 @@ impl CheapClone for IpfsClient {
 
 impl IpfsClient {
     pub fn new(base: &str) -> Result<Self, Error> {
+        Self::with_proxies(base, &ENV_VARS.proxies)
+    }
+
+    /// A client for the IPFS node at `base` that connects to it through
+    /// the proxy that `proxies` sets for IPFS
+    pub fn with_proxies(base: &str, proxies: &Proxies) -> Result<Self, Error> {
         Ok(IpfsClient {
-            client: Arc::new(reqwest::Client::new()),
+            client: Arc::new(proxies.http_client(ProxyTarget::Ipfs).build()?),
             base: Arc::new(Uri::from_str(base)?),
         })
     }
 
     pub fn localhost() -> Self {
         IpfsClient {
-            client: Arc::new(reqwest::Client::new()),
+            client: Arc::new(
+                ENV_VARS
+                    .proxies
+                    .http_client(ProxyTarget::Ipfs)
+                    .build()
+                    .unwrap(),
+            ),
             base: Arc::new(Uri::from_str("http://localhost:5001").unwrap()),
         }
     }
@@ -165,6 +183,27 @@ impl IpfsClient {
             .await
     }
 
+    /// Download the contents of `file` block by block and check every
+    /// block against its CID. Fails with `CatVerifiedError::Mismatch` if
+    /// the content of a block does not match its CID.
+    pub async fn cat_verified(
+        &self,
+        file: &CidFile,
+        max_file_size: u64,
+        timeout: Duration,
+    ) -> Result<Bytes, CatVerifiedError> {
+        let fetch = move |cid: Cid| async move { Ok(self.block_get(&cid, timeout).await?) };
+        verify::cat(fetch, file, max_file_size).await
+    }
+
+    /// Download the raw contents of the block `cid`
+    pub async fn block_get(&self, cid: &Cid, timeout: Duration) -> Result<Bytes, reqwest::Error> {
+        self.call(self.url("block/get", &cid.to_string()), None, Some(timeout))
+            .await?
+            .bytes()
+            .await
+    }
+
     pub async fn cat(
         &self,
         cid: &str,
@@ -237,7 +276,26 @@ mod test {
     use anyhow::anyhow;
     use cid::Cid;
 
-    use crate::ipfs_client::CidFile;
+    use crate::ipfs_client::{CidFile, IpfsClient};
+    use crate::util::proxy::{fixture::RecordingProxy, Proxies, ProxySetting};
+
+    #[tokio::test]
+    async fn uses_proxy() {
+        let proxy = RecordingProxy::start(r#"{"Version":"0.0.0"}"#).await;
+        let proxies = Proxies {
+            ipfs: ProxySetting::Proxy(proxy.url()),
+            ..Default::default()
+        };
+
+        // The host does not resolve, the request can only succeed through
+        // the proxy
+        let client = IpfsClient::with_proxies("http://ipfs.invalid:5001", &proxies).unwrap();
+        client.test().await.unwrap();
+        assert_eq!(
+            vec!["POST http://ipfs.invalid:5001/api/v0/version HTTP/1.1".to_string()],
+            proxy.requests()
+        );
+    }
 
     #[test]
     fn test_cid_parsing() {
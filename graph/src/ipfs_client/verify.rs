@@ -0,0 +1,366 @@
+//! Download files from IPFS block by block and check that the content of
+//! every block matches the CID under which it was requested. That makes it
+//! impossible for a misbehaving gateway to pass off arbitrary bytes as the
+//! content of a CID.
+//!
+//! Files can either be single blocks with the `raw` codec, or UnixFS files
+//! encoded as a DAG of `dag-pb` nodes, whose leaves can be `dag-pb` or
+//! `raw` blocks. Paths into UnixFS directories are resolved by following
+//! the named links of each directory. HAMT-sharded directories and other
+//! codecs are not supported.
+
+use std::future::Future;
+
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
+use cid::Cid;
+use multihash::{Code, MultihashDigest};
+use prost::Message;
+use thiserror::Error;
+
+use super::CidFile;
+
+const RAW: u64 = 0x55;
+const DAG_PB: u64 = 0x70;
+const IDENTITY: u64 = 0x00;
+
+#[derive(Debug, Error)]
+pub enum CatVerifiedError {
+    /// The content returned for a block does not match its CID
+    #[error("content of IPFS block {0} does not match its CID")]
+    Mismatch(Cid),
+    /// The file can not be verified, for example because it uses a codec
+    /// or hash function that we do not support
+    #[error("IPFS file can not be verified: {0}")]
+    Unsupported(String),
+    #[error(transparent)]
+    Fetch(#[from] reqwest::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PbLink {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    hash: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "2")]
+    name: Option<String>,
+    #[prost(uint64, optional, tag = "3")]
+    tsize: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PbNode {
+    #[prost(message, repeated, tag = "2")]
+    links: Vec<PbLink>,
+    #[prost(bytes = "vec", optional, tag = "1")]
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct UnixFsData {
+    #[prost(enumeration = "DataType", required, tag = "1")]
+    r#type: i32,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    data: Option<Vec<u8>>,
+    #[prost(uint64, optional, tag = "3")]
+    filesize: Option<u64>,
+    #[prost(uint64, repeated, packed = "false", tag = "4")]
+    blocksizes: Vec<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum DataType {
+    Raw = 0,
+    Directory = 1,
+    File = 2,
+    Metadata = 3,
+    Symlink = 4,
+    HamtShard = 5,
+}
+
+/// Check that `block` is the content of the block `cid`
+pub fn verify_block(cid: &Cid, block: &[u8]) -> Result<(), CatVerifiedError> {
+    let expected = cid.hash();
+    if expected.code() == IDENTITY {
+        return match expected.digest() == block {
+            true => Ok(()),
+            false => Err(CatVerifiedError::Mismatch(*cid)),
+        };
+    }
+
+    let code = Code::try_from(expected.code()).map_err(|_| {
+        CatVerifiedError::Unsupported(format!(
+            "hash function {:#x} of {} is not supported",
+            expected.code(),
+            cid
+        ))
+    })?;
+    let actual = code.digest(block);
+    match actual.digest().get(..expected.size() as usize) == Some(expected.digest()) {
+        true => Ok(()),
+        false => Err(CatVerifiedError::Mismatch(*cid)),
+    }
+}
+
+/// Fetch the block `cid` with `fetch` and verify its content. Blocks with
+/// an identity hash contain their content in the CID and are not fetched
+async fn fetch_verified<F, Fut>(fetch: &F, cid: &Cid) -> Result<Bytes, CatVerifiedError>
+where
+    F: Fn(Cid) -> Fut,
+    Fut: Future<Output = Result<Bytes, CatVerifiedError>>,
+{
+    if cid.hash().code() == IDENTITY {
+        return Ok(Bytes::copy_from_slice(cid.hash().digest()));
+    }
+    let block = fetch(*cid).await?;
+    verify_block(cid, &block)?;
+    Ok(block)
+}
+
+fn decode_dag_pb(cid: &Cid, block: Bytes) -> Result<(PbNode, UnixFsData, DataType), Error> {
+    let node = PbNode::decode(block).map_err(|e| anyhow!("invalid dag-pb node {}: {}", cid, e))?;
+    let data = UnixFsData::decode(node.data.as_deref().unwrap_or_default())
+        .map_err(|e| anyhow!("invalid UnixFS data in {}: {}", cid, e))?;
+    let data_type = DataType::from_i32(data.r#type)
+        .ok_or_else(|| anyhow!("unknown UnixFS type {} in {}", data.r#type, cid))?;
+    Ok((node, data, data_type))
+}
+
+fn link_cid(cid: &Cid, link: &PbLink) -> Result<Cid, Error> {
+    let hash = link
+        .hash
+        .as_deref()
+        .ok_or_else(|| anyhow!("link without a hash in {}", cid))?;
+    Cid::try_from(hash).map_err(|e| anyhow!("invalid link in {}: {}", cid, e))
+}
+
+type Error = CatVerifiedError;
+
+/// Download the content of `file`, using `fetch` to get the raw content
+/// of individual blocks, and verify every block against its CID. Fails if
+/// the file is larger than `max_file_size` bytes.
+pub async fn cat<F, Fut>(fetch: F, file: &CidFile, max_file_size: u64) -> Result<Bytes, Error>
+where
+    F: Fn(Cid) -> Fut,
+    Fut: Future<Output = Result<Bytes, Error>>,
+{
+    let too_large = || {
+        anyhow!(
+            "IPFS file {} is too large. It can be at most {} bytes",
+            file,
+            max_file_size
+        )
+    };
+
+    // Follow the path through directories to the file
+    let mut cid = file.cid;
+    let mut block = fetch_verified(&fetch, &cid).await?;
+    let segments = file.path.iter().flat_map(|path| path.split('/'));
+    for segment in segments.filter(|segment| !segment.is_empty()) {
+        if cid.codec() != DAG_PB {
+            return Err(anyhow!("{} is not a directory", cid).into());
+        }
+        let (node, _, data_type) = decode_dag_pb(&cid, block)?;
+        match data_type {
+            DataType::Directory => {}
+            DataType::HamtShard => {
+                return Err(Error::Unsupported(format!(
+                    "sharded directory {} is not supported",
+                    cid
+                )))
+            }
+            _ => return Err(anyhow!("{} is not a directory", cid).into()),
+        }
+        let link = node
+            .links
+            .iter()
+            .find(|link| link.name.as_deref() == Some(segment))
+            .ok_or_else(|| anyhow!("{} has no entry `{}`", cid, segment))?;
+        cid = link_cid(&cid, link)?;
+        block = fetch_verified(&fetch, &cid).await?;
+    }
+
+    // Read the file by walking its DAG depth-first, left to right
+    let mut content = BytesMut::new();
+    let mut pending = vec![(cid, Some(block))];
+    while let Some((cid, block)) = pending.pop() {
+        let block = match block {
+            Some(block) => block,
+            None => fetch_verified(&fetch, &cid).await?,
+        };
+
+        match cid.codec() {
+            RAW => content.extend_from_slice(&block),
+            DAG_PB => {
+                let (node, data, data_type) = decode_dag_pb(&cid, block)?;
+                match data_type {
+                    DataType::Raw | DataType::File => {}
+                    DataType::Directory | DataType::HamtShard => {
+                        return Err(anyhow!("{} is a directory", cid).into())
+                    }
+                    DataType::Metadata | DataType::Symlink => {
+                        return Err(Error::Unsupported(format!(
+                            "UnixFS type {:?} of {} is not supported",
+                            data_type, cid
+                        )))
+                    }
+                }
+                if data.filesize.unwrap_or(0) > max_file_size {
+                    return Err(too_large().into());
+                }
+                if let Some(data) = &data.data {
+                    content.extend_from_slice(data);
+                }
+                for link in node.links.iter().rev() {
+                    pending.push((link_cid(&cid, link)?, None));
+                }
+            }
+            codec => {
+                return Err(Error::Unsupported(format!(
+                    "codec {:#x} of {} is not supported",
+                    codec, cid
+                )))
+            }
+        }
+
+        if content.len() as u64 > max_file_size {
+            return Err(too_large().into());
+        }
+    }
+
+    Ok(content.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use super::*;
+
+    // Blocks for files of each kind that we support, together with their
+    // CIDs. The `dag-pb` blocks are encoded like `ipfs add` encodes them.
+    const RAW_FILE: (&str, &str) = (
+        "bafkreibqchjr6zcse5yc6wbxrhps45i5gbc3sr5iiydkppjhpamcc52jmu",
+        "68656c6c6f207261770a",
+    );
+    // A single `dag-pb` node containing `hello world\n`
+    const SINGLE_NODE_FILE: (&str, &str) = (
+        "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o",
+        "0a120802120c68656c6c6f20776f726c640a180c",
+    );
+    // A `dag-pb` node for `hello world\n` with two raw leaves
+    const CHUNKED_FILE: (&str, &str) = (
+        "bafybeievgwqmxi5xni5riojtkcuxod5ae2qwwxk4npgxcigvxk5fg6ott4",
+        "122a0a24015512205e3235a8346e5a4585f8c58562f5052b8fe26a3bb122e1e96c76784964df\
+         c46112001806122a0a2401551220e258d248fda94c63753607f7c4494ee0fcbe92f1a76bfdac795c9d\
+         84101eb317120018060a080802180c20062006",
+    );
+    const LEAF_1: (&str, &str) = (
+        "bafkreic6gi22qndoljcyl6gfqvrpkbjlr7rguo5relq6s3dwpbewjx6eme",
+        "68656c6c6f20",
+    );
+    const LEAF_2: (&str, &str) = (
+        "bafkreihcldjer7njjrrxknqh67cestxa7s7jf4nhnp62y6k4twcbahvtc4",
+        "776f726c640a",
+    );
+    // A directory with `SINGLE_NODE_FILE` as `file.txt`
+    const DIRECTORY: (&str, &str) = (
+        "Qmet1ysxdJinBcZZwu2QS5Uf9vpaiCuZUYubnJZQHHKn5e",
+        "12300a22122046d44814b9c5af141c3aaab7c05dc5e844ead5f91f12858b021eba45768b4c0e1208\
+         66696c652e74787418140a020801",
+    );
+
+    fn blocks(fixtures: &[(&str, &str)]) -> HashMap<Cid, Bytes> {
+        fixtures
+            .iter()
+            .map(|(cid, hex)| {
+                let cid = Cid::from_str(cid).unwrap();
+                (cid, Bytes::from(hex::decode(hex).unwrap()))
+            })
+            .collect()
+    }
+
+    async fn cat_from(blocks: &HashMap<Cid, Bytes>, file: &str) -> Result<Bytes, Error> {
+        let fetch = |cid: Cid| async move {
+            blocks
+                .get(&cid)
+                .cloned()
+                .ok_or_else(|| Error::from(anyhow!("block {} not found", cid)))
+        };
+        cat(fetch, &CidFile::from_str(file).unwrap(), 1024).await
+    }
+
+    fn all_blocks() -> HashMap<Cid, Bytes> {
+        blocks(&[
+            RAW_FILE,
+            SINGLE_NODE_FILE,
+            CHUNKED_FILE,
+            LEAF_1,
+            LEAF_2,
+            DIRECTORY,
+        ])
+    }
+
+    #[tokio::test]
+    async fn cat_raw_block() {
+        let content = cat_from(&all_blocks(), RAW_FILE.0).await.unwrap();
+        assert_eq!(&content[..], b"hello raw\n");
+    }
+
+    #[tokio::test]
+    async fn cat_dag_pb() {
+        let blocks = all_blocks();
+
+        let content = cat_from(&blocks, SINGLE_NODE_FILE.0).await.unwrap();
+        assert_eq!(&content[..], b"hello world\n");
+
+        let content = cat_from(&blocks, CHUNKED_FILE.0).await.unwrap();
+        assert_eq!(&content[..], b"hello world\n");
+
+        let content = cat_from(&blocks, &format!("{}/file.txt", DIRECTORY.0))
+            .await
+            .unwrap();
+        assert_eq!(&content[..], b"hello world\n");
+
+        let err = cat_from(&blocks, &format!("{}/missing.txt", DIRECTORY.0))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Other(_)));
+
+        let err = cat_from(&blocks, DIRECTORY.0).await.unwrap_err();
+        assert!(matches!(err, Error::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn cat_mismatch() {
+        // The gateway returns the content of a different block
+        let mut blocks = all_blocks();
+        let leaf = Cid::from_str(LEAF_2.0).unwrap();
+        blocks.insert(leaf, Bytes::from_static(b"garbage"));
+
+        let err = cat_from(&blocks, CHUNKED_FILE.0).await.unwrap_err();
+        assert!(matches!(err, Error::Mismatch(cid) if cid == leaf));
+
+        let raw = Cid::from_str(RAW_FILE.0).unwrap();
+        let single = Cid::from_str(SINGLE_NODE_FILE.0).unwrap();
+        let wrong_block = blocks[&single].clone();
+        blocks.insert(raw, wrong_block);
+        let err = cat_from(&blocks, RAW_FILE.0).await.unwrap_err();
+        assert!(matches!(err, Error::Mismatch(cid) if cid == raw));
+    }
+
+    #[tokio::test]
+    async fn cat_too_large() {
+        let blocks = all_blocks();
+        let file = CidFile::from_str(CHUNKED_FILE.0).unwrap();
+        let fetch = |cid: Cid| {
+            let block = blocks[&cid].clone();
+            async move { Ok(block) }
+        };
+        let err = cat(fetch, &file, 8).await.unwrap_err();
+        assert!(matches!(err, Error::Other(_)));
+    }
+}
@@ -13,6 +13,17 @@ pub trait AscHeap {
     /// Allocate new space and write `bytes`, return the allocated address.
     fn raw_new(&mut self, bytes: &[u8], gas: &GasCounter) -> Result<u32, DeterministicHostError>;
 
+    /// Allocate new space and write the concatenation of `parts`, return the
+    /// allocated address. Heaps should override this to write the parts
+    /// without concatenating them first.
+    fn raw_new_parts(
+        &mut self,
+        parts: &[&[u8]],
+        gas: &GasCounter,
+    ) -> Result<u32, DeterministicHostError> {
+        self.raw_new(&parts.concat(), gas)
+    }
+
     fn read<'a>(
         &self,
         offset: u32,
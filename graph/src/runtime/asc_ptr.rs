@@ -111,7 +111,7 @@ impl<C: AscType> AscPtr<C> {
                 )?;
                 let header_len = header.len() as u32;
 
-                let heap_ptr = heap.raw_new(&[header, bytes].concat(), gas)?;
+                let heap_ptr = heap.raw_new_parts(&[&header, &bytes], gas)?;
 
                 // Use header length as offset. so the AscPtr points directly at the content.
                 Ok(AscPtr::new(heap_ptr + header_len))
@@ -119,6 +119,37 @@ impl<C: AscType> AscPtr<C> {
         }
     }
 
+    /// Allocate an Asc object of class `C` whose bytes, without the header,
+    /// are the concatenation of `parts`. `content_len` is the length of the
+    /// content without any padding. This lets large strings and byte arrays
+    /// be written to the heap without first collecting them in one buffer.
+    /// Only used for version >= 0.0.5.
+    pub fn alloc_parts<H: AscHeap + ?Sized>(
+        parts: &[&[u8]],
+        content_len: usize,
+        heap: &mut H,
+        gas: &GasCounter,
+    ) -> Result<AscPtr<C>, DeterministicHostError>
+    where
+        C: AscIndexId,
+    {
+        const ALIGNMENT: [u8; 16] = [0; 16];
+
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let aligned_len = padding_to_16(len);
+        let header =
+            Self::generate_header(heap, C::INDEX_ASC_TYPE_ID, content_len, len + aligned_len)?;
+        let header_len = header.len() as u32;
+
+        let mut all = Vec::with_capacity(parts.len() + 2);
+        all.push(header.as_slice());
+        all.extend_from_slice(parts);
+        all.push(&ALIGNMENT[..aligned_len]);
+        let heap_ptr = heap.raw_new_parts(&all, gas)?;
+
+        Ok(AscPtr::new(heap_ptr + header_len))
+    }
+
     /// Helper used by arrays and strings to read their length.
     /// Only used for version <= 0.0.4.
     pub fn read_u32<H: AscHeap + ?Sized>(
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+
+/// How much effort the compiler spends on the machine code for a WASM
+/// module. The profile only affects how long compiling the module and
+/// running its handlers takes; the results of running the handlers are
+/// the same with every profile.
+///
+/// The profile does change how much of the native stack each WASM call
+/// uses, and `GRAPH_RUNTIME_MAX_STACK_SIZE` limits the native stack. A
+/// deeply recursive handler can therefore overflow the stack with one
+/// profile and not with the other. Stack overflows are not deterministic
+/// errors: the handler is retried and the overflow is never recorded as
+/// the result of the handler, so it can not change the PoI, but the
+/// deployment will not make progress until the stack size is raised or
+/// the profile is changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CompileProfile {
+    /// Compile as quickly as possible, without optional optimizations.
+    /// Best for small modules and for modules that handle few triggers
+    #[default]
+    FastCompile,
+    /// Optimize the machine code for speed. Compiling takes longer, which
+    /// pays off for modules that handle many triggers
+    Optimized,
+}
+
+impl CompileProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompileProfile::FastCompile => "fast-compile",
+            CompileProfile::Optimized => "optimized",
+        }
+    }
+}
+
+impl fmt::Display for CompileProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CompileProfile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast-compile" => Ok(CompileProfile::FastCompile),
+            "optimized" => Ok(CompileProfile::Optimized),
+            _ => Err(anyhow!(
+                "invalid compile profile `{}`, expected `fast-compile` or `optimized`",
+                s
+            )),
+        }
+    }
+}
@@ -3,6 +3,11 @@
 
 use super::*;
 
+/// The version of the gas costs in this file. It must be increased
+/// whenever any of them changes since that changes when handlers run out
+/// of gas; it is part of the compatibility fingerprint of deployments.
+pub const GAS_SCHEDULE_VERSION: u32 = 1;
+
 /// Using 10 gas = ~1ns for WASM instructions.
 const GAS_PER_SECOND: u64 = 10_000_000_000;
 
@@ -63,7 +63,6 @@ impl Gas {
         Gas(gas)
     }
 
-    #[cfg(debug_assertions)]
     pub const fn value(&self) -> u64 {
         self.0
     }
@@ -7,11 +7,13 @@ pub mod gas;
 
 mod asc_heap;
 mod asc_ptr;
+mod compile_profile;
 
 pub use asc_heap::{
     asc_get, asc_new, asc_new_or_missing, asc_new_or_null, AscHeap, FromAscObj, ToAscObj,
 };
 pub use asc_ptr::AscPtr;
+pub use compile_profile::CompileProfile;
 
 use anyhow::Error;
 use semver::Version;
@@ -367,6 +369,18 @@ pub enum IndexForAscTypeId {
     //    name and implementation before running this script.
     // 2. Replace `3500` part with the first number of that blockchain's reserved discriminant space.
     // 3. Insert the output right before the end of this block.
+
+    // Reserved discriminant space for chain-agnostic type IDs: [4,500, 5,499]
+    //
+    // These are used by host exports that all chains share. The `TypeId`
+    // enum in graph-ts must use the same values.
+    ArrayTypedMapStringStoreValue = 4500,
+    RelatedEntityPage = 4501,
+    // Continue to add more chain-agnostic type IDs here.
+    // e.g.:
+    // NextChainAgnosticType = 4502,
+    // ...
+    // LastChainAgnosticType = 5499,
     UnitTestNetworkUnitTestTypeU32 = u32::MAX - 7,
     UnitTestNetworkUnitTestTypeU32Array = u32::MAX - 6,
 
@@ -11,3 +11,16 @@ where
         &mut *(dst as *mut [MaybeUninit<T>] as *mut [T])
     }
 }
+
+/// The resident memory of this process in bytes, read from
+/// `/proc/self/status`. `None` if that is not available, e.g., when not
+/// running on Linux
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rss| rss.trim().strip_suffix("kB"))
+        .and_then(|kb| kb.trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
@@ -29,3 +29,6 @@ pub mod mem;
 
 /// Data structures instrumented with Prometheus metrics.
 pub mod monitored;
+
+/// HTTP and SOCKS5 proxies for outbound connections.
+pub mod proxy;
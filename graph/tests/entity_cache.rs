@@ -1,16 +1,26 @@
 use async_trait::async_trait;
 use graph::blockchain::block_stream::FirehoseCursor;
 use graph::blockchain::BlockPtr;
+use graph::components::subgraph::handler_entity_types::HandlerWrite;
+use graph::data::subgraph::fingerprint::CompatibilityFingerprint;
+use graph::data::subgraph::invariant::Invariant;
+use graph::data::subgraph::retention::EntityRetention;
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::data::subgraph::status::{self, PoiDivergence};
+use graph::data::value::Word;
 use graph::data_source::CausalityRegion;
-use graph::prelude::{Schema, StopwatchMetrics, StoreError, UnfailOutcome};
+use graph::prelude::{BlockNumber, Schema, StopwatchMetrics, StoreError, UnfailOutcome};
+use graph::runtime::CompileProfile;
 use lazy_static::lazy_static;
 use slog::Logger;
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::Duration;
 
+use graph::components::metrics::usage::Usage;
 use graph::components::store::{
-    EntityKey, EntityType, ReadStore, StoredDynamicDataSource, WritableStore,
+    EntityKey, EntityType, PendingSyncedActions, ReadStore, RelatedEntityQuery,
+    StoredDynamicDataSource, WritableStore,
 };
 use graph::{
     components::store::{DeploymentId, DeploymentLocator},
@@ -59,6 +69,23 @@ impl ReadStore for MockStore {
         Ok(self.get_many_res.clone())
     }
 
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        Ok(self
+            .get_many_res
+            .iter()
+            .filter(|(key, entity)| {
+                query.matches(key, entity) && RelatedEntityQuery::is_after(key, after)
+            })
+            .take(limit)
+            .map(|(key, entity)| (key.clone(), entity.clone()))
+            .collect())
+    }
+
     fn input_schema(&self) -> Arc<Schema> {
         SCHEMA.clone()
     }
@@ -78,6 +105,78 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
+    async fn record_node_version(&self, _: String) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn check_compatibility(
+        &self,
+        _: CompatibilityFingerprint,
+    ) -> Result<Option<CompatibilityFingerprint>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn compile_profile(&self) -> Result<Option<CompileProfile>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_compilation(&self, _: CompileProfile, _: Duration) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn reorg_threshold(&self) -> Result<Option<BlockNumber>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn start_entity_retention(&self) -> Result<Vec<EntityRetention>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_usage(&self, _: Usage) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_handler_stats(
+        &self,
+        _: Vec<status::HandlerStats>,
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        unimplemented!()
+    }
+
+    async fn handler_stats(&self) -> Result<status::HandlerStatsReport, StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_handler_entity_types(&self, _: Vec<HandlerWrite>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_bus_backends(&self, _: Vec<String>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn bus_critical_entity_types(&self) -> Result<BTreeSet<EntityType>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_bus_critical_delivery(
+        &self,
+        _: String,
+        _: BTreeSet<EntityType>,
+        _: BlockNumber,
+        _: bool,
+    ) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError> {
+        unimplemented!()
+    }
+
+    async fn record_poi_divergence(&self, _: Option<PoiDivergence>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn public_proof_of_indexing(&self, _: BlockPtr) -> Result<Option<[u8; 32]>, StoreError> {
+        unimplemented!()
+    }
+
     async fn revert_block_operations(
         &self,
         _: BlockPtr,
@@ -116,6 +215,8 @@ impl WritableStore for MockStore {
         _: Vec<SubgraphError>,
         _: Vec<(u32, String)>,
         _: Vec<StoredDynamicDataSource>,
+        _: Option<String>,
+        _: Vec<Invariant>,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
@@ -135,7 +236,19 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
-    fn deployment_synced(&self) -> Result<(), StoreError> {
+    fn deployment_synced(&self, _block_ptr: BlockPtr) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn synced_actions_pending(&self) -> Result<Option<PendingSyncedActions>, StoreError> {
+        unimplemented!()
+    }
+
+    fn synced_action_done(&self, _key: String) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn synced_actions_done(&self) -> Result<(), StoreError> {
         unimplemented!()
     }
 
@@ -151,6 +264,10 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
+    async fn reload_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        unimplemented!()
+    }
+
     async fn causality_region_curr_val(&self) -> Result<Option<CausalityRegion>, StoreError> {
         unimplemented!()
     }
@@ -349,3 +466,70 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn load_related_page() {
+    let label = |id: &'static str, label: &str| {
+        make_band(
+            id,
+            vec![
+                ("id", id.into()),
+                ("name", id.into()),
+                ("label", label.into()),
+            ],
+        )
+    };
+    let store = {
+        let entities = vec![
+            label("mogwai", "Rock Action Records").1,
+            label("sigurros", "FatCat Records").1,
+            label("explosions", "Rock Action Records").1,
+            label("arab", "Rock Action Records").1,
+        ];
+        MockStore::new(entity_version_map("Band", entities))
+    };
+    let mut cache = EntityCache::new(Arc::new(store));
+
+    // Changes in the cache make entities match or stop matching
+    let (key, data) = make_band("sigurros", vec![("label", "Rock Action Records".into())]);
+    cache.set(key, data).unwrap();
+    let (key, data) = make_band("mogwai", vec![("label", "Chemikal Underground".into())]);
+    cache.set(key, data).unwrap();
+    cache.remove(label("explosions", "").0);
+    let (key, data) = label("dirty", "Rock Action Records");
+    cache.set(key, data).unwrap();
+
+    let query = RelatedEntityQuery {
+        entity_type: EntityType::new("Band".to_string()),
+        field: "label".to_string(),
+        value: "Rock Action Records".into(),
+        causality_region: CausalityRegion::ONCHAIN,
+    };
+    let changes = cache.related_changes(&query).unwrap();
+    let page = |after: Option<&str>, limit: usize| -> Vec<String> {
+        let after = after.map(Word::from);
+        cache
+            .load_related_page(&query, &changes, after.as_ref(), limit)
+            .unwrap()
+            .into_keys()
+            .map(|key| key.entity_id.to_string())
+            .collect()
+    };
+    assert_eq!(vec!["arab", "dirty", "sigurros"], page(None, 10));
+    assert_eq!(vec!["arab"], page(None, 1));
+    // The store returns `explosions` and `mogwai` for the second page, but
+    // the changes remove both from the result
+    assert_eq!(vec!["dirty", "sigurros"], page(Some("arab"), 2));
+    assert_eq!(vec!["sigurros"], page(Some("dirty"), 2));
+    assert!(page(Some("sigurros"), 2).is_empty());
+
+    // Pages are read with the changes as they were when they were taken
+    let (key, data) = label("zappa", "Rock Action Records");
+    cache.set(key, data).unwrap();
+    let related = cache.load_related_page(&query, &changes, None, 10).unwrap();
+    assert_eq!(3, related.len());
+    assert_eq!(
+        Some(&Value::from("sigurros")),
+        related.values().last().unwrap().get("name")
+    );
+}
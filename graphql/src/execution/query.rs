@@ -136,6 +136,43 @@ pub struct Query {
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
     pub query_id: String,
+
+    /// The block number from the `@minBlock(number: ..)` directive of the
+    /// query. The query fails unless the deployment has indexed at least
+    /// up to that block
+    pub min_block: Option<BlockNumber>,
+}
+
+/// Get the block number from the `@minBlock` directive in `directives`,
+/// resolving variable references with `variables`
+fn min_block(
+    directives: &[q::Directive],
+    variables: &HashMap<String, r::Value>,
+) -> Result<Option<BlockNumber>, Vec<QueryExecutionError>> {
+    let dir = match directives.iter().find(|dir| dir.name == "minBlock") {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let arg = dir
+        .arguments
+        .iter()
+        .find(|(name, _)| name == "number")
+        .map(|(_, value)| value);
+    let value = match arg {
+        Some(q::Value::Variable(var)) => variables.get(var).cloned(),
+        Some(q::Value::Int(num)) => num.as_i64().map(r::Value::Int),
+        Some(_) | None => None,
+    };
+    match value {
+        Some(r::Value::Int(number)) if (0..=BlockNumber::MAX as i64).contains(&number) => {
+            Ok(Some(number as BlockNumber))
+        }
+        _ => Err(vec![QueryExecutionError::InvalidArgumentError(
+            dir.position,
+            "minBlock.number".to_string(),
+            arg.cloned().unwrap_or(q::Value::Null),
+        )]),
+    }
 }
 
 fn validate_query(
@@ -225,6 +262,12 @@ impl Query {
         let operation = operation.ok_or(QueryExecutionError::OperationNameRequired)?;
 
         let variables = coerce_variables(schema.as_ref(), &operation, query.variables)?;
+        let min_block = match &operation {
+            q::OperationDefinition::Query(q::Query { directives, .. }) => {
+                min_block(directives, &variables)?
+            }
+            _ => None,
+        };
         let (kind, selection_set) = match operation {
             q::OperationDefinition::Query(q::Query { selection_set, .. }) => {
                 (Kind::Query, selection_set)
@@ -275,6 +318,7 @@ impl Query {
             query_text: query.query_text.cheap_clone(),
             variables_text: query.variables_text.cheap_clone(),
             query_id,
+            min_block,
         };
 
         Ok(Arc::new(query))
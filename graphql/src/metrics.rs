@@ -3,6 +3,7 @@ use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 
+use graph::components::metrics::query_rate;
 use graph::data::query::QueryResults;
 use graph::prelude::{DeploymentHash, GraphQLMetrics as GraphQLMetricsTrait, MetricsRegistry};
 use graph::prometheus::{CounterVec, Gauge, Histogram, HistogramVec};
@@ -42,6 +43,9 @@ impl GraphQLMetricsTrait for GraphQLMetrics {
         self.query_execution_time
             .with_label_values(&[id, status])
             .observe(duration.as_secs_f64());
+        if let Some(deployment) = results.deployment_hash() {
+            query_rate::record_query(deployment.as_str());
+        }
     }
 
     fn observe_query_parsing(&self, duration: Duration, results: &QueryResults) {
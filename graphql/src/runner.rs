@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crate::metrics::GraphQLMetrics;
 use crate::prelude::{QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions};
@@ -16,7 +16,7 @@ use graph::{
 };
 use graph::{data::graphql::effort::LoadManager, prelude::QueryStoreManager};
 use graph::{
-    data::query::{QueryResults, QueryTarget},
+    data::query::{IndexingProgress, QueryResults, QueryTarget},
     prelude::QueryStore,
 };
 
@@ -89,6 +89,44 @@ where
         Ok(())
     }
 
+    /// How far the deployment has indexed, for reporting in the response.
+    /// `latest_ts` is the timestamp of the latest block of the deployment
+    /// if the caller already knows it. This is best effort, and fields
+    /// that can not be determined are left empty
+    async fn indexing_progress(
+        &self,
+        store: &dyn QueryStore,
+        state: &DeploymentState,
+        latest_ts: Option<u64>,
+    ) -> IndexingProgress {
+        let head_block = store
+            .chain_head_ptr()
+            .await
+            .ok()
+            .flatten()
+            .map(|ptr| ptr.number);
+        let latest_ts = match latest_ts {
+            Some(ts) => Some(ts),
+            None => store
+                .block_number_with_timestamp(&state.latest_block.hash)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|(_, ts)| ts),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+        IndexingProgress {
+            latest_block: state.latest_block.number,
+            earliest_block: state.earliest_block_number,
+            head_block,
+            lag_seconds: latest_ts.map(|ts| now.saturating_sub(ts)),
+            health: state.health.as_str(),
+        }
+    }
+
     async fn execute(
         &self,
         query: Query,
@@ -124,6 +162,7 @@ where
 
         let max_depth = max_depth.unwrap_or(ENV_VARS.graphql.max_depth);
         let trace = query.trace;
+        let indexing_progress = query.indexing_progress;
         let query = crate::execution::Query::new(
             &self.logger,
             schema,
@@ -133,6 +172,22 @@ where
             max_depth,
             metrics.cheap_clone(),
         )?;
+
+        // Fail before doing any work if the deployment is not far enough
+        if let Some(min_block) = query.min_block {
+            if state.latest_block.number < min_block {
+                let mut result = QueryResults::from(QueryExecutionError::MinBlockNotReached(
+                    state.id.to_string(),
+                    state.latest_block.number,
+                    min_block,
+                ));
+                if indexing_progress {
+                    let progress = self.indexing_progress(store.as_ref(), &state, None).await;
+                    result.set_indexing_progress(progress);
+                }
+                return Err(result);
+            }
+        }
         self.load_manager
             .decide(
                 &store.wait_stats().map_err(QueryExecutionError::from)?,
@@ -142,6 +197,7 @@ where
             .to_result()?;
         let by_block_constraint = query.block_constraint()?;
         let mut max_block = 0;
+        let mut latest_ts = None;
         let mut result: QueryResults = QueryResults::empty();
 
         // Note: This will always iterate at least once.
@@ -159,6 +215,11 @@ where
             )
             .await?;
             max_block = max_block.max(resolver.block_number());
+            if let Some(ptr) = &resolver.block_ptr {
+                if ptr.ptr == state.latest_block {
+                    latest_ts = ptr.timestamp;
+                }
+            }
             let query_res = execute_query(
                 query.clone(),
                 Some(selection_set),
@@ -178,6 +239,12 @@ where
         }
 
         query.log_execution(max_block);
+        if indexing_progress {
+            let progress = self
+                .indexing_progress(store.as_ref(), &state, latest_ts)
+                .await;
+            result.set_indexing_progress(progress);
+        }
         self.deployment_changed(store.as_ref(), state, max_block as u64)
             .await
             .map_err(QueryResults::from)
@@ -134,6 +134,13 @@ impl StoreResolver {
             state: &DeploymentState,
             block: BlockNumber,
         ) -> Result<(), QueryExecutionError> {
+            if state.block_pruned(block) {
+                return Err(QueryExecutionError::HistoryPruned(
+                    state.id.to_string(),
+                    state.earliest_block_number,
+                    block,
+                ));
+            }
             state
                 .block_queryable(block)
                 .map_err(|msg| QueryExecutionError::ValueParseError("block.number".to_owned(), msg))
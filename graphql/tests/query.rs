@@ -18,7 +18,7 @@ use std::{
 use graph::{
     components::store::DeploymentLocator,
     data::graphql::{object, object_value},
-    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::{SubgraphError, SubgraphErrorCode},
     data::{
         query::{QueryResults, QueryTarget},
         subgraph::SubgraphFeature,
@@ -130,6 +130,9 @@ async fn setup(
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            retention: vec![],
+            invariants: vec![],
+            reorg_threshold: None,
             chain: PhantomData,
         };
 
@@ -2220,6 +2223,93 @@ fn query_detects_reorg() {
     })
 }
 
+#[test]
+fn query_min_block() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(
+            store.as_ref(),
+            "graphqlQueryMinBlock",
+            BTreeSet::new(),
+            IdType::String,
+        )
+        .await;
+
+        // The deployment is at block 1
+        let result = execute_query(
+            &deployment,
+            "query @minBlock(number: 1) { musician(id: \"m1\") { id } }",
+        )
+        .await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(musician: object!(id: "m1")))
+        );
+
+        let result = execute_query(
+            &deployment,
+            "query @minBlock(number: 2) { musician(id: \"m1\") { id } }",
+        )
+        .await;
+        match &result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::MinBlockNotReached(_, 1, 2)) => {
+                /* expected */
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    })
+}
+
+#[test]
+fn query_indexing_progress() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(
+            store.as_ref(),
+            "graphqlQueryIndexingProgress",
+            BTreeSet::new(),
+            IdType::String,
+        )
+        .await;
+
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let run = |indexing_progress: bool| {
+            let runner = runner.clone();
+            let target = QueryTarget::Deployment(deployment.hash.clone(), Default::default());
+            let query = graphql_parser::parse_query("query { musician(id: \"m1\") { id } }")
+                .unwrap()
+                .into_static();
+            let mut query = Query::new(query, None, false);
+            query.indexing_progress = indexing_progress;
+            async move {
+                runner
+                    .run_query_with_complexity(query, target, None, None, None, None)
+                    .await
+            }
+        };
+
+        // The progress is only reported when the client asks for it
+        let result = run(false).await;
+        assert_eq!(None, result.indexing_progress());
+
+        let result = run(true).await;
+        assert!(!result.has_errors());
+        let progress = result.indexing_progress().unwrap();
+        assert_eq!(1, progress.latest_block);
+        assert_eq!(0, progress.earliest_block);
+        assert_eq!("healthy", progress.health);
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["extensions"]["indexing"]["latestBlock"], 1);
+        assert_eq!(json["extensions"]["indexing"]["earliestBlock"], 0);
+        assert_eq!(json["extensions"]["indexing"]["health"], "healthy");
+    })
+}
+
 #[test]
 fn can_query_meta() {
     // metadata for the latest block (block 1)
@@ -2300,6 +2390,8 @@ fn non_fatal_errors() {
             block_ptr: Some(BLOCK_TWO.block_ptr()),
             handler: Some("handleMoo".to_string()),
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         transact_errors(&*STORE, &deployment, BLOCK_TWO.block_ptr(), vec![err])
@@ -2406,6 +2498,8 @@ fn deterministic_error() {
             block_ptr: Some(BLOCK_TWO.block_ptr()),
             handler: Some("handleMoo".to_string()),
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         transact_errors(&*STORE, &deployment, BLOCK_TWO.block_ptr(), vec![err])
@@ -6,7 +6,7 @@ use graph::{
     log::logger,
     prelude::{
         anyhow::{self, Context as AnyhowContextTrait},
-        info, o, slog, tokio, Logger, NodeId, ENV_VARS,
+        info, o, slog, tokio, BlockNumber, Logger, NodeId, ENV_VARS,
     },
     url::Url,
 };
@@ -16,6 +16,7 @@ use graph_graphql::prelude::GraphQlRunner;
 use graph_node::config::{self, Config as Cfg};
 use graph_node::manager::color::Terminal;
 use graph_node::manager::commands;
+use graph_node::manager::output::{self, OutputFormat};
 use graph_node::{
     chain::create_all_ethereum_networks,
     manager::{deployment::DeploymentSearch, PanicSubscriptionManager},
@@ -52,6 +53,13 @@ pub struct Opt {
         help = "whether to colorize the output. Set to 'auto' to colorize only on\nterminals (the default), 'always' to always colorize, or 'never'\nto not colorize at all"
     )]
     pub color: String,
+    #[clap(
+        long,
+        default_value = "text",
+        value_name = "FORMAT",
+        help = "how to print results. Set to 'text' for people (the default) or\n'json' to print a single JSON object for scripts. Only some\ncommands support 'json'; see docs/graphman.md\n"
+    )]
+    pub output: OutputFormat,
     #[clap(
         long,
         short,
@@ -146,6 +154,15 @@ pub enum Command {
         /// The deployment (see `help info`)
         deployment: DeploymentSearch,
     },
+    /// Start a deployment on the node it is assigned to right away
+    ///
+    /// A node that starts its deployments in safe mode starts them in
+    /// waves; this starts the deployment without waiting for its turn,
+    /// and also starts it if the node stopped the ramp-up
+    ForceStart {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
     /// Rewind a subgraph to a specific block
     Rewind {
         /// Force rewinding even if the block hash is not found in the local
@@ -245,6 +262,85 @@ pub enum Command {
     #[clap(subcommand)]
     Database(DatabaseCommand),
 
+    /// Report how much shared infrastructure deployments used
+    #[clap(subcommand)]
+    Usage(UsageCommand),
+
+    /// Inspect and acknowledge changes to compatibility fingerprints
+    ///
+    /// The compatibility fingerprint of a deployment covers the settings
+    /// of graph-node that affect the results of indexing. With
+    /// `GRAPH_STRICT_COMPATIBILITY` set, deployments whose fingerprint
+    /// changed since they were first started fail until the change is
+    /// acknowledged.
+    #[clap(subcommand)]
+    Compat(CompatCommand),
+
+    /// Inspect and change how the WASM modules of a deployment are compiled
+    ///
+    /// With the `fast-compile` profile, modules compile quickly without
+    /// optional optimizations; with `optimized`, compiling takes longer
+    /// but handlers run faster. Deployments without a profile use
+    /// `GRAPH_WASM_COMPILE_PROFILE`. The profile never changes the results
+    /// of handlers, but it changes how much stack they use: a deeply
+    /// recursive handler can exceed `GRAPH_RUNTIME_MAX_STACK_SIZE` with
+    /// one profile and not with the other, and is then retried until the
+    /// stack size or the profile is changed.
+    #[clap(subcommand)]
+    CompileProfile(CompileProfileCommand),
+
+    /// Inspect and reset the cumulative statistics of the handlers of a
+    /// deployment
+    ///
+    /// The statistics count invocations, execution time, gas, and errors
+    /// of each handler. They survive restarts and are kept until they are
+    /// reset.
+    #[clap(subcommand)]
+    HandlerStats(HandlerStatsCommand),
+
+    /// Inspect and change the reorg threshold of a deployment
+    ///
+    /// A deployment can lower the reorg threshold of its chain so that
+    /// pruning and the block stream treat blocks as final sooner, but
+    /// never below what `GRAPH_ETHEREUM_MIN_REORG_THRESHOLD` allows for
+    /// the chain. If a reorg turns out to be deeper than the threshold of
+    /// the deployment, the deployment goes back to the threshold of its
+    /// chain.
+    #[clap(subcommand)]
+    ReorgThreshold(ReorgThresholdCommand),
+
+    /// Inspect and change the critical entity types of a deployment
+    ///
+    /// Messages with changes to critical entity types always wait for the
+    /// bus to confirm them and are retried more often than others. The
+    /// indexing status reports up to which block the changes to each
+    /// critical type were delivered; a message that could not be
+    /// delivered holds that block back until it is acknowledged.
+    #[clap(subcommand)]
+    BusCritical(BusCriticalCommand),
+
+    /// Inspect and change the labels of a deployment
+    ///
+    /// Labels are names that tools which route queries can look up to find
+    /// deployments. The `label` action for synced deployments adds a label
+    /// when a deployment first reaches the chain head.
+    #[clap(subcommand)]
+    Label(LabelCommand),
+
+    /// Inspect and change the retention policies of a deployment
+    ///
+    /// A retention policy declares that rows of an entity type are only
+    /// needed for a while, either until a timestamp field is older than a
+    /// maximum age or for a number of blocks. The node removes older rows
+    /// in the background. Policies are declared in the `retention`
+    /// section of the manifest and can be changed here.
+    #[clap(subcommand)]
+    Retention(RetentionCommand),
+
+    /// Work with the triggers of a deployment
+    #[clap(subcommand)]
+    Triggers(TriggersCommand),
+
     /// Delete a deployment and all it's indexed data
     ///
     /// The deployment can be specified as either a subgraph name, an IPFS
@@ -302,7 +398,13 @@ pub enum UnusedCommand {
         /// Remove unused deployments that were recorded at least this many minutes ago
         #[clap(short, long)]
         older: Option<u32>,
+        /// Remove all data of each deployment at once instead of one table
+        /// at a time. This is only advisable for small deployments
+        #[clap(long)]
+        fast: bool,
     },
+    /// List deployments whose removal has not finished yet
+    Removals,
 }
 
 #[derive(Clone, Debug, Subcommand)]
@@ -461,6 +563,218 @@ pub enum CallCacheCommand {
     },
 }
 
+#[derive(Clone, Debug, Subcommand)]
+pub enum CompileProfileCommand {
+    /// Show the profile of the deployment and how long compiling its
+    /// modules took when it was last started
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Set the profile that is used the next time the deployment is
+    /// started
+    Set {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// One of `fast-compile`, `optimized`, or `default` to use the
+        /// default of the node that runs the deployment
+        profile: String,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum HandlerStatsCommand {
+    /// Show the statistics of the handlers of the deployment as of the
+    /// last time they were written
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Remove the statistics of the handlers of the deployment so that
+    /// they start over from zero
+    Reset {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ReorgThresholdCommand {
+    /// Show the reorg threshold of the deployment and how far it can be
+    /// lowered
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Set the reorg threshold of the deployment
+    Set {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The number of blocks, or `default` to use the reorg threshold
+        /// of the chain
+        threshold: String,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum BusCriticalCommand {
+    /// Show the critical entity types of the deployment
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Set the critical entity types of the deployment, replacing the
+    /// ones that were set before
+    Set {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The entity types; none to make no entity type critical
+        entity_types: Vec<String>,
+    },
+    /// Acknowledge the messages with critical changes that could not be
+    /// delivered so that the delivered block of their types advances again
+    Acknowledge {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// Only acknowledge the messages for this bus backend
+        #[clap(long)]
+        backend: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum LabelCommand {
+    /// List the labels of the deployment
+    List {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Add a label to the deployment
+    Add {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The label
+        label: String,
+    },
+    /// Remove a label from the deployment
+    Remove {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The label
+        label: String,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum RetentionCommand {
+    /// Show the retention policies of the deployment
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Set the retention policy of an entity type, replacing the policy
+    /// from the manifest
+    Set {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The entity type
+        entity_type: String,
+        /// Remove rows whose timestamp field is more than this older than
+        /// the newest timestamp of the entity type, like `30d`, `12h`, or
+        /// `90m`
+        #[clap(long, requires = "timestamp-field", conflicts_with = "max-blocks")]
+        max_age: Option<String>,
+        /// The `Int` or `BigInt` field with the timestamp of a row in
+        /// seconds since the epoch
+        #[clap(long)]
+        timestamp_field: Option<String>,
+        /// Remove rows that were written more than this many blocks
+        /// before the latest block
+        #[clap(long)]
+        max_blocks: Option<BlockNumber>,
+    },
+    /// Remove the retention policy of an entity type so that its rows are
+    /// kept
+    Remove {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// The entity type
+        entity_type: String,
+    },
+    /// Apply the retention policies of the deployment now
+    Prune {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+        /// Only show how many rows would be removed
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum TriggersCommand {
+    /// Write the triggers that the data sources of a deployment match in a
+    /// range of blocks to a file
+    ///
+    /// The file contains the decoded triggers and the handlers they go to,
+    /// and can be used with `graph-node test-run` to run mappings against
+    /// them without a node. Only Ethereum deployments are supported and
+    /// offchain data sources are left out.
+    Export {
+        /// The first block to export
+        #[clap(long)]
+        from: BlockNumber,
+        /// The last block to export
+        #[clap(long)]
+        to: BlockNumber,
+        /// The file to write the triggers to
+        #[clap(long, short)]
+        out: String,
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum CompatCommand {
+    /// Show the recorded fingerprint and how the current one differs
+    Show {
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+    /// Accept the changed fingerprint and restart the deployment
+    Ack {
+        /// A note to record with the acknowledgment, e.g., why the change
+        /// is safe
+        #[clap(long, short)]
+        note: Option<String>,
+        /// The deployment (see `help info`).
+        deployment: DeploymentSearch,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum UsageCommand {
+    /// Print the usage of shared infrastructure per deployment
+    ///
+    /// Usage is recorded in hourly buckets; the report contains all buckets
+    /// that start in the interval `[from, to)`. Timestamps can be given in
+    /// RFC 3339 format or as dates `YYYY-MM-DD`
+    Report {
+        /// The start of the interval
+        #[clap(long, parse(try_from_str = commands::usage::parse_timestamp))]
+        from: chrono::DateTime<chrono::Utc>,
+        /// The end of the interval (exclusive)
+        #[clap(long, parse(try_from_str = commands::usage::parse_timestamp))]
+        to: chrono::DateTime<chrono::Utc>,
+        /// The output format
+        #[clap(long, default_value = "csv", possible_values = &["csv", "json"])]
+        format: String,
+        /// The IPFS hashes of the deployments to report on; all if omitted
+        deployments: Vec<String>,
+    },
+}
+
 #[derive(Clone, Debug, Subcommand)]
 pub enum StatsCommand {
     /// Toggle whether a table is account-like
@@ -891,6 +1205,10 @@ async fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
 
     Terminal::set_color_preference(&opt.color);
+    OutputFormat::set(opt.output);
+    if opt.output == OutputFormat::Json {
+        output::report_panics_as_json();
+    }
 
     let version_label = opt.version_label.clone();
     // Set up logger
@@ -906,7 +1224,10 @@ async fn main() -> anyhow::Result<()> {
         render_testament!(TESTAMENT)
     );
 
-    let mut config = Cfg::load(&logger, &opt.clone().into()).context("Configuration error")?;
+    let mut config = match Cfg::load(&logger, &opt.clone().into()).context("Configuration error") {
+        Ok(config) => config,
+        Err(e) => return output::finish(Err(e)),
+    };
 
     if opt.pool_size > 0 && !opt.cmd.use_configured_pool_size() {
         // Override pool size from configuration
@@ -958,7 +1279,7 @@ async fn main() -> anyhow::Result<()> {
     );
 
     use Command::*;
-    match opt.cmd {
+    let result = match opt.cmd {
         TxnSpeed { delay } => commands::txn_speed::run(ctx.primary_pool(), delay),
         Info {
             deployment,
@@ -986,11 +1307,19 @@ async fn main() -> anyhow::Result<()> {
                     count,
                     deployment,
                     older,
+                    fast,
                 } => {
                     let count = count.unwrap_or(1_000_000);
                     let older = older.map(|older| chrono::Duration::minutes(older as i64));
-                    commands::unused_deployments::remove(store, count, deployment.as_deref(), older)
+                    commands::unused_deployments::remove(
+                        store,
+                        count,
+                        deployment.as_deref(),
+                        older,
+                        fast,
+                    )
                 }
+                Removals => commands::unused_deployments::removals(store),
             }
         }
         Config(cmd) => {
@@ -1020,6 +1349,10 @@ async fn main() -> anyhow::Result<()> {
             let sender = ctx.notification_sender();
             commands::assign::reassign(ctx.primary_pool(), &sender, &deployment, node)
         }
+        ForceStart { deployment } => {
+            let sender = ctx.notification_sender();
+            commands::assign::force_start(ctx.primary_pool(), &sender, &deployment)
+        }
         Rewind {
             force,
             sleep,
@@ -1298,9 +1631,8 @@ async fn main() -> anyhow::Result<()> {
             match cmd {
                 DatabaseCommand::Migrate => {
                     /* creating the store builder runs migrations */
-                    let _store_builder = ctx.store_builder().await;
-                    println!("All database migrations have been applied");
-                    Ok(())
+                    let store_builder = ctx.store_builder().await;
+                    commands::database::migrate(&store_builder.coord)
                 }
                 DatabaseCommand::Remap {
                     source,
@@ -1312,6 +1644,209 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
+        Compat(cmd) => {
+            use CompatCommand::*;
+            match cmd {
+                Show { deployment } => {
+                    let (store, primary_pool) = ctx.store_and_primary();
+                    commands::compat::show(store.subgraph_store(), primary_pool, &deployment)
+                }
+                Ack { note, deployment } => {
+                    let sender = ctx.notification_sender();
+                    let (store, primary_pool) = ctx.store_and_primary();
+                    commands::compat::ack(
+                        store.subgraph_store(),
+                        primary_pool,
+                        &sender,
+                        &deployment,
+                        note,
+                    )
+                }
+            }
+        }
+        CompileProfile(cmd) => {
+            use CompileProfileCommand::*;
+            let (store, primary_pool) = ctx.store_and_primary();
+            match cmd {
+                Show { deployment } => commands::compile_profile::show(
+                    store.subgraph_store(),
+                    primary_pool,
+                    &deployment,
+                ),
+                Set {
+                    deployment,
+                    profile,
+                } => commands::compile_profile::set(
+                    store.subgraph_store(),
+                    primary_pool,
+                    &deployment,
+                    &profile,
+                ),
+            }
+        }
+        HandlerStats(cmd) => {
+            use HandlerStatsCommand::*;
+            let (store, primary_pool) = ctx.store_and_primary();
+            match cmd {
+                Show { deployment } => {
+                    commands::handler_stats::show(store.subgraph_store(), primary_pool, &deployment)
+                }
+                Reset { deployment } => commands::handler_stats::reset(
+                    store.subgraph_store(),
+                    primary_pool,
+                    &deployment,
+                ),
+            }
+        }
+        ReorgThreshold(cmd) => {
+            use ReorgThresholdCommand::*;
+            let (store, primary_pool) = ctx.store_and_primary();
+            match cmd {
+                Show { deployment } => commands::reorg_threshold::show(
+                    store.subgraph_store(),
+                    primary_pool,
+                    &deployment,
+                ),
+                Set {
+                    deployment,
+                    threshold,
+                } => commands::reorg_threshold::set(
+                    store.subgraph_store(),
+                    primary_pool,
+                    &deployment,
+                    &threshold,
+                ),
+            }
+        }
+        BusCritical(cmd) => {
+            use BusCriticalCommand::*;
+            let (store, primary_pool) = ctx.store_and_primary();
+            let subgraph_store = store.subgraph_store();
+            match cmd {
+                Show { deployment } => {
+                    commands::bus_critical::show(subgraph_store, primary_pool, &deployment)
+                }
+                Set {
+                    deployment,
+                    entity_types,
+                } => commands::bus_critical::set(
+                    subgraph_store,
+                    primary_pool,
+                    &deployment,
+                    entity_types,
+                ),
+                Acknowledge {
+                    deployment,
+                    backend,
+                } => commands::bus_critical::acknowledge(
+                    subgraph_store,
+                    primary_pool,
+                    &deployment,
+                    backend,
+                ),
+            }
+        }
+        Label(cmd) => {
+            use LabelCommand::*;
+            let (store, primary_pool) = ctx.store_and_primary();
+            let subgraph_store = store.subgraph_store();
+            match cmd {
+                List { deployment } => {
+                    commands::label::list(subgraph_store, primary_pool, &deployment)
+                }
+                Add { deployment, label } => {
+                    commands::label::add(subgraph_store, primary_pool, &deployment, label)
+                }
+                Remove { deployment, label } => {
+                    commands::label::remove(subgraph_store, primary_pool, &deployment, label)
+                }
+            }
+        }
+        Retention(cmd) => {
+            use RetentionCommand::*;
+            let logger = ctx.logger.clone();
+            let (store, primary_pool) = ctx.store_and_primary();
+            let subgraph_store = store.subgraph_store();
+            match cmd {
+                Show { deployment } => {
+                    commands::retention::show(subgraph_store, primary_pool, &deployment)
+                }
+                Set {
+                    deployment,
+                    entity_type,
+                    max_age,
+                    timestamp_field,
+                    max_blocks,
+                } => commands::retention::set(
+                    subgraph_store,
+                    primary_pool,
+                    &deployment,
+                    entity_type,
+                    max_age,
+                    timestamp_field,
+                    max_blocks,
+                ),
+                Remove {
+                    deployment,
+                    entity_type,
+                } => commands::retention::remove(
+                    subgraph_store,
+                    primary_pool,
+                    &deployment,
+                    &entity_type,
+                ),
+                Prune {
+                    deployment,
+                    dry_run,
+                } => {
+                    commands::retention::prune(
+                        &logger,
+                        subgraph_store,
+                        primary_pool,
+                        &deployment,
+                        dry_run,
+                    )
+                    .await
+                }
+            }
+        }
+        Triggers(cmd) => {
+            let TriggersCommand::Export {
+                from,
+                to,
+                out,
+                deployment,
+            } = cmd;
+            let logger = ctx.logger.clone();
+            let registry = ctx.metrics_registry();
+            let ipfs_url = ctx.ipfs_url.clone();
+            let networks = ctx.ethereum_networks().await?;
+            let (store, primary) = ctx.store_and_primary();
+            let locator = deployment.locate_unique(&primary)?;
+            let file = commands::triggers::export(
+                &logger,
+                store,
+                networks,
+                registry,
+                ipfs_url,
+                &locator,
+                from..=to,
+            )
+            .await?;
+            file.write(&out)?;
+            output::emit(&commands::triggers::TriggersExported::new(
+                &locator, &file, &out,
+            ))
+        }
+        Usage(cmd) => {
+            let UsageCommand::Report {
+                from,
+                to,
+                format,
+                deployments,
+            } = cmd;
+            commands::usage::report(ctx.store(), deployments, from, to, &format)
+        }
         Prune {
             deployment,
             history,
@@ -1343,7 +1878,8 @@ async fn main() -> anyhow::Result<()> {
             )
             .await
         }
-    }
+    };
+    output::finish(result)
 }
 
 fn parse_duration_in_secs(s: &str) -> Result<Duration, ParseIntError> {
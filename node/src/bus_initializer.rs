@@ -1,13 +1,24 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use bus_google::GooglePubSub;
+use graph::anyhow::{anyhow, Error};
 use graph::components::bus::Bus;
 use graph::components::bus::BusMessage;
+use graph::components::bus::BusMetrics;
+use graph::components::bus::BusRouter;
+use graph::components::bus::CriticalDelivery;
+use graph::prelude::{MetricsRegistry, ENV_VARS};
 use graph::slog::info;
 use graph::slog::warn;
 use graph::tokio::sync::mpsc::unbounded_channel;
 use graph::tokio::sync::mpsc::UnboundedReceiver;
 use graph::tokio::sync::mpsc::UnboundedSender;
+use graph::util::proxy::ProxyTarget;
 use regex::Regex;
 
+use crate::config::BusSection;
+
 pub struct BusInitializer;
 
 pub enum BusScheme {
@@ -15,7 +26,7 @@ pub enum BusScheme {
 }
 
 impl BusInitializer {
-    fn get_bus_scheme(uri: &Option<String>) -> Option<BusScheme> {
+    pub(crate) fn get_bus_scheme(uri: &Option<String>) -> Option<BusScheme> {
         if uri.is_none() {
             return None;
         }
@@ -52,4 +63,51 @@ impl BusInitializer {
             }
         }
     }
+
+    /// Start the backends in `config`, and return the router that decides
+    /// which of them each deployment publishes to
+    pub async fn router(
+        config: &BusSection,
+        logger: graph::slog::Logger,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Result<BusRouter, Error> {
+        let metrics = BusMetrics::new(registry.clone());
+        let mut backends = BTreeMap::new();
+        for (name, backend) in &config.backends {
+            let sender = match BusInitializer::get_bus_scheme(&Some(backend.url.clone())) {
+                Some(BusScheme::GooglePubSub) => {
+                    // The Pub/Sub client opens its own connections, and there
+                    // is no way to send them through a proxy
+                    if let Some(proxy) = ENV_VARS.proxies.proxy(ProxyTarget::Bus) {
+                        return Err(anyhow!(
+                            "bus backend {} uses Google Pub/Sub, which can not connect through \
+                             the proxy {}; set GRAPH_PROXY_BUS=none to connect to it directly",
+                            name,
+                            proxy
+                        ));
+                    }
+                    info!(logger, "Starting GooglePubSub"; "backend" => name);
+                    let bus = GooglePubSub::new(backend.url.clone(), logger.clone())
+                        .await
+                        .with_critical_delivery(CriticalDelivery::new(name, registry.clone()))
+                        .with_bus_metrics(metrics.for_backend(name));
+                    let (sender, receiver) = unbounded_channel();
+                    graph::spawn(async move { bus.start(receiver).await });
+                    sender
+                }
+                None => {
+                    return Err(anyhow!(
+                        "bus backend {} has an unsupported url {}",
+                        name,
+                        backend.url
+                    ))
+                }
+            };
+            backends.insert(name.clone(), sender);
+        }
+        if backends.is_empty() {
+            warn!(logger, "No bus at work";);
+        }
+        BusRouter::new(backends, config.routes(), config.default.clone())
+    }
 }
@@ -12,6 +12,7 @@ use graph::prelude::{anyhow, tokio};
 use graph::prelude::{prost, MetricsRegistry as MetricsRegistryTrait};
 use graph::slog::{debug, error, info, o, Logger};
 use graph::url::Url;
+use graph::util::proxy::ProxyTarget;
 use graph::util::security::SafeDisplay;
 use graph_chain_ethereum::{self as ethereum, EthereumAdapterTrait, Transport};
 use std::collections::{BTreeMap, HashMap};
@@ -450,11 +451,29 @@ pub async fn create_ethereum_networks_for_chain(
         let transport = match web3.transport {
             Rpc => Transport::new_rpc(Url::parse(&web3.url)?, web3.headers.clone()),
             Ipc => Transport::new_ipc(&web3.url).await,
-            Ws => Transport::new_ws(&web3.url).await,
+            Ws => {
+                if let Some(proxy) = graph::env::ENV_VARS.proxies.proxy(ProxyTarget::Providers) {
+                    bail!(
+                        "provider {} uses a WebSocket transport, which can not connect through \
+                         the proxy {}; use an rpc transport or set GRAPH_PROXY_PROVIDERS=none",
+                        provider.label,
+                        proxy
+                    );
+                }
+                Transport::new_ws(&web3.url).await
+            }
         };
 
         let supports_eip_1898 = !web3.features.contains("no_eip1898");
 
+        eth_rpc_metrics.track_provider(
+            &logger,
+            &provider.label,
+            network_name,
+            web3.costs.clone(),
+            web3.budget.clone(),
+        );
+
         parsed_networks.insert(
             network_name.to_string(),
             capabilities,
@@ -472,6 +491,9 @@ pub async fn create_ethereum_networks_for_chain(
             ),
             web3.limit_for(&config.node),
         );
+        for class in &web3.pin {
+            parsed_networks.pin(network_name, *class, &provider.label);
+        }
     }
 
     parsed_networks.sort();
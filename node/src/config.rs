@@ -1,6 +1,7 @@
 use graph::{
     anyhow::Error,
     blockchain::BlockchainKind,
+    components::bus::{BusRoute, DeploymentMatcher},
     firehose::SUBGRAPHS_PER_CONN,
     prelude::{
         anyhow::{anyhow, bail, Context, Result},
@@ -13,14 +14,17 @@ use graph::{
         serde_json, Logger, NodeId, StoreError,
     },
 };
-use graph_chain_ethereum::{self as ethereum, NodeCapabilities};
+use graph_chain_ethereum::{self as ethereum, CallClass, NodeCapabilities, ProviderBudget};
+use graph_core::{SyncedAction, SyncedActionKind};
 use graph_store_postgres::{DeploymentPlacer, Shard as ShardName, PRIMARY_SHARD};
 
+use crate::bus_initializer::BusInitializer;
 use http::{HeaderMap, Uri};
 use std::fs::read_to_string;
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt,
+    time::Duration,
 };
 use url::Url;
 
@@ -74,6 +78,10 @@ pub struct Config {
     pub stores: BTreeMap<String, Shard>,
     pub chains: ChainSection,
     pub deployment: Deployment,
+    #[serde(default)]
+    pub bus: BusSection,
+    #[serde(default)]
+    pub synced: SyncedSection,
 }
 
 fn validate_name(s: &str) -> Result<()> {
@@ -145,6 +153,8 @@ impl Config {
         }
 
         self.chains.validate()?;
+        self.bus.validate()?;
+        self.synced.validate()?;
 
         Ok(())
     }
@@ -153,7 +163,14 @@ impl Config {
     /// a config from the command line arguments in `opt`
     pub fn load(logger: &Logger, opt: &Opt) -> Result<Config> {
         if let Some(config) = &opt.config {
-            Self::from_file(logger, config, &opt.node_id)
+            let mut config = Self::from_file(logger, config, &opt.node_id)?;
+            // Configuration files from before the `[bus]` section existed
+            // keep working with `BUS_URL`
+            if config.bus.backends.is_empty() {
+                config.bus = BusSection::from_opt(opt);
+                config.bus.validate()?;
+            }
+            Ok(config)
         } else {
             info!(
                 logger,
@@ -189,6 +206,8 @@ impl Config {
             stores,
             chains,
             deployment,
+            bus: BusSection::from_opt(opt),
+            synced: SyncedSection::default(),
         })
     }
 
@@ -479,6 +498,9 @@ impl ChainSection {
                         features,
                         headers: Default::default(),
                         rules: Vec::new(),
+                        costs: BTreeMap::new(),
+                        budget: None,
+                        pin: BTreeSet::new(),
                     }),
                 };
                 let entry = chains.entry(name.to_string()).or_insert_with(|| Chain {
@@ -513,6 +535,24 @@ impl Chain {
         for provider in self.providers.iter_mut() {
             provider.validate()?
         }
+
+        let mut pins = BTreeMap::new();
+        for provider in &self.providers {
+            let web3 = match &provider.details {
+                ProviderDetails::Web3(web3) | ProviderDetails::Web3Call(web3) => web3,
+                ProviderDetails::Firehose(_) | ProviderDetails::Substreams(_) => continue,
+            };
+            for class in &web3.pin {
+                if let Some(other) = pins.insert(*class, &provider.label) {
+                    bail!(
+                        "`{}` requests are pinned to both provider {} and provider {}",
+                        class,
+                        other,
+                        provider.label
+                    );
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -625,6 +665,20 @@ pub struct Web3Provider {
 
     #[serde(default, rename = "match")]
     rules: Vec<Web3Rule>,
+
+    /// The units the provider bills for a request, by method. Methods
+    /// that are not listed cost one unit
+    #[serde(default)]
+    pub costs: BTreeMap<String, f64>,
+
+    #[serde(default)]
+    pub budget: Option<ProviderBudget>,
+
+    /// The classes of requests (`logs`, `calls`, `blocks`) that latency
+    /// routing always sends to this provider when it has sufficient
+    /// capabilities
+    #[serde(default)]
+    pub pin: BTreeSet<CallClass>,
 }
 
 impl Web3Provider {
@@ -702,6 +756,27 @@ impl Provider {
                     }
                 }
 
+                if let Some((method, cost)) = web3
+                    .costs
+                    .iter()
+                    .find(|(_, cost)| !cost.is_finite() || **cost < 0.0)
+                {
+                    bail!(
+                        "the cost {} of `{}` for provider {} must be a non-negative number",
+                        cost,
+                        method,
+                        self.label
+                    );
+                }
+                if let Some(budget) = &web3.budget {
+                    if !budget.monthly_units.is_finite() || budget.monthly_units <= 0.0 {
+                        bail!(
+                            "the monthly budget for provider {} must be positive",
+                            self.label
+                        );
+                    }
+                }
+
                 web3.url = shellexpand::env(&web3.url)?.into_owned();
 
                 let label = &self.label;
@@ -746,6 +821,9 @@ impl<'de> Deserialize<'de> for Provider {
                 let mut features = None;
                 let mut headers = None;
                 let mut nodes = Vec::new();
+                let mut costs = None;
+                let mut budget = None;
+                let mut pin = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -790,6 +868,24 @@ impl<'de> Deserialize<'de> for Provider {
                         ProviderField::Match => {
                             nodes = map.next_value()?;
                         }
+                        ProviderField::Costs => {
+                            if costs.is_some() {
+                                return Err(serde::de::Error::duplicate_field("costs"));
+                            }
+                            costs = Some(map.next_value()?);
+                        }
+                        ProviderField::Budget => {
+                            if budget.is_some() {
+                                return Err(serde::de::Error::duplicate_field("budget"));
+                            }
+                            budget = Some(map.next_value()?);
+                        }
+                        ProviderField::Pin => {
+                            if pin.is_some() {
+                                return Err(serde::de::Error::duplicate_field("pin"));
+                            }
+                            pin = Some(map.next_value()?);
+                        }
                     }
                 }
 
@@ -809,7 +905,18 @@ impl<'de> Deserialize<'de> for Provider {
                             | ProviderDetails::Substreams(ref mut firehose) => {
                                 firehose.rules = nodes
                             }
-                            _ => {}
+                            ProviderDetails::Web3(ref mut web3)
+                            | ProviderDetails::Web3Call(ref mut web3) => {
+                                if let Some(costs) = costs {
+                                    web3.costs = costs;
+                                }
+                                if budget.is_some() {
+                                    web3.budget = budget;
+                                }
+                                if let Some(pin) = pin {
+                                    web3.pin = pin;
+                                }
+                            }
                         }
 
                         v
@@ -821,6 +928,9 @@ impl<'de> Deserialize<'de> for Provider {
                             .ok_or_else(|| serde::de::Error::missing_field("features"))?,
                         headers: headers.unwrap_or_else(|| HeaderMap::new()),
                         rules: nodes,
+                        costs: costs.unwrap_or_default(),
+                        budget,
+                        pin: pin.unwrap_or_default(),
                     }),
                 };
 
@@ -835,6 +945,9 @@ impl<'de> Deserialize<'de> for Provider {
             "url",
             "features",
             "headers",
+            "costs",
+            "budget",
+            "pin",
         ];
         deserializer.deserialize_struct("Provider", FIELDS, ProviderVisitor)
     }
@@ -846,6 +959,9 @@ enum ProviderField {
     Label,
     Details,
     Match,
+    Costs,
+    Budget,
+    Pin,
 
     // Deprecated fields
     Url,
@@ -1048,6 +1164,210 @@ impl NetworkPredicate {
     }
 }
 
+/// The bus backends that deployments can publish to, and which deployments
+/// publish to which backends
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BusSection {
+    #[serde(default, rename = "backend")]
+    pub backends: BTreeMap<String, BusBackend>,
+    #[serde(default, rename = "route")]
+    routes: Vec<BusRouteRule>,
+    /// The backends for deployments that no route matches; if this is
+    /// empty, those deployments do not publish to the bus
+    #[serde(default)]
+    pub default: Vec<String>,
+}
+
+impl BusSection {
+    fn validate(&self) -> Result<()> {
+        for (name, backend) in &self.backends {
+            validate_name(name).context("illegal name for bus backend")?;
+            if BusInitializer::get_bus_scheme(&Some(backend.url.clone())).is_none() {
+                return Err(anyhow!(
+                    "bus backend {} has an unsupported url {}",
+                    name,
+                    backend.url
+                ));
+            }
+        }
+        for (i, route) in self.routes.iter().enumerate() {
+            route.validate()?;
+            for backend in &route.backends {
+                if !self.backends.contains_key(backend) {
+                    return Err(anyhow!(
+                        "unknown bus backend {} in bus route {}",
+                        backend,
+                        i
+                    ));
+                }
+            }
+        }
+        for backend in &self.default {
+            if !self.backends.contains_key(backend) {
+                return Err(anyhow!("unknown default bus backend {}", backend));
+            }
+        }
+        Ok(())
+    }
+
+    /// Use the bus at `BUS_URL` as the backend `default` for all
+    /// deployments
+    fn from_opt(opt: &Opt) -> Self {
+        match &opt.bus_url {
+            Some(url) => {
+                let name = "default".to_string();
+                let backend = BusBackend { url: url.clone() };
+                Self {
+                    backends: BTreeMap::from([(name.clone(), backend)]),
+                    routes: vec![],
+                    default: vec![name],
+                }
+            }
+            None => Self::default(),
+        }
+    }
+
+    pub fn routes(&self) -> Vec<BusRoute> {
+        self.routes.iter().map(BusRouteRule::to_route).collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BusBackend {
+    pub url: String,
+}
+
+/// Send the messages of the deployments listed in `deployments`, or of the
+/// deployments whose name matches `name`, to `backends`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BusRouteRule {
+    #[serde(default)]
+    deployments: Vec<String>,
+    #[serde(with = "serde_regex", default)]
+    name: Option<Regex>,
+    backends: Vec<String>,
+}
+
+impl BusRouteRule {
+    fn validate(&self) -> Result<()> {
+        match (self.deployments.is_empty(), &self.name) {
+            (true, None) => Err(anyhow!(
+                "a bus route must have either `deployments` or `name`"
+            )),
+            (false, Some(_)) => Err(anyhow!(
+                "a bus route can not have both `deployments` and `name`"
+            )),
+            (true, Some(name)) => DeploymentMatcher::name(name.as_str())
+                .map(|_| ())
+                .map_err(|e| anyhow!("invalid `name` `{}` in bus route: {}", name, e)),
+            (false, None) => Ok(()),
+        }
+    }
+
+    fn to_route(&self) -> BusRoute {
+        let matcher = match &self.name {
+            Some(name) => DeploymentMatcher::name(name.as_str())
+                .expect("the name of a bus route is checked in `validate`"),
+            None => DeploymentMatcher::Hashes(self.deployments.iter().cloned().collect()),
+        };
+        BusRoute {
+            matcher,
+            backends: self.backends.clone(),
+        }
+    }
+}
+
+/// What to do when a deployment reaches the chain head for the first time
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SyncedSection {
+    #[serde(default, rename = "action")]
+    actions: Vec<SyncedActionConfig>,
+}
+
+impl SyncedSection {
+    fn validate(&self) -> Result<()> {
+        let mut keys = BTreeSet::new();
+        for (i, action) in self.actions.iter().enumerate() {
+            let action = action
+                .to_action()
+                .with_context(|| format!("invalid synced action {}", i))?;
+            // The store records which actions ran by their key
+            if !keys.insert(action.kind.key()) {
+                bail!("synced action {} is the same as an earlier one", i);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn actions(&self) -> Vec<SyncedAction> {
+        self.actions
+            .iter()
+            .filter_map(|action| action.to_action().ok())
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SyncedActionName {
+    Bus,
+    Webhook,
+    Reassign,
+    Label,
+}
+
+/// One action for synced deployments. Which of `url`, `node` and `label`
+/// are needed depends on `kind`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct SyncedActionConfig {
+    kind: SyncedActionName,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    node: Option<String>,
+    #[serde(default)]
+    label: Option<String>,
+    /// The timeout in seconds
+    #[serde(default = "default_synced_action_timeout")]
+    timeout: u64,
+}
+
+impl SyncedActionConfig {
+    fn to_action(&self) -> Result<SyncedAction> {
+        let kind = match (self.kind, &self.url, &self.node, &self.label) {
+            (SyncedActionName::Bus, None, None, None) => SyncedActionKind::Bus,
+            (SyncedActionName::Webhook, Some(url), None, None) => {
+                Url::parse(url).with_context(|| format!("invalid webhook url {}", url))?;
+                SyncedActionKind::Webhook { url: url.clone() }
+            }
+            (SyncedActionName::Reassign, None, Some(node), None) => SyncedActionKind::Reassign {
+                node: NodeId::new(node).map_err(|()| anyhow!("invalid node id {}", node))?,
+            },
+            (SyncedActionName::Label, None, None, Some(label)) => {
+                if label.is_empty() {
+                    bail!("the label of a label action can not be empty");
+                }
+                SyncedActionKind::Label {
+                    label: label.clone(),
+                }
+            }
+            (SyncedActionName::Bus, _, _, _) => {
+                bail!("a bus action takes neither `url`, `node` nor `label`")
+            }
+            (SyncedActionName::Webhook, _, _, _) => bail!("a webhook action needs `url` only"),
+            (SyncedActionName::Reassign, _, _, _) => bail!("a reassign action needs `node` only"),
+            (SyncedActionName::Label, _, _, _) => bail!("a label action needs `label` only"),
+        };
+        if self.timeout == 0 {
+            bail!("the timeout must be at least one second");
+        }
+        Ok(SyncedAction {
+            kind,
+            timeout: Duration::from_secs(self.timeout),
+        })
+    }
+}
+
 /// Replace the host portion of `url` and return a new URL with `host`
 /// as the host portion
 ///
@@ -1082,6 +1402,10 @@ fn one() -> usize {
     1
 }
 
+fn default_synced_action_timeout() -> u64 {
+    30
+}
+
 fn default_node_id() -> NodeId {
     NodeId::new("default").unwrap()
 }
@@ -1124,15 +1448,19 @@ mod tests {
     use crate::config::Web3Rule;
 
     use super::{
-        Chain, Config, FirehoseProvider, Provider, ProviderDetails, Transport, Web3Provider,
+        BusSection, Chain, Config, FirehoseProvider, Provider, ProviderDetails, SyncedSection,
+        Transport, Web3Provider,
     };
     use graph::blockchain::BlockchainKind;
     use graph::prelude::regex::Regex;
     use graph::prelude::NodeId;
+    use graph_chain_ethereum::{CallClass, ProviderBudget};
+    use graph_core::{SyncedAction, SyncedActionKind};
     use http::{HeaderMap, HeaderValue};
     use std::collections::BTreeSet;
     use std::fs::read_to_string;
     use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     #[test]
     fn it_works_on_standard_config() {
@@ -1212,6 +1540,9 @@ mod tests {
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
                     rules: Vec::new(),
+                    costs: BTreeMap::new(),
+                    budget: None,
+                    pin: BTreeSet::new(),
                 }),
             },
             actual
@@ -1238,6 +1569,9 @@ mod tests {
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
                     rules: Vec::new(),
+                    costs: BTreeMap::new(),
+                    budget: None,
+                    pin: BTreeSet::new(),
                 }),
             },
             actual
@@ -1304,12 +1638,100 @@ mod tests {
                     features,
                     headers,
                     rules: Vec::new(),
+                    costs: BTreeMap::new(),
+                    budget: None,
+                    pin: BTreeSet::new(),
                 }),
             },
             actual
         );
     }
 
+    #[test]
+    fn it_parses_web3_provider_costs_and_budget() {
+        let mut actual: Provider = toml::from_str(
+            r#"
+            label = "peering"
+            url = "http://localhost:8545"
+            features = []
+            costs = { eth_getLogs = 75, eth_call = 26.5 }
+            budget = { monthly_units = 3e8, deprioritize_backfills = true }
+        "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        let web3 = match actual.details {
+            ProviderDetails::Web3(web3) => web3,
+            _ => panic!("expected a web3 provider"),
+        };
+        assert_eq!(Some(&75.0), web3.costs.get("eth_getLogs"));
+        assert_eq!(Some(&26.5), web3.costs.get("eth_call"));
+        assert_eq!(
+            Some(ProviderBudget {
+                monthly_units: 3e8,
+                deprioritize_backfills: true
+            }),
+            web3.budget
+        );
+
+        let mut negative: Provider = toml::from_str(
+            r#"
+            label = "peering"
+            details = { type = "web3", url = "http://localhost:8545", features = [], costs = { eth_call = -1 } }
+        "#,
+        )
+        .unwrap();
+        assert!(negative.validate().is_err());
+    }
+
+    #[test]
+    fn it_parses_web3_provider_pins() {
+        let mut actual: Chain = toml::from_str(
+            r#"
+            shard = "primary"
+            provider = [
+              { label = "archive", url = "http://localhost:8545", features = ["archive"] },
+              { label = "full", details = { type = "web3", url = "http://localhost:8546", features = [] },
+                pin = ["calls", "blocks"] } ]
+        "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        let pins: Vec<_> = actual
+            .providers
+            .iter()
+            .map(|provider| match &provider.details {
+                ProviderDetails::Web3(web3) => web3.pin.iter().cloned().collect::<Vec<_>>(),
+                _ => panic!("expected a web3 provider"),
+            })
+            .collect();
+        assert_eq!(
+            vec![vec![], vec![CallClass::Calls, CallClass::Blocks]],
+            pins
+        );
+
+        let mut twice: Chain = toml::from_str(
+            r#"
+            shard = "primary"
+            provider = [
+              { label = "archive", url = "http://localhost:8545", features = [], pin = ["logs"] },
+              { label = "full", url = "http://localhost:8546", features = [], pin = ["logs"] } ]
+        "#,
+        )
+        .unwrap();
+        assert!(twice.validate().is_err());
+
+        let unknown = toml::from_str::<Chain>(
+            r#"
+            shard = "primary"
+            provider = [ { label = "full", url = "http://localhost:8546", features = [], pin = ["traces"] } ]
+        "#,
+        );
+        assert!(unknown.is_err());
+    }
+
     #[test]
     fn it_works_on_new_web3_provider_without_transport_from_toml() {
         let actual = toml::from_str(
@@ -1329,6 +1751,9 @@ mod tests {
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
                     rules: Vec::new(),
+                    costs: BTreeMap::new(),
+                    budget: None,
+                    pin: BTreeSet::new(),
                 }),
             },
             actual
@@ -1646,9 +2071,158 @@ mod tests {
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
                     rules: Vec::new(),
+                    costs: BTreeMap::new(),
+                    budget: None,
+                    pin: BTreeSet::new(),
                 }),
             },
             actual
         );
     }
+
+    #[test]
+    fn bus_section() {
+        let parse = |s: &str| toml::from_str::<BusSection>(s).unwrap();
+
+        let bus = parse(
+            r#"
+            default = ["pubsub"]
+
+            [backend.pubsub]
+            url = "pubsub://graph-node"
+
+            [backend.other]
+            url = "pubsub://other"
+
+            [[route]]
+            deployments = ["QmA"]
+            backends = ["other"]
+
+            [[route]]
+            name = "uniswap/.*"
+            backends = ["pubsub", "other"]
+        "#,
+        );
+        bus.validate().unwrap();
+        assert_eq!(2, bus.routes().len());
+
+        // Routes and the default can only use backends that are defined
+        let bus = parse(
+            r#"
+            [backend.pubsub]
+            url = "pubsub://graph-node"
+
+            [[route]]
+            deployments = ["QmA"]
+            backends = ["rabbit"]
+        "#,
+        );
+        assert!(bus.validate().is_err());
+
+        let bus = parse(
+            r#"
+            default = ["rabbit"]
+
+            [backend.pubsub]
+            url = "pubsub://graph-node"
+        "#,
+        );
+        assert!(bus.validate().is_err());
+
+        // A route needs to say which deployments it applies to
+        let bus = parse(
+            r#"
+            [backend.pubsub]
+            url = "pubsub://graph-node"
+
+            [[route]]
+            backends = ["pubsub"]
+        "#,
+        );
+        assert!(bus.validate().is_err());
+
+        // Without a `[bus]` section, nothing is published
+        assert!(BusSection::default().validate().is_ok());
+    }
+
+    #[test]
+    fn synced_section() {
+        let parse = |s: &str| toml::from_str::<SyncedSection>(s).unwrap();
+
+        let synced = parse(
+            r#"
+            [[action]]
+            kind = "bus"
+
+            [[action]]
+            kind = "webhook"
+            url = "https://router.example.com/synced"
+            timeout = 5
+
+            [[action]]
+            kind = "reassign"
+            node = "query_node_1"
+
+            [[action]]
+            kind = "label"
+            label = "live"
+        "#,
+        );
+        synced.validate().unwrap();
+        assert_eq!(
+            vec![
+                SyncedAction {
+                    kind: SyncedActionKind::Bus,
+                    timeout: Duration::from_secs(30),
+                },
+                SyncedAction {
+                    kind: SyncedActionKind::Webhook {
+                        url: "https://router.example.com/synced".to_string()
+                    },
+                    timeout: Duration::from_secs(5),
+                },
+                SyncedAction {
+                    kind: SyncedActionKind::Reassign {
+                        node: NodeId::new("query_node_1").unwrap()
+                    },
+                    timeout: Duration::from_secs(30),
+                },
+                SyncedAction {
+                    kind: SyncedActionKind::Label {
+                        label: "live".to_string()
+                    },
+                    timeout: Duration::from_secs(30),
+                },
+            ],
+            synced.actions()
+        );
+
+        // Each kind of action needs exactly the settings it uses
+        for action in [
+            r#"kind = "webhook""#,
+            r#"kind = "webhook"
+               url = "not a url""#,
+            r#"kind = "reassign""#,
+            r#"kind = "reassign"
+               node = """#,
+            r#"kind = "bus"
+               url = "https://router.example.com/synced""#,
+            r#"kind = "bus"
+               timeout = 0"#,
+            r#"kind = "label""#,
+            r#"kind = "label"
+               label = """#,
+            r#"kind = "label"
+               label = "live"
+               node = "query_node_1""#,
+            r#"kind = "bus"
+               [[action]]
+               kind = "bus""#,
+        ] {
+            let synced = parse(&format!("[[action]]\n{}", action));
+            assert!(synced.validate().is_err(), "{} is invalid", action);
+        }
+
+        assert!(SyncedSection::default().validate().is_ok());
+    }
 }
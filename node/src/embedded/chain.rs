@@ -0,0 +1,566 @@
+//! A chain whose blocks and triggers are supplied by the embedder instead
+//! of being read from a provider or Firehose. Blocks are handed to the
+//! subgraph as a static Firehose stream, and reorgs are emitted whenever
+//! the next block is not a child of the previous one.
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use graph::anyhow::{anyhow, Error};
+use graph::blockchain::block_stream::{
+    BlockRefetcher, BlockStream, BlockStreamBuilder, BlockStreamEvent, BlockWithTriggers,
+    ChainHeadUpdateListener, FirehoseCursor,
+};
+use graph::blockchain::{
+    Block, BlockHash, BlockPtr, Blockchain, RuntimeAdapter, TriggersAdapter,
+    TriggersAdapterSelector,
+};
+use graph::cheap_clone::CheapClone;
+use graph::components::store::DeploymentLocator;
+use graph::data::subgraph::UnifiedMappingApiVersion;
+use graph::firehose::{FirehoseEndpoint, FirehoseEndpoints, SubgraphLimit};
+use graph::prelude::ethabi::ethereum_types::{H256, U64};
+use graph::prelude::futures03::{stream, Stream, StreamExt};
+use graph::prelude::web3::types::{Address, Log, Transaction, H160};
+use graph::prelude::{
+    async_trait, ethabi, tiny_keccak, BlockNumber, LightEthereumBlock, LoggerFactory,
+    MetricsRegistry, NodeId,
+};
+use graph::slog::{o, Discard, Logger};
+use graph_chain_ethereum::chain::BlockFinality;
+use graph_chain_ethereum::network::EthereumNetworkAdapters;
+use graph_chain_ethereum::trigger::{EthereumBlockTriggerType, EthereumTrigger};
+use graph_chain_ethereum::{Chain, ENV_VARS};
+use graph_store_postgres::ChainStore;
+
+/// The pointer of the test block with number `n`
+pub fn test_ptr(n: BlockNumber) -> BlockPtr {
+    test_ptr_reorged(n, 0)
+}
+
+/// Like `test_ptr`, but for a block on the fork `reorg_n`; `n` is stored in
+/// the low bits and `reorg_n` in the high bits of the hash
+pub fn test_ptr_reorged(n: BlockNumber, reorg_n: u32) -> BlockPtr {
+    let mut hash = H256::from_low_u64_be(n as u64);
+    hash[0..4].copy_from_slice(&reorg_n.to_be_bytes());
+    BlockPtr {
+        hash: hash.into(),
+        number: n,
+    }
+}
+
+/// A chain and a handle to change the blocks it streams to subgraphs
+pub struct MockChain<C: Blockchain> {
+    pub chain: Arc<C>,
+    pub block_stream_builder: Arc<MutexBlockStreamBuilder<C>>,
+}
+
+impl<C: Blockchain> Clone for MockChain<C> {
+    fn clone(&self) -> Self {
+        MockChain {
+            chain: self.chain.cheap_clone(),
+            block_stream_builder: self.block_stream_builder.cheap_clone(),
+        }
+    }
+}
+
+impl<C: Blockchain> MockChain<C> {
+    /// Stream `blocks` to subgraphs that are started from now on. Subgraphs
+    /// that are already running keep the blocks they were started with
+    pub fn set_block_stream(&self, blocks: Vec<BlockWithTriggers<C>>)
+    where
+        C::TriggerData: Clone,
+    {
+        let static_block_stream = Arc::new(StaticStreamBuilder { chain: blocks });
+        *self.block_stream_builder.0.lock().unwrap() = static_block_stream;
+    }
+}
+
+/// An Ethereum chain called `network` that streams `blocks`. Triggers for
+/// reprocessed blocks come from `triggers_adapter`; if it is `None`, no
+/// triggers are found when blocks are reprocessed
+pub fn ethereum(
+    logger_factory: &LoggerFactory,
+    registry: Arc<dyn MetricsRegistry>,
+    network: String,
+    node_id: NodeId,
+    chain_store: Arc<ChainStore>,
+    chain_head_listener: Arc<dyn ChainHeadUpdateListener>,
+    blocks: Vec<BlockWithTriggers<Chain>>,
+    triggers_adapter: Option<Arc<dyn TriggersAdapterSelector<Chain>>>,
+) -> MockChain<Chain> {
+    let triggers_adapter = triggers_adapter.unwrap_or_else(|| {
+        Arc::new(NoopAdapterSelector {
+            triggers_in_block_sleep: Duration::ZERO,
+            x: PhantomData,
+        })
+    });
+    let triggers_adapter = Arc::new(FinalizingAdapterSelector(triggers_adapter));
+
+    // The stream builder is only used if there is a Firehose endpoint. The
+    // endpoint itself is never used because the stream is mocked
+    let firehose_endpoints: FirehoseEndpoints = vec![Arc::new(FirehoseEndpoint::new(
+        "",
+        "https://example.com",
+        None,
+        true,
+        false,
+        SubgraphLimit::Unlimited,
+    ))]
+    .into();
+
+    let static_block_stream = Arc::new(StaticStreamBuilder { chain: blocks });
+    let block_stream_builder = Arc::new(MutexBlockStreamBuilder(Mutex::new(static_block_stream)));
+
+    let chain = Chain::new(
+        logger_factory.clone(),
+        network,
+        node_id,
+        registry,
+        chain_store.cheap_clone(),
+        chain_store,
+        firehose_endpoints,
+        EthereumNetworkAdapters::default(),
+        chain_head_listener,
+        block_stream_builder.clone(),
+        Arc::new(StaticBlockRefetcher { x: PhantomData }),
+        triggers_adapter,
+        Arc::new(NoopRuntimeAdapter { x: PhantomData }),
+        ENV_VARS.reorg_threshold,
+        // The mock chain is always ingestible
+        true,
+    );
+
+    MockChain {
+        chain: Arc::new(chain),
+        block_stream_builder,
+    }
+}
+
+/// The genesis block of the test chain, with a block trigger
+pub fn genesis() -> BlockWithTriggers<Chain> {
+    let ptr = test_ptr(0);
+    BlockWithTriggers::<Chain> {
+        block: BlockFinality::Final(Arc::new(LightEthereumBlock {
+            hash: Some(H256::from_slice(ptr.hash.as_slice())),
+            number: Some(U64::from(ptr.number)),
+            ..Default::default()
+        })),
+        trigger_data: vec![EthereumTrigger::Block(ptr, EthereumBlockTriggerType::Every)],
+    }
+}
+
+/// A block with a block trigger and a single transaction that logs can be
+/// added to with `push_test_log`
+pub fn empty_block(parent_ptr: BlockPtr, ptr: BlockPtr) -> BlockWithTriggers<Chain> {
+    assert!(ptr != parent_ptr);
+    assert!(ptr.number > parent_ptr.number);
+
+    // A 0x000.. transaction is used so `push_test_log` can use it
+    let transactions = vec![Transaction {
+        hash: H256::zero(),
+        block_hash: Some(H256::from_slice(ptr.hash.as_slice().into())),
+        block_number: Some(ptr.number.into()),
+        transaction_index: Some(0.into()),
+        from: Some(H160::zero()),
+        to: Some(H160::zero()),
+        ..Default::default()
+    }];
+
+    BlockWithTriggers::<Chain> {
+        block: BlockFinality::Final(Arc::new(LightEthereumBlock {
+            hash: Some(H256::from_slice(ptr.hash.as_slice())),
+            number: Some(U64::from(ptr.number)),
+            parent_hash: H256::from_slice(parent_ptr.hash.as_slice()),
+            transactions,
+            ..Default::default()
+        })),
+        trigger_data: vec![EthereumTrigger::Block(ptr, EthereumBlockTriggerType::Every)],
+    }
+}
+
+/// Add a `TestEvent(string)` log with `payload` from the zero address to
+/// `block`
+pub fn push_test_log(block: &mut BlockWithTriggers<Chain>, payload: impl Into<String>) {
+    block.trigger_data.push(EthereumTrigger::Log(
+        Arc::new(Log {
+            address: Address::zero(),
+            topics: vec![tiny_keccak::keccak256(b"TestEvent(string)").into()],
+            data: ethabi::encode(&[ethabi::Token::String(payload.into())]).into(),
+            block_hash: Some(H256::from_slice(block.ptr().hash.as_slice())),
+            block_number: Some(block.ptr().number.into()),
+            transaction_hash: Some(H256::from_low_u64_be(0).into()),
+            transaction_index: Some(0.into()),
+            log_index: Some(0.into()),
+            transaction_log_index: Some(0.into()),
+            log_type: None,
+            removed: None,
+        }),
+        None,
+    ))
+}
+
+struct StaticBlockRefetcher<C: Blockchain> {
+    x: PhantomData<C>,
+}
+
+#[async_trait]
+impl<C: Blockchain> BlockRefetcher<C> for StaticBlockRefetcher<C> {
+    fn required(&self, _chain: &C) -> bool {
+        false
+    }
+
+    async fn get_block(
+        &self,
+        _chain: &C,
+        _logger: &Logger,
+        _cursor: FirehoseCursor,
+    ) -> Result<C::Block, Error> {
+        Err(anyhow!(
+            "embedded chains never refetch blocks, but block refetching was requested"
+        ))
+    }
+}
+
+/// A block stream builder whose underlying builder can be swapped out
+pub struct MutexBlockStreamBuilder<C: Blockchain>(pub Mutex<Arc<dyn BlockStreamBuilder<C>>>);
+
+#[async_trait]
+impl<C: Blockchain> BlockStreamBuilder<C> for MutexBlockStreamBuilder<C> {
+    async fn build_firehose(
+        &self,
+        chain: &C,
+        deployment: DeploymentLocator,
+        block_cursor: FirehoseCursor,
+        start_blocks: Vec<BlockNumber>,
+        subgraph_current_block: Option<BlockPtr>,
+        filter: Arc<<C as Blockchain>::TriggerFilter>,
+        unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Box<dyn BlockStream<C>>, Error> {
+        let builder = self.0.lock().unwrap().clone();
+
+        builder
+            .build_firehose(
+                chain,
+                deployment,
+                block_cursor,
+                start_blocks,
+                subgraph_current_block,
+                filter,
+                unified_api_version,
+            )
+            .await
+    }
+
+    async fn build_polling(
+        &self,
+        _chain: Arc<C>,
+        _deployment: DeploymentLocator,
+        _start_blocks: Vec<BlockNumber>,
+        _subgraph_current_block: Option<BlockPtr>,
+        _filter: Arc<<C as Blockchain>::TriggerFilter>,
+        _unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Box<dyn BlockStream<C>>, Error> {
+        Err(anyhow!(
+            "embedded chains only stream blocks with Firehose, but a polling block stream was requested"
+        ))
+    }
+}
+
+/// `chain` is the sequence of chain heads to be processed. If the next block to be processed in the
+/// chain is not a descendant of the previous one, reorgs will be emitted until it is.
+///
+/// If the stream is reset, emitted reorged blocks will not be emitted again.
+struct StaticStreamBuilder<C: Blockchain> {
+    chain: Vec<BlockWithTriggers<C>>,
+}
+
+#[async_trait]
+impl<C: Blockchain> BlockStreamBuilder<C> for StaticStreamBuilder<C>
+where
+    C::TriggerData: Clone,
+{
+    async fn build_firehose(
+        &self,
+        _chain: &C,
+        _deployment: DeploymentLocator,
+        _block_cursor: FirehoseCursor,
+        _start_blocks: Vec<BlockNumber>,
+        current_block: Option<BlockPtr>,
+        _filter: Arc<C::TriggerFilter>,
+        _unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Box<dyn BlockStream<C>>, Error> {
+        let current_idx = current_block.map(|current_block| {
+            self.chain
+                .iter()
+                .enumerate()
+                .find(|(_, b)| b.ptr() == current_block)
+                .unwrap()
+                .0
+        });
+        let events = stream_events(&self.chain, current_idx);
+        Ok(Box::new(StaticStream {
+            stream: Box::pin(stream::iter(events.into_iter().map(Ok))),
+        }))
+    }
+
+    async fn build_polling(
+        &self,
+        _chain: Arc<C>,
+        _deployment: DeploymentLocator,
+        _start_blocks: Vec<BlockNumber>,
+        _subgraph_current_block: Option<BlockPtr>,
+        _filter: Arc<C::TriggerFilter>,
+        _unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Box<dyn BlockStream<C>>, Error> {
+        Err(anyhow!(
+            "embedded chains only stream blocks with Firehose, but a polling block stream was requested"
+        ))
+    }
+}
+
+struct StaticStream<C: Blockchain> {
+    stream: Pin<Box<dyn Stream<Item = Result<BlockStreamEvent<C>, Error>> + Send>>,
+}
+
+impl<C: Blockchain> BlockStream<C> for StaticStream<C> {}
+
+impl<C: Blockchain> Stream for StaticStream<C> {
+    type Item = Result<BlockStreamEvent<C>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+/// The events for streaming `blocks` to a subgraph that has processed the
+/// block at `current_idx`
+fn stream_events<C: Blockchain>(
+    blocks: &[BlockWithTriggers<C>],
+    current_idx: Option<usize>,
+) -> Vec<BlockStreamEvent<C>>
+where
+    C::TriggerData: Clone,
+{
+    let mut events = Vec::new();
+    let current_block = current_idx.map(|idx| &blocks[idx]);
+    let mut current_ptr = current_block.map(|b| b.ptr());
+    let mut current_parent_ptr = current_block.and_then(|b| b.parent_ptr());
+    let skip = current_idx.map(|idx| idx + 1).unwrap_or(0);
+    let mut blocks_iter = blocks.iter().skip(skip).peekable();
+    while let Some(&block) = blocks_iter.peek() {
+        if block.parent_ptr() == current_ptr {
+            current_ptr = Some(block.ptr());
+            current_parent_ptr = block.parent_ptr();
+            blocks_iter.next(); // Block consumed, advance the iterator.
+            events.push(BlockStreamEvent::ProcessBlock(
+                block.clone(),
+                FirehoseCursor::None,
+            ));
+        } else {
+            let revert_to = current_parent_ptr.unwrap();
+            current_ptr = Some(revert_to.clone());
+            current_parent_ptr = blocks
+                .iter()
+                .find(|b| b.ptr() == revert_to)
+                .unwrap()
+                .block
+                .parent_ptr();
+            events.push(BlockStreamEvent::Revert(revert_to, FirehoseCursor::None));
+        }
+    }
+    events
+}
+
+struct NoopRuntimeAdapter<C> {
+    x: PhantomData<C>,
+}
+
+impl<C: Blockchain> RuntimeAdapter<C> for NoopRuntimeAdapter<C> {
+    fn host_fns(
+        &self,
+        _ds: &<C as Blockchain>::DataSource,
+    ) -> Result<Vec<graph::blockchain::HostFn>, Error> {
+        Ok(vec![])
+    }
+}
+
+/// Selects a triggers adapter that finds no triggers in reprocessed blocks
+pub struct NoopAdapterSelector<C> {
+    pub x: PhantomData<C>,
+    pub triggers_in_block_sleep: Duration,
+}
+
+impl<C: Blockchain> TriggersAdapterSelector<C> for NoopAdapterSelector<C> {
+    fn triggers_adapter(
+        &self,
+        _loc: &DeploymentLocator,
+        _capabilities: &<C as Blockchain>::NodeCapabilities,
+        _unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Arc<dyn TriggersAdapter<C>>, Error> {
+        // Return no triggers on data source reprocessing.
+        let triggers_in_block = Arc::new(|block| {
+            let logger = Logger::root(Discard, o!());
+            Ok(BlockWithTriggers::new(block, Vec::new(), &logger))
+        });
+        Ok(Arc::new(MockTriggersAdapter {
+            x: PhantomData,
+            triggers_in_block,
+            triggers_in_block_sleep: self.triggers_in_block_sleep,
+        }))
+    }
+}
+
+/// Selects a triggers adapter that finds the triggers in reprocessed blocks
+/// with `triggers_in_block`, after sleeping for `triggers_in_block_sleep`
+pub struct MockAdapterSelector<C: Blockchain> {
+    pub x: PhantomData<C>,
+    pub triggers_in_block_sleep: Duration,
+    pub triggers_in_block:
+        Arc<dyn Fn(<C as Blockchain>::Block) -> Result<BlockWithTriggers<C>, Error> + Sync + Send>,
+}
+
+impl<C: Blockchain> TriggersAdapterSelector<C> for MockAdapterSelector<C> {
+    fn triggers_adapter(
+        &self,
+        _loc: &DeploymentLocator,
+        _capabilities: &<C as Blockchain>::NodeCapabilities,
+        _unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Arc<dyn TriggersAdapter<C>>, Error> {
+        Ok(Arc::new(MockTriggersAdapter {
+            x: PhantomData,
+            triggers_in_block: self.triggers_in_block.clone(),
+            triggers_in_block_sleep: self.triggers_in_block_sleep,
+        }))
+    }
+}
+
+struct MockTriggersAdapter<C: Blockchain> {
+    x: PhantomData<C>,
+    triggers_in_block_sleep: Duration,
+    triggers_in_block:
+        Arc<dyn Fn(<C as Blockchain>::Block) -> Result<BlockWithTriggers<C>, Error> + Sync + Send>,
+}
+
+#[async_trait]
+impl<C: Blockchain> TriggersAdapter<C> for MockTriggersAdapter<C> {
+    async fn ancestor_block(
+        &self,
+        ptr: BlockPtr,
+        offset: BlockNumber,
+    ) -> Result<Option<<C as Blockchain>::Block>, Error> {
+        Err(anyhow!(
+            "embedded chains can not look up the ancestor {} blocks before block {}",
+            offset,
+            ptr
+        ))
+    }
+
+    async fn scan_triggers(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+        _filter: &<C as Blockchain>::TriggerFilter,
+    ) -> Result<Vec<BlockWithTriggers<C>>, Error> {
+        Err(anyhow!(
+            "embedded chains can not scan blocks {} to {} for triggers",
+            from,
+            to
+        ))
+    }
+
+    async fn triggers_in_block(
+        &self,
+        _logger: &Logger,
+        block: <C as Blockchain>::Block,
+        _filter: &<C as Blockchain>::TriggerFilter,
+    ) -> Result<BlockWithTriggers<C>, Error> {
+        graph::tokio::time::sleep(self.triggers_in_block_sleep).await;
+
+        (self.triggers_in_block)(block)
+    }
+
+    async fn is_on_main_chain(&self, ptr: BlockPtr) -> Result<bool, Error> {
+        Err(anyhow!(
+            "embedded chains can not check whether block {} is on the main chain",
+            ptr
+        ))
+    }
+
+    async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error> {
+        match block.number {
+            0 => Ok(None),
+            n => Ok(Some(BlockPtr {
+                hash: BlockHash::default(),
+                number: n - 1,
+            })),
+        }
+    }
+}
+
+/// Selects the adapter of the wrapped selector, and runs block finalizers
+/// like the triggers adapter of a real Ethereum chain
+struct FinalizingAdapterSelector(Arc<dyn TriggersAdapterSelector<Chain>>);
+
+impl TriggersAdapterSelector<Chain> for FinalizingAdapterSelector {
+    fn triggers_adapter(
+        &self,
+        loc: &DeploymentLocator,
+        capabilities: &<Chain as Blockchain>::NodeCapabilities,
+        unified_api_version: UnifiedMappingApiVersion,
+    ) -> Result<Arc<dyn TriggersAdapter<Chain>>, Error> {
+        let adapter = self
+            .0
+            .triggers_adapter(loc, capabilities, unified_api_version)?;
+        Ok(Arc::new(FinalizingTriggersAdapter(adapter)))
+    }
+}
+
+struct FinalizingTriggersAdapter(Arc<dyn TriggersAdapter<Chain>>);
+
+#[async_trait]
+impl TriggersAdapter<Chain> for FinalizingTriggersAdapter {
+    async fn ancestor_block(
+        &self,
+        ptr: BlockPtr,
+        offset: BlockNumber,
+    ) -> Result<Option<BlockFinality>, Error> {
+        self.0.ancestor_block(ptr, offset).await
+    }
+
+    async fn scan_triggers(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+        filter: &<Chain as Blockchain>::TriggerFilter,
+    ) -> Result<Vec<BlockWithTriggers<Chain>>, Error> {
+        self.0.scan_triggers(from, to, filter).await
+    }
+
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: BlockFinality,
+        filter: &<Chain as Blockchain>::TriggerFilter,
+    ) -> Result<BlockWithTriggers<Chain>, Error> {
+        self.0.triggers_in_block(logger, block, filter).await
+    }
+
+    async fn is_on_main_chain(&self, ptr: BlockPtr) -> Result<bool, Error> {
+        self.0.is_on_main_chain(ptr).await
+    }
+
+    async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error> {
+        self.0.parent_ptr(block).await
+    }
+
+    fn block_finalizer_trigger(&self, block: &BlockFinality) -> Option<EthereumTrigger> {
+        Some(EthereumTrigger::Block(
+            block.ptr(),
+            EthereumBlockTriggerType::Finalize,
+        ))
+    }
+}
@@ -0,0 +1,510 @@
+//! Run the indexing machinery of graph-node for a single deployment inside
+//! another Rust program, for example a test, without starting the servers
+//! and chain ingestors that the `graph-node` binary runs.
+//!
+//! An [`EmbeddedNodeBuilder`] wires up a store from a [`Config`], one
+//! Ethereum chain whose blocks are supplied by the embedder (see
+//! [`chain`]), and a `SubgraphInstanceManager` that runs the mappings with
+//! the `RuntimeHostBuilder`. The deployment itself is read from IPFS, just
+//! like when it is deployed to a regular node. The resulting
+//! [`EmbeddedNode`] starts and stops the deployment, waits for it to reach
+//! a block, and reads the entities it wrote.
+//!
+//! The store must be a real Postgres database. Everything the embedded node
+//! writes for its deployment is removed when the deployment is created and
+//! again when the node is dropped, so the same subgraph name must not be
+//! used by other nodes that share the database.
+//!
+//! `tests/tests/embedded_tests.rs` shows how to use the API.
+
+pub mod chain;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::anyhow::{anyhow, bail, Error};
+use graph::blockchain::block_stream::BlockWithTriggers;
+use graph::blockchain::{BlockPtr, BlockchainMap, ChainIdentifier, TriggersAdapterSelector};
+use graph::cheap_clone::CheapClone;
+use graph::components::bus::BusRouter;
+use graph::components::store::{BlockStore as _, DeploymentLocator, EntityKey, EntityType};
+use graph::data::graphql::effort::LoadManager;
+use graph::data::query::{Query, QueryTarget};
+use graph::data::subgraph::schema::SubgraphError;
+use graph::data_source::CausalityRegion;
+use graph::env::EnvVars;
+use graph::ipfs_client::IpfsClient;
+use graph::prelude::{
+    lazy_static, r, tokio, ApiVersion, BlockNumber, DeploymentHash, Entity, GraphQlRunner as _,
+    LoggerFactory, MetricsRegistry, NodeId, SubgraphAssignmentProvider, SubgraphName,
+    SubgraphRegistrar, SubgraphStore as _, SubgraphVersionSwitchingMode,
+};
+use graph::prometheus::Registry;
+use graph::slog::{crit, debug, Logger};
+use graph_chain_ethereum::Chain;
+use graph_core::polling_monitor::ipfs_service;
+use graph_core::{
+    LinkResolver, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
+    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar, SyncedAction,
+};
+use graph_store_postgres::{Store, SubgraphStore};
+
+use crate::config::Config;
+use crate::manager::PanicSubscriptionManager;
+use crate::store_builder::StoreBuilder;
+
+pub use self::chain::{
+    empty_block, genesis, push_test_log, test_ptr, test_ptr_reorged, MockAdapterSelector,
+    MockChain, NoopAdapterSelector,
+};
+
+/// The id of the node that runs the deployment; the deployment rules in
+/// the configuration must assign deployments to it
+pub const EMBEDDED_NODE_ID: &str = "default";
+
+/// How often to check whether the deployment has reached a block
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type GraphQlRunner = graph_graphql::prelude::GraphQlRunner<Store, PanicSubscriptionManager>;
+
+lazy_static! {
+    /// Setting up a store runs migrations, which must not happen
+    /// concurrently
+    static ref STORE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Builds an [`EmbeddedNode`] for the deployment `hash` under the name
+/// `subgraph_name`
+pub struct EmbeddedNodeBuilder {
+    config: Config,
+    subgraph_name: SubgraphName,
+    hash: DeploymentHash,
+    logger: Option<Logger>,
+    env_vars: Option<EnvVars>,
+    ipfs: Vec<IpfsClient>,
+    graft_block: Option<BlockPtr>,
+    blocks: Vec<BlockWithTriggers<Chain>>,
+    triggers_adapter: Option<Arc<dyn TriggersAdapterSelector<Chain>>>,
+    chain: Option<(Arc<Store>, MockChain<Chain>)>,
+    synced_actions: Vec<SyncedAction>,
+    sync_timeout: Duration,
+}
+
+impl EmbeddedNodeBuilder {
+    /// The chain of the embedded node is the first chain in `config`
+    pub fn new(config: Config, subgraph_name: SubgraphName, hash: DeploymentHash) -> Self {
+        Self {
+            config,
+            subgraph_name,
+            hash,
+            logger: None,
+            env_vars: None,
+            ipfs: vec![IpfsClient::localhost()],
+            graft_block: None,
+            blocks: vec![genesis()],
+            triggers_adapter: None,
+            chain: None,
+            synced_actions: Vec::new(),
+            sync_timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Log to `logger` instead of the terminal
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Use `env_vars` instead of reading them from the environment
+    pub fn env_vars(mut self, env_vars: EnvVars) -> Self {
+        self.env_vars = Some(env_vars);
+        self
+    }
+
+    /// Read the deployment and files from `ipfs` instead of the IPFS node
+    /// at `localhost:5001`
+    pub fn ipfs(mut self, ipfs: Vec<IpfsClient>) -> Self {
+        self.ipfs = ipfs;
+        self
+    }
+
+    /// Graft the deployment onto its graft base at `block`
+    pub fn graft(mut self, block: BlockPtr) -> Self {
+        self.graft_block = Some(block);
+        self
+    }
+
+    /// The blocks the chain streams to the deployment. By default, the
+    /// chain only has the block `genesis()`
+    pub fn blocks(mut self, blocks: Vec<BlockWithTriggers<Chain>>) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    /// Find the triggers for blocks that are reprocessed with
+    /// `triggers_adapter`. By default, reprocessed blocks have no triggers
+    pub fn triggers_adapter(
+        mut self,
+        triggers_adapter: Arc<dyn TriggersAdapterSelector<Chain>>,
+    ) -> Self {
+        self.triggers_adapter = Some(triggers_adapter);
+        self
+    }
+
+    /// Run the deployment on `chain` and keep its data in `store` instead
+    /// of setting up a store from the configuration and a chain that
+    /// streams the blocks given with `blocks`. The chain must use the chain
+    /// store in `store` for the first chain in the configuration
+    pub fn chain(mut self, store: Arc<Store>, chain: MockChain<Chain>) -> Self {
+        self.chain = Some((store, chain));
+        self
+    }
+
+    /// Run `synced_actions` when the deployment becomes synced
+    pub fn synced_actions(mut self, synced_actions: Vec<SyncedAction>) -> Self {
+        self.synced_actions = synced_actions;
+        self
+    }
+
+    /// How long `EmbeddedNode::wait_for_block` waits before giving up. The
+    /// default is one minute
+    pub fn sync_timeout(mut self, timeout: Duration) -> Self {
+        self.sync_timeout = timeout;
+        self
+    }
+
+    /// Set up the store and the chain, and create the deployment. The
+    /// deployment is not started
+    pub async fn build(self) -> Result<EmbeddedNode, Error> {
+        let logger = self.logger.unwrap_or_else(|| graph::log::logger(false));
+        let env_vars = Arc::new(match self.env_vars {
+            Some(env_vars) => env_vars,
+            None => EnvVars::from_env()?,
+        });
+        let registry: Arc<dyn MetricsRegistry> = Arc::new(graph_core::MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(Registry::new()),
+        ));
+        let logger_factory = LoggerFactory::new(logger.clone(), None, registry.clone());
+        let node_id = NodeId::new(EMBEDDED_NODE_ID).unwrap();
+
+        let network = match self.config.chains.chains.keys().next() {
+            Some(network) => network.clone(),
+            None => bail!("the configuration for an embedded node must have a chain"),
+        };
+        let (network_store, chain) = match self.chain {
+            Some((network_store, chain)) => (network_store, chain),
+            None => {
+                let genesis_block_hash = match self.blocks.first() {
+                    Some(block) => block.ptr().hash,
+                    None => test_ptr(0).hash,
+                };
+
+                let (network_store, chain_head_listener) = {
+                    let _lock = STORE_LOCK.lock().await;
+                    let store_builder =
+                        StoreBuilder::new(&logger, &node_id, &self.config, None, registry.clone())
+                            .await;
+                    let chain_head_listener = store_builder.chain_head_update_listener();
+                    let network_identifiers = vec![(
+                        network.clone(),
+                        vec![ChainIdentifier {
+                            net_version: "".into(),
+                            genesis_block_hash,
+                        }],
+                    )];
+                    (
+                        store_builder.network_store(network_identifiers),
+                        chain_head_listener,
+                    )
+                };
+                let chain_store = network_store
+                    .block_store()
+                    .chain_store(&network)
+                    .ok_or_else(|| anyhow!("no chain store for {}", network))?;
+
+                let chain = chain::ethereum(
+                    &logger_factory,
+                    registry.clone(),
+                    network.clone(),
+                    node_id.clone(),
+                    chain_store,
+                    chain_head_listener,
+                    self.blocks,
+                    self.triggers_adapter,
+                );
+                (network_store, chain)
+            }
+        };
+
+        // Start from a clean slate
+        let subgraph_store = network_store.subgraph_store();
+        cleanup(&subgraph_store, &self.subgraph_name, &self.hash)?;
+
+        let mut blockchain_map = BlockchainMap::new();
+        blockchain_map.insert(network, chain.chain.clone());
+        let blockchain_map = Arc::new(blockchain_map);
+
+        let link_resolver = Arc::new(LinkResolver::new(self.ipfs.clone(), env_vars.cheap_clone()));
+        let ipfs_service = ipfs_service(
+            self.ipfs,
+            env_vars.mappings.max_ipfs_file_bytes as u64,
+            env_vars.mappings.ipfs_timeout,
+            env_vars.mappings.ipfs_request_limit,
+            env_vars.mappings.ipfs_strict_verification_file_data_sources,
+            logger.clone(),
+        );
+
+        let instance_manager = SubgraphInstanceManager::new(
+            &logger_factory,
+            env_vars.cheap_clone(),
+            subgraph_store.clone(),
+            blockchain_map.clone(),
+            registry.clone(),
+            link_resolver.cheap_clone(),
+            ipfs_service,
+            env_vars.experimental_static_filters,
+            Arc::new(BusRouter::default()),
+        )
+        .with_synced_actions(self.synced_actions);
+        let provider = Arc::new(IpfsSubgraphAssignmentProvider::new(
+            &logger_factory,
+            link_resolver.cheap_clone(),
+            instance_manager.clone(),
+        ));
+        let registrar = IpfsSubgraphRegistrar::new(
+            &logger_factory,
+            link_resolver.cheap_clone(),
+            provider.clone(),
+            subgraph_store.clone(),
+            Arc::new(PanicSubscriptionManager {}),
+            blockchain_map.clone(),
+            node_id.clone(),
+            SubgraphVersionSwitchingMode::Instant,
+        );
+
+        let load_manager = LoadManager::new(&logger, Vec::new(), registry.clone());
+        let graphql_runner = Arc::new(GraphQlRunner::new(
+            &logger,
+            network_store.clone(),
+            Arc::new(PanicSubscriptionManager {}),
+            Arc::new(load_manager),
+            registry,
+        ));
+
+        registrar
+            .create_subgraph(self.subgraph_name.clone())
+            .await?;
+        let deployment = registrar
+            .create_subgraph_version(
+                self.subgraph_name.clone(),
+                self.hash,
+                node_id,
+                None,
+                None,
+                self.graft_block,
+            )
+            .await?;
+
+        Ok(EmbeddedNode {
+            logger: logger_factory.subgraph_logger(&deployment),
+            chain,
+            provider,
+            instance_manager,
+            link_resolver,
+            env_vars,
+            network_store,
+            store: subgraph_store,
+            blockchain_map,
+            graphql_runner,
+            deployment,
+            subgraph_name: self.subgraph_name,
+            sync_timeout: self.sync_timeout,
+        })
+    }
+}
+
+/// A single deployment indexing the blocks of a mock chain. See the
+/// [module documentation](self)
+pub struct EmbeddedNode {
+    logger: Logger,
+    chain: MockChain<Chain>,
+    provider: Arc<IpfsSubgraphAssignmentProvider<SubgraphInstanceManager<SubgraphStore>>>,
+    instance_manager: SubgraphInstanceManager<SubgraphStore>,
+    link_resolver: Arc<LinkResolver>,
+    env_vars: Arc<EnvVars>,
+    network_store: Arc<Store>,
+    store: Arc<SubgraphStore>,
+    blockchain_map: Arc<BlockchainMap>,
+    graphql_runner: Arc<GraphQlRunner>,
+    deployment: DeploymentLocator,
+    subgraph_name: SubgraphName,
+    sync_timeout: Duration,
+}
+
+impl EmbeddedNode {
+    pub fn deployment(&self) -> &DeploymentLocator {
+        &self.deployment
+    }
+
+    /// The chain, for example to change the blocks it streams before the
+    /// deployment is restarted
+    pub fn chain(&self) -> &MockChain<Chain> {
+        &self.chain
+    }
+
+    pub fn store(&self) -> Arc<SubgraphStore> {
+        self.store.cheap_clone()
+    }
+
+    /// The store that holds the data of all chains and deployments
+    pub fn network_store(&self) -> Arc<Store> {
+        self.network_store.cheap_clone()
+    }
+
+    /// The logger of the deployment
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    pub fn provider(
+        &self,
+    ) -> Arc<IpfsSubgraphAssignmentProvider<SubgraphInstanceManager<SubgraphStore>>> {
+        self.provider.cheap_clone()
+    }
+
+    /// The instance manager, for example to build a runner for the
+    /// deployment without starting it
+    pub fn instance_manager(&self) -> &SubgraphInstanceManager<SubgraphStore> {
+        &self.instance_manager
+    }
+
+    pub fn link_resolver(&self) -> Arc<LinkResolver> {
+        self.link_resolver.cheap_clone()
+    }
+
+    pub fn env_vars(&self) -> Arc<EnvVars> {
+        self.env_vars.cheap_clone()
+    }
+
+    pub fn blockchain_map(&self) -> Arc<BlockchainMap> {
+        self.blockchain_map.cheap_clone()
+    }
+
+    pub fn graphql_runner(&self) -> Arc<GraphQlRunner> {
+        self.graphql_runner.cheap_clone()
+    }
+
+    /// Start indexing, and stop after processing `stop_block` if it is
+    /// given. If the deployment is already running, it is restarted
+    pub async fn start(&self, stop_block: Option<BlockNumber>) -> Result<(), Error> {
+        self.stop().await?;
+        self.provider
+            .start(self.deployment.cheap_clone(), stop_block)
+            .await?;
+        Ok(())
+    }
+
+    /// Stop indexing. Stopping a deployment that is not running does
+    /// nothing
+    pub async fn stop(&self) -> Result<(), Error> {
+        self.provider.stop(self.deployment.cheap_clone()).await?;
+        Ok(())
+    }
+
+    /// Wait until the deployment has processed the block `number` and
+    /// return the pointer of the block it is at. Fails if the deployment
+    /// fails before it gets there, or if it does not get there within the
+    /// sync timeout
+    pub async fn wait_for_block(&self, number: BlockNumber) -> Result<BlockPtr, Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(error) = self.fatal_error() {
+                bail!(
+                    "deployment {} failed before reaching block {}: {}",
+                    self.deployment.hash,
+                    number,
+                    error.message
+                );
+            }
+            if let Some(ptr) = self.store.least_block_ptr(&self.deployment.hash).await? {
+                if ptr.number >= number {
+                    return Ok(ptr);
+                }
+                debug!(self.logger, "Waiting for block"; "current" => ptr.number, "target" => number);
+            }
+            if start.elapsed() > self.sync_timeout {
+                bail!(
+                    "deployment {} did not reach block {} within {}s",
+                    self.deployment.hash,
+                    number,
+                    self.sync_timeout.as_secs()
+                );
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// The error that stopped the deployment, if any
+    pub fn fatal_error(&self) -> Option<SubgraphError> {
+        self.store.status_for_id(self.deployment.id).fatal_error
+    }
+
+    /// The latest version of the entity `entity_type` with `id`
+    pub async fn entity(&self, entity_type: &str, id: &str) -> Result<Option<Entity>, Error> {
+        let key = EntityKey {
+            entity_type: EntityType::new(entity_type.to_string()),
+            entity_id: id.into(),
+            causality_region: CausalityRegion::ONCHAIN,
+        };
+        let writable = self
+            .store
+            .cheap_clone()
+            .writable(self.logger.clone(), self.deployment.id)
+            .await?;
+        Ok(writable.get(&key)?)
+    }
+
+    /// Run the GraphQL `query` against the deployment
+    pub async fn query(&self, query: &str) -> Result<Option<r::Value>, Error> {
+        let target = QueryTarget::Deployment(self.deployment.hash.clone(), ApiVersion::default());
+        let query = graphql_parser::parse_query(query)
+            .map_err(|e| anyhow!("invalid query: {}", e))?
+            .into_static();
+        let results = self
+            .graphql_runner
+            .cheap_clone()
+            .run_query(Query::new(query, None, false), target)
+            .await;
+        let result = results
+            .first()
+            .ok_or_else(|| anyhow!("the query did not return a result"))?
+            .duplicate();
+        result.to_result().map_err(|errors| {
+            let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            anyhow!("query failed: {}", errors.join(", "))
+        })
+    }
+}
+
+impl Drop for EmbeddedNode {
+    fn drop(&mut self) {
+        if let Err(e) = cleanup(&self.store, &self.subgraph_name, &self.deployment.hash) {
+            crit!(self.logger, "error cleaning up embedded subgraph"; "error" => e.to_string());
+        }
+    }
+}
+
+/// Remove the subgraph `name` and all deployments of `hash`
+pub fn cleanup(
+    subgraph_store: &SubgraphStore,
+    name: &SubgraphName,
+    hash: &DeploymentHash,
+) -> Result<(), Error> {
+    let locators = subgraph_store.locators(hash)?;
+    subgraph_store.remove_subgraph(name.clone())?;
+    for locator in locators {
+        subgraph_store.remove_deployment(locator.id.into())?;
+    }
+    Ok(())
+}
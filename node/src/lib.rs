@@ -9,8 +9,10 @@ extern crate diesel;
 pub mod bus_initializer;
 pub mod chain;
 pub mod config;
+pub mod embedded;
 pub mod opt;
 pub mod store_builder;
+pub mod test_run;
 
 pub mod manager;
 
@@ -6,6 +6,7 @@ use ethereum::{
 use git_testament::{git_testament, render_testament};
 use graph::blockchain::{Blockchain, BlockchainMap};
 use graph::components::store::BlockStore;
+use graph::components::subgraph::{handler_stats::take_handler_stats, SafeMode};
 use graph::data::graphql::effort::LoadManager;
 use graph::env::EnvVars;
 use graph::firehose::{FirehoseEndpoints, FirehoseNetworks};
@@ -13,14 +14,17 @@ use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, *};
 use graph::prometheus::Registry;
 use graph::url::Url;
+use graph::util::proxy::ProxyTarget;
 use graph_chain_ethereum as ethereum;
 use graph_chain_substreams as substreams;
 use graph_core::polling_monitor::ipfs_service;
 use graph_core::{
-    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    LinkResolver, MetricsRegistry, RunnerShutdown, SafeModeConfig, SafeModeRampUp,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
 use graph_graphql::prelude::GraphQlRunner;
+use graph_node::bus_initializer::BusInitializer;
 use graph_node::chain::{
     connect_ethereum_networks, create_all_ethereum_networks, create_ipfs_clients,
 };
@@ -30,9 +34,12 @@ use graph_node::store_builder::StoreBuilder;
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
-use graph_server_metrics::PrometheusMetricsServer;
+use graph_server_metrics::{PrometheusMetricsServer, PrometheusPushGateway};
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::{register_jobs as register_store_jobs, ChainHeadUpdateListener, Store};
+use graph_store_postgres::{
+    command_support::catalog, connection_pool::ConnectionPool,
+    register_jobs as register_store_jobs, register_query_rate_job, ChainHeadUpdateListener, Store,
+};
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::sync::atomic;
@@ -92,6 +99,19 @@ async fn main() {
     // Set up logger
     let logger = logger(opt.debug);
 
+    if let Some(opt::Command::TestRun {
+        triggers,
+        wasm,
+        data_source,
+    }) = opt.command.clone()
+    {
+        if let Err(e) = graph_node::test_run::run(logger, &triggers, &wasm, data_source).await {
+            eprintln!("test run failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Log version information
     info!(
         logger,
@@ -184,6 +204,7 @@ async fn main() {
     };
 
     info!(logger, "Starting up");
+    ENV_VARS.proxies.log(&logger);
 
     // Optionally, identify the Elasticsearch logging configuration
     let elastic_config = opt
@@ -193,7 +214,11 @@ async fn main() {
             endpoint: endpoint.clone(),
             username: opt.elasticsearch_user.clone(),
             password: opt.elasticsearch_password.clone(),
-            client: reqwest::Client::new(),
+            client: ENV_VARS
+                .proxies
+                .http_client(ProxyTarget::Other)
+                .build()
+                .expect("failed to create the Elasticsearch client"),
         });
 
     // Set up Prometheus registry
@@ -209,12 +234,14 @@ async fn main() {
 
     // Try to create IPFS clients for each URL specified in `--ipfs`
     let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &opt.ipfs);
-    let ipfs_client = ipfs_clients.first().cloned().expect("Missing IPFS client");
+    assert!(!ipfs_clients.is_empty(), "Missing IPFS client");
     let ipfs_service = ipfs_service(
-        ipfs_client,
+        ipfs_clients.clone(),
         ENV_VARS.mappings.max_ipfs_file_bytes as u64,
         ENV_VARS.mappings.ipfs_timeout,
         ENV_VARS.mappings.ipfs_request_limit,
+        ENV_VARS.mappings.ipfs_strict_verification_file_data_sources,
+        logger.clone(),
     );
 
     // Convert the clients into a link resolver. Since we want to get past
@@ -223,6 +250,22 @@ async fn main() {
     let mut metrics_server =
         PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
 
+    // Push metrics to a push gateway in addition to serving them for nodes
+    // that do not live long enough to be scraped
+    let push_gateway = opt.metrics_push_gateway.clone().map(|url| {
+        PrometheusPushGateway::new(
+            &logger_factory,
+            prometheus_registry.clone(),
+            metrics_registry.clone(),
+            &node_id,
+            url,
+            Duration::from_secs(opt.metrics_push_interval),
+        )
+    });
+    if let Some(push_gateway) = &push_gateway {
+        graph::spawn(push_gateway.clone().run());
+    }
+
     // Ethereum clients; query nodes ignore all ethereum clients and never
     // connect to them directly
     let eth_networks = if query_only {
@@ -250,6 +293,13 @@ async fn main() {
     )
     .await;
 
+    // What is needed to record a clean shutdown of this node
+    let shutdown_pool = store_builder.primary_pool();
+    let shutdown_store = store_builder.subgraph_store();
+    let shutdown_node_id = node_id.clone();
+    let runners = RunnerShutdown::default();
+    let shutdown_runners = runners.clone();
+
     let launch_services = |logger: Logger, env_vars: Arc<EnvVars>| async move {
         let subscription_manager = store_builder.subscription_manager();
         let chain_head_update_listener = store_builder.chain_head_update_listener();
@@ -335,11 +385,34 @@ async fn main() {
                 network_store.clone(),
                 primary_pool,
                 metrics_registry.clone(),
+                // Like `graphman prune`, use the setting for eth chains
+                ethereum::ENV_VARS.reorg_threshold,
             );
             graph::spawn_blocking(job_runner.start());
         }
+
+        // Query nodes report the rates of the queries they serve, and index
+        // nodes use them to prioritize deployments
+        let mut query_rate_runner = graph::util::jobs::Runner::new(&logger);
+        register_query_rate_job(
+            &mut query_rate_runner,
+            network_store.clone(),
+            node_id.clone(),
+        );
+        graph::spawn_blocking(query_rate_runner.start());
+
         let static_filters = ENV_VARS.experimental_static_filters;
 
+        let bus_router =
+            BusInitializer::router(&config.bus, logger.clone(), metrics_registry.clone()).await;
+        let bus_router = match bus_router {
+            Ok(router) => Arc::new(router),
+            Err(e) => {
+                eprintln!("bus configuration error: {}", e);
+                std::process::exit(1);
+            }
+        };
+
         let subgraph_instance_manager = SubgraphInstanceManager::new(
             &logger_factory,
             env_vars.cheap_clone(),
@@ -349,8 +422,10 @@ async fn main() {
             link_resolver.clone(),
             ipfs_service,
             static_filters,
-            None,
-        );
+            bus_router,
+        )
+        .with_synced_actions(config.synced.actions())
+        .with_shutdown(&runners);
 
         // Create IPFS-based subgraph provider
         let subgraph_provider = IpfsSubgraphAssignmentProvider::new(
@@ -363,7 +438,7 @@ async fn main() {
         let version_switching_mode = ENV_VARS.subgraph_version_switching_mode;
 
         // Create named subgraph provider for resolving subgraph name->ID mappings
-        let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
+        let mut subgraph_registrar = IpfsSubgraphRegistrar::new(
             &logger_factory,
             link_resolver,
             Arc::new(subgraph_provider),
@@ -372,7 +447,16 @@ async fn main() {
             blockchain_map,
             node_id.clone(),
             version_switching_mode,
-        ));
+        );
+        if !query_only && start_in_safe_mode(&logger, &primary_pool, &node_id) {
+            subgraph_registrar = subgraph_registrar.with_safe_mode(SafeModeRampUp::new(
+                &logger,
+                SafeModeConfig::from_env(&env_vars),
+                network_store.clone(),
+                metrics_registry.clone(),
+            ));
+        }
+        let subgraph_registrar = Arc::new(subgraph_registrar);
 
         if !query_only {
             graph::spawn(
@@ -514,7 +598,93 @@ async fn main() {
         }
     });
 
-    futures::future::pending::<()>().await;
+    shutdown_signal().await;
+    info!(logger, "Shutting down");
+    // Stop the subgraphs, and record the clean shutdown so that the next
+    // start of this node does not think it crashed
+    if !query_only {
+        // Runners write their handler statistics when they stop; what is
+        // left afterwards belongs to runners that did not stop in time
+        if !shutdown_runners.stop_all(RUNNER_SHUTDOWN_TIMEOUT).await {
+            warn!(logger, "Not all subgraphs stopped before the shutdown timeout";
+                  "timeout_s" => RUNNER_SHUTDOWN_TIMEOUT.as_secs());
+        }
+        flush_handler_stats(&logger, &shutdown_store);
+        if let Err(e) = shutdown_pool
+            .get()
+            .and_then(|conn| catalog::Connection::new(conn).record_node_stop(&shutdown_node_id))
+        {
+            warn!(logger, "Failed to record the shutdown of this node"; "error" => e.to_string());
+        }
+    }
+    // Push the metrics one last time so that the push gateway has the
+    // final state of the node
+    if let Some(push_gateway) = push_gateway {
+        info!(logger, "Pushing metrics one last time");
+        push_gateway.push().await;
+    }
+    std::process::exit(0);
+}
+
+/// How long to wait for the subgraph runners to stop when the node shuts
+/// down
+const RUNNER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Write the handler statistics that were recorded since the subgraph
+/// runners last wrote them; they would otherwise be lost on shutdown
+fn flush_handler_stats(logger: &Logger, store: &graph_store_postgres::SubgraphStore) {
+    for (deployment, stats) in take_handler_stats() {
+        let res = match DeploymentHash::new(deployment.as_str()) {
+            Ok(hash) => store
+                .record_handler_stats(&hash, &stats)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(format!("invalid deployment hash `{}`", e)),
+        };
+        if let Err(e) = res {
+            warn!(logger, "Failed to write handler statistics";
+                  "deployment" => deployment, "error" => e);
+        }
+    }
+}
+
+/// Record that this node started, and decide whether to start its
+/// deployments in safe mode
+fn start_in_safe_mode(logger: &Logger, primary_pool: &ConnectionPool, node_id: &NodeId) -> bool {
+    let crashed = primary_pool
+        .get()
+        .and_then(|conn| catalog::Connection::new(conn).record_node_start(node_id));
+    let crashed = match crashed {
+        Ok(crashed) => crashed,
+        Err(e) => {
+            warn!(logger, "Failed to record the start of this node"; "error" => e.to_string());
+            false
+        }
+    };
+    match ENV_VARS.safe_mode {
+        SafeMode::Off => false,
+        SafeMode::On => true,
+        SafeMode::Auto => {
+            if crashed {
+                info!(
+                    logger,
+                    "The previous run of this node did not shut down cleanly, \
+                     starting deployments in safe mode"
+                );
+            }
+            crashed
+        }
+    }
+}
+
+/// Wait until the node is asked to shut down with SIGINT or SIGTERM
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
 
 /// Return the hashmap of ethereum chains and also add them to `blockchain_map`.
@@ -556,6 +726,7 @@ fn ethereum_networks_as_chains(
 
             let adapter_selector = EthereumAdapterSelector::new(
                 logger_factory.clone(),
+                network_name.clone(),
                 Arc::new(eth_adapters.clone()),
                 Arc::new(
                     firehose_endpoints
@@ -569,6 +740,7 @@ fn ethereum_networks_as_chains(
             let runtime_adapter = Arc::new(RuntimeAdapter {
                 eth_adapters: Arc::new(eth_adapters.clone()),
                 call_cache: chain_store.cheap_clone(),
+                chain_store: chain_store.cheap_clone(),
             });
 
             let chain = ethereum::Chain::new(
@@ -78,3 +78,69 @@ impl std::io::Write for Terminal {
         self.out.flush()
     }
 }
+
+/// Output that can be styled like a `Terminal`
+pub trait Styled: std::io::Write {
+    fn green(&mut self) -> CmdResult;
+    fn blue(&mut self) -> CmdResult;
+    fn dim(&mut self) -> CmdResult;
+    fn bold(&mut self) -> CmdResult;
+    fn reset(&mut self) -> CmdResult;
+}
+
+impl Styled for Terminal {
+    fn green(&mut self) -> CmdResult {
+        Terminal::green(self)
+    }
+
+    fn blue(&mut self) -> CmdResult {
+        Terminal::blue(self)
+    }
+
+    fn dim(&mut self) -> CmdResult {
+        Terminal::dim(self)
+    }
+
+    fn bold(&mut self) -> CmdResult {
+        Terminal::bold(self)
+    }
+
+    fn reset(&mut self) -> CmdResult {
+        Terminal::reset(self)
+    }
+}
+
+/// Output that ignores styles, for example to render text into a buffer
+pub struct Plain<'a>(pub &'a mut dyn std::io::Write);
+
+impl std::io::Write for Plain<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Styled for Plain<'_> {
+    fn green(&mut self) -> CmdResult {
+        Ok(())
+    }
+
+    fn blue(&mut self) -> CmdResult {
+        Ok(())
+    }
+
+    fn dim(&mut self) -> CmdResult {
+        Ok(())
+    }
+
+    fn bold(&mut self) -> CmdResult {
+        Ok(())
+    }
+
+    fn reset(&mut self) -> CmdResult {
+        Ok(())
+    }
+}
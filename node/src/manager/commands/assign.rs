@@ -1,15 +1,83 @@
-use graph::prelude::{anyhow::anyhow, Error, NodeId, StoreEvent};
+use std::io::{self, Write};
+
+use graph::prelude::{
+    anyhow::anyhow, EntityChange, EntityChangeOperation, Error, NodeId, StoreEvent,
+};
 use graph_store_postgres::{
     command_support::catalog, connection_pool::ConnectionPool, NotificationSender,
 };
+use serde::Serialize;
 
 use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentChange {
+    Assigned,
+    Reassigned,
+    Unchanged,
+    Unassigned,
+}
+
+/// How the assignment of a deployment changed
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AssignmentOutput {
+    pub deployment: DeploymentRef,
+    pub change: AssignmentChange,
+    /// The node the deployment is assigned to now
+    pub node: Option<String>,
+    /// The node the deployment was assigned to before
+    pub previous_node: Option<String>,
+}
+
+impl CommandOutput for AssignmentOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        let node = self.node.as_deref().unwrap_or("-");
+        let previous = self.previous_node.as_deref().unwrap_or("-");
+        match self.change {
+            AssignmentChange::Assigned => writeln!(out, "assigned {deployment} to {node}"),
+            AssignmentChange::Reassigned => {
+                writeln!(out, "reassigned {deployment} to {node} (was {previous})")
+            }
+            AssignmentChange::Unchanged => {
+                writeln!(out, "deployment {deployment} is already assigned to {node}")
+            }
+            AssignmentChange::Unassigned => writeln!(out, "unassigned {deployment}"),
+        }
+    }
+}
+
+/// The node that was asked to start a deployment right away
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ForceStartOutput {
+    pub deployment: DeploymentRef,
+    pub node: String,
+}
+
+impl CommandOutput for ForceStartOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "asked {} to start {}", self.node, self.deployment)
+    }
+}
 
 pub async fn unassign(
     primary: ConnectionPool,
     sender: &NotificationSender,
     search: &DeploymentSearch,
 ) -> Result<(), Error> {
+    let output = unassign_deployment(primary, sender, search).await?;
+    output::emit(&output)
+}
+
+/// Unassign the deployment that `search` finds and report what changed
+/// without printing anything
+pub async fn unassign_deployment(
+    primary: ConnectionPool,
+    sender: &NotificationSender,
+    search: &DeploymentSearch,
+) -> Result<AssignmentOutput, Error> {
     let locator = search.locate_unique(&primary)?;
 
     let conn = primary.get()?;
@@ -19,11 +87,16 @@ pub async fn unassign(
         .locate_site(locator.clone())?
         .ok_or_else(|| anyhow!("failed to locate site for {locator}"))?;
 
-    println!("unassigning {locator}");
+    let previous_node = conn.assigned_node(&site)?;
     let changes = conn.unassign_subgraph(&site)?;
     conn.send_store_event(sender, &StoreEvent::new(changes))?;
 
-    Ok(())
+    Ok(AssignmentOutput {
+        deployment: DeploymentRef::from(&locator),
+        change: AssignmentChange::Unassigned,
+        node: None,
+        previous_node: previous_node.map(|node| node.to_string()),
+    })
 }
 
 pub fn reassign(
@@ -32,7 +105,8 @@ pub fn reassign(
     search: &DeploymentSearch,
     node: String,
 ) -> Result<(), Error> {
-    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("illegal node id `{}`", node))?;
+    let node = NodeId::new(node.clone())
+        .map_err(|()| CommandError::invalid_argument(format!("illegal node id `{}`", node)))?;
     let locator = search.locate_unique(&primary)?;
 
     let conn = primary.get()?;
@@ -41,22 +115,59 @@ pub fn reassign(
     let site = conn
         .locate_site(locator.clone())?
         .ok_or_else(|| anyhow!("failed to locate site for {locator}"))?;
-    let changes = match conn.assigned_node(&site)? {
+    let previous_node = conn.assigned_node(&site)?;
+    let (change, changes) = match &previous_node {
         Some(cur) => {
-            if cur == node {
-                println!("deployment {locator} is already assigned to {cur}");
-                vec![]
+            if cur == &node {
+                (AssignmentChange::Unchanged, vec![])
             } else {
-                println!("reassigning {locator} to {node} (was {cur})");
-                conn.reassign_subgraph(&site, &node)?
+                let changes = conn.reassign_subgraph(&site, &node)?;
+                (AssignmentChange::Reassigned, changes)
             }
         }
         None => {
-            println!("assigning {locator} to {node}");
-            conn.assign_subgraph(&site, &node)?
+            let changes = conn.assign_subgraph(&site, &node)?;
+            (AssignmentChange::Assigned, changes)
         }
     };
     conn.send_store_event(sender, &StoreEvent::new(changes))?;
 
-    Ok(())
+    output::emit(&AssignmentOutput {
+        deployment: DeploymentRef::from(&locator),
+        change,
+        node: Some(node.to_string()),
+        previous_node: previous_node.map(|node| node.to_string()),
+    })
+}
+
+/// Ask the node that a deployment is assigned to to start it right away,
+/// even if the node is still starting deployments in safe mode and the
+/// deployment has not had its turn yet
+pub fn force_start(
+    primary: ConnectionPool,
+    sender: &NotificationSender,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let conn = primary.get()?;
+    let conn = catalog::Connection::new(conn);
+
+    let site = conn
+        .locate_site(locator.clone())?
+        .ok_or_else(|| anyhow!("failed to locate site for {locator}"))?;
+    let node = conn
+        .assigned_node(&site)?
+        .ok_or_else(|| anyhow!("deployment {locator} is not assigned to any node"))?;
+
+    // The assignment itself does not change; announcing it again makes the
+    // node start the deployment if it is not running yet
+    let deployment = DeploymentRef::from(&locator);
+    let change = EntityChange::for_assignment(locator, EntityChangeOperation::Set);
+    conn.send_store_event(sender, &StoreEvent::new(vec![change]))?;
+
+    output::emit(&ForceStartOutput {
+        deployment,
+        node: node.to_string(),
+    })
 }
@@ -0,0 +1,127 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::components::store::EntityType;
+use graph::prelude::anyhow::Error;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CriticalTypesOutput {
+    pub deployment: DeploymentRef,
+    pub entity_types: Vec<String>,
+}
+
+impl CommandOutput for CriticalTypesOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.entity_types.is_empty() {
+            writeln!(out, "{} has no critical entity types", self.deployment)?;
+        }
+        for entity_type in &self.entity_types {
+            writeln!(out, "{}", entity_type)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CriticalTypesSet {
+    pub deployment: DeploymentRef,
+    pub entity_types: Vec<String>,
+}
+
+impl CommandOutput for CriticalTypesSet {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        if self.entity_types.is_empty() {
+            writeln!(out, "{deployment} has no critical entity types")?;
+        } else {
+            writeln!(
+                out,
+                "the critical entity types of {deployment} are {}",
+                self.entity_types.join(", ")
+            )?;
+        }
+        writeln!(
+            out,
+            "the runner uses the new entity types the next time {deployment} is started"
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeadLettersAcknowledged {
+    pub deployment: DeploymentRef,
+    /// The backend whose dead letters were acknowledged; all if `None`
+    pub backend: Option<String>,
+    pub count: usize,
+}
+
+impl CommandOutput for DeadLettersAcknowledged {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "acknowledged {} dead letters of {}",
+            self.count, self.deployment
+        )
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let entity_types = store
+        .bus_critical_entity_types(&locator)?
+        .iter()
+        .map(|entity_type| entity_type.to_string())
+        .collect();
+    output::emit(&CriticalTypesOutput {
+        deployment: DeploymentRef::from(&locator),
+        entity_types,
+    })
+}
+
+/// Make `entity_types` the critical entity types of the deployment; with
+/// none, no entity type is critical
+pub fn set(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    entity_types: Vec<String>,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let entity_types: BTreeSet<_> = entity_types.into_iter().map(EntityType::from).collect();
+    store.set_bus_critical_entity_types(&locator, &entity_types)?;
+
+    output::emit(&CriticalTypesSet {
+        deployment: DeploymentRef::from(&locator),
+        entity_types: entity_types.iter().map(|t| t.to_string()).collect(),
+    })
+}
+
+/// Forget the dead letters of the deployment so that the delivery of its
+/// critical entity types is tracked again
+pub fn acknowledge(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    backend: Option<String>,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let count = store.acknowledge_bus_dead_letters(&locator, backend.as_deref())?;
+    output::emit(&DeadLettersAcknowledged {
+        deployment: DeploymentRef::from(&locator),
+        backend,
+        count,
+    })
+}
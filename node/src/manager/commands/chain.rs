@@ -1,3 +1,4 @@
+use std::io::{self, Write};
 use std::sync::Arc;
 
 use graph::blockchain::BlockPtr;
@@ -6,7 +7,6 @@ use graph::prelude::BlockNumber;
 use graph::prelude::ChainStore as _;
 use graph::prelude::EthereumBlock;
 use graph::prelude::LightEthereumBlockExt as _;
-use graph::prelude::{anyhow, anyhow::bail};
 use graph::{
     components::store::BlockStore as _, prelude::anyhow::Error, prelude::serde_json as json,
 };
@@ -15,6 +15,183 @@ use graph_store_postgres::ChainStore;
 use graph_store_postgres::{
     command_support::catalog::block_store, connection_pool::ConnectionPool,
 };
+use serde::Serialize;
+
+use crate::manager::output::{self, CommandError, CommandOutput, ErrorCode};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainInfo {
+    pub name: String,
+    pub shard: String,
+    pub namespace: String,
+    pub net_version: String,
+    /// The head block; `None` if the chain has none or its store is not
+    /// configured
+    pub head_block: Option<BlockNumber>,
+    /// Whether the node has a store for the chain
+    pub configured: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainListOutput {
+    pub chains: Vec<ChainInfo>,
+}
+
+impl CommandOutput for ChainListOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.chains.is_empty() {
+            writeln!(
+                out,
+                "{:^20} | {:^10} | {:^10} | {:^7} | {:^10}",
+                "name", "shard", "namespace", "version", "head block"
+            )?;
+            writeln!(
+                out,
+                "{:-^20}-+-{:-^10}-+-{:-^10}-+-{:-^7}-+-{:-^10}",
+                "", "", "", "", ""
+            )?;
+        }
+        for chain in &self.chains {
+            let head_block = match (chain.configured, chain.head_block) {
+                (false, _) => "no chain".to_string(),
+                (true, None) => "none".to_string(),
+                (true, Some(number)) => number.to_string(),
+            };
+            writeln!(
+                out,
+                "{:<20} | {:<10} | {:<10} | {:>7} | {:>10}",
+                chain.name, chain.shard, chain.namespace, chain.net_version, head_block
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CallCacheCleared {
+    pub from: Option<i32>,
+    pub to: Option<i32>,
+}
+
+impl CommandOutput for CallCacheCleared {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "The call cache has cleared")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Block {
+    pub number: BlockNumber,
+    pub hash: String,
+}
+
+impl From<BlockPtr> for Block {
+    fn from(ptr: BlockPtr) -> Self {
+        Block {
+            number: ptr.number,
+            hash: ptr.hash.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainDetails {
+    pub name: String,
+    pub shard: String,
+    pub namespace: String,
+    pub net_version: String,
+    pub genesis: String,
+    pub head_block: Option<Block>,
+    pub reorg_threshold: BlockNumber,
+    /// The block `reorg_threshold` blocks before the head block
+    pub reorg_ancestor: Option<Block>,
+    /// Whether the text output includes block hashes
+    #[serde(skip)]
+    pub hashes: bool,
+}
+
+impl CommandOutput for ChainDetails {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        fn row(out: &mut dyn Write, label: &str, value: impl std::fmt::Display) -> io::Result<()> {
+            writeln!(out, "{:<16} | {}", label, value)
+        }
+
+        fn block(
+            out: &mut dyn Write,
+            label: &str,
+            block: &Option<Block>,
+            hashes: bool,
+        ) -> io::Result<()> {
+            match block {
+                None => row(out, label, "ø"),
+                Some(block) => {
+                    row(out, label, block.number)?;
+                    if hashes {
+                        row(out, "", &block.hash)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        row(out, "name", &self.name)?;
+        row(out, "shard", &self.shard)?;
+        row(out, "namespace", &self.namespace)?;
+        row(out, "net_version", &self.net_version)?;
+        if self.hashes {
+            row(out, "genesis", &self.genesis)?;
+        }
+        block(out, "head block", &self.head_block, self.hashes)?;
+        row(out, "reorg threshold", self.reorg_threshold)?;
+        block(out, "reorg ancestor", &self.reorg_ancestor, self.hashes)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainDeployment {
+    pub namespace: String,
+    pub deployment: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainRemoveOutput {
+    pub chain: String,
+    pub removed: bool,
+    /// The deployments that use the chain and keep it from being removed
+    pub deployments: Vec<ChainDeployment>,
+}
+
+impl CommandOutput for ChainRemoveOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.removed {
+            return writeln!(out, "removed chain {}", self.chain);
+        }
+        writeln!(
+            out,
+            "there are {} deployments using chain {}:",
+            self.deployments.len(),
+            self.chain
+        )?;
+        for deployment in &self.deployments {
+            writeln!(
+                out,
+                "{:<8} | {} ",
+                deployment.namespace, deployment.deployment
+            )?;
+        }
+        Ok(())
+    }
+
+    fn failure(&self) -> Option<CommandError> {
+        if self.removed {
+            return None;
+        }
+        Some(CommandError::new(
+            ErrorCode::Failed,
+            format!("remove all deployments using chain {} first", self.chain),
+        ))
+    }
+}
 
 pub async fn list(primary: ConnectionPool, store: Arc<BlockStore>) -> Result<(), Error> {
     let mut chains = {
@@ -23,31 +200,25 @@ pub async fn list(primary: ConnectionPool, store: Arc<BlockStore>) -> Result<(),
     };
     chains.sort_by_key(|chain| chain.name.clone());
 
-    if !chains.is_empty() {
-        println!(
-            "{:^20} | {:^10} | {:^10} | {:^7} | {:^10}",
-            "name", "shard", "namespace", "version", "head block"
-        );
-        println!(
-            "{:-^20}-+-{:-^10}-+-{:-^10}-+-{:-^7}-+-{:-^10}",
-            "", "", "", "", ""
-        );
-    }
+    let mut infos = Vec::with_capacity(chains.len());
     for chain in chains {
-        let head_block = match store.chain_store(&chain.name) {
-            None => "no chain".to_string(),
-            Some(chain_store) => chain_store
-                .chain_head_ptr()
-                .await?
-                .map(|ptr| ptr.number.to_string())
-                .unwrap_or("none".to_string()),
+        let (configured, head_block) = match store.chain_store(&chain.name) {
+            None => (false, None),
+            Some(chain_store) => (
+                true,
+                chain_store.chain_head_ptr().await?.map(|ptr| ptr.number),
+            ),
         };
-        println!(
-            "{:<20} | {:<10} | {:<10} | {:>7} | {:>10}",
-            chain.name, chain.shard, chain.storage, chain.net_version, head_block
-        );
+        infos.push(ChainInfo {
+            name: chain.name,
+            shard: chain.shard.to_string(),
+            namespace: chain.storage.to_string(),
+            net_version: chain.net_version,
+            head_block,
+            configured,
+        });
     }
-    Ok(())
+    output::emit(&ChainListOutput { chains: infos })
 }
 
 pub async fn clear_call_cache(
@@ -56,8 +227,7 @@ pub async fn clear_call_cache(
     to: Option<i32>,
 ) -> Result<(), Error> {
     chain_store.clear_call_cache(from, to).await?;
-    println!("The call cache has cleared");
-    Ok(())
+    output::emit(&CallCacheCleared { from, to })
 }
 
 pub async fn info(
@@ -67,32 +237,14 @@ pub async fn info(
     offset: BlockNumber,
     hashes: bool,
 ) -> Result<(), Error> {
-    fn row(label: &str, value: impl std::fmt::Display) {
-        println!("{:<16} | {}", label, value.to_string());
-    }
-
-    fn print_ptr(label: &str, ptr: Option<BlockPtr>, hashes: bool) {
-        match ptr {
-            None => {
-                row(label, "ø");
-            }
-            Some(ptr) => {
-                row(label, ptr.number);
-                if hashes {
-                    row("", ptr.hash);
-                }
-            }
-        }
-    }
-
     let conn = primary.get()?;
 
-    let chain =
-        block_store::find_chain(&conn, &name)?.ok_or_else(|| anyhow!("unknown chain: {}", name))?;
+    let chain = block_store::find_chain(&conn, &name)?
+        .ok_or_else(|| CommandError::not_found(format!("unknown chain: {}", name)))?;
 
     let chain_store = store
         .chain_store(&chain.name)
-        .ok_or_else(|| anyhow!("unknown chain: {}", name))?;
+        .ok_or_else(|| CommandError::not_found(format!("unknown chain: {}", name)))?;
     let head_block = chain_store.cheap_clone().chain_head_ptr().await?;
     let ancestor = match &head_block {
         None => None,
@@ -104,18 +256,17 @@ pub async fn info(
             .map(|b| b.block.block_ptr()),
     };
 
-    row("name", chain.name);
-    row("shard", chain.shard);
-    row("namespace", chain.storage);
-    row("net_version", chain.net_version);
-    if hashes {
-        row("genesis", chain.genesis_block);
-    }
-    print_ptr("head block", head_block, hashes);
-    row("reorg threshold", offset);
-    print_ptr("reorg ancestor", ancestor, hashes);
-
-    Ok(())
+    output::emit(&ChainDetails {
+        name: chain.name,
+        shard: chain.shard.to_string(),
+        namespace: chain.storage.to_string(),
+        net_version: chain.net_version,
+        genesis: chain.genesis_block,
+        head_block: head_block.map(Block::from),
+        reorg_threshold: offset,
+        reorg_ancestor: ancestor.map(Block::from),
+        hashes,
+    })
 }
 
 pub fn remove(primary: ConnectionPool, store: Arc<BlockStore>, name: String) -> Result<(), Error> {
@@ -124,19 +275,20 @@ pub fn remove(primary: ConnectionPool, store: Arc<BlockStore>, name: String) ->
         conn.find_sites_for_network(&name)?
     };
 
-    if !sites.is_empty() {
-        println!(
-            "there are {} deployments using chain {}:",
-            sites.len(),
-            name
-        );
-        for site in sites {
-            println!("{:<8} | {} ", site.namespace, site.deployment);
-        }
-        bail!("remove all deployments using chain {} first", name);
+    let removed = sites.is_empty();
+    if removed {
+        store.drop_chain(&name)?;
     }
 
-    store.drop_chain(&name)?;
-
-    Ok(())
+    output::emit(&ChainRemoveOutput {
+        chain: name,
+        removed,
+        deployments: sites
+            .iter()
+            .map(|site| ChainDeployment {
+                namespace: site.namespace.to_string(),
+                deployment: site.deployment.to_string(),
+            })
+            .collect(),
+    })
 }
@@ -1,6 +1,7 @@
+use crate::manager::output::{self, CommandError, CommandOutput};
 use crate::manager::prompt::prompt_for_confirmation;
 use graph::{
-    anyhow::{bail, ensure},
+    anyhow::ensure,
     components::store::ChainStore as ChainStoreTrait,
     prelude::{
         anyhow::{self, anyhow, Context},
@@ -10,16 +11,131 @@ use graph::{
 };
 use graph_chain_ethereum::{EthereumAdapter, EthereumAdapterTrait};
 use graph_store_postgres::ChainStore;
+use serde::Serialize;
+use std::io::{self, Write};
 use std::sync::Arc;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckOutcome {
+    /// The cached block is the same as the block from the provider
+    Matches,
+    /// The cached block differs from the block from the provider and was
+    /// deleted from the cache
+    Diverged,
+    /// The cache has no block with the number
+    Missing,
+    /// The cache has several blocks with the number; they were only
+    /// deleted if that was asked for
+    Duplicates,
+}
+
+/// The outcome of checking the cached blocks for one block number or hash
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CheckedBlock {
+    /// The number of the block; `None` when checking by hash
+    pub number: Option<i32>,
+    /// The hashes of the cached blocks that were checked
+    pub hashes: Vec<String>,
+    pub outcome: CheckOutcome,
+    /// How the cached block differs from the block from the provider
+    pub diff: Option<String>,
+    /// Whether blocks were deleted from the cache
+    pub deleted: bool,
+}
+
+impl CheckedBlock {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let number = self.number.unwrap_or_default();
+        match self.outcome {
+            CheckOutcome::Matches => writeln!(
+                out,
+                "Cached block {} is equal to the same block from provider.",
+                self.hashes.join(", ")
+            ),
+            CheckOutcome::Diverged => {
+                for hash in &self.hashes {
+                    writeln!(out, "block {hash} diverges from cache:")?;
+                }
+                if let Some(diff) = &self.diff {
+                    writeln!(out, "{diff}")?;
+                }
+                for hash in &self.hashes {
+                    writeln!(out, "Deleted block {hash} from cache.")?;
+                }
+                Ok(())
+            }
+            CheckOutcome::Missing => writeln!(out, "Found no block hash with number {number}"),
+            CheckOutcome::Duplicates => {
+                writeln!(
+                    out,
+                    "graphman found {} different block hashes for block number {} in the store \
+                     and is unable to tell which one to check:",
+                    self.hashes.len(),
+                    number
+                )?;
+                for (num, hash) in self.hashes.iter().enumerate() {
+                    writeln!(out, "{:>4}:  {hash}", num + 1)?;
+                }
+                if self.deleted {
+                    writeln!(out, "Deleted the duplicated blocks.")
+                } else {
+                    writeln!(
+                        out,
+                        "Operation aborted for block number {number}.\n\
+                         To delete the duplicated blocks and continue this operation, rerun this command with \
+                         the `--delete-duplicates` option."
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CheckBlocksOutput {
+    pub blocks: Vec<CheckedBlock>,
+}
+
+impl CommandOutput for CheckBlocksOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for block in &self.blocks {
+            block.render_text(out)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TruncateOutput {
+    pub chain: String,
+    /// Whether the block cache was truncated; `false` if the user did not
+    /// confirm
+    pub truncated: bool,
+}
+
+impl CommandOutput for TruncateOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.truncated {
+            writeln!(out, "Deleted all cached blocks for {}.", self.chain)
+        } else {
+            writeln!(out, "Aborting.")
+        }
+    }
+}
+
 pub async fn by_hash(
     hash: &str,
     chain_store: Arc<ChainStore>,
     ethereum_adapter: &EthereumAdapter,
     logger: &Logger,
 ) -> anyhow::Result<()> {
-    let block_hash = helpers::parse_block_hash(hash)?;
-    run(&block_hash, &chain_store, ethereum_adapter, logger).await
+    let block_hash = helpers::parse_block_hash(hash)
+        .map_err(|e| CommandError::invalid_argument(format!("invalid block hash `{hash}`: {e}")))?;
+    let block = run(None, &block_hash, &chain_store, ethereum_adapter, logger).await?;
+    output::emit(&CheckBlocksOutput {
+        blocks: vec![block],
+    })
 }
 
 pub async fn by_number(
@@ -29,16 +145,23 @@ pub async fn by_number(
     logger: &Logger,
     delete_duplicates: bool,
 ) -> anyhow::Result<()> {
-    let block_hashes = steps::resolve_block_hash_from_block_number(number, &chain_store)?;
-
-    match &block_hashes.as_slice() {
-        [] => bail!("Could not find a block with number {} in store", number),
-        [block_hash] => run(block_hash, &chain_store, ethereum_adapter, logger).await,
-        &block_hashes => {
-            handle_multiple_block_hashes(number, block_hashes, &chain_store, delete_duplicates)
-                .await
-        }
+    let block = check_number(
+        number,
+        &chain_store,
+        ethereum_adapter,
+        logger,
+        delete_duplicates,
+    )
+    .await?;
+    if block.outcome == CheckOutcome::Missing {
+        return Err(CommandError::not_found(format!(
+            "Could not find a block with number {} in store",
+            number
+        )));
     }
+    output::emit(&CheckBlocksOutput {
+        blocks: vec![block],
+    })
 }
 
 pub async fn by_range(
@@ -50,7 +173,8 @@ pub async fn by_range(
     delete_duplicates: bool,
 ) -> anyhow::Result<()> {
     // Resolve a range of block numbers into a collection of blocks hashes
-    let range = ranges::Range::new(range_from, range_to)?;
+    let range = ranges::Range::new(range_from, range_to)
+        .map_err(|e| CommandError::invalid_argument(e.to_string()))?;
     let max = match range.upper_bound {
         // When we have an open upper bound, we use the chain head's block number
         None => steps::find_chain_head(&chain_store)?,
@@ -58,24 +182,20 @@ pub async fn by_range(
     };
     // FIXME: This performs poorly.
     // TODO: This could be turned into async code
+    let mut blocks = Vec::new();
     for block_number in range.lower_bound..=max {
-        println!("Checking block [{block_number}/{max}]");
-        let block_hashes = steps::resolve_block_hash_from_block_number(block_number, &chain_store)?;
-        match &block_hashes.as_slice() {
-            [] => eprintln!("Found no block hash with number {block_number}"),
-            [block_hash] => run(block_hash, &chain_store, ethereum_adapter, logger).await?,
-            &block_hashes => {
-                handle_multiple_block_hashes(
-                    block_number,
-                    block_hashes,
-                    &chain_store,
-                    delete_duplicates,
-                )
-                .await?
-            }
-        }
+        output::progress(format!("Checking block [{block_number}/{max}]"));
+        let block = check_number(
+            block_number,
+            &chain_store,
+            ethereum_adapter,
+            logger,
+            delete_duplicates,
+        )
+        .await?;
+        blocks.push(block);
     }
-    Ok(())
+    output::emit(&CheckBlocksOutput { blocks })
 }
 
 pub fn truncate(chain_store: Arc<ChainStore>, skip_confirmation: bool) -> anyhow::Result<()> {
@@ -83,61 +203,90 @@ pub fn truncate(chain_store: Arc<ChainStore>, skip_confirmation: bool) -> anyhow
         "This will delete all cached blocks for {}.\nProceed?",
         chain_store.chain
     );
-    if !skip_confirmation && !prompt_for_confirmation(&prompt)? {
-        println!("Aborting.");
-        return Ok(());
+    let truncated = skip_confirmation || prompt_for_confirmation(&prompt)?;
+    if truncated {
+        chain_store
+            .truncate_block_cache()
+            .with_context(|| format!("Failed to truncate block cache for {}", chain_store.chain))?;
     }
+    output::emit(&TruncateOutput {
+        chain: chain_store.chain.clone(),
+        truncated,
+    })
+}
 
-    chain_store
-        .truncate_block_cache()
-        .with_context(|| format!("Failed to truncate block cache for {}", chain_store.chain))
+/// Check the cached blocks with number `number`
+async fn check_number(
+    number: i32,
+    chain_store: &ChainStore,
+    ethereum_adapter: &EthereumAdapter,
+    logger: &Logger,
+    delete_duplicates: bool,
+) -> anyhow::Result<CheckedBlock> {
+    let block_hashes = steps::resolve_block_hash_from_block_number(number, chain_store)?;
+    match block_hashes.as_slice() {
+        [] => Ok(CheckedBlock {
+            number: Some(number),
+            hashes: vec![],
+            outcome: CheckOutcome::Missing,
+            diff: None,
+            deleted: false,
+        }),
+        [block_hash] => {
+            run(
+                Some(number),
+                block_hash,
+                chain_store,
+                ethereum_adapter,
+                logger,
+            )
+            .await
+        }
+        block_hashes => {
+            if delete_duplicates {
+                for hash in block_hashes {
+                    steps::delete_block(hash, chain_store)?;
+                }
+            }
+            Ok(CheckedBlock {
+                number: Some(number),
+                hashes: block_hashes
+                    .iter()
+                    .map(|hash| format!("{hash:?}"))
+                    .collect(),
+                outcome: CheckOutcome::Duplicates,
+                diff: None,
+                deleted: delete_duplicates,
+            })
+        }
+    }
 }
 
 async fn run(
+    number: Option<i32>,
     block_hash: &H256,
     chain_store: &ChainStore,
     ethereum_adapter: &EthereumAdapter,
     logger: &Logger,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<CheckedBlock> {
     let cached_block = steps::fetch_single_cached_block(*block_hash, chain_store)?;
     let provider_block =
         steps::fetch_single_provider_block(block_hash, ethereum_adapter, logger).await?;
     let diff = steps::diff_block_pair(&cached_block, &provider_block);
-    steps::report_difference(diff.as_deref(), block_hash);
-    if diff.is_some() {
-        steps::delete_block(block_hash, chain_store)?;
-    }
-    Ok(())
-}
-
-async fn handle_multiple_block_hashes(
-    block_number: i32,
-    block_hashes: &[H256],
-    chain_store: &ChainStore,
-    delete_duplicates: bool,
-) -> anyhow::Result<()> {
-    println!(
-        "graphman found {} different block hashes for block number {} in the store \
-         and is unable to tell which one to check:",
-        block_hashes.len(),
-        block_number
-    );
-    for (num, hash) in block_hashes.iter().enumerate() {
-        println!("{:>4}:  {hash:?}", num + 1);
-    }
-    if delete_duplicates {
-        println!("Deleting duplicated blocks...");
-        for hash in block_hashes {
-            steps::delete_block(hash, chain_store)?;
+    let outcome = match &diff {
+        None => CheckOutcome::Matches,
+        Some(_) => {
+            steps::delete_block(block_hash, chain_store)?;
+            CheckOutcome::Diverged
         }
-    } else {
-        eprintln!(
-            "Operation aborted for block number {block_number}.\n\
-             To delete the duplicated blocks and continue this operation, rerun this command with \
-             the `--delete-duplicates` option."
-        )
-    }
-    Ok(())
+    };
+    Ok(CheckedBlock {
+        number,
+        hashes: vec![format!("{block_hash:?}")],
+        outcome,
+        deleted: diff.is_some(),
+        diff,
+    })
 }
 
 mod steps {
@@ -226,21 +375,9 @@ mod steps {
         }
     }
 
-    /// Prints the difference between two [`serde_json::Value`] values to the user.
-    pub(super) fn report_difference(difference: Option<&str>, hash: &H256) {
-        if let Some(diff) = difference {
-            eprintln!("block {hash} diverges from cache:");
-            eprintln!("{diff}");
-        } else {
-            println!("Cached block is equal to the same block from provider.")
-        }
-    }
-
     /// Attempts to delete a block from the block cache.
     pub(super) fn delete_block(hash: &H256, chain_store: &ChainStore) -> anyhow::Result<()> {
-        println!("Deleting block {hash} from cache.");
         chain_store.delete_blocks(&[hash])?;
-        println!("Done.");
         Ok(())
     }
 
@@ -0,0 +1,153 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::prelude::{
+    anyhow::{anyhow, Error},
+    EntityChange, EntityChangeOperation, StoreEvent,
+};
+use graph_store_postgres::{
+    command_support::catalog, connection_pool::ConnectionPool, NotificationSender, SubgraphStore,
+};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Fingerprint {
+    pub hash: String,
+    /// One `setting: value` entry for each setting the fingerprint covers
+    pub settings: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Mismatch {
+    pub hash: String,
+    /// How the settings differ from the recorded fingerprint
+    pub changes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompatOutput {
+    pub deployment: DeploymentRef,
+    pub recorded: Fingerprint,
+    /// The fingerprint the deployment was last started with if it is not
+    /// the recorded one
+    pub mismatch: Option<Mismatch>,
+}
+
+impl CommandOutput for CompatOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "recorded fingerprint: {}", self.recorded.hash)?;
+        for setting in &self.recorded.settings {
+            writeln!(out, "  {}", setting)?;
+        }
+        match &self.mismatch {
+            None => writeln!(
+                out,
+                "the deployment was last started with the recorded fingerprint"
+            ),
+            Some(mismatch) => {
+                writeln!(out, "last started with:    {}", mismatch.hash)?;
+                for change in &mismatch.changes {
+                    writeln!(out, "  {}", change)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompatAckOutput {
+    pub deployment: DeploymentRef,
+    /// Whether there was a changed fingerprint to acknowledge
+    pub acknowledged: bool,
+    /// The node that was asked to restart the deployment
+    pub restarted_on: Option<String>,
+}
+
+impl CommandOutput for CompatAckOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        if !self.acknowledged {
+            return writeln!(
+                out,
+                "the fingerprint of {deployment} did not change; nothing to acknowledge"
+            );
+        }
+        writeln!(out, "acknowledged the changed fingerprint of {deployment}")?;
+        match &self.restarted_on {
+            Some(node) => writeln!(out, "restarting {deployment} on {node}"),
+            None => writeln!(
+                out,
+                "{deployment} is not assigned to a node; assign it to continue indexing"
+            ),
+        }
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let (recorded, mismatch) = store.compatibility(&locator)?.ok_or_else(|| {
+        CommandError::not_found(format!(
+            "no fingerprint recorded for {locator}; it was never started"
+        ))
+    })?;
+
+    output::emit(&CompatOutput {
+        deployment: DeploymentRef::from(&locator),
+        recorded: Fingerprint {
+            hash: recorded.hash(),
+            settings: recorded.description().lines().map(str::to_string).collect(),
+        },
+        mismatch: mismatch.map(|mismatch| Mismatch {
+            hash: mismatch.hash(),
+            changes: mismatch.changes_from(&recorded),
+        }),
+    })
+}
+
+/// Accept the changes to the compatibility fingerprint of the deployment
+/// and restart it if it is assigned to a node
+pub fn ack(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    sender: &NotificationSender,
+    search: &DeploymentSearch,
+    note: Option<String>,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+    let deployment = DeploymentRef::from(&locator);
+
+    if !store.acknowledge_compatibility(&locator, note.as_deref())? {
+        return output::emit(&CompatAckOutput {
+            deployment,
+            acknowledged: false,
+            restarted_on: None,
+        });
+    }
+
+    let conn = catalog::Connection::new(primary.get()?);
+    let site = conn
+        .locate_site(locator.clone())?
+        .ok_or_else(|| anyhow!("failed to locate site for {locator}"))?;
+    let node = conn.assigned_node(&site)?;
+    if node.is_some() {
+        let changes = vec![
+            EntityChange::for_assignment(locator.clone(), EntityChangeOperation::Removed),
+            EntityChange::for_assignment(locator, EntityChangeOperation::Set),
+        ];
+        conn.send_store_event(sender, &StoreEvent::new(changes))?;
+    }
+    output::emit(&CompatAckOutput {
+        deployment,
+        acknowledged: true,
+        restarted_on: node.map(|node| node.to_string()),
+    })
+}
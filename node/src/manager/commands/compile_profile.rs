@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::env::ENV_VARS;
+use graph::prelude::anyhow::Error;
+use graph::runtime::CompileProfile;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LastCompilation {
+    pub profile: String,
+    pub compile_time_ms: u128,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompileProfileOutput {
+    pub deployment: DeploymentRef,
+    /// The profile set for the deployment; `None` if it uses the default
+    /// of the node that runs it
+    pub profile: Option<String>,
+    /// The default profile of the node that ran the command
+    pub node_default: String,
+    pub last_compilation: Option<LastCompilation>,
+}
+
+impl CommandOutput for CompileProfileOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.profile {
+            Some(profile) => writeln!(out, "profile:          {}", profile)?,
+            None => writeln!(
+                out,
+                "profile:          node default ({} on this node)",
+                self.node_default
+            )?,
+        }
+        match &self.last_compilation {
+            Some(compilation) => {
+                writeln!(out, "last compiled as: {}", compilation.profile)?;
+                writeln!(out, "compile time:     {}ms", compilation.compile_time_ms)
+            }
+            None => writeln!(out, "last compiled as: never compiled"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompileProfileSet {
+    pub deployment: DeploymentRef,
+    /// The new profile; `None` if the deployment uses the default of the
+    /// node that runs it
+    pub profile: Option<String>,
+}
+
+impl CommandOutput for CompileProfileSet {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        match &self.profile {
+            Some(profile) => writeln!(out, "set the compile profile of {deployment} to {profile}")?,
+            None => writeln!(
+                out,
+                "{deployment} uses the compile profile of the node that runs it"
+            )?,
+        }
+        writeln!(
+            out,
+            "the profile is used the next time {deployment} is started"
+        )
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let (profile, compiled) = store.compilation(&locator)?;
+    output::emit(&CompileProfileOutput {
+        deployment: DeploymentRef::from(&locator),
+        profile: profile.map(|profile| profile.to_string()),
+        node_default: ENV_VARS.mappings.compile_profile.to_string(),
+        last_compilation: compiled.map(|(profile, compile_time)| LastCompilation {
+            profile: profile.to_string(),
+            compile_time_ms: compile_time.as_millis(),
+        }),
+    })
+}
+
+/// Set the compile profile of the deployment; `default` removes the
+/// profile so that the deployment uses the default of the node
+pub fn set(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    profile: &str,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let profile = match profile {
+        "default" => None,
+        profile => Some(
+            CompileProfile::from_str(profile)
+                .map_err(|e| CommandError::invalid_argument(e.to_string()))?,
+        ),
+    };
+    store.set_compile_profile(&locator, profile)?;
+
+    output::emit(&CompileProfileSet {
+        deployment: DeploymentRef::from(&locator),
+        profile: profile.map(|profile| profile.to_string()),
+    })
+}
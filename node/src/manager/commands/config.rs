@@ -1,50 +1,131 @@
+use std::io::{self, Write};
 use std::{collections::BTreeMap, sync::Arc};
 
 use graph::{
-    anyhow::bail,
     components::metrics::MetricsRegistry,
-    itertools::Itertools,
     prelude::{
         anyhow::{anyhow, Error},
-        NodeId,
+        serde_json, NodeId,
     },
     slog::Logger,
 };
 use graph_chain_ethereum::{EthereumAdapterTrait, NodeCapabilities, ProviderEthRpcMetrics};
 use graph_store_postgres::DeploymentPlacer;
+use serde::Serialize;
 
+use crate::manager::output::{self, CommandError, CommandOutput};
 use crate::{chain::create_ethereum_networks_for_chain, config::Config};
 
-pub fn place(placer: &dyn DeploymentPlacer, name: &str, network: &str) -> Result<(), Error> {
-    match placer.place(name, network).map_err(|s| anyhow!(s))? {
-        None => {
-            println!(
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Placement {
+    pub shards: Vec<String>,
+    pub nodes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlaceOutput {
+    pub subgraph: String,
+    pub network: String,
+    /// `None` if no placement rule matches and the default placement is
+    /// used
+    pub placement: Option<Placement>,
+}
+
+impl CommandOutput for PlaceOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.placement {
+            None => writeln!(
+                out,
                 "no matching placement rule; default placement from JSON RPC call would be used"
-            );
+            ),
+            Some(placement) => {
+                writeln!(out, "subgraph: {}", self.subgraph)?;
+                writeln!(out, "network:  {}", self.network)?;
+                writeln!(out, "shard:    {}", placement.shards.join(", "))?;
+                writeln!(out, "nodes:    {}", placement.nodes.join(", "))
+            }
         }
-        Some((shards, nodes)) => {
-            let nodes: Vec<_> = nodes.into_iter().map(|n| n.to_string()).collect();
-            let shards: Vec<_> = shards.into_iter().map(|s| s.to_string()).collect();
-            println!("subgraph: {}", name);
-            println!("network:  {}", network);
-            println!("shard:    {}", shards.join(", "));
-            println!("nodes:    {}", nodes.join(", "));
+    }
+}
+
+pub fn place(placer: &dyn DeploymentPlacer, name: &str, network: &str) -> Result<(), Error> {
+    let placement = placer
+        .place(name, network)
+        .map_err(|s| anyhow!(s))?
+        .map(|(shards, nodes)| Placement {
+            shards: shards.into_iter().map(|s| s.to_string()).collect(),
+            nodes: nodes.into_iter().map(|n| n.to_string()).collect(),
+        });
+    output::emit(&PlaceOutput {
+        subgraph: name.to_string(),
+        network: network.to_string(),
+        placement,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CheckOutput {
+    /// The configuration if it was asked for
+    pub config: Option<serde_json::Value>,
+    /// The configuration as it is printed in text output
+    #[serde(skip)]
+    pub text: Option<String>,
+}
+
+impl CommandOutput for CheckOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.text {
+            Some(text) => writeln!(out, "{}", text),
+            None => writeln!(out, "Successfully validated configuration"),
         }
     }
-    Ok(())
 }
 
 pub fn check(config: &Config, print: bool) -> Result<(), Error> {
-    match config.to_json() {
-        Ok(txt) => {
-            if print {
-                println!("{}", txt);
-            } else {
-                println!("Successfully validated configuration");
+    let txt = config
+        .to_json()
+        .map_err(|e| anyhow!("error serializing config: {}", e))?;
+    let output = if print {
+        CheckOutput {
+            config: Some(serde_json::from_str(&txt)?),
+            text: Some(txt),
+        }
+    } else {
+        CheckOutput {
+            config: None,
+            text: None,
+        }
+    };
+    output::emit(&output)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PoolsOutput {
+    /// The size of the pool for each shard and replica, summed over all
+    /// nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shards: Option<BTreeMap<String, u32>>,
+    /// The size of the pool for each shard and replica for each node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<BTreeMap<String, BTreeMap<String, u32>>>,
+}
+
+impl CommandOutput for PoolsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(shards) = &self.shards {
+            for (shard_name, size) in shards {
+                writeln!(out, "{}: {}", shard_name, size)?;
             }
-            Ok(())
         }
-        Err(e) => Err(anyhow!("error serializing config: {}", e)),
+        if let Some(nodes) = &self.nodes {
+            for (node, sizes) in nodes {
+                writeln!(out, "{}:", node)?;
+                for (shard, size) in sizes {
+                    writeln!(out, "    {}: {}", shard, size)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -54,8 +135,9 @@ pub fn pools(config: &Config, nodes: Vec<String>, shard: bool) -> Result<(), Err
     let nodes: Vec<_> = nodes
         .into_iter()
         .map(|name| {
-            NodeId::new(name.replace('-', "_"))
-                .map_err(|()| anyhow!("illegal node name `{}`", name))
+            NodeId::new(name.replace('-', "_")).map_err(|()| {
+                CommandError::invalid_argument(format!("illegal node name `{}`", name))
+            })
         })
         .collect::<Result<_, _>>()?;
     // node -> shard_name -> size
@@ -74,27 +156,46 @@ pub fn pools(config: &Config, nodes: Vec<String>, shard: bool) -> Result<(), Err
         sizes.insert(node.to_string(), shard_sizes);
     }
 
-    if shard {
-        let mut by_shard: BTreeMap<&str, u32> = BTreeMap::new();
+    let output = if shard {
+        let mut by_shard: BTreeMap<String, u32> = BTreeMap::new();
         for shard_sizes in sizes.values() {
             for (shard_name, size) in shard_sizes {
-                *by_shard.entry(shard_name).or_default() += size;
+                *by_shard.entry(shard_name.clone()).or_default() += size;
             }
         }
-        for (shard_name, size) in by_shard {
-            println!("{}: {}", shard_name, size);
+        PoolsOutput {
+            shards: Some(by_shard),
+            nodes: None,
         }
     } else {
-        for node in &nodes {
-            let empty = BTreeMap::new();
-            println!("{}:", node);
-            let node_sizes = sizes.get(node.as_str()).unwrap_or(&empty);
-            for (shard, size) in node_sizes {
-                println!("    {}: {}", shard, size);
-            }
+        PoolsOutput {
+            shards: None,
+            nodes: Some(sizes),
         }
+    };
+    output::emit(&output)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProviderOutput {
+    pub network: String,
+    pub features: String,
+    pub node: String,
+    /// The providers a deployment with `features` could use
+    pub providers: Vec<String>,
+}
+
+impl CommandOutput for ProviderOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "deploy on network {} with features [{}] on node {}\neligible providers: {}",
+            self.network,
+            self.features,
+            self.node,
+            self.providers.join(", ")
+        )
     }
-    Ok(())
 }
 
 pub async fn provider(
@@ -114,7 +215,12 @@ pub async fn provider(
             match feature {
                 "archive" => caps.archive = true,
                 "traces" => caps.traces = true,
-                _ => bail!("unknown feature {}", feature),
+                _ => {
+                    return Err(CommandError::invalid_argument(format!(
+                        "unknown feature {}",
+                        feature
+                    )))
+                }
             }
         }
         Ok(caps)
@@ -128,15 +234,14 @@ pub async fn provider(
         .networks
         .get(&network)
         .ok_or_else(|| anyhow!("unknown network {}", network))?;
-    let adapters = adapters.all_cheapest_with(&caps);
-    println!(
-        "deploy on network {} with features [{}] on node {}\neligible providers: {}",
+    let providers = adapters
+        .all_cheapest_with(&caps)
+        .map(|adapter| adapter.provider().to_string())
+        .collect();
+    output::emit(&ProviderOutput {
         network,
-        caps,
-        config.node.as_str(),
-        adapters
-            .map(|adapter| adapter.provider().to_string())
-            .join(", ")
-    );
-    Ok(())
+        features: caps.to_string(),
+        node: config.node.to_string(),
+        providers,
+    })
 }
@@ -1,12 +1,18 @@
 use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, RunQueryDsl};
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use graph::{
     components::store::BlockStore as _,
     data::query::QueryTarget,
     prelude::{
         anyhow::{anyhow, bail, Error},
-        chrono::{DateTime, Duration, SecondsFormat, Utc},
+        chrono::{DateTime, Duration, Utc},
         BlockPtr, ChainStore, DeploymentHash, NodeId, QueryStoreManager,
     },
 };
@@ -18,6 +24,7 @@ use graph_store_postgres::{connection_pool::ConnectionPool, Shard, Store, Subgra
 
 use crate::manager::deployment::DeploymentSearch;
 use crate::manager::display::List;
+use crate::manager::output::{self, timestamp, CommandError, CommandOutput, DeploymentRef};
 
 type UtcDateTime = DateTime<Utc>;
 
@@ -49,6 +56,25 @@ struct CopyTableState {
     started_at: UtcDateTime,
     finished_at: Option<UtcDateTime>,
     duration_ms: i64,
+    src_count: Option<i64>,
+    dst_count: Option<i64>,
+    src_checksum: Option<String>,
+    dst_checksum: Option<String>,
+    checked_at: Option<UtcDateTime>,
+}
+
+impl CopyTableState {
+    /// The result of the integrity check, if there was one
+    fn check_result(&self) -> Option<IntegrityCheck> {
+        match (self.checked_at, self.src_count, self.dst_count) {
+            (Some(_), Some(src), Some(dst)) => Some(IntegrityCheck {
+                src_count: src,
+                dst_count: dst,
+                ok: src == dst && self.src_checksum == self.dst_checksum,
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl CopyState {
@@ -79,6 +105,252 @@ impl CopyState {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyProgress {
+    Queued,
+    Started,
+    Finished,
+    CancelRequested,
+    Cancelled,
+}
+
+impl CopyProgress {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CopyProgress::Queued => "queued",
+            CopyProgress::Started => "started",
+            CopyProgress::Finished => "finished",
+            CopyProgress::CancelRequested => "cancel requested",
+            CopyProgress::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// How many rows of a copy have been copied, measured in `vid`s
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct RowProgress {
+    pub next: i64,
+    pub target: i64,
+}
+
+impl RowProgress {
+    fn of(tables: &[CopyTableState]) -> Self {
+        RowProgress {
+            next: tables.iter().map(|table| table.next_vid).sum(),
+            target: tables.iter().map(|table| table.target_vid).sum(),
+        }
+    }
+}
+
+impl std::fmt::Display for RowProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pct = self.next as f64 / self.target as f64 * 100.0;
+        write!(f, "{:.2}% done, {}/{}", pct, self.next, self.target)
+    }
+}
+
+/// The result of comparing a copied table with its source
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct IntegrityCheck {
+    pub src_count: i64,
+    pub dst_count: i64,
+    /// Whether counts and checksums of source and copy are the same
+    pub ok: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CopyCreated {
+    pub src: DeploymentRef,
+    pub dst: DeploymentRef,
+}
+
+impl CommandOutput for CopyCreated {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "created deployment {} as copy of {}",
+            self.dst, self.src
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CopyActivated {
+    pub deployment: DeploymentRef,
+}
+
+impl CommandOutput for CopyActivated {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "activated copy {}", self.deployment)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ActiveCopy {
+    pub deployment: String,
+    pub src: i32,
+    pub dst: i32,
+    pub shard: String,
+    pub state: CopyProgress,
+    /// When the copy reached its current state
+    pub since: String,
+    /// Only set while the copy is running
+    pub progress: Option<RowProgress>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CopyList {
+    pub copies: Vec<ActiveCopy>,
+}
+
+impl CommandOutput for CopyList {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.copies.is_empty() {
+            return writeln!(out, "no active copies");
+        }
+        for copy in &self.copies {
+            writeln!(out, "{:-<78}", "")?;
+            writeln!(out, "{:20} | {}", "deployment", copy.deployment)?;
+            writeln!(
+                out,
+                "{:20} | sgd{} -> sgd{} ({})",
+                "action", copy.src, copy.dst, copy.shard
+            )?;
+            writeln!(out, "{:20} | {}", copy.state.as_str(), copy.since)?;
+            if let Some(progress) = &copy.progress {
+                writeln!(out, "{:20} | {}", "progress", progress)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TableCopyStatus {
+    pub entity_type: String,
+    pub next_vid: i64,
+    pub target_vid: i64,
+    pub batch_size: i64,
+    pub duration_ms: i64,
+    pub finished: bool,
+    pub check: Option<IntegrityCheck>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CopyStatus {
+    pub deployment: String,
+    /// The id of the source; not known until the copy has started
+    pub src: Option<i32>,
+    pub dst: i32,
+    pub target_block: Option<i32>,
+    pub duration_ms: Option<i64>,
+    pub state: CopyProgress,
+    /// Only set while the copy is running
+    pub progress: Option<RowProgress>,
+    pub cancel_requested_at: Option<String>,
+    pub cancelled_at: Option<String>,
+    pub tables: Vec<TableCopyStatus>,
+}
+
+fn human_duration(duration: Duration) -> String {
+    if duration.num_seconds() < 5 {
+        format!("{}ms", duration.num_milliseconds())
+    } else if duration.num_minutes() < 5 {
+        format!("{}s", duration.num_seconds())
+    } else {
+        format!("{}m", duration.num_minutes())
+    }
+}
+
+impl CommandOutput for CopyStatus {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        fn done(finished: bool) -> &'static str {
+            if finished {
+                "✓"
+            } else {
+                "."
+            }
+        }
+
+        let (src, target_block, duration_ms) = match (self.src, self.target_block, self.duration_ms)
+        {
+            (Some(src), Some(target_block), Some(duration_ms)) => (src, target_block, duration_ms),
+            _ => return writeln!(out, "copying is queued but has not started"),
+        };
+
+        let progress = match &self.progress {
+            Some(progress) => progress.to_string(),
+            None => done(true).to_string(),
+        };
+        let mut lst = vec![
+            "deployment",
+            "src",
+            "dst",
+            "target block",
+            "duration",
+            "status",
+        ];
+        let mut vals = vec![
+            self.deployment.clone(),
+            src.to_string(),
+            self.dst.to_string(),
+            target_block.to_string(),
+            human_duration(Duration::milliseconds(duration_ms)),
+            progress,
+        ];
+        match (&self.cancel_requested_at, &self.cancelled_at) {
+            (Some(c), None) => {
+                lst.push("cancel");
+                vals.push(format!("requested at {}", c));
+            }
+            (_, Some(c)) => {
+                lst.push("cancel");
+                vals.push(format!("cancelled at {}", c));
+            }
+            (None, None) => {}
+        }
+        let mut lst = List::new(lst);
+        lst.append(vals);
+        lst.write(out)?;
+        writeln!(out)?;
+
+        writeln!(
+            out,
+            "{:^30} | {:^8} | {:^8} | {:^8} | {:^8} | {:^8}",
+            "entity type", "next", "target", "batch", "duration", "check"
+        )?;
+        writeln!(out, "{:-<85}", "-")?;
+        for table in &self.tables {
+            let status = if table.next_vid > 0 && table.next_vid < table.target_vid {
+                ">"
+            } else if table.target_vid < 0 {
+                // empty source table
+                "✓"
+            } else {
+                done(table.finished)
+            };
+            let check = match &table.check {
+                Some(check) if check.ok => "✓".to_string(),
+                Some(check) => format!("{}/{}", check.dst_count, check.src_count),
+                None => ".".to_string(),
+            };
+            writeln!(
+                out,
+                "{} {:<28} | {:>8} | {:>8} | {:>8} | {:>8} | {:>8}",
+                status,
+                table.entity_type,
+                table.next_vid,
+                table.target_vid,
+                table.batch_size,
+                human_duration(Duration::milliseconds(table.duration_ms)),
+                check,
+            )?;
+        }
+        Ok(())
+    }
+}
+
 pub async fn create(
     store: Arc<Store>,
     primary: ConnectionPool,
@@ -126,36 +398,39 @@ pub async fn create(
     let base_ptr = BlockPtr::new(hash, src_number);
 
     if !shards.contains(&shard) {
-        bail!(
+        return Err(CommandError::invalid_argument(format!(
             "unknown shard {shard}, only shards {} are configured",
             shards.join(", ")
-        )
+        )));
     }
     let shard = Shard::new(shard)?;
-    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+    let node = NodeId::new(node.clone())
+        .map_err(|()| CommandError::invalid_argument(format!("invalid node id `{}`", node)))?;
 
     let dst = subgraph_store.copy_deployment(&src, shard, node, base_ptr)?;
 
-    println!("created deployment {} as copy of {}", dst, src);
-    Ok(())
+    output::emit(&CopyCreated {
+        src: DeploymentRef::from(&src),
+        dst: DeploymentRef::from(&dst),
+    })
 }
 
 pub fn activate(store: Arc<SubgraphStore>, deployment: String, shard: String) -> Result<(), Error> {
     let shard = Shard::new(shard)?;
-    let deployment =
-        DeploymentHash::new(deployment).map_err(|s| anyhow!("illegal deployment hash `{}`", s))?;
+    let deployment = DeploymentHash::new(deployment)
+        .map_err(|s| CommandError::invalid_argument(format!("illegal deployment hash `{}`", s)))?;
     let deployment = store
         .locate_in_shard(&deployment, shard.clone())?
         .ok_or_else(|| {
-            anyhow!(
+            CommandError::not_found(format!(
                 "could not find a copy for {} in shard {}",
-                deployment,
-                shard
-            )
+                deployment, shard
+            ))
         })?;
     store.activate(&deployment)?;
-    println!("activated copy {}", deployment);
-    Ok(())
+    output::emit(&CopyActivated {
+        deployment: DeploymentRef::from(&deployment),
+    })
 }
 
 pub fn list(pools: HashMap<Shard, ConnectionPool>) -> Result<(), Error> {
@@ -176,72 +451,46 @@ pub fn list(pools: HashMap<Shard, ConnectionPool>) -> Result<(), Error> {
             ds::shard,
         ))
         .load::<(i32, i32, Option<UtcDateTime>, UtcDateTime, String, Shard)>(&conn)?;
-    if copies.is_empty() {
-        println!("no active copies");
-    } else {
-        fn status(name: &str, at: UtcDateTime) {
-            println!(
-                "{:20} | {}",
-                name,
-                at.to_rfc3339_opts(SecondsFormat::Secs, false)
-            );
-        }
-
-        for (src, dst, cancelled_at, queued_at, deployment_hash, shard) in copies {
-            println!("{:-<78}", "");
-
-            println!("{:20} | {}", "deployment", deployment_hash);
-            println!("{:20} | sgd{} -> sgd{} ({})", "action", src, dst, shard);
-            match CopyState::find(&pools, &shard, dst)? {
-                Some((state, tables)) => match cancelled_at {
-                    Some(cancel_requested) => match state.cancelled_at {
-                        Some(cancelled_at) => status("cancelled", cancelled_at),
-                        None => status("cancel requested", cancel_requested),
-                    },
-                    None => match state.finished_at {
-                        Some(finished_at) => status("finished", finished_at),
-                        None => {
-                            let target: i64 = tables.iter().map(|table| table.target_vid).sum();
-                            let next: i64 = tables.iter().map(|table| table.next_vid).sum();
-                            let done = next as f64 / target as f64 * 100.0;
-                            status("started", state.started_at);
-                            println!("{:20} | {:.2}% done, {}/{}", "progress", done, next, target)
-                        }
-                    },
+    let mut list = Vec::new();
+    for (src, dst, cancelled_at, queued_at, deployment_hash, shard) in copies {
+        let mut copy = ActiveCopy {
+            deployment: deployment_hash,
+            src,
+            dst,
+            shard: shard.to_string(),
+            state: CopyProgress::Queued,
+            since: timestamp(&queued_at),
+            progress: None,
+        };
+        if let Some((state, tables)) = CopyState::find(&pools, &shard, dst)? {
+            let (progress, since) = match cancelled_at {
+                Some(cancel_requested) => match state.cancelled_at {
+                    Some(cancelled_at) => (CopyProgress::Cancelled, cancelled_at),
+                    None => (CopyProgress::CancelRequested, cancel_requested),
+                },
+                None => match state.finished_at {
+                    Some(finished_at) => (CopyProgress::Finished, finished_at),
+                    None => {
+                        copy.progress = Some(RowProgress::of(&tables));
+                        (CopyProgress::Started, state.started_at)
+                    }
                 },
-                None => status("queued", queued_at),
             };
+            copy.state = progress;
+            copy.since = timestamp(&since);
         }
+        list.push(copy);
     }
-    Ok(())
+    output::emit(&CopyList { copies: list })
 }
 
 pub fn status(pools: HashMap<Shard, ConnectionPool>, dst: &DeploymentSearch) -> Result<(), Error> {
     use catalog::active_copies as ac;
     use catalog::deployment_schemas as ds;
 
-    fn done(ts: &Option<UtcDateTime>) -> String {
-        ts.map(|_| "✓").unwrap_or(".").to_string()
-    }
-
-    fn duration(start: &UtcDateTime, end: &Option<UtcDateTime>) -> String {
-        let start = *start;
-        let end = *end;
-
+    fn duration(start: &UtcDateTime, end: &Option<UtcDateTime>) -> Duration {
         let end = end.unwrap_or(UtcDateTime::from(SystemTime::now()));
-        let duration = end - start;
-
-        human_duration(duration)
-    }
-
-    fn human_duration(duration: Duration) -> String {
-        if duration.num_seconds() < 5 {
-            format!("{}ms", duration.num_milliseconds())
-        } else if duration.num_minutes() < 5 {
-            format!("{}s", duration.num_seconds())
-        } else {
-            format!("{}m", duration.num_minutes())
-        }
+        end - *start
     }
 
     let primary = pools
@@ -267,80 +516,60 @@ pub fn status(pools: HashMap<Shard, ConnectionPool>, dst: &DeploymentSearch) ->
         Some((state, tables)) => (state, tables),
         None => {
             if active {
-                println!("copying is queued but has not started");
-                return Ok(());
+                return output::emit(&CopyStatus {
+                    deployment,
+                    src: None,
+                    dst,
+                    target_block: None,
+                    duration_ms: None,
+                    state: CopyProgress::Queued,
+                    progress: None,
+                    cancel_requested_at: cancelled_at.as_ref().map(timestamp),
+                    cancelled_at: None,
+                    tables: vec![],
+                });
             } else {
-                bail!("no copy operation for {} exists", dst);
+                return Err(CommandError::not_found(format!(
+                    "no copy operation for {} exists",
+                    dst
+                )));
             }
         }
     };
 
-    let progress = match &state.finished_at {
-        Some(_) => done(&state.finished_at),
-        None => {
-            let target: i64 = tables.iter().map(|table| table.target_vid).sum();
-            let next: i64 = tables.iter().map(|table| table.next_vid).sum();
-            let pct = next as f64 / target as f64 * 100.0;
-            format!("{:.2}% done, {}/{}", pct, next, target)
-        }
+    let copy_state = match (cancelled_at, state.cancelled_at, state.finished_at) {
+        (_, Some(_), _) => CopyProgress::Cancelled,
+        (Some(_), None, _) => CopyProgress::CancelRequested,
+        (None, None, Some(_)) => CopyProgress::Finished,
+        (None, None, None) => CopyProgress::Started,
     };
-
-    let mut lst = vec![
-        "deployment",
-        "src",
-        "dst",
-        "target block",
-        "duration",
-        "status",
-    ];
-    let mut vals = vec![
+    let progress = match state.finished_at {
+        Some(_) => None,
+        None => Some(RowProgress::of(&tables)),
+    };
+    let tables = tables
+        .iter()
+        .map(|table| TableCopyStatus {
+            entity_type: table.entity_type.clone(),
+            next_vid: table.next_vid,
+            target_vid: table.target_vid,
+            batch_size: table.batch_size,
+            duration_ms: table.duration_ms,
+            finished: table.finished_at.is_some(),
+            check: table.check_result(),
+        })
+        .collect();
+
+    output::emit(&CopyStatus {
         deployment,
-        state.src.to_string(),
-        state.dst.to_string(),
-        state.target_block_number.to_string(),
-        duration(&state.started_at, &state.finished_at),
+        src: Some(state.src),
+        dst: state.dst,
+        target_block: Some(state.target_block_number),
+        duration_ms: Some(duration(&state.started_at, &state.finished_at).num_milliseconds()),
+        state: copy_state,
         progress,
-    ];
-    match (cancelled_at, state.cancelled_at) {
-        (Some(c), None) => {
-            lst.push("cancel");
-            vals.push(format!("requested at {}", c));
-        }
-        (_, Some(c)) => {
-            lst.push("cancel");
-            vals.push(format!("cancelled at {}", c));
-        }
-        (None, None) => {}
-    }
-    let mut lst = List::new(lst);
-    lst.append(vals);
-    lst.render();
-    println!("");
-
-    println!(
-        "{:^30} | {:^8} | {:^8} | {:^8} | {:^8}",
-        "entity type", "next", "target", "batch", "duration"
-    );
-    println!("{:-<74}", "-");
-    for table in tables {
-        let status = if table.next_vid > 0 && table.next_vid < table.target_vid {
-            ">".to_string()
-        } else if table.target_vid < 0 {
-            // empty source table
-            "✓".to_string()
-        } else {
-            done(&table.finished_at)
-        };
-        println!(
-            "{} {:<28} | {:>8} | {:>8} | {:>8} | {:>8}",
-            status,
-            table.entity_type,
-            table.next_vid,
-            table.target_vid,
-            table.batch_size,
-            human_duration(Duration::milliseconds(table.duration_ms)),
-        );
-    }
-
-    Ok(())
+        cancel_requested_at: cancelled_at.as_ref().map(timestamp),
+        cancelled_at: state.cancelled_at.as_ref().map(timestamp),
+        tables,
+    })
 }
@@ -1,14 +1,32 @@
+use std::io::{self, Write};
 use std::sync::Arc;
 
-use graph::prelude::{anyhow, Error, SubgraphName, SubgraphStore as _};
+use graph::prelude::{Error, SubgraphName, SubgraphStore as _};
 use graph_store_postgres::SubgraphStore;
+use serde::Serialize;
+
+use crate::manager::output::{self, CommandError, CommandOutput};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CreateOutput {
+    /// The name of the subgraph that was created
+    pub subgraph: String,
+}
+
+impl CommandOutput for CreateOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Created subgraph {}", self.subgraph)
+    }
+}
 
 pub fn run(store: Arc<SubgraphStore>, name: String) -> Result<(), Error> {
-    let name = SubgraphName::new(name.clone())
-        .map_err(|()| anyhow!("illegal subgraph name `{}`", name))?;
+    let name = SubgraphName::new(name.clone()).map_err(|()| {
+        CommandError::invalid_argument(format!("illegal subgraph name `{}`", name))
+    })?;
 
-    println!("creating subgraph {}", name);
-    store.create_subgraph(name)?;
+    store.create_subgraph(name.clone())?;
 
-    Ok(())
+    output::emit(&CreateOutput {
+        subgraph: name.to_string(),
+    })
 }
@@ -1,7 +1,91 @@
-use std::{io::Write, time::Instant};
+use std::io::{self, Write};
+use std::time::Instant;
 
 use graph::prelude::anyhow;
 use graph_store_postgres::connection_pool::PoolCoordinator;
+use serde::Serialize;
+
+use crate::manager::output::{self, CommandError, CommandOutput, ErrorCode};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MigrateOutput {
+    /// The shards whose databases are up to date
+    pub shards: Vec<String>,
+}
+
+impl CommandOutput for MigrateOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "All database migrations have been applied")
+    }
+}
+
+/// Creating the store builder runs migrations; this only reports them
+pub fn migrate(coord: &PoolCoordinator) -> Result<(), anyhow::Error> {
+    let mut shards: Vec<_> = coord
+        .pools()
+        .iter()
+        .map(|pool| pool.shard.to_string())
+        .collect();
+    shards.sort();
+    output::emit(&MigrateOutput { shards })
+}
+
+/// Remapping the imports from the shard `source` in the shard
+/// `destination`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Remap {
+    pub source: String,
+    pub destination: String,
+    pub seconds: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RemapOutput {
+    pub remaps: Vec<Remap>,
+}
+
+impl CommandOutput for RemapOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for remap in &self.remaps {
+            match &remap.error {
+                None => writeln!(
+                    out,
+                    "Remapped imports from {} in shard {} (done in {}s)",
+                    remap.source, remap.destination, remap.seconds
+                )?,
+                Some(e) => {
+                    writeln!(
+                        out,
+                        "Remapping imports from {} in shard {} FAILED",
+                        remap.source, remap.destination
+                    )?;
+                    writeln!(out, "  error: {e}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn failure(&self) -> Option<CommandError> {
+        let failed = self
+            .remaps
+            .iter()
+            .filter(|remap| remap.error.is_some())
+            .count();
+        if failed == 0 {
+            return None;
+        }
+        Some(CommandError::new(
+            ErrorCode::PartialFailure,
+            format!(
+                "remapping failed for {} of {} shards",
+                failed,
+                self.remaps.len()
+            ),
+        ))
+    }
+}
 
 pub async fn remap(
     coord: &PoolCoordinator,
@@ -18,12 +102,16 @@ pub async fn remap(
 
     if let Some(src) = &src {
         if !servers.iter().any(|srv| srv.shard.as_str() == src) {
-            return Err(anyhow!("unknown source shard {src}"));
+            return Err(CommandError::not_found(format!(
+                "unknown source shard {src}"
+            )));
         }
     }
     if let Some(dst) = &dst {
         if !pools.iter().any(|pool| pool.shard.as_str() == dst) {
-            return Err(anyhow!("unknown destination shard {dst}"));
+            return Err(CommandError::not_found(format!(
+                "unknown destination shard {dst}"
+            )));
         }
     }
 
@@ -32,7 +120,8 @@ pub async fn remap(
         Some(src) => srv.shard.as_str() == src,
     });
 
-    for server in servers {
+    let mut remaps = Vec::new();
+    'servers: for server in servers {
         let pools = pools.iter().filter(|pool| match &dst {
             None => true,
             Some(dst) => pool.shard.as_str() == dst,
@@ -40,21 +129,22 @@ pub async fn remap(
 
         for pool in pools {
             let start = Instant::now();
-            print!(
+            output::progress(format!(
                 "Remapping imports from {} in shard {}",
                 server.shard, pool.shard
-            );
-            std::io::stdout().flush().ok();
-            if let Err(e) = pool.remap(server) {
-                println!(" FAILED");
-                println!("  error: {e}");
-                if !force {
-                    return Ok(());
-                }
-            } else {
-                println!(" (done in {}s)", start.elapsed().as_secs());
+            ));
+            let error = pool.remap(server).err().map(|e| e.to_string());
+            let failed = error.is_some();
+            remaps.push(Remap {
+                source: server.shard.to_string(),
+                destination: pool.shard.to_string(),
+                seconds: start.elapsed().as_secs(),
+                error,
+            });
+            if failed && !force {
+                break 'servers;
             }
         }
     }
-    Ok(())
+    output::emit(&RemapOutput { remaps })
 }
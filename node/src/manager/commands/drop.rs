@@ -1,12 +1,64 @@
 use crate::manager::{
+    commands::{
+        assign::{self, AssignmentOutput},
+        remove::{self, RemoveOutput},
+        unused_deployments::{self, UnusedRecordOutput, UnusedRemoveOutput},
+    },
     deployment::{Deployment, DeploymentSearch},
     display::List,
+    output::{self, CommandError, CommandOutput, OutputFormat},
     prompt::prompt_for_confirmation,
 };
-use graph::anyhow::{self, bail};
+use graph::anyhow;
 use graph_store_postgres::{connection_pool::ConnectionPool, NotificationSender, SubgraphStore};
+use serde::Serialize;
+use std::io::{self, Write};
 use std::sync::Arc;
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DropCandidate {
+    pub name: String,
+    pub deployment: String,
+}
+
+/// What each of the steps of `drop` did; the steps that did not run are
+/// left empty
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DropOutput {
+    pub deployments: Vec<DropCandidate>,
+    /// Whether the user confirmed dropping the deployments
+    pub confirmed: bool,
+    pub unassigned: Option<AssignmentOutput>,
+    pub removed: Vec<RemoveOutput>,
+    pub recorded: Option<UnusedRecordOutput>,
+    pub removals: Vec<UnusedRemoveOutput>,
+}
+
+impl CommandOutput for DropOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.confirmed {
+            return writeln!(out, "Execution aborted by user");
+        }
+        if let Some(unassigned) = &self.unassigned {
+            unassigned.render_text(out)?;
+        }
+        for removed in &self.removed {
+            removed.render_text(out)?;
+        }
+        if let Some(recorded) = &self.recorded {
+            recorded.render_text(out)?;
+        }
+        for removal in &self.removals {
+            removal.render_text(out)?;
+        }
+        Ok(())
+    }
+
+    fn failure(&self) -> Option<CommandError> {
+        self.removals.iter().find_map(|removal| removal.failure())
+    }
+}
+
 /// Finds, unassigns, record and remove matching deployments.
 ///
 /// Asks for confirmation before removing any data.
@@ -24,45 +76,72 @@ pub async fn run(
     // call `graphman info` to find matching deployments
     let deployments = search_term.find(primary_pool.clone(), current, pending, used)?;
     if deployments.is_empty() {
-        bail!("Found no deployment for search_term: {search_term}")
-    } else {
-        print_deployments(&deployments);
-        if !skip_confirmation && !prompt_for_confirmation("\nContinue?")? {
-            println!("Execution aborted by user");
-            return Ok(());
-        }
+        return Err(CommandError::not_found(format!(
+            "Found no deployment for search_term: {search_term}"
+        )));
+    }
+    let mut output = DropOutput {
+        deployments: deployments
+            .iter()
+            .map(|deployment| DropCandidate {
+                name: deployment.name.clone(),
+                deployment: deployment.deployment.clone(),
+            })
+            .collect(),
+        confirmed: true,
+        unassigned: None,
+        removed: vec![],
+        recorded: None,
+        removals: vec![],
+    };
+    print_deployments(&deployments)?;
+    if !skip_confirmation && !prompt_for_confirmation("\nContinue?")? {
+        output.confirmed = false;
+        return output::emit(&output);
     }
+
     // call `graphman unassign` to stop any active deployments
-    crate::manager::commands::assign::unassign(primary_pool, &sender, &search_term).await?;
+    output.unassigned =
+        Some(assign::unassign_deployment(primary_pool, &sender, &search_term).await?);
 
     // call `graphman remove` to unregister the subgraph's name
     for deployment in &deployments {
-        crate::manager::commands::remove::run(subgraph_store.clone(), &deployment.name)?;
+        output.removed.push(remove::remove_subgraph(
+            subgraph_store.clone(),
+            &deployment.name,
+        )?);
     }
 
     // call `graphman unused record` to register those deployments unused
-    crate::manager::commands::unused_deployments::record(subgraph_store.clone())?;
+    output.recorded = Some(unused_deployments::record_unused(subgraph_store.clone())?);
 
     // call `graphman unused remove` to remove each deployment's data
     for deployment in &deployments {
-        crate::manager::commands::unused_deployments::remove(
+        output.removals.push(unused_deployments::remove_unused(
             subgraph_store.clone(),
             1_000_000,
             Some(&deployment.deployment),
             None,
-        )?;
+            false,
+        )?);
     }
-    Ok(())
+    output::emit(&output)
 }
 
-fn print_deployments(deployments: &[Deployment]) {
+/// Show the deployments that are about to be dropped; with JSON output,
+/// they go to stderr, like the confirmation prompt
+fn print_deployments(deployments: &[Deployment]) -> io::Result<()> {
     let mut list = List::new(vec!["name", "deployment"]);
-    println!("Found {} deployment(s) to remove:", deployments.len());
     for deployment in deployments {
         list.append(vec![
             deployment.name.to_string(),
             deployment.deployment.to_string(),
         ]);
     }
-    list.render();
+    let mut out: Box<dyn Write> = match OutputFormat::current() {
+        OutputFormat::Text => Box::new(io::stdout().lock()),
+        OutputFormat::Json => Box::new(io::stderr().lock()),
+    };
+    writeln!(out, "Found {} deployment(s) to remove:", deployments.len())?;
+    list.write(&mut out)
 }
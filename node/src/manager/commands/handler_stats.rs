@@ -0,0 +1,112 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::data::subgraph::status::HandlerStats;
+use graph::prelude::anyhow::Error;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HandlerStatsInfo {
+    pub handler: String,
+    pub invocations: u64,
+    pub execution_time_ms: u128,
+    pub gas: u64,
+    pub errors: u64,
+}
+
+impl From<&HandlerStats> for HandlerStatsInfo {
+    fn from(stats: &HandlerStats) -> Self {
+        HandlerStatsInfo {
+            handler: stats.handler.clone(),
+            invocations: stats.invocations,
+            execution_time_ms: stats.execution_time.as_millis(),
+            gas: stats.gas,
+            errors: stats.errors,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HandlerStatsOutput {
+    pub deployment: DeploymentRef,
+    /// When the statistics were first recorded; `None` if none were
+    pub since: Option<String>,
+    pub handlers: Vec<HandlerStatsInfo>,
+}
+
+impl CommandOutput for HandlerStatsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.since {
+            Some(since) => writeln!(out, "since: {}", since)?,
+            None => {
+                return writeln!(
+                    out,
+                    "no handler statistics were recorded for {}",
+                    self.deployment
+                )
+            }
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<40} {:>12} {:>14} {:>16} {:>8}",
+            "handler", "invocations", "time (ms)", "gas", "errors"
+        )?;
+        for stats in &self.handlers {
+            writeln!(
+                out,
+                "{:<40} {:>12} {:>14} {:>16} {:>8}",
+                stats.handler, stats.invocations, stats.execution_time_ms, stats.gas, stats.errors
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HandlerStatsReset {
+    pub deployment: DeploymentRef,
+}
+
+impl CommandOutput for HandlerStatsReset {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "reset the handler statistics of {}", self.deployment)?;
+        writeln!(
+            out,
+            "a node that runs {} reports the reset statistics after it next writes them",
+            self.deployment
+        )
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let report = store.handler_stats(&locator)?;
+    output::emit(&HandlerStatsOutput {
+        deployment: DeploymentRef::from(&locator),
+        since: report.since.as_ref().map(output::timestamp),
+        handlers: report.handlers.iter().map(HandlerStatsInfo::from).collect(),
+    })
+}
+
+pub fn reset(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    store.reset_handler_stats(&locator)?;
+    output::emit(&HandlerStatsReset {
+        deployment: DeploymentRef::from(&locator),
+    })
+}
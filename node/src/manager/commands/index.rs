@@ -1,6 +1,10 @@
-use crate::manager::{color::Terminal, deployment::DeploymentSearch, CmdResult};
+use crate::manager::{
+    color::{Plain, Styled, Terminal},
+    deployment::DeploymentSearch,
+    output::{self, CommandError, CommandOutput, DeploymentRef, OutputFormat},
+    CmdResult,
+};
 use graph::{
-    components::store::DeploymentLocator,
     itertools::Itertools,
     prelude::{anyhow, StoreError},
 };
@@ -9,21 +13,48 @@ use graph_store_postgres::{
     connection_pool::ConnectionPool,
     SubgraphStore,
 };
-use std::io::Write as _;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
 use std::{collections::HashSet, sync::Arc};
 
 fn validate_fields<T: AsRef<str>>(fields: &[T]) -> Result<(), anyhow::Error> {
     // Must be non-empty. Double checking, since [`StructOpt`] already checks this.
     if fields.is_empty() {
-        anyhow::bail!("at least one field must be informed")
+        return Err(CommandError::invalid_argument(
+            "at least one field must be informed",
+        ));
     }
     // All values must be unique
     let unique: HashSet<_> = fields.iter().map(AsRef::as_ref).collect();
     if fields.len() != unique.len() {
-        anyhow::bail!("entity fields must be unique")
+        return Err(CommandError::invalid_argument(
+            "entity fields must be unique",
+        ));
     }
     Ok(())
 }
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexCreated {
+    pub deployment: DeploymentRef,
+    pub entity_type: String,
+    pub fields: Vec<String>,
+    pub method: String,
+}
+
+impl CommandOutput for IndexCreated {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Created index on {}({}) using {}",
+            self.entity_type,
+            self.fields.join(", "),
+            self.method
+        )
+    }
+}
+
 pub async fn create(
     store: Arc<SubgraphStore>,
     pool: ConnectionPool,
@@ -34,111 +65,187 @@ pub async fn create(
 ) -> Result<(), anyhow::Error> {
     validate_fields(&field_names)?;
     let deployment_locator = search.locate_unique(&pool)?;
-    println!("Index creation started. Please wait.");
-    let index_method = index_method
-        .parse::<Method>()
-        .map_err(|()| anyhow!("unknown index method `{}`", index_method))?;
+    output::progress("Index creation started. Please wait.");
+    let method = index_method.parse::<Method>().map_err(|()| {
+        CommandError::invalid_argument(format!("unknown index method `{}`", index_method))
+    })?;
     match store
-        .create_manual_index(&deployment_locator, entity_name, field_names, index_method)
+        .create_manual_index(
+            &deployment_locator,
+            entity_name,
+            field_names.clone(),
+            method,
+        )
         .await
     {
-        Ok(()) => Ok(()),
-        Err(StoreError::Canceled) => {
-            eprintln!("Index creation attempt failed. Please retry.");
-            ::std::process::exit(1);
-        }
+        Ok(()) => output::emit(&IndexCreated {
+            deployment: DeploymentRef::from(&deployment_locator),
+            entity_type: entity_name.to_string(),
+            fields: field_names,
+            method: index_method,
+        }),
+        Err(StoreError::Canceled) => Err(anyhow!("Index creation attempt failed. Please retry.")),
         Err(other) => Err(anyhow::anyhow!(other)),
     }
 }
 
-pub async fn list(
-    store: Arc<SubgraphStore>,
-    pool: ConnectionPool,
-    search: DeploymentSearch,
-    entity_name: &str,
-    no_attribute_indexes: bool,
-    no_default_indexes: bool,
-    to_sql: bool,
-    concurrent: bool,
-    if_not_exists: bool,
-) -> Result<(), anyhow::Error> {
-    fn header(
-        term: &mut Terminal,
-        indexes: &[CreateIndex],
-        loc: &DeploymentLocator,
-        entity: &str,
-    ) -> Result<(), anyhow::Error> {
-        use CreateIndex::*;
-
-        let index = indexes.iter().find(|index| matches!(index, Parsed { .. }));
-        match index {
-            Some(Parsed { nsp, table, .. }) => {
-                term.bold()?;
-                writeln!(term, "{:^76}", format!("Indexes for {nsp}.{table}"))?;
-                term.reset()?;
-            }
-            _ => {
-                writeln!(
-                    term,
-                    "{:^76}",
-                    format!("Indexes for sgd{}.{entity}", loc.id)
-                )?;
-            }
-        }
-        writeln!(term, "{: ^12} IPFS hash: {}", "", loc.hash)?;
-        writeln!(term, "{:-^76}", "")?;
-        Ok(())
-    }
-
-    fn print_index(term: &mut Terminal, index: &CreateIndex) -> CmdResult {
-        use CreateIndex::*;
+/// An index of a table; `name` is `None` if the definition of the index
+/// could not be parsed, and `sql` is its definition
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexInfo {
+    pub name: Option<String>,
+    pub unique: bool,
+    pub method: Option<String>,
+    pub columns: Vec<String>,
+    pub condition: Option<String>,
+    pub with: Option<String>,
+    /// Whether graph-node creates the index for every deployment
+    pub default: bool,
+    pub sql: String,
+}
 
-        match index {
-            Unknown { defn } => {
-                writeln!(term, "*unknown*")?;
-                writeln!(term, "  {defn}")?;
-            }
-            Parsed {
+impl IndexInfo {
+    fn new(index: &CreateIndex, concurrent: bool, if_not_exists: bool) -> Result<Self, fmt::Error> {
+        let sql = index.to_sql(concurrent, if_not_exists)?;
+        let info = match index {
+            CreateIndex::Unknown { .. } => IndexInfo {
+                name: None,
+                unique: false,
+                method: None,
+                columns: vec![],
+                condition: None,
+                with: None,
+                default: false,
+                sql,
+            },
+            CreateIndex::Parsed {
                 unique,
                 name,
-                nsp: _,
-                table: _,
                 method,
                 columns,
                 cond,
                 with,
-            } => {
-                let unique = if *unique { " unique" } else { "" };
-                let start = format!("{unique} using {method}");
-                let columns = columns.into_iter().map(|c| c.to_string()).join(", ");
-
-                term.green()?;
-                if index.is_default_index() {
-                    term.dim()?;
-                } else {
-                    term.bold()?;
-                }
-                write!(term, "{name}")?;
-                term.reset()?;
-                write!(term, "{start}")?;
-                term.blue()?;
-                if name.len() + start.len() + columns.len() <= 76 {
-                    writeln!(term, "({columns})")?;
-                } else {
-                    writeln!(term, "\n  on ({})", columns)?;
-                }
-                term.reset()?;
-                if let Some(cond) = cond {
-                    writeln!(term, "  where {cond}")?;
-                }
-                if let Some(with) = with {
-                    writeln!(term, "  with {with}")?;
-                }
+                ..
+            } => IndexInfo {
+                name: Some(name.clone()),
+                unique: *unique,
+                method: Some(method.to_string()),
+                columns: columns.iter().map(|c| c.to_string()).collect(),
+                condition: cond.as_ref().map(|cond| cond.to_string()),
+                with: with.clone(),
+                default: index.is_default_index(),
+                sql,
+            },
+        };
+        Ok(info)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexListOutput {
+    pub deployment: DeploymentRef,
+    /// The table the indexes belong to, like `sgd1.token`
+    pub table: String,
+    pub indexes: Vec<IndexInfo>,
+    /// Whether the text output only consists of the SQL for the indexes
+    #[serde(skip)]
+    pub to_sql: bool,
+}
+
+impl IndexListOutput {
+    fn render(&self, term: &mut dyn Styled) -> CmdResult {
+        if self.to_sql {
+            for index in &self.indexes {
+                writeln!(term, "{};", index.sql)?;
+            }
+            return Ok(());
+        }
+
+        // The table name is only known for certain if one of the indexes
+        // could be parsed
+        if self.indexes.iter().any(|index| index.name.is_some()) {
+            term.bold()?;
+            writeln!(term, "{:^76}", format!("Indexes for {}", self.table))?;
+            term.reset()?;
+        } else {
+            writeln!(term, "{:^76}", format!("Indexes for {}", self.table))?;
+        }
+        writeln!(
+            term,
+            "{: ^12} IPFS hash: {}",
+            "", self.deployment.deployment
+        )?;
+        writeln!(term, "{:-^76}", "")?;
+
+        let mut first = true;
+        for index in &self.indexes {
+            if first {
+                first = false;
+            } else {
+                writeln!(term, "{:-^76}", "")?;
             }
+            print_index(term, index)?;
         }
         Ok(())
     }
+}
+
+fn print_index(term: &mut dyn Styled, index: &IndexInfo) -> CmdResult {
+    let (name, method) = match (&index.name, &index.method) {
+        (Some(name), Some(method)) => (name, method),
+        _ => {
+            writeln!(term, "*unknown*")?;
+            writeln!(term, "  {}", index.sql)?;
+            return Ok(());
+        }
+    };
+    let unique = if index.unique { " unique" } else { "" };
+    let start = format!("{unique} using {method}");
+    let columns = index.columns.iter().join(", ");
+
+    term.green()?;
+    if index.default {
+        term.dim()?;
+    } else {
+        term.bold()?;
+    }
+    write!(term, "{name}")?;
+    term.reset()?;
+    write!(term, "{start}")?;
+    term.blue()?;
+    if name.len() + start.len() + columns.len() <= 76 {
+        writeln!(term, "({columns})")?;
+    } else {
+        writeln!(term, "\n  on ({})", columns)?;
+    }
+    term.reset()?;
+    if let Some(cond) = &index.condition {
+        writeln!(term, "  where {cond}")?;
+    }
+    if let Some(with) = &index.with {
+        writeln!(term, "  with {with}")?;
+    }
+    Ok(())
+}
+
+impl CommandOutput for IndexListOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.render(&mut Plain(out))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
 
+pub async fn list(
+    store: Arc<SubgraphStore>,
+    pool: ConnectionPool,
+    search: DeploymentSearch,
+    entity_name: &str,
+    no_attribute_indexes: bool,
+    no_default_indexes: bool,
+    to_sql: bool,
+    concurrent: bool,
+    if_not_exists: bool,
+) -> Result<(), anyhow::Error> {
     let deployment_locator = search.locate_unique(&pool)?;
     let indexes: Vec<_> = {
         let mut indexes = store
@@ -159,25 +266,40 @@ pub async fn list(
         indexes
     };
 
-    let mut term = Terminal::new();
+    let table = indexes
+        .iter()
+        .find_map(|index| match index {
+            CreateIndex::Parsed { nsp, table, .. } => Some(format!("{nsp}.{table}")),
+            CreateIndex::Unknown { .. } => None,
+        })
+        .unwrap_or_else(|| format!("sgd{}.{entity_name}", deployment_locator.id));
+    let output = IndexListOutput {
+        deployment: DeploymentRef::from(&deployment_locator),
+        table,
+        indexes: indexes
+            .iter()
+            .map(|index| IndexInfo::new(index, concurrent, if_not_exists))
+            .collect::<Result<_, _>>()?,
+        to_sql,
+    };
 
-    if to_sql {
-        for index in indexes {
-            writeln!(term, "{};", index.to_sql(concurrent, if_not_exists)?)?;
-        }
-    } else {
-        let mut first = true;
-        header(&mut term, &indexes, &deployment_locator, entity_name)?;
-        for index in &indexes {
-            if first {
-                first = false;
-            } else {
-                writeln!(term, "{:-^76}", "")?;
-            }
-            print_index(&mut term, index)?;
-        }
+    // Only text output on a terminal is colored
+    match OutputFormat::current() {
+        OutputFormat::Text => output.render(&mut Terminal::new()),
+        OutputFormat::Json => output::emit(&output),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexDropped {
+    pub deployment: DeploymentRef,
+    pub index: String,
+}
+
+impl CommandOutput for IndexDropped {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Dropped index {}", self.index)
     }
-    Ok(())
 }
 
 pub async fn drop(
@@ -190,6 +312,8 @@ pub async fn drop(
     store
         .drop_index_for_deployment(&deployment_locator, &index_name)
         .await?;
-    println!("Dropped index {index_name}");
-    Ok(())
+    output::emit(&IndexDropped {
+        deployment: DeploymentRef::from(&deployment_locator),
+        index: index_name.to_string(),
+    })
 }
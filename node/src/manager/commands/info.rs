@@ -1,9 +1,128 @@
+use std::io::{self, Write};
 use std::sync::Arc;
 
-use graph::{components::store::StatusStore, data::subgraph::status, prelude::anyhow};
+use graph::{
+    components::store::StatusStore,
+    data::subgraph::status,
+    prelude::{anyhow, BlockNumber},
+};
 use graph_store_postgres::{connection_pool::ConnectionPool, Store};
+use serde::Serialize;
 
 use crate::manager::deployment::{Deployment, DeploymentSearch};
+use crate::manager::display::List;
+use crate::manager::output::{self, CommandOutput};
+
+/// How far a deployment has indexed; only reported with `--status`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct IndexingStatus {
+    pub synced: bool,
+    pub health: String,
+    pub latest_block: Option<BlockNumber>,
+    pub chain_head_block: Option<BlockNumber>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeploymentInfo {
+    pub name: String,
+    pub status: String,
+    pub deployment: String,
+    pub namespace: String,
+    pub shard: String,
+    pub active: bool,
+    pub chain: String,
+    pub node_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexing: Option<IndexingStatus>,
+}
+
+impl DeploymentInfo {
+    fn new(deployment: Deployment, statuses: &[status::Info]) -> Self {
+        let indexing = statuses
+            .iter()
+            .find(|status| status.id.0 == deployment.id)
+            .map(|status| {
+                let chain = &status.chains[0];
+                IndexingStatus {
+                    synced: status.synced,
+                    health: status.health.as_str().to_string(),
+                    latest_block: chain.latest_block.as_ref().map(|b| b.number()),
+                    chain_head_block: chain.chain_head_block.as_ref().map(|b| b.number()),
+                }
+            });
+        DeploymentInfo {
+            name: deployment.name,
+            status: deployment.status,
+            deployment: deployment.deployment,
+            namespace: deployment.namespace,
+            shard: deployment.shard,
+            active: deployment.active,
+            chain: deployment.chain,
+            node_id: deployment.node_id,
+            indexing,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InfoOutput {
+    pub deployments: Vec<DeploymentInfo>,
+}
+
+impl CommandOutput for InfoOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.deployments.is_empty() {
+            return writeln!(out, "No matches");
+        }
+
+        let with_status = self.deployments.iter().any(|d| d.indexing.is_some());
+        let mut headers = vec![
+            "name",
+            "status",
+            "id",
+            "namespace",
+            "shard",
+            "active",
+            "chain",
+            "node_id",
+        ];
+        if with_status {
+            headers.extend(vec!["synced", "health", "latest block", "chain head block"]);
+        }
+
+        let mut list = List::new(headers);
+        for deployment in &self.deployments {
+            let mut row = vec![
+                deployment.name.clone(),
+                deployment.status.clone(),
+                deployment.deployment.clone(),
+                deployment.namespace.clone(),
+                deployment.shard.clone(),
+                deployment.active.to_string(),
+                deployment.chain.clone(),
+                deployment.node_id.clone().unwrap_or("---".to_string()),
+            ];
+            if with_status {
+                let number = |block: Option<BlockNumber>| {
+                    block
+                        .map(|number| number.to_string())
+                        .unwrap_or("-".to_string())
+                };
+                match &deployment.indexing {
+                    Some(indexing) => row.extend(vec![
+                        indexing.synced.to_string(),
+                        indexing.health.clone(),
+                        number(indexing.latest_block),
+                        number(indexing.chain_head_block),
+                    ]),
+                    None => row.extend(vec!["-".to_string(); 4]),
+                }
+            }
+            list.append(row);
+        }
+        list.write(out)
+    }
+}
 
 pub fn run(
     pool: ConnectionPool,
@@ -20,10 +139,9 @@ pub fn run(
         None => vec![],
     };
 
-    if deployments.is_empty() {
-        println!("No matches");
-    } else {
-        Deployment::print_table(deployments, statuses);
-    }
-    Ok(())
+    let deployments = deployments
+        .into_iter()
+        .map(|deployment| DeploymentInfo::new(deployment, &statuses))
+        .collect();
+    output::emit(&InfoOutput { deployments })
 }
@@ -0,0 +1,99 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::prelude::anyhow::Error;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LabelsOutput {
+    pub deployment: DeploymentRef,
+    pub labels: Vec<String>,
+}
+
+impl CommandOutput for LabelsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.labels.is_empty() {
+            writeln!(out, "{} has no labels", self.deployment)?;
+        }
+        for label in &self.labels {
+            writeln!(out, "{}", label)?;
+        }
+        Ok(())
+    }
+}
+
+/// How adding or removing a label changed the labels of a deployment
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LabelChangeOutput {
+    pub deployment: DeploymentRef,
+    pub label: String,
+    /// Whether the deployment has the label now
+    pub labeled: bool,
+    /// Whether the command changed anything
+    pub changed: bool,
+}
+
+impl CommandOutput for LabelChangeOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        let label = &self.label;
+        match (self.labeled, self.changed) {
+            (true, true) => writeln!(out, "added label {label} to {deployment}"),
+            (true, false) => writeln!(out, "{deployment} already has label {label}"),
+            (false, true) => writeln!(out, "removed label {label} from {deployment}"),
+            (false, false) => writeln!(out, "{deployment} does not have label {label}"),
+        }
+    }
+}
+
+pub fn list(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let labels = store.deployment_labels(&locator)?;
+    output::emit(&LabelsOutput {
+        deployment: DeploymentRef::from(&locator),
+        labels,
+    })
+}
+
+pub fn add(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    label: String,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let changed = store.add_deployment_label(&locator, &label)?;
+    output::emit(&LabelChangeOutput {
+        deployment: DeploymentRef::from(&locator),
+        label,
+        labeled: true,
+        changed,
+    })
+}
+
+pub fn remove(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    label: String,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let changed = store.remove_deployment_label(&locator, &label)?;
+    output::emit(&LabelChangeOutput {
+        deployment: DeploymentRef::from(&locator),
+        label,
+        labeled: false,
+        changed,
+    })
+}
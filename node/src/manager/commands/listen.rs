@@ -1,30 +1,28 @@
+use std::collections::BTreeSet;
 use std::iter::FromIterator;
 use std::sync::Arc;
-use std::{collections::BTreeSet, io::Write};
 
 use futures::compat::Future01CompatExt;
 //use futures::future;
 use graph::{
     components::store::{EntityType, SubscriptionManager as _},
-    prelude::{serde_json, Error, Stream, SubscriptionFilter},
+    prelude::{anyhow::anyhow, Error, Stream, SubscriptionFilter},
 };
 use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::SubscriptionManager;
 
 use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output;
 
 async fn listen(
     mgr: Arc<SubscriptionManager>,
     filter: BTreeSet<SubscriptionFilter>,
 ) -> Result<(), Error> {
     let events = mgr.subscribe(filter);
-    println!("press ctrl-c to stop");
+    output::progress("press ctrl-c to stop");
     let res = events
         .inspect(move |event| {
-            serde_json::to_writer_pretty(std::io::stdout(), event)
-                .expect("event can be serialized to JSON");
-            writeln!(std::io::stdout(), "").unwrap();
-            std::io::stdout().flush().unwrap();
+            output::stream_item(event).expect("event can be written to stdout");
         })
         .collect()
         .compat()
@@ -32,17 +30,15 @@ async fn listen(
 
     match res {
         Ok(_) => {
-            println!("stream finished")
-        }
-        Err(()) => {
-            eprintln!("stream failed")
+            output::progress("stream finished");
+            Ok(())
         }
+        Err(()) => Err(anyhow!("stream failed")),
     }
-    Ok(())
 }
 
 pub async fn assignments(mgr: Arc<SubscriptionManager>) -> Result<(), Error> {
-    println!("waiting for assignment events");
+    output::progress("waiting for assignment events");
     listen(
         mgr,
         FromIterator::from_iter([SubscriptionFilter::Assignment]),
@@ -64,7 +60,7 @@ pub async fn entities(
         .map(|et| SubscriptionFilter::Entities(locator.hash.clone(), EntityType::new(et)))
         .collect();
 
-    println!("waiting for store events from {}", locator);
+    output::progress(format!("waiting for store events from {}", locator));
     listen(mgr, filter).await?;
 
     Ok(())
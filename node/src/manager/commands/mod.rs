@@ -1,19 +1,28 @@
 pub mod assign;
+pub mod bus_critical;
 pub mod chain;
 pub mod check_blocks;
+pub mod compat;
+pub mod compile_profile;
 pub mod config;
 pub mod copy;
 pub mod create;
 pub mod database;
 pub mod drop;
+pub mod handler_stats;
 pub mod index;
 pub mod info;
+pub mod label;
 pub mod listen;
 pub mod prune;
 pub mod query;
 pub mod remove;
+pub mod reorg_threshold;
+pub mod retention;
 pub mod rewind;
 pub mod run;
 pub mod stats;
+pub mod triggers;
 pub mod txn_speed;
 pub mod unused_deployments;
+pub mod usage;
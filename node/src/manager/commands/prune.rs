@@ -1,6 +1,6 @@
 use std::{
     collections::HashSet,
-    io::Write,
+    io::{self, Write},
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -12,14 +12,36 @@ use graph::{
 };
 use graph_chain_ethereum::ENV_VARS as ETH_ENV;
 use graph_store_postgres::{connection_pool::ConnectionPool, Store};
+use serde::Serialize;
 
 use crate::manager::{
     commands::stats::{abbreviate_table_name, show_stats},
     deployment::DeploymentSearch,
+    output::{self, CommandError, CommandOutput, DeploymentRef, OutputFormat},
 };
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PruneOutput {
+    pub deployment: DeploymentRef,
+    pub latest_block: BlockNumber,
+    pub final_block: BlockNumber,
+    /// The earliest block the deployment has history for now
+    pub earliest_block: BlockNumber,
+    pub seconds: u64,
+}
+
+impl CommandOutput for PruneOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Finished pruning in {}s", self.seconds)
+    }
+}
+
+/// Ignore the progress of pruning since JSON output only has the result
+struct Quiet;
+
+impl PruneReporter for Quiet {}
+
 struct Progress {
-    start: Instant,
     analyze_start: Instant,
     switch_start: Instant,
     table_start: Instant,
@@ -30,7 +52,6 @@ struct Progress {
 impl Progress {
     fn new() -> Self {
         Self {
-            start: Instant::now(),
             analyze_start: Instant::now(),
             switch_start: Instant::now(),
             final_start: Instant::now(),
@@ -133,10 +154,6 @@ impl PruneReporter for Progress {
         }
         std::io::stdout().flush().ok();
     }
-
-    fn finish_prune(&mut self) {
-        println!("Finished pruning in {}s", self.start.elapsed().as_secs());
-    }
 }
 
 pub async fn run(
@@ -151,7 +168,7 @@ pub async fn run(
     let mut info = store
         .status(status::Filter::DeploymentIds(vec![deployment.id]))?
         .pop()
-        .ok_or_else(|| anyhow!("deployment {deployment} not found"))?;
+        .ok_or_else(|| CommandError::not_found(format!("deployment {deployment} not found")))?;
     if info.chains.len() > 1 {
         return Err(anyhow!(
             "deployment {deployment} indexes {} chains, not sure how to deal with more than one chain",
@@ -164,15 +181,19 @@ pub async fn run(
         .ok_or_else(|| anyhow!("deployment {} does not index any chain", deployment))?;
     let latest = status.latest_block.map(|ptr| ptr.number()).unwrap_or(0);
     if latest <= history {
-        return Err(anyhow!("deployment {deployment} has only indexed up to block {latest} and we can't preserve {history} blocks of history"));
+        return Err(CommandError::invalid_argument(format!("deployment {deployment} has only indexed up to block {latest} and we can't preserve {history} blocks of history")));
     }
 
-    println!("prune {deployment}");
-    println!("    latest: {latest}");
-    println!("     final: {}", latest - ETH_ENV.reorg_threshold);
-    println!("  earliest: {}\n", latest - history);
+    output::progress(format!("prune {deployment}"));
+    output::progress(format!("    latest: {latest}"));
+    output::progress(format!("     final: {}", latest - ETH_ENV.reorg_threshold));
+    output::progress(format!("  earliest: {}\n", latest - history));
 
-    let reporter = Box::new(Progress::new());
+    let start = Instant::now();
+    let reporter: Box<dyn PruneReporter> = match OutputFormat::current() {
+        OutputFormat::Text => Box::new(Progress::new()),
+        OutputFormat::Json => Box::new(Quiet),
+    };
     store
         .subgraph_store()
         .prune(
@@ -188,5 +209,11 @@ pub async fn run(
         )
         .await?;
 
-    Ok(())
+    output::emit(&PruneOutput {
+        deployment: DeploymentRef::from(&deployment),
+        latest_block: latest,
+        final_block: latest - ETH_ENV.reorg_threshold,
+        earliest_block: latest - history,
+        seconds: start.elapsed().as_secs(),
+    })
 }
@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 use std::iter::FromIterator;
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
@@ -9,15 +9,31 @@ use graph::prelude::r;
 use graph::{
     data::query::QueryTarget,
     prelude::{
-        anyhow::{self, anyhow},
-        serde_json, DeploymentHash, GraphQlRunner as _, Query, QueryVariables, SubgraphName,
+        anyhow, serde_json, DeploymentHash, GraphQlRunner as _, Query, QueryVariables, SubgraphName,
     },
 };
 use graph_graphql::prelude::GraphQlRunner;
 use graph_store_postgres::Store;
+use serde::Serialize;
 
+use crate::manager::output::{self, CommandError, CommandOutput};
 use crate::manager::PanicSubscriptionManager;
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryOutput {
+    /// The result of the query, like the GraphQL endpoint returns it
+    pub result: serde_json::Value,
+    /// How long the query took, as printed in text output
+    #[serde(skip)]
+    pub trace: String,
+}
+
+impl CommandOutput for QueryOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "{}", self.trace)
+    }
+}
+
 pub async fn run(
     runner: Arc<GraphQlRunner<Store, PanicSubscriptionManager>>,
     target: String,
@@ -27,12 +43,14 @@ pub async fn run(
     trace: Option<String>,
 ) -> Result<(), anyhow::Error> {
     let target = if target.starts_with("Qm") {
-        let id =
-            DeploymentHash::new(target).map_err(|id| anyhow!("illegal deployment id `{}`", id))?;
+        let id = DeploymentHash::new(target).map_err(|id| {
+            CommandError::invalid_argument(format!("illegal deployment id `{}`", id))
+        })?;
         QueryTarget::Deployment(id, Default::default())
     } else {
-        let name = SubgraphName::new(target.clone())
-            .map_err(|()| anyhow!("illegal subgraph name `{}`", target))?;
+        let name = SubgraphName::new(target.clone()).map_err(|()| {
+            CommandError::invalid_argument(format!("illegal subgraph name `{}`", target))
+        })?;
         QueryTarget::Name(name, Default::default())
     };
 
@@ -48,10 +66,10 @@ pub async fn run(
                 .unwrap_or(r::Value::Null);
             match key {
                 Some(key) => Ok((key, value)),
-                None => Err(anyhow!(
+                None => Err(CommandError::invalid_argument(format!(
                     "malformed variable `{}`, it must be of the form `key=value`",
                     v
-                )),
+                ))),
             }
         })
         .collect::<Result<_, _>>()?;
@@ -80,13 +98,22 @@ pub async fn run(
         writeln!(f, "{}", json)?;
     }
 
+    let mut brief = Vec::new();
     for trace in res.traces() {
-        print_brief_trace("root", trace, 0)?;
+        print_brief_trace(&mut brief, "root", trace, 0)?;
     }
-    Ok(())
+    output::emit(&QueryOutput {
+        result: serde_json::to_value(&res)?,
+        trace: String::from_utf8(brief)?,
+    })
 }
 
-fn print_brief_trace(name: &str, trace: &Trace, indent: usize) -> Result<(), anyhow::Error> {
+fn print_brief_trace(
+    out: &mut dyn Write,
+    name: &str,
+    trace: &Trace,
+    indent: usize,
+) -> Result<(), anyhow::Error> {
     use Trace::*;
 
     fn query_time(trace: &Trace) -> Duration {
@@ -114,20 +141,21 @@ fn print_brief_trace(name: &str, trace: &Trace, indent: usize) -> Result<(), any
             let qt = query_time(trace);
             let pt = elapsed - qt;
 
-            println!(
+            writeln!(
+                out,
                 "{space:indent$}{name:rest$} {elapsed:7}ms",
                 space = " ",
                 indent = indent,
                 rest = 48 - indent,
                 name = name,
                 elapsed = elapsed.as_millis(),
-            );
+            )?;
             for (name, trace) in children {
-                print_brief_trace(name, trace, indent + 2)?;
+                print_brief_trace(out, name, trace, indent + 2)?;
             }
-            println!("\nquery:      {:7}ms", qt.as_millis());
-            println!("other:      {:7}ms", pt.as_millis());
-            println!("total:      {:7}ms", elapsed.as_millis())
+            writeln!(out, "\nquery:      {:7}ms", qt.as_millis())?;
+            writeln!(out, "other:      {:7}ms", pt.as_millis())?;
+            writeln!(out, "total:      {:7}ms", elapsed.as_millis())?;
         }
         Query {
             elapsed,
@@ -135,7 +163,8 @@ fn print_brief_trace(name: &str, trace: &Trace, indent: usize) -> Result<(), any
             children,
             ..
         } => {
-            println!(
+            writeln!(
+                out,
                 "{space:indent$}{name:rest$} {elapsed:7}ms [{count:7} entities]",
                 space = " ",
                 indent = indent,
@@ -143,9 +172,9 @@ fn print_brief_trace(name: &str, trace: &Trace, indent: usize) -> Result<(), any
                 name = name,
                 elapsed = elapsed.as_millis(),
                 count = entity_count
-            );
+            )?;
             for (name, trace) in children {
-                print_brief_trace(name, trace, indent + 2)?;
+                print_brief_trace(out, name, trace, indent + 2)?;
             }
         }
     }
@@ -1,13 +1,38 @@
+use std::io::{self, Write};
 use std::sync::Arc;
 
-use graph::prelude::{anyhow, Error, SubgraphName, SubgraphStore as _};
+use graph::prelude::{Error, SubgraphName, SubgraphStore as _};
 use graph_store_postgres::SubgraphStore;
+use serde::Serialize;
+
+use crate::manager::output::{self, CommandError, CommandOutput};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RemoveOutput {
+    /// The name of the subgraph that was removed
+    pub subgraph: String,
+}
+
+impl CommandOutput for RemoveOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Removed subgraph {}", self.subgraph)
+    }
+}
 
 pub fn run(store: Arc<SubgraphStore>, name: &str) -> Result<(), Error> {
-    let name = SubgraphName::new(name).map_err(|()| anyhow!("illegal subgraph name `{}`", name))?;
+    let output = remove_subgraph(store, name)?;
+    output::emit(&output)
+}
+
+/// Remove the subgraph `name` without printing anything
+pub fn remove_subgraph(store: Arc<SubgraphStore>, name: &str) -> Result<RemoveOutput, Error> {
+    let name = SubgraphName::new(name).map_err(|()| {
+        CommandError::invalid_argument(format!("illegal subgraph name `{}`", name))
+    })?;
 
-    println!("Removing subgraph {}", name);
-    store.remove_subgraph(name)?;
+    store.remove_subgraph(name.clone())?;
 
-    Ok(())
+    Ok(RemoveOutput {
+        subgraph: name.to_string(),
+    })
 }
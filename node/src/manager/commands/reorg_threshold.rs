@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::blockchain::validate_reorg_threshold;
+use graph::prelude::anyhow::Error;
+use graph::prelude::BlockNumber;
+use graph_chain_ethereum::ENV_VARS as ETH_ENV;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::{Deployment, DeploymentSearch};
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef};
+
+fn find_unique(primary: &ConnectionPool, search: &DeploymentSearch) -> Result<Deployment, Error> {
+    let mut deployments = search.lookup(primary)?;
+    // The same deployment shows up once for each name it is deployed under
+    deployments.sort_by_key(|deployment| deployment.id);
+    deployments.dedup_by_key(|deployment| deployment.id);
+    match deployments.len() {
+        0 => Err(CommandError::not_found(format!(
+            "no deployment matches {}",
+            search
+        ))),
+        1 => Ok(deployments.pop().unwrap()),
+        _ => Err(CommandError::ambiguous(format!(
+            "{} matches more than one deployment, use the namespace `sgdNNN` to pick one",
+            search
+        ))),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReorgThresholdOutput {
+    pub deployment: DeploymentRef,
+    pub chain: String,
+    /// The threshold set for the deployment; `None` if it uses the
+    /// threshold of its chain
+    pub threshold: Option<BlockNumber>,
+    /// The threshold of the chain, which is also the highest threshold
+    /// the deployment can use
+    pub chain_threshold: BlockNumber,
+    /// The lowest threshold the deployment can use
+    pub min_threshold: BlockNumber,
+}
+
+impl CommandOutput for ReorgThresholdOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self.threshold {
+            Some(threshold) => writeln!(
+                out,
+                "reorg threshold: {} (chain default: {})",
+                threshold, self.chain_threshold
+            )?,
+            None => writeln!(
+                out,
+                "reorg threshold: chain default ({})",
+                self.chain_threshold
+            )?,
+        }
+        if self.min_threshold < self.chain_threshold {
+            writeln!(
+                out,
+                "allowed:         {} to {} blocks",
+                self.min_threshold, self.chain_threshold
+            )
+        } else {
+            writeln!(
+                out,
+                "allowed:         {} can not lower its reorg threshold",
+                self.chain
+            )
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReorgThresholdSet {
+    pub deployment: DeploymentRef,
+    /// The new threshold; `None` if the deployment uses the threshold of
+    /// its chain
+    pub threshold: Option<BlockNumber>,
+}
+
+impl CommandOutput for ReorgThresholdSet {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        match self.threshold {
+            Some(threshold) => writeln!(
+                out,
+                "set the reorg threshold of {deployment} to {threshold} blocks"
+            )?,
+            None => writeln!(out, "{deployment} uses the reorg threshold of its chain")?,
+        }
+        writeln!(
+            out,
+            "the block stream uses the new threshold the next time {deployment} is started"
+        )
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let deployment = find_unique(&primary, search)?;
+    let locator = deployment.locator();
+
+    let range = ETH_ENV
+        .min_reorg_thresholds
+        .range(&deployment.chain, ETH_ENV.reorg_threshold);
+    let threshold = store.reorg_threshold(&locator)?;
+    output::emit(&ReorgThresholdOutput {
+        deployment: DeploymentRef::from(&locator),
+        chain: deployment.chain,
+        threshold: threshold.map(|threshold| threshold.min(*range.end())),
+        chain_threshold: *range.end(),
+        min_threshold: *range.start(),
+    })
+}
+
+/// Set the reorg threshold of the deployment; `default` removes the
+/// override so that the deployment uses the reorg threshold of its chain
+pub fn set(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    threshold: &str,
+) -> Result<(), Error> {
+    let deployment = find_unique(&primary, search)?;
+    let locator = deployment.locator();
+
+    let threshold = match threshold {
+        "default" => None,
+        threshold => {
+            let threshold = threshold.parse::<BlockNumber>().map_err(|e| {
+                CommandError::invalid_argument(format!(
+                    "invalid reorg threshold `{}`: {}",
+                    threshold, e
+                ))
+            })?;
+            let range = ETH_ENV
+                .min_reorg_thresholds
+                .range(&deployment.chain, ETH_ENV.reorg_threshold);
+            validate_reorg_threshold(threshold, Some(range))
+                .map_err(|e| CommandError::invalid_argument(e.to_string()))?;
+            Some(threshold)
+        }
+    };
+    store.set_reorg_threshold(&locator, threshold)?;
+
+    output::emit(&ReorgThresholdSet {
+        deployment: DeploymentRef::from(&locator),
+        threshold,
+    })
+}
@@ -0,0 +1,286 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::data::subgraph::retention::{
+    parse_age, EntityRetention, RetentionOutcome, RetentionRule,
+};
+use graph::prelude::{anyhow::Error, BlockNumber, Logger, ENV_VARS};
+use graph_chain_ethereum::ENV_VARS as ETH_ENV;
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+use serde::Serialize;
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef};
+
+/// A retention policy; `rule` describes it for people, the other fields
+/// for scripts
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetentionPolicy {
+    pub entity_type: String,
+    pub rule: String,
+    pub timestamp_field: Option<String>,
+    pub max_age_secs: Option<u64>,
+    pub max_blocks: Option<BlockNumber>,
+}
+
+impl From<&EntityRetention> for RetentionPolicy {
+    fn from(retention: &EntityRetention) -> Self {
+        let (timestamp_field, max_age_secs, max_blocks) = match &retention.rule {
+            RetentionRule::Age { field, max_age } => {
+                (Some(field.clone()), Some(max_age.as_secs()), None)
+            }
+            RetentionRule::Blocks(blocks) => (None, None, Some(*blocks)),
+        };
+        RetentionPolicy {
+            entity_type: retention.entity_type.clone(),
+            rule: retention.rule.to_string(),
+            timestamp_field,
+            max_age_secs,
+            max_blocks,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetentionOutput {
+    pub deployment: DeploymentRef,
+    pub policies: Vec<RetentionPolicy>,
+}
+
+impl CommandOutput for RetentionOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.policies.is_empty() {
+            writeln!(out, "{} has no retention policies", self.deployment)?;
+        }
+        for policy in &self.policies {
+            writeln!(out, "{:<30} | {}", policy.entity_type, policy.rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetentionSet {
+    pub deployment: DeploymentRef,
+    pub policy: RetentionPolicy,
+}
+
+impl CommandOutput for RetentionSet {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        writeln!(
+            out,
+            "rows of {} in {deployment} are removed when {}",
+            self.policy.entity_type, self.policy.rule
+        )?;
+        writeln!(
+            out,
+            "the policy takes effect the next time {deployment} is started"
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetentionRemoved {
+    pub deployment: DeploymentRef,
+    pub entity_type: String,
+    /// Whether the entity type had a policy that was removed
+    pub removed: bool,
+}
+
+impl CommandOutput for RetentionRemoved {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let deployment = &self.deployment;
+        let entity_type = &self.entity_type;
+        if self.removed {
+            writeln!(
+                out,
+                "rows of {entity_type} in {deployment} are kept from now on"
+            )
+        } else {
+            writeln!(out, "{entity_type} in {deployment} has no retention policy")
+        }
+    }
+}
+
+/// What applying one retention policy removed
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PrunedEntityType {
+    pub policy: RetentionPolicy,
+    /// The number of rows that were, or would be, removed
+    pub rows: usize,
+    pub newest_block: Option<BlockNumber>,
+    /// Whether time-travel queries for blocks the deployment still has
+    /// history for could see the rows
+    pub loses_history: bool,
+    /// Whether rows were kept because of `loses_history` since
+    /// `GRAPH_STORE_RETENTION_STRICT_HISTORY` is set
+    pub strict: bool,
+    /// Whether all rows were removed; `false` if pruning stopped early
+    pub complete: bool,
+}
+
+impl From<&RetentionOutcome> for PrunedEntityType {
+    fn from(outcome: &RetentionOutcome) -> Self {
+        PrunedEntityType {
+            policy: RetentionPolicy::from(&outcome.retention),
+            rows: outcome.rows,
+            newest_block: outcome.newest_block,
+            loses_history: outcome.loses_history,
+            strict: outcome.loses_history && ENV_VARS.store.retention_strict_history,
+            complete: outcome.complete,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RetentionPruneOutput {
+    pub deployment: DeploymentRef,
+    pub dry_run: bool,
+    pub entity_types: Vec<PrunedEntityType>,
+}
+
+impl CommandOutput for RetentionPruneOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.entity_types.is_empty() {
+            return writeln!(out, "{} has no retention policies", self.deployment);
+        }
+
+        let verb = if self.dry_run {
+            "would remove"
+        } else {
+            "removed"
+        };
+        for pruned in &self.entity_types {
+            let policy = &pruned.policy;
+            writeln!(
+                out,
+                "{}: {} {} rows ({})",
+                policy.entity_type, verb, pruned.rows, policy.rule
+            )?;
+            if let Some(block) = pruned.newest_block {
+                writeln!(out, "    newest of them written at block {block}")?;
+            }
+            if pruned.loses_history && !pruned.strict {
+                writeln!(
+                    out,
+                    "    time-travel queries for blocks up to {} could see them; \
+                     use `graphman prune` to remove that history first",
+                    pruned.newest_block.unwrap_or_default()
+                )?;
+            }
+            if !self.dry_run && !pruned.complete {
+                if pruned.strict {
+                    writeln!(out, "    nothing removed since time-travel queries can still see these rows and GRAPH_STORE_RETENTION_STRICT_HISTORY is set")?;
+                } else {
+                    writeln!(out, "    pruning stopped early since the database is busy; the rest is removed later")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn show(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let policies = store
+        .entity_retention(&locator)?
+        .iter()
+        .map(RetentionPolicy::from)
+        .collect();
+    output::emit(&RetentionOutput {
+        deployment: DeploymentRef::from(&locator),
+        policies,
+    })
+}
+
+pub fn set(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    entity_type: String,
+    max_age: Option<String>,
+    timestamp_field: Option<String>,
+    max_blocks: Option<BlockNumber>,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let rule = match (max_age, timestamp_field, max_blocks) {
+        (Some(max_age), Some(field), None) => RetentionRule::Age {
+            field,
+            max_age: parse_age(&max_age)
+                .map_err(|e| CommandError::invalid_argument(e.to_string()))?,
+        },
+        (None, None, Some(blocks)) if blocks > 0 => RetentionRule::Blocks(blocks),
+        (None, None, Some(blocks)) => {
+            return Err(CommandError::invalid_argument(format!(
+                "--max-blocks must be positive, not {}",
+                blocks
+            )))
+        }
+        _ => {
+            return Err(CommandError::invalid_argument(
+                "use either --max-age and --timestamp-field or --max-blocks",
+            ))
+        }
+    };
+    let retention = EntityRetention { entity_type, rule };
+    store.set_entity_retention(&locator, &retention)?;
+    output::emit(&RetentionSet {
+        deployment: DeploymentRef::from(&locator),
+        policy: RetentionPolicy::from(&retention),
+    })
+}
+
+pub fn remove(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    entity_type: &str,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    let removed = store.remove_entity_retention(&locator, entity_type)?;
+    output::emit(&RetentionRemoved {
+        deployment: DeploymentRef::from(&locator),
+        entity_type: entity_type.to_string(),
+        removed,
+    })
+}
+
+/// Apply the retention policies of the deployment right away, or with
+/// `dry_run`, show what applying them would remove
+pub async fn prune(
+    logger: &Logger,
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+
+    // Unlike the background job, keep going until everything is removed
+    let deadline = Instant::now() + Duration::from_secs(365 * 24 * 60 * 60);
+    let outcomes = store
+        .apply_retention(
+            logger,
+            &locator,
+            // Using the setting for eth chains is a bit lazy, just like in
+            // `graphman prune`
+            ETH_ENV.reorg_threshold,
+            deadline,
+            dry_run,
+        )
+        .await?;
+    output::emit(&RetentionPruneOutput {
+        deployment: DeploymentRef::from(&locator),
+        dry_run,
+        entity_types: outcomes.iter().map(PrunedEntityType::from).collect(),
+    })
+}
@@ -1,3 +1,4 @@
+use std::io::{self, Write};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -8,8 +9,68 @@ use graph::components::store::{BlockStore as _, ChainStore as _};
 use graph::prelude::{anyhow, BlockNumber, BlockPtr, NodeId, SubgraphStore};
 use graph_store_postgres::BlockStore;
 use graph_store_postgres::{connection_pool::ConnectionPool, Store};
+use serde::Serialize;
 
 use crate::manager::deployment::{Deployment, DeploymentSearch};
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef, ErrorCode};
+
+/// What happened to one of the deployments that were rewound
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RewoundDeployment {
+    pub deployment: DeploymentRef,
+    /// Whether the deployment was paused; deployments that are not
+    /// assigned or already paused are not paused again
+    pub paused: bool,
+    pub rewound: bool,
+    /// Why the deployment could not be paused, rewound, or resumed
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RewindOutput {
+    pub block_hash: String,
+    pub block_number: BlockNumber,
+    pub deployments: Vec<RewoundDeployment>,
+}
+
+impl CommandOutput for RewindOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.deployments.is_empty() {
+            return writeln!(out, "nothing to do");
+        }
+        writeln!(
+            out,
+            "Rewinding to block {} ({})",
+            self.block_number, self.block_hash
+        )?;
+        for deployment in &self.deployments {
+            match &deployment.error {
+                None => writeln!(out, "  ... rewound {}", deployment.deployment)?,
+                Some(e) => writeln!(out, "  ... failed {}: {}", deployment.deployment, e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn failure(&self) -> Option<CommandError> {
+        let failed = self
+            .deployments
+            .iter()
+            .filter(|deployment| deployment.error.is_some())
+            .count();
+        if failed == 0 {
+            return None;
+        }
+        Some(CommandError::new(
+            ErrorCode::PartialFailure,
+            format!(
+                "rewinding failed for {} of {} deployments",
+                failed,
+                self.deployments.len()
+            ),
+        ))
+    }
+}
 
 async fn block_ptr(
     store: Arc<BlockStore>,
@@ -79,8 +140,11 @@ pub async fn run(
         .flatten()
         .collect::<Vec<_>>();
     if deployments.is_empty() {
-        println!("nothing to do");
-        return Ok(());
+        return output::emit(&RewindOutput {
+            block_hash,
+            block_number,
+            deployments: vec![],
+        });
     }
 
     let block_ptr_to = block_ptr(
@@ -93,42 +157,78 @@ pub async fn run(
     )
     .await?;
 
-    println!("Pausing deployments");
-    let mut paused = false;
-    for deployment in &deployments {
+    let mut results: Vec<_> = deployments
+        .iter()
+        .map(|deployment| RewoundDeployment {
+            deployment: DeploymentRef::from(&deployment.locator()),
+            paused: false,
+            rewound: false,
+            error: None,
+        })
+        .collect();
+
+    output::progress("Pausing deployments");
+    for (deployment, result) in deployments.iter().zip(results.iter_mut()) {
         if let Some(node) = &deployment.node_id {
             if !node.starts_with(PAUSED) {
                 let loc = deployment.locator();
                 let node =
                     NodeId::new(format!("{}{}", PAUSED, node)).expect("paused_ node id is valid");
-                subgraph_store.reassign_subgraph(&loc, &node)?;
-                println!("  ... paused {}", loc);
-                paused = true;
+                match subgraph_store.reassign_subgraph(&loc, &node) {
+                    Ok(()) => {
+                        output::progress(format!("  ... paused {}", loc));
+                        result.paused = true;
+                    }
+                    Err(e) => result.error = Some(format!("failed to pause: {}", e)),
+                }
             }
         }
     }
 
-    if paused {
+    if results.iter().any(|result| result.paused) {
         // There's no good way to tell that a subgraph has in fact stopped
         // indexing. We sleep and hope for the best.
-        println!("\nWaiting 10s to make sure pausing was processed");
+        output::progress(format!(
+            "\nWaiting {}s to make sure pausing was processed",
+            sleep.as_secs()
+        ));
         thread::sleep(sleep);
     }
 
-    println!("\nRewinding deployments");
-    for deployment in &deployments {
+    output::progress("\nRewinding deployments");
+    for (deployment, result) in deployments.iter().zip(results.iter_mut()) {
+        // Never rewind a deployment that might still be indexing
+        if result.error.is_some() {
+            continue;
+        }
         let loc = deployment.locator();
-        subgraph_store.rewind(loc.hash.clone(), block_ptr_to.clone())?;
-        println!("  ... rewound {}", loc);
+        match subgraph_store.rewind(loc.hash.clone(), block_ptr_to.clone()) {
+            Ok(()) => result.rewound = true,
+            Err(e) => result.error = Some(format!("failed to rewind: {}", e)),
+        }
     }
 
-    println!("Resuming deployments");
-    for deployment in &deployments {
+    output::progress("Resuming deployments");
+    for (deployment, result) in deployments.iter().zip(results.iter_mut()) {
+        if !result.paused {
+            continue;
+        }
         if let Some(node) = &deployment.node_id {
             let loc = deployment.locator();
             let node = NodeId::new(node.clone()).expect("node id is valid");
-            subgraph_store.reassign_subgraph(&loc, &node)?;
+            if let Err(e) = subgraph_store.reassign_subgraph(&loc, &node) {
+                let e = format!("failed to resume: {}", e);
+                result.error = Some(match result.error.take() {
+                    Some(prev) => format!("{}; {}", prev, e),
+                    None => e,
+                });
+            }
         }
     }
-    Ok(())
+
+    output::emit(&RewindOutput {
+        block_hash: block_ptr_to.hash_hex(),
+        block_number: block_ptr_to.number,
+        deployments: results,
+    })
 }
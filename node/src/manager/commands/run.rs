@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::io::{self, Write};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -7,6 +8,7 @@ use crate::chain::{
     create_ipfs_clients,
 };
 use crate::config::Config;
+use crate::manager::output::{self, CommandOutput, DeploymentRef};
 use crate::manager::PanicSubscriptionManager;
 use crate::store_builder::StoreBuilder;
 use crate::MetricsContext;
@@ -15,6 +17,7 @@ use ethereum::{ProviderEthRpcMetrics, RuntimeAdapter as EthereumRuntimeAdapter};
 use graph::anyhow::{bail, format_err};
 use graph::blockchain::{BlockchainKind, BlockchainMap};
 use graph::cheap_clone::CheapClone;
+use graph::components::bus::BusRouter;
 use graph::components::store::{BlockStore as _, DeploymentLocator};
 use graph::env::EnvVars;
 use graph::firehose::FirehoseEndpoints;
@@ -29,6 +32,26 @@ use graph_core::{
     LinkResolver, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
     SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunOutput {
+    pub subgraph: String,
+    pub deployment: DeploymentRef,
+    pub stop_block: BlockNumber,
+    /// The block the deployment had reached when it was stopped
+    pub block: BlockNumber,
+}
+
+impl CommandOutput for RunOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} reached block {} and was removed again (stop block {})",
+            self.deployment, self.block, self.stop_block
+        )
+    }
+}
 
 fn locate(store: &dyn SubgraphStore, hash: &str) -> Result<DeploymentLocator, anyhow::Error> {
     let mut locators = store.locators(&hash)?;
@@ -50,10 +73,10 @@ pub async fn run(
     subgraph: String,
     stop_block: BlockNumber,
 ) -> Result<(), anyhow::Error> {
-    println!(
+    output::progress(format!(
         "Run command: starting subgraph => {}, stop_block = {}",
         subgraph, stop_block
-    );
+    ));
 
     let env_vars = Arc::new(EnvVars::from_env().unwrap());
     let metrics_registry = metrics_ctx.registry.clone();
@@ -61,12 +84,14 @@ pub async fn run(
 
     // FIXME: Hard-coded IPFS config, take it from config file instead?
     let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &ipfs_url);
-    let ipfs_client = ipfs_clients.first().cloned().expect("Missing IPFS client");
+    assert!(!ipfs_clients.is_empty(), "Missing IPFS client");
     let ipfs_service = ipfs_service(
-        ipfs_client,
+        ipfs_clients.clone(),
         env_vars.mappings.max_ipfs_file_bytes as u64,
         env_vars.mappings.ipfs_timeout,
         env_vars.mappings.ipfs_request_limit,
+        env_vars.mappings.ipfs_strict_verification_file_data_sources,
+        logger.clone(),
     );
 
     // Convert the clients into a link resolver. Since we want to get past
@@ -128,6 +153,7 @@ pub async fn run(
         Arc::new(EthereumBlockRefetcher {}),
         Arc::new(EthereumAdapterSelector::new(
             logger_factory.clone(),
+            network_name.clone(),
             Arc::new(eth_adapters),
             Arc::new(firehose_endpoints.clone()),
             metrics_registry.clone(),
@@ -136,6 +162,7 @@ pub async fn run(
         Arc::new(EthereumRuntimeAdapter {
             call_cache: chain_store.cheap_clone(),
             eth_adapters: Arc::new(eth_adapters2),
+            chain_store: chain_store.cheap_clone(),
         }),
         ethereum::ENV_VARS.reorg_threshold,
         // We assume the tested chain is always ingestible for now
@@ -157,7 +184,7 @@ pub async fn run(
         link_resolver.cheap_clone(),
         ipfs_service,
         static_filters,
-        None,
+        Arc::new(BusRouter::default()),
     );
 
     // Create IPFS-based subgraph provider
@@ -218,10 +245,11 @@ pub async fn run(
 
     let locator = locate(subgraph_store.as_ref(), &hash)?;
 
+    let deployment = DeploymentRef::from(&locator);
     SubgraphAssignmentProvider::start(subgraph_provider.as_ref(), locator, Some(stop_block))
         .await?;
 
-    loop {
+    let block = loop {
         tokio::time::sleep(Duration::from_millis(1000)).await;
 
         let block_ptr = subgraph_store
@@ -237,9 +265,9 @@ pub async fn run(
                 &logger,
                 "subgraph now at block {}, reached stop block {}", block_ptr.number, stop_block
             );
-            break;
+            break block_ptr.number;
         }
-    }
+    };
 
     info!(&logger, "Removing subgraph {}", name);
     subgraph_store.clone().remove_subgraph(subgraph_name)?;
@@ -257,5 +285,10 @@ pub async fn run(
         .await??;
     }
 
-    Ok(())
+    output::emit(&RunOutput {
+        subgraph: name.to_string(),
+        deployment,
+        stop_block,
+        block,
+    })
 }
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io::{self, Write};
 use std::sync::Arc;
 
 use crate::manager::deployment::DeploymentSearch;
+use crate::manager::output::{self, CommandOutput, DeploymentRef};
 use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::PooledConnection;
 use diesel::PgConnection;
@@ -15,6 +17,170 @@ use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::Shard;
 use graph_store_postgres::SubgraphStore;
 use graph_store_postgres::PRIMARY_SHARD;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TableStats {
+    pub table: String,
+    pub entities: i32,
+    pub versions: i32,
+    /// The ratio `entities / versions`
+    pub ratio: f64,
+    pub account_like: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatsOutput {
+    pub tables: Vec<TableStats>,
+}
+
+impl StatsOutput {
+    pub fn new(stats: &[VersionStats], account_like: &HashSet<String>) -> Self {
+        let tables = stats
+            .iter()
+            .map(|s| TableStats {
+                table: s.tablename.clone(),
+                entities: s.entities,
+                versions: s.versions,
+                ratio: s.ratio,
+                account_like: account_like.contains(&s.tablename),
+            })
+            .collect();
+        StatsOutput { tables }
+    }
+}
+
+impl CommandOutput for StatsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:^30} | {:^10} | {:^10} | {:^7}",
+            "table", "entities", "versions", "ratio"
+        )?;
+        writeln!(out, "{:-^30}-+-{:-^10}-+-{:-^10}-+-{:-^7}", "", "", "", "")?;
+        for s in &self.tables {
+            writeln!(
+                out,
+                "{:<26} {:3} | {:>10} | {:>10} | {:>5.1}%",
+                abbreviate_table_name(&s.table, 26),
+                if s.account_like { "(a)" } else { "   " },
+                s.entities,
+                s.versions,
+                s.ratio * 100.0
+            )?;
+        }
+        if self.tables.iter().any(|s| s.account_like) {
+            writeln!(out, "  (a): account-like flag set")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AccountLikeOutput {
+    pub table: String,
+    pub account_like: bool,
+}
+
+impl CommandOutput for AccountLikeOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let text = if self.account_like { "set" } else { "cleared" };
+        writeln!(out, "{}: account-like flag {}", self.table, text)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AnalyzeOutput {
+    pub deployment: DeploymentRef,
+    /// The entity type whose table was analyzed; all tables if `None`
+    pub entity: Option<String>,
+}
+
+impl CommandOutput for AnalyzeOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.entity {
+            Some(entity) => writeln!(out, "Analyzed table sgd{}.{}", self.deployment.id, entity),
+            None => writeln!(out, "Analyzed all tables for sgd{}", self.deployment.id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ColumnTarget {
+    pub table: String,
+    pub column: String,
+    pub target: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatsTargetsOutput {
+    pub deployment: DeploymentRef,
+    /// The statistics target of the database
+    pub default: i32,
+    /// The columns whose statistics target differs from the default
+    pub targets: Vec<ColumnTarget>,
+}
+
+impl CommandOutput for StatsTargetsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let id = self.deployment.id;
+        let default = self.default;
+        if self.targets.is_empty() {
+            return writeln!(
+                out,
+                "no statistics targets set for sgd{id}, global default is {default}"
+            );
+        }
+        writeln!(
+            out,
+            "{:^74}",
+            format!("Statistics targets for sgd{id} (default: {default})")
+        )?;
+        writeln!(out, "{:^30} | {:^30} | {:^8}", "table", "column", "target")?;
+        writeln!(out, "{:-^30}-+-{:-^30}-+-{:-^8}", "", "", "")?;
+        for target in &self.targets {
+            writeln!(
+                out,
+                "{:<30} | {:<30} | {:>8}",
+                target.table, target.column, target.target
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SetTargetOutput {
+    pub deployment: DeploymentRef,
+    pub entity: Option<String>,
+    pub columns: Vec<String>,
+    /// The new statistics target; -1 resets it to the default
+    pub target: i32,
+    pub analyzed: bool,
+}
+
+impl CommandOutput for SetTargetOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        let tables = match &self.entity {
+            Some(entity) => format!("sgd{}.{}", self.deployment.id, entity),
+            None => format!("all tables of sgd{}", self.deployment.id),
+        };
+        let columns = self.columns.join(", ");
+        if self.target < 0 {
+            writeln!(out, "reset statistics target of {columns} in {tables}")?;
+        } else {
+            writeln!(
+                out,
+                "set statistics target of {columns} in {tables} to {}",
+                self.target
+            )?;
+        }
+        if self.analyzed {
+            writeln!(out, "analyzed {tables}")?;
+        }
+        Ok(())
+    }
+}
 
 fn site_and_conn(
     pools: HashMap<Shard, ConnectionPool>,
@@ -45,10 +211,10 @@ pub async fn account_like(
     let locator = search.locate_unique(&primary_pool)?;
 
     store.set_account_like(&locator, &table, !clear).await?;
-    let clear_text = if clear { "cleared" } else { "set" };
-    println!("{}: account-like flag {}", table, clear_text);
-
-    Ok(())
+    output::emit(&AccountLikeOutput {
+        table,
+        account_like: !clear,
+    })
 }
 
 pub fn abbreviate_table_name(table: &str, size: usize) -> String {
@@ -64,41 +230,12 @@ pub fn abbreviate_table_name(table: &str, size: usize) -> String {
     }
 }
 
+/// Print `stats` as text, for commands that show them along the way
 pub fn show_stats(
     stats: &[VersionStats],
     account_like: HashSet<String>,
 ) -> Result<(), anyhow::Error> {
-    fn header() {
-        println!(
-            "{:^30} | {:^10} | {:^10} | {:^7}",
-            "table", "entities", "versions", "ratio"
-        );
-        println!("{:-^30}-+-{:-^10}-+-{:-^10}-+-{:-^7}", "", "", "", "");
-    }
-
-    fn footer() {
-        println!("  (a): account-like flag set");
-    }
-
-    fn print_stats(s: &VersionStats, account_like: bool) {
-        println!(
-            "{:<26} {:3} | {:>10} | {:>10} | {:>5.1}%",
-            abbreviate_table_name(&s.tablename, 26),
-            if account_like { "(a)" } else { "   " },
-            s.entities,
-            s.versions,
-            s.ratio * 100.0
-        );
-    }
-
-    header();
-    for s in stats {
-        print_stats(s, account_like.contains(&s.tablename));
-    }
-    if !account_like.is_empty() {
-        footer();
-    }
-
+    StatsOutput::new(stats, &account_like).render_text(&mut io::stdout().lock())?;
     Ok(())
 }
 
@@ -112,7 +249,7 @@ pub fn show(
 
     let account_like = store_catalog::account_like(&conn, &site)?;
 
-    show_stats(stats.as_slice(), account_like)
+    output::emit(&StatsOutput::new(stats.as_slice(), &account_like))
 }
 
 pub fn analyze(
@@ -122,17 +259,23 @@ pub fn analyze(
     entity_name: Option<&str>,
 ) -> Result<(), anyhow::Error> {
     let locator = search.locate_unique(&pool)?;
-    analyze_loc(store, &locator, entity_name)
+    analyze_loc(&store, &locator, entity_name)?;
+    output::emit(&AnalyzeOutput {
+        deployment: DeploymentRef::from(&locator),
+        entity: entity_name.map(str::to_string),
+    })
 }
 
 fn analyze_loc(
-    store: Arc<SubgraphStore>,
+    store: &SubgraphStore,
     locator: &DeploymentLocator,
     entity_name: Option<&str>,
 ) -> Result<(), anyhow::Error> {
     match entity_name {
-        Some(entity_name) => println!("Analyzing table sgd{}.{entity_name}", locator.id),
-        None => println!("Analyzing all tables for sgd{}", locator.id),
+        Some(entity_name) => {
+            output::progress(format!("Analyzing table sgd{}.{entity_name}", locator.id))
+        }
+        None => output::progress(format!("Analyzing all tables for sgd{}", locator.id)),
     }
     store.analyze(&locator, entity_name).map_err(|e| anyhow!(e))
 }
@@ -145,34 +288,25 @@ pub fn target(
     let locator = search.locate_unique(&primary)?;
     let (default, targets) = store.stats_targets(&locator)?;
 
-    let has_targets = targets
-        .values()
-        .any(|cols| cols.values().any(|target| *target > 0));
+    let targets = targets
+        .into_iter()
+        .flat_map(|(table, columns)| {
+            columns
+                .into_iter()
+                .filter(|(_, target)| *target > 0)
+                .map(move |(column, target)| ColumnTarget {
+                    table: table.to_string(),
+                    column: column.to_string(),
+                    target,
+                })
+        })
+        .collect();
 
-    if has_targets {
-        println!(
-            "{:^74}",
-            format!(
-                "Statistics targets for sgd{} (default: {default})",
-                locator.id
-            )
-        );
-        println!("{:^30} | {:^30} | {:^8}", "table", "column", "target");
-        println!("{:-^30}-+-{:-^30}-+-{:-^8}", "", "", "");
-        for (table, columns) in targets {
-            for (column, target) in columns {
-                if target > 0 {
-                    println!("{:<30} | {:<30} | {:>8}", table, column, target);
-                }
-            }
-        }
-    } else {
-        println!(
-            "no statistics targets set for sgd{}, global default is {default}",
-            locator.id
-        );
-    }
-    Ok(())
+    output::emit(&StatsTargetsOutput {
+        deployment: DeploymentRef::from(&locator),
+        default,
+        targets,
+    })
 }
 
 pub fn set_target(
@@ -192,10 +326,16 @@ pub fn set_target(
 
     let locator = search.locate_unique(&primary)?;
 
-    store.set_stats_target(&locator, entity, columns, target)?;
+    store.set_stats_target(&locator, entity, columns.clone(), target)?;
 
     if !no_analyze {
-        analyze_loc(store, &locator, entity)?;
+        analyze_loc(&store, &locator, entity)?;
     }
-    Ok(())
+    output::emit(&SetTargetOutput {
+        deployment: DeploymentRef::from(&locator),
+        entity: entity.map(str::to_string),
+        columns,
+        target,
+        analyzed: !no_analyze,
+    })
 }
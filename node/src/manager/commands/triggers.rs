@@ -0,0 +1,209 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use graph::blockchain::replay::{
+    ReplayBlock, ReplayDataSource, ReplayNodeVersion, ReplayTrigger, ReplayableTrigger,
+    TriggerFile, TRIGGER_FILE_VERSION,
+};
+use graph::blockchain::{Block as _, DataSource as _, TriggerFilter as _, TriggersAdapter as _};
+use graph::cheap_clone::CheapClone;
+use graph::components::store::{
+    BlockStore as _, DeploymentLocator, StatusStore as _, SubgraphStore as _,
+};
+use graph::data::subgraph::status;
+use graph::data::subgraph::SubgraphManifest;
+use graph::env::EnvVars;
+use graph::prelude::{
+    anyhow::{anyhow, Context, Error},
+    serde_json, serde_yaml, BlockNumber, LinkResolver as _, MetricsRegistry, ENV_VARS,
+};
+use graph::slog::{info, Logger};
+use graph_chain_ethereum::chain::TriggersAdapter;
+use graph_chain_ethereum::{Chain, EthereumNetworks, SubgraphEthRpcMetrics, TriggerFilter};
+use graph_core::{load_dynamic_data_sources, LinkResolver};
+use graph_store_postgres::Store;
+use serde::Serialize;
+
+use crate::chain::create_ipfs_clients;
+use crate::manager::output::{CommandOutput, DeploymentRef};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TriggersExported {
+    pub deployment: DeploymentRef,
+    /// The number of blocks with triggers in the file
+    pub blocks: usize,
+    pub triggers: usize,
+    pub file: String,
+}
+
+impl TriggersExported {
+    pub fn new(deployment: &DeploymentLocator, file: &TriggerFile, path: &str) -> Self {
+        TriggersExported {
+            deployment: DeploymentRef::from(deployment),
+            blocks: file.blocks.len(),
+            triggers: file.blocks.iter().map(|block| block.triggers.len()).sum(),
+            file: path.to_string(),
+        }
+    }
+}
+
+impl CommandOutput for TriggersExported {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "wrote the triggers of {} blocks of {} to {}",
+            self.blocks, self.deployment, self.file
+        )
+    }
+}
+
+/// Collect the triggers that the data sources of `deployment` match in
+/// `blocks` into a trigger file. Offchain data sources are not exported
+pub async fn export(
+    logger: &Logger,
+    store: Arc<Store>,
+    networks: EthereumNetworks,
+    registry: Arc<dyn MetricsRegistry>,
+    ipfs_url: Vec<String>,
+    deployment: &DeploymentLocator,
+    blocks: RangeInclusive<BlockNumber>,
+) -> Result<TriggerFile, Error> {
+    let env_vars = Arc::new(EnvVars::from_env()?);
+    let link_resolver: Arc<dyn graph::prelude::LinkResolver> = Arc::new(LinkResolver::new(
+        create_ipfs_clients(logger, &ipfs_url),
+        env_vars,
+    ));
+
+    let raw = link_resolver
+        .cat(logger, &deployment.hash.to_ipfs_link())
+        .await
+        .with_context(|| format!("failed to fetch the manifest of {}", deployment))?;
+    let raw: serde_yaml::Mapping = serde_yaml::from_slice(&raw)?;
+    let mut manifest = SubgraphManifest::<Chain>::resolve_from_raw(
+        deployment.hash.cheap_clone(),
+        raw,
+        &link_resolver,
+        logger,
+        ENV_VARS.max_spec_version.clone(),
+    )
+    .await?;
+    let writable = store
+        .subgraph_store()
+        .writable(logger.clone(), deployment.id)
+        .await?;
+    let dynamic = load_dynamic_data_sources(writable, logger.clone(), &manifest).await?;
+    manifest.data_sources.extend(dynamic);
+
+    let network = manifest.network_name();
+    let data_sources: Vec<_> = manifest
+        .data_sources
+        .iter()
+        .filter_map(|ds| ds.as_onchain())
+        .collect();
+    let filter = TriggerFilter::from_data_sources(data_sources.iter().copied());
+    let chain_store = store
+        .block_store()
+        .chain_store(&network)
+        .ok_or_else(|| anyhow!("could not find a network named `{}`", network))?;
+    let eth_adapter = networks
+        .networks
+        .get(&network)
+        .ok_or_else(|| anyhow!("no Ethereum adapters for network `{}`", network))?
+        .cheapest_with(&filter.node_capabilities())?;
+    let adapter = TriggersAdapter::new(
+        logger.clone(),
+        deployment.hash.cheap_clone(),
+        Arc::new(SubgraphEthRpcMetrics::new(registry, &deployment.hash)),
+        chain_store,
+        eth_adapter,
+        manifest.unified_mapping_api_version()?,
+    );
+
+    info!(logger, "Scanning blocks {} to {} for triggers", blocks.start(), blocks.end();
+          "deployment" => deployment.hash.as_str());
+    let mut replay_blocks = Vec::new();
+    for block in adapter
+        .scan_triggers(*blocks.start(), *blocks.end(), &filter)
+        .await?
+    {
+        let number = block.block.number();
+        let hash = block.block.hash();
+        let block_data = Arc::new(block.block);
+        let mut data = None;
+        let mut triggers = Vec::new();
+        for trigger_data in &block.trigger_data {
+            for (idx, ds) in data_sources.iter().enumerate() {
+                let active = ds.start_block() <= number
+                    && ds
+                        .creation_block()
+                        .map_or(true, |created| created <= number);
+                if !active {
+                    continue;
+                }
+                let trigger = match ds.match_and_decode(trigger_data, &block_data, logger)? {
+                    Some(trigger) => trigger,
+                    None => continue,
+                };
+                if data.is_none() {
+                    data = Some(serde_json::to_value(trigger.trigger.replay_block())?);
+                }
+                triggers.push(ReplayTrigger {
+                    data_source: idx,
+                    handler: trigger.handler_name().to_string(),
+                    trigger: trigger.trigger.to_replay()?,
+                });
+            }
+        }
+        if let Some(data) = data {
+            replay_blocks.push(ReplayBlock {
+                number,
+                hash: hash.to_string(),
+                data,
+                triggers,
+            });
+        }
+    }
+
+    let data_sources = data_sources
+        .iter()
+        .map(|ds| ReplayDataSource {
+            name: ds.name().to_string(),
+            address: ds.address().map(Into::into),
+            api_version: ds.api_version().to_string(),
+            context: (*ds.context()).clone(),
+        })
+        .collect();
+
+    // The versions of graph-node that indexed any of the exported blocks
+    let node_versions = store
+        .status(status::Filter::DeploymentIds(vec![deployment.id]))?
+        .into_iter()
+        .flat_map(|info| info.node_versions)
+        .filter(|version| {
+            version
+                .start_block
+                .map_or(true, |start| start <= *blocks.end())
+                && version.end_block.map_or(true, |end| end >= *blocks.start())
+        })
+        .map(|version| ReplayNodeVersion {
+            git_commit_hash: version.git_commit_hash,
+            crate_version: version.crate_version,
+            host_export_hash: version.host_export_hash,
+            start_block: version.start_block,
+            end_block: version.end_block,
+        })
+        .collect();
+
+    Ok(TriggerFile {
+        version: TRIGGER_FILE_VERSION,
+        deployment: deployment.hash.to_string(),
+        chain: "ethereum".to_string(),
+        network,
+        spec_version: manifest.spec_version.to_string(),
+        schema: manifest.schema.document.to_string(),
+        data_sources,
+        blocks: replay_blocks,
+        node_versions,
+    })
+}
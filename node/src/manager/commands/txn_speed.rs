@@ -1,10 +1,44 @@
 use diesel::PgConnection;
+use std::io::{self, Write};
 use std::{collections::HashMap, thread::sleep, time::Duration};
 
 use graph::prelude::anyhow;
 use graph_store_postgres::connection_pool::ConnectionPool;
+use serde::Serialize;
 
 use crate::manager::catalog;
+use crate::manager::output::{self, CommandOutput};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TxnSpeed {
+    pub database: String,
+    /// All transactions per minute
+    pub all: f64,
+    /// Write transactions per minute
+    pub write: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TxnSpeedOutput {
+    /// How many seconds the transactions were counted for
+    pub delay: u64,
+    pub databases: Vec<TxnSpeed>,
+}
+
+impl CommandOutput for TxnSpeedOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Number of transactions/minute")?;
+        writeln!(out, "{:10} {:>7} {}", "database", "all", "write")?;
+        for speed in &self.databases {
+            writeln!(
+                out,
+                "{:10} {:>7} {}",
+                speed.database, speed.all, speed.write
+            )?;
+        }
+        Ok(())
+    }
+}
 
 pub fn run(pool: ConnectionPool, delay: u64) -> Result<(), anyhow::Error> {
     fn query(conn: &PgConnection) -> Result<Vec<(String, i64, i64)>, anyhow::Error> {
@@ -35,13 +69,12 @@ pub fn run(pool: ConnectionPool, delay: u64) -> Result<(), anyhow::Error> {
     for (datname, all_txn, write_txn) in query(&conn)? {
         speeds.insert(datname, (all_txn, write_txn));
     }
-    println!(
+    output::progress(format!(
         "Looking for number of transactions performed in {}s ...",
         delay
-    );
+    ));
     sleep(Duration::from_secs(delay));
-    println!("Number of transactions/minute");
-    println!("{:10} {:>7} {}", "database", "all", "write");
+    let mut databases = Vec::new();
     for (datname, all_txn, write_txn) in query(&conn)? {
         let (all_speed, write_speed) = speeds
             .get(&datname)
@@ -49,10 +82,12 @@ pub fn run(pool: ConnectionPool, delay: u64) -> Result<(), anyhow::Error> {
                 (all_txn - *all_txn_old, write_txn - *write_txn_old)
             })
             .unwrap_or((0, 0));
-        let all_speed = all_speed as f64 * 60.0 / delay as f64;
-        let write_speed = write_speed as f64 * 60.0 / delay as f64;
-        println!("{:10} {:>7} {}", datname, all_speed, write_speed);
+        databases.push(TxnSpeed {
+            database: datname,
+            all: all_speed as f64 * 60.0 / delay as f64,
+            write: write_speed as f64 * 60.0 / delay as f64,
+        });
     }
 
-    Ok(())
+    output::emit(&TxnSpeedOutput { delay, databases })
 }
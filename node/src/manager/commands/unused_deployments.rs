@@ -1,76 +1,202 @@
-use std::{sync::Arc, time::Instant};
+use std::io::{self, Write};
+use std::{sync::Arc, thread, time::Instant};
 
-use graph::prelude::{anyhow::Error, chrono};
-use graph_store_postgres::{unused, SubgraphStore, UnusedDeployment};
+use graph::components::store::DeploymentId;
+use graph::prelude::{anyhow::Error, chrono, StoreError, ENV_VARS};
+use graph_store_postgres::{unused, DeploymentRemoval, SubgraphStore, UnusedDeployment};
+use serde::Serialize;
 
 use crate::manager::display::List;
+use crate::manager::output::{self, CommandError, CommandOutput, DeploymentRef, ErrorCode};
 
-fn make_list() -> List {
-    List::new(vec!["id", "shard", "namespace", "subgraphs", "entities"])
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UnusedDeploymentInfo {
+    pub deployment: DeploymentRef,
+    pub shard: String,
+    pub namespace: String,
+    pub subgraphs: Vec<String>,
+    pub entity_count: i32,
 }
 
-fn add_row(list: &mut List, deployment: UnusedDeployment) {
-    let UnusedDeployment {
-        id,
-        shard,
-        namespace,
-        subgraphs,
-        entity_count,
-        ..
-    } = deployment;
-    let subgraphs = subgraphs.unwrap_or(vec![]).join(", ");
+impl From<&UnusedDeployment> for UnusedDeploymentInfo {
+    fn from(unused: &UnusedDeployment) -> Self {
+        UnusedDeploymentInfo {
+            deployment: DeploymentRef {
+                deployment: unused.deployment.clone(),
+                id: DeploymentId::from(unused.id).0,
+            },
+            shard: unused.shard.clone(),
+            namespace: unused.namespace.clone(),
+            subgraphs: unused.subgraphs.clone().unwrap_or_default(),
+            entity_count: unused.entity_count,
+        }
+    }
+}
+
+fn write_unused(out: &mut dyn Write, deployments: &[UnusedDeploymentInfo]) -> io::Result<()> {
+    let mut list = List::new(vec!["id", "shard", "namespace", "subgraphs", "entities"]);
+    for deployment in deployments {
+        list.append(vec![
+            deployment.deployment.id.to_string(),
+            deployment.shard.clone(),
+            deployment.namespace.clone(),
+            deployment.subgraphs.join(", "),
+            deployment.entity_count.to_string(),
+        ]);
+    }
+    list.write(out)
+}
 
-    list.append(vec![
-        id.to_string(),
-        shard,
-        namespace,
-        subgraphs,
-        entity_count.to_string(),
-    ])
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UnusedListOutput {
+    pub deployments: Vec<UnusedDeploymentInfo>,
 }
 
-pub fn list(store: Arc<SubgraphStore>, existing: bool) -> Result<(), Error> {
-    let mut list = make_list();
+impl CommandOutput for UnusedListOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.deployments.is_empty() {
+            return writeln!(out, "no unused deployments");
+        }
+        write_unused(out, &self.deployments)
+    }
+}
 
+pub fn list(store: Arc<SubgraphStore>, existing: bool) -> Result<(), Error> {
     let filter = if existing {
         unused::Filter::New
     } else {
         unused::Filter::All
     };
 
-    for deployment in store.list_unused_deployments(filter)? {
-        add_row(&mut list, deployment);
-    }
+    let deployments = store
+        .list_unused_deployments(filter)?
+        .iter()
+        .map(UnusedDeploymentInfo::from)
+        .collect();
 
-    if list.is_empty() {
-        println!("no unused deployments");
-    } else {
-        list.render();
-    }
+    output::emit(&UnusedListOutput { deployments })
+}
 
-    Ok(())
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UnusedRecordOutput {
+    /// The deployments that were newly recorded as unused
+    pub deployments: Vec<UnusedDeploymentInfo>,
+}
+
+impl CommandOutput for UnusedRecordOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        write_unused(out, &self.deployments)?;
+        writeln!(
+            out,
+            "Recorded {} unused deployments",
+            self.deployments.len()
+        )
+    }
 }
 
 pub fn record(store: Arc<SubgraphStore>) -> Result<(), Error> {
-    let mut list = make_list();
+    let output = record_unused(store)?;
+    output::emit(&output)
+}
 
-    println!("Recording unused deployments. This might take a while.");
+/// Record the unused deployments and return the ones that were newly
+/// recorded
+pub fn record_unused(store: Arc<SubgraphStore>) -> Result<UnusedRecordOutput, Error> {
+    output::progress("Recording unused deployments. This might take a while.");
     let recorded = store.record_unused_deployments()?;
 
-    for unused in store.list_unused_deployments(unused::Filter::New)? {
-        if recorded
+    let deployments = store
+        .list_unused_deployments(unused::Filter::New)?
+        .iter()
+        .filter(|unused| recorded.iter().any(|r| r.deployment == unused.deployment))
+        .map(UnusedDeploymentInfo::from)
+        .collect();
+
+    Ok(UnusedRecordOutput { deployments })
+}
+
+/// What happened to one of the unused deployments that were to be removed
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RemovedDeployment {
+    #[serde(flatten)]
+    pub unused: UnusedDeploymentInfo,
+    /// Whether the deployment is gone; deployments that are in use again
+    /// are not removed
+    pub removed: bool,
+    /// How long the removal took in seconds
+    pub seconds: f64,
+    /// Why the removal failed
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UnusedRemoveOutput {
+    /// The deployment the command was restricted to, if any
+    pub filter: Option<String>,
+    pub deployments: Vec<RemovedDeployment>,
+}
+
+impl CommandOutput for UnusedRemoveOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.deployments.is_empty() {
+            return match &self.filter {
+                Some(s) => writeln!(out, "No unused subgraph matches `{}`", s),
+                None => writeln!(out, "Nothing to remove."),
+            };
+        }
+        let removed = self.deployments.iter().filter(|d| d.removed).count();
+        writeln!(
+            out,
+            "Removed {} of {} unused deployments",
+            removed,
+            self.deployments.len()
+        )
+    }
+
+    fn failure(&self) -> Option<CommandError> {
+        let failed = self
+            .deployments
             .iter()
-            .find(|r| r.deployment == unused.deployment)
-            .is_some()
-        {
-            add_row(&mut list, unused);
+            .filter(|deployment| deployment.error.is_some())
+            .count();
+        if failed == 0 {
+            return None;
         }
+        Some(CommandError::new(
+            ErrorCode::PartialFailure,
+            format!(
+                "removal failed for {} of {} deployments",
+                failed,
+                self.deployments.len()
+            ),
+        ))
     }
+}
 
-    list.render();
-    println!("Recorded {} unused deployments", recorded.len());
+/// Remove the deployment `deployment` one table at a time, pausing between
+/// tables like the background removal does. Return `false` if the
+/// deployment is in use and was not removed
+fn remove_by_table(
+    store: &SubgraphStore,
+    deployment: &UnusedDeployment,
+) -> Result<bool, StoreError> {
+    if !store.start_removal(deployment.id)? {
+        output::progress("deployment is in use and was not removed");
+        return Ok(false);
+    }
 
-    Ok(())
+    let mut tables = 0;
+    loop {
+        let step = store.continue_removal(deployment.id)?;
+        tables += step.tables_dropped;
+        if step.tables_dropped > 0 || step.done {
+            output::progress(format!("  {:>14}: {} tables dropped", "progress", tables));
+        }
+        if step.done {
+            return Ok(true);
+        }
+        thread::sleep(ENV_VARS.store.removal_pause);
+    }
 }
 
 pub fn remove(
@@ -78,7 +204,21 @@ pub fn remove(
     count: usize,
     deployment: Option<&str>,
     older: Option<chrono::Duration>,
+    fast: bool,
 ) -> Result<(), Error> {
+    let output = remove_unused(store, count, deployment, older, fast)?;
+    output::emit(&output)
+}
+
+/// Remove up to `count` unused deployments and report what happened to
+/// each of them; errors removing a deployment are part of the output
+pub fn remove_unused(
+    store: Arc<SubgraphStore>,
+    count: usize,
+    deployment: Option<&str>,
+    older: Option<chrono::Duration>,
+    fast: bool,
+) -> Result<UnusedRemoveOutput, Error> {
     let filter = match older {
         Some(duration) => unused::Filter::UnusedLongerThan(duration),
         None => unused::Filter::New,
@@ -92,48 +232,143 @@ pub fn remove(
             .collect::<Vec<_>>(),
     };
 
-    if unused.is_empty() {
-        match &deployment {
-            Some(s) => println!("No unused subgraph matches `{}`", s),
-            None => println!("Nothing to remove."),
-        }
-        return Ok(());
-    }
-
+    let mut deployments = Vec::new();
     for (i, deployment) in unused.iter().take(count).enumerate() {
-        println!("{:=<36} {:4} {:=<36}", "", i + 1, "");
-        println!(
+        output::progress(format!("{:=<36} {:4} {:=<36}", "", i + 1, ""));
+        output::progress(format!(
             "removing {} from {}",
             deployment.namespace, deployment.shard
-        );
-        println!("  {:>14}: {}", "deployment id", deployment.deployment);
-        println!("  {:>14}: {}", "entities", deployment.entity_count);
+        ));
+        output::progress(format!(
+            "  {:>14}: {}",
+            "deployment id", deployment.deployment
+        ));
+        output::progress(format!("  {:>14}: {}", "entities", deployment.entity_count));
         if let Some(subgraphs) = &deployment.subgraphs {
             let mut first = true;
             for name in subgraphs {
                 if first {
-                    println!("  {:>14}: {}", "subgraphs", name);
+                    output::progress(format!("  {:>14}: {}", "subgraphs", name));
                 } else {
-                    println!("  {:>14}  {}", "", name);
+                    output::progress(format!("  {:>14}  {}", "", name));
                 }
                 first = false;
             }
         }
 
         let start = Instant::now();
-        match store.remove_deployment(deployment.id) {
-            Ok(()) => {
-                println!(
-                    "done removing {} from {} in {:.1}s\n",
-                    deployment.namespace,
-                    deployment.shard,
-                    start.elapsed().as_millis() as f64 / 1000.0
-                );
+        let res = if fast {
+            store.remove_deployment(deployment.id).map(|()| true)
+        } else {
+            remove_by_table(&store, deployment)
+        };
+        let seconds = start.elapsed().as_millis() as f64 / 1000.0;
+        let (removed, error) = match res {
+            Ok(removed) => {
+                if removed {
+                    output::progress(format!(
+                        "done removing {} from {} in {:.1}s\n",
+                        deployment.namespace, deployment.shard, seconds
+                    ));
+                }
+                (removed, None)
             }
             Err(e) => {
-                println!("removal failed: {}", e)
+                output::progress(format!("removal failed: {}", e));
+                (false, Some(e.to_string()))
             }
+        };
+        deployments.push(RemovedDeployment {
+            unused: UnusedDeploymentInfo::from(deployment),
+            removed,
+            seconds,
+            error,
+        });
+    }
+
+    Ok(UnusedRemoveOutput {
+        filter: deployment.map(str::to_string),
+        deployments,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RemovalInfo {
+    pub deployment: DeploymentRef,
+    pub shard: String,
+    pub namespace: String,
+    pub started_at: String,
+    /// When the last table was dropped
+    pub updated_at: String,
+    pub tables_dropped: i32,
+}
+
+impl From<DeploymentRemoval> for RemovalInfo {
+    fn from(removal: DeploymentRemoval) -> Self {
+        let DeploymentRemoval {
+            id,
+            deployment,
+            shard,
+            namespace,
+            started_at,
+            updated_at,
+            tables_dropped,
+        } = removal;
+        RemovalInfo {
+            deployment: DeploymentRef {
+                deployment,
+                id: DeploymentId::from(id).0,
+            },
+            shard,
+            namespace,
+            started_at: output::timestamp(&started_at),
+            updated_at: output::timestamp(&updated_at),
+            tables_dropped,
         }
     }
-    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RemovalsOutput {
+    pub removals: Vec<RemovalInfo>,
+}
+
+impl CommandOutput for RemovalsOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.removals.is_empty() {
+            return writeln!(out, "no deployments are being removed");
+        }
+
+        let mut list = List::new(vec![
+            "id",
+            "deployment",
+            "shard",
+            "namespace",
+            "started",
+            "updated",
+            "tables dropped",
+        ]);
+        for removal in &self.removals {
+            list.append(vec![
+                removal.deployment.id.to_string(),
+                removal.deployment.deployment.clone(),
+                removal.shard.clone(),
+                removal.namespace.clone(),
+                removal.started_at.clone(),
+                removal.updated_at.clone(),
+                removal.tables_dropped.to_string(),
+            ]);
+        }
+        list.write(out)
+    }
+}
+
+pub fn removals(store: Arc<SubgraphStore>) -> Result<(), Error> {
+    let removals = store
+        .removals()?
+        .into_iter()
+        .map(RemovalInfo::from)
+        .collect();
+
+    output::emit(&RemovalsOutput { removals })
 }
@@ -0,0 +1,103 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use graph::components::store::StatusStore;
+use graph::data::subgraph::status::UsageRecord;
+use graph::prelude::{
+    anyhow::{anyhow, Error},
+    chrono::{DateTime, NaiveDate, Utc},
+    serde_json as json,
+};
+use graph_store_postgres::Store;
+use serde::Serialize;
+
+use crate::manager::output::{self, CommandError, CommandOutput};
+
+/// Parse a timestamp given either in RFC 3339 format or as a date
+/// `YYYY-MM-DD`, which means midnight UTC of that day
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| anyhow!("invalid timestamp `{}`: use RFC 3339 or YYYY-MM-DD", s))?;
+    Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UsageEntry {
+    pub deployment: String,
+    pub bucket: String,
+    pub resource: String,
+    pub amount: u64,
+}
+
+impl From<UsageRecord> for UsageEntry {
+    fn from(record: UsageRecord) -> Self {
+        let UsageRecord {
+            deployment,
+            bucket,
+            resource,
+            amount,
+        } = record;
+        UsageEntry {
+            deployment,
+            bucket: bucket.to_rfc3339(),
+            resource,
+            amount,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UsageOutput {
+    pub records: Vec<UsageEntry>,
+    /// The `--format` for text output, `csv` or `json`
+    #[serde(skip)]
+    pub format: String,
+}
+
+impl CommandOutput for UsageOutput {
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.format == "json" {
+            json::to_writer_pretty(&mut *out, &self.records)?;
+            return writeln!(out);
+        }
+        writeln!(out, "deployment,bucket,resource,amount")?;
+        for record in &self.records {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                record.deployment, record.bucket, record.resource, record.amount
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn report(
+    store: Arc<Store>,
+    deployments: Vec<String>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    format: &str,
+) -> Result<(), Error> {
+    if from >= to {
+        return Err(CommandError::invalid_argument(
+            "`--from` must be before `--to`",
+        ));
+    }
+    if !matches!(format, "csv" | "json") {
+        return Err(CommandError::invalid_argument(format!(
+            "unknown format `{}`",
+            format
+        )));
+    }
+
+    let records = store.usage(deployments, from, to)?;
+
+    output::emit(&UsageOutput {
+        records: records.into_iter().map(UsageEntry::from).collect(),
+        format: format.to_string(),
+    })
+}
@@ -8,13 +8,12 @@ use diesel::{sql_types::Text, PgConnection};
 use graph::components::store::DeploymentId;
 use graph::{
     components::store::DeploymentLocator,
-    data::subgraph::status,
     prelude::{anyhow, lazy_static, regex::Regex, DeploymentHash},
 };
 use graph_store_postgres::command_support::catalog as store_catalog;
 use graph_store_postgres::connection_pool::ConnectionPool;
 
-use crate::manager::display::List;
+use crate::manager::output::CommandError;
 
 lazy_static! {
     // `Qm...` optionally follow by `:$shard`
@@ -155,9 +154,19 @@ impl DeploymentSearch {
         .into_iter()
         .collect();
         let deployment_locator = match locators.len() {
-            0 => anyhow::bail!("Found no deployment for `{}`", self),
+            0 => {
+                return Err(CommandError::not_found(format!(
+                    "Found no deployment for `{}`",
+                    self
+                )))
+            }
             1 => locators.pop().unwrap(),
-            n => anyhow::bail!("Found {} deployments for `{}`", n, self),
+            n => {
+                return Err(CommandError::ambiguous(format!(
+                    "Found {} deployments for `{}`",
+                    n, self
+                )))
+            }
         };
         Ok(deployment_locator)
     }
@@ -184,59 +193,4 @@ impl Deployment {
             Some(self.name.clone()),
         )
     }
-
-    pub fn print_table(deployments: Vec<Self>, statuses: Vec<status::Info>) {
-        let mut rows = vec![
-            "name",
-            "status",
-            "id",
-            "namespace",
-            "shard",
-            "active",
-            "chain",
-            "node_id",
-        ];
-        if !statuses.is_empty() {
-            rows.extend(vec!["synced", "health", "latest block", "chain head block"]);
-        }
-
-        let mut list = List::new(rows);
-
-        for deployment in deployments {
-            let status = statuses
-                .iter()
-                .find(|status| &status.id.0 == &deployment.id);
-
-            let mut rows = vec![
-                deployment.name,
-                deployment.status,
-                deployment.deployment,
-                deployment.namespace,
-                deployment.shard,
-                deployment.active.to_string(),
-                deployment.chain,
-                deployment.node_id.unwrap_or("---".to_string()),
-            ];
-            if let Some(status) = status {
-                let chain = &status.chains[0];
-                rows.extend(vec![
-                    status.synced.to_string(),
-                    status.health.as_str().to_string(),
-                    chain
-                        .latest_block
-                        .as_ref()
-                        .map(|b| b.number().to_string())
-                        .unwrap_or("-".to_string()),
-                    chain
-                        .chain_head_block
-                        .as_ref()
-                        .map(|b| b.number().to_string())
-                        .unwrap_or("-".to_string()),
-                ])
-            }
-            list.append(rows);
-        }
-
-        list.render();
-    }
 }
@@ -1,3 +1,5 @@
+use std::io::{self, Write};
+
 pub struct List {
     pub headers: Vec<String>,
     pub rows: Vec<Vec<String>>,
@@ -29,6 +31,11 @@ impl List {
     }
 
     pub fn render(&self) {
+        self.write(&mut std::io::stdout().lock())
+            .expect("can write to stdout");
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
         const LINE_WIDTH: usize = 78;
 
         let header_width = self.headers.iter().map(|h| h.len()).max().unwrap_or(0);
@@ -36,19 +43,21 @@ impl List {
         let mut first = true;
         for row in &self.rows {
             if !first {
-                println!(
+                writeln!(
+                    out,
                     "{:-<width$}-+-{:-<rest$}",
                     "",
                     "",
                     width = header_width,
                     rest = LINE_WIDTH - 3 - header_width
-                );
+                )?;
             }
             first = false;
 
             for (header, value) in self.headers.iter().zip(row) {
-                println!("{:width$} | {}", header, value, width = header_width);
+                writeln!(out, "{:width$} | {}", header, value, width = header_width)?;
             }
         }
+        Ok(())
     }
 }
@@ -10,6 +10,7 @@ pub mod color;
 pub mod commands;
 pub mod deployment;
 mod display;
+pub mod output;
 pub mod prompt;
 
 /// A dummy subscription manager that always panics
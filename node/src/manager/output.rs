@@ -0,0 +1,1535 @@
+//! Render what graphman commands did either as text for people or as JSON
+//! for scripts. Each command builds a result struct that implements
+//! `CommandOutput` and hands it to `emit`; `finish` takes care of errors.
+//!
+//! With `--output json`, every command prints exactly one JSON object to
+//! stdout. It looks like `{"status": "ok", "result": ..}` if the command
+//! succeeded and like `{"status": "error", "error": {"code": .., "message":
+//! .., "causes": [..]}}` if it failed. Commands that work on several items
+//! report partial failures with both a `result` and an `error`. Commands
+//! like `listen` that run until they are stopped are the exception: they
+//! print each item they produce with `stream_item`, one JSON object per
+//! line, and only print an error object if they fail.
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use graph::components::store::DeploymentLocator;
+use graph::prelude::{
+    anyhow,
+    chrono::{DateTime, SecondsFormat, Utc},
+    lazy_static, serde_json,
+};
+use serde::Serialize;
+
+use super::CmdResult;
+
+lazy_static! {
+    static ref OUTPUT_FORMAT: Mutex<OutputFormat> = Mutex::new(OutputFormat::Text);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn set(format: OutputFormat) {
+        *OUTPUT_FORMAT.lock().unwrap() = format;
+    }
+
+    pub fn current() -> OutputFormat {
+        *OUTPUT_FORMAT.lock().unwrap()
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unknown output format `{}`, use `text` or `json`",
+                s
+            )),
+        }
+    }
+}
+
+/// The codes that JSON output uses for errors. They are part of the
+/// documented output and must not change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Nothing matched what the command was asked to work on
+    NotFound,
+    /// More than one thing matched where the command needs exactly one
+    Ambiguous,
+    /// An argument of the command is not valid
+    InvalidArgument,
+    /// The command failed for some of the items it worked on
+    PartialFailure,
+    /// The command panicked
+    Panic,
+    /// Any other error
+    Failed,
+}
+
+/// An error whose code scripts can rely on; errors that are not a
+/// `CommandError` are reported with code `failed`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<String>,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            causes: vec![],
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> anyhow::Error {
+        Self::new(ErrorCode::NotFound, message).into()
+    }
+
+    pub fn ambiguous(message: impl Into<String>) -> anyhow::Error {
+        Self::new(ErrorCode::Ambiguous, message).into()
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> anyhow::Error {
+        Self::new(ErrorCode::InvalidArgument, message).into()
+    }
+
+    fn from_anyhow(e: &anyhow::Error) -> Self {
+        let mut error = match e.downcast_ref::<CommandError>() {
+            Some(error) => error.clone(),
+            None => Self::new(ErrorCode::Failed, e.to_string()),
+        };
+        error.causes = e.chain().skip(1).map(|cause| cause.to_string()).collect();
+        error
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Marks an error that `emit` already printed as JSON
+#[derive(Debug)]
+struct Reported;
+
+impl fmt::Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the error has already been reported")
+    }
+}
+
+impl std::error::Error for Reported {}
+
+/// The result of a command. Its serialization is the `result` of the JSON
+/// output and therefore needs to stay stable
+pub trait CommandOutput: Serialize {
+    /// Write the result for people to read
+    fn render_text(&self, out: &mut dyn Write) -> io::Result<()>;
+
+    /// The error to report if the command only partially succeeded
+    fn failure(&self) -> Option<CommandError> {
+        None
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<CommandError>,
+}
+
+/// The JSON object for a command that produced `output`
+pub fn json_output<T: CommandOutput>(output: &T) -> serde_json::Value {
+    let error = output.failure();
+    let envelope = Envelope {
+        status: if error.is_some() {
+            Status::Error
+        } else {
+            Status::Ok
+        },
+        result: Some(output),
+        error,
+    };
+    serde_json::to_value(&envelope).expect("command output can be serialized")
+}
+
+/// The JSON object for a command that failed with `e`
+pub fn json_error(e: &anyhow::Error) -> serde_json::Value {
+    let envelope = Envelope::<()> {
+        status: Status::Error,
+        result: None,
+        error: Some(CommandError::from_anyhow(e)),
+    };
+    serde_json::to_value(&envelope).expect("errors can be serialized")
+}
+
+fn print_json(value: &serde_json::Value) -> CmdResult {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Print `output` in the format the user asked for. If the command only
+/// partially succeeded, return an error after printing
+pub fn emit<T: CommandOutput>(output: &T) -> CmdResult {
+    match OutputFormat::current() {
+        OutputFormat::Text => {
+            output.render_text(&mut io::stdout().lock())?;
+            match output.failure() {
+                Some(error) => Err(error.into()),
+                None => Ok(()),
+            }
+        }
+        OutputFormat::Json => {
+            print_json(&json_output(output))?;
+            match output.failure() {
+                Some(_) => Err(Reported.into()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Report the outcome of a command. With JSON output, errors are printed
+/// as a JSON object and the process exits with status 1; with text output,
+/// `result` is returned unchanged
+pub fn finish(result: CmdResult) -> CmdResult {
+    match (OutputFormat::current(), result) {
+        (OutputFormat::Json, Err(e)) => {
+            if e.downcast_ref::<Reported>().is_none() {
+                print_json(&json_error(&e))?;
+            }
+            std::process::exit(1);
+        }
+        (_, result) => result,
+    }
+}
+
+/// Tell people what a long-running command is doing; nothing is printed
+/// with JSON output since that only consists of the final result
+pub fn progress(msg: impl fmt::Display) {
+    if OutputFormat::current() == OutputFormat::Text {
+        println!("{}", msg);
+    }
+}
+
+/// Print one of the items that a command that runs until it is stopped,
+/// like `listen`, produces. With JSON output, each item is a JSON object on
+/// a line of its own instead of being part of a result
+pub fn stream_item<T: Serialize>(item: &T) -> CmdResult {
+    let mut out = io::stdout().lock();
+    match OutputFormat::current() {
+        OutputFormat::Text => serde_json::to_writer_pretty(&mut out, item)?,
+        OutputFormat::Json => serde_json::to_writer(&mut out, item)?,
+    }
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Make panics print a JSON error object instead of text
+pub fn report_panics_as_json() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(msg) => msg.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(msg) => msg.clone(),
+                None => "the command panicked".to_string(),
+            },
+        };
+        let message = match info.location() {
+            Some(location) => format!("{} at {}", message, location),
+            None => message,
+        };
+        let error = anyhow::Error::from(CommandError::new(ErrorCode::Panic, message));
+        print_json(&json_error(&error)).ok();
+    }));
+}
+
+/// How results refer to a deployment: its hash and the id of the copy in
+/// the database, printed as `hash[id]` in text output
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeploymentRef {
+    pub deployment: String,
+    pub id: i32,
+}
+
+impl From<&DeploymentLocator> for DeploymentRef {
+    fn from(locator: &DeploymentLocator) -> Self {
+        DeploymentRef {
+            deployment: locator.hash.to_string(),
+            id: locator.id.0,
+        }
+    }
+}
+
+impl fmt::Display for DeploymentRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.deployment, self.id)
+    }
+}
+
+/// Format a timestamp the same way for text and JSON output
+pub fn timestamp(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use graph::prelude::serde_json::json;
+
+    use super::*;
+    use crate::manager::commands::{
+        assign::{AssignmentChange, AssignmentOutput, ForceStartOutput},
+        bus_critical::{CriticalTypesSet, DeadLettersAcknowledged},
+        chain::{ChainDeployment, ChainRemoveOutput},
+        check_blocks::{CheckBlocksOutput, CheckOutcome, CheckedBlock},
+        compat::{CompatAckOutput, CompatOutput, Fingerprint, Mismatch},
+        compile_profile::{CompileProfileOutput, LastCompilation},
+        config::PoolsOutput,
+        copy::{ActiveCopy, CopyList, CopyProgress, CopyStatus, RowProgress, TableCopyStatus},
+        database::{Remap, RemapOutput},
+        drop::{DropCandidate, DropOutput},
+        handler_stats::{HandlerStatsInfo, HandlerStatsOutput},
+        index::{IndexInfo, IndexListOutput},
+        info::{DeploymentInfo, IndexingStatus, InfoOutput},
+        label::{LabelChangeOutput, LabelsOutput},
+        prune::PruneOutput,
+        remove::RemoveOutput,
+        reorg_threshold::ReorgThresholdOutput,
+        retention::{PrunedEntityType, RetentionPolicy, RetentionPruneOutput},
+        rewind::{RewindOutput, RewoundDeployment},
+        stats::{StatsOutput, TableStats},
+        txn_speed::{TxnSpeed, TxnSpeedOutput},
+        unused_deployments::{
+            RemovalInfo, RemovalsOutput, RemovedDeployment, UnusedDeploymentInfo, UnusedListOutput,
+            UnusedRemoveOutput,
+        },
+        usage::{UsageEntry, UsageOutput},
+    };
+
+    fn text<T: CommandOutput>(output: &T) -> String {
+        let mut buf = Vec::new();
+        output.render_text(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn lines(lines: &[&str]) -> String {
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    fn deployment(deployment: &str, id: i32) -> DeploymentRef {
+        DeploymentRef {
+            deployment: deployment.to_string(),
+            id,
+        }
+    }
+
+    #[test]
+    fn info() {
+        let output = InfoOutput {
+            deployments: vec![DeploymentInfo {
+                name: "subgraph".to_string(),
+                status: "current".to_string(),
+                deployment: "QmDeployment".to_string(),
+                namespace: "sgd1".to_string(),
+                shard: "primary".to_string(),
+                active: true,
+                chain: "mainnet".to_string(),
+                node_id: Some("index_node_0".to_string()),
+                indexing: Some(IndexingStatus {
+                    synced: false,
+                    health: "healthy".to_string(),
+                    latest_block: Some(10),
+                    chain_head_block: None,
+                }),
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "name             | subgraph",
+                "status           | current",
+                "id               | QmDeployment",
+                "namespace        | sgd1",
+                "shard            | primary",
+                "active           | true",
+                "chain            | mainnet",
+                "node_id          | index_node_0",
+                "synced           | false",
+                "health           | healthy",
+                "latest block     | 10",
+                "chain head block | -",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployments": [{
+                        "name": "subgraph",
+                        "status": "current",
+                        "deployment": "QmDeployment",
+                        "namespace": "sgd1",
+                        "shard": "primary",
+                        "active": true,
+                        "chain": "mainnet",
+                        "node_id": "index_node_0",
+                        "indexing": {
+                            "synced": false,
+                            "health": "healthy",
+                            "latest_block": 10,
+                            "chain_head_block": null
+                        }
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = InfoOutput {
+            deployments: vec![],
+        };
+        assert_eq!("No matches\n", text(&output));
+        assert_eq!(
+            json!({ "status": "ok", "result": { "deployments": [] } }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn reassign() {
+        let output = AssignmentOutput {
+            deployment: deployment("QmDeployment", 1),
+            change: AssignmentChange::Reassigned,
+            node: Some("index_node_1".to_string()),
+            previous_node: Some("index_node_0".to_string()),
+        };
+
+        assert_eq!(
+            "reassigned QmDeployment[1] to index_node_1 (was index_node_0)\n",
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "change": "reassigned",
+                    "node": "index_node_1",
+                    "previous_node": "index_node_0"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn rewind() {
+        let output = RewindOutput {
+            block_hash: "0xabcd".to_string(),
+            block_number: 100,
+            deployments: vec![
+                RewoundDeployment {
+                    deployment: deployment("QmA", 1),
+                    paused: true,
+                    rewound: true,
+                    error: None,
+                },
+                RewoundDeployment {
+                    deployment: deployment("QmB", 2),
+                    paused: true,
+                    rewound: false,
+                    error: Some("failed to rewind: boom".to_string()),
+                },
+            ],
+        };
+
+        assert_eq!(
+            lines(&[
+                "Rewinding to block 100 (0xabcd)",
+                "  ... rewound QmA[1]",
+                "  ... failed QmB[2]: failed to rewind: boom",
+            ]),
+            text(&output)
+        );
+        // A partial failure reports the result for each deployment and an
+        // error
+        assert_eq!(
+            json!({
+                "status": "error",
+                "result": {
+                    "block_hash": "0xabcd",
+                    "block_number": 100,
+                    "deployments": [
+                        {
+                            "deployment": { "deployment": "QmA", "id": 1 },
+                            "paused": true,
+                            "rewound": true,
+                            "error": null
+                        },
+                        {
+                            "deployment": { "deployment": "QmB", "id": 2 },
+                            "paused": true,
+                            "rewound": false,
+                            "error": "failed to rewind: boom"
+                        }
+                    ]
+                },
+                "error": {
+                    "code": "partial_failure",
+                    "message": "rewinding failed for 1 of 2 deployments"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let output = RemoveOutput {
+            subgraph: "my/subgraph".to_string(),
+        };
+
+        assert_eq!("Removed subgraph my/subgraph\n", text(&output));
+        assert_eq!(
+            json!({ "status": "ok", "result": { "subgraph": "my/subgraph" } }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn copy() {
+        let progress = RowProgress {
+            next: 50,
+            target: 100,
+        };
+        let output = CopyStatus {
+            deployment: "QmSrc".to_string(),
+            src: Some(1),
+            dst: 2,
+            target_block: Some(100),
+            duration_ms: Some(2000),
+            state: CopyProgress::Started,
+            progress: Some(progress),
+            cancel_requested_at: None,
+            cancelled_at: None,
+            tables: vec![TableCopyStatus {
+                entity_type: "Token".to_string(),
+                next_vid: 50,
+                target_vid: 100,
+                batch_size: 10000,
+                duration_ms: 1500,
+                finished: false,
+                check: None,
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "deployment   | QmSrc",
+                "src          | 1",
+                "dst          | 2",
+                "target block | 100",
+                "duration     | 2000ms",
+                "status       | 50.00% done, 50/100",
+                "",
+                "         entity type           |   next   |  target  |  batch   | duration |  check  ",
+                &"-".repeat(85),
+                "> Token                        |       50 |      100 |    10000 |   1500ms |        .",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": "QmSrc",
+                    "src": 1,
+                    "dst": 2,
+                    "target_block": 100,
+                    "duration_ms": 2000,
+                    "state": "started",
+                    "progress": { "next": 50, "target": 100 },
+                    "cancel_requested_at": null,
+                    "cancelled_at": null,
+                    "tables": [{
+                        "entity_type": "Token",
+                        "next_vid": 50,
+                        "target_vid": 100,
+                        "batch_size": 10000,
+                        "duration_ms": 1500,
+                        "finished": false,
+                        "check": null
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = CopyList {
+            copies: vec![ActiveCopy {
+                deployment: "QmSrc".to_string(),
+                src: 1,
+                dst: 2,
+                shard: "shard_a".to_string(),
+                state: CopyProgress::Started,
+                since: "2023-07-01T00:00:00+00:00".to_string(),
+                progress: Some(progress),
+            }],
+        };
+        assert_eq!(
+            lines(&[
+                &"-".repeat(78),
+                "deployment           | QmSrc",
+                "action               | sgd1 -> sgd2 (shard_a)",
+                "started              | 2023-07-01T00:00:00+00:00",
+                "progress             | 50.00% done, 50/100",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "copies": [{
+                        "deployment": "QmSrc",
+                        "src": 1,
+                        "dst": 2,
+                        "shard": "shard_a",
+                        "state": "started",
+                        "since": "2023-07-01T00:00:00+00:00",
+                        "progress": { "next": 50, "target": 100 }
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let output = StatsOutput {
+            tables: vec![TableStats {
+                table: "token".to_string(),
+                entities: 5,
+                versions: 10,
+                ratio: 0.5,
+                account_like: true,
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "            table              |  entities  |  versions  |  ratio ",
+                "-------------------------------+------------+------------+--------",
+                "token                      (a) |          5 |         10 |  50.0%",
+                "  (a): account-like flag set",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "tables": [{
+                        "table": "token",
+                        "entities": 5,
+                        "versions": 10,
+                        "ratio": 0.5,
+                        "account_like": true
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    fn unused(deployment_id: i32) -> UnusedDeploymentInfo {
+        UnusedDeploymentInfo {
+            deployment: deployment(&format!("Qm{}", deployment_id), deployment_id),
+            shard: "primary".to_string(),
+            namespace: format!("sgd{}", deployment_id),
+            subgraphs: vec!["a/subgraph".to_string()],
+            entity_count: 7,
+        }
+    }
+
+    #[test]
+    fn unused_list() {
+        let output = UnusedListOutput {
+            deployments: vec![unused(1)],
+        };
+
+        assert_eq!(
+            lines(&[
+                "id        | 1",
+                "shard     | primary",
+                "namespace | sgd1",
+                "subgraphs | a/subgraph",
+                "entities  | 7",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployments": [{
+                        "deployment": { "deployment": "Qm1", "id": 1 },
+                        "shard": "primary",
+                        "namespace": "sgd1",
+                        "subgraphs": ["a/subgraph"],
+                        "entity_count": 7
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+        assert_eq!(
+            "no unused deployments\n",
+            text(&UnusedListOutput {
+                deployments: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn unused_remove() {
+        let output = UnusedRemoveOutput {
+            filter: None,
+            deployments: vec![
+                RemovedDeployment {
+                    unused: unused(1),
+                    removed: true,
+                    seconds: 1.5,
+                    error: None,
+                },
+                RemovedDeployment {
+                    unused: unused(2),
+                    removed: false,
+                    seconds: 0.5,
+                    error: Some("boom".to_string()),
+                },
+            ],
+        };
+
+        assert_eq!(lines(&["Removed 1 of 2 unused deployments"]), text(&output));
+        assert_eq!(
+            json!({
+                "status": "error",
+                "result": {
+                    "filter": null,
+                    "deployments": [
+                        {
+                            "deployment": { "deployment": "Qm1", "id": 1 },
+                            "shard": "primary",
+                            "namespace": "sgd1",
+                            "subgraphs": ["a/subgraph"],
+                            "entity_count": 7,
+                            "removed": true,
+                            "seconds": 1.5,
+                            "error": null
+                        },
+                        {
+                            "deployment": { "deployment": "Qm2", "id": 2 },
+                            "shard": "primary",
+                            "namespace": "sgd2",
+                            "subgraphs": ["a/subgraph"],
+                            "entity_count": 7,
+                            "removed": false,
+                            "seconds": 0.5,
+                            "error": "boom"
+                        }
+                    ]
+                },
+                "error": {
+                    "code": "partial_failure",
+                    "message": "removal failed for 1 of 2 deployments"
+                }
+            }),
+            json_output(&output)
+        );
+        assert_eq!(
+            "No unused subgraph matches `QmNope`\n",
+            text(&UnusedRemoveOutput {
+                filter: Some("QmNope".to_string()),
+                deployments: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn removals() {
+        let output = RemovalsOutput {
+            removals: vec![RemovalInfo {
+                deployment: deployment("QmA", 1),
+                shard: "primary".to_string(),
+                namespace: "sgd1".to_string(),
+                started_at: "2023-07-10T12:00:00+00:00".to_string(),
+                updated_at: "2023-07-10T12:05:00+00:00".to_string(),
+                tables_dropped: 3,
+            }],
+        };
+
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "removals": [{
+                        "deployment": { "deployment": "QmA", "id": 1 },
+                        "shard": "primary",
+                        "namespace": "sgd1",
+                        "started_at": "2023-07-10T12:00:00+00:00",
+                        "updated_at": "2023-07-10T12:05:00+00:00",
+                        "tables_dropped": 3
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+        assert_eq!(
+            "no deployments are being removed\n",
+            text(&RemovalsOutput { removals: vec![] })
+        );
+    }
+
+    #[test]
+    fn force_start() {
+        let output = ForceStartOutput {
+            deployment: deployment("QmDeployment", 1),
+            node: "index_node_0".to_string(),
+        };
+
+        assert_eq!(
+            "asked index_node_0 to start QmDeployment[1]\n",
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "node": "index_node_0"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn compat() {
+        let output = CompatOutput {
+            deployment: deployment("QmDeployment", 1),
+            recorded: Fingerprint {
+                hash: "abc".to_string(),
+                settings: vec!["version: 1".to_string()],
+            },
+            mismatch: Some(Mismatch {
+                hash: "def".to_string(),
+                changes: vec!["version: 1 -> 2".to_string()],
+            }),
+        };
+
+        assert_eq!(
+            lines(&[
+                "recorded fingerprint: abc",
+                "  version: 1",
+                "last started with:    def",
+                "  version: 1 -> 2",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "recorded": { "hash": "abc", "settings": ["version: 1"] },
+                    "mismatch": { "hash": "def", "changes": ["version: 1 -> 2"] }
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = CompatAckOutput {
+            deployment: deployment("QmDeployment", 1),
+            acknowledged: true,
+            restarted_on: Some("index_node_0".to_string()),
+        };
+        assert_eq!(
+            lines(&[
+                "acknowledged the changed fingerprint of QmDeployment[1]",
+                "restarting QmDeployment[1] on index_node_0",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "acknowledged": true,
+                    "restarted_on": "index_node_0"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn compile_profile() {
+        let output = CompileProfileOutput {
+            deployment: deployment("QmDeployment", 1),
+            profile: None,
+            node_default: "fast-compile".to_string(),
+            last_compilation: Some(LastCompilation {
+                profile: "fast-compile".to_string(),
+                compile_time_ms: 250,
+            }),
+        };
+
+        assert_eq!(
+            lines(&[
+                "profile:          node default (fast-compile on this node)",
+                "last compiled as: fast-compile",
+                "compile time:     250ms",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "profile": null,
+                    "node_default": "fast-compile",
+                    "last_compilation": { "profile": "fast-compile", "compile_time_ms": 250 }
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn handler_stats() {
+        let output = HandlerStatsOutput {
+            deployment: deployment("QmDeployment", 1),
+            since: Some("2023-07-01T00:00:00+00:00".to_string()),
+            handlers: vec![HandlerStatsInfo {
+                handler: "handleTransfer".to_string(),
+                invocations: 10,
+                execution_time_ms: 120,
+                gas: 5000,
+                errors: 1,
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "since: 2023-07-01T00:00:00+00:00",
+                "",
+                "handler                                   invocations      time (ms)              gas   errors",
+                "handleTransfer                                     10            120             5000        1",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "since": "2023-07-01T00:00:00+00:00",
+                    "handlers": [{
+                        "handler": "handleTransfer",
+                        "invocations": 10,
+                        "execution_time_ms": 120,
+                        "gas": 5000,
+                        "errors": 1
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = HandlerStatsOutput {
+            deployment: deployment("QmDeployment", 1),
+            since: None,
+            handlers: vec![],
+        };
+        assert_eq!(
+            "no handler statistics were recorded for QmDeployment[1]\n",
+            text(&output)
+        );
+    }
+
+    #[test]
+    fn reorg_threshold() {
+        let output = ReorgThresholdOutput {
+            deployment: deployment("QmDeployment", 1),
+            chain: "mainnet".to_string(),
+            threshold: Some(50),
+            chain_threshold: 250,
+            min_threshold: 20,
+        };
+
+        assert_eq!(
+            lines(&[
+                "reorg threshold: 50 (chain default: 250)",
+                "allowed:         20 to 250 blocks",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "chain": "mainnet",
+                    "threshold": 50,
+                    "chain_threshold": 250,
+                    "min_threshold": 20
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn bus_critical() {
+        let output = CriticalTypesSet {
+            deployment: deployment("QmDeployment", 1),
+            entity_types: vec!["Pool".to_string(), "Token".to_string()],
+        };
+
+        assert_eq!(
+            lines(&[
+                "the critical entity types of QmDeployment[1] are Pool, Token",
+                "the runner uses the new entity types the next time QmDeployment[1] is started",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "entity_types": ["Pool", "Token"]
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = DeadLettersAcknowledged {
+            deployment: deployment("QmDeployment", 1),
+            backend: None,
+            count: 3,
+        };
+        assert_eq!(
+            "acknowledged 3 dead letters of QmDeployment[1]\n",
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "backend": null,
+                    "count": 3
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn label() {
+        let output = LabelsOutput {
+            deployment: deployment("QmDeployment", 1),
+            labels: vec![],
+        };
+
+        assert_eq!("QmDeployment[1] has no labels\n", text(&output));
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "labels": []
+                }
+            }),
+            json_output(&output)
+        );
+
+        let output = LabelChangeOutput {
+            deployment: deployment("QmDeployment", 1),
+            label: "team-a".to_string(),
+            labeled: true,
+            changed: false,
+        };
+        assert_eq!("QmDeployment[1] already has label team-a\n", text(&output));
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "label": "team-a",
+                    "labeled": true,
+                    "changed": false
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn retention_prune() {
+        let output = RetentionPruneOutput {
+            deployment: deployment("QmDeployment", 1),
+            dry_run: true,
+            entity_types: vec![PrunedEntityType {
+                policy: RetentionPolicy {
+                    entity_type: "Swap".to_string(),
+                    rule: "more than 1000 blocks old".to_string(),
+                    timestamp_field: None,
+                    max_age_secs: None,
+                    max_blocks: Some(1000),
+                },
+                rows: 42,
+                newest_block: Some(500),
+                loses_history: false,
+                strict: false,
+                complete: true,
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "Swap: would remove 42 rows (more than 1000 blocks old)",
+                "    newest of them written at block 500",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "dry_run": true,
+                    "entity_types": [{
+                        "policy": {
+                            "entity_type": "Swap",
+                            "rule": "more than 1000 blocks old",
+                            "timestamp_field": null,
+                            "max_age_secs": null,
+                            "max_blocks": 1000
+                        },
+                        "rows": 42,
+                        "newest_block": 500,
+                        "loses_history": false,
+                        "strict": false,
+                        "complete": true
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn chain_remove() {
+        let output = ChainRemoveOutput {
+            chain: "mainnet".to_string(),
+            removed: false,
+            deployments: vec![ChainDeployment {
+                namespace: "sgd1".to_string(),
+                deployment: "QmDeployment".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "there are 1 deployments using chain mainnet:",
+                "sgd1     | QmDeployment ",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "error",
+                "result": {
+                    "chain": "mainnet",
+                    "removed": false,
+                    "deployments": [{ "namespace": "sgd1", "deployment": "QmDeployment" }]
+                },
+                "error": {
+                    "code": "failed",
+                    "message": "remove all deployments using chain mainnet first"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn check_blocks() {
+        let output = CheckBlocksOutput {
+            blocks: vec![
+                CheckedBlock {
+                    number: Some(6),
+                    hashes: vec!["0xaa".to_string()],
+                    outcome: CheckOutcome::Matches,
+                    diff: None,
+                    deleted: false,
+                },
+                CheckedBlock {
+                    number: Some(7),
+                    hashes: vec!["0xbb".to_string(), "0xcc".to_string()],
+                    outcome: CheckOutcome::Duplicates,
+                    diff: None,
+                    deleted: false,
+                },
+            ],
+        };
+
+        assert_eq!(
+            lines(&[
+                "Cached block 0xaa is equal to the same block from provider.",
+                "graphman found 2 different block hashes for block number 7 in the store \
+                 and is unable to tell which one to check:",
+                "   1:  0xbb",
+                "   2:  0xcc",
+                "Operation aborted for block number 7.",
+                "To delete the duplicated blocks and continue this operation, rerun this command \
+                 with the `--delete-duplicates` option.",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "blocks": [{
+                        "number": 6,
+                        "hashes": ["0xaa"],
+                        "outcome": "matches",
+                        "diff": null,
+                        "deleted": false
+                    }, {
+                        "number": 7,
+                        "hashes": ["0xbb", "0xcc"],
+                        "outcome": "duplicates",
+                        "diff": null,
+                        "deleted": false
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn database_remap() {
+        let output = RemapOutput {
+            remaps: vec![
+                Remap {
+                    source: "primary".to_string(),
+                    destination: "primary".to_string(),
+                    seconds: 1,
+                    error: None,
+                },
+                Remap {
+                    source: "primary".to_string(),
+                    destination: "shard_a".to_string(),
+                    seconds: 0,
+                    error: Some("connection refused".to_string()),
+                },
+            ],
+        };
+
+        assert_eq!(
+            lines(&[
+                "Remapped imports from primary in shard primary (done in 1s)",
+                "Remapping imports from primary in shard shard_a FAILED",
+                "  error: connection refused",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "error",
+                "result": {
+                    "remaps": [{
+                        "source": "primary",
+                        "destination": "primary",
+                        "seconds": 1,
+                        "error": null
+                    }, {
+                        "source": "primary",
+                        "destination": "shard_a",
+                        "seconds": 0,
+                        "error": "connection refused"
+                    }]
+                },
+                "error": {
+                    "code": "partial_failure",
+                    "message": "remapping failed for 1 of 2 shards"
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn drop() {
+        let output = DropOutput {
+            deployments: vec![DropCandidate {
+                name: "subgraph".to_string(),
+                deployment: "QmDeployment".to_string(),
+            }],
+            confirmed: false,
+            unassigned: None,
+            removed: vec![],
+            recorded: None,
+            removals: vec![],
+        };
+
+        assert_eq!("Execution aborted by user\n", text(&output));
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployments": [{ "name": "subgraph", "deployment": "QmDeployment" }],
+                    "confirmed": false,
+                    "unassigned": null,
+                    "removed": [],
+                    "recorded": null,
+                    "removals": []
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn index_list() {
+        let output = IndexListOutput {
+            deployment: deployment("QmDeployment", 1),
+            table: "sgd1.token".to_string(),
+            indexes: vec![IndexInfo {
+                name: Some("attr_0_0_token_id".to_string()),
+                unique: false,
+                method: Some("btree".to_string()),
+                columns: vec!["id".to_string()],
+                condition: None,
+                with: None,
+                default: true,
+                sql: "create index attr_0_0_token_id on sgd1.token using btree(id)".to_string(),
+            }],
+            to_sql: false,
+        };
+
+        let header = format!("{:^76}", "Indexes for sgd1.token");
+        let hash = format!("{:12} IPFS hash: QmDeployment", "");
+        let rule = "-".repeat(76);
+        assert_eq!(
+            lines(&[
+                header.as_str(),
+                hash.as_str(),
+                rule.as_str(),
+                "attr_0_0_token_id using btree(id)",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "table": "sgd1.token",
+                    "indexes": [{
+                        "name": "attr_0_0_token_id",
+                        "unique": false,
+                        "method": "btree",
+                        "columns": ["id"],
+                        "condition": null,
+                        "with": null,
+                        "default": true,
+                        "sql": "create index attr_0_0_token_id on sgd1.token using btree(id)"
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn prune() {
+        let output = PruneOutput {
+            deployment: deployment("QmDeployment", 1),
+            latest_block: 2000,
+            final_block: 1750,
+            earliest_block: 1000,
+            seconds: 12,
+        };
+
+        assert_eq!("Finished pruning in 12s\n", text(&output));
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "deployment": { "deployment": "QmDeployment", "id": 1 },
+                    "latest_block": 2000,
+                    "final_block": 1750,
+                    "earliest_block": 1000,
+                    "seconds": 12
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn usage() {
+        let output = UsageOutput {
+            records: vec![UsageEntry {
+                deployment: "QmDeployment".to_string(),
+                bucket: "2026-01-01T00:00:00+00:00".to_string(),
+                resource: "entity_writes".to_string(),
+                amount: 42,
+            }],
+            format: "csv".to_string(),
+        };
+
+        assert_eq!(
+            lines(&[
+                "deployment,bucket,resource,amount",
+                "QmDeployment,2026-01-01T00:00:00+00:00,entity_writes,42",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "records": [{
+                        "deployment": "QmDeployment",
+                        "bucket": "2026-01-01T00:00:00+00:00",
+                        "resource": "entity_writes",
+                        "amount": 42
+                    }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn config_pools() {
+        let output = PoolsOutput {
+            shards: Some(BTreeMap::from([("primary".to_string(), 10)])),
+            nodes: None,
+        };
+
+        assert_eq!("primary: 10\n", text(&output));
+        assert_eq!(
+            json!({ "status": "ok", "result": { "shards": { "primary": 10 } } }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn txn_speed() {
+        let output = TxnSpeedOutput {
+            delay: 60,
+            databases: vec![TxnSpeed {
+                database: "graph".to_string(),
+                all: 120.0,
+                write: 60.0,
+            }],
+        };
+
+        assert_eq!(
+            lines(&[
+                "Number of transactions/minute",
+                "database       all write",
+                "graph          120 60",
+            ]),
+            text(&output)
+        );
+        assert_eq!(
+            json!({
+                "status": "ok",
+                "result": {
+                    "delay": 60,
+                    "databases": [{ "database": "graph", "all": 120.0, "write": 60.0 }]
+                }
+            }),
+            json_output(&output)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let e = CommandError::not_found("Found no deployment for `QmNope`");
+        assert_eq!(
+            json!({
+                "status": "error",
+                "error": {
+                    "code": "not_found",
+                    "message": "Found no deployment for `QmNope`"
+                }
+            }),
+            json_error(&e)
+        );
+
+        let e = anyhow::anyhow!("connection refused").context("Configuration error");
+        assert_eq!(
+            json!({
+                "status": "error",
+                "error": {
+                    "code": "failed",
+                    "message": "Configuration error",
+                    "causes": ["connection refused"]
+                }
+            }),
+            json_error(&e)
+        );
+    }
+}
@@ -1,10 +1,21 @@
 use graph::anyhow;
 use std::io::{self, Write};
 
-/// Asks users if they are certain about a certain action.
+use crate::manager::output::OutputFormat;
+
+/// Asks users if they are certain about a certain action. With JSON
+/// output, the prompt goes to stderr so that stdout only has the result
 pub fn prompt_for_confirmation(prompt: &str) -> anyhow::Result<bool> {
-    print!("{prompt} [y/N] ");
-    io::stdout().flush()?;
+    match OutputFormat::current() {
+        OutputFormat::Text => {
+            print!("{prompt} [y/N] ");
+            io::stdout().flush()?;
+        }
+        OutputFormat::Json => {
+            eprint!("{prompt} [y/N] ");
+            io::stderr().flush()?;
+        }
+    }
 
     let mut answer = String::new();
     io::stdin().read_line(&mut answer)?;
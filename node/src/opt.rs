@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use git_testament::{git_testament, render_testament};
 use lazy_static::lazy_static;
 
@@ -14,7 +14,8 @@ lazy_static! {
     name = "graph-node",
     about = "Scalable queries for a decentralized future",
     author = "Graph Protocol, Inc.",
-    version = RENDERED_TESTAMENT.as_str()
+    version = RENDERED_TESTAMENT.as_str(),
+    subcommand_negates_reqs = true
 )]
 pub struct Opt {
     #[clap(
@@ -145,6 +146,22 @@ pub struct Opt {
         help = "Port for the Prometheus metrics server"
     )]
     pub metrics_port: u16,
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "METRICS_PUSH_GATEWAY",
+        help = "Prometheus push gateway to push metrics to, in addition to serving them on the metrics port"
+    )]
+    pub metrics_push_gateway: Option<String>,
+    #[clap(
+        long,
+        default_value = "15",
+        value_name = "SECONDS",
+        env = "METRICS_PUSH_INTERVAL",
+        parse(try_from_str = parse_push_interval),
+        help = "How often to push metrics to the push gateway, at least 1"
+    )]
+    pub metrics_push_interval: u64,
     #[clap(
         long,
         default_value = "default",
@@ -252,6 +269,33 @@ pub struct Opt {
         help = "Bus service to send event from graph-node to"
     )]
     pub bus_url: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Run the mappings of a deployment against the triggers in a file that
+    /// `graphman triggers export` wrote, and print the entity changes and
+    /// the proof of indexing of each block. Entities are kept in memory and
+    /// nothing is fetched over the network
+    TestRun {
+        #[clap(long, value_name = "FILE", help = "the trigger file")]
+        triggers: String,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "the compiled WASM module of the mappings"
+        )]
+        wasm: String,
+        #[clap(
+            long,
+            value_name = "NAME",
+            help = "only handle the triggers of the data sources with this name"
+        )]
+        data_source: Option<String>,
+    },
 }
 
 impl From<Opt> for config::Opt {
@@ -288,3 +332,39 @@ impl From<Opt> for config::Opt {
         }
     }
 }
+
+/// Parse the number of seconds between pushes to the push gateway, which
+/// must be at least 1
+fn parse_push_interval(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("the push interval must be at least 1 second".to_string()),
+        Ok(secs) => Ok(secs),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::Opt;
+
+    fn parse(args: &[&str]) -> Result<Opt, clap::Error> {
+        let base = ["graph-node", "--config", "config.toml"];
+        Opt::try_parse_from(base.iter().chain(args))
+    }
+
+    #[test]
+    fn metrics_push_interval() {
+        assert_eq!(15, parse(&[]).unwrap().metrics_push_interval);
+        assert_eq!(
+            1,
+            parse(&["--metrics-push-interval", "1"])
+                .unwrap()
+                .metrics_push_interval
+        );
+        assert!(parse(&["--metrics-push-interval", "0"]).is_err());
+        assert!(parse(&["--metrics-push-interval", "-1"]).is_err());
+        assert!(parse(&["--metrics-push-interval", "soon"]).is_err());
+    }
+}
@@ -293,6 +293,10 @@ impl StoreBuilder {
         self.chain_head_update_listener.clone()
     }
 
+    pub fn subgraph_store(&self) -> Arc<SubgraphStore> {
+        self.subgraph_store.cheap_clone()
+    }
+
     pub fn primary_pool(&self) -> ConnectionPool {
         self.pools.get(&*PRIMARY_SHARD).unwrap().clone()
     }
@@ -0,0 +1,141 @@
+//! Run the mappings of a deployment against the triggers in a trigger file
+//! that `graphman triggers export` wrote, without a provider or a database.
+//! What each block changed is printed as one line of JSON, together with
+//! the per-type change counts that a block summary on the bus would carry.
+
+use std::fs;
+use std::sync::Arc;
+
+use graph::blockchain::replay::TriggerFile;
+use graph::components::bus::EntityCounts;
+use graph::prelude::{
+    anyhow::{anyhow, bail, Context, Error},
+    serde_json::{self, json},
+    warn, EntityModification, Logger,
+};
+use graph::prometheus::Registry;
+use graph::semver::Version;
+use graph_chain_ethereum::{replay_host_fns, Chain};
+use graph_core::MetricsRegistry;
+use graph_runtime_wasm::replay::{replay, ReplayedBlock};
+use graph_runtime_wasm::{host_export_surface_hash, ExperimentalFeatures};
+
+pub async fn run(
+    logger: Logger,
+    triggers: &str,
+    wasm: &str,
+    data_source: Option<String>,
+) -> Result<(), Error> {
+    let file = TriggerFile::read(triggers)?;
+    if file.chain != "ethereum" {
+        bail!(
+            "only trigger files for Ethereum can be run, but `{}` is for `{}`",
+            triggers,
+            file.chain
+        );
+    }
+    let raw_module =
+        fs::read(wasm).with_context(|| format!("failed to read WASM module {}", wasm))?;
+    let host_fns = replay_host_fns();
+
+    // Replaying only reproduces the proofs of indexing of the deployment if
+    // the blocks were indexed with the same host exports
+    let api_versions = file
+        .data_sources
+        .iter()
+        .map(|ds| Version::parse(&ds.api_version))
+        .collect::<Result<Vec<_>, _>>()?;
+    let host_export_hash = host_export_surface_hash(
+        &api_versions,
+        &host_fns,
+        &ExperimentalFeatures {
+            allow_non_deterministic_ipfs: false,
+        },
+    );
+    for warning in file.version_warnings(&host_export_hash) {
+        warn!(logger, "Proofs of indexing might not match: {}", warning);
+    }
+
+    let registry = Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ));
+
+    // Mappings run synchronously, keep them off the runtime's threads
+    let replayed = graph::spawn_blocking_allow_panic(move || {
+        replay::<Chain>(
+            &logger,
+            &file,
+            &raw_module,
+            host_fns,
+            registry,
+            data_source.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| anyhow!("running the mappings panicked: {}", e))??;
+
+    for block in &replayed {
+        println!("{}", serde_json::to_string(&block_json(block))?);
+        if !block.errors.is_empty() {
+            bail!("handlers failed in block {}", block.ptr.number);
+        }
+    }
+    Ok(())
+}
+
+fn block_json(block: &ReplayedBlock) -> serde_json::Value {
+    let modifications: Vec<_> = block
+        .modifications
+        .iter()
+        .map(|modification| {
+            let key = modification.entity_ref();
+            let operation = match modification {
+                EntityModification::Insert { .. } => "insert",
+                EntityModification::Overwrite { .. } => "overwrite",
+                EntityModification::Remove { .. } => "remove",
+            };
+            json!({
+                "operation": operation,
+                "entityType": key.entity_type.as_str(),
+                "id": key.entity_id.as_str(),
+                "data": modification.entity(),
+            })
+        })
+        .collect();
+    // The same counts, in the same format, as in the `entities` of a block
+    // summary on the bus; blocks without changes have an empty summary
+    let summary: Vec<_> = EntityCounts::from_modifications(&block.modifications)
+        .iter()
+        .map(|(entity_type, counts)| {
+            json!({
+                "entityType": entity_type.as_str(),
+                "inserts": counts.inserts,
+                "updates": counts.updates,
+                "removes": counts.removes,
+            })
+        })
+        .collect();
+    let proof_of_indexing: serde_json::Map<_, _> = block
+        .proof_of_indexing
+        .iter()
+        .map(|(region, digest)| (region.clone(), json!(digest.to_string())))
+        .collect();
+    let errors: Vec<_> = block
+        .errors
+        .iter()
+        .map(|error| {
+            json!({
+                "handler": error.handler,
+                "message": error.message,
+            })
+        })
+        .collect();
+    json!({
+        "block": { "number": block.ptr.number, "hash": block.ptr.hash.to_string() },
+        "modifications": modifications,
+        "summary": summary,
+        "proofOfIndexing": proof_of_indexing,
+        "errors": errors,
+    })
+}
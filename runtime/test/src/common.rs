@@ -10,6 +10,7 @@ use graph::prelude::*;
 use graph_chain_ethereum::{
     Chain, DataSource, DataSourceTemplate, Mapping, MappingABI, TemplateSource,
 };
+use graph_runtime_wasm::mapping::RecentLogs;
 use graph_runtime_wasm::{HostExports, MappingContext};
 use semver::Version;
 use std::env;
@@ -49,6 +50,7 @@ fn mock_host_exports(
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                block_finalizer: None,
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -70,6 +72,8 @@ fn mock_host_exports(
         )),
         ens_lookup,
         Some(bus_sender),
+        false,
+        Arc::default(),
     )
 }
 
@@ -121,6 +125,7 @@ pub fn mock_context(
         proof_of_indexing: None,
         host_fns: Arc::new(Vec::new()),
         debug_fork: None,
+        recent_logs: RecentLogs::default(),
     }
 }
 
@@ -143,6 +148,7 @@ pub fn mock_data_source(path: &str, api_version: Version) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            block_finalizer: None,
             link: Link {
                 link: "link".to_owned(),
             },
@@ -150,6 +156,7 @@ pub fn mock_data_source(path: &str, api_version: Version) -> DataSource {
         },
         context: Default::default(),
         creation_block: None,
+        creation_seq: None,
         contract_abi: Arc::new(mock_abi()),
     }
 }
@@ -1,14 +1,18 @@
 use crate::common::{mock_context, mock_data_source};
 use graph::data::store::scalar;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
+use graph::data::subgraph::status;
 use graph::data::subgraph::*;
 use graph::prelude::web3::types::U256;
 use graph::prelude::*;
-use graph::runtime::{AscIndexId, AscType};
-use graph::runtime::{AscPtr, ToAscObj};
+use graph::runtime::{AscIndexId, AscType, CompileProfile};
+use graph::runtime::{AscPtr, HostExportError, ToAscObj};
 use graph::{components::store::*, ipfs_client::IpfsClient};
 use graph_chain_ethereum::{Chain, DataSource};
 use graph_mock::MockMetricsRegistry;
-use graph_runtime_wasm::asc_abi::class::{Array, AscBigInt, AscEntity, AscString, Uint8Array};
+use graph_runtime_wasm::asc_abi::class::{
+    Array, AscBigInt, AscEntity, AscEnum, AscString, StoreValueKind, Uint8Array,
+};
 use graph_runtime_wasm::{ExperimentalFeatures, ValidModule, WasmInstance};
 use hex;
 use semver::Version;
@@ -46,7 +50,14 @@ async fn test_valid_module_and_store(
     Arc<impl SubgraphStore>,
     DeploymentLocator,
 ) {
-    test_valid_module_and_store_with_timeout(subgraph_id, data_source, api_version, None).await
+    test_valid_module_and_store_with_timeout(
+        subgraph_id,
+        data_source,
+        api_version,
+        None,
+        CompileProfile::default(),
+    )
+    .await
 }
 
 async fn test_valid_module_and_store_with_timeout(
@@ -54,6 +65,7 @@ async fn test_valid_module_and_store_with_timeout(
     data_source: DataSource,
     api_version: Version,
     timeout: Option<Duration>,
+    compile_profile: CompileProfile,
 ) -> (
     WasmInstance<Chain>,
     Arc<impl SubgraphStore>,
@@ -98,7 +110,14 @@ async fn test_valid_module_and_store_with_timeout(
     };
 
     let module = WasmInstance::from_valid_module_with_ctx(
-        Arc::new(ValidModule::new(&logger, data_source.mapping.runtime.as_ref()).unwrap()),
+        Arc::new(
+            ValidModule::new(
+                &logger,
+                data_source.mapping.runtime.as_ref(),
+                compile_profile,
+            )
+            .unwrap(),
+        ),
         mock_context(
             deployment.clone(),
             data_source,
@@ -108,6 +127,7 @@ async fn test_valid_module_and_store_with_timeout(
         ),
         host_metrics,
         timeout,
+        None,
         experimental_features,
     )
     .unwrap();
@@ -582,6 +602,31 @@ async fn ipfs_map_v0_0_5() {
     test_ipfs_map(API_VERSION_0_0_5, "'id' should not be null").await;
 }
 
+// The block states of the callbacks are merged as they are produced, and
+// the entities the callbacks change are bounded by
+// `GRAPH_IPFS_MAP_MAX_CHANGES`; see `ipfs_map_stage` in `host_exports` for
+// the bound itself. This checks that a file with many more values than
+// `GRAPH_IPFS_MAP_CHUNK_SIZE` still produces the changes of its last values
+#[tokio::test(flavor = "multi_thread")]
+async fn ipfs_map_large_file() {
+    const VALUES: usize = 5_000;
+    const IDS: usize = 10;
+
+    let ipfs = IpfsClient::localhost();
+    let lines: Vec<_> = (0..VALUES)
+        .map(|i| make_thing(&format!("id{}", i % IDS), &format!("value{}", i)).0)
+        .collect();
+    let ops = run_ipfs_map(ipfs, "ipfsMapLarge", lines.join("\n"), API_VERSION_0_0_5)
+        .await
+        .expect("call failed");
+
+    // Each entity ends up with the value of the last line that wrote it
+    let expected: Vec<_> = (VALUES - IDS..VALUES)
+        .map(|i| make_thing(&format!("id{}", i % IDS), &format!("value{}", i)).1)
+        .collect();
+    assert_eq!(expected, ops);
+}
+
 async fn test_ipfs_fail(api_version: Version) {
     let runtime = tokio::runtime::Handle::current();
 
@@ -692,14 +737,20 @@ async fn big_int_to_hex_v0_0_5() {
     test_big_int_to_hex(API_VERSION_0_0_5, 2858580).await;
 }
 
-async fn test_big_int_arithmetic(api_version: Version, gas_used: u64) {
-    let mut module = test_module(
-        "BigIntArithmetic",
+async fn test_big_int_arithmetic(api_version: Version, gas_used: u64, profile: CompileProfile) {
+    let subgraph_id = match profile {
+        CompileProfile::FastCompile => "BigIntArithmetic",
+        CompileProfile::Optimized => "BigIntArithmeticOptimized",
+    };
+    let (mut module, _, _) = test_valid_module_and_store_with_timeout(
+        subgraph_id,
         mock_data_source(
             &wasm_file_path("big_int_arithmetic.wasm", api_version.clone()),
             api_version.clone(),
         ),
         api_version,
+        None,
+        profile,
     )
     .await;
 
@@ -750,12 +801,19 @@ async fn test_big_int_arithmetic(api_version: Version, gas_used: u64) {
 
 #[tokio::test]
 async fn big_int_arithmetic_v0_0_4() {
-    test_big_int_arithmetic(API_VERSION_0_0_4, 54962411).await;
+    test_big_int_arithmetic(API_VERSION_0_0_4, 54962411, CompileProfile::FastCompile).await;
 }
 
 #[tokio::test]
 async fn big_int_arithmetic_v0_0_5() {
-    test_big_int_arithmetic(API_VERSION_0_0_5, 7318364).await;
+    test_big_int_arithmetic(API_VERSION_0_0_5, 7318364, CompileProfile::FastCompile).await;
+}
+
+#[tokio::test]
+async fn big_int_arithmetic_optimized() {
+    // The compile profile must not change results or gas
+    test_big_int_arithmetic(API_VERSION_0_0_4, 54962411, CompileProfile::Optimized).await;
+    test_big_int_arithmetic(API_VERSION_0_0_5, 7318364, CompileProfile::Optimized).await;
 }
 
 async fn test_abort(api_version: Version, error_msg: &str) {
@@ -790,6 +848,124 @@ async fn abort_v0_0_5() {
     .await;
 }
 
+#[tokio::test]
+async fn recent_log_lines() {
+    let mut module = test_module(
+        "recentLogLines",
+        mock_data_source(
+            &wasm_file_path("abort.wasm", API_VERSION_0_0_5),
+            API_VERSION_0_0_5,
+        ),
+        API_VERSION_0_0_5,
+    )
+    .await;
+    let gas = module.gas.cheap_clone();
+
+    let capacity = ENV_VARS.mappings.recent_log_lines;
+    for i in 0..capacity + 1 {
+        let msg: AscPtr<AscString> = module.asc_new(&format!("line {}", i)).unwrap();
+        // Level 2 is `warning`
+        module.instance_ctx_mut().log_log(&gas, 2, msg).unwrap();
+    }
+
+    // Only the last lines are kept
+    let lines: Vec<_> = module
+        .instance_ctx_mut()
+        .ctx
+        .recent_logs
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let expected: Vec<_> = (1..capacity + 1)
+        .map(|i| format!("[WARN] line {}", i))
+        .collect();
+    assert_eq!(expected, lines);
+
+    let error = module
+        .instance_ctx_mut()
+        .ctx
+        .recent_logs
+        .attach(anyhow!("unreachable"));
+    assert!(format!("{:#}", error).contains(&format!("[WARN] line {}", capacity)));
+
+    // Nothing leaks into the context of the next trigger
+    let next = module
+        .instance_ctx_mut()
+        .ctx
+        .derive_with_empty_block_state();
+    assert_eq!(0, next.recent_logs.lines().count());
+}
+
+#[tokio::test]
+async fn value_json_host_exports() {
+    let mut module = test_module(
+        "valueJsonHostExports",
+        mock_data_source(
+            &wasm_file_path("abi_classes.wasm", API_VERSION_0_0_5),
+            API_VERSION_0_0_5,
+        ),
+        API_VERSION_0_0_5,
+    )
+    .await;
+    let gas = module.gas.cheap_clone();
+
+    let value = Value::List(vec![
+        Value::Null,
+        Value::BigInt(scalar::BigInt::from(-17)),
+        Value::BigDecimal(scalar::BigDecimal::from_str("1.50").unwrap()),
+        Value::Bytes(scalar::Bytes::from(&[0xde, 0xad][..])),
+    ]);
+    let json = r#"{"data":[null,{"data":"-17","type":"BigInt"},{"data":"1.5","type":"BigDecimal"},{"data":"0xdead","type":"Bytes"}],"type":"List"}"#;
+
+    // value.toJSON
+    let value_ptr: AscPtr<AscEnum<StoreValueKind>> = module.asc_new(&value).unwrap();
+    let json_ptr = module
+        .instance_ctx_mut()
+        .value_to_json(&gas, value_ptr)
+        .unwrap();
+    let encoded: String = module.asc_get(json_ptr).unwrap();
+    assert_eq!(json, encoded);
+
+    // value.fromJSON
+    let json_ptr: AscPtr<AscString> = module.asc_new(json).unwrap();
+    let value_ptr = module
+        .instance_ctx_mut()
+        .value_from_json(&gas, json_ptr)
+        .unwrap();
+    let decoded: Value = module.asc_get(value_ptr).unwrap();
+    assert_eq!(value, decoded);
+
+    // Gas for value.toJSON depends on the length of the JSON, not on the
+    // size of the value
+    let mut gas_for_json = |value: Value| {
+        let value_ptr: AscPtr<AscEnum<StoreValueKind>> = module.asc_new(&value).unwrap();
+        let before = module.gas_used();
+        module
+            .instance_ctx_mut()
+            .value_to_json(&gas, value_ptr)
+            .unwrap();
+        module.gas_used() - before
+    };
+    let small = gas_for_json(Value::BigDecimal(
+        scalar::BigDecimal::from_str("1e1").unwrap(),
+    ));
+    let large = gas_for_json(Value::BigDecimal(
+        scalar::BigDecimal::from_str("1e6000").unwrap(),
+    ));
+    assert!(large > 10 * small);
+
+    // Exponents outside of the range of `BigDecimal` are a deterministic
+    // error
+    let json_ptr: AscPtr<AscString> = module
+        .asc_new(r#"{"data":"1e6145","type":"BigDecimal"}"#)
+        .unwrap();
+    let err = module
+        .instance_ctx_mut()
+        .value_from_json(&gas, json_ptr)
+        .unwrap_err();
+    assert!(err.to_string().contains("exponent"));
+}
+
 async fn test_bytes_to_base58(api_version: Version, gas_used: u64) {
     let mut module = test_module(
         "bytesToBase58",
@@ -1018,6 +1194,196 @@ async fn entity_store_v0_0_5() {
     test_entity_store(API_VERSION_0_0_5).await;
 }
 
+/// Store a `User` whose name is an `Int` and return the error
+async fn store_set_invalid_user(
+    api_version: Version,
+) -> (HostExportError, Arc<impl SubgraphStore>, DeploymentLocator) {
+    let (mut module, store, deployment) = test_valid_module_and_store(
+        "storeSetInvalidUser",
+        mock_data_source(
+            &wasm_file_path("store.wasm", API_VERSION_0_0_5),
+            api_version.clone(),
+        ),
+        api_version,
+    )
+    .await;
+
+    let gas = module.gas.cheap_clone();
+    let entity: AscPtr<AscString> = module.asc_new("User").unwrap();
+    let id: AscPtr<AscString> = module.asc_new("u1").unwrap();
+    let data: AscPtr<AscEntity> = module
+        .asc_new(&vec![
+            ("id".to_string(), Value::from("u1")),
+            ("name".to_string(), Value::Int(17)),
+        ])
+        .unwrap();
+    let err = module
+        .instance_ctx_mut()
+        .store_set(&gas, entity, id, data)
+        .unwrap_err();
+    (err, store, deployment)
+}
+
+const INVALID_USER_ERROR: &str =
+    "Entity User[u1]: the value `17` for field `name` must have type String but has type Int";
+
+#[tokio::test]
+async fn store_set_validation_v0_0_5() {
+    // Mappings from before `apiVersion` 0.0.8 keep treating validation
+    // errors as non-deterministic
+    let (err, _, _) = store_set_invalid_user(API_VERSION_0_0_5).await;
+    assert!(matches!(err, HostExportError::Unknown(_)), "{:?}", err);
+    assert_eq!(INVALID_USER_ERROR, err.to_string());
+}
+
+#[tokio::test]
+async fn store_set_validation_v0_0_8() {
+    let (err, store, deployment) = store_set_invalid_user(API_VERSION_0_0_8).await;
+    assert!(
+        matches!(err, HostExportError::Deterministic(_)),
+        "{:?}",
+        err
+    );
+
+    // The structured error shows up verbatim in the failure record and
+    // the indexing status
+    let error = SubgraphError {
+        subgraph_id: deployment.hash.clone(),
+        message: err.to_string(),
+        block_ptr: Some(test_store::GENESIS_PTR.clone()),
+        handler: Some("handleUser".to_string()),
+        deterministic: true,
+        code: SubgraphErrorCode::HandlerError,
+        provider: None,
+    };
+    store
+        .writable(LOGGER.clone(), deployment.id)
+        .await
+        .unwrap()
+        .fail_subgraph(error)
+        .await
+        .unwrap();
+    let infos = STORE
+        .status(status::Filter::Deployments(vec![deployment
+            .hash
+            .to_string()]))
+        .unwrap();
+    let fatal_error = infos[0].fatal_error.as_ref().unwrap();
+    assert_eq!(INVALID_USER_ERROR, fatal_error.message);
+    assert!(fatal_error.deterministic);
+}
+
+async fn test_load_related_page(api_version: Version) {
+    let (mut module, _, deployment) = test_valid_module_and_store(
+        "loadRelatedPage",
+        mock_data_source(
+            &wasm_file_path("store.wasm", api_version.clone()),
+            api_version.clone(),
+        ),
+        api_version,
+    )
+    .await;
+
+    let user = |id: &str, name: &str| {
+        let mut user = Entity::new();
+        user.set("id", id);
+        user.set("name", name);
+        user
+    };
+    let user_type = EntityType::from("User");
+    test_store::insert_entities(
+        &deployment,
+        ["alex1", "alex2", "alex3", "alex4", "alex5"]
+            .iter()
+            .map(|id| (user_type.clone(), user(id, "Alex")))
+            .chain(Some((user_type.clone(), user("steve", "Steve"))))
+            .collect(),
+    )
+    .await
+    .unwrap();
+
+    let load_page = |module: &mut WasmInstance<Chain>,
+                     token: Option<&str>|
+     -> Result<(Vec<(String, String)>, Option<String>), HostExportError> {
+        let gas = module.gas.cheap_clone();
+        let entity: AscPtr<AscString> = module.asc_new("User").unwrap();
+        let field: AscPtr<AscString> = module.asc_new("name").unwrap();
+        let value = module.asc_new(&Value::from("Alex")).unwrap();
+        let token: AscPtr<AscString> = match token {
+            Some(token) => module.asc_new(token).unwrap(),
+            None => AscPtr::null(),
+        };
+        let page = module
+            .instance_ctx_mut()
+            .store_load_related_page(&gas, entity, field, value, 2, token)?;
+
+        let page = page.read_ptr(&*module.instance_ctx_mut(), &gas).unwrap();
+        let entities: Vec<HashMap<String, Value>> = module.asc_get(page.entities).unwrap();
+        let entities = entities
+            .into_iter()
+            .map(|entity| {
+                let field = |name: &str| entity.get(name).unwrap().clone().as_string().unwrap();
+                (field("id"), field("name"))
+            })
+            .collect();
+        let next_token = match page.next_token.is_null() {
+            true => None,
+            false => Some(module.asc_get(page.next_token).unwrap()),
+        };
+        Ok((entities, next_token))
+    };
+    let alex = |ids: &[&str]| -> Vec<(String, String)> {
+        ids.iter()
+            .map(|id| (id.to_string(), "Alex".to_string()))
+            .collect()
+    };
+
+    let (entities, token) = load_page(&mut module, None).unwrap();
+    assert_eq!(alex(&["alex1", "alex2"]), entities);
+
+    // Change the entities that match between pages
+    {
+        let cache = &mut module.instance_ctx_mut().ctx.state.entity_cache;
+        cache
+            .set(EntityKey::data("User", "alex0"), user("alex0", "Alex"))
+            .unwrap();
+        cache
+            .set(EntityKey::data("User", "alex3"), user("alex3", "Bob"))
+            .unwrap();
+        cache.remove(EntityKey::data("User", "alex4"));
+    }
+
+    // Later pages come from the snapshot taken for the first page
+    let (entities, token) = load_page(&mut module, token.as_deref()).unwrap();
+    assert_eq!(alex(&["alex3", "alex4"]), entities);
+    let (entities, token) = load_page(&mut module, token.as_deref()).unwrap();
+    assert_eq!(alex(&["alex5"]), entities);
+    assert_eq!(None, token);
+
+    // A new query sees the changes
+    let (entities, token) = load_page(&mut module, None).unwrap();
+    assert_eq!(alex(&["alex0", "alex1"]), entities);
+    let (entities, token) = load_page(&mut module, token.as_deref()).unwrap();
+    assert_eq!(alex(&["alex2", "alex5"]), entities);
+    assert_eq!(None, token);
+
+    // Tokens that were not handed out are rejected
+    for token in ["2:0", "0:6", "garbage"] {
+        let err = load_page(&mut module, Some(token)).unwrap_err();
+        assert!(matches!(err, HostExportError::Deterministic(_)));
+    }
+}
+
+#[tokio::test]
+async fn load_related_page_v0_0_4() {
+    test_load_related_page(API_VERSION_0_0_4).await;
+}
+
+#[tokio::test]
+async fn load_related_page_v0_0_5() {
+    test_load_related_page(API_VERSION_0_0_5).await;
+}
+
 fn test_detect_contract_calls(api_version: Version) {
     let data_source_without_calls = mock_data_source(
         &wasm_file_path("abi_store_value.wasm", api_version.clone()),
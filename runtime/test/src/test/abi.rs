@@ -1,8 +1,12 @@
 use graph::prelude::{ethabi::Token, web3::types::U256};
+use graph::runtime::{AscHeap, DeterministicHostError, IndexForAscTypeId};
 use graph_runtime_wasm::{
-    asc_abi::class::{
-        ArrayBuffer, AscAddress, AscEnum, AscEnumArray, EthereumValueKind, StoreValueKind,
-        TypedArray,
+    asc_abi::{
+        class::{
+            ArrayBuffer, AscAddress, AscEnum, AscEnumArray, EthereumValueKind, StoreValueKind,
+            TypedArray,
+        },
+        direct::{self, DIRECT_COPY_THRESHOLD},
     },
     TRAP_TIMEOUT,
 };
@@ -19,6 +23,7 @@ async fn test_unbounded_loop(api_version: Version) {
         ),
         api_version,
         Some(Duration::from_secs(3)),
+        CompileProfile::default(),
     )
     .await
     .0;
@@ -165,6 +170,193 @@ async fn abi_bytes_and_fixed_bytes_v0_0_5() {
     test_abi_bytes_and_fixed_bytes(API_VERSION_0_0_5).await;
 }
 
+async fn test_abi_large_bytes_and_strings(api_version: Version) {
+    let mut module = test_module(
+        "abiLargeBytesAndStrings",
+        mock_data_source(
+            &wasm_file_path("abi_classes.wasm", api_version.clone()),
+            api_version.clone(),
+        ),
+        api_version,
+    )
+    .await;
+    let gas = module.gas.cheap_clone();
+
+    // Sizes around the threshold from which on values are copied directly
+    for len in [
+        DIRECT_COPY_THRESHOLD - 2,
+        DIRECT_COPY_THRESHOLD,
+        DIRECT_COPY_THRESHOLD + 2,
+    ] {
+        let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+        // Both paths read what the other wrote, for the same gas
+        let before = module.gas_used();
+        let direct_ptr = direct::new_bytes(&mut *module.instance_ctx_mut(), &bytes, &gas).unwrap();
+        let direct_gas = module.gas_used() - before;
+        let before = module.gas_used();
+        let ptr: AscPtr<Uint8Array> = module.asc_new(bytes.as_slice()).unwrap();
+        assert_eq!(direct_gas, module.gas_used() - before);
+
+        let read: Vec<u8> = module.asc_get(direct_ptr).unwrap();
+        assert_eq!(bytes, read);
+        let before = module.gas_used();
+        let read = direct::read_bytes(&*module.instance_ctx_mut(), ptr, &gas).unwrap();
+        let direct_gas = module.gas_used() - before;
+        assert_eq!(bytes, read);
+        let before = module.gas_used();
+        let _: Vec<u8> = module.asc_get(ptr).unwrap();
+        assert_eq!(direct_gas, module.gas_used() - before);
+
+        // The mapping can read the array, and we can read the subarray it
+        // returns, which does not start at the start of its buffer
+        let bytes: Vec<u8> = (0..len * 4).map(|i| i as u8).collect();
+        let ptr = direct::new_bytes(&mut *module.instance_ctx_mut(), &bytes, &gas).unwrap();
+        let quarter: AscPtr<Uint8Array> =
+            module.takes_ptr_returns_ptr("byte_array_third_quarter", ptr);
+        let read = direct::read_bytes(&*module.instance_ctx_mut(), quarter, &gas).unwrap();
+        assert_eq!(&bytes[len * 2..len * 3], read.as_slice());
+
+        // Strings with `len` bytes of UTF-16, and with surrogate pairs
+        for string in ["x".repeat(len / 2), "🇧🇷".repeat(len / 8)] {
+            let before = module.gas_used();
+            let direct_ptr =
+                direct::new_string(&mut *module.instance_ctx_mut(), &string, &gas).unwrap();
+            let direct_gas = module.gas_used() - before;
+            let before = module.gas_used();
+            let ptr: AscPtr<AscString> = module.asc_new(string.as_str()).unwrap();
+            assert_eq!(direct_gas, module.gas_used() - before);
+
+            let read: String = module.asc_get(direct_ptr).unwrap();
+            assert_eq!(string, read);
+            let before = module.gas_used();
+            let read = direct::read_string(&*module.instance_ctx_mut(), ptr, &gas).unwrap();
+            let direct_gas = module.gas_used() - before;
+            assert_eq!(string, read);
+            let before = module.gas_used();
+            let _: String = module.asc_get(ptr).unwrap();
+            assert_eq!(direct_gas, module.gas_used() - before);
+
+            let doubled: AscPtr<AscString> =
+                module.takes_ptr_returns_ptr("repeat_twice", direct_ptr);
+            let read = direct::read_string(&*module.instance_ctx_mut(), doubled, &gas).unwrap();
+            assert_eq!(string.repeat(2), read);
+        }
+
+        // Null characters are stripped like on the general path
+        let string = format!("{}\u{0000}", "x".repeat(len / 2));
+        let ptr = direct::new_string(&mut *module.instance_ctx_mut(), &string, &gas).unwrap();
+        let read = direct::read_string(&*module.instance_ctx_mut(), ptr, &gas).unwrap();
+        assert_eq!("x".repeat(len / 2), read);
+
+        // Entity values take the direct path
+        for value in [
+            Value::Bytes(bytes.as_slice().into()),
+            Value::String("x".repeat(len / 2)),
+        ] {
+            let ptr: AscPtr<AscEnum<StoreValueKind>> = module.asc_new(&value).unwrap();
+            let read: Value = module.asc_get(ptr).unwrap();
+            assert_eq!(value, read);
+        }
+    }
+}
+
+#[tokio::test]
+async fn abi_large_bytes_and_strings_v0_0_4() {
+    test_abi_large_bytes_and_strings(API_VERSION_0_0_4).await;
+}
+
+#[tokio::test]
+async fn abi_large_bytes_and_strings_v0_0_5() {
+    test_abi_large_bytes_and_strings(API_VERSION_0_0_5).await;
+}
+
+/// A `Uint8Array` for apiVersion 0.0.5 with arbitrary fields, like a
+/// mapping could write it
+struct ForgedUint8Array {
+    buffer: u32,
+    data_start: u32,
+    byte_length: u32,
+}
+
+impl AscType for ForgedUint8Array {
+    fn to_asc_bytes(&self) -> Result<Vec<u8>, DeterministicHostError> {
+        Ok([self.buffer, self.data_start, self.byte_length]
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect())
+    }
+
+    fn from_asc_bytes(
+        _asc_obj: &[u8],
+        _api_version: &Version,
+    ) -> Result<Self, DeterministicHostError> {
+        unimplemented!()
+    }
+}
+
+impl AscIndexId for ForgedUint8Array {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::Uint8Array;
+}
+
+#[tokio::test]
+async fn abi_oversized_bytes() {
+    let mut module = test_module(
+        "abiOversizedBytes",
+        mock_data_source(
+            &wasm_file_path("abi_classes.wasm", API_VERSION_0_0_5),
+            API_VERSION_0_0_5,
+        ),
+        API_VERSION_0_0_5,
+    )
+    .await;
+    let gas = module.gas.cheap_clone();
+
+    let bytes = vec![1u8; DIRECT_COPY_THRESHOLD];
+    let ptr = direct::new_bytes(&mut *module.instance_ctx_mut(), &bytes, &gas).unwrap();
+    let buffer = module
+        .instance_ctx_mut()
+        .read_u32(ptr.wasm_ptr(), &gas)
+        .unwrap();
+
+    // Arrays that claim more bytes than their buffer holds, or that start
+    // after its end, are rejected instead of being cut off
+    let len = DIRECT_COPY_THRESHOLD as u32;
+    for (data_start, byte_length) in [
+        (buffer, len + 1),
+        (buffer + 1, len),
+        (buffer + len + 1, len),
+        (buffer, u32::MAX),
+    ] {
+        let forged = ForgedUint8Array {
+            buffer,
+            data_start,
+            byte_length,
+        };
+        let forged: AscPtr<ForgedUint8Array> =
+            AscPtr::alloc_obj(forged, &mut *module.instance_ctx_mut(), &gas).unwrap();
+        let forged: AscPtr<Uint8Array> = AscPtr::new(forged.wasm_ptr());
+        let err = direct::read_bytes(&*module.instance_ctx_mut(), forged, &gas).unwrap_err();
+        assert!(
+            err.to_string().contains("does not fit in its buffer"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    // The array that covers the whole buffer is read as usual
+    let forged = ForgedUint8Array {
+        buffer,
+        data_start: buffer,
+        byte_length: len,
+    };
+    let forged: AscPtr<ForgedUint8Array> =
+        AscPtr::alloc_obj(forged, &mut *module.instance_ctx_mut(), &gas).unwrap();
+    let forged: AscPtr<Uint8Array> = AscPtr::new(forged.wasm_ptr());
+    let read = direct::read_bytes(&*module.instance_ctx_mut(), forged, &gas).unwrap();
+    assert_eq!(bytes, read);
+}
+
 async fn test_abi_ethabi_token_identity(api_version: Version) {
     let mut module = test_module(
         "abiEthabiTokenIdentity",
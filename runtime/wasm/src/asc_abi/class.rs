@@ -618,6 +618,23 @@ impl AscIndexId for AscTypedMap<AscString, AscTypedMap<AscString, AscEnum<JsonVa
 }
 
 pub type AscEntity = AscTypedMap<AscString, AscEnum<StoreValueKind>>;
+
+impl AscIndexId for Array<AscPtr<AscEntity>> {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::ArrayTypedMapStringStoreValue;
+}
+
+/// A page of the entities that `store.loadRelatedPage` returns. The
+/// `next_token` is null on the last page
+#[repr(C)]
+#[derive(AscType)]
+pub struct AscRelatedEntityPage {
+    pub entities: AscPtr<Array<AscPtr<AscEntity>>>,
+    pub next_token: AscPtr<AscString>,
+}
+
+impl AscIndexId for AscRelatedEntityPage {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::RelatedEntityPage;
+}
 pub(crate) type AscJson = AscTypedMap<AscString, AscEnum<JsonValueKind>>;
 
 #[repr(u32)]
@@ -0,0 +1,208 @@
+//! Low-copy marshaling of large strings and byte arrays.
+//!
+//! The general `asc_new`/`asc_get` path copies a value several times on its
+//! way between a Rust value and the WASM heap: into an intermediate Asc
+//! object, into its byte layout, into a buffer with the object header, and
+//! finally into WASM memory. For entity fields with large `Bytes` and
+//! `String` values that dominates the cost of marshaling the entity. Values
+//! whose content is at least `DIRECT_COPY_THRESHOLD` bytes long are instead
+//! encoded once and written straight into WASM memory, and read straight out
+//! of the exact region of WASM memory that holds their content.
+//!
+//! The objects that this module writes are byte for byte the same as those
+//! that the general path writes, and both paths consume the same amount of
+//! gas. Mappings with an apiVersion <= 0.0.4 always use the general path.
+
+use std::marker::PhantomData;
+
+use semver::Version;
+
+use graph::runtime::gas::{Gas, GasCounter};
+use graph::runtime::{
+    asc_get, asc_new, AscHeap, AscPtr, DeterministicHostError, FromAscObj, HEADER_SIZE,
+};
+
+use crate::asc_abi::class::{self, AscString, Uint8Array};
+use crate::asc_abi::v0_0_5;
+use crate::gas_rules::GAS_COST_LOAD;
+
+/// The size in bytes of the content of a string or byte array from which on
+/// it is marshaled with this module
+pub const DIRECT_COPY_THRESHOLD: usize = 16 * 1024;
+
+fn uses_direct_copy(api_version: &Version, content_len: usize) -> bool {
+    *api_version > Version::new(0, 0, 4) && content_len >= DIRECT_COPY_THRESHOLD
+}
+
+/// The number of zero bytes that the general path adds after the content
+/// of an `ArrayBuffer` or string with `content_len` bytes
+fn extra_capacity(content_len: usize) -> usize {
+    let total_size = content_len + HEADER_SIZE;
+    total_size.next_power_of_two() - total_size
+}
+
+fn check_fits(content_len: usize, what: &str) -> Result<(), DeterministicHostError> {
+    if content_len > u32::max_value() as usize {
+        return Err(DeterministicHostError::from(anyhow::anyhow!(
+            "{} cannot fit in WASM memory",
+            what
+        )));
+    }
+    Ok(())
+}
+
+/// Allocate a `Uint8Array` holding `bytes`
+pub fn new_bytes<H: AscHeap + ?Sized>(
+    heap: &mut H,
+    bytes: &[u8],
+    gas: &GasCounter,
+) -> Result<AscPtr<Uint8Array>, DeterministicHostError> {
+    if !uses_direct_copy(&heap.api_version(), bytes.len()) {
+        return asc_new(heap, bytes, gas);
+    }
+    check_fits(bytes.len(), "slice")?;
+
+    let padding = vec![0; extra_capacity(bytes.len())];
+    let buffer: AscPtr<class::ArrayBuffer> =
+        AscPtr::alloc_parts(&[bytes, &padding], bytes.len(), heap, gas)?;
+
+    let array = class::TypedArray::ApiVersion0_0_5(v0_0_5::TypedArray {
+        buffer: AscPtr::new(buffer.wasm_ptr()),
+        data_start: buffer.wasm_ptr(),
+        byte_length: bytes.len() as u32,
+        ty: PhantomData,
+    });
+    AscPtr::alloc_obj(array, heap, gas)
+}
+
+/// Allocate an `AscString` holding `string`
+pub fn new_string<H: AscHeap + ?Sized>(
+    heap: &mut H,
+    string: &str,
+    gas: &GasCounter,
+) -> Result<AscPtr<AscString>, DeterministicHostError> {
+    // A string never has more UTF-16 code units than UTF-8 bytes, so this
+    // avoids encoding small strings twice
+    if !uses_direct_copy(&heap.api_version(), string.len() * 2) {
+        return asc_new(heap, string, gas);
+    }
+
+    let mut content = Vec::with_capacity(string.len() * 2);
+    for code_unit in string.encode_utf16() {
+        content.extend_from_slice(&code_unit.to_le_bytes());
+    }
+    if content.len() < DIRECT_COPY_THRESHOLD {
+        return asc_new(heap, string, gas);
+    }
+    check_fits(content.len(), "string")?;
+
+    let padding = vec![0; extra_capacity(content.len())];
+    AscPtr::alloc_parts(&[&content, &padding], content.len(), heap, gas)
+}
+
+/// Read the content of the `Uint8Array` that `ptr` points to
+pub fn read_bytes<H: AscHeap + ?Sized>(
+    heap: &H,
+    ptr: AscPtr<Uint8Array>,
+    gas: &GasCounter,
+) -> Result<Vec<u8>, DeterministicHostError> {
+    let array = match ptr.read_ptr(heap, gas)? {
+        class::TypedArray::ApiVersion0_0_5(array)
+            if array.byte_length as usize >= DIRECT_COPY_THRESHOLD =>
+        {
+            array
+        }
+        array => return Vec::from_asc_obj(array, heap, gas),
+    };
+
+    array.buffer.check_is_not_null()?;
+    let offset = array
+        .data_start
+        .checked_sub(array.buffer.wasm_ptr())
+        .ok_or_else(|| {
+            DeterministicHostError::from(anyhow::anyhow!(
+                "Subtract overflow on pointer: {}",
+                array.data_start
+            ))
+        })?;
+    let buffer_len = array.buffer.read_len(heap, gas)?;
+    if offset > buffer_len || array.byte_length > buffer_len - offset {
+        return Err(DeterministicHostError::from(anyhow::anyhow!(
+            "Uint8Array of {} bytes at offset {} does not fit in its buffer of {} bytes",
+            array.byte_length,
+            offset,
+            buffer_len
+        )));
+    }
+
+    // Only read the part of the buffer that the array covers, but charge
+    // gas for reading all of it, like the general path does
+    gas.consume_host_fn(Gas::new(GAS_COST_LOAD as u64 * buffer_len as u64))?;
+    read_content(
+        heap,
+        array.data_start,
+        array.byte_length,
+        &GasCounter::new(),
+    )
+}
+
+/// Read the string that `ptr` points to
+pub fn read_string<H: AscHeap + ?Sized>(
+    heap: &H,
+    ptr: AscPtr<AscString>,
+    gas: &GasCounter,
+) -> Result<String, DeterministicHostError> {
+    let api_version = heap.api_version();
+    if api_version <= Version::new(0, 0, 4) {
+        return asc_get(heap, ptr, gas);
+    }
+
+    // Look at the length without charging gas so that small strings are
+    // charged exactly what the general path charges
+    let len = ptr.read_len(heap, &GasCounter::new())?;
+    if !uses_direct_copy(&api_version, len as usize) {
+        return asc_get(heap, ptr, gas);
+    }
+
+    let len = ptr.read_len(heap, gas)?;
+    let content = read_content(heap, ptr.wasm_ptr(), len, gas)?;
+    if content.len() % 2 != 0 {
+        return Err(DeterministicHostError::from(anyhow::anyhow!(
+            "Attempted to read past end of string content bytes chunk"
+        )));
+    }
+
+    let code_units = content
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    let mut string = String::with_capacity(content.len() / 2);
+    for c in char::decode_utf16(code_units.clone()) {
+        match c {
+            // Strip null characters since they are not accepted by Postgres.
+            Ok('\u{0000}') => {}
+            Ok(c) => string.push(c),
+            Err(_) => {
+                // Fail with the same error as the general path
+                let code_units: Vec<u16> = code_units.collect();
+                let e = String::from_utf16(&code_units).unwrap_err();
+                return Err(DeterministicHostError::from(anyhow::Error::from(e)));
+            }
+        }
+    }
+    Ok(string)
+}
+
+fn read_content<H: AscHeap + ?Sized>(
+    heap: &H,
+    offset: u32,
+    len: u32,
+    gas: &GasCounter,
+) -> Result<Vec<u8>, DeterministicHostError> {
+    let len = len as usize;
+    let mut content = Vec::with_capacity(len);
+    let read = heap.read(offset, &mut content.spare_capacity_mut()[..len], gas)?;
+    assert_eq!(read.len(), len);
+    // Safety: `read` initialized the first `len` bytes of `content`
+    unsafe { content.set_len(len) };
+    Ok(content)
+}
@@ -1,4 +1,5 @@
 // This unecessary nesting of the module should be resolved by further refactoring.
 pub mod class;
+pub mod direct;
 pub mod v0_0_4;
 pub mod v0_0_5;
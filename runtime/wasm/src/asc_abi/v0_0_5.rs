@@ -104,11 +104,11 @@ pub struct TypedArray<T> {
     // #data -> Backing buffer reference
     pub buffer: AscPtr<ArrayBuffer>,
     // #dataStart -> Start within the #data
-    data_start: u32,
+    pub(crate) data_start: u32,
     // #dataLength -> Length of the data from #dataStart
-    byte_length: u32,
+    pub(crate) byte_length: u32,
     // Not included in memory layout, it's just for typings
-    ty: PhantomData<T>,
+    pub(crate) ty: PhantomData<T>,
 }
 
 impl<T: AscValue> TypedArray<T> {
@@ -1,4 +1,5 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeSet;
 use std::time::Instant;
 
 use async_trait::async_trait;
@@ -8,7 +9,7 @@ use tokio::sync::mpsc::UnboundedSender;
 
 use graph::blockchain::{Blockchain, HostFn, RuntimeAdapter};
 use graph::components::bus::BusMessage;
-use graph::components::store::{EnsLookup, SubgraphFork};
+use graph::components::store::{EnsLookup, EntityType, SubgraphFork};
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::data_source::{
     DataSource, DataSourceTemplate, MappingTrigger, TriggerData, TriggerWithHandler,
@@ -17,16 +18,18 @@ use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
 
-use crate::mapping::{MappingContext, MappingRequest};
+use crate::mapping::{MappingContext, MappingRequest, MappingResponse, RecentLogs, ValidModule};
 use crate::module::ToAscPtr;
 use crate::{host_exports::HostExports, module::ExperimentalFeatures};
-use graph::runtime::gas::Gas;
+use graph::runtime::CompileProfile;
 
 pub struct RuntimeHostBuilder<C: Blockchain> {
     runtime_adapter: Arc<dyn RuntimeAdapter<C>>,
     link_resolver: Arc<dyn LinkResolver>,
     ens_lookup: Arc<dyn EnsLookup>,
     bus_sender: Option<UnboundedSender<BusMessage>>,
+    enforce_declared_entities: bool,
+    retained_entity_types: Arc<BTreeSet<EntityType>>,
 }
 
 impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
@@ -36,6 +39,8 @@ impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
             link_resolver: self.link_resolver.cheap_clone(),
             ens_lookup: self.ens_lookup.cheap_clone(),
             bus_sender: self.bus_sender.clone(),
+            enforce_declared_entities: self.enforce_declared_entities,
+            retained_entity_types: self.retained_entity_types.cheap_clone(),
         }
     }
 }
@@ -46,12 +51,16 @@ impl<C: Blockchain> RuntimeHostBuilder<C> {
         link_resolver: Arc<dyn LinkResolver>,
         ens_lookup: Arc<dyn EnsLookup>,
         bus_sender: Option<UnboundedSender<BusMessage>>,
+        enforce_declared_entities: bool,
+        retained_entity_types: Arc<BTreeSet<EntityType>>,
     ) -> Self {
         RuntimeHostBuilder {
             runtime_adapter,
             link_resolver,
             ens_lookup,
             bus_sender,
+            enforce_declared_entities,
+            retained_entity_types,
         }
     }
 }
@@ -68,12 +77,22 @@ where
         logger: Logger,
         subgraph_id: DeploymentHash,
         metrics: Arc<HostMetrics>,
+        compile_profile: CompileProfile,
     ) -> Result<Sender<Self::Req>, Error> {
         let experimental_features = ExperimentalFeatures {
             allow_non_deterministic_ipfs: ENV_VARS.mappings.allow_non_deterministic_ipfs,
         };
+
+        let start = Instant::now();
+        let valid_module = Arc::new(ValidModule::new(&logger, raw_module, compile_profile)?);
+        let compile_time = start.elapsed();
+        metrics.record_compile(compile_profile, compile_time);
+        debug!(logger, "Compiled WASM module";
+            "profile" => compile_profile.as_str(),
+            "time_ms" => compile_time.as_millis());
+
         crate::mapping::spawn_module(
-            raw_module,
+            valid_module,
             logger,
             subgraph_id,
             metrics,
@@ -103,6 +122,8 @@ where
             metrics,
             self.ens_lookup.cheap_clone(),
             self.bus_sender.clone(),
+            self.enforce_declared_entities,
+            self.retained_entity_types.cheap_clone(),
         )
     }
 }
@@ -130,6 +151,8 @@ where
         metrics: Arc<HostMetrics>,
         ens_lookup: Arc<dyn EnsLookup>,
         bus_sender: Option<UnboundedSender<BusMessage>>,
+        enforce_declared_entities: bool,
+        retained_entity_types: Arc<BTreeSet<EntityType>>,
     ) -> Result<Self, Error> {
         // Create new instance of externally hosted functions invoker. The `Arc` is simply to avoid
         // implementing `Clone` for `HostExports`.
@@ -141,6 +164,8 @@ where
             link_resolver,
             ens_lookup,
             bus_sender,
+            enforce_declared_entities,
+            retained_entity_types,
         ));
 
         let host_fns = data_source
@@ -182,6 +207,7 @@ where
         let (result_sender, result_receiver) = channel();
         let start_time = Instant::now();
         let metrics = self.metrics.clone();
+        let _request = metrics.activity.mapping_request();
 
         self.mapping_request_sender
             .clone()
@@ -194,6 +220,7 @@ where
                     proof_of_indexing,
                     host_fns: self.host_fns.cheap_clone(),
                     debug_fork: debug_fork.cheap_clone(),
+                    recent_logs: RecentLogs::default(),
                 },
                 trigger,
                 result_sender,
@@ -202,19 +229,29 @@ where
             .await
             .context("Mapping terminated before passing in trigger")?;
 
-        let result = result_receiver
+        let MappingResponse {
+            result,
+            gas_used,
+            wasm_time,
+            host_wait_time,
+        } = result_receiver
             .await
             .context("Mapping terminated before handling trigger")?;
 
         let elapsed = start_time.elapsed();
         metrics.observe_handler_execution_time(elapsed.as_secs_f64(), &handler);
+        metrics.wasm_performance.record_handler(wasm_time);
 
-        // If there is an error, "gas_used" is incorrectly reported as 0.
-        let gas_used = result.as_ref().map(|(_, gas)| gas).unwrap_or(&Gas::ZERO);
+        metrics
+            .handler_stats
+            .record(&handler, elapsed, gas_used.value(), result.is_err());
+        metrics.activity.trigger_processed(&handler);
         info!(
             logger, "Done processing trigger";
             &extras,
             "total_ms" => elapsed.as_millis(),
+            "wasm_ms" => wasm_time.as_millis(),
+            "host_wait_ms" => host_wait_time.as_millis(),
             "handler" => handler,
             "data_source" => &self.data_source.name(),
             "gas_used" => gas_used.to_string(),
@@ -1,16 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
+use graph::blockchain::replay::ReplayDataSource;
 use graph::blockchain::Blockchain;
-use graph::components::bus::BusMessage;
+use graph::components::bus::{BusMessage, BusPayload};
 use graph::components::store::EnsLookup;
-use graph::components::store::{EntityKey, EntityType};
+use graph::components::store::{EntityKey, EntityType, RelatedEntityQuery};
 use graph::components::subgraph::{
     PoICausalityRegion, ProofOfIndexingEvent, SharedProofOfIndexing,
 };
-use graph::data::store;
+use graph::data::store::{self, EntityValidation};
+use graph::data::value::Word;
 use graph::data_source::{CausalityRegion, DataSource, DataSourceTemplate, EntityTypeAccess};
 use graph::ensure;
 use graph::prelude::ethabi::param_type::Reader;
@@ -57,14 +59,158 @@ impl IntoTrap for HostExportError {
     }
 }
 
+/// The `store.loadRelatedPage` queries of one handler invocation. When the
+/// mapping asks for the first page of a query, the changes that the handler
+/// and earlier handlers in the block made to entities the query could match
+/// are recorded, and all pages are read from the store with these changes
+/// applied, even if the handler changes matching entities in between. Since
+/// the store does not change while a handler runs, that makes pagination
+/// stable: every entity that matched at the first call is returned exactly
+/// once, in the order of its id, and with its data as of the first call.
+/// Only the entities of the requested page are read from the store.
+#[derive(Default)]
+pub(crate) struct RelatedPages {
+    snapshots: Vec<RelatedSnapshot>,
+    /// The number of pages returned so far
+    pages_read: usize,
+}
+
+struct RelatedSnapshot {
+    query: RelatedEntityQuery,
+    changes: BTreeMap<EntityKey, Option<Entity>>,
+    /// The id of the last entity of each page that was returned; page
+    /// tokens refer to them by their index
+    cursors: Vec<Word>,
+}
+
+impl RelatedPages {
+    /// Parse a token of the form `<snapshot>:<cursor>`
+    fn parse_token(&self, token: &str) -> Option<(usize, usize)> {
+        let (snapshot, cursor) = token.split_once(':')?;
+        let (snapshot, cursor) = (snapshot.parse().ok()?, cursor.parse().ok()?);
+        let cursors = &self.snapshots.get(snapshot)?.cursors;
+        (cursor < cursors.len()).then(|| (snapshot, cursor))
+    }
+}
+
+/// What one call to `ipfs.map` processed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct IpfsMapStats {
+    /// The number of values passed to the callback
+    pub values: usize,
+    /// The number of bytes these values took up in the file
+    pub bytes: usize,
+    /// The number of chunks of values that were flushed into the stage
+    pub chunks: usize,
+    /// The largest number of changed entities that the stage held after
+    /// flushing a chunk
+    pub peak_changes: usize,
+}
+
+/// Collects the block states that the callbacks of one `ipfs.map` call
+/// produce. Every state is added to the staged state right away so that
+/// only the accumulated entity changes are kept in memory. After every
+/// chunk of `chunk_size` values, the entities that the callbacks read from
+/// the store are evicted from the staged state's cache so that memory use
+/// does not grow with the entities the callbacks only read. The changes
+/// themselves can not be written before the block is, and the stage
+/// therefore refuses to hold more than `max_changes` changed entities, no
+/// matter how large the file is. Nothing reaches the block state of the
+/// handler until `finish`; dropping the stage discards everything the
+/// callbacks did
+pub(crate) struct IpfsMapStage<C: Blockchain> {
+    staged: BlockState<C>,
+    chunk_size: usize,
+    max_changes: usize,
+    in_chunk: usize,
+    stats: IpfsMapStats,
+}
+
+impl<C: Blockchain> IpfsMapStage<C> {
+    pub fn new(staged: BlockState<C>, chunk_size: usize, max_changes: usize) -> Self {
+        IpfsMapStage {
+            staged,
+            chunk_size: chunk_size.max(1),
+            max_changes,
+            in_chunk: 0,
+            stats: IpfsMapStats::default(),
+        }
+    }
+
+    /// Add the state that the callback produced for a value that took up
+    /// `bytes` in the file. Fails if that makes the stage hold more than
+    /// `max_changes` changed entities
+    pub fn push(&mut self, state: BlockState<C>, bytes: usize) -> Result<(), anyhow::Error> {
+        self.staged.extend(state);
+        self.stats.values += 1;
+        self.stats.bytes += bytes;
+
+        let changes = self.staged.entity_cache.changes();
+        if changes > self.max_changes {
+            return Err(anyhow!(
+                "the callbacks for the first {} values changed {} entities, \
+                 more than the limit of {} (GRAPH_IPFS_MAP_MAX_CHANGES)",
+                self.stats.values,
+                changes,
+                self.max_changes
+            ));
+        }
+
+        self.in_chunk += 1;
+        if self.in_chunk >= self.chunk_size {
+            self.flush();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if self.in_chunk == 0 {
+            return;
+        }
+        self.in_chunk = 0;
+        self.staged
+            .entity_cache
+            .evict_current(ENV_VARS.mappings.entity_cache_size);
+        self.stats.chunks += 1;
+        self.stats.peak_changes = self
+            .stats
+            .peak_changes
+            .max(self.staged.entity_cache.changes());
+    }
+
+    pub fn stats(&self) -> &IpfsMapStats {
+        &self.stats
+    }
+
+    /// Return the staged state that contains the changes of all
+    /// callbacks, and what the call processed
+    pub fn finish(mut self) -> (BlockState<C>, IpfsMapStats) {
+        self.flush();
+        (self.staged, self.stats)
+    }
+}
+
+/// The entities on one page of the result of `store.loadRelatedPage`
+pub(crate) struct RelatedEntityPage {
+    pub entities: Vec<Vec<(String, store::Value)>>,
+    /// The token for the next page; `None` on the last page
+    pub next_token: Option<String>,
+}
+
 pub struct HostExports<C: Blockchain> {
     pub(crate) subgraph_id: DeploymentHash,
     pub api_version: Version,
-    data_source_name: String,
+    pub(crate) data_source_name: String,
     data_source_address: Vec<u8>,
     subgraph_network: String,
     data_source_context: Arc<Option<DataSourceContext>>,
     entity_type_access: EntityTypeAccess,
+    /// Only set if the manifest declares the `declaredEntities` feature
+    declared_entities: Option<Vec<EntityType>>,
+    /// Immutable entity types with a retention policy, which mappings can
+    /// not load since which of their rows exist depends on when the
+    /// retention job ran
+    retained_entity_types: Arc<BTreeSet<EntityType>>,
     data_source_causality_region: CausalityRegion,
 
     /// Some data sources have indeterminism or different notions of time. These
@@ -87,6 +233,8 @@ impl<C: Blockchain> HostExports<C> {
         link_resolver: Arc<dyn LinkResolver>,
         ens_lookup: Arc<dyn EnsLookup>,
         bus_sender: Option<UnboundedSender<BusMessage>>,
+        enforce_declared_entities: bool,
+        retained_entity_types: Arc<BTreeSet<EntityType>>,
     ) -> Self {
         Self {
             subgraph_id,
@@ -95,6 +243,10 @@ impl<C: Blockchain> HostExports<C> {
             data_source_address: data_source.address().unwrap_or_default(),
             data_source_context: data_source.context().cheap_clone(),
             entity_type_access: data_source.entities(),
+            declared_entities: enforce_declared_entities
+                .then(|| data_source.declared_entities())
+                .flatten(),
+            retained_entity_types,
             data_source_causality_region: data_source.causality_region(),
             poi_causality_region: PoICausalityRegion::from_network(&subgraph_network),
             subgraph_network,
@@ -105,6 +257,45 @@ impl<C: Blockchain> HostExports<C> {
         }
     }
 
+    /// Host exports for running the mappings of a data source from a
+    /// trigger file. Such a data source can not create data sources from
+    /// templates and does not send messages to the bus
+    pub fn for_replay(
+        subgraph_id: DeploymentHash,
+        data_source: &ReplayDataSource,
+        subgraph_network: String,
+        link_resolver: Arc<dyn LinkResolver>,
+        ens_lookup: Arc<dyn EnsLookup>,
+    ) -> Result<Self, Error> {
+        let api_version = Version::parse(&data_source.api_version).with_context(|| {
+            format!(
+                "invalid API version `{}` for data source `{}`",
+                data_source.api_version, data_source.name
+            )
+        })?;
+        Ok(Self {
+            subgraph_id,
+            api_version,
+            data_source_name: data_source.name.clone(),
+            data_source_address: data_source
+                .address
+                .as_ref()
+                .map(|address| address.as_slice().to_vec())
+                .unwrap_or_default(),
+            data_source_context: Arc::new(data_source.context.clone()),
+            entity_type_access: EntityTypeAccess::Any,
+            declared_entities: None,
+            retained_entity_types: Arc::default(),
+            data_source_causality_region: CausalityRegion::ONCHAIN,
+            poi_causality_region: PoICausalityRegion::from_network(&subgraph_network),
+            subgraph_network,
+            templates: Arc::new(vec![]),
+            link_resolver,
+            ens_lookup,
+            bus_sender: None,
+        })
+    }
+
     /// Enfore the entity type access restrictions. See also: entity-type-access
     fn check_entity_type_access(&self, entity_type: &EntityType) -> Result<(), HostExportError> {
         match self.entity_type_access.allows(entity_type) {
@@ -120,6 +311,25 @@ impl<C: Blockchain> HostExports<C> {
         }
     }
 
+    /// Reject loading entity types whose rows the retention job removes
+    fn check_entity_type_load(&self, entity_type: &EntityType) -> Result<(), HostExportError> {
+        check_retained_entity_load(
+            &self.retained_entity_types,
+            &self.data_source_name,
+            entity_type,
+        )
+    }
+
+    /// Reject writes to entity types that are not declared in the manifest
+    /// when the manifest declares the `declaredEntities` feature
+    fn check_declared_entity_write(&self, entity_type: &EntityType) -> Result<(), HostExportError> {
+        check_declared_entity_write(
+            self.declared_entities.as_deref(),
+            &self.data_source_name,
+            entity_type,
+        )
+    }
+
     pub(crate) fn abort(
         &self,
         message: Option<String>,
@@ -182,11 +392,25 @@ impl<C: Blockchain> HostExports<C> {
             causality_region: self.data_source_causality_region,
         };
         self.check_entity_type_access(&key.entity_type)?;
+        self.check_declared_entity_write(&key.entity_type)?;
 
         gas.consume_host_fn(gas::STORE_SET.with_args(complexity::Linear, (&key, &data)))?;
 
         let entity = Entity::from(data);
-        state.entity_cache.set(key.clone(), entity)?;
+        let validation = EntityValidation::for_api_version(&self.api_version);
+        state
+            .entity_cache
+            .set_with(key.clone(), entity, validation)
+            .map_err(|e| match e.downcast::<store::EntityValidationError>() {
+                // Storing the same invalid entity will always fail, but
+                // mappings before `apiVersion` 0.0.8 keep treating
+                // validation errors as non-deterministic, as they always did
+                Ok(e) if validation == EntityValidation::Strict => {
+                    HostExportError::Deterministic(e.into())
+                }
+                Ok(e) => HostExportError::Unknown(e.into()),
+                Err(e) => HostExportError::Unknown(e),
+            })?;
 
         Ok(())
     }
@@ -215,6 +439,7 @@ impl<C: Blockchain> HostExports<C> {
             causality_region: self.data_source_causality_region,
         };
         self.check_entity_type_access(&key.entity_type)?;
+        self.check_declared_entity_write(&key.entity_type)?;
 
         gas.consume_host_fn(gas::STORE_REMOVE.with_args(complexity::Size, &key))?;
 
@@ -236,6 +461,7 @@ impl<C: Blockchain> HostExports<C> {
             causality_region: self.data_source_causality_region,
         };
         self.check_entity_type_access(&store_key.entity_type)?;
+        self.check_entity_type_load(&store_key.entity_type)?;
 
         let result = state.entity_cache.get(&store_key)?;
         gas.consume_host_fn(gas::STORE_GET.with_args(complexity::Linear, (&store_key, &result)))?;
@@ -243,6 +469,112 @@ impl<C: Blockchain> HostExports<C> {
         Ok(result)
     }
 
+    pub(crate) fn related_entity_query(
+        &self,
+        entity_type: String,
+        field: String,
+        value: store::Value,
+    ) -> Result<RelatedEntityQuery, HostExportError> {
+        let query = RelatedEntityQuery {
+            entity_type: EntityType::new(entity_type),
+            field,
+            value,
+            causality_region: self.data_source_causality_region,
+        };
+        self.check_entity_type_access(&query.entity_type)?;
+        self.check_entity_type_load(&query.entity_type)?;
+        Ok(query)
+    }
+
+    /// Return the page of the entities matching `query` that `page_token`
+    /// points to, or the first page if there is no `page_token`. See
+    /// `RelatedPages` for why later pages can contain entities that no
+    /// longer match the query
+    pub(crate) fn store_load_related_page(
+        &self,
+        state: &mut BlockState<C>,
+        pages: &mut RelatedPages,
+        query: RelatedEntityQuery,
+        page_size: u32,
+        page_token: Option<String>,
+        gas: &GasCounter,
+    ) -> Result<RelatedEntityPage, HostExportError> {
+        if page_size == 0 {
+            return Err(HostExportError::Deterministic(anyhow!(
+                "store.loadRelatedPage: the page size must be greater than 0"
+            )));
+        }
+        let max_pages = ENV_VARS.mappings.max_related_pages;
+        if pages.pages_read >= max_pages {
+            return Err(HostExportError::Deterministic(anyhow!(
+                "store.loadRelatedPage: a handler can read at most {} pages",
+                max_pages
+            )));
+        }
+        pages.pages_read += 1;
+
+        let (idx, cursor) = match page_token {
+            None => {
+                let changes = state
+                    .entity_cache
+                    .related_changes(&query)
+                    .map_err(anyhow::Error::from)?;
+                pages.snapshots.push(RelatedSnapshot {
+                    query,
+                    changes,
+                    cursors: Vec::new(),
+                });
+                (pages.snapshots.len() - 1, None)
+            }
+            Some(token) => {
+                let (idx, cursor) = pages.parse_token(&token).ok_or_else(|| {
+                    HostExportError::Deterministic(anyhow!(
+                        "store.loadRelatedPage: invalid page token `{}`",
+                        token
+                    ))
+                })?;
+                (idx, Some(cursor))
+            }
+        };
+
+        // Read one more entity than fits on the page to know whether there
+        // is a next page
+        let snapshot = &mut pages.snapshots[idx];
+        let after = cursor.map(|cursor| snapshot.cursors[cursor].clone());
+        let page_size = page_size as usize;
+        let mut page: Vec<_> = state
+            .entity_cache
+            .load_related_page(
+                &snapshot.query,
+                &snapshot.changes,
+                after.as_ref(),
+                page_size + 1,
+            )
+            .map_err(anyhow::Error::from)?
+            .into_iter()
+            .collect();
+        let has_next = page.len() > page_size;
+        page.truncate(page_size);
+
+        let (keys, entities): (Vec<_>, Vec<_>) = page.into_iter().unzip();
+        gas.consume_host_fn(gas::STORE_GET.with_args(
+            complexity::Linear,
+            (&snapshot.query.entity_type, entities.as_slice()),
+        ))?;
+
+        let next_token = match keys.last() {
+            Some(last) if has_next => {
+                snapshot.cursors.push(last.entity_id.clone());
+                Some(format!("{}:{}", idx, snapshot.cursors.len() - 1))
+            }
+            _ => None,
+        };
+        Ok(RelatedEntityPage {
+            entities: entities.into_iter().map(Entity::sorted).collect(),
+            next_token,
+        })
+    }
+
     pub(crate) fn bus_send(
         &self,
         value: Vec<String>,
@@ -251,8 +583,9 @@ impl<C: Blockchain> HostExports<C> {
         // NOTE: Always OK because we dont want to interrupt/terminate the WasmRuntimeHost
         if let Some(sender) = &self.bus_sender {
             let msg = BusMessage {
-                value,
+                payload: BusPayload::PlainText(value),
                 subgraph_id: self.subgraph_id.as_str().to_owned(),
+                critical: None,
             };
             let _send = sender.clone().send(msg);
         }
@@ -301,12 +634,18 @@ impl<C: Blockchain> HostExports<C> {
     }
 
     // Read the IPFS file `link`, split it into JSON objects, and invoke the
-    // exported function `callback` on each JSON object. The successful return
-    // value contains the block state produced by each callback invocation. Each
-    // invocation of `callback` happens in its own instance of a WASM module,
-    // which is identical to `module` when it was first started. The signature
-    // of the callback must be `callback(JSONValue, Value)`, and the `userData`
-    // parameter is passed to the callback without any changes
+    // exported function `callback` on each JSON object. Each invocation of
+    // `callback` happens in its own instance of a WASM module, which is
+    // identical to `module` when it was first started. The signature of the
+    // callback must be `callback(JSONValue, Value)`, and the `userData`
+    // parameter is passed to the callback without any changes.
+    //
+    // The block states that the invocations produce are collected in an
+    // `IpfsMapStage` as the file is read, so that memory use depends on the
+    // entities the callbacks write, not on the size of the file. Only when
+    // the whole file has been processed are the staged changes added to the
+    // block state of `module`; if reading the file or any invocation fails,
+    // the staged changes are dropped and `module` is left unchanged.
     pub(crate) fn ipfs_map(
         link_resolver: &Arc<dyn LinkResolver>,
         module: &mut WasmInstanceContext<C>,
@@ -314,7 +653,7 @@ impl<C: Blockchain> HostExports<C> {
         callback: &str,
         user_data: store::Value,
         flags: Vec<String>,
-    ) -> Result<Vec<BlockState<C>>, anyhow::Error> {
+    ) -> Result<IpfsMapStats, anyhow::Error> {
         // Does not consume gas because this is not a part of deterministic APIs.
         // Ideally we would consume gas the same as ipfs_cat and then share
         // gas across the spawned modules for callbacks.
@@ -342,30 +681,40 @@ impl<C: Blockchain> HostExports<C> {
         let result = {
             let mut stream: JsonValueStream =
                 graph::block_on(link_resolver.json_stream(&logger, &Link { link }))?;
-            let mut v = Vec::new();
+            let mut stage = IpfsMapStage::new(
+                ctx.derive_with_empty_block_state().state,
+                ENV_VARS.mappings.ipfs_map_chunk_size,
+                ENV_VARS.mappings.ipfs_map_max_changes,
+            );
             while let Some(sv) = graph::block_on(stream.next()) {
                 let sv = sv?;
-                let module = WasmInstance::from_valid_module_with_ctx(
+                let instance = WasmInstance::from_valid_module_with_ctx(
                     valid_module.clone(),
                     ctx.derive_with_empty_block_state(),
                     host_metrics.clone(),
-                    module.timeout,
+                    module.timeout.timeout(),
+                    module.timeout.host_wait_timeout(),
                     module.experimental_features,
                 )?;
-                let result = module.handle_json_callback(&callback, &sv.value, &user_data)?;
+                let state = instance.handle_json_callback(&callback, &sv.value, &user_data)?;
+                stage.push(state, sv.bytes)?;
+                host_metrics.record_ipfs_map_value(sv.bytes);
+                host_metrics.usage.ipfs_bytes(sv.bytes);
                 // Log progress every 15s
                 if last_log.elapsed() > Duration::from_secs(15) {
                     debug!(
                         logger,
-                        "Processed {} lines in {}s so far",
+                        "Processed {} lines ({} bytes) in {}s so far",
                         sv.line,
+                        stage.stats().bytes,
                         start.elapsed().as_secs()
                     );
                     last_log = Instant::now();
                 }
-                v.push(result)
             }
-            Ok(v)
+            let (staged, stats) = stage.finish();
+            module.ctx.state.extend(staged);
+            Ok(stats)
         };
         result.map_err(move |e: Error| anyhow::anyhow!("{}: {}", errmsg, e.to_string()))
     }
@@ -692,6 +1041,8 @@ impl<C: Blockchain> HostExports<C> {
             params,
             context,
             creation_block,
+            // Assigned by `push_created_data_source`
+            creation_seq: 0,
         });
 
         Ok(())
@@ -768,6 +1119,27 @@ impl<C: Blockchain> HostExports<C> {
             .map_err(|e| DeterministicHostError::from(Error::from(e)))
     }
 
+    pub(crate) fn value_to_json(
+        &self,
+        value: &store::Value,
+        gas: &GasCounter,
+    ) -> Result<String, DeterministicHostError> {
+        // A `BigDecimal` with a large exponent is small but expands into a
+        // long string, so charge for the JSON rather than the value
+        let json = store::json::to_json(value);
+        gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, &json))?;
+        Ok(json)
+    }
+
+    pub(crate) fn value_from_json(
+        &self,
+        json: &str,
+        gas: &GasCounter,
+    ) -> Result<store::Value, DeterministicHostError> {
+        gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, json))?;
+        store::json::from_json(json).map_err(DeterministicHostError::from)
+    }
+
     pub(crate) fn string_to_h160(
         &self,
         string: &str,
@@ -876,3 +1248,168 @@ fn bytes_to_string_is_lossy() {
         )
     )
 }
+
+/// Reject writes of `data_source` to entity types that are not in
+/// `declared`; `None` means that writes are not checked
+fn check_declared_entity_write(
+    declared: Option<&[EntityType]>,
+    data_source: &str,
+    entity_type: &EntityType,
+) -> Result<(), HostExportError> {
+    match declared {
+        Some(declared) if !declared.contains(entity_type) => {
+            Err(HostExportError::Deterministic(anyhow!(
+                "data source `{}` writes entity type `{}`, which is not on its 'entities' list. \
+                 Hint: Add `{}` to the 'entities' list in the manifest",
+                data_source,
+                entity_type,
+                entity_type,
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reject loads of `data_source` from entity types in `retained`
+fn check_retained_entity_load(
+    retained: &BTreeSet<EntityType>,
+    data_source: &str,
+    entity_type: &EntityType,
+) -> Result<(), HostExportError> {
+    match retained.contains(entity_type) {
+        true => Err(HostExportError::Deterministic(anyhow!(
+            "data source `{}` loads entity type `{}`, which has a retention policy. \
+             Hint: Remove the retention policy or stop loading `{}` in mappings",
+            data_source,
+            entity_type,
+            entity_type,
+        ))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipfs_map_stage() {
+        use graph::blockchain::mock::MockBlockchain;
+        use graph::components::store::EmptyStore;
+        use graph::util::lfu_cache::LfuCache;
+
+        const SCHEMA: &str = "type Thing @entity { id: ID!, value: String! }";
+        const VALUES: usize = 200_000;
+        const IDS: usize = 10;
+
+        let schema = Schema::parse(SCHEMA, DeploymentHash::new("ipfsMapStage").unwrap()).unwrap();
+        let schema = Arc::new(schema);
+        let state =
+            || BlockState::<MockBlockchain>::new(EmptyStore::new(schema.clone()), LfuCache::new());
+        let thing = |i: usize| {
+            let id = format!("id{}", i % IDS);
+            EntityOperation::Set {
+                key: EntityKey::data("Thing".to_string(), id.clone()),
+                data: Entity::from(vec![
+                    ("id", Value::from(id)),
+                    ("value", Value::from(format!("value{}", i))),
+                ]),
+            }
+        };
+
+        // The stage only holds one change per entity, no matter how many
+        // values write it, so that a limit of `IDS` changes is enough for
+        // any number of values
+        let mut stage = IpfsMapStage::new(state(), 100, IDS);
+        for i in 0..VALUES {
+            let mut value_state = state();
+            value_state.entity_cache.append(vec![thing(i)]);
+            stage.push(value_state, 10).unwrap();
+        }
+        let (staged, stats) = stage.finish();
+        assert_eq!(
+            IpfsMapStats {
+                values: VALUES,
+                bytes: 10 * VALUES,
+                chunks: VALUES / 100,
+                peak_changes: IDS,
+            },
+            stats
+        );
+        let mods = staged
+            .entity_cache
+            .as_modifications()
+            .unwrap()
+            .modifications;
+        assert_eq!(IDS, mods.len());
+
+        // Values that each write a different entity would make the stage
+        // grow with the file; it fails once the limit is exceeded
+        let mut stage = IpfsMapStage::new(state(), 100, 1_000);
+        let failed = (0..VALUES).find(|i| {
+            let mut value_state = state();
+            value_state.entity_cache.append(vec![EntityOperation::Set {
+                key: EntityKey::data("Thing".to_string(), format!("distinct{}", i)),
+                data: Entity::from(vec![
+                    ("id", Value::from(format!("distinct{}", i))),
+                    ("value", Value::from("value")),
+                ]),
+            }]);
+            stage.push(value_state, 10).is_err()
+        });
+        assert_eq!(Some(1_000), failed);
+        assert_eq!(1_001, stage.stats().values);
+
+        // Dropping the stage leaves the handler's state unchanged
+        let mut handler_state = state();
+        let mut stage = IpfsMapStage::new(state(), 100, IDS);
+        let mut value_state = state();
+        value_state.entity_cache.append(vec![thing(0)]);
+        stage.push(value_state, 10).unwrap();
+        drop(stage);
+        assert_eq!(0, handler_state.entity_cache.changes());
+        handler_state.enter_handler();
+        handler_state.exit_handler();
+        assert!(handler_state
+            .entity_cache
+            .as_modifications()
+            .unwrap()
+            .modifications
+            .is_empty());
+    }
+
+    #[test]
+    fn declared_entity_writes() {
+        let declared = vec![EntityType::new("Transfer".to_string())];
+        let transfer = EntityType::new("Transfer".to_string());
+        let account = EntityType::new("Account".to_string());
+
+        // Without enforcement, everything can be written
+        assert!(check_declared_entity_write(None, "Token", &account).is_ok());
+
+        assert!(check_declared_entity_write(Some(&declared), "Token", &transfer).is_ok());
+        match check_declared_entity_write(Some(&declared), "Token", &account) {
+            Err(HostExportError::Deterministic(e)) => assert!(e
+                .to_string()
+                .starts_with("data source `Token` writes entity type `Account`")),
+            Err(e) => panic!("expected a deterministic error, got {:?}", e),
+            Ok(()) => panic!("writing an undeclared entity type must fail"),
+        }
+    }
+
+    #[test]
+    fn retained_entity_loads() {
+        let transfer = EntityType::new("Transfer".to_string());
+        let account = EntityType::new("Account".to_string());
+        let retained: BTreeSet<_> = vec![transfer.clone()].into_iter().collect();
+
+        assert!(check_retained_entity_load(&retained, "Token", &account).is_ok());
+        match check_retained_entity_load(&retained, "Token", &transfer) {
+            Err(HostExportError::Deterministic(e)) => assert!(e
+                .to_string()
+                .starts_with("data source `Token` loads entity type `Transfer`")),
+            Err(e) => panic!("expected a deterministic error, got {:?}", e),
+            Ok(()) => panic!("loading a retained entity type must fail"),
+        }
+    }
+}
@@ -17,10 +17,13 @@ pub mod host_exports;
 pub mod error;
 mod gas_rules;
 
+/// Run mappings against the triggers in a trigger file.
+pub mod replay;
+
 pub use host::RuntimeHostBuilder;
 pub use host_exports::HostExports;
 pub use mapping::{MappingContext, ValidModule};
-pub use module::{ExperimentalFeatures, WasmInstance};
+pub use module::{host_export_surface_hash, trap_error_code, ExperimentalFeatures, WasmInstance};
 
 #[cfg(debug_assertions)]
 pub use module::TRAP_TIMEOUT;
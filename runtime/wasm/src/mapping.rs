@@ -4,17 +4,21 @@ use futures::sync::mpsc;
 use futures03::channel::oneshot::Sender;
 use graph::blockchain::{Blockchain, HostFn};
 use graph::components::store::SubgraphFork;
+use graph::components::subgraph::mapping_terminations::TerminationReason;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::data_source::{MappingTrigger, TriggerWithHandler};
 use graph::prelude::*;
 use graph::runtime::gas::Gas;
-use std::collections::BTreeMap;
+use graph::runtime::CompileProfile;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 /// Spawn a wasm module in its own thread.
 pub fn spawn_module<C: Blockchain>(
-    raw_module: &[u8],
+    valid_module: Arc<ValidModule>,
     logger: Logger,
     subgraph_id: DeploymentHash,
     host_metrics: Arc<HostMetrics>,
@@ -25,7 +29,7 @@ pub fn spawn_module<C: Blockchain>(
 where
     <C as Blockchain>::MappingTrigger: ToAscPtr,
 {
-    let valid_module = Arc::new(ValidModule::new(&logger, raw_module)?);
+    let host_wait_timeout = ENV_VARS.mappings.host_wait_timeout;
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
@@ -35,41 +39,57 @@ where
     //
     // In case of failure, this thread may panic or simply terminate,
     // dropping the `mapping_request_receiver` which ultimately causes the
-    // subgraph to fail the next time it tries to handle an event.
+    // subgraph to fail the next time it tries to handle an event. Either
+    // way, the termination is counted in the host metrics.
     let conf =
         thread::Builder::new().name(format!("mapping-{}-{}", &subgraph_id, uuid::Uuid::new_v4()));
     conf.spawn(move || {
         let _runtime_guard = runtime.enter();
+        let metrics = host_metrics.cheap_clone();
 
         // Pass incoming triggers to the WASM module and return entity changes;
         // Stop when canceled because all RuntimeHosts and their senders were dropped.
-        match mapping_request_receiver
-            .map_err(|()| unreachable!())
-            .for_each(move |request| {
-                let MappingRequest {
-                    ctx,
-                    trigger,
-                    result_sender,
-                } = request;
-
-                let result = instantiate_module_and_handle_trigger(
-                    valid_module.cheap_clone(),
-                    ctx,
-                    trigger,
-                    host_metrics.cheap_clone(),
-                    timeout,
-                    experimental_features,
-                );
-
-                result_sender
-                    .send(result)
-                    .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
-            })
-            .wait()
-        {
-            Ok(()) => debug!(logger, "Subgraph stopped, WASM runtime thread terminated"),
-            Err(e) => debug!(logger, "WASM runtime thread terminated abnormally";
-                                    "error" => e.to_string()),
+        let handle_requests = AssertUnwindSafe(|| {
+            mapping_request_receiver
+                .map_err(|()| unreachable!())
+                .for_each(move |request| {
+                    let MappingRequest {
+                        ctx,
+                        trigger,
+                        result_sender,
+                    } = request;
+
+                    let response = instantiate_module_and_handle_trigger(
+                        valid_module.cheap_clone(),
+                        ctx,
+                        trigger,
+                        host_metrics.cheap_clone(),
+                        timeout,
+                        host_wait_timeout,
+                        experimental_features,
+                    );
+
+                    result_sender
+                        .send(response)
+                        .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
+                })
+                .wait()
+        });
+
+        match panic::catch_unwind(handle_requests) {
+            Ok(Ok(())) => {
+                debug!(logger, "Subgraph stopped, WASM runtime thread terminated");
+                metrics.record_termination(&logger, TerminationReason::Canceled);
+            }
+            Ok(Err(e)) => {
+                debug!(logger, "WASM runtime thread terminated abnormally";
+                    "error" => e.to_string());
+                metrics.record_termination(&logger, TerminationReason::ChannelClosed);
+            }
+            Err(panic) => {
+                metrics.record_termination(&logger, TerminationReason::Panic);
+                panic::resume_unwind(panic);
+            }
         }
     })
     .map(|_| ())
@@ -84,8 +104,9 @@ fn instantiate_module_and_handle_trigger<C: Blockchain>(
     trigger: TriggerWithHandler<MappingTrigger<C>>,
     host_metrics: Arc<HostMetrics>,
     timeout: Option<Duration>,
+    host_wait_timeout: Option<Duration>,
     experimental_features: ExperimentalFeatures,
-) -> Result<(BlockState<C>, Gas), MappingError>
+) -> MappingResponse<C>
 where
     <C as Blockchain>::MappingTrigger: ToAscPtr,
 {
@@ -93,27 +114,62 @@ where
 
     // Start the WASM module runtime.
     let section = host_metrics.stopwatch.start_section("module_init");
-    let module = WasmInstance::from_valid_module_with_ctx(
+    let module = match WasmInstance::from_valid_module_with_ctx(
         valid_module,
         ctx,
         host_metrics.cheap_clone(),
         timeout,
+        host_wait_timeout,
         experimental_features,
     )
-    .context("module instantiation failed")?;
+    .context("module instantiation failed")
+    {
+        Ok(module) => module,
+        Err(e) => {
+            return MappingResponse {
+                result: Err(e.into()),
+                gas_used: Gas::ZERO,
+                wasm_time: Duration::ZERO,
+                host_wait_time: Duration::ZERO,
+            }
+        }
+    };
     section.end();
 
     let _section = host_metrics.stopwatch.start_section("run_handler");
     if ENV_VARS.log_trigger_data {
         debug!(logger, "trigger data: {:?}", trigger);
     }
-    module.handle_trigger(trigger)
+    let start = Instant::now();
+    let timeout = module.instance_ctx().timeout.cheap_clone();
+    let gas = module.gas.clone();
+    let result = module.handle_trigger(trigger);
+    let host_wait_time = timeout.host_wait_time();
+    let wasm_time = start.elapsed().saturating_sub(host_wait_time);
+    host_metrics.usage.wasm_cpu(wasm_time);
+
+    MappingResponse {
+        result,
+        gas_used: gas.get(),
+        wasm_time,
+        host_wait_time,
+    }
 }
 
 pub struct MappingRequest<C: Blockchain> {
     pub(crate) ctx: MappingContext<C>,
     pub(crate) trigger: TriggerWithHandler<MappingTrigger<C>>,
-    pub(crate) result_sender: Sender<Result<(BlockState<C>, Gas), MappingError>>,
+    pub(crate) result_sender: Sender<MappingResponse<C>>,
+}
+
+/// The outcome of handling a trigger, together with the gas the handler
+/// used, even if it failed, and how the time the handler took splits into
+/// executing WASM and waiting on host fns that perform I/O.
+pub struct MappingResponse<C: Blockchain> {
+    pub(crate) result: Result<(BlockState<C>, Gas), MappingError>,
+    pub(crate) gas_used: Gas,
+    pub(crate) wasm_time: Duration,
+    pub(crate) host_wait_time: Duration,
 }
 
 pub struct MappingContext<C: Blockchain> {
@@ -124,6 +180,7 @@ pub struct MappingContext<C: Blockchain> {
     pub proof_of_indexing: SharedProofOfIndexing,
     pub host_fns: Arc<Vec<HostFn>>,
     pub debug_fork: Option<Arc<dyn SubgraphFork>>,
+    pub recent_logs: RecentLogs,
 }
 
 impl<C: Blockchain> MappingContext<C> {
@@ -136,10 +193,62 @@ impl<C: Blockchain> MappingContext<C> {
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
             host_fns: self.host_fns.cheap_clone(),
             debug_fork: self.debug_fork.cheap_clone(),
+            recent_logs: RecentLogs::default(),
         }
     }
 }
 
+/// The last lines that a mapping logged while handling a trigger. When the
+/// handler fails, they are added to the error so that the failure shows
+/// what the handler logged right before it
+pub struct RecentLogs {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RecentLogs {
+    pub fn new(capacity: usize) -> Self {
+        RecentLogs {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, level: slog::Level, msg: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines
+            .push_back(format!("[{}] {}", level.as_str(), msg.replace('\n', " ")));
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// Add the recent log lines to `error`, if there are any
+    pub fn attach(&self, error: Error) -> Error {
+        if self.lines.is_empty() {
+            return error;
+        }
+        let lines: Vec<_> = self.lines().collect();
+        error.context(format!("last mapping logs: {}", lines.join("\t")))
+    }
+}
+
+impl Default for RecentLogs {
+    fn default() -> Self {
+        RecentLogs::new(ENV_VARS.mappings.recent_log_lines)
+    }
+}
+
 /// A pre-processed and valid WASM module, ready to be started as a WasmModule.
 pub struct ValidModule {
     pub module: wasmtime::Module,
@@ -156,8 +265,12 @@ pub struct ValidModule {
 }
 
 impl ValidModule {
-    /// Pre-process and validate the module.
-    pub fn new(logger: &Logger, raw_module: &[u8]) -> Result<Self, anyhow::Error> {
+    /// Pre-process and validate the module, and compile it with `profile`.
+    pub fn new(
+        logger: &Logger,
+        raw_module: &[u8],
+        profile: CompileProfile,
+    ) -> Result<Self, anyhow::Error> {
         // Add the gas calls here. Module name "gas" must match. See also
         // e3f03e62-40e4-4f8c-b4a1-d0375cca0b76. We do this by round-tripping the module through
         // parity - injecting gas then serializing again.
@@ -182,13 +295,22 @@ impl ValidModule {
         let raw_module = parity_module.into_bytes()?;
 
         // We currently use Cranelift as a compilation engine. Cranelift is an optimizing compiler,
-        // but that should not cause determinism issues since it adheres to the Wasm spec. Still we
-        // turn off optional optimizations to be conservative.
+        // but that should not cause determinism issues since it adheres to the Wasm spec. Unless
+        // the deployment asks for optimized code, we turn off optional optimizations since they
+        // make compiling large modules slow. NaN canonicalization is on with every profile, so the
+        // profile can not change the results of a mapping. The profile does change how much native
+        // stack a WASM call needs, so whether a deep recursion hits `max_wasm_stack` depends on it;
+        // stack overflows are therefore never treated as deterministic errors, see
+        // `WasmInstance::invoke_handler`.
+        let opt_level = match profile {
+            CompileProfile::FastCompile => wasmtime::OptLevel::None,
+            CompileProfile::Optimized => wasmtime::OptLevel::Speed,
+        };
         let mut config = wasmtime::Config::new();
         config.strategy(wasmtime::Strategy::Cranelift).unwrap();
         config.interruptable(true); // For timeouts.
         config.cranelift_nan_canonicalization(true); // For NaN determinism.
-        config.cranelift_opt_level(wasmtime::OptLevel::None);
+        config.cranelift_opt_level(opt_level);
         config
             .max_wasm_stack(ENV_VARS.mappings.max_stack_size)
             .unwrap(); // Safe because this only panics if size passed is 0.
@@ -215,3 +337,42 @@ impl ValidModule {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn recent_logs_keep_last_lines() {
+        let mut logs = RecentLogs::new(2);
+        logs.push(slog::Level::Info, "one");
+        logs.push(slog::Level::Warning, "two");
+        logs.push(slog::Level::Error, "three\nfour");
+        assert_eq!(
+            vec!["[WARN] two", "[ERROR] three four"],
+            logs.lines().collect::<Vec<_>>()
+        );
+
+        let error = logs.attach(anyhow!("trap"));
+        assert_eq!(
+            "last mapping logs: [WARN] two\t[ERROR] three four: trap",
+            format!("{:#}", error)
+        );
+        // The original error is still part of the chain
+        assert_eq!("trap", error.root_cause().to_string());
+
+        logs.clear();
+        assert_eq!(0, logs.lines().count());
+        assert_eq!("trap", format!("{:#}", logs.attach(anyhow!("trap"))));
+    }
+
+    #[test]
+    fn recent_logs_disabled() {
+        let mut logs = RecentLogs::new(0);
+        logs.push(slog::Level::Info, "one");
+        assert_eq!(0, logs.lines().count());
+        assert_eq!("trap", format!("{:#}", logs.attach(anyhow!("trap"))));
+    }
+}
@@ -13,9 +13,10 @@ use never::Never;
 use semver::Version;
 use wasmtime::{Memory, Trap};
 
-use graph::blockchain::{Blockchain, HostFnCtx};
+use graph::blockchain::{Blockchain, HostFn, HostFnCtx};
+use graph::components::subgraph::mapping_terminations::TerminationReason;
 use graph::data::store;
-use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode};
 use graph::data_source::{offchain, MappingTrigger, TriggerWithHandler};
 use graph::prelude::*;
 use graph::runtime::{
@@ -28,20 +29,185 @@ use graph::util::mem::init_slice;
 use graph::{components::subgraph::MappingError, runtime::AscPtr};
 pub use into_wasm_ret::IntoWasmRet;
 pub use stopwatch::TimeoutStopwatch;
+pub use timeout::HandlerTimeout;
 
 use crate::asc_abi::class::*;
 use crate::error::DeterminismLevel;
 use crate::gas_rules::{GAS_COST_LOAD, GAS_COST_STORE};
 pub use crate::host_exports;
-use crate::host_exports::HostExports;
+use crate::host_exports::{HostExports, RelatedPages};
 use crate::mapping::MappingContext;
 use crate::mapping::ValidModule;
 
 mod into_wasm_ret;
 pub mod stopwatch;
+mod timeout;
 
 pub const TRAP_TIMEOUT: &str = "trap: interrupt";
 
+/// Invokes `$link!` with the wasm name, the name of the method of
+/// `WasmInstanceContext`, optionally the stopwatch section, and the
+/// parameters of each host export that is not chain-specific. Both
+/// linking and `host_export_names` go through this list, so that the
+/// host export surface hash can not miss a host export.
+macro_rules! for_each_host_export {
+    ($link:ident, $api_version:expr, $experimental_features:expr) => {
+        $link!("ethereum.encode", ethereum_encode, params_ptr);
+        $link!("ethereum.decode", ethereum_decode, params_ptr, data_ptr);
+
+        $link!("abort", abort, message_ptr, file_name_ptr, line, column);
+
+        $link!("store.get", store_get, "host_export_store_get", entity, id);
+        $link!(
+            "store.loadRelatedPage",
+            store_load_related_page,
+            "host_export_store_load_related_page",
+            entity,
+            field,
+            value,
+            page_size,
+            page_token
+        );
+        $link!(
+            "store.set",
+            store_set,
+            "host_export_store_set",
+            entity,
+            id,
+            data
+        );
+
+        // NOTE: where is this section name defined?
+        $link!("bus.send", bus_send, "host_export_bus_send", any_string);
+
+        // All IPFS-related functions exported by the host WASM runtime should be listed in the
+        // graph::data::subgraph::features::IPFS_ON_ETHEREUM_CONTRACTS_FUNCTION_NAMES array for
+        // automatic feature detection to work.
+        //
+        // For reference, search this codebase for: ff652476-e6ad-40e4-85b8-e815d6c6e5e2
+        $link!("ipfs.cat", ipfs_cat, "host_export_ipfs_cat", hash_ptr);
+        $link!(
+            "ipfs.map",
+            ipfs_map,
+            "host_export_ipfs_map",
+            link_ptr,
+            callback,
+            user_data,
+            flags
+        );
+        // The previous ipfs-related functions are unconditionally linked for backward compatibility
+        if $experimental_features.allow_non_deterministic_ipfs {
+            $link!(
+                "ipfs.getBlock",
+                ipfs_get_block,
+                "host_export_ipfs_get_block",
+                hash_ptr
+            );
+        }
+
+        $link!("store.remove", store_remove, entity_ptr, id_ptr);
+
+        $link!("typeConversion.bytesToString", bytes_to_string, ptr);
+        $link!("typeConversion.bytesToHex", bytes_to_hex, ptr);
+        $link!("typeConversion.bigIntToString", big_int_to_string, ptr);
+        $link!("typeConversion.bigIntToHex", big_int_to_hex, ptr);
+        $link!("typeConversion.stringToH160", string_to_h160, ptr);
+        $link!("typeConversion.bytesToBase58", bytes_to_base58, ptr);
+
+        $link!("json.fromBytes", json_from_bytes, ptr);
+        $link!("json.try_fromBytes", json_try_from_bytes, ptr);
+        $link!("json.toI64", json_to_i64, ptr);
+        $link!("json.toU64", json_to_u64, ptr);
+        $link!("json.toF64", json_to_f64, ptr);
+        $link!("json.toBigInt", json_to_big_int, ptr);
+
+        $link!("value.toJSON", value_to_json, ptr);
+        $link!("value.fromJSON", value_from_json, ptr);
+
+        $link!("crypto.keccak256", crypto_keccak_256, ptr);
+
+        $link!("bigInt.plus", big_int_plus, x_ptr, y_ptr);
+        $link!("bigInt.minus", big_int_minus, x_ptr, y_ptr);
+        $link!("bigInt.times", big_int_times, x_ptr, y_ptr);
+        $link!("bigInt.dividedBy", big_int_divided_by, x_ptr, y_ptr);
+        $link!("bigInt.dividedByDecimal", big_int_divided_by_decimal, x, y);
+        $link!("bigInt.mod", big_int_mod, x_ptr, y_ptr);
+        $link!("bigInt.pow", big_int_pow, x_ptr, exp);
+        $link!("bigInt.fromString", big_int_from_string, ptr);
+        $link!("bigInt.bitOr", big_int_bit_or, x_ptr, y_ptr);
+        $link!("bigInt.bitAnd", big_int_bit_and, x_ptr, y_ptr);
+        $link!("bigInt.leftShift", big_int_left_shift, x_ptr, bits);
+        $link!("bigInt.rightShift", big_int_right_shift, x_ptr, bits);
+
+        $link!("bigDecimal.toString", big_decimal_to_string, ptr);
+        $link!("bigDecimal.fromString", big_decimal_from_string, ptr);
+        $link!("bigDecimal.plus", big_decimal_plus, x_ptr, y_ptr);
+        $link!("bigDecimal.minus", big_decimal_minus, x_ptr, y_ptr);
+        $link!("bigDecimal.times", big_decimal_times, x_ptr, y_ptr);
+        $link!("bigDecimal.dividedBy", big_decimal_divided_by, x, y);
+        $link!("bigDecimal.equals", big_decimal_equals, x_ptr, y_ptr);
+
+        $link!("dataSource.create", data_source_create, name, params);
+        $link!(
+            "dataSource.createWithContext",
+            data_source_create_with_context,
+            name,
+            params,
+            context
+        );
+        $link!("dataSource.address", data_source_address,);
+        $link!("dataSource.network", data_source_network,);
+        $link!("dataSource.context", data_source_context,);
+
+        $link!("ens.nameByHash", ens_name_by_hash, ptr);
+
+        $link!("log.log", log_log, level, msg_ptr);
+
+        // `arweave and `box` functionality was removed, but apiVersion <= 0.0.4 must link it.
+        if $api_version <= Version::new(0, 0, 4) {
+            $link!("arweave.transactionData", arweave_transaction_data, ptr);
+            $link!("box.profile", box_profile, ptr);
+        }
+    };
+}
+
+/// The names of the host exports that `from_valid_module_with_ctx` links
+/// for a module with `api_version`, not counting the chain-specific host
+/// fns
+pub fn host_export_names(
+    api_version: &Version,
+    experimental_features: &ExperimentalFeatures,
+) -> Vec<&'static str> {
+    let mut names = vec!["gas"];
+    macro_rules! name {
+        ($wasm_name:expr, $($rest:tt)*) => {
+            names.push($wasm_name);
+        };
+    }
+    for_each_host_export!(name, *api_version, experimental_features);
+    names
+}
+
+/// A hash over the names of the host exports that are linked for modules
+/// with one of `api_versions`, including the chain-specific `host_fns`.
+/// The hash changes whenever a host export is added or removed, and is
+/// recorded for each deployment so that we can tell which host export
+/// surface a range of blocks was indexed with.
+pub fn host_export_surface_hash<'a>(
+    api_versions: impl IntoIterator<Item = &'a Version>,
+    host_fns: &[HostFn],
+    experimental_features: &ExperimentalFeatures,
+) -> String {
+    let mut names: Vec<_> = api_versions
+        .into_iter()
+        .flat_map(|version| host_export_names(version, experimental_features))
+        .chain(host_fns.iter().map(|host_fn| host_fn.name))
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    hex::encode(tiny_keccak::keccak256(names.join("\n").as_bytes()))
+}
+
 pub trait IntoTrap {
     fn determinism_level(&self) -> DeterminismLevel;
     fn into_trap(self) -> Trap;
@@ -147,6 +313,7 @@ impl<C: Blockchain> WasmInstance<C> {
         let value = asc_new(self.instance_ctx_mut().deref_mut(), value, &gas)?;
         let user_data = asc_new(self.instance_ctx_mut().deref_mut(), user_data, &gas)?;
 
+        self.instance_ctx_mut().handler = handler_name.to_owned();
         self.instance_ctx_mut().ctx.state.enter_handler();
 
         // Invoke the callback
@@ -212,22 +379,42 @@ impl<C: Blockchain> WasmInstance<C> {
             .context("wasm function has incorrect signature")?;
 
         // Caution: Make sure all exit paths from this function call `exit_handler`.
+        self.instance_ctx_mut().handler = handler.to_owned();
         self.instance_ctx_mut().ctx.state.enter_handler();
+        // Only lines logged by this handler are attached to its errors
+        self.instance_ctx_mut().ctx.recent_logs.clear();
 
         // This `match` will return early if there was a non-deterministic trap.
-        let deterministic_error: Option<Error> = match func.call(arg.wasm_ptr()) {
+        let result = func
+            .call(arg.wasm_ptr())
+            .map_err(|trap| self.record_trap(trap));
+        let deterministic_error: Option<Error> = match result {
             Ok(()) => None,
             Err(trap) if self.instance_ctx().possible_reorg => {
                 self.instance_ctx_mut().ctx.state.exit_handler();
-                return Err(MappingError::PossibleReorg(trap.into()));
+                return Err(MappingError::PossibleReorg(
+                    self.with_recent_logs(trap.into()),
+                ));
             }
             Err(trap) if trap.to_string().contains(TRAP_TIMEOUT) => {
                 self.instance_ctx_mut().ctx.state.exit_handler();
-                return Err(MappingError::Unknown(Error::from(trap).context(format!(
-                    "Handler '{}' hit the timeout of '{}' seconds",
-                    handler,
-                    self.instance_ctx().timeout.unwrap().as_secs()
-                ))));
+                let timeout = self.instance_ctx().timeout.cheap_clone();
+                let message = if timeout.host_wait_is_exhausted() {
+                    format!(
+                        "Handler '{}' hit the host wait timeout of '{}' seconds",
+                        handler,
+                        timeout.host_wait_timeout().unwrap().as_secs()
+                    )
+                } else {
+                    format!(
+                        "Handler '{}' hit the timeout of '{}' seconds",
+                        handler,
+                        timeout.timeout().unwrap().as_secs()
+                    )
+                };
+                return Err(MappingError::Unknown(
+                    self.with_recent_logs(Error::from(trap).context(message)),
+                ));
             }
             Err(trap) => {
                 use wasmtime::TrapCode::*;
@@ -243,16 +430,30 @@ impl<C: Blockchain> WasmInstance<C> {
                     | Some(IntegerDivisionByZero)
                     | Some(BadConversionToInteger)
                     | Some(UnreachableCodeReached) => Some(e),
+                    // How much stack a handler needs depends on the compile
+                    // profile, so a stack overflow is not deterministic
+                    Some(StackOverflow) => {
+                        self.instance_ctx_mut().ctx.state.exit_handler();
+                        let message = format!(
+                            "Handler '{}' exceeded the maximum stack size of {} bytes; \
+                             stack usage depends on the compile profile of the deployment",
+                            handler, ENV_VARS.mappings.max_stack_size
+                        );
+                        return Err(MappingError::Unknown(
+                            self.with_recent_logs(e.context(message)),
+                        ));
+                    }
                     _ if self.instance_ctx().deterministic_host_trap => Some(e),
                     _ => {
                         self.instance_ctx_mut().ctx.state.exit_handler();
-                        return Err(MappingError::Unknown(e));
+                        return Err(MappingError::Unknown(self.with_recent_logs(e)));
                     }
                 }
             }
         };
 
         if let Some(deterministic_error) = deterministic_error {
+            let deterministic_error = self.with_recent_logs(deterministic_error);
             let message = format!("{:#}", deterministic_error).replace('\n', "\t");
 
             // Log the error and restore the updates snapshot, effectively reverting the handler.
@@ -267,6 +468,11 @@ impl<C: Blockchain> WasmInstance<C> {
                 block_ptr: Some(self.instance_ctx().ctx.block_ptr.cheap_clone()),
                 handler: Some(handler.to_string()),
                 deterministic: true,
+                // Deterministic traps without a trap code come from host
+                // exports like `abort`
+                code: trap_error_code(&deterministic_error)
+                    .unwrap_or(SubgraphErrorCode::HandlerError),
+                provider: None,
             };
             self.instance_ctx_mut()
                 .ctx
@@ -279,6 +485,71 @@ impl<C: Blockchain> WasmInstance<C> {
         let gas = self.gas.get();
         Ok((self.take_ctx().ctx.state, gas))
     }
+
+    /// Add the lines that the current handler logged last to `error`
+    fn with_recent_logs(&self, error: Error) -> Error {
+        self.instance_ctx().ctx.recent_logs.attach(error)
+    }
+
+    /// Count `trap` as a termination of the mapping
+    fn record_trap(&self, trap: Trap) -> Trap {
+        let ctx = self.instance_ctx();
+        let reason =
+            trap_termination_reason(&trap, ctx.possible_reorg, ctx.deterministic_host_trap);
+        ctx.host_metrics.record_termination(&ctx.ctx.logger, reason);
+        trap
+    }
+}
+
+/// The code for a failure caused by `error` if the error is a WASM trap
+/// that did not come from a host export, or a handler timeout
+pub fn trap_error_code(error: &Error) -> Option<SubgraphErrorCode> {
+    let trap = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<Trap>())?;
+    if trap.to_string().contains(TRAP_TIMEOUT) {
+        Some(SubgraphErrorCode::Timeout)
+    } else {
+        trap.trap_code().map(|_| SubgraphErrorCode::WasmTrap)
+    }
+}
+
+/// Classify why `trap` ended the execution of a handler. Traps without a
+/// trap code come from host exports; `possible_reorg` and
+/// `deterministic_host_trap` are the flags that the host export set on the
+/// instance before it trapped
+fn trap_termination_reason(
+    trap: &Trap,
+    possible_reorg: bool,
+    deterministic_host_trap: bool,
+) -> TerminationReason {
+    use wasmtime::TrapCode::*;
+
+    let message = trap.to_string();
+    if message.contains(TRAP_TIMEOUT) {
+        return TerminationReason::Timeout;
+    }
+    // AssemblyScript aborts with this message when it can't allocate
+    if message.to_lowercase().contains("out of memory") {
+        return TerminationReason::OutOfMemory;
+    }
+    let kind = match trap.trap_code() {
+        None if possible_reorg => "possible_reorg",
+        None if deterministic_host_trap => "host",
+        None => "host_nondeterministic",
+        Some(StackOverflow) => "stack_overflow",
+        Some(MemoryOutOfBounds) => "memory_out_of_bounds",
+        Some(HeapMisaligned) => "heap_misaligned",
+        Some(TableOutOfBounds) => "table_out_of_bounds",
+        Some(IndirectCallToNull) => "indirect_call_to_null",
+        Some(BadSignature) => "bad_signature",
+        Some(IntegerOverflow) => "integer_overflow",
+        Some(IntegerDivisionByZero) => "integer_division_by_zero",
+        Some(BadConversionToInteger) => "bad_conversion_to_integer",
+        Some(UnreachableCodeReached) => "unreachable",
+        Some(_) => "other",
+    };
+    TerminationReason::Trap(kind.to_string())
 }
 
 #[derive(Copy, Clone)]
@@ -302,10 +573,11 @@ pub struct WasmInstanceContext<C: Blockchain> {
     pub ctx: MappingContext<C>,
     pub valid_module: Arc<ValidModule>,
     pub host_metrics: Arc<HostMetrics>,
-    pub(crate) timeout: Option<Duration>,
+    // Paused by ipfs.map, and by host fns while they wait on I/O.
+    pub(crate) timeout: HandlerTimeout,
 
-    // Used by ipfs.map.
-    pub(crate) timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
+    // The name of the handler that is currently running.
+    pub(crate) handler: String,
 
     // First free byte in the current arena. Set on the first call to `raw_new`.
     arena_start_ptr: i32,
@@ -319,16 +591,21 @@ pub struct WasmInstanceContext<C: Blockchain> {
     // A host export trap ocurred for a deterministic reason.
     pub deterministic_host_trap: bool,
 
+    // The results of `store.loadRelatedPage` queries in this handler.
+    pub(crate) related_pages: RelatedPages,
+
     pub(crate) experimental_features: ExperimentalFeatures,
 }
 
 impl<C: Blockchain> WasmInstance<C> {
-    /// Instantiates the module and sets it to be interrupted after `timeout`.
+    /// Instantiates the module and sets it to be interrupted after `timeout`,
+    /// or after waiting on host fns for longer than `host_wait_timeout`.
     pub fn from_valid_module_with_ctx(
         valid_module: Arc<ValidModule>,
         ctx: MappingContext<C>,
         host_metrics: Arc<HostMetrics>,
         timeout: Option<Duration>,
+        host_wait_timeout: Option<Duration>,
         experimental_features: ExperimentalFeatures,
     ) -> Result<WasmInstance<C>, anyhow::Error> {
         let mut linker = wasmtime::Linker::new(&wasmtime::Store::new(valid_module.module.engine()));
@@ -346,19 +623,16 @@ impl<C: Blockchain> WasmInstance<C> {
         let ctx: Rc<RefCell<Option<MappingContext<C>>>> = Rc::new(RefCell::new(Some(ctx)));
 
         // Start the timeout watchdog task.
-        let timeout_stopwatch = Arc::new(std::sync::Mutex::new(TimeoutStopwatch::start_new()));
-        if let Some(timeout) = timeout {
+        let timeout = HandlerTimeout::start(timeout, host_wait_timeout);
+        if timeout.is_limited() {
             // This task is likely to outlive the instance, which is fine.
             let interrupt_handle = linker.store().interrupt_handle().unwrap();
-            let timeout_stopwatch = timeout_stopwatch.clone();
+            let timeout = timeout.cheap_clone();
             graph::spawn_allow_panic(async move {
                 let minimum_wait = Duration::from_secs(1);
                 loop {
-                    let time_left =
-                        timeout.checked_sub(timeout_stopwatch.lock().unwrap().elapsed());
-                    match time_left {
-                        None => break interrupt_handle.interrupt(), // Timed out.
-
+                    match timeout.time_left() {
+                        None => break,
                         Some(time) if time < minimum_wait => break interrupt_handle.interrupt(),
                         Some(time) => tokio::time::sleep(time).await,
                     }
@@ -388,7 +662,7 @@ impl<C: Blockchain> WasmInstance<C> {
                     let func_shared_ctx = Rc::downgrade(&shared_ctx);
                     let valid_module = valid_module.cheap_clone();
                     let host_metrics = host_metrics.cheap_clone();
-                    let timeout_stopwatch = timeout_stopwatch.cheap_clone();
+                    let timeout = timeout.cheap_clone();
                     let ctx = ctx.cheap_clone();
                     let gas = gas.cheap_clone();
                     linker.func(
@@ -405,8 +679,7 @@ impl<C: Blockchain> WasmInstance<C> {
                                     ctx.borrow_mut().take().unwrap(),
                                     valid_module.cheap_clone(),
                                     host_metrics.cheap_clone(),
-                                    timeout,
-                                    timeout_stopwatch.cheap_clone(),
+                                    timeout.cheap_clone(),
                                     experimental_features.clone()
                                 ).unwrap())
                             }
@@ -475,8 +748,13 @@ impl<C: Blockchain> WasmInstance<C> {
                     let _section =
                         stopwatch.start_section(&format!("host_export_{}", name_for_metrics));
 
+                    // Chain-specific host fns call out to the chain's
+                    // provider, so count their time as waiting on the host.
+                    let _waiting = instance.timeout.wait_on_host();
+
                     let ctx = HostFnCtx {
                         logger: instance.ctx.logger.cheap_clone(),
+                        deployment: instance.ctx.host_exports.subgraph_id.cheap_clone(),
                         block_ptr: instance.ctx.block_ptr.cheap_clone(),
                         heap: instance,
                         gas: gas.cheap_clone(),
@@ -501,109 +779,7 @@ impl<C: Blockchain> WasmInstance<C> {
             }
         }
 
-        link!("ethereum.encode", ethereum_encode, params_ptr);
-        link!("ethereum.decode", ethereum_decode, params_ptr, data_ptr);
-
-        link!("abort", abort, message_ptr, file_name_ptr, line, column);
-
-        link!("store.get", store_get, "host_export_store_get", entity, id);
-        link!(
-            "store.set",
-            store_set,
-            "host_export_store_set",
-            entity,
-            id,
-            data
-        );
-
-        // NOTE: where is this section name defined?
-        link!("bus.send", bus_send, "host_export_bus_send", any_string);
-
-        // All IPFS-related functions exported by the host WASM runtime should be listed in the
-        // graph::data::subgraph::features::IPFS_ON_ETHEREUM_CONTRACTS_FUNCTION_NAMES array for
-        // automatic feature detection to work.
-        //
-        // For reference, search this codebase for: ff652476-e6ad-40e4-85b8-e815d6c6e5e2
-        link!("ipfs.cat", ipfs_cat, "host_export_ipfs_cat", hash_ptr);
-        link!(
-            "ipfs.map",
-            ipfs_map,
-            "host_export_ipfs_map",
-            link_ptr,
-            callback,
-            user_data,
-            flags
-        );
-        // The previous ipfs-related functions are unconditionally linked for backward compatibility
-        if experimental_features.allow_non_deterministic_ipfs {
-            link!(
-                "ipfs.getBlock",
-                ipfs_get_block,
-                "host_export_ipfs_get_block",
-                hash_ptr
-            );
-        }
-
-        link!("store.remove", store_remove, entity_ptr, id_ptr);
-
-        link!("typeConversion.bytesToString", bytes_to_string, ptr);
-        link!("typeConversion.bytesToHex", bytes_to_hex, ptr);
-        link!("typeConversion.bigIntToString", big_int_to_string, ptr);
-        link!("typeConversion.bigIntToHex", big_int_to_hex, ptr);
-        link!("typeConversion.stringToH160", string_to_h160, ptr);
-        link!("typeConversion.bytesToBase58", bytes_to_base58, ptr);
-
-        link!("json.fromBytes", json_from_bytes, ptr);
-        link!("json.try_fromBytes", json_try_from_bytes, ptr);
-        link!("json.toI64", json_to_i64, ptr);
-        link!("json.toU64", json_to_u64, ptr);
-        link!("json.toF64", json_to_f64, ptr);
-        link!("json.toBigInt", json_to_big_int, ptr);
-
-        link!("crypto.keccak256", crypto_keccak_256, ptr);
-
-        link!("bigInt.plus", big_int_plus, x_ptr, y_ptr);
-        link!("bigInt.minus", big_int_minus, x_ptr, y_ptr);
-        link!("bigInt.times", big_int_times, x_ptr, y_ptr);
-        link!("bigInt.dividedBy", big_int_divided_by, x_ptr, y_ptr);
-        link!("bigInt.dividedByDecimal", big_int_divided_by_decimal, x, y);
-        link!("bigInt.mod", big_int_mod, x_ptr, y_ptr);
-        link!("bigInt.pow", big_int_pow, x_ptr, exp);
-        link!("bigInt.fromString", big_int_from_string, ptr);
-        link!("bigInt.bitOr", big_int_bit_or, x_ptr, y_ptr);
-        link!("bigInt.bitAnd", big_int_bit_and, x_ptr, y_ptr);
-        link!("bigInt.leftShift", big_int_left_shift, x_ptr, bits);
-        link!("bigInt.rightShift", big_int_right_shift, x_ptr, bits);
-
-        link!("bigDecimal.toString", big_decimal_to_string, ptr);
-        link!("bigDecimal.fromString", big_decimal_from_string, ptr);
-        link!("bigDecimal.plus", big_decimal_plus, x_ptr, y_ptr);
-        link!("bigDecimal.minus", big_decimal_minus, x_ptr, y_ptr);
-        link!("bigDecimal.times", big_decimal_times, x_ptr, y_ptr);
-        link!("bigDecimal.dividedBy", big_decimal_divided_by, x, y);
-        link!("bigDecimal.equals", big_decimal_equals, x_ptr, y_ptr);
-
-        link!("dataSource.create", data_source_create, name, params);
-        link!(
-            "dataSource.createWithContext",
-            data_source_create_with_context,
-            name,
-            params,
-            context
-        );
-        link!("dataSource.address", data_source_address,);
-        link!("dataSource.network", data_source_network,);
-        link!("dataSource.context", data_source_context,);
-
-        link!("ens.nameByHash", ens_name_by_hash, ptr);
-
-        link!("log.log", log_log, level, msg_ptr);
-
-        // `arweave and `box` functionality was removed, but apiVersion <= 0.0.4 must link it.
-        if api_version <= Version::new(0, 0, 4) {
-            link!("arweave.transactionData", arweave_transaction_data, ptr);
-            link!("box.profile", box_profile, ptr);
-        }
+        for_each_host_export!(link, api_version, experimental_features);
 
         // link the `gas` function
         // See also e3f03e62-40e4-4f8c-b4a1-d0375cca0b76
@@ -633,7 +809,6 @@ impl<C: Blockchain> WasmInstance<C> {
                 valid_module,
                 host_metrics,
                 timeout,
-                timeout_stopwatch,
                 experimental_features,
             )?);
         }
@@ -659,16 +834,26 @@ impl<C: Blockchain> WasmInstance<C> {
 
 impl<C: Blockchain> AscHeap for WasmInstanceContext<C> {
     fn raw_new(&mut self, bytes: &[u8], gas: &GasCounter) -> Result<u32, DeterministicHostError> {
+        self.raw_new_parts(&[bytes], gas)
+    }
+
+    fn raw_new_parts(
+        &mut self,
+        parts: &[&[u8]],
+        gas: &GasCounter,
+    ) -> Result<u32, DeterministicHostError> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+
         // The cost of writing to wasm memory from the host is the same as of writing from wasm
         // using load instructions.
-        gas.consume_host_fn(Gas::new(GAS_COST_STORE as u64 * bytes.len() as u64))?;
+        gas.consume_host_fn(Gas::new(GAS_COST_STORE as u64 * len as u64))?;
 
         // We request large chunks from the AssemblyScript allocator to use as arenas that we
         // manage directly.
 
         static MIN_ARENA_SIZE: i32 = 10_000;
 
-        let size = i32::try_from(bytes.len()).unwrap();
+        let size = i32::try_from(len).unwrap();
         if size > self.arena_free_size {
             // Allocate a new arena. Any free space left in the previous arena is left unused. This
             // causes at most half of memory to be wasted, which is acceptable.
@@ -697,8 +882,12 @@ impl<C: Blockchain> AscHeap for WasmInstanceContext<C> {
 
         let ptr = self.arena_start_ptr as usize;
 
-        // Unwrap: We have just allocated enough space for `bytes`.
-        self.memory.write(ptr, bytes).unwrap();
+        // Unwrap: We have just allocated enough space for all parts.
+        let mut offset = ptr;
+        for part in parts {
+            self.memory.write(offset, part).unwrap();
+            offset += part.len();
+        }
         self.arena_start_ptr += size;
         self.arena_free_size -= size;
 
@@ -774,8 +963,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         ctx: MappingContext<C>,
         valid_module: Arc<ValidModule>,
         host_metrics: Arc<HostMetrics>,
-        timeout: Option<Duration>,
-        timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
+        timeout: HandlerTimeout,
         experimental_features: ExperimentalFeatures,
     ) -> Result<Self, anyhow::Error> {
         // Provide access to the WASM runtime linear memory
@@ -813,11 +1001,12 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             valid_module,
             host_metrics,
             timeout,
-            timeout_stopwatch,
+            handler: String::new(),
             arena_free_size: 0,
             arena_start_ptr: 0,
             possible_reorg: false,
             deterministic_host_trap: false,
+            related_pages: RelatedPages::default(),
             experimental_features,
         })
     }
@@ -827,8 +1016,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         ctx: MappingContext<C>,
         valid_module: Arc<ValidModule>,
         host_metrics: Arc<HostMetrics>,
-        timeout: Option<Duration>,
-        timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
+        timeout: HandlerTimeout,
         experimental_features: ExperimentalFeatures,
     ) -> Result<Self, anyhow::Error> {
         let memory = caller
@@ -869,11 +1057,12 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             valid_module,
             host_metrics,
             timeout,
-            timeout_stopwatch,
+            handler: String::new(),
             arena_free_size: 0,
             arena_start_ptr: 0,
             possible_reorg: false,
             deterministic_host_trap: false,
+            related_pages: RelatedPages::default(),
             experimental_features,
         })
     }
@@ -924,7 +1113,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         let stopwatch = &self.host_metrics.stopwatch;
         stopwatch.start_section("host_export_store_set__wasm_instance_context_store_set");
 
-        let entity = asc_get(self, entity_ptr, gas)?;
+        let entity: String = asc_get(self, entity_ptr, gas)?;
         let id = asc_get(self, id_ptr, gas)?;
         let data = asc_get(self, data_ptr, gas)?;
 
@@ -932,12 +1121,14 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             &self.ctx.logger,
             &mut self.ctx.state,
             &self.ctx.proof_of_indexing,
-            entity,
+            entity.clone(),
             id,
             data,
             stopwatch,
             gas,
         )?;
+        // Only writes that were allowed count as observed
+        self.observe_write(&entity);
 
         Ok(())
     }
@@ -949,16 +1140,27 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
     ) -> Result<(), HostExportError> {
-        let entity = asc_get(self, entity_ptr, gas)?;
+        let entity: String = asc_get(self, entity_ptr, gas)?;
         let id = asc_get(self, id_ptr, gas)?;
         self.ctx.host_exports.store_remove(
             &self.ctx.logger,
             &mut self.ctx.state,
             &self.ctx.proof_of_indexing,
-            entity,
+            entity.clone(),
             id,
             gas,
-        )
+        )?;
+        self.observe_write(&entity);
+        Ok(())
+    }
+
+    /// Record that the current handler wrote an entity of type `entity`
+    fn observe_write(&self, entity: &str) {
+        self.host_metrics.handler_entity_types.observe(
+            &self.ctx.host_exports.data_source_name,
+            &self.handler,
+            entity,
+        );
     }
 
     /// function bus.send(any_string: string): void
@@ -973,6 +1175,46 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         Ok(())
     }
 
+    /// function store.loadRelatedPage(entity: string, field: string, value: Value,
+    ///     pageSize: u32, pageToken: string | null): RelatedEntityPage
+    pub fn store_load_related_page(
+        &mut self,
+        gas: &GasCounter,
+        entity_ptr: AscPtr<AscString>,
+        field_ptr: AscPtr<AscString>,
+        value_ptr: AscPtr<AscEnum<StoreValueKind>>,
+        page_size: u32,
+        page_token_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscRelatedEntityPage>, HostExportError> {
+        let _timer = self
+            .host_metrics
+            .cheap_clone()
+            .time_host_fn_execution_region("store_load_related_page");
+
+        let entity_type: String = asc_get(self, entity_ptr, gas)?;
+        let field: String = asc_get(self, field_ptr, gas)?;
+        let value: store::Value = asc_get(self, value_ptr, gas)?;
+        let page_token: Option<String> = if page_token_ptr.is_null() {
+            None
+        } else {
+            Some(asc_get(self, page_token_ptr, gas)?)
+        };
+
+        let query = self
+            .ctx
+            .host_exports
+            .related_entity_query(entity_type, field, value)?;
+        let page = self.ctx.host_exports.store_load_related_page(
+            &mut self.ctx.state,
+            &mut self.related_pages,
+            query,
+            page_size,
+            page_token,
+            gas,
+        )?;
+        Ok(asc_new(self, &page, gas)?)
+    }
+
     /// function store.get(entity: string, id: string): Entity | null
     pub fn store_get(
         &mut self,
@@ -1173,9 +1415,15 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         }
 
         let link = asc_get(self, link_ptr, gas)?;
-        let ipfs_res = self.ctx.host_exports.ipfs_cat(&self.ctx.logger, link);
+        let ipfs_res = {
+            let _waiting = self.timeout.wait_on_host();
+            self.ctx.host_exports.ipfs_cat(&self.ctx.logger, link)
+        };
         match ipfs_res {
-            Ok(bytes) => asc_new(self, &*bytes, gas).map_err(Into::into),
+            Ok(bytes) => {
+                self.host_metrics.usage.ipfs_bytes(bytes.len());
+                asc_new(self, &*bytes, gas).map_err(Into::into)
+            }
 
             // Return null in case of error.
             Err(e) => {
@@ -1203,9 +1451,15 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         }
 
         let link = asc_get(self, link_ptr, gas)?;
-        let ipfs_res = self.ctx.host_exports.ipfs_get_block(&self.ctx.logger, link);
+        let ipfs_res = {
+            let _waiting = self.timeout.wait_on_host();
+            self.ctx.host_exports.ipfs_get_block(&self.ctx.logger, link)
+        };
         match ipfs_res {
-            Ok(bytes) => asc_new(self, &*bytes, gas).map_err(Into::into),
+            Ok(bytes) => {
+                self.host_metrics.usage.ipfs_bytes(bytes.len());
+                asc_new(self, &*bytes, gas).map_err(Into::into)
+            }
 
             // Return null in case of error.
             Err(e) => {
@@ -1243,12 +1497,10 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         let flags = asc_get(self, flags, gas)?;
 
         // Pause the timeout while running ipfs_map, ensure it will be restarted by using a guard.
-        self.timeout_stopwatch.lock().unwrap().stop();
-        let defer_stopwatch = self.timeout_stopwatch.clone();
-        let _stopwatch_guard = defer::defer(|| defer_stopwatch.lock().unwrap().start());
+        let _stopwatch_guard = self.timeout.pause();
 
         let start_time = Instant::now();
-        let output_states = HostExports::ipfs_map(
+        let stats = HostExports::ipfs_map(
             &self.ctx.host_exports.link_resolver.clone(),
             self,
             link.clone(),
@@ -1262,12 +1514,12 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             "Successfully processed file with ipfs.map";
             "link" => &link,
             "callback" => &*callback,
-            "n_calls" => output_states.len(),
+            "n_calls" => stats.values,
+            "bytes" => stats.bytes,
+            "chunks" => stats.chunks,
+            "peak_changes" => stats.peak_changes,
             "time" => format!("{}ms", start_time.elapsed().as_millis())
         );
-        for output_state in output_states {
-            self.ctx.state.extend(output_state);
-        }
 
         Ok(())
     }
@@ -1322,6 +1574,30 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         asc_new(self, &*big_int, gas)
     }
 
+    /// Encodes the value with the canonical encoding from
+    /// `graph::data::store::json`.
+    /// function value.toJSON(value: Value): string
+    pub fn value_to_json(
+        &mut self,
+        gas: &GasCounter,
+        value_ptr: AscPtr<AscEnum<StoreValueKind>>,
+    ) -> Result<AscPtr<AscString>, DeterministicHostError> {
+        let value: store::Value = asc_get(self, value_ptr, gas)?;
+        let json = self.ctx.host_exports.value_to_json(&value, gas)?;
+        asc_new(self, &json, gas)
+    }
+
+    /// function value.fromJSON(json: string): Value
+    pub fn value_from_json(
+        &mut self,
+        gas: &GasCounter,
+        json_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscEnum<StoreValueKind>>, DeterministicHostError> {
+        let json: String = asc_get(self, json_ptr, gas)?;
+        let value = self.ctx.host_exports.value_from_json(&json, gas)?;
+        asc_new(self, &value, gas)
+    }
+
     /// function crypto.keccak256(input: Bytes): Bytes
     pub fn crypto_keccak_256(
         &mut self,
@@ -1724,6 +2000,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
     ) -> Result<(), DeterministicHostError> {
         let level = LogLevel::from(level).into();
         let msg: String = asc_get(self, msg, gas)?;
+        self.ctx.recent_logs.push(level, &msg);
         self.ctx
             .host_exports
             .log_log(&self.ctx.logger, level, msg, gas)
@@ -1786,3 +2063,53 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use graph::blockchain::HostFn;
+    use semver::Version;
+
+    use super::{host_export_names, host_export_surface_hash, ExperimentalFeatures};
+
+    const FEATURES: ExperimentalFeatures = ExperimentalFeatures {
+        allow_non_deterministic_ipfs: false,
+    };
+
+    #[test]
+    fn host_export_names_follow_linking() {
+        let old = host_export_names(&Version::new(0, 0, 4), &FEATURES);
+        let new = host_export_names(&Version::new(0, 0, 7), &FEATURES);
+        assert!(old.contains(&"box.profile"));
+        assert!(!new.contains(&"box.profile"));
+        assert!(new.contains(&"gas"));
+        assert!(new.contains(&"store.set"));
+        assert!(!new.contains(&"ipfs.getBlock"));
+
+        let ipfs = ExperimentalFeatures {
+            allow_non_deterministic_ipfs: true,
+        };
+        assert!(host_export_names(&Version::new(0, 0, 7), &ipfs).contains(&"ipfs.getBlock"));
+    }
+
+    #[test]
+    fn surface_hash_includes_chain_host_fns() {
+        let versions = [Version::new(0, 0, 7)];
+        let ethereum_call = HostFn {
+            name: "ethereum.call",
+            func: Arc::new(|_, _| Ok(0)),
+        };
+        let without = host_export_surface_hash(&versions, &[], &FEATURES);
+        let with = host_export_surface_hash(&versions, &[ethereum_call], &FEATURES);
+        assert_ne!(without, with);
+        assert_eq!(
+            without,
+            host_export_surface_hash(
+                &[Version::new(0, 0, 7), Version::new(0, 0, 7)],
+                &[],
+                &FEATURES
+            )
+        );
+    }
+}
@@ -46,6 +46,11 @@ impl TimeoutStopwatch {
         self.start_time = None;
     }
 
+    /// Whether the stopwatch is running
+    pub fn is_running(&self) -> bool {
+        self.start_time.is_some()
+    }
+
     /// Returns the elapsed time since the start of the stopwatch.
     pub fn elapsed(&self) -> Duration {
         match self.start_time {
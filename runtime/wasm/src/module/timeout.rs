@@ -0,0 +1,240 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use graph::prelude::CheapClone;
+
+use super::TimeoutStopwatch;
+
+/// Keeps track of how long a handler has been running, split into the time
+/// spent executing WASM and the time spent blocked on host functions that
+/// perform I/O, like `ethereum.call`.
+///
+/// Without a `host_wait_timeout`, both count towards `timeout`. With a
+/// `host_wait_timeout`, `timeout` only limits the time spent executing
+/// WASM, and waiting on the host is limited by `host_wait_timeout`.
+///
+/// The guards from `pause` and `wait_on_host` can be nested, for example,
+/// when a callback of `ipfs.map` calls `ethereum.call`; which stopwatches
+/// run is decided by all the guards that are alive, not by the one that
+/// was dropped last.
+#[derive(Clone)]
+pub struct HandlerTimeout {
+    timeout: Option<Duration>,
+    host_wait_timeout: Option<Duration>,
+    state: Arc<Mutex<State>>,
+}
+
+impl CheapClone for HandlerTimeout {}
+
+struct State {
+    /// The time that counts towards `timeout`
+    stopwatch: TimeoutStopwatch,
+    /// The time spent waiting on the host
+    host_wait_stopwatch: TimeoutStopwatch,
+    /// The number of guards from `pause` that are alive
+    pauses: usize,
+    /// The number of guards from `wait_on_host` that are alive
+    host_waits: usize,
+}
+
+impl State {
+    /// Run exactly the stopwatches that the guards that are alive call for
+    fn update(&mut self, host_wait_excluded: bool) {
+        let paused = self.pauses > 0;
+        let waiting = self.host_waits > 0;
+        set_running(
+            &mut self.stopwatch,
+            !paused && !(waiting && host_wait_excluded),
+        );
+        set_running(&mut self.host_wait_stopwatch, !paused && waiting);
+    }
+}
+
+fn set_running(stopwatch: &mut TimeoutStopwatch, running: bool) {
+    match (stopwatch.is_running(), running) {
+        (false, true) => stopwatch.start(),
+        (true, false) => stopwatch.stop(),
+        _ => {}
+    }
+}
+
+impl HandlerTimeout {
+    pub fn start(timeout: Option<Duration>, host_wait_timeout: Option<Duration>) -> Self {
+        HandlerTimeout {
+            timeout,
+            host_wait_timeout,
+            state: Arc::new(Mutex::new(State {
+                stopwatch: TimeoutStopwatch::start_new(),
+                host_wait_stopwatch: TimeoutStopwatch::new(),
+                pauses: 0,
+                host_waits: 0,
+            })),
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn host_wait_timeout(&self) -> Option<Duration> {
+        self.host_wait_timeout
+    }
+
+    /// Whether there is any budget that the handler can run out of
+    pub fn is_limited(&self) -> bool {
+        self.timeout.is_some() || self.host_wait_timeout.is_some()
+    }
+
+    /// The time until the first budget runs out. Returns `None` if there is
+    /// no budget at all
+    pub fn time_left(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        let wasm = time_left(self.timeout, &state.stopwatch);
+        let host_wait = time_left(self.host_wait_timeout, &state.host_wait_stopwatch);
+        wasm.into_iter().chain(host_wait).min()
+    }
+
+    /// Whether the budget for waiting on the host is the one that runs out
+    /// first. Used to explain why a handler was interrupted
+    pub fn host_wait_is_exhausted(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match (
+            time_left(self.timeout, &state.stopwatch),
+            time_left(self.host_wait_timeout, &state.host_wait_stopwatch),
+        ) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(wasm), Some(host_wait)) => host_wait < wasm,
+        }
+    }
+
+    /// The time spent blocked on host functions that perform I/O
+    pub fn host_wait_time(&self) -> Duration {
+        self.state.lock().unwrap().host_wait_stopwatch.elapsed()
+    }
+
+    /// Stop counting time against any budget until the returned guard is
+    /// dropped
+    pub fn pause(&self) -> impl Drop {
+        self.change(|state| state.pauses += 1);
+        let this = self.cheap_clone();
+        defer::defer(move || this.change(|state| state.pauses -= 1))
+    }
+
+    /// Count the time until the returned guard is dropped as time spent
+    /// waiting on the host. This needs to be called by host functions
+    /// before they block on I/O
+    pub fn wait_on_host(&self) -> impl Drop {
+        self.change(|state| state.host_waits += 1);
+        let this = self.cheap_clone();
+        defer::defer(move || this.change(|state| state.host_waits -= 1))
+    }
+
+    fn change(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        state.update(self.host_wait_timeout.is_some());
+    }
+}
+
+fn time_left(budget: Option<Duration>, stopwatch: &TimeoutStopwatch) -> Option<Duration> {
+    budget.map(|budget| budget.checked_sub(stopwatch.elapsed()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(20);
+
+    /// Whether the stopwatch for `timeout` and the one for waiting on the
+    /// host are running
+    fn running(timeout: &HandlerTimeout) -> (bool, bool) {
+        let state = timeout.state.lock().unwrap();
+        (
+            state.stopwatch.is_running(),
+            state.host_wait_stopwatch.is_running(),
+        )
+    }
+
+    #[test]
+    fn host_wait_counts_towards_timeout_without_own_budget() {
+        let timeout = HandlerTimeout::start(Some(Duration::from_secs(60)), None);
+        assert_eq!((true, false), running(&timeout));
+        {
+            let _waiting = timeout.wait_on_host();
+            assert_eq!((true, true), running(&timeout));
+        }
+        assert_eq!((true, false), running(&timeout));
+        assert!(!timeout.host_wait_is_exhausted());
+    }
+
+    #[test]
+    fn host_wait_has_own_budget() {
+        let timeout =
+            HandlerTimeout::start(Some(Duration::from_secs(60)), Some(Duration::from_secs(60)));
+        let waiting = timeout.wait_on_host();
+        assert_eq!((false, true), running(&timeout));
+        sleep(TICK);
+        drop(waiting);
+        assert_eq!((true, false), running(&timeout));
+
+        assert!(timeout.host_wait_time() >= TICK);
+        // Waiting did not use up the budget for WASM
+        assert!(timeout.time_left().unwrap() > Duration::from_secs(60) - TICK);
+    }
+
+    #[test]
+    fn nested_guards() {
+        let timeout =
+            HandlerTimeout::start(Some(Duration::from_secs(60)), Some(Duration::from_secs(60)));
+
+        // Like `ipfs.map` with a callback that calls `ethereum.call`
+        let paused = timeout.pause();
+        assert_eq!((false, false), running(&timeout));
+        let callback = timeout.cheap_clone();
+        {
+            let _waiting = callback.wait_on_host();
+            assert_eq!((false, false), running(&timeout));
+        }
+        // Dropping the inner guard must not restart anything while paused
+        assert_eq!((false, false), running(&timeout));
+        sleep(TICK);
+        drop(paused);
+        assert_eq!((true, false), running(&timeout));
+        assert_eq!(Duration::ZERO, timeout.host_wait_time());
+
+        // Nested waits keep waiting until the outermost guard is dropped
+        let outer = timeout.wait_on_host();
+        let inner = timeout.wait_on_host();
+        drop(inner);
+        assert_eq!((false, true), running(&timeout));
+        drop(outer);
+        assert_eq!((true, false), running(&timeout));
+
+        // Nested pauses, too
+        let outer = timeout.pause();
+        let inner = timeout.pause();
+        drop(inner);
+        assert_eq!((false, false), running(&timeout));
+        drop(outer);
+        assert_eq!((true, false), running(&timeout));
+    }
+
+    #[test]
+    fn exhausted_budgets() {
+        let timeout = HandlerTimeout::start(Some(Duration::from_secs(60)), Some(TICK));
+        {
+            let _waiting = timeout.wait_on_host();
+            sleep(TICK * 2);
+        }
+        assert_eq!(Some(Duration::ZERO), timeout.time_left());
+        assert!(timeout.host_wait_is_exhausted());
+
+        let timeout = HandlerTimeout::start(None, None);
+        assert!(!timeout.is_limited());
+        assert_eq!(None, timeout.time_left());
+    }
+}
@@ -0,0 +1,411 @@
+//! Run the mappings of a deployment against the triggers in a trigger file.
+//!
+//! The triggers are handled block by block as the subgraph runner would
+//! handle them, but entities are kept in memory and nothing is fetched over
+//! the network: IPFS files can not be read, ENS names are never found and
+//! chains have to supply host functions that don't need a provider. The
+//! store starts out empty, so the proof of indexing only matches the one of
+//! the deployment if the file starts at the first block of the deployment.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use atomic_refcell::AtomicRefCell;
+use graph::blockchain::replay::{ReplayableTrigger, TriggerFile};
+use graph::blockchain::{Blockchain, HostFn};
+use graph::components::store::{
+    DeploymentId, DeploymentLocator, EnsLookup, EntityKey, ReadStore, RelatedEntityQuery,
+};
+use graph::components::subgraph::{MappingError, ProofOfIndexing, ProofOfIndexingVersion};
+use graph::data::schema::Schema;
+use graph::data::store::scalar::Bytes;
+use graph::data::subgraph::schema::{SubgraphError, POI_OBJECT};
+use graph::data::subgraph::SPEC_VERSION_0_0_6;
+use graph::data::value::Word;
+use graph::data_source::{CausalityRegion, MappingTrigger, TriggerWithHandler};
+use graph::prelude::*;
+use graph::runtime::CompileProfile;
+use graph::util::lfu_cache::LfuCache;
+use semver::Version;
+
+use crate::mapping::RecentLogs;
+use crate::module::ToAscPtr;
+use crate::{ExperimentalFeatures, HostExports, MappingContext, ValidModule, WasmInstance};
+
+/// What handling the triggers of one block did
+pub struct ReplayedBlock {
+    pub ptr: BlockPtr,
+    pub modifications: Vec<EntityModification>,
+    /// The digest of the proof of indexing of each causality region after
+    /// the block
+    pub proof_of_indexing: BTreeMap<String, Bytes>,
+    /// The deterministic errors that handlers ran into. The node would fail
+    /// the deployment at this block, and replaying stops here
+    pub errors: Vec<SubgraphError>,
+}
+
+/// Run the triggers in `file` through the mappings in `raw_module` and
+/// return what happened in each block. Only the triggers of `data_source`
+/// are handled if it is given
+pub fn replay<C: Blockchain>(
+    logger: &Logger,
+    file: &TriggerFile,
+    raw_module: &[u8],
+    host_fns: Vec<HostFn>,
+    registry: Arc<dyn MetricsRegistry>,
+    data_source: Option<&str>,
+) -> Result<Vec<ReplayedBlock>, Error>
+where
+    C::MappingTrigger: ToAscPtr + ReplayableTrigger,
+{
+    let deployment = DeploymentHash::new(file.deployment.clone())
+        .map_err(|id| anyhow!("invalid deployment hash `{}`", id))?;
+    let locator = DeploymentLocator::new(DeploymentId(0), deployment.clone(), None);
+    let schema = Schema::parse(&file.schema, deployment.clone())?;
+    let spec_version = Version::parse(&file.spec_version)
+        .with_context(|| format!("invalid spec version `{}`", file.spec_version))?;
+    let poi_version = if spec_version.ge(&SPEC_VERSION_0_0_6) {
+        ProofOfIndexingVersion::Fast
+    } else {
+        ProofOfIndexingVersion::Legacy
+    };
+
+    if let Some(name) = data_source {
+        if !file.has_data_source(name) {
+            return Err(anyhow!("the trigger file has no data source `{}`", name));
+        }
+    }
+    let mut host_exports = Vec::new();
+    for ds in &file.data_sources {
+        let exports = HostExports::<C>::for_replay(
+            deployment.clone(),
+            ds,
+            file.network.clone(),
+            Arc::new(NoLinkResolver),
+            Arc::new(NoEnsLookup),
+        )?;
+        host_exports.push(Arc::new(exports));
+    }
+
+    let valid_module = Arc::new(ValidModule::new(
+        logger,
+        raw_module,
+        CompileProfile::FastCompile,
+    )?);
+    let stopwatch = StopwatchMetrics::new(logger.clone(), &locator, "replay", registry.clone());
+    let host_metrics = Arc::new(HostMetrics::new(registry, &locator, stopwatch));
+    let host_fns = Arc::new(host_fns);
+    let store = Arc::new(MemoryStore::new(Arc::new(schema)));
+
+    let mut replayed = Vec::new();
+    for block in &file.blocks {
+        let ptr = block.ptr()?;
+        let data = Arc::new(
+            serde_json::from_value(block.data.clone())
+                .with_context(|| format!("invalid data for block {}", block.number))?,
+        );
+        let proof_of_indexing = Arc::new(AtomicRefCell::new(ProofOfIndexing::new(
+            ptr.number,
+            poi_version,
+        )));
+        let mut state = BlockState::<C>::new(store.cheap_clone(), LfuCache::new());
+
+        for trigger in &block.triggers {
+            let ds = file.data_sources.get(trigger.data_source).ok_or_else(|| {
+                anyhow!(
+                    "a trigger in block {} is for the unknown data source #{}",
+                    block.number,
+                    trigger.data_source
+                )
+            })?;
+            if data_source.map_or(false, |name| ds.name != name) {
+                continue;
+            }
+            let mapping_trigger = C::MappingTrigger::from_replay(&data, trigger.trigger.clone())
+                .with_context(|| {
+                    format!(
+                        "invalid trigger for handler `{}` in block {}",
+                        trigger.handler, block.number
+                    )
+                })?;
+            let ctx = MappingContext {
+                logger: logger.cheap_clone(),
+                host_exports: host_exports[trigger.data_source].cheap_clone(),
+                block_ptr: ptr.cheap_clone(),
+                state,
+                proof_of_indexing: Some(proof_of_indexing.cheap_clone()),
+                host_fns: host_fns.cheap_clone(),
+                debug_fork: None,
+                recent_logs: RecentLogs::default(),
+            };
+            let instance = WasmInstance::from_valid_module_with_ctx(
+                valid_module.cheap_clone(),
+                ctx,
+                host_metrics.cheap_clone(),
+                ENV_VARS.mappings.timeout,
+                ENV_VARS.mappings.host_wait_timeout,
+                ExperimentalFeatures {
+                    allow_non_deterministic_ipfs: false,
+                },
+            )
+            .context("module instantiation failed")?;
+            let trigger = TriggerWithHandler::new(
+                MappingTrigger::Onchain(mapping_trigger),
+                trigger.handler.clone(),
+                ptr.cheap_clone(),
+            );
+            let handler = trigger.handler_name().to_string();
+            state = match instance.handle_trigger(trigger) {
+                Ok((state, _gas)) => state,
+                Err(MappingError::Unknown(e)) | Err(MappingError::PossibleReorg(e)) => {
+                    return Err(e.context(format!(
+                        "handler `{}` failed in block {}",
+                        handler, block.number
+                    )))
+                }
+            };
+        }
+
+        let errors = std::mem::take(&mut state.deterministic_errors);
+        let proof_of_indexing = Arc::try_unwrap(proof_of_indexing)
+            .map_err(|_| anyhow!("the proof of indexing is still in use"))?
+            .into_inner();
+        update_proof_of_indexing(proof_of_indexing, &mut state.entity_cache)?;
+        let modifications = state.entity_cache.as_modifications()?.modifications;
+        store.apply(&modifications);
+
+        let stop = !errors.is_empty();
+        replayed.push(ReplayedBlock {
+            ptr,
+            modifications,
+            proof_of_indexing: store.proofs_of_indexing(),
+            errors,
+        });
+        if stop {
+            break;
+        }
+    }
+    Ok(replayed)
+}
+
+/// Turn the proof of indexing events of a block into entity changes, like
+/// the subgraph runner does
+fn update_proof_of_indexing(
+    proof_of_indexing: ProofOfIndexing,
+    entity_cache: &mut EntityCache,
+) -> Result<(), Error> {
+    for (causality_region, stream) in proof_of_indexing.take() {
+        let entity_key = EntityKey {
+            entity_type: POI_OBJECT.to_owned(),
+            entity_id: causality_region.into(),
+            causality_region: CausalityRegion::ONCHAIN,
+        };
+        let prev_poi = entity_cache
+            .get(&entity_key)
+            .map_err(Error::from)?
+            .and_then(|entity| match entity.get("digest") {
+                Some(Value::Bytes(b)) => Some(b.clone()),
+                _ => None,
+            });
+        let digest: Bytes = stream.pause(prev_poi.as_deref()).as_slice().into();
+        let entity = entity! {
+            id: entity_key.entity_id.to_string(),
+            digest: digest,
+        };
+        entity_cache.set(entity_key, entity)?;
+    }
+    Ok(())
+}
+
+/// The entities that the replayed handlers wrote
+struct MemoryStore {
+    schema: Arc<Schema>,
+    entities: Mutex<BTreeMap<EntityKey, Entity>>,
+}
+
+impl MemoryStore {
+    fn new(schema: Arc<Schema>) -> Self {
+        MemoryStore {
+            schema,
+            entities: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn apply(&self, modifications: &[EntityModification]) {
+        let mut entities = self.entities.lock().unwrap();
+        for modification in modifications {
+            match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => {
+                    entities.insert(key.clone(), data.clone());
+                }
+                EntityModification::Remove { key } => {
+                    entities.remove(key);
+                }
+            }
+        }
+    }
+
+    fn proofs_of_indexing(&self) -> BTreeMap<String, Bytes> {
+        self.entities
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.entity_type == *POI_OBJECT)
+            .filter_map(|(key, entity)| match entity.get("digest") {
+                Some(Value::Bytes(digest)) => Some((key.entity_id.to_string(), digest.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl ReadStore for MemoryStore {
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        Ok(self.entities.lock().unwrap().get(key).cloned())
+    }
+
+    fn get_many(
+        &self,
+        keys: BTreeSet<EntityKey>,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        let entities = self.entities.lock().unwrap();
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| entities.get(&key).cloned().map(|entity| (key, entity)))
+            .collect())
+    }
+
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        Ok(self
+            .entities
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, entity)| {
+                query.matches(key, entity) && RelatedEntityQuery::is_after(key, after)
+            })
+            .take(limit)
+            .map(|(key, entity)| (key.clone(), entity.clone()))
+            .collect())
+    }
+
+    fn input_schema(&self) -> Arc<Schema> {
+        self.schema.cheap_clone()
+    }
+}
+
+/// Replaying never fetches files
+#[derive(Debug)]
+struct NoLinkResolver;
+
+#[async_trait]
+impl LinkResolver for NoLinkResolver {
+    fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolver> {
+        Box::new(NoLinkResolver)
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolver> {
+        Box::new(NoLinkResolver)
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        Err(anyhow!(
+            "can not read `{}` when replaying triggers",
+            link.link
+        ))
+    }
+
+    async fn get_block(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        Err(anyhow!(
+            "can not read `{}` when replaying triggers",
+            link.link
+        ))
+    }
+
+    async fn json_stream(&self, _logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        Err(anyhow!(
+            "can not read `{}` when replaying triggers",
+            link.link
+        ))
+    }
+}
+
+/// Replaying has no rainbow table, so ENS names are never found
+struct NoEnsLookup;
+
+impl EnsLookup for NoEnsLookup {
+    fn find_name(&self, _hash: &str) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+
+    fn is_table_empty(&self) -> Result<bool, StoreError> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "type Pool @entity { id: ID!, token: String! }";
+
+    fn key(entity_type: &str, id: &str) -> EntityKey {
+        EntityKey::data(entity_type.to_string(), id.to_string())
+    }
+
+    #[test]
+    fn memory_store() {
+        let deployment = DeploymentHash::new("QmReplay").unwrap();
+        let schema = Schema::parse(SCHEMA, deployment).unwrap();
+        let store = MemoryStore::new(Arc::new(schema));
+
+        store.apply(&[
+            EntityModification::Insert {
+                key: key("Pool", "1"),
+                data: entity! { id: "1", token: "a" },
+            },
+            EntityModification::Insert {
+                key: key("Pool", "2"),
+                data: entity! { id: "2", token: "b" },
+            },
+            EntityModification::Insert {
+                key: EntityKey {
+                    entity_type: POI_OBJECT.to_owned(),
+                    entity_id: "ethereum/mainnet".into(),
+                    causality_region: CausalityRegion::ONCHAIN,
+                },
+                data: entity! { id: "ethereum/mainnet", digest: Bytes::from(&[1u8, 2][..]) },
+            },
+        ]);
+        store.apply(&[
+            EntityModification::Overwrite {
+                key: key("Pool", "1"),
+                data: entity! { id: "1", token: "c" },
+            },
+            EntityModification::Remove {
+                key: key("Pool", "2"),
+            },
+        ]);
+
+        assert_eq!(
+            Some(entity! { id: "1", token: "c" }),
+            store.get(&key("Pool", "1")).unwrap()
+        );
+        assert_eq!(None, store.get(&key("Pool", "2")).unwrap());
+        let keys = vec![key("Pool", "1"), key("Pool", "2")]
+            .into_iter()
+            .collect();
+        assert_eq!(1, store.get_many(keys).unwrap().len());
+        assert_eq!(
+            vec![("ethereum/mainnet".to_string(), Bytes::from(&[1u8, 2][..]))],
+            store.proofs_of_indexing().into_iter().collect::<Vec<_>>()
+        );
+    }
+}
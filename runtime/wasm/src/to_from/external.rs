@@ -8,6 +8,8 @@ use graph::{prelude::serde_json, runtime::FromAscObj};
 use graph::{prelude::web3::types as web3, runtime::AscHeap};
 
 use crate::asc_abi::class::*;
+use crate::asc_abi::direct;
+use crate::host_exports::RelatedEntityPage;
 
 impl ToAscObj<Uint8Array> for web3::H160 {
     fn to_asc_obj<H: AscHeap + ?Sized>(
@@ -245,7 +247,7 @@ impl FromAscObj<AscEnum<StoreValueKind>> for store::Value {
         Ok(match asc_enum.kind {
             StoreValueKind::String => {
                 let ptr: AscPtr<AscString> = AscPtr::from(payload);
-                Value::String(asc_get(heap, ptr, gas)?)
+                Value::String(direct::read_string(heap, ptr, gas)?)
             }
             StoreValueKind::Int => Value::Int(i32::from(payload)),
             StoreValueKind::BigDecimal => {
@@ -260,8 +262,8 @@ impl FromAscObj<AscEnum<StoreValueKind>> for store::Value {
             StoreValueKind::Null => Value::Null,
             StoreValueKind::Bytes => {
                 let ptr: AscPtr<Uint8Array> = AscPtr::from(payload);
-                let array: Vec<u8> = asc_get(heap, ptr, gas)?;
-                Value::Bytes(array.as_slice().into())
+                let array = direct::read_bytes(heap, ptr, gas)?;
+                Value::Bytes(array.into())
             }
             StoreValueKind::BigInt => {
                 let ptr: AscPtr<AscBigInt> = AscPtr::from(payload);
@@ -281,16 +283,13 @@ impl ToAscObj<AscEnum<StoreValueKind>> for store::Value {
         use self::store::Value;
 
         let payload = match self {
-            Value::String(string) => asc_new(heap, string.as_str(), gas)?.into(),
+            Value::String(string) => direct::new_string(heap, string.as_str(), gas)?.into(),
             Value::Int(n) => EnumPayload::from(*n),
             Value::BigDecimal(n) => asc_new(heap, n, gas)?.into(),
             Value::Bool(b) => EnumPayload::from(*b),
             Value::List(array) => asc_new(heap, array.as_slice(), gas)?.into(),
             Value::Null => EnumPayload(0),
-            Value::Bytes(bytes) => {
-                let bytes_obj: AscPtr<Uint8Array> = asc_new(heap, bytes.as_slice(), gas)?;
-                bytes_obj.into()
-            }
+            Value::Bytes(bytes) => direct::new_bytes(heap, bytes.as_slice(), gas)?.into(),
             Value::BigInt(big_int) => {
                 let bytes_obj: AscPtr<Uint8Array> =
                     asc_new(heap, &*big_int.to_signed_bytes_le(), gas)?;
@@ -331,6 +330,22 @@ impl ToAscObj<AscEntity> for Vec<(String, store::Value)> {
     }
 }
 
+impl ToAscObj<AscRelatedEntityPage> for RelatedEntityPage {
+    fn to_asc_obj<H: AscHeap + ?Sized>(
+        &self,
+        heap: &mut H,
+        gas: &GasCounter,
+    ) -> Result<AscRelatedEntityPage, DeterministicHostError> {
+        Ok(AscRelatedEntityPage {
+            entities: asc_new(heap, self.entities.as_slice(), gas)?,
+            next_token: match &self.next_token {
+                Some(token) => asc_new(heap, token.as_str(), gas)?,
+                None => AscPtr::null(),
+            },
+        })
+    }
+}
+
 impl ToAscObj<AscEnum<JsonValueKind>> for serde_json::Value {
     fn to_asc_obj<H: AscHeap + ?Sized>(
         &self,
@@ -118,6 +118,27 @@ where
         Ok(version)
     }
 
+    /// Whether the client asked for the indexing progress of the deployment
+    /// in the response, either with the header `X-GraphIndexingProgress:
+    /// true` or with the query parameter `indexing-progress=true`
+    fn indexing_progress_requested(request: &Request<Body>) -> bool {
+        let header = request
+            .headers()
+            .get("X-GraphIndexingProgress")
+            .map(|v| v.to_str().map(|s| s == "true").unwrap_or(false))
+            .unwrap_or(false);
+        let param = request
+            .uri()
+            .query()
+            .map(|query| {
+                query
+                    .split('&')
+                    .any(|pair| pair == "indexing-progress=true")
+            })
+            .unwrap_or(false);
+        header || param
+    }
+
     async fn handle_graphql_query_by_name(
         self,
         subgraph_name: String,
@@ -172,10 +193,14 @@ where
                     })
                     .unwrap_or(false)
         };
+        let indexing_progress = Self::indexing_progress_requested(&request);
         let body = hyper::body::to_bytes(request.into_body())
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = parse_graphql_request(&body, trace);
+        let query = parse_graphql_request(&body, trace).map(|mut query| {
+            query.indexing_progress = indexing_progress;
+            query
+        });
         let query_parsing_time = start.elapsed();
 
         let result = match query {
@@ -200,7 +225,10 @@ where
             Ok(Response::builder()
                 .status(200)
                 .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
+                .header(
+                    ACCESS_CONTROL_ALLOW_HEADERS,
+                    "Content-Type, User-Agent, X-GraphIndexingProgress",
+                )
                 .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
                 .header(CONTENT_TYPE, "text/html")
                 .body(Body::from(""))
@@ -7,6 +7,9 @@ use web3::types::Address;
 
 use graph::blockchain::{Blockchain, BlockchainKind, BlockchainMap};
 use graph::components::store::{BlockStore, EntityType, Store};
+use graph::components::subgraph::handler_stats::handler_stats_report;
+use graph::components::subgraph::mapping_terminations::termination_counts;
+use graph::components::subgraph::wasm_performance::wasm_performance_summary;
 use graph::components::versions::VERSIONS;
 use graph::data::graphql::{object, IntoValue, ObjectOrInterface, ValueMap};
 use graph::data::subgraph::features::detect_features;
@@ -88,6 +91,52 @@ impl<S: Store> IndexNodeResolver<S> {
         }
     }
 
+    /// The statuses from the store, with the mapping terminations, WASM
+    /// performance, and current handler statistics of the deployments that
+    /// this node runs
+    fn statuses(&self, filter: status::Filter) -> Result<Vec<status::Info>, QueryExecutionError> {
+        let infos = self.store.status(filter)?;
+        Ok(infos
+            .into_iter()
+            .map(|info| {
+                let reorg_threshold = self.effective_reorg_threshold(&info);
+                let mapping_terminations = termination_counts(&info.subgraph)
+                    .into_iter()
+                    .map(|(reason, count)| status::MappingTerminationCount { reason, count })
+                    .collect();
+                let wasm_performance = wasm_performance_summary(&info.subgraph);
+                let handler_stats =
+                    handler_stats_report(&info.subgraph).unwrap_or(info.handler_stats);
+                status::Info {
+                    mapping_terminations,
+                    wasm_performance,
+                    handler_stats,
+                    reorg_threshold,
+                    ..info
+                }
+            })
+            .collect())
+    }
+
+    /// The reorg threshold that the deployment of `info` uses: the one it
+    /// set for itself, but never more than the one of its chain. For
+    /// chains that this node does not know, that is just what the
+    /// deployment set for itself
+    fn effective_reorg_threshold(&self, info: &status::Info) -> Option<BlockNumber> {
+        let chain = info.chains.first().and_then(|chain| {
+            self.blockchain_map
+                .get::<graph_chain_ethereum::Chain>(chain.network.clone())
+                .ok()
+        });
+        match chain.and_then(|chain| chain.reorg_threshold_range()) {
+            Some(range) => Some(
+                info.reorg_threshold
+                    .map_or(*range.end(), |threshold| threshold.min(*range.end())),
+            ),
+            None => info.reorg_threshold,
+        }
+    }
+
     fn resolve_indexing_statuses(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
         let deployments = field
             .argument_value("subgraphs")
@@ -103,9 +152,7 @@ impl<S: Store> IndexNodeResolver<S> {
             })
             .unwrap_or_else(Vec::new);
 
-        let infos = self
-            .store
-            .status(status::Filter::Deployments(deployments))?;
+        let infos = self.statuses(status::Filter::Deployments(deployments))?;
         Ok(infos.into_value())
     }
 
@@ -126,13 +173,41 @@ impl<S: Store> IndexNodeResolver<S> {
             "name" => &subgraph_name
         );
 
-        let infos = self
-            .store
-            .status(status::Filter::SubgraphName(subgraph_name))?;
+        let infos = self.statuses(status::Filter::SubgraphName(subgraph_name))?;
 
         Ok(infos.into_value())
     }
 
+    fn resolve_usage(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let deployments = field
+            .argument_value("subgraphs")
+            .map(|value| match value {
+                r::Value::List(ids) => ids
+                    .iter()
+                    .map(|id| match id {
+                        r::Value::String(s) => s.clone(),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+                _ => unreachable!(),
+            })
+            .unwrap_or_else(Vec::new);
+
+        let timestamp = |name: &str| {
+            let value = field
+                .get_required::<String>(name)
+                .expect("timestamp not provided");
+            chrono::DateTime::parse_from_rfc3339(&value)
+                .map(|ts| ts.with_timezone(&chrono::Utc))
+                .map_err(|e| QueryExecutionError::ValueParseError(name.to_string(), e.to_string()))
+        };
+        let from = timestamp("from")?;
+        let to = timestamp("to")?;
+
+        let usage = self.store.usage(deployments, from, to)?;
+        Ok(usage.into_value())
+    }
+
     fn resolve_entity_changes_in_block(
         &self,
         field: &a::Field,
@@ -153,6 +228,44 @@ impl<S: Store> IndexNodeResolver<S> {
         Ok(entity_changes_to_graphql(entity_changes))
     }
 
+    fn resolve_entity_types(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let deployment = field
+            .get_required::<DeploymentHash>("subgraph")
+            .map_err(|e| {
+                QueryExecutionError::ValueParseError("subgraph".to_string(), e.to_string())
+            })?;
+        let exact = field
+            .get_optional::<bool>("exact")
+            .expect("Invalid exact")
+            .unwrap_or(false);
+
+        let stats = self
+            .store
+            .subgraph_store()
+            .entity_type_stats(&deployment, exact)?;
+        Ok(stats.into_value())
+    }
+
+    fn resolve_block_providers(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let deployment = field
+            .get_required::<DeploymentHash>("subgraph")
+            .map_err(|e| {
+                QueryExecutionError::ValueParseError("subgraph".to_string(), e.to_string())
+            })?;
+        let from = field
+            .get_required::<BlockNumber>("fromBlock")
+            .expect("Valid fromBlock required");
+        let to = field
+            .get_required::<BlockNumber>("toBlock")
+            .expect("Valid toBlock required");
+
+        let providers = self
+            .store
+            .subgraph_store()
+            .block_providers(&deployment, from, to)?;
+        Ok(providers.into_value())
+    }
+
     fn resolve_block_data(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
         let network = field
             .get_required::<String>("network")
@@ -357,6 +470,39 @@ impl<S: Store> IndexNodeResolver<S> {
         ))
     }
 
+    fn resolve_provider_rankings(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let network = field
+            .get_required::<String>("network")
+            .expect("Valid network required");
+
+        let chain = match self
+            .blockchain_map
+            .get::<graph_chain_ethereum::Chain>(network.clone())
+        {
+            Ok(chain) => chain,
+            Err(_) => return Ok(r::Value::List(vec![])),
+        };
+
+        Ok(r::Value::List(
+            chain
+                .provider_rankings()
+                .into_iter()
+                .map(|ranking| {
+                    object! {
+                        __typename: "ProviderRanking",
+                        class: ranking.class.as_str(),
+                        rank: ranking.rank as i32,
+                        provider: ranking.provider,
+                        capabilities: ranking.capabilities.to_string(),
+                        latency: ranking.latency,
+                        failing: ranking.failing,
+                        pinned: ranking.pinned,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
     fn resolve_proof_of_indexing(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
         let deployment_id = field
             .get_required::<DeploymentHash>("subgraph")
@@ -475,7 +621,7 @@ impl<S: Store> IndexNodeResolver<S> {
             "current_version" => current_version,
         );
 
-        let infos = self.store.status(status::Filter::SubgraphVersion(
+        let infos = self.statuses(status::Filter::SubgraphVersion(
             subgraph_name,
             current_version,
         ))?;
@@ -844,6 +990,10 @@ impl<S: Store> Resolver for IndexNodeResolver<S> {
             (None, "CachedEthereumCall", "cachedEthereumCalls") => {
                 self.resolve_cached_ethereum_calls(field).await
             }
+            (None, "DeploymentUsage", "usage") => self.resolve_usage(field),
+            (None, "EntityTypeStats", "entityTypes") => self.resolve_entity_types(field),
+            (None, "BlockProvider", "blockProviders") => self.resolve_block_providers(field),
+            (None, "ProviderRanking", "providerRankings") => self.resolve_provider_rankings(field),
 
             // The top-level `publicProofsOfIndexing` field
             (None, "PublicProofOfIndexingResult", "publicProofsOfIndexing") => {
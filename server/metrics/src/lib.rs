@@ -10,6 +10,10 @@ use thiserror::Error;
 use graph::prelude::*;
 use graph::prometheus::{Encoder, Registry, TextEncoder};
 
+mod push;
+
+pub use push::PrometheusPushGateway;
+
 /// Errors that may occur when starting the server.
 #[derive(Debug, Error)]
 pub enum PrometheusMetricsServeError {
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::prelude::*;
+
+/// The job label of the metrics that the node pushes
+const JOB: &str = "graph-node";
+
+/// Pushes the metrics of the node to a Prometheus push gateway. This is
+/// meant for nodes that do not live long enough to be scraped reliably;
+/// pushing can be used together with the metrics server.
+///
+/// Metrics are grouped by the job `graph-node` and an instance label that
+/// is the node ID, so that every node replaces its own metrics in the
+/// gateway with each push.
+#[derive(Clone)]
+pub struct PrometheusPushGateway {
+    logger: Logger,
+    registry: Arc<Registry>,
+    url: String,
+    instance: String,
+    interval: Duration,
+    failures: Counter,
+}
+
+impl PrometheusPushGateway {
+    /// Create a gateway that pushes every `interval`, which must not be
+    /// zero
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        registry: Arc<Registry>,
+        metrics_registry: Arc<dyn MetricsRegistry>,
+        node_id: &NodeId,
+        url: String,
+        interval: Duration,
+    ) -> Self {
+        assert!(!interval.is_zero(), "the push interval must not be zero");
+        let failures = metrics_registry
+            .global_counter(
+                "metrics_push_failures",
+                "The number of failed attempts to push metrics to the push gateway",
+                HashMap::new(),
+            )
+            .expect("failed to create `metrics_push_failures` counter");
+        PrometheusPushGateway {
+            logger: logger_factory.component_logger("MetricsPushGateway", None),
+            registry,
+            url,
+            instance: node_id.to_string(),
+            interval,
+            failures,
+        }
+    }
+
+    /// Push the metrics every `interval`. The returned future never
+    /// finishes
+    pub async fn run(self) {
+        info!(
+            self.logger,
+            "Pushing metrics to {} every {}s",
+            self.url,
+            self.interval.as_secs()
+        );
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.push().await;
+        }
+    }
+
+    /// Push the current metrics once. Failures are logged and counted,
+    /// but otherwise ignored
+    pub async fn push(&self) {
+        let metric_families = self.registry.gather();
+        let url = self.url.clone();
+        let grouping = HashMap::from([("instance".to_string(), self.instance.clone())]);
+        let res = graph::spawn_blocking_allow_panic(move || {
+            prometheus::push_metrics(JOB, grouping, &url, metric_families, None)
+        })
+        .await;
+
+        let error = match res {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        self.failures.inc();
+        warn!(self.logger, "Failed to push metrics to the push gateway";
+                           "url" => &self.url,
+                           "error" => error);
+    }
+}
@@ -0,0 +1,14 @@
+create table if not exists subgraphs.subgraph_deployment_node_versions (
+    vid bigserial primary key,
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    graph_node_version_id integer not null
+        references subgraphs.graph_node_versions(id),
+    host_export_hash text not null,
+    start_block_number integer,
+    end_block_number integer,
+    started_at timestamptz not null default now()
+);
+
+create index if not exists subgraph_deployment_node_versions_id
+    on subgraphs.subgraph_deployment_node_versions(id, vid);
@@ -0,0 +1,6 @@
+alter table subgraphs.copy_table_state
+      drop column src_count,
+      drop column dst_count,
+      drop column src_checksum,
+      drop column dst_checksum,
+      drop column checked_at;
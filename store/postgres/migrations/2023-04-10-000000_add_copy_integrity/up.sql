@@ -0,0 +1,9 @@
+-- The result of comparing the entities in the source and destination of
+-- a copy once all data has been copied; these are null until the check
+-- for a table has run
+alter table subgraphs.copy_table_state
+      add column src_count    int8,
+      add column dst_count    int8,
+      add column src_checksum text,
+      add column dst_checksum text,
+      add column checked_at   timestamptz;
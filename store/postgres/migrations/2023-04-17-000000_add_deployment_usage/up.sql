@@ -0,0 +1,14 @@
+-- How much of the infrastructure that is shared between deployments, like
+-- RPC providers or IPFS, each deployment used, aggregated by hour
+create table if not exists subgraphs.deployment_usage (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    -- The start of the hour
+    bucket timestamptz not null,
+    resource text not null,
+    amount int8 not null,
+    primary key(id, bucket, resource)
+);
+
+create index if not exists deployment_usage_bucket
+    on subgraphs.deployment_usage(bucket);
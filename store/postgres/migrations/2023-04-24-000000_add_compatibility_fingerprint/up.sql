@@ -0,0 +1,19 @@
+-- The compatibility fingerprint of each deployment, i.e., a hash over the
+-- settings of graph-node that affect the results of indexing, recorded
+-- when the deployment was first started or when a change was last
+-- acknowledged
+create table if not exists subgraphs.compatibility_fingerprint (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    fingerprint text not null,
+    -- The settings that went into the fingerprint, one `setting=value`
+    -- per line
+    description text not null,
+    recorded_at timestamptz not null default now(),
+    -- The fingerprint the deployment was last started with if it differs
+    -- from `fingerprint`; cleared when the change is acknowledged
+    mismatch text,
+    mismatch_description text,
+    acknowledged_at timestamptz,
+    acknowledged_note text
+);
@@ -0,0 +1 @@
+drop table if exists subgraphs.handler_entity_types;
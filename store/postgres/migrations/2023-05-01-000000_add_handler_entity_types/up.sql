@@ -0,0 +1,12 @@
+-- The entity types that each handler of a deployment was observed writing.
+-- Handlers are identified by their data source, too, since handlers of
+-- different data sources can have the same name
+create table if not exists subgraphs.handler_entity_types (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    data_source text not null,
+    handler text not null,
+    entity_type text not null,
+    first_seen_at timestamptz not null default now(),
+    primary key(id, data_source, handler, entity_type)
+);
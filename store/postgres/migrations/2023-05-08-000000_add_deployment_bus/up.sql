@@ -0,0 +1,8 @@
+-- The bus backends that a deployment publishes to, as of the last time it
+-- was started
+create table if not exists subgraphs.deployment_bus (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    backends text[] not null,
+    recorded_at timestamptz not null default now()
+);
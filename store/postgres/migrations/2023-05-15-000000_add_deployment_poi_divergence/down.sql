@@ -0,0 +1 @@
+drop table if exists subgraphs.deployment_poi_divergence;
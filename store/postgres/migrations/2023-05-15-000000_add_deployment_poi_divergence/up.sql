@@ -0,0 +1,10 @@
+-- The earliest block at which the PoI of a deployment differed from the
+-- PoI that a quorum of reference indexers reported
+create table if not exists subgraphs.deployment_poi_divergence (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    block_number integer not null,
+    local_poi bytea not null,
+    reference_poi bytea not null,
+    detected_at timestamptz not null default now()
+);
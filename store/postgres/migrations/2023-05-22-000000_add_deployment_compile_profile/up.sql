@@ -0,0 +1,12 @@
+-- The profile with which the WASM modules of a deployment are compiled,
+-- and the profile they were last compiled with
+create table if not exists subgraphs.deployment_compile_profile (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    -- Set with graphman; null if the deployment uses the default of the
+    -- node that runs it
+    profile text,
+    compiled_profile text,
+    compile_time_ms bigint,
+    compiled_at timestamptz
+);
@@ -0,0 +1 @@
+alter table subgraphs.subgraph_error drop column if exists code;
@@ -0,0 +1,3 @@
+-- A stable code that classifies the error; errors recorded before codes
+-- existed have none
+alter table subgraphs.subgraph_error add column if not exists code text;
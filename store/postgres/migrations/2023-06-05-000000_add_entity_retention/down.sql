@@ -0,0 +1 @@
+drop table if exists subgraphs.entity_retention;
@@ -0,0 +1,19 @@
+-- Retention policies for entity types, declared in the manifest or set
+-- with graphman
+create table if not exists subgraphs.entity_retention (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    entity_type text not null,
+    -- Either `timestamp_field` and `max_age_secs` or `max_blocks` are set
+    timestamp_field text,
+    max_age_secs bigint,
+    max_blocks integer,
+    -- Policies are only applied once the deployment was started with them
+    -- so that what its mappings can load does not change while it runs
+    active boolean not null default false,
+    primary key (id, entity_type),
+    check ((timestamp_field is not null and max_age_secs is not null
+            and max_blocks is null)
+        or (timestamp_field is null and max_age_secs is null
+            and max_blocks is not null))
+);
@@ -0,0 +1,9 @@
+-- When each index node was last started and stopped, so that a node can
+-- tell at startup whether its previous run crashed
+create table if not exists public.node_status (
+    node_id text primary key,
+    started_at timestamptz not null,
+    -- Null while the node runs, and after it stopped without a clean
+    -- shutdown
+    stopped_at timestamptz
+);
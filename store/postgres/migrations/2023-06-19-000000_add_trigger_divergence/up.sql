@@ -0,0 +1,17 @@
+-- Differences between the triggers that two providers returned for the
+-- same block, found by sampling blocks with
+-- GRAPH_ETHEREUM_TRIGGER_VERIFICATION
+create table if not exists public.trigger_divergence (
+    id serial primary key,
+    network text not null,
+    block_number int4 not null,
+    block_hash bytea not null,
+    primary_provider text not null,
+    secondary_provider text not null,
+    kind text not null,
+    details text not null,
+    detected_at timestamptz not null default now()
+);
+
+create index if not exists trigger_divergence_network_block
+    on public.trigger_divergence(network, block_number);
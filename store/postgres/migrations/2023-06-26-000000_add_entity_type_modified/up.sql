@@ -0,0 +1,9 @@
+-- The highest block at which the deployment inserted, updated or removed
+-- an entity of each entity type
+create table if not exists subgraphs.entity_type_modified (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    entity_type text not null,
+    block_number int4 not null,
+    primary key(id, entity_type)
+);
@@ -0,0 +1,2 @@
+drop table if exists subgraphs.invariant_aggregate;
+drop table if exists subgraphs.invariant_violation;
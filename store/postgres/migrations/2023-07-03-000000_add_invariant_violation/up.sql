@@ -0,0 +1,25 @@
+-- The invariants from the manifest of a deployment that are currently
+-- violated; `first_block` is where the current violation started
+create table if not exists subgraphs.invariant_violation (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    invariant text not null,
+    check_expr text not null,
+    first_block int4 not null,
+    last_block int4 not null,
+    message text not null,
+    primary key(id, invariant)
+);
+
+-- The aggregates that the invariants of a deployment use, like
+-- `sum(Account.balance)`, as of `block_number`. They are updated with the
+-- changes of each block; `value` is null for `min` and `max` of an entity
+-- type without entities
+create table if not exists subgraphs.invariant_aggregate (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    term text not null,
+    block_number int4 not null,
+    value numeric,
+    primary key(id, term, block_number)
+);
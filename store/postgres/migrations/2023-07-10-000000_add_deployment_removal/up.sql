@@ -0,0 +1,15 @@
+-- Deployments whose data is being removed one table at a time. A row is
+-- added when the removal starts and deleted once all data and metadata
+-- of the deployment are gone; `id` is the former deployment_schemas.id
+create table if not exists public.deployment_removal (
+    id integer primary key,
+    deployment text not null,
+    shard text not null,
+    namespace text not null,
+    started_at timestamptz not null default now(),
+    updated_at timestamptz not null default now(),
+    tables_dropped int4 not null default 0
+);
+
+create index if not exists deployment_removal_deployment
+    on public.deployment_removal(deployment);
@@ -0,0 +1,7 @@
+-- A reorg threshold for a deployment that is lower than the one of its
+-- chain, declared in the manifest or set with graphman
+create table if not exists subgraphs.deployment_reorg_threshold (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    reorg_threshold integer not null check (reorg_threshold >= 0)
+);
@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_error drop column if exists provider;
+drop table if exists subgraphs.deployment_block_provider;
+drop table if exists subgraphs.block_provider;
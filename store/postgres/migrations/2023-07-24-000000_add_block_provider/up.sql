@@ -0,0 +1,22 @@
+-- The providers that served blocks to deployments in this shard. Their
+-- ids are stored instead of the labels, and must therefore never change
+create table if not exists subgraphs.block_provider (
+    id smallserial primary key,
+    label text not null unique
+);
+
+-- Which provider served each of the most recent blocks of a deployment.
+-- The table is a ring buffer: a block is stored in the slot given by its
+-- number modulo the number of blocks that are kept
+create table if not exists subgraphs.deployment_block_provider (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    slot int4 not null,
+    block_number int4 not null,
+    provider smallint not null
+        references subgraphs.block_provider(id),
+    primary key(id, slot)
+);
+
+-- The provider that served the block at which an error happened
+alter table subgraphs.subgraph_error add column if not exists provider text;
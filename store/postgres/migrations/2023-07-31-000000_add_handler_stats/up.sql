@@ -0,0 +1,14 @@
+-- The cumulative statistics of each handler of a deployment since the
+-- statistics were last reset
+create table if not exists subgraphs.deployment_handler_stats (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    handler text not null,
+    invocations int8 not null,
+    execution_us int8 not null,
+    gas int8 not null,
+    errors int8 not null,
+    -- When the statistics for the handler were first recorded
+    since timestamptz not null default now(),
+    primary key(id, handler)
+);
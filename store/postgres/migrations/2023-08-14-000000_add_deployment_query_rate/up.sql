@@ -0,0 +1,10 @@
+-- The rate of queries that each query node serves for a deployment, so
+-- that index nodes, which usually serve no queries, can tell which
+-- deployments users query. Each node replaces its own rows periodically
+create table if not exists public.deployment_query_rate (
+    deployment text not null,
+    node_id text not null,
+    rate float8 not null,
+    updated_at timestamptz not null default now(),
+    primary key (deployment, node_id)
+);
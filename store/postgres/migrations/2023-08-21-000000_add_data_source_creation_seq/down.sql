@@ -0,0 +1,15 @@
+-- remove creation_seq column from data_sources$ table for each subgraph deployment
+do $$
+declare
+  deployments cursor for
+     select t.table_schema as sgd
+       from information_schema.columns t
+      where t.table_schema like 'sgd%'
+        and t.table_name = 'data_sources$'
+        and t.column_name = 'creation_seq';
+begin
+  for d in deployments loop
+    execute 'alter table ' || d.sgd || '.data_sources$ drop column creation_seq';
+  end loop;
+end;
+$$;
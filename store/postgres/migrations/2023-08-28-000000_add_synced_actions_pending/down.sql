@@ -0,0 +1,2 @@
+drop table if exists subgraphs.deployment_label;
+drop table if exists subgraphs.synced_actions_pending;
@@ -0,0 +1,21 @@
+-- Deployments that became synced and whose synced actions have not run to
+-- completion yet
+create table if not exists subgraphs.synced_actions_pending (
+    id integer primary key
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    -- The block at which the deployment became synced
+    block_hash bytea not null,
+    block_number integer not null,
+    -- When the deployment became synced
+    synced_at timestamptz not null default now(),
+    -- The keys of the actions that ran to completion
+    done text[] not null default '{}'
+);
+
+-- Labels that synced actions or graphman put on a deployment
+create table if not exists subgraphs.deployment_label (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    label text not null,
+    primary key (id, label)
+);
@@ -0,0 +1,2 @@
+drop table if exists subgraphs.deployment_bus_critical_delivery;
+drop table if exists subgraphs.deployment_bus_critical;
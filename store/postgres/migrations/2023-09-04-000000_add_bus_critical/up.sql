@@ -0,0 +1,24 @@
+-- The entity types of a deployment whose delivery to the bus needs to be
+-- verifiable, set with graphman
+create table if not exists subgraphs.deployment_bus_critical (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    entity_type text not null,
+    primary key (id, entity_type)
+);
+
+-- Up to which block the changes of a deployment to each critical entity
+-- type were delivered to each bus backend
+create table if not exists subgraphs.deployment_bus_critical_delivery (
+    id integer not null
+        references subgraphs.subgraph_deployment(id) on delete cascade,
+    entity_type text not null,
+    backend text not null,
+    -- The broker confirmed all changes to the type up to this block
+    confirmed_block integer,
+    -- The earliest block whose changes to the type were dead-lettered and
+    -- not acknowledged yet; `confirmed_block` does not advance while this
+    -- is set
+    dead_lettered_block integer,
+    primary key (id, entity_type, backend)
+);
@@ -1,4 +1,4 @@
-use diesel::sql_types::{Bool, Integer};
+use diesel::sql_types::{BigInt, Bool, Integer};
 use diesel::{connection::SimpleConnection, prelude::RunQueryDsl, select};
 use diesel::{insert_into, OptionalExtension};
 use diesel::{pg::PgConnection, sql_query};
@@ -706,6 +706,63 @@ pub fn stats(conn: &PgConnection, namespace: &Namespace) -> Result<Vec<VersionSt
     Ok(stats.into_iter().map(|s| s.into()).collect())
 }
 
+/// Return Postgres' estimate of the number of rows (`pg_class.reltuples`)
+/// for each table in `namespace`, keyed by table name. Tables that were
+/// never analyzed are reported as empty
+pub fn estimated_rows(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<HashMap<String, u64>, StoreError> {
+    #[derive(Queryable, QueryableByName)]
+    struct Estimate {
+        #[sql_type = "Text"]
+        tablename: String,
+        #[sql_type = "BigInt"]
+        rows: i64,
+    }
+
+    let query = "select c.relname as tablename, greatest(c.reltuples, 0)::int8 as rows
+                   from pg_namespace n, pg_class c
+                  where n.nspname = $1
+                    and c.relnamespace = n.oid
+                    and c.relkind = 'r'";
+
+    let estimates = sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<Estimate>(conn)?;
+
+    Ok(estimates
+        .into_iter()
+        .map(|e| (e.tablename, e.rows as u64))
+        .collect())
+}
+
+/// Return the names of all tables in `namespace`, ordered by name
+pub(crate) fn table_names(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(Queryable, QueryableByName)]
+    struct TableName {
+        #[sql_type = "Text"]
+        tablename: String,
+    }
+
+    let query = "select c.relname as tablename
+                   from pg_namespace n, pg_class c
+                  where n.nspname = $1
+                    and c.relnamespace = n.oid
+                    and c.relkind = 'r'
+                  order by c.relname";
+
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<TableName>(conn)?
+        .into_iter()
+        .map(|t| t.tablename)
+        .collect())
+}
+
 /// Return by how much the slowest replica connected to the database `conn`
 /// is lagging. The returned value has millisecond precision. If the
 /// database has no replicas, return `0`
@@ -41,6 +41,19 @@ mod public {
             head_block_cursor -> Nullable<Varchar>,
         }
     }
+
+    table! {
+        trigger_divergence (id) {
+            id -> Integer,
+            network -> Text,
+            block_number -> Integer,
+            block_hash -> Binary,
+            primary_provider -> Text,
+            secondary_provider -> Text,
+            kind -> Text,
+            details -> Text,
+        }
+    }
 }
 
 pub use data::Storage;
@@ -1839,6 +1852,36 @@ impl ChainStoreTrait for ChainStore {
         })
         .await
     }
+
+    async fn record_trigger_divergence(
+        &self,
+        block: &BlockPtr,
+        primary: &str,
+        secondary: &str,
+        kind: &str,
+        details: &str,
+    ) -> Result<(), StoreError> {
+        use public::trigger_divergence as d;
+
+        let values = (
+            d::network.eq(self.chain.clone()),
+            d::block_number.eq(block.number),
+            d::block_hash.eq(block.hash_slice().to_vec()),
+            d::primary_provider.eq(primary.to_string()),
+            d::secondary_provider.eq(secondary.to_string()),
+            d::kind.eq(kind.to_string()),
+            d::details.eq(details.to_string()),
+        );
+        self.pool
+            .with_conn(move |conn, _| {
+                insert_into(d::table)
+                    .values(values)
+                    .execute(conn)
+                    .map_err(|e| StoreError::from(e).into())
+            })
+            .await?;
+        Ok(())
+    }
 }
 
 mod recent_blocks_cache {
@@ -279,21 +279,28 @@ impl PoolName {
 #[derive(Clone)]
 struct PoolStateTracker {
     available: Arc<AtomicBool>,
+    /// Mirrors `available` as a metric: 1 if the database is reachable,
+    /// 0 if it is not
+    reachable_gauge: Gauge,
 }
 
 impl PoolStateTracker {
-    fn new() -> Self {
+    fn new(reachable_gauge: Gauge) -> Self {
+        reachable_gauge.set(1.0);
         Self {
             available: Arc::new(AtomicBool::new(true)),
+            reachable_gauge,
         }
     }
 
     fn mark_available(&self) {
         self.available.store(true, Ordering::Relaxed);
+        self.reachable_gauge.set(1.0);
     }
 
     fn mark_unavailable(&self) {
         self.available.store(false, Ordering::Relaxed);
+        self.reachable_gauge.set(0.0);
     }
 
     fn is_available(&self) -> bool {
@@ -312,9 +319,22 @@ impl ConnectionPool {
         registry: Arc<dyn MetricsRegistry>,
         coord: Arc<PoolCoordinator>,
     ) -> ConnectionPool {
-        let state_tracker = PoolStateTracker::new();
         let shard =
             Shard::new(shard_name.to_string()).expect("shard_name is a valid name for a shard");
+        let const_labels = {
+            let mut map = HashMap::new();
+            map.insert("pool".to_owned(), pool_name.as_str().to_owned());
+            map.insert("shard".to_string(), shard.to_string());
+            map
+        };
+        let reachable_gauge = registry
+            .global_gauge(
+                "store_reachable",
+                "Whether the database is reachable (1) or not (0)",
+                const_labels,
+            )
+            .expect("failed to create `store_reachable` gauge");
+        let state_tracker = PoolStateTracker::new(reachable_gauge);
         let pool_state = {
             if pool_size == 0 {
                 PoolState::Disabled
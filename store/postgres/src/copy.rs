@@ -11,6 +11,11 @@
 //! `subgraphs.copy_state` and `subgraphs.copy_table_state` so that a copy
 //! operation can resume after an interruption, for example, because
 //! `graph-node` was restarted while the copy was running.
+//!
+//! Once all data has been copied, the number of entities of each type that
+//! are visible at the target block is compared between source and
+//! destination, and the result is also recorded in
+//! `subgraphs.copy_table_state`.
 use std::{
     convert::TryFrom,
     io::Write,
@@ -26,7 +31,7 @@ use diesel::{
     select,
     serialize::Output,
     sql_query,
-    sql_types::{BigInt, Integer},
+    sql_types::{BigInt, Integer, Nullable, Text},
     types::{FromSql, ToSql},
     update, Connection as _, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
     RunQueryDsl,
@@ -34,7 +39,8 @@ use diesel::{
 use graph::{
     components::store::EntityType,
     constraint_violation,
-    prelude::{info, o, warn, BlockNumber, BlockPtr, Logger, StoreError, ENV_VARS},
+    data::subgraph::schema::{SubgraphError, SubgraphErrorCode},
+    prelude::{anyhow::anyhow, info, o, warn, BlockNumber, BlockPtr, Logger, StoreError, ENV_VARS},
 };
 
 use crate::{
@@ -92,6 +98,12 @@ table! {
         // Measures just the time we spent working, not any wait time for
         // connections or the like
         duration_ms -> BigInt,
+        // The results of the integrity check, null until the check has run
+        src_count -> Nullable<BigInt>,
+        dst_count -> Nullable<BigInt>,
+        src_checksum -> Nullable<Text>,
+        dst_checksum -> Nullable<Text>,
+        checked_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -383,10 +395,73 @@ impl BatchCopy {
     }
 }
 
+/// The result of comparing the entities of one type that are visible at
+/// the target block in the source and the destination of a copy
+struct TableIntegrity {
+    src_count: i64,
+    dst_count: i64,
+    src_checksum: Option<String>,
+    dst_checksum: Option<String>,
+}
+
+impl TableIntegrity {
+    /// The number of entities that are missing from or extra in the
+    /// destination. When the counts agree but the checksums do not, we
+    /// know that at least one entity is wrong
+    fn discrepancy(&self) -> u64 {
+        let diff = (self.src_count - self.dst_count).unsigned_abs();
+        if diff == 0 && self.src_checksum != self.dst_checksum {
+            1
+        } else {
+            diff
+        }
+    }
+
+    /// Count the entities in `table` that are visible at `block`, and if
+    /// `checksum` is set, compute a checksum over their ids
+    fn visible(
+        conn: &PgConnection,
+        table: &Table,
+        block: BlockNumber,
+        checksum: bool,
+    ) -> Result<(i64, Option<String>), StoreError> {
+        #[derive(QueryableByName)]
+        struct Visible {
+            #[sql_type = "BigInt"]
+            count: i64,
+            #[sql_type = "Nullable<Text>"]
+            checksum: Option<String>,
+        }
+
+        let visible_clause = if table.immutable {
+            "block$ <= $1"
+        } else {
+            "block_range @> $1"
+        };
+        let checksum = if checksum {
+            "md5(coalesce(string_agg(id::text, ',' order by id), ''))"
+        } else {
+            "null::text"
+        };
+        let visible = sql_query(&format!(
+            "select count(*) as count, {} as checksum from {} where {}",
+            checksum,
+            table.qualified_name.as_str(),
+            visible_clause
+        ))
+        .bind::<Integer, _>(block)
+        .get_result::<Visible>(conn)?;
+        Ok((visible.count, visible.checksum))
+    }
+}
+
 struct TableState {
     batch: BatchCopy,
     dst_site: Arc<Site>,
     duration_ms: i64,
+    /// The result of the integrity check, `None` if the check has not run
+    /// yet for this table
+    integrity: Option<TableIntegrity>,
 }
 
 impl TableState {
@@ -423,6 +498,7 @@ impl TableState {
             batch: BatchCopy::new(src, dst, 0, target_vid),
             dst_site,
             duration_ms: 0,
+            integrity: None,
         })
     }
 
@@ -459,6 +535,20 @@ impl TableState {
                 .map(|table| table.clone())
         }
 
+        type Row = (
+            i32,
+            String,
+            i64,
+            i64,
+            i64,
+            i64,
+            Option<i64>,
+            Option<i64>,
+            Option<String>,
+            Option<String>,
+            bool,
+        );
+
         cts::table
             .filter(cts::dst.eq(dst_layout.site.id))
             .select((
@@ -468,12 +558,29 @@ impl TableState {
                 cts::target_vid,
                 cts::batch_size,
                 cts::duration_ms,
+                cts::src_count,
+                cts::dst_count,
+                cts::src_checksum,
+                cts::dst_checksum,
+                cts::checked_at.is_not_null(),
             ))
             .order_by(cts::entity_type)
-            .load::<(i32, String, i64, i64, i64, i64)>(conn)?
+            .load::<Row>(conn)?
             .into_iter()
             .map(
-                |(id, entity_type, current_vid, target_vid, size, duration_ms)| {
+                |(
+                    id,
+                    entity_type,
+                    current_vid,
+                    target_vid,
+                    size,
+                    duration_ms,
+                    src_count,
+                    dst_count,
+                    src_checksum,
+                    dst_checksum,
+                    checked,
+                )| {
                     let entity_type = EntityType::new(entity_type);
                     let src =
                         resolve_entity(src_layout, "source", &entity_type, dst_layout.site.id, id);
@@ -491,10 +598,22 @@ impl TableState {
 
                             batch.batch_size = batch_size;
 
+                            let integrity = if checked {
+                                Some(TableIntegrity {
+                                    src_count: src_count.unwrap_or(0),
+                                    dst_count: dst_count.unwrap_or(0),
+                                    src_checksum,
+                                    dst_checksum,
+                                })
+                            } else {
+                                None
+                            };
+
                             Ok(TableState {
                                 batch,
                                 dst_site: dst_layout.site.clone(),
                                 duration_ms,
+                                integrity,
                             })
                         }
                         (Err(e), _) => Err(e),
@@ -556,6 +675,43 @@ impl TableState {
         Ok(())
     }
 
+    /// Compare the entities visible at `block` in the source and the
+    /// destination table and record the result
+    fn check_integrity(
+        &mut self,
+        conn: &PgConnection,
+        block: BlockNumber,
+        checksum: bool,
+    ) -> Result<&TableIntegrity, StoreError> {
+        use copy_table_state as cts;
+
+        let (src_count, src_checksum) =
+            TableIntegrity::visible(conn, &self.batch.src, block, checksum)?;
+        let (dst_count, dst_checksum) =
+            TableIntegrity::visible(conn, &self.batch.dst, block, checksum)?;
+
+        update(
+            cts::table
+                .filter(cts::dst.eq(self.dst_site.id))
+                .filter(cts::entity_type.eq(self.batch.dst.object.as_str())),
+        )
+        .set((
+            cts::src_count.eq(src_count),
+            cts::dst_count.eq(dst_count),
+            cts::src_checksum.eq(&src_checksum),
+            cts::dst_checksum.eq(&dst_checksum),
+            cts::checked_at.eq(sql("now()")),
+        ))
+        .execute(conn)?;
+
+        Ok(self.integrity.insert(TableIntegrity {
+            src_count,
+            dst_count,
+            src_checksum,
+            dst_checksum,
+        }))
+    }
+
     fn is_cancelled(&self, conn: &PgConnection) -> Result<bool, StoreError> {
         use active_copies as ac;
 
@@ -742,6 +898,119 @@ impl Connection {
         Ok(())
     }
 
+    /// Pause if replication is lagging behind to avoid overloading
+    /// replicas
+    fn wait_for_replication(&self) -> Result<(), StoreError> {
+        let mut lag = catalog::replication_lag(&self.conn)?;
+        if lag > MAX_REPLICATION_LAG {
+            loop {
+                info!(&self.logger,
+                     "Replicas are lagging too much; pausing copying for {}s to allow them to catch up",
+                     REPLICATION_SLEEP.as_secs();
+                     "lag_s" => lag.as_secs());
+                std::thread::sleep(REPLICATION_SLEEP);
+                lag = catalog::replication_lag(&self.conn)?;
+                if lag <= ACCEPTABLE_REPLICATION_LAG {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare the number of entities of each type that are visible at
+    /// the target block in source and destination. Tables that were
+    /// checked before, e.g., before `graph-node` was restarted, are not
+    /// checked again. If the total discrepancy is above the configured
+    /// threshold, mark the destination as failed and return an error
+    fn check_integrity(&self, state: &mut CopyState) -> Result<Status, StoreError> {
+        if !ENV_VARS.store.copy_check_integrity {
+            return Ok(Status::Finished);
+        }
+
+        if let Some(max_versions) = ENV_VARS.store.copy_check_max_versions {
+            let versions: i64 = state
+                .tables
+                .iter()
+                .map(|table| table.batch.target_vid + 1)
+                .sum();
+            if versions > max_versions {
+                info!(&self.logger,
+                      "Skipping integrity check since the source has too many entity versions";
+                      "versions" => versions, "max_versions" => max_versions);
+                return Ok(Status::Finished);
+            }
+        }
+
+        let block = state.target_block.number;
+        let checksum = ENV_VARS.store.copy_check_checksums;
+        for table in state
+            .tables
+            .iter_mut()
+            .filter(|table| table.integrity.is_none())
+        {
+            if table.is_cancelled(&self.conn)? {
+                return Ok(Status::Cancelled);
+            }
+            self.wait_for_replication()?;
+
+            let entity_type = table.batch.dst.object.to_string();
+            let start = Instant::now();
+            let integrity = table.check_integrity(&self.conn, block, checksum)?;
+            if integrity.discrepancy() > 0 {
+                warn!(&self.logger, "Copied entities do not match the source";
+                      "entity_type" => entity_type,
+                      "src_count" => integrity.src_count,
+                      "dst_count" => integrity.dst_count,
+                      "time_ms" => start.elapsed().as_millis());
+            }
+
+            std::thread::sleep(ENV_VARS.store.copy_check_pause);
+        }
+
+        let discrepancy: u64 = state
+            .tables
+            .iter()
+            .filter_map(|table| table.integrity.as_ref())
+            .map(TableIntegrity::discrepancy)
+            .sum();
+        if discrepancy == 0 {
+            info!(
+                &self.logger,
+                "Integrity check found no differences between source and destination"
+            );
+            return Ok(Status::Finished);
+        }
+
+        match ENV_VARS.store.copy_check_fail_threshold {
+            Some(threshold) if discrepancy > threshold => {
+                let message = format!(
+                    "copying data from {} found {} entities that are missing or differ, \
+                     which is more than the allowed {}",
+                    state.src.site.deployment, discrepancy, threshold
+                );
+                let error = SubgraphError {
+                    subgraph_id: state.dst.site.deployment.clone(),
+                    message: message.clone(),
+                    block_ptr: None,
+                    handler: None,
+                    deterministic: false,
+                    code: SubgraphErrorCode::CopyFailed,
+                    provider: None,
+                };
+                self.transaction(|conn| {
+                    crate::deployment::fail(conn, &state.dst.site.deployment, &error)
+                })?;
+                Err(StoreError::Unknown(anyhow!(message)))
+            }
+            _ => {
+                warn!(&self.logger, "Integrity check found differences between source and destination";
+                      "discrepancy" => discrepancy);
+                Ok(Status::Finished)
+            }
+        }
+    }
+
     pub fn copy_data_internal(&self) -> Result<Status, StoreError> {
         let mut state = self.transaction(|conn| {
             CopyState::new(
@@ -764,22 +1033,7 @@ impl Connection {
                     return Ok(Status::Cancelled);
                 }
 
-                // Pause copying if replication is lagging behind to avoid
-                // overloading replicas
-                let mut lag = catalog::replication_lag(&self.conn)?;
-                if lag > MAX_REPLICATION_LAG {
-                    loop {
-                        info!(&self.logger,
-                             "Replicas are lagging too much; pausing copying for {}s to allow them to catch up",
-                             REPLICATION_SLEEP.as_secs();
-                             "lag_s" => lag.as_secs());
-                        std::thread::sleep(REPLICATION_SLEEP);
-                        lag = catalog::replication_lag(&self.conn)?;
-                        if lag <= ACCEPTABLE_REPLICATION_LAG {
-                            break;
-                        }
-                    }
-                }
+                self.wait_for_replication()?;
 
                 let status = self.transaction(|conn| table.copy_batch(conn))?;
                 if status == Status::Cancelled {
@@ -792,6 +1046,12 @@ impl Connection {
 
         self.copy_private_data_sources(&state)?;
 
+        // The integrity check needs access to the source tables, and has
+        // to happen before we finish, since that might remove them
+        if self.check_integrity(&mut state)? == Status::Cancelled {
+            return Ok(Status::Cancelled);
+        }
+
         self.transaction(|conn| state.finished(conn))?;
         progress.finished();
 
@@ -11,11 +11,15 @@ use diesel::{expression::SqlLiteral, pg::PgConnection, sql_types::Numeric};
 use diesel::{
     prelude::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl},
     sql_query,
-    sql_types::{Nullable, Text},
+    sql_types::{Array, BigInt, Binary, Nullable, Text},
 };
 use graph::{blockchain::block_stream::FirehoseCursor, data::subgraph::schema::SubgraphError};
 use graph::{
-    components::store::EntityType,
+    components::{
+        metrics::usage::Usage,
+        store::{EntityType, PendingSyncedActions},
+        subgraph::handler_entity_types::HandlerWrite,
+    },
     prelude::{
         anyhow, bigdecimal::ToPrimitive, hex, web3::types::H256, BigDecimal, BlockNumber, BlockPtr,
         DeploymentHash, DeploymentState, Schema, StoreError,
@@ -23,9 +27,13 @@ use graph::{
 };
 use graph::{
     data::subgraph::{
+        fingerprint::CompatibilityFingerprint,
+        retention::{EntityRetention, RetentionRule},
         schema::{DeploymentCreate, SubgraphManifestEntity},
+        status::{HandlerStats, PoiDivergence},
         SubgraphFeature,
     },
+    runtime::CompileProfile,
     util::backoff::ExponentialBackoff,
 };
 use stable_hash_legacy::crypto::SetHasher;
@@ -100,6 +108,8 @@ table! {
         handler -> Nullable<Text>,
         deterministic -> Bool,
         block_range -> Range<Integer>,
+        code -> Nullable<Text>,
+        provider -> Nullable<Text>,
     }
 }
 
@@ -136,7 +146,242 @@ table! {
     }
 }
 
+table! {
+    /// The versions of graph-node that a deployment was indexed with, in
+    /// the order in which they started indexing it
+    subgraphs.subgraph_deployment_node_versions (vid) {
+        vid -> BigInt,
+        // subgraph_deployment.id
+        id -> Integer,
+        graph_node_version_id -> Integer,
+        host_export_hash -> Text,
+        /// The first block indexed with this version; `null` if the
+        /// version indexed the deployment from its start block
+        start_block_number -> Nullable<Integer>,
+        /// The last block indexed with this version; `null` while the
+        /// version is still in use
+        end_block_number -> Nullable<Integer>,
+        started_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// How much of a shared resource a deployment used in each hour
+    subgraphs.deployment_usage (id, bucket, resource) {
+        // subgraph_deployment.id
+        id -> Integer,
+        /// The start of the hour
+        bucket -> Timestamptz,
+        resource -> Text,
+        amount -> BigInt,
+    }
+}
+
+table! {
+    /// The compatibility fingerprint of a deployment
+    subgraphs.compatibility_fingerprint (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        fingerprint -> Text,
+        description -> Text,
+        recorded_at -> Timestamptz,
+        mismatch -> Nullable<Text>,
+        mismatch_description -> Nullable<Text>,
+        acknowledged_at -> Nullable<Timestamptz>,
+        acknowledged_note -> Nullable<Text>,
+    }
+}
+
+table! {
+    /// The entity types that each handler of a deployment was observed
+    /// writing
+    subgraphs.handler_entity_types (id, data_source, handler, entity_type) {
+        // subgraph_deployment.id
+        id -> Integer,
+        data_source -> Text,
+        handler -> Text,
+        entity_type -> Text,
+        first_seen_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// The bus backends that a deployment publishes to
+    subgraphs.deployment_bus (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        backends -> Array<Text>,
+        recorded_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// The entity types of a deployment whose delivery to the bus needs
+    /// to be verifiable
+    subgraphs.deployment_bus_critical (id, entity_type) {
+        // subgraph_deployment.id
+        id -> Integer,
+        entity_type -> Text,
+    }
+}
+
+table! {
+    /// Up to which block the changes of a deployment to each critical
+    /// entity type were delivered to each bus backend
+    subgraphs.deployment_bus_critical_delivery (id, entity_type, backend) {
+        // subgraph_deployment.id
+        id -> Integer,
+        entity_type -> Text,
+        backend -> Text,
+        confirmed_block -> Nullable<Integer>,
+        dead_lettered_block -> Nullable<Integer>,
+    }
+}
+
+table! {
+    /// Deployments whose synced actions have not run to completion yet
+    subgraphs.synced_actions_pending (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        block_hash -> Binary,
+        block_number -> Integer,
+        synced_at -> Timestamptz,
+        done -> Array<Text>,
+    }
+}
+
+table! {
+    /// Labels that synced actions or graphman put on a deployment
+    subgraphs.deployment_label (id, label) {
+        // subgraph_deployment.id
+        id -> Integer,
+        label -> Text,
+    }
+}
+
+table! {
+    /// Where the PoI of a deployment diverged from the reference indexers
+    subgraphs.deployment_poi_divergence (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        block_number -> Integer,
+        local_poi -> Binary,
+        reference_poi -> Binary,
+        detected_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// The profile with which the WASM modules of a deployment are
+    /// compiled, and the profile they were last compiled with
+    subgraphs.deployment_compile_profile (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        /// `null` if the deployment uses the default of the node
+        profile -> Nullable<Text>,
+        compiled_profile -> Nullable<Text>,
+        compile_time_ms -> Nullable<BigInt>,
+        compiled_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    /// Retention policies for the entity types of a deployment
+    subgraphs.entity_retention (id, entity_type) {
+        // subgraph_deployment.id
+        id -> Integer,
+        entity_type -> Text,
+        timestamp_field -> Nullable<Text>,
+        max_age_secs -> Nullable<BigInt>,
+        max_blocks -> Nullable<Integer>,
+        active -> Bool,
+    }
+}
+
+table! {
+    /// The highest block at which a deployment modified each entity type
+    subgraphs.entity_type_modified (id, entity_type) {
+        // subgraph_deployment.id
+        id -> Integer,
+        entity_type -> Text,
+        block_number -> Integer,
+    }
+}
+
+table! {
+    /// The aggregates that the invariants of the deployment use as of
+    /// `block_number`
+    subgraphs.invariant_aggregate (id, term, block_number) {
+        // subgraph_deployment.id
+        id -> Integer,
+        term -> Text,
+        block_number -> Integer,
+        value -> Nullable<Numeric>,
+    }
+}
+
+table! {
+    /// The invariants from the manifest that the deployment currently
+    /// violates
+    subgraphs.invariant_violation (id, invariant) {
+        // subgraph_deployment.id
+        id -> Integer,
+        invariant -> Text,
+        check_expr -> Text,
+        first_block -> Integer,
+        last_block -> Integer,
+        message -> Text,
+    }
+}
+
+table! {
+    /// A reorg threshold for a deployment that is lower than the one of
+    /// its chain
+    subgraphs.deployment_reorg_threshold (id) {
+        // subgraph_deployment.id
+        id -> Integer,
+        reorg_threshold -> Integer,
+    }
+}
+
+table! {
+    /// The providers that served blocks to deployments in this shard
+    subgraphs.block_provider (id) {
+        id -> SmallInt,
+        label -> Text,
+    }
+}
+
+table! {
+    /// Which provider served each of the most recent blocks of a
+    /// deployment, kept as a ring buffer with one slot per block
+    subgraphs.deployment_block_provider (id, slot) {
+        // subgraph_deployment.id
+        id -> Integer,
+        slot -> Integer,
+        block_number -> Integer,
+        provider -> SmallInt,
+    }
+}
+
+table! {
+    /// The cumulative statistics of each handler of a deployment
+    subgraphs.deployment_handler_stats (id, handler) {
+        // subgraph_deployment.id
+        id -> Integer,
+        handler -> Text,
+        invocations -> BigInt,
+        execution_us -> BigInt,
+        gas -> BigInt,
+        errors -> BigInt,
+        since -> Timestamptz,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(subgraph_deployment, subgraph_error, subgraph_manifest);
+allow_tables_to_appear_in_same_query!(block_provider, deployment_block_provider);
+joinable!(deployment_block_provider -> block_provider (provider));
+allow_tables_to_appear_in_same_query!(subgraph_deployment_node_versions, graph_node_versions);
 
 /// Look up the graft point for the given subgraph in the database and
 /// return it. If `pending_only` is `true`, only return `Some(_)` if the
@@ -283,169 +528,1216 @@ pub fn features(conn: &PgConnection, site: &Site) -> Result<BTreeSet<SubgraphFea
         .collect()
 }
 
-/// This migrates subgraphs that existed before the raw_yaml column was added.
-pub fn set_manifest_raw_yaml(
+/// This migrates subgraphs that existed before the raw_yaml column was added.
+pub fn set_manifest_raw_yaml(
+    conn: &PgConnection,
+    site: &Site,
+    raw_yaml: &str,
+) -> Result<(), StoreError> {
+    use subgraph_manifest as sm;
+
+    update(sm::table.filter(sm::id.eq(site.id)))
+        .filter(sm::raw_yaml.is_null())
+        .set(sm::raw_yaml.eq(raw_yaml))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+pub fn transact_block(
+    conn: &PgConnection,
+    site: &Site,
+    ptr: &BlockPtr,
+    firehose_cursor: &FirehoseCursor,
+    full_count_query: &str,
+    count: i32,
+) -> Result<(), StoreError> {
+    use crate::diesel::BoolExpressionMethods;
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    let count_sql = if count == 0 {
+        // This amounts to a noop - the entity count does not change
+        "entity_count".to_string()
+    } else {
+        entity_count_sql(full_count_query, count)
+    };
+
+    let row_count = update(
+        d::table.filter(d::id.eq(site.id)).filter(
+            // Asserts that the processing direction is forward.
+            d::latest_ethereum_block_number
+                .lt(sql(&number))
+                .or(d::latest_ethereum_block_number.is_null()),
+        ),
+    )
+    .set((
+        d::latest_ethereum_block_number.eq(sql(&number)),
+        d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
+        d::firehose_cursor.eq(firehose_cursor.as_ref()),
+        d::entity_count.eq(sql(&count_sql)),
+        d::current_reorg_depth.eq(0),
+    ))
+    .execute(conn)
+    .map_err(StoreError::from)?;
+
+    match row_count {
+        // Common case: A single row was updated.
+        1 => Ok(()),
+
+        // No matching rows were found. This is an error. By the filter conditions, this can only be
+        // due to a missing deployment (which `block_ptr` catches) or duplicate block processing.
+        0 => match block_ptr(conn, &site.deployment)? {
+            Some(block_ptr_from) if block_ptr_from.number >= ptr.number => Err(
+                StoreError::DuplicateBlockProcessing(site.deployment.clone(), ptr.number),
+            ),
+            None | Some(_) => Err(StoreError::Unknown(anyhow!(
+                "unknown error forwarding block ptr"
+            ))),
+        },
+
+        // More than one matching row was found.
+        _ => Err(StoreError::ConstraintViolation(
+            "duplicate deployments in shard".to_owned(),
+        )),
+    }
+}
+
+pub fn forward_block_ptr(
+    conn: &PgConnection,
+    id: &DeploymentHash,
+    ptr: &BlockPtr,
+) -> Result<(), StoreError> {
+    use crate::diesel::BoolExpressionMethods;
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    let row_count = update(
+        d::table.filter(d::deployment.eq(id.as_str())).filter(
+            // Asserts that the processing direction is forward.
+            d::latest_ethereum_block_number
+                .lt(sql(&number))
+                .or(d::latest_ethereum_block_number.is_null()),
+        ),
+    )
+    .set((
+        d::latest_ethereum_block_number.eq(sql(&number)),
+        d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
+        d::current_reorg_depth.eq(0),
+    ))
+    .execute(conn)
+    .map_err(StoreError::from)?;
+
+    match row_count {
+        // Common case: A single row was updated.
+        1 => Ok(()),
+
+        // No matching rows were found. This is an error. By the filter conditions, this can only be
+        // due to a missing deployment (which `block_ptr` catches) or duplicate block processing.
+        0 => match block_ptr(conn, id)? {
+            Some(block_ptr_from) if block_ptr_from.number >= ptr.number => {
+                Err(StoreError::DuplicateBlockProcessing(id.clone(), ptr.number))
+            }
+            None | Some(_) => Err(StoreError::Unknown(anyhow!(
+                "unknown error forwarding block ptr"
+            ))),
+        },
+
+        // More than one matching row was found.
+        _ => Err(StoreError::ConstraintViolation(
+            "duplicate deployments in shard".to_owned(),
+        )),
+    }
+}
+
+pub fn get_subgraph_firehose_cursor(
+    conn: &PgConnection,
+    site: Arc<Site>,
+) -> Result<Option<String>, StoreError> {
+    use subgraph_deployment as d;
+
+    let res = d::table
+        .filter(d::deployment.eq(site.deployment.as_str()))
+        .select(d::firehose_cursor)
+        .first::<Option<String>>(conn)
+        .map_err(StoreError::from);
+    res
+}
+
+pub fn revert_block_ptr(
+    conn: &PgConnection,
+    id: &DeploymentHash,
+    ptr: BlockPtr,
+    firehose_cursor: &FirehoseCursor,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    update(d::table.filter(d::deployment.eq(id.as_str())))
+        .set((
+            d::latest_ethereum_block_number.eq(sql(&number)),
+            d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
+            d::firehose_cursor.eq(firehose_cursor.as_ref()),
+            d::reorg_count.eq(d::reorg_count + 1),
+            d::current_reorg_depth.eq(d::current_reorg_depth + 1),
+            d::max_reorg_depth.eq(sql("greatest(current_reorg_depth + 1, max_reorg_depth)")),
+        ))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+/// The number of blocks that the deployment has reverted since it last
+/// moved forward
+pub fn current_reorg_depth(conn: &PgConnection, site: &Site) -> Result<BlockNumber, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(site.id))
+        .select(d::current_reorg_depth)
+        .first::<BlockNumber>(conn)?)
+}
+
+/// Record that the deployment is indexed with the current version of
+/// graph-node and a host export surface with hash `host_export_hash` from
+/// the block after its current block pointer on. If the most recent entry
+/// for the deployment has the same version and hash, nothing is changed;
+/// otherwise, that entry is closed at the current block pointer and a new
+/// entry is added.
+pub fn record_node_version(
+    conn: &PgConnection,
+    site: &Site,
+    host_export_hash: &str,
+) -> Result<(), StoreError> {
+    use subgraph_deployment_node_versions as nv;
+
+    let version_id = GraphNodeVersion::create_or_get(conn)?;
+
+    let latest = nv::table
+        .filter(nv::id.eq(site.id))
+        .order_by(nv::vid.desc())
+        .select((nv::vid, nv::graph_node_version_id, nv::host_export_hash))
+        .first::<(i64, i32, String)>(conn)
+        .optional()?;
+
+    let head = block_ptr(conn, &site.deployment)?.map(|ptr| ptr.number);
+
+    match latest {
+        Some((_, latest_version_id, latest_hash))
+            if latest_version_id == version_id && latest_hash == host_export_hash =>
+        {
+            return Ok(());
+        }
+        Some((vid, _, _)) => {
+            update(nv::table.filter(nv::vid.eq(vid)))
+                .set(nv::end_block_number.eq(head))
+                .execute(conn)?;
+        }
+        None => { /* first time this deployment is started */ }
+    }
+
+    insert_into(nv::table)
+        .values((
+            nv::id.eq(site.id),
+            nv::graph_node_version_id.eq(version_id),
+            nv::host_export_hash.eq(host_export_hash),
+            nv::start_block_number.eq(head.map(|number| number + 1)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Compare `current` with the compatibility fingerprint recorded for the
+/// deployment. If there is no recorded fingerprint yet, record `current`.
+/// If they differ, remember `current` as the mismatch that needs to be
+/// acknowledged and return the recorded fingerprint
+pub fn check_compatibility(
+    conn: &PgConnection,
+    site: &Site,
+    current: &CompatibilityFingerprint,
+) -> Result<Option<CompatibilityFingerprint>, StoreError> {
+    use compatibility_fingerprint as cf;
+
+    let hash = current.hash();
+    let description = current.description();
+
+    insert_into(cf::table)
+        .values((
+            cf::id.eq(site.id),
+            cf::fingerprint.eq(&hash),
+            cf::description.eq(&description),
+        ))
+        .on_conflict(cf::id)
+        .do_nothing()
+        .execute(conn)?;
+
+    let (recorded, recorded_description) = cf::table
+        .filter(cf::id.eq(site.id))
+        .select((cf::fingerprint, cf::description))
+        .first::<(String, String)>(conn)?;
+
+    let (mismatch, mismatch_description, result) = if recorded == hash {
+        (None, None, None)
+    } else {
+        (
+            Some(hash),
+            Some(description),
+            Some(CompatibilityFingerprint::parse(&recorded_description)),
+        )
+    };
+    update(cf::table.filter(cf::id.eq(site.id)))
+        .set((
+            cf::mismatch.eq(mismatch),
+            cf::mismatch_description.eq(mismatch_description),
+        ))
+        .execute(conn)?;
+    Ok(result)
+}
+
+/// Return the recorded compatibility fingerprint of the deployment and the
+/// fingerprint it was last started with if that is different
+pub fn compatibility(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<(CompatibilityFingerprint, Option<CompatibilityFingerprint>)>, StoreError> {
+    use compatibility_fingerprint as cf;
+
+    let fingerprints = cf::table
+        .filter(cf::id.eq(site.id))
+        .select((cf::description, cf::mismatch_description))
+        .first::<(String, Option<String>)>(conn)
+        .optional()?;
+    Ok(fingerprints.map(|(recorded, mismatch)| {
+        (
+            CompatibilityFingerprint::parse(&recorded),
+            mismatch.as_deref().map(CompatibilityFingerprint::parse),
+        )
+    }))
+}
+
+/// Make the fingerprint the deployment was last started with its recorded
+/// fingerprint. Return `false` if there was no mismatch to acknowledge
+pub fn acknowledge_compatibility(
+    conn: &PgConnection,
+    site: &Site,
+    note: Option<&str>,
+) -> Result<bool, StoreError> {
+    const QUERY: &str = "update subgraphs.compatibility_fingerprint \
+                         set fingerprint = mismatch, \
+                             description = mismatch_description, \
+                             recorded_at = now(), \
+                             mismatch = null, \
+                             mismatch_description = null, \
+                             acknowledged_at = now(), \
+                             acknowledged_note = $2 \
+                         where id = $1 and mismatch is not null";
+
+    let count = sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Nullable<Text>, _>(note)
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Add `usage` to the usage of the deployment in the current hour
+pub fn record_usage(conn: &PgConnection, site: &Site, usage: &Usage) -> Result<(), StoreError> {
+    const INSERT: &str = "insert into subgraphs.deployment_usage(id, bucket, resource, amount) \
+                          values ($1, date_trunc('hour', now()), $2, $3) \
+                          on conflict(id, bucket, resource) \
+                          do update set amount = deployment_usage.amount + excluded.amount";
+
+    for (resource, amount) in usage.iter() {
+        sql_query(INSERT)
+            .bind::<Integer, _>(site.id)
+            .bind::<Text, _>(resource)
+            .bind::<BigInt, _>(i64::try_from(amount).unwrap_or(i64::MAX))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Remove the usage records of all deployments, whether they are running
+/// or not, that are older than `retention_days`. Return the number of
+/// records that were removed
+pub fn expire_usage(conn: &PgConnection, retention_days: u32) -> Result<usize, StoreError> {
+    const EXPIRE: &str = "delete from subgraphs.deployment_usage \
+                          where bucket < now() - make_interval(days => $1)";
+
+    Ok(sql_query(EXPIRE)
+        .bind::<Integer, _>(retention_days as i32)
+        .execute(conn)?)
+}
+
+/// Record the entity types that handlers wrote. Writes that were recorded
+/// before are ignored
+pub fn record_handler_entity_types(
+    conn: &PgConnection,
+    site: &Site,
+    writes: &[HandlerWrite],
+) -> Result<(), StoreError> {
+    use handler_entity_types as h;
+
+    let rows: Vec<_> = writes
+        .iter()
+        .map(|write| {
+            (
+                h::id.eq(site.id),
+                h::data_source.eq(&write.data_source),
+                h::handler.eq(&write.handler),
+                h::entity_type.eq(&write.entity_type),
+            )
+        })
+        .collect();
+    insert_into(h::table)
+        .values(rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Add `stats` to the cumulative statistics of the handlers of the
+/// deployment with a single statement
+pub fn record_handler_stats(
+    conn: &PgConnection,
+    site: &Site,
+    stats: &[HandlerStats],
+) -> Result<(), StoreError> {
+    const QUERY: &str = "insert into subgraphs.deployment_handler_stats as s\
+                         (id, handler, invocations, execution_us, gas, errors) \
+                         select $1, * from unnest($2::text[], $3::int8[], $4::int8[], $5::int8[], $6::int8[]) \
+                         on conflict(id, handler) \
+                         do update set invocations = s.invocations + excluded.invocations, \
+                                       execution_us = s.execution_us + excluded.execution_us, \
+                                       gas = s.gas + excluded.gas, \
+                                       errors = s.errors + excluded.errors";
+
+    fn clamp(value: u128) -> i64 {
+        i64::try_from(value).unwrap_or(i64::MAX)
+    }
+
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let handlers: Vec<_> = stats.iter().map(|s| s.handler.as_str()).collect();
+    let invocations: Vec<_> = stats.iter().map(|s| clamp(s.invocations as u128)).collect();
+    let execution_us: Vec<_> = stats
+        .iter()
+        .map(|s| clamp(s.execution_time.as_micros()))
+        .collect();
+    let gas: Vec<_> = stats.iter().map(|s| clamp(s.gas as u128)).collect();
+    let errors: Vec<_> = stats.iter().map(|s| clamp(s.errors as u128)).collect();
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Array<Text>, _>(handlers)
+        .bind::<Array<BigInt>, _>(invocations)
+        .bind::<Array<BigInt>, _>(execution_us)
+        .bind::<Array<BigInt>, _>(gas)
+        .bind::<Array<BigInt>, _>(errors)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the statistics of the handlers of the deployment so that they
+/// start over from zero
+pub fn reset_handler_stats(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use deployment_handler_stats as s;
+
+    delete(s::table.filter(s::id.eq(site.id))).execute(conn)?;
+    Ok(())
+}
+
+/// Record that the deployment modified entities of the types in
+/// `entity_types` at `block`
+pub fn record_entity_types_modified(
+    conn: &PgConnection,
+    site: &Site,
+    entity_types: &[&str],
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    const QUERY: &str = "insert into subgraphs.entity_type_modified as m(id, entity_type, block_number) \
+                         select $1, entity_type, $3 from unnest($2::text[]) as entity_type \
+                         on conflict(id, entity_type) \
+                         do update set block_number = greatest(m.block_number, excluded.block_number)";
+
+    if entity_types.is_empty() {
+        return Ok(());
+    }
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Array<Text>, _>(entity_types)
+        .bind::<Integer, _>(block)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget modifications at `block` and later. Until the entity types are
+/// modified again, the block at which they were last modified has to be
+/// determined from the entity data
+pub fn revert_entity_types_modified(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use entity_type_modified as m;
+
+    delete(
+        m::table
+            .filter(m::id.eq(site.id))
+            .filter(m::block_number.ge(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Return the highest block at which the deployment modified each entity
+/// type, for the entity types for which that is known
+pub fn entity_types_modified(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Vec<(String, BlockNumber)>, StoreError> {
+    use entity_type_modified as m;
+
+    m::table
+        .filter(m::id.eq(site.id))
+        .select((m::entity_type, m::block_number))
+        .load(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the id of the provider with label `label`, registering the
+/// provider if this is the first time it served a block in this shard.
+/// Since ids are never reused, they stay meaningful across restarts. This
+/// must not be called inside a transaction that might be rolled back since
+/// callers cache the id
+pub(crate) fn block_provider_id(conn: &PgConnection, label: &str) -> Result<i16, StoreError> {
+    use block_provider as p;
+
+    let id = p::table
+        .filter(p::label.eq(label))
+        .select(p::id)
+        .first::<i16>(conn)
+        .optional()?;
+    if let Some(id) = id {
+        return Ok(id);
+    }
+
+    insert_into(p::table)
+        .values(p::label.eq(label))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    p::table
+        .filter(p::label.eq(label))
+        .select(p::id)
+        .first::<i16>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Record that the provider with id `provider` served `block` to the
+/// deployment. Only the last `history` blocks are kept; the slot for
+/// `block` is reused for later blocks
+pub fn record_block_provider(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+    provider: i16,
+    history: BlockNumber,
+) -> Result<(), StoreError> {
+    use deployment_block_provider as bp;
+
+    if history <= 0 {
+        return Ok(());
+    }
+    let slot = block.rem_euclid(history);
+    insert_into(bp::table)
+        .values((
+            bp::id.eq(site.id),
+            bp::slot.eq(slot),
+            bp::block_number.eq(block),
+            bp::provider.eq(provider),
+        ))
+        .on_conflict((bp::id, bp::slot))
+        .do_update()
+        .set((bp::block_number.eq(block), bp::provider.eq(provider)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget which providers served `block` and any later blocks since they
+/// are being reverted
+pub fn revert_block_providers(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use deployment_block_provider as bp;
+
+    delete(
+        bp::table
+            .filter(bp::id.eq(site.id))
+            .filter(bp::block_number.ge(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Return the providers that served the blocks of the deployment between
+/// `from` and `to`, both inclusive, ordered by block number. Only the most
+/// recent blocks are known
+pub fn block_providers(
+    conn: &PgConnection,
+    site: &Site,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Result<Vec<(BlockNumber, String)>, StoreError> {
+    use block_provider as p;
+    use deployment_block_provider as bp;
+
+    bp::table
+        .inner_join(p::table)
+        .filter(bp::id.eq(site.id))
+        .filter(bp::block_number.between(from, to))
+        .order(bp::block_number)
+        .select((bp::block_number, p::label))
+        .load(conn)
+        .map_err(StoreError::from)
+}
+
+/// Record that the invariant `invariant` with check `check` was violated
+/// at `block`. If it was already violated before, keep the block at which
+/// the violation started
+pub fn record_invariant_violation(
+    conn: &PgConnection,
+    site: &Site,
+    invariant: &str,
+    check: &str,
+    block: BlockNumber,
+    message: &str,
+) -> Result<(), StoreError> {
+    use invariant_violation as v;
+
+    insert_into(v::table)
+        .values((
+            v::id.eq(site.id),
+            v::invariant.eq(invariant),
+            v::check_expr.eq(check),
+            v::first_block.eq(block),
+            v::last_block.eq(block),
+            v::message.eq(message),
+        ))
+        .on_conflict((v::id, v::invariant))
+        .do_update()
+        .set((
+            v::check_expr.eq(check),
+            v::last_block.eq(block),
+            v::message.eq(message),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget the violation of `invariant` since it holds again
+pub fn clear_invariant_violation(
+    conn: &PgConnection,
+    site: &Site,
+    invariant: &str,
+) -> Result<(), StoreError> {
+    use invariant_violation as v;
+
+    delete(
+        v::table
+            .filter(v::id.eq(site.id))
+            .filter(v::invariant.eq(invariant)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Forget violations that started at `block` or later, and end the ones
+/// that started earlier before `block`
+pub fn revert_invariant_violations(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use invariant_violation as v;
+
+    delete(
+        v::table
+            .filter(v::id.eq(site.id))
+            .filter(v::first_block.ge(block)),
+    )
+    .execute(conn)?;
+    update(
+        v::table
+            .filter(v::id.eq(site.id))
+            .filter(v::last_block.ge(block)),
+    )
+    .set(v::last_block.eq(block - 1))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The latest value that was recorded for the aggregate `term`. Return
+/// `None` if none was recorded, and `Some(None)` if the aggregate has no
+/// value
+pub fn invariant_aggregate(
+    conn: &PgConnection,
+    site: &Site,
+    term: &str,
+) -> Result<Option<Option<BigDecimal>>, StoreError> {
+    use invariant_aggregate as a;
+
+    a::table
+        .filter(a::id.eq(site.id))
+        .filter(a::term.eq(term))
+        .order_by(a::block_number.desc())
+        .select(a::value)
+        .first::<Option<BigDecimal>>(conn)
+        .optional()
+        .map_err(StoreError::from)
+}
+
+/// Record that the aggregate `term` has `value` as of `block`
+pub fn set_invariant_aggregate(
+    conn: &PgConnection,
+    site: &Site,
+    term: &str,
+    block: BlockNumber,
+    value: Option<&BigDecimal>,
+) -> Result<(), StoreError> {
+    use invariant_aggregate as a;
+
+    insert_into(a::table)
+        .values((
+            a::id.eq(site.id),
+            a::term.eq(term),
+            a::block_number.eq(block),
+            a::value.eq(value),
+        ))
+        .on_conflict((a::id, a::term, a::block_number))
+        .do_update()
+        .set(a::value.eq(value))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the values of aggregates that were replaced at or before
+/// `earliest_block`; the deployment can not be reverted to the blocks
+/// where they applied
+pub fn prune_invariant_aggregates(
+    conn: &PgConnection,
+    site: &Site,
+    earliest_block: BlockNumber,
+) -> Result<(), StoreError> {
+    const QUERY: &str = "delete from subgraphs.invariant_aggregate a \
+                          where a.id = $1 \
+                            and a.block_number < (select max(b.block_number) \
+                                                    from subgraphs.invariant_aggregate b \
+                                                   where b.id = a.id \
+                                                     and b.term = a.term \
+                                                     and b.block_number <= $2)";
+
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Integer, _>(earliest_block)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget the values of aggregates as of `block` or later
+pub fn revert_invariant_aggregates(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use invariant_aggregate as a;
+
+    delete(
+        a::table
+            .filter(a::id.eq(site.id))
+            .filter(a::block_number.ge(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Record that the deployment publishes to the bus backends `backends`,
+/// replacing the backends that were recorded before
+pub fn record_bus_backends(
+    conn: &PgConnection,
+    site: &Site,
+    backends: &[String],
+) -> Result<(), StoreError> {
+    const QUERY: &str = "insert into subgraphs.deployment_bus(id, backends) \
+                         values ($1, $2) \
+                         on conflict(id) \
+                         do update set backends = excluded.backends, recorded_at = now()";
+
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Array<Text>, _>(backends)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The entity types of the deployment whose delivery to the bus needs to
+/// be verifiable
+pub fn bus_critical_entity_types(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<BTreeSet<EntityType>, StoreError> {
+    use deployment_bus_critical as c;
+
+    Ok(c::table
+        .filter(c::id.eq(site.id))
+        .select(c::entity_type)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(EntityType::from)
+        .collect())
+}
+
+/// Make `entity_types` the critical entity types of the deployment. The
+/// delivery status of types that are no longer critical is forgotten
+pub fn set_bus_critical_entity_types(
+    conn: &PgConnection,
+    site: &Site,
+    entity_types: &BTreeSet<EntityType>,
+) -> Result<(), StoreError> {
+    use deployment_bus_critical as c;
+    use deployment_bus_critical_delivery as d;
+
+    let entity_types: Vec<_> = entity_types.iter().map(|t| t.as_str()).collect();
+    delete(
+        c::table
+            .filter(c::id.eq(site.id))
+            .filter(c::entity_type.ne_all(&entity_types)),
+    )
+    .execute(conn)?;
+    delete(
+        d::table
+            .filter(d::id.eq(site.id))
+            .filter(d::entity_type.ne_all(&entity_types)),
+    )
+    .execute(conn)?;
+    let rows: Vec<_> = entity_types
+        .iter()
+        .map(|entity_type| (c::id.eq(site.id), c::entity_type.eq(*entity_type)))
+        .collect();
+    insert_into(c::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that `backend` delivered the changes of the deployment to
+/// `entity_types` in `block`, or, if `delivered` is `false`, that they
+/// were dead-lettered. The confirmed block of a type only advances while
+/// it has no dead-lettered block, so that it is a block up to which all
+/// changes to the type were delivered. Return the confirmed block of each
+/// of `entity_types` afterwards
+pub fn record_bus_critical_delivery(
+    conn: &PgConnection,
+    site: &Site,
+    backend: &str,
+    entity_types: &BTreeSet<EntityType>,
+    block: BlockNumber,
+    delivered: bool,
+) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError> {
+    use deployment_bus_critical_delivery as d;
+
+    const CONFIRMED: &str = "insert into subgraphs.deployment_bus_critical_delivery\
+                               (id, entity_type, backend, confirmed_block) \
+                             select $1, entity_type, $3, $4 from unnest($2::text[]) entity_type \
+                             on conflict(id, entity_type, backend) do update \
+                                set confirmed_block = excluded.confirmed_block \
+                              where deployment_bus_critical_delivery.dead_lettered_block is null";
+    const DEAD_LETTERED: &str = "insert into subgraphs.deployment_bus_critical_delivery\
+                                   (id, entity_type, backend, dead_lettered_block) \
+                                 select $1, entity_type, $3, $4 from unnest($2::text[]) entity_type \
+                                 on conflict(id, entity_type, backend) do update \
+                                    set dead_lettered_block = \
+                                          coalesce(deployment_bus_critical_delivery.dead_lettered_block, \
+                                                   excluded.dead_lettered_block)";
+
+    let entity_types: Vec<_> = entity_types.iter().map(|t| t.as_str()).collect();
+    sql_query(if delivered { CONFIRMED } else { DEAD_LETTERED })
+        .bind::<Integer, _>(site.id)
+        .bind::<Array<Text>, _>(&entity_types)
+        .bind::<Text, _>(backend)
+        .bind::<Integer, _>(block)
+        .execute(conn)?;
+
+    Ok(d::table
+        .filter(d::id.eq(site.id))
+        .filter(d::backend.eq(backend))
+        .filter(d::entity_type.eq_any(&entity_types))
+        .select((d::entity_type, d::confirmed_block))
+        .load::<(String, Option<BlockNumber>)>(conn)?
+        .into_iter()
+        .map(|(entity_type, block)| (EntityType::from(entity_type), block))
+        .collect())
+}
+
+/// Forget the dead-lettered blocks of the critical entity types of the
+/// deployment, for all backends or only for `backend`, so that their
+/// confirmed blocks advance again. Return how many were forgotten
+pub fn acknowledge_bus_dead_letters(
+    conn: &PgConnection,
+    site: &Site,
+    backend: Option<&str>,
+) -> Result<usize, StoreError> {
+    use deployment_bus_critical_delivery as d;
+
+    let rows = d::table
+        .filter(d::id.eq(site.id))
+        .filter(d::dead_lettered_block.is_not_null());
+    let count = match backend {
+        Some(backend) => update(rows.filter(d::backend.eq(backend)))
+            .set(d::dead_lettered_block.eq(None::<BlockNumber>))
+            .execute(conn)?,
+        None => update(rows)
+            .set(d::dead_lettered_block.eq(None::<BlockNumber>))
+            .execute(conn)?,
+    };
+    Ok(count)
+}
+
+/// Record that the PoI of the deployment diverged from the reference
+/// indexers at `divergence.block`, unless a divergence at an earlier block
+/// was recorded already. With `None`, forget any divergence since the PoI
+/// agrees with the reference indexers again
+pub fn record_poi_divergence(
     conn: &PgConnection,
     site: &Site,
-    raw_yaml: &str,
+    divergence: Option<&PoiDivergence>,
 ) -> Result<(), StoreError> {
-    use subgraph_manifest as sm;
+    use deployment_poi_divergence as d;
+
+    match divergence {
+        Some(divergence) => {
+            const QUERY: &str = "insert into subgraphs.deployment_poi_divergence\
+                                   (id, block_number, local_poi, reference_poi) \
+                                 values ($1, $2, $3, $4) \
+                                 on conflict(id) do update \
+                                    set block_number = excluded.block_number, \
+                                        local_poi = excluded.local_poi, \
+                                        reference_poi = excluded.reference_poi, \
+                                        detected_at = now() \
+                                  where excluded.block_number \
+                                      < subgraphs.deployment_poi_divergence.block_number";
+
+            sql_query(QUERY)
+                .bind::<Integer, _>(site.id)
+                .bind::<Integer, _>(divergence.block)
+                .bind::<Binary, _>(&divergence.local_poi)
+                .bind::<Binary, _>(&divergence.reference_poi)
+                .execute(conn)?;
+        }
+        None => {
+            delete(d::table.filter(d::id.eq(site.id))).execute(conn)?;
+        }
+    }
+    Ok(())
+}
 
-    update(sm::table.filter(sm::id.eq(site.id)))
-        .filter(sm::raw_yaml.is_null())
-        .set(sm::raw_yaml.eq(raw_yaml))
-        .execute(conn)
-        .map(|_| ())
-        .map_err(|e| e.into())
+/// The retention policies of the deployment, ordered by entity type,
+/// including those that are not in effect yet
+pub fn entity_retention(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Vec<EntityRetention>, StoreError> {
+    load_entity_retention(conn, site, false)
 }
 
-pub fn transact_block(
+/// The retention policies of the deployment that are in effect, i.e., that
+/// the deployment was started with, ordered by entity type
+pub fn active_entity_retention(
     conn: &PgConnection,
     site: &Site,
-    ptr: &BlockPtr,
-    firehose_cursor: &FirehoseCursor,
-    full_count_query: &str,
-    count: i32,
-) -> Result<(), StoreError> {
-    use crate::diesel::BoolExpressionMethods;
-    use subgraph_deployment as d;
+) -> Result<Vec<EntityRetention>, StoreError> {
+    load_entity_retention(conn, site, true)
+}
 
-    // Work around a Diesel issue with serializing BigDecimals to numeric
-    let number = format!("{}::numeric", ptr.number);
+/// Put all retention policies of the deployment into effect and return
+/// them. This must be called when the deployment starts
+pub fn activate_entity_retention(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Vec<EntityRetention>, StoreError> {
+    use entity_retention as r;
 
-    let count_sql = if count == 0 {
-        // This amounts to a noop - the entity count does not change
-        "entity_count".to_string()
-    } else {
-        entity_count_sql(full_count_query, count)
-    };
+    update(r::table.filter(r::id.eq(site.id)))
+        .set(r::active.eq(true))
+        .execute(conn)?;
+    active_entity_retention(conn, site)
+}
 
-    let row_count = update(
-        d::table.filter(d::id.eq(site.id)).filter(
-            // Asserts that the processing direction is forward.
-            d::latest_ethereum_block_number
-                .lt(sql(&number))
-                .or(d::latest_ethereum_block_number.is_null()),
-        ),
-    )
-    .set((
-        d::latest_ethereum_block_number.eq(sql(&number)),
-        d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
-        d::firehose_cursor.eq(firehose_cursor.as_ref()),
-        d::entity_count.eq(sql(&count_sql)),
-        d::current_reorg_depth.eq(0),
-    ))
-    .execute(conn)
-    .map_err(StoreError::from)?;
+fn load_entity_retention(
+    conn: &PgConnection,
+    site: &Site,
+    active_only: bool,
+) -> Result<Vec<EntityRetention>, StoreError> {
+    use entity_retention as r;
 
-    match row_count {
-        // Common case: A single row was updated.
-        1 => Ok(()),
+    let mut query = r::table
+        .filter(r::id.eq(site.id))
+        .select((
+            r::entity_type,
+            r::timestamp_field,
+            r::max_age_secs,
+            r::max_blocks,
+        ))
+        .order_by(r::entity_type)
+        .into_boxed();
+    if active_only {
+        query = query.filter(r::active.eq(true));
+    }
+    query
+        .load::<(String, Option<String>, Option<i64>, Option<i32>)>(conn)?
+        .into_iter()
+        .map(|(entity_type, field, max_age_secs, max_blocks)| {
+            let rule = match (field, max_age_secs, max_blocks) {
+                (Some(field), Some(secs), None) => RetentionRule::Age {
+                    field,
+                    max_age: Duration::from_secs(secs as u64),
+                },
+                (None, None, Some(blocks)) => RetentionRule::Blocks(blocks),
+                _ => {
+                    return Err(constraint_violation!(
+                        "invalid retention policy for {}.{}",
+                        site.deployment,
+                        entity_type
+                    ))
+                }
+            };
+            Ok(EntityRetention { entity_type, rule })
+        })
+        .collect()
+}
 
-        // No matching rows were found. This is an error. By the filter conditions, this can only be
-        // due to a missing deployment (which `block_ptr` catches) or duplicate block processing.
-        0 => match block_ptr(conn, &site.deployment)? {
-            Some(block_ptr_from) if block_ptr_from.number >= ptr.number => Err(
-                StoreError::DuplicateBlockProcessing(site.deployment.clone(), ptr.number),
-            ),
-            None | Some(_) => Err(StoreError::Unknown(anyhow!(
-                "unknown error forwarding block ptr"
-            ))),
-        },
+/// The deployments in this shard that have retention policies in effect
+pub fn retained_deployments(conn: &PgConnection) -> Result<Vec<DeploymentId>, StoreError> {
+    use entity_retention as r;
 
-        // More than one matching row was found.
-        _ => Err(StoreError::ConstraintViolation(
-            "duplicate deployments in shard".to_owned(),
-        )),
-    }
+    Ok(r::table
+        .filter(r::active.eq(true))
+        .select(r::id)
+        .distinct()
+        .order_by(r::id)
+        .get_results::<DeploymentId>(conn)?)
 }
 
-pub fn forward_block_ptr(
+/// Set the retention policy for `retention.entity_type`, replacing the
+/// policy it had before. The policy takes effect the next time the
+/// deployment starts
+pub fn set_entity_retention(
     conn: &PgConnection,
-    id: &DeploymentHash,
-    ptr: &BlockPtr,
+    site: &Site,
+    retention: &EntityRetention,
 ) -> Result<(), StoreError> {
-    use crate::diesel::BoolExpressionMethods;
-    use subgraph_deployment as d;
+    const QUERY: &str = "insert into subgraphs.entity_retention\
+                           (id, entity_type, timestamp_field, max_age_secs, max_blocks) \
+                         values ($1, $2, $3, $4, $5) \
+                         on conflict(id, entity_type) do update \
+                            set timestamp_field = excluded.timestamp_field, \
+                                max_age_secs = excluded.max_age_secs, \
+                                max_blocks = excluded.max_blocks, \
+                                active = false";
+
+    let (field, max_age_secs, max_blocks) = match &retention.rule {
+        RetentionRule::Age { field, max_age } => (
+            Some(field.as_str()),
+            Some(i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX)),
+            None,
+        ),
+        RetentionRule::Blocks(blocks) => (None, None, Some(*blocks)),
+    };
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Text, _>(retention.entity_type.as_str())
+        .bind::<Nullable<Text>, _>(field)
+        .bind::<Nullable<BigInt>, _>(max_age_secs)
+        .bind::<Nullable<Integer>, _>(max_blocks)
+        .execute(conn)?;
+    Ok(())
+}
 
-    // Work around a Diesel issue with serializing BigDecimals to numeric
-    let number = format!("{}::numeric", ptr.number);
+/// Remove the retention policy for `entity_type`. Return `false` if it
+/// did not have one
+pub fn remove_entity_retention(
+    conn: &PgConnection,
+    site: &Site,
+    entity_type: &str,
+) -> Result<bool, StoreError> {
+    use entity_retention as r;
 
-    let row_count = update(
-        d::table.filter(d::deployment.eq(id.as_str())).filter(
-            // Asserts that the processing direction is forward.
-            d::latest_ethereum_block_number
-                .lt(sql(&number))
-                .or(d::latest_ethereum_block_number.is_null()),
-        ),
+    let rows = delete(
+        r::table
+            .filter(r::id.eq(site.id))
+            .filter(r::entity_type.eq(entity_type)),
     )
-    .set((
-        d::latest_ethereum_block_number.eq(sql(&number)),
-        d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
-        d::current_reorg_depth.eq(0),
-    ))
-    .execute(conn)
-    .map_err(StoreError::from)?;
+    .execute(conn)?;
+    Ok(rows > 0)
+}
 
-    match row_count {
-        // Common case: A single row was updated.
-        1 => Ok(()),
+fn parse_compile_profile(site: &Site, profile: &str) -> Result<CompileProfile, StoreError> {
+    CompileProfile::from_str(profile).map_err(|e| {
+        constraint_violation!("invalid compile profile for {}: {}", site.deployment, e)
+    })
+}
 
-        // No matching rows were found. This is an error. By the filter conditions, this can only be
-        // due to a missing deployment (which `block_ptr` catches) or duplicate block processing.
-        0 => match block_ptr(conn, id)? {
-            Some(block_ptr_from) if block_ptr_from.number >= ptr.number => {
-                Err(StoreError::DuplicateBlockProcessing(id.clone(), ptr.number))
-            }
-            None | Some(_) => Err(StoreError::Unknown(anyhow!(
-                "unknown error forwarding block ptr"
-            ))),
-        },
+/// The compile profile that was set for the deployment; `None` if the
+/// deployment uses the default of the node that runs it
+pub fn compile_profile(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<CompileProfile>, StoreError> {
+    use deployment_compile_profile as cp;
 
-        // More than one matching row was found.
-        _ => Err(StoreError::ConstraintViolation(
-            "duplicate deployments in shard".to_owned(),
-        )),
-    }
+    cp::table
+        .filter(cp::id.eq(site.id))
+        .select(cp::profile)
+        .first::<Option<String>>(conn)
+        .optional()?
+        .flatten()
+        .map(|profile| parse_compile_profile(site, &profile))
+        .transpose()
 }
 
-pub fn get_subgraph_firehose_cursor(
+/// Set the compile profile of the deployment; with `None`, the deployment
+/// uses the default of the node that runs it. The profile is used the
+/// next time the deployment is started
+pub fn set_compile_profile(
     conn: &PgConnection,
-    site: Arc<Site>,
-) -> Result<Option<String>, StoreError> {
-    use subgraph_deployment as d;
+    site: &Site,
+    profile: Option<CompileProfile>,
+) -> Result<(), StoreError> {
+    const QUERY: &str = "insert into subgraphs.deployment_compile_profile(id, profile) \
+                         values ($1, $2) \
+                         on conflict(id) do update set profile = excluded.profile";
 
-    let res = d::table
-        .filter(d::deployment.eq(site.deployment.as_str()))
-        .select(d::firehose_cursor)
-        .first::<Option<String>>(conn)
-        .map_err(StoreError::from);
-    res
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Nullable<Text>, _>(profile.map(|profile| profile.as_str()))
+        .execute(conn)?;
+    Ok(())
 }
 
-pub fn revert_block_ptr(
+/// Record that the modules of the deployment were compiled with `profile`
+/// and that compiling them took `compile_time`
+pub fn record_compilation(
     conn: &PgConnection,
-    id: &DeploymentHash,
-    ptr: BlockPtr,
-    firehose_cursor: &FirehoseCursor,
+    site: &Site,
+    profile: CompileProfile,
+    compile_time: Duration,
 ) -> Result<(), StoreError> {
-    use subgraph_deployment as d;
+    const QUERY: &str = "insert into subgraphs.deployment_compile_profile\
+                           (id, compiled_profile, compile_time_ms, compiled_at) \
+                         values ($1, $2, $3, now()) \
+                         on conflict(id) do update \
+                            set compiled_profile = excluded.compiled_profile, \
+                                compile_time_ms = excluded.compile_time_ms, \
+                                compiled_at = excluded.compiled_at";
+
+    sql_query(QUERY)
+        .bind::<Integer, _>(site.id)
+        .bind::<Text, _>(profile.as_str())
+        .bind::<BigInt, _>(i64::try_from(compile_time.as_millis()).unwrap_or(i64::MAX))
+        .execute(conn)?;
+    Ok(())
+}
 
-    // Work around a Diesel issue with serializing BigDecimals to numeric
-    let number = format!("{}::numeric", ptr.number);
+/// The reorg threshold that the deployment uses instead of the one of its
+/// chain; `None` if it uses the one of its chain
+pub fn reorg_threshold(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<BlockNumber>, StoreError> {
+    use deployment_reorg_threshold as rt;
+
+    Ok(rt::table
+        .filter(rt::id.eq(site.id))
+        .select(rt::reorg_threshold)
+        .first::<BlockNumber>(conn)
+        .optional()?)
+}
 
-    update(d::table.filter(d::deployment.eq(id.as_str())))
-        .set((
-            d::latest_ethereum_block_number.eq(sql(&number)),
-            d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
-            d::firehose_cursor.eq(firehose_cursor.as_ref()),
-            d::reorg_count.eq(d::reorg_count + 1),
-            d::current_reorg_depth.eq(d::current_reorg_depth + 1),
-            d::max_reorg_depth.eq(sql("greatest(current_reorg_depth + 1, max_reorg_depth)")),
-        ))
-        .execute(conn)
-        .map(|_| ())
-        .map_err(|e| e.into())
+/// The reorg threshold that the deployment actually uses when the reorg
+/// threshold of its chain is `chain_threshold`. The deployment can only
+/// lower the threshold, never raise it
+pub fn effective_reorg_threshold(
+    conn: &PgConnection,
+    site: &Site,
+    chain_threshold: BlockNumber,
+) -> Result<BlockNumber, StoreError> {
+    Ok(reorg_threshold(conn, site)?
+        .map_or(chain_threshold, |threshold| threshold.min(chain_threshold)))
+}
+
+/// Set the reorg threshold of the deployment; with `None`, the deployment
+/// uses the reorg threshold of its chain. The caller must make sure that
+/// the chain allows the threshold
+pub fn set_reorg_threshold(
+    conn: &PgConnection,
+    site: &Site,
+    reorg_threshold: Option<BlockNumber>,
+) -> Result<(), StoreError> {
+    use deployment_reorg_threshold as rt;
+
+    match reorg_threshold {
+        Some(threshold) => {
+            insert_into(rt::table)
+                .values((rt::id.eq(site.id), rt::reorg_threshold.eq(threshold)))
+                .on_conflict(rt::id)
+                .do_update()
+                .set(rt::reorg_threshold.eq(threshold))
+                .execute(conn)?;
+        }
+        None => {
+            delete(rt::table.filter(rt::id.eq(site.id))).execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the compile profile that was set for the deployment, and the
+/// profile its modules were last compiled with together with how long
+/// compiling them took
+pub fn compilation(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<(Option<CompileProfile>, Option<(CompileProfile, Duration)>), StoreError> {
+    use deployment_compile_profile as cp;
+
+    let row = cp::table
+        .filter(cp::id.eq(site.id))
+        .select((cp::profile, cp::compiled_profile, cp::compile_time_ms))
+        .first::<(Option<String>, Option<String>, Option<i64>)>(conn)
+        .optional()?;
+    let (profile, compiled_profile, compile_time_ms) = match row {
+        Some(row) => row,
+        None => return Ok((None, None)),
+    };
+
+    let profile = profile
+        .map(|profile| parse_compile_profile(site, &profile))
+        .transpose()?;
+    let compiled = match (compiled_profile, compile_time_ms) {
+        (Some(compiled_profile), Some(ms)) => Some((
+            parse_compile_profile(site, &compiled_profile)?,
+            Duration::from_millis(ms as u64),
+        )),
+        _ => None,
+    };
+    Ok((profile, compiled))
 }
 
 pub fn block_ptr(conn: &PgConnection, id: &DeploymentHash) -> Result<Option<BlockPtr>, StoreError> {
@@ -538,6 +1830,11 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
             d::latest_ethereum_block_number,
             d::latest_ethereum_block_hash,
             d::earliest_block_number,
+            d::health,
+            sql::<Nullable<Integer>>(
+                "(select m.start_block_number from subgraphs.subgraph_manifest m \
+                  where m.id = subgraph_deployment.id)",
+            ),
         ))
         .first::<(
             String,
@@ -546,6 +1843,8 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
             Option<BigDecimal>,
             Option<Vec<u8>>,
             BlockNumber,
+            SubgraphHealth,
+            Option<BlockNumber>,
         )>(conn)
         .optional()?
     {
@@ -560,6 +1859,8 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
             latest_block_number,
             latest_block_hash,
             earliest_block_number,
+            health,
+            start_block_number,
         )) => {
             let reorg_count = convert_to_u32(Some(reorg_count), "reorg_count", id.as_str())?;
             let max_reorg_depth =
@@ -584,25 +1885,130 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
                 max_reorg_depth,
                 latest_block,
                 earliest_block_number,
+                // The earliest block starts out as the start block and
+                // only moves past it when the deployment is pruned
+                history_pruned: earliest_block_number > start_block_number.unwrap_or(0),
+                health: health.into(),
             })
         }
     }
 }
 
-/// Mark the deployment `id` as synced
-pub fn set_synced(conn: &PgConnection, id: &DeploymentHash) -> Result<(), StoreError> {
+/// Mark the deployment `id` as synced at `block_ptr`. Return `true` if it
+/// was not synced before. In that case, its synced actions also become
+/// pending, in the same transaction as the change of the flag, so that they
+/// are not lost if the node goes down before they ran
+pub fn set_synced(
+    conn: &PgConnection,
+    id: &DeploymentHash,
+    block_ptr: &BlockPtr,
+) -> Result<bool, StoreError> {
     use subgraph_deployment as d;
+    use synced_actions_pending as p;
 
-    update(
+    let ids = update(
         d::table
             .filter(d::deployment.eq(id.as_str()))
             .filter(d::synced.eq(false)),
     )
     .set(d::synced.eq(true))
-    .execute(conn)?;
+    .returning(d::id)
+    .get_results::<i32>(conn)?;
+    for id in &ids {
+        insert_into(p::table)
+            .values((
+                p::id.eq(id),
+                p::block_hash.eq(block_ptr.hash_slice()),
+                p::block_number.eq(block_ptr.number),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(!ids.is_empty())
+}
+
+/// The synced actions of the deployment that still have to run, if any
+pub fn synced_actions_pending(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<PendingSyncedActions>, StoreError> {
+    use synced_actions_pending as p;
+
+    Ok(p::table
+        .filter(p::id.eq(site.id))
+        .select((p::block_hash, p::block_number, p::done))
+        .first::<(Vec<u8>, i32, Vec<String>)>(conn)
+        .optional()?
+        .map(|(hash, number, done)| PendingSyncedActions {
+            block: BlockPtr::from((hash, number)),
+            done: done.into_iter().collect(),
+        }))
+}
+
+/// Record that the synced action `key` of the deployment ran to completion
+pub fn synced_action_done(conn: &PgConnection, site: &Site, key: &str) -> Result<(), StoreError> {
+    let query = "update subgraphs.synced_actions_pending
+                    set done = array_append(done, $2)
+                  where id = $1
+                    and not $2 = any(done)";
+    sql_query(query)
+        .bind::<Integer, _>(site.id)
+        .bind::<Text, _>(key)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that the synced actions of the deployment ran
+pub fn synced_actions_done(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use synced_actions_pending as p;
+
+    delete(p::table.filter(p::id.eq(site.id))).execute(conn)?;
     Ok(())
 }
 
+/// The labels on the deployment
+pub fn deployment_labels(conn: &PgConnection, site: &Site) -> Result<Vec<String>, StoreError> {
+    use deployment_label as l;
+
+    Ok(l::table
+        .filter(l::id.eq(site.id))
+        .select(l::label)
+        .order(l::label)
+        .load::<String>(conn)?)
+}
+
+/// Put `label` on the deployment. Return `false` if it already had it
+pub fn add_deployment_label(
+    conn: &PgConnection,
+    site: &Site,
+    label: &str,
+) -> Result<bool, StoreError> {
+    use deployment_label as l;
+
+    let count = insert_into(l::table)
+        .values((l::id.eq(site.id), l::label.eq(label)))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Take `label` off the deployment. Return `false` if it did not have it
+pub fn remove_deployment_label(
+    conn: &PgConnection,
+    site: &Site,
+    label: &str,
+) -> Result<bool, StoreError> {
+    use deployment_label as l;
+
+    let count = delete(
+        l::table
+            .filter(l::id.eq(site.id))
+            .filter(l::label.eq(label)),
+    )
+    .execute(conn)?;
+    Ok(count > 0)
+}
+
 /// Returns `true` if the deployment (as identified by `site.id`)
 pub fn exists(conn: &PgConnection, site: &Site) -> Result<bool, StoreError> {
     use subgraph_deployment as d;
@@ -641,6 +2047,8 @@ fn insert_subgraph_error(conn: &PgConnection, error: &SubgraphError) -> anyhow::
         handler,
         block_ptr,
         deterministic,
+        code,
+        provider,
     } = error;
 
     let block_num = match &block_ptr {
@@ -660,6 +2068,8 @@ fn insert_subgraph_error(conn: &PgConnection, error: &SubgraphError) -> anyhow::
             e::deterministic.eq(deterministic),
             e::block_hash.eq(block_ptr.as_ref().map(|ptr| ptr.hash_slice())),
             e::block_range.eq((Bound::Included(block_num), Bound::Unbounded)),
+            e::code.eq(code.as_str()),
+            e::provider.eq(provider),
         ))
         .on_conflict_do_nothing()
         .execute(conn)?;
@@ -871,10 +2281,10 @@ pub(crate) fn copy_errors(
     let query = format!(
         "\
       insert into subgraphs.subgraph_error(id,
-             subgraph_id, message, block_hash, handler, deterministic, block_range)
+             subgraph_id, message, block_hash, handler, deterministic, block_range, code, provider)
       select md5($2 || e.message || coalesce(e.block_hash, 'nohash') || coalesce(e.handler, 'nohandler') || e.deterministic) as id,
              $2 as subgraph_id, e.message, e.block_hash,
-             e.handler, e.deterministic, e.block_range
+             e.handler, e.deterministic, e.block_range, e.code, e.provider
         from {src_nsp}.subgraph_error e
        where e.subgraph_id = $1
          and lower(e.block_range) <= $3",
@@ -952,6 +2362,8 @@ pub fn create_deployment(
         graft_base,
         graft_block,
         debug_fork,
+        retention,
+        reorg_threshold,
     } = deployment;
     let earliest_block_number = start_block.as_ref().map(|ptr| ptr.number).unwrap_or(0);
     let entities_with_causality_region = Vec::from_iter(entities_with_causality_region.into_iter());
@@ -1010,6 +2422,17 @@ pub fn create_deployment(
             .values(manifest_values)
             .execute(conn)?;
     }
+
+    if exists && replace {
+        use entity_retention as r;
+        delete(r::table.filter(r::id.eq(site.id))).execute(conn)?;
+    }
+    for retention in &retention {
+        set_entity_retention(conn, site, retention)?;
+    }
+    if reorg_threshold.is_some() || (exists && replace) {
+        set_reorg_threshold(conn, site, reorg_threshold)?;
+    }
     Ok(())
 }
 
@@ -1070,6 +2493,17 @@ pub fn set_entity_count(
     Ok(())
 }
 
+/// Make the writer recount the deployment's entities the next time it
+/// changes the entity count; see `entity_count_sql`
+pub fn invalidate_entity_count(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::entity_count.eq(sql("-1")))
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn set_earliest_block(
     conn: &PgConnection,
     site: &Site,
@@ -5,37 +5,49 @@ use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use graph::anyhow::Context;
 use graph::blockchain::block_stream::FirehoseCursor;
-use graph::components::store::{EntityKey, EntityType, PruneReporter, StoredDynamicDataSource};
+use graph::components::metrics::usage::Usage;
+use graph::components::store::{
+    EntityKey, EntityType, PendingSyncedActions, PruneReporter, StoredDynamicDataSource,
+};
+use graph::components::subgraph::handler_entity_types::HandlerWrite;
 use graph::components::versions::VERSIONS;
 use graph::data::query::Trace;
-use graph::data::subgraph::{status, SPEC_VERSION_0_0_6};
+use graph::data::subgraph::invariant::{Invariant, InvariantOutcome};
+use graph::data::subgraph::retention::{EntityRetention, RetentionOutcome, RetentionRule};
+use graph::data::subgraph::{fingerprint::CompatibilityFingerprint, status, SPEC_VERSION_0_0_6};
+use graph::data::value::Word;
 use graph::data_source::CausalityRegion;
+use graph::prelude::chrono::{DateTime, Utc};
 use graph::prelude::{
     tokio, ApiVersion, CancelHandle, CancelToken, CancelableError, EntityOperation, PoolWaitStats,
     SubgraphDeploymentEntity,
 };
+use graph::runtime::CompileProfile;
 use graph::semver::Version;
+use graph::util::timed_cache::TimedCache;
 use lru_time_cache::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::Into;
 use std::iter::FromIterator;
 use std::ops::Bound;
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use graph::components::store::EntityCollection;
+use graph::components::store::{EntityCollection, RelatedEntityQuery};
 use graph::components::subgraph::{ProofOfIndexingFinisher, ProofOfIndexingVersion};
 use graph::constraint_violation;
-use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError, POI_OBJECT};
+use graph::data::subgraph::schema::{
+    DeploymentCreate, SubgraphError, SubgraphErrorCode, POI_OBJECT,
+};
 use graph::prelude::{
     anyhow, debug, info, o, warn, web3, ApiSchema, AttributeNames, BlockNumber, BlockPtr,
-    CheapClone, DeploymentHash, DeploymentState, Entity, EntityModification, EntityQuery, Error,
-    Logger, QueryExecutionError, Schema, StopwatchMetrics, StoreError, StoreEvent, UnfailOutcome,
-    Value, ENV_VARS,
+    CheapClone, DeploymentHash, DeploymentState, Entity, EntityFilter, EntityModification,
+    EntityOrder, EntityQuery, EntityRange, Error, Logger, QueryExecutionError, Schema,
+    StopwatchMetrics, StoreError, StoreEvent, UnfailOutcome, Value, ENV_VARS,
 };
 use graph_graphql::prelude::api_schema;
 use web3::types::Address;
@@ -46,10 +58,15 @@ use crate::deployment;
 use crate::detail::ErrorDetail;
 use crate::dynds::DataSourcesTable;
 use crate::relational::index::{CreateIndex, Method};
-use crate::relational::{Layout, LayoutCache, SqlName, Table};
+use crate::relational::{ColumnType, Layout, LayoutCache, RetentionCutoff, SqlName, Table};
 use crate::relational_queries::FromEntityData;
+use crate::removal::{self, RemovalStep};
 use crate::{connection_pool::ConnectionPool, detail};
-use crate::{dynds, primary::Site};
+use crate::{dynds, primary::DeploymentId, primary::Site};
+
+/// How long the results of `entity_type_stats` are reused before we ask
+/// the database again
+const ENTITY_TYPE_STATS_CACHE_TTL: Duration = Duration::from_secs(60);
 
 /// When connected to read replicas, this allows choosing which DB server to use for an operation.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -102,6 +119,15 @@ pub struct StoreInner {
     /// hosts this because it lives long enough, but it is managed from
     /// the entities module
     pub(crate) layout_cache: LayoutCache,
+
+    /// The row counts and last modifications of entity types for the
+    /// index node API, keyed by deployment and whether rows were counted
+    /// exactly
+    entity_type_stats: TimedCache<(DeploymentId, bool), Vec<status::EntityTypeStats>>,
+
+    /// The ids of block providers by label. Ids never change once they
+    /// are assigned, so entries never need to be invalidated
+    block_provider_ids: Mutex<HashMap<String, i16>>,
 }
 
 /// Storage of the data for individual deployments. Each `DeploymentStore`
@@ -157,6 +183,8 @@ impl DeploymentStore {
             conn_round_robin_counter: AtomicUsize::new(0),
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             layout_cache: LayoutCache::new(ENV_VARS.store.query_stats_refresh_interval),
+            entity_type_stats: TimedCache::new(ENTITY_TYPE_STATS_CACHE_TTL),
+            block_provider_ids: Mutex::new(HashMap::new()),
         };
 
         DeploymentStore(Arc::new(store))
@@ -237,6 +265,22 @@ impl DeploymentStore {
         })
     }
 
+    /// Drop the next table of the deployment `site`. Once all data is
+    /// gone, remove the metadata, too. This operation is not reversible
+    pub(crate) fn remove_deployment_step(&self, site: &Site) -> Result<RemovalStep, StoreError> {
+        let conn = self.get_conn()?;
+        let step = removal::remove_step(&conn, &site.namespace)?;
+        if step.done {
+            conn.transaction(|| {
+                if !site.schema_version.private_data_sources() {
+                    crate::dynds::shared::drop(&conn, &site.deployment)?;
+                }
+                crate::deployment::drop_metadata(&conn, site)
+            })?;
+        }
+        Ok(step)
+    }
+
     pub(crate) fn execute_query<T: FromEntityData>(
         &self,
         conn: &PgConnection,
@@ -637,9 +681,31 @@ impl DeploymentStore {
         deployment::exists_and_synced(&conn, id.as_str())
     }
 
-    pub(crate) fn deployment_synced(&self, id: &DeploymentHash) -> Result<(), StoreError> {
+    pub(crate) fn deployment_synced(
+        &self,
+        id: &DeploymentHash,
+        block_ptr: &BlockPtr,
+    ) -> Result<bool, StoreError> {
         let conn = self.get_conn()?;
-        conn.transaction(|| deployment::set_synced(&conn, id))
+        conn.transaction(|| deployment::set_synced(&conn, id, block_ptr))
+    }
+
+    pub(crate) fn synced_actions_pending(
+        &self,
+        site: &Site,
+    ) -> Result<Option<PendingSyncedActions>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::synced_actions_pending(&conn, site)
+    }
+
+    pub(crate) fn synced_action_done(&self, site: &Site, key: &str) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::synced_action_done(&conn, site, key)
+    }
+
+    pub(crate) fn synced_actions_done(&self, site: &Site) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::synced_actions_done(&conn, site)
     }
 
     // Only used for tests
@@ -672,7 +738,7 @@ impl DeploymentStore {
 
         let conn = self.get_conn()?;
         conn.batch_execute(QUERY)?;
-        conn.batch_execute("delete from deployment_schemas;")?;
+        conn.batch_execute("delete from deployment_schemas; delete from deployment_removal;")?;
         Ok(())
     }
 
@@ -861,6 +927,8 @@ impl DeploymentStore {
             let layout = store.layout(conn, site.clone())?;
             cancel.check_cancel()?;
             let state = deployment::state(conn, site.deployment.clone())?;
+            let reorg_threshold =
+                deployment::effective_reorg_threshold(conn, &site, reorg_threshold)?;
 
             if state.latest_block.number <= reorg_threshold {
                 return Ok(reporter);
@@ -883,12 +951,6 @@ impl DeploymentStore {
 
             cancel.check_cancel()?;
 
-            conn.transaction(|| {
-                deployment::set_earliest_block(conn, site.as_ref(), earliest_block)
-            })?;
-
-            cancel.check_cancel()?;
-
             layout.prune_by_copying(
                 &store.logger,
                 reporter.as_mut(),
@@ -898,6 +960,7 @@ impl DeploymentStore {
                 prune_ratio,
                 cancel,
             )?;
+            deployment::prune_invariant_aggregates(conn, &site, earliest_block)?;
             Ok(reporter)
         })
         .await
@@ -918,6 +981,13 @@ impl DeploymentStore {
         .await
     }
 
+    /// Like `block_ptr`, but for callers that are already running on a
+    /// thread where they can block
+    pub(crate) fn block_ptr_sync(&self, site: Arc<Site>) -> Result<Option<BlockPtr>, StoreError> {
+        let conn = self.get_conn()?;
+        Self::block_ptr_with_conn(&conn, site)
+    }
+
     pub(crate) async fn block_cursor(&self, site: Arc<Site>) -> Result<FirehoseCursor, StoreError> {
         let site = site.cheap_clone();
 
@@ -1070,6 +1140,96 @@ impl DeploymentStore {
         layout.find_many(&conn, ids_for_type, block)
     }
 
+    /// Retrieve the first `limit` entities, ordered by id, that match
+    /// `query` and whose id comes after `after` from the deployment `site`.
+    /// Only consider entities as of the given `block`
+    pub(crate) fn get_related(
+        &self,
+        site: Arc<Site>,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        let conn = self.get_conn()?;
+        let mut filter = EntityFilter::Equal(query.field.clone(), query.value.clone());
+        if let Some(after) = after {
+            filter = EntityFilter::And(vec![
+                filter,
+                EntityFilter::GreaterThan("id".to_string(), Value::from(after.as_str())),
+            ]);
+        }
+        let entity_query = EntityQuery::new(
+            site.deployment.cheap_clone(),
+            block,
+            EntityCollection::All(vec![(query.entity_type.clone(), AttributeNames::All)]),
+        )
+        .filter(filter)
+        .order(EntityOrder::Default)
+        .range(EntityRange {
+            first: Some(u32::try_from(limit).unwrap_or(u32::MAX)),
+            skip: 0,
+        });
+        let (entities, _) = self.execute_query::<Entity>(&conn, site, entity_query)?;
+
+        entities
+            .into_iter()
+            .map(|mut entity| -> Result<_, StoreError> {
+                entity.remove("__typename");
+                let key = query.key(entity.id()?.into());
+                Ok((key, entity))
+            })
+            .collect()
+    }
+
+    pub(crate) fn entity_type_stats(
+        &self,
+        site: Arc<Site>,
+        exact: bool,
+    ) -> Result<Vec<status::EntityTypeStats>, StoreError> {
+        let key = (site.id, exact);
+        if let Some(stats) = self.entity_type_stats.get(&key) {
+            return Ok(stats.as_ref().clone());
+        }
+
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        let stats = layout.entity_type_stats(&conn, exact)?;
+        self.entity_type_stats.set(key, Arc::new(stats.clone()));
+        Ok(stats)
+    }
+
+    /// Return the id of the provider with label `label`, consulting the
+    /// database only the first time a label is seen
+    fn block_provider_id(&self, conn: &PgConnection, label: &str) -> Result<i16, StoreError> {
+        if let Some(id) = self.block_provider_ids.lock().unwrap().get(label) {
+            return Ok(*id);
+        }
+        let id = deployment::block_provider_id(conn, label)?;
+        self.block_provider_ids
+            .lock()
+            .unwrap()
+            .insert(label.to_string(), id);
+        Ok(id)
+    }
+
+    pub(crate) fn block_providers(
+        &self,
+        site: Arc<Site>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<status::BlockProvider>, StoreError> {
+        let conn = self.get_conn()?;
+        let providers = deployment::block_providers(&conn, &site, from, to)?
+            .into_iter()
+            .map(|(block_number, provider)| status::BlockProvider {
+                block_number,
+                provider,
+            })
+            .collect();
+        Ok(providers)
+    }
+
     pub(crate) fn get_changes(
         &self,
         site: Arc<Site>,
@@ -1094,6 +1254,11 @@ impl DeploymentStore {
             .map(|(entities, _)| entities)
     }
 
+    /// Write the changes of a block and check `invariants` against the
+    /// entities with the changes written. If a strict invariant is
+    /// violated, only the PoI of the block is written, the deployment fails
+    /// with a deterministic error at the block, and that error is returned
+    /// next to the outcomes of the checks
     pub(crate) fn transact_block_operations(
         &self,
         site: Arc<Site>,
@@ -1105,32 +1270,134 @@ impl DeploymentStore {
         deterministic_errors: &[SubgraphError],
         manifest_idx_and_name: &[(u32, String)],
         processed_data_sources: &[StoredDynamicDataSource],
-    ) -> Result<StoreEvent, StoreError> {
+        block_provider: Option<&str>,
+        invariants: &[Invariant],
+    ) -> Result<
+        (
+            StoreEvent,
+            Vec<(String, InvariantOutcome)>,
+            Option<SubgraphError>,
+        ),
+        StoreError,
+    > {
         let conn = {
             let _section = stopwatch.start_section("transact_blocks_get_conn");
             self.get_conn()?
         };
 
-        let event = deployment::with_lock(&conn, &site, || {
-            conn.transaction(|| -> Result<_, StoreError> {
-                // Emit a store event for the changes we are about to make. We
-                // wait with sending it until we have done all our other work
-                // so that we do not hold a lock on the notification queue
-                // for longer than we have to
-                let event: StoreEvent = StoreEvent::from_mods(&site.deployment, mods);
+        // Look the provider up outside of the transaction so that a
+        // provider that is registered here stays registered even if the
+        // transaction is rolled back
+        let provider_label = block_provider;
+        let block_provider = match block_provider {
+            Some(label) if ENV_VARS.store.block_provider_history > 0 => {
+                Some(self.block_provider_id(&conn, label)?)
+            }
+            _ => None,
+        };
 
-                // Make the changes
+        let result = deployment::with_lock(&conn, &site, || {
+            conn.transaction(|| -> Result<_, StoreError> {
                 let layout = self.layout(&conn, site.clone())?;
 
-                let section = stopwatch.start_section("apply_entity_modifications");
-                let count = self.apply_entity_modifications(
+                // Make the changes in a savepoint so that we can undo them
+                // if they violate a strict invariant
+                let mut outcomes = Vec::new();
+                let written = conn.transaction(|| -> Result<_, StoreError> {
+                    let section = stopwatch.start_section("apply_entity_modifications");
+                    let count = self.apply_entity_modifications(
+                        &conn,
+                        layout.as_ref(),
+                        mods,
+                        block_ptr_to,
+                        stopwatch,
+                    )?;
+                    section.end();
+
+                    if !invariants.is_empty() {
+                        let _section = stopwatch.start_section("check_invariants");
+                        outcomes =
+                            layout.check_invariants(&conn, invariants, block_ptr_to.number)?;
+                    }
+                    let violation = invariants.iter().zip(outcomes.iter()).find_map(
+                        |(invariant, (_, outcome))| match outcome {
+                            InvariantOutcome::Violated { left, right } if invariant.strict => {
+                                Some(SubgraphError {
+                                    subgraph_id: site.deployment.clone(),
+                                    message: format!(
+                                        "invariant `{}` is violated: {}",
+                                        invariant.name,
+                                        invariant.check.violation_message(left, right)
+                                    ),
+                                    block_ptr: Some(block_ptr_to.clone()),
+                                    handler: None,
+                                    deterministic: true,
+                                    code: SubgraphErrorCode::InvariantViolated,
+                                    provider: provider_label.map(str::to_string),
+                                })
+                            }
+                            _ => None,
+                        },
+                    );
+                    match violation {
+                        Some(error) => Err(StoreError::InvariantViolated(error)),
+                        None => Ok(count),
+                    }
+                });
+
+                let count = match written {
+                    Ok(count) => count,
+                    Err(StoreError::InvariantViolated(error)) => {
+                        // Keep the PoI so that the deployment can advance
+                        // to the block where it failed, like it does for
+                        // any other deterministic error
+                        let pois: Vec<_> = mods
+                            .iter()
+                            .filter(|modification| {
+                                modification.entity_ref().entity_type == *POI_OBJECT
+                            })
+                            .cloned()
+                            .collect();
+                        let count = self.apply_entity_modifications(
+                            &conn,
+                            layout.as_ref(),
+                            &pois,
+                            block_ptr_to,
+                            stopwatch,
+                        )?;
+                        deployment::fail(&conn, &site.deployment, &error)?;
+                        deployment::transact_block(
+                            &conn,
+                            &site,
+                            block_ptr_to,
+                            firehose_cursor,
+                            layout.count_query.as_str(),
+                            count,
+                        )?;
+                        let event = StoreEvent::from_mods(&site.deployment, &pois);
+                        return Ok((event, outcomes, Some(error)));
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                // Emit a store event for the changes we made. We wait with
+                // sending it until we have done all our other work so that
+                // we do not hold a lock on the notification queue for
+                // longer than we have to
+                let event: StoreEvent = StoreEvent::from_mods(&site.deployment, mods);
+
+                let entity_types: BTreeSet<_> = mods
+                    .iter()
+                    .map(|modification| &modification.entity_ref().entity_type)
+                    .filter(|entity_type| *entity_type != &*POI_OBJECT)
+                    .map(|entity_type| entity_type.as_str())
+                    .collect();
+                deployment::record_entity_types_modified(
                     &conn,
-                    layout.as_ref(),
-                    mods,
-                    block_ptr_to,
-                    stopwatch,
+                    &site,
+                    &entity_types.into_iter().collect::<Vec<_>>(),
+                    block_ptr_to.number,
                 )?;
-                section.end();
 
                 dynds::insert(
                     &conn,
@@ -1142,6 +1409,16 @@ impl DeploymentStore {
 
                 dynds::update_offchain_status(&conn, &site, processed_data_sources)?;
 
+                if let Some(provider) = block_provider {
+                    deployment::record_block_provider(
+                        &conn,
+                        &site,
+                        block_ptr_to.number,
+                        provider,
+                        ENV_VARS.store.block_provider_history,
+                    )?;
+                }
+
                 if !deterministic_errors.is_empty() {
                     deployment::insert_subgraph_errors(
                         &conn,
@@ -1151,6 +1428,27 @@ impl DeploymentStore {
                     )?;
                 }
 
+                for (invariant, (_, outcome)) in invariants.iter().zip(outcomes.iter()) {
+                    match outcome {
+                        InvariantOutcome::Holds => {
+                            deployment::clear_invariant_violation(&conn, &site, &invariant.name)?
+                        }
+                        InvariantOutcome::Violated { left, right } => {
+                            deployment::record_invariant_violation(
+                                &conn,
+                                &site,
+                                &invariant.name,
+                                &invariant.check.to_string(),
+                                block_ptr_to.number,
+                                &invariant.check.violation_message(left, right),
+                            )?
+                        }
+                        // We can't tell whether the invariant holds; leave
+                        // what we recorded before alone
+                        InvariantOutcome::Skipped(_) => {}
+                    }
+                }
+
                 deployment::transact_block(
                     &conn,
                     &site,
@@ -1160,11 +1458,11 @@ impl DeploymentStore {
                     count,
                 )?;
 
-                Ok(event)
+                Ok((event, outcomes, None))
             })
         })?;
 
-        Ok(event)
+        Ok(result)
     }
 
     fn rewind_with_conn(
@@ -1192,6 +1490,20 @@ impl DeploymentStore {
                     }
                 }
 
+                // Don't revert past history that pruning already removed;
+                // the data we would end up with would be wrong
+                let state = deployment::state(conn, site.deployment.clone())?;
+                if state.history_pruned && block_ptr_to.number < state.earliest_block_number {
+                    return Err(anyhow!(
+                        "Can not revert subgraph `{}` to block {} as its history \
+                         before block {} has been pruned",
+                        site.deployment.clone(),
+                        block_ptr_to.number,
+                        state.earliest_block_number
+                    )
+                    .into());
+                }
+
                 // The revert functions want the number of the first block that we need to get rid of
                 let block = block_ptr_to.number + 1;
 
@@ -1202,6 +1514,21 @@ impl DeploymentStore {
                     firehose_cursor,
                 )?;
 
+                // A reorg that is deeper than the reorg threshold the
+                // deployment set for itself means that the threshold was too
+                // optimistic; go back to the one of the chain so that pruning
+                // keeps enough history for the next one
+                if let Some(threshold) = deployment::reorg_threshold(conn, &site)? {
+                    let depth = deployment::current_reorg_depth(conn, &site)?;
+                    if depth > threshold {
+                        warn!(self.logger, "Reorg is deeper than the reorg threshold of the deployment, using the reorg threshold of the chain from now on";
+                            "sgd" => site.id.to_string(),
+                            "depth" => depth,
+                            "reorg_threshold" => threshold);
+                        deployment::set_reorg_threshold(conn, &site, None)?;
+                    }
+                }
+
                 // Revert the data
                 let layout = self.layout(conn, site.clone())?;
 
@@ -1214,6 +1541,10 @@ impl DeploymentStore {
                 // changes that might need to be reverted
                 Layout::revert_metadata(conn, &site, block)?;
 
+                // The providers of reverted blocks would otherwise still be
+                // reported, e.g., as the provider of the latest block
+                deployment::revert_block_providers(conn, &site, block)?;
+
                 deployment::update_entity_count(
                     conn,
                     site.as_ref(),
@@ -1224,6 +1555,11 @@ impl DeploymentStore {
             })
         })?;
 
+        // The last modifications of entity types that we cached might be
+        // for blocks that were just reverted
+        self.entity_type_stats.remove(&(site.id, true));
+        self.entity_type_stats.remove(&(site.id, false));
+
         Ok(event)
     }
 
@@ -1655,6 +1991,403 @@ impl DeploymentStore {
         });
     }
 
+    pub(crate) fn record_node_version(
+        &self,
+        site: Arc<Site>,
+        host_export_hash: &str,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::record_node_version(&conn, &site, host_export_hash))
+    }
+
+    pub(crate) fn check_compatibility(
+        &self,
+        site: Arc<Site>,
+        current: &CompatibilityFingerprint,
+    ) -> Result<Option<CompatibilityFingerprint>, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::check_compatibility(&conn, &site, current))
+    }
+
+    pub(crate) fn compatibility(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Option<(CompatibilityFingerprint, Option<CompatibilityFingerprint>)>, StoreError>
+    {
+        let conn = self.get_conn()?;
+        deployment::compatibility(&conn, &site)
+    }
+
+    pub(crate) fn acknowledge_compatibility(
+        &self,
+        site: Arc<Site>,
+        note: Option<&str>,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::acknowledge_compatibility(&conn, &site, note)
+    }
+
+    pub(crate) fn compile_profile(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Option<CompileProfile>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::compile_profile(&conn, &site)
+    }
+
+    pub(crate) fn set_compile_profile(
+        &self,
+        site: Arc<Site>,
+        profile: Option<CompileProfile>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::set_compile_profile(&conn, &site, profile)
+    }
+
+    pub(crate) fn reorg_threshold(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::reorg_threshold(&conn, &site)
+    }
+
+    pub(crate) fn set_reorg_threshold(
+        &self,
+        site: Arc<Site>,
+        reorg_threshold: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::set_reorg_threshold(&conn, &site, reorg_threshold)
+    }
+
+    pub(crate) fn record_compilation(
+        &self,
+        site: Arc<Site>,
+        profile: CompileProfile,
+        compile_time: Duration,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::record_compilation(&conn, &site, profile, compile_time)
+    }
+
+    pub(crate) fn compilation(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<(Option<CompileProfile>, Option<(CompileProfile, Duration)>), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::compilation(&conn, &site)
+    }
+
+    pub(crate) fn entity_retention(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Vec<EntityRetention>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::entity_retention(&conn, &site)
+    }
+
+    /// Put the retention policies of the deployment into effect and
+    /// return them
+    pub(crate) fn start_entity_retention(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Vec<EntityRetention>, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::activate_entity_retention(&conn, &site))
+    }
+
+    pub(crate) fn set_entity_retention(
+        &self,
+        site: Arc<Site>,
+        retention: &EntityRetention,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.cheap_clone())?;
+        let table = layout.table_for_entity(&retention.entity_type.as_str().into())?;
+        if let RetentionRule::Age { field, .. } = &retention.rule {
+            let column = table.column_for_field(field)?;
+            if !matches!(column.column_type, ColumnType::Int | ColumnType::BigInt)
+                || column.is_list()
+            {
+                return Err(StoreError::Unknown(anyhow!(
+                    "the timestamp field `{}.{}` must be an Int or BigInt",
+                    retention.entity_type,
+                    field
+                )));
+            }
+        }
+        deployment::set_entity_retention(&conn, &site, retention)
+    }
+
+    pub(crate) fn remove_entity_retention(
+        &self,
+        site: Arc<Site>,
+        entity_type: &str,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::remove_entity_retention(&conn, &site, entity_type)
+    }
+
+    pub(crate) fn retained_deployments(&self) -> Result<Vec<DeploymentId>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::retained_deployments(&conn)
+    }
+
+    /// Whether the average wait for a connection to this shard is so long
+    /// that maintenance work should wait for a quieter time
+    fn is_busy(&self) -> Result<bool, StoreError> {
+        let wait_stats = self.pool.wait_stats()?;
+        let busy = wait_stats
+            .read()
+            .unwrap()
+            .average_gt(ENV_VARS.store.retention_max_pool_wait);
+        Ok(busy)
+    }
+
+    /// Apply the retention policies of the deployment: remove the rows
+    /// they match in batches until all of them are gone, `deadline` has
+    /// passed, or the shard gets busy. Only rows written, or for mutable
+    /// entity types replaced, at least `reorg_threshold` blocks before the
+    /// latest block are removed, and current versions of mutable entities
+    /// are always kept. Only policies that the deployment was started with
+    /// are applied. With `dry_run`, only report what would be removed
+    pub(crate) async fn apply_retention(
+        self: &Arc<Self>,
+        logger: Logger,
+        site: Arc<Site>,
+        reorg_threshold: BlockNumber,
+        deadline: Instant,
+        dry_run: bool,
+    ) -> Result<Vec<RetentionOutcome>, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, cancel| {
+            let policies = deployment::active_entity_retention(conn, &site)?;
+            if policies.is_empty() {
+                return Ok(Vec::new());
+            }
+            let layout = store.layout(conn, site.clone())?;
+            let state = deployment::state(conn, site.deployment.clone())?;
+            let reorg_threshold =
+                deployment::effective_reorg_threshold(conn, &site, reorg_threshold)?;
+            let cutoff = RetentionCutoff {
+                latest_block: state.latest_block.number,
+                final_block: state.latest_block.number - reorg_threshold,
+            };
+
+            let mut outcomes = Vec::new();
+            let mut removed_any = false;
+            for retention in policies {
+                cancel.check_cancel()?;
+                let filter = layout.retention_filter(conn, &retention, cutoff)?;
+                let (rows, newest_block) = layout.retained_rows(conn, &filter)?;
+                let loses_history =
+                    newest_block.map_or(false, |block| state.earliest_block_number <= block);
+                let mut outcome = RetentionOutcome {
+                    retention,
+                    rows,
+                    newest_block,
+                    loses_history,
+                    complete: rows == 0,
+                };
+                if dry_run || rows == 0 {
+                    outcomes.push(outcome);
+                    continue;
+                }
+
+                let entity_type = outcome.retention.entity_type.as_str();
+                if loses_history {
+                    if ENV_VARS.store.retention_strict_history {
+                        warn!(logger, "Not applying retention policy since time-travel queries can still see the rows it would remove";
+                              "entity_type" => entity_type,
+                              "rows" => rows,
+                              "earliest_block" => state.earliest_block_number);
+                        outcome.rows = 0;
+                        outcomes.push(outcome);
+                        continue;
+                    }
+                    warn!(logger, "Applying retention policy removes rows that time-travel queries can still see";
+                          "entity_type" => entity_type,
+                          "rows" => rows,
+                          "earliest_block" => state.earliest_block_number);
+                }
+
+                let mut removed = 0;
+                while removed < rows && Instant::now() < deadline && !store.is_busy()? {
+                    let batch = conn.transaction(|| {
+                        layout.prune_retained(conn, &filter, ENV_VARS.store.retention_batch_size)
+                    })?;
+                    cancel.check_cancel()?;
+                    if batch == 0 {
+                        break;
+                    }
+                    removed += batch;
+                }
+                removed_any = removed_any || removed > 0;
+                outcome.complete = removed >= rows;
+                outcome.rows = removed;
+                outcomes.push(outcome);
+            }
+            if removed_any {
+                deployment::invalidate_entity_count(conn, &site)?;
+            }
+            Ok(outcomes)
+        })
+        .await
+    }
+
+    pub(crate) fn record_usage(&self, site: Arc<Site>, usage: &Usage) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::record_usage(&conn, &site, usage))
+    }
+
+    pub(crate) async fn expire_usage(&self, retention_days: u32) -> Result<usize, StoreError> {
+        self.with_conn(move |conn, _| {
+            deployment::expire_usage(conn, retention_days).map_err(Into::into)
+        })
+        .await
+    }
+
+    /// Add `stats` to the statistics of the handlers of the deployment and
+    /// return the resulting totals
+    pub(crate) fn record_handler_stats(
+        &self,
+        site: Arc<Site>,
+        stats: &[status::HandlerStats],
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            deployment::record_handler_stats(&conn, &site, stats)?;
+            let mut reports = detail::handler_stats(&conn, &[site.cheap_clone()])?;
+            Ok(reports.remove(&site.id).unwrap_or_default())
+        })
+    }
+
+    pub(crate) fn handler_stats(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        let conn = self.get_conn()?;
+        let mut reports = detail::handler_stats(&conn, &[site.cheap_clone()])?;
+        Ok(reports.remove(&site.id).unwrap_or_default())
+    }
+
+    pub(crate) fn reset_handler_stats(&self, site: Arc<Site>) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::reset_handler_stats(&conn, &site)
+    }
+
+    pub(crate) fn record_handler_entity_types(
+        &self,
+        site: Arc<Site>,
+        writes: &[HandlerWrite],
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::record_handler_entity_types(&conn, &site, writes)
+    }
+
+    pub(crate) fn record_bus_backends(
+        &self,
+        site: Arc<Site>,
+        backends: &[String],
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::record_bus_backends(&conn, &site, backends)
+    }
+
+    pub(crate) fn bus_critical_entity_types(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<BTreeSet<EntityType>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::bus_critical_entity_types(&conn, &site)
+    }
+
+    pub(crate) fn set_bus_critical_entity_types(
+        &self,
+        site: Arc<Site>,
+        entity_types: &BTreeSet<EntityType>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.cheap_clone())?;
+        for entity_type in entity_types {
+            layout.table_for_entity(entity_type)?;
+        }
+        conn.transaction(|| deployment::set_bus_critical_entity_types(&conn, &site, entity_types))
+    }
+
+    pub(crate) fn record_bus_critical_delivery(
+        &self,
+        site: Arc<Site>,
+        backend: &str,
+        entity_types: &BTreeSet<EntityType>,
+        block: BlockNumber,
+        delivered: bool,
+    ) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            deployment::record_bus_critical_delivery(
+                &conn,
+                &site,
+                backend,
+                entity_types,
+                block,
+                delivered,
+            )
+        })
+    }
+
+    pub(crate) fn acknowledge_bus_dead_letters(
+        &self,
+        site: Arc<Site>,
+        backend: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::acknowledge_bus_dead_letters(&conn, &site, backend)
+    }
+
+    pub(crate) fn deployment_labels(&self, site: Arc<Site>) -> Result<Vec<String>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::deployment_labels(&conn, &site)
+    }
+
+    pub(crate) fn add_deployment_label(
+        &self,
+        site: Arc<Site>,
+        label: &str,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::add_deployment_label(&conn, &site, label)
+    }
+
+    pub(crate) fn remove_deployment_label(
+        &self,
+        site: Arc<Site>,
+        label: &str,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::remove_deployment_label(&conn, &site, label)
+    }
+
+    pub(crate) fn record_poi_divergence(
+        &self,
+        site: Arc<Site>,
+        divergence: Option<&status::PoiDivergence>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::record_poi_divergence(&conn, &site, divergence)
+    }
+
+    pub(crate) fn usage(
+        &self,
+        sites: &[Arc<Site>],
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<status::UsageRecord>, StoreError> {
+        let conn = self.get_conn()?;
+        detail::usage(&conn, sites, from, to)
+    }
+
     pub(crate) async fn health(
         &self,
         site: &Site,
@@ -11,19 +11,26 @@ use diesel_derives::Associations;
 use git_testament::{git_testament, git_testament_macros};
 use graph::blockchain::BlockHash;
 use graph::components::store::EntityType;
-use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode, SubgraphManifestEntity};
 use graph::prelude::{
-    bigdecimal::ToPrimitive, BigDecimal, BlockPtr, DeploymentHash, StoreError,
-    SubgraphDeploymentEntity,
+    bigdecimal::ToPrimitive,
+    chrono::{DateTime, Utc},
+    BigDecimal, BlockNumber, BlockPtr, DeploymentHash, StoreError, SubgraphDeploymentEntity,
 };
 use graph::{constraint_violation, data::subgraph::status, prelude::web3::types::H256};
 use itertools::Itertools;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::str::FromStr;
 use std::{ops::Bound, sync::Arc};
 
+use crate::copy::copy_table_state;
 use crate::deployment::{
-    graph_node_versions, subgraph_deployment, subgraph_error, subgraph_manifest,
-    SubgraphHealth as HealthType,
+    block_provider, deployment_block_provider, deployment_bus, deployment_bus_critical,
+    deployment_bus_critical_delivery, deployment_handler_stats, deployment_poi_divergence,
+    deployment_reorg_threshold, deployment_usage, graph_node_versions, handler_entity_types,
+    invariant_violation, subgraph_deployment, subgraph_deployment_node_versions, subgraph_error,
+    subgraph_manifest, SubgraphHealth as HealthType,
 };
 use crate::primary::{DeploymentId, Site};
 
@@ -81,6 +88,8 @@ pub(crate) struct ErrorDetail {
     handler: Option<String>,
     pub deterministic: bool,
     pub block_range: (Bound<i32>, Bound<i32>),
+    code: Option<String>,
+    provider: Option<String>,
 }
 
 impl ErrorDetail {
@@ -116,6 +125,8 @@ impl TryFrom<ErrorDetail> for SubgraphError {
             handler,
             deterministic,
             block_range,
+            code,
+            provider,
         } = value;
         let block_number = crate::block_range::first_block_in_range(&block_range);
         // FIXME:
@@ -133,12 +144,19 @@ impl TryFrom<ErrorDetail> for SubgraphError {
         let subgraph_id = DeploymentHash::new(subgraph_id).map_err(|id| {
             StoreError::ConstraintViolation(format!("invalid subgraph id `{}` in fatal error", id))
         })?;
+        // Errors recorded before codes existed have none, and codes that
+        // this version does not know are treated the same
+        let code = code
+            .and_then(|code| SubgraphErrorCode::from_str(&code).ok())
+            .unwrap_or_default();
         Ok(SubgraphError {
             subgraph_id,
             message,
             block_ptr,
             handler,
             deterministic,
+            code,
+            provider,
         })
     }
 }
@@ -181,6 +199,9 @@ pub(crate) fn info_from_details(
     detail: DeploymentDetail,
     fatal: Option<ErrorDetail>,
     non_fatal: Vec<ErrorDetail>,
+    node_versions: Vec<status::NodeVersion>,
+    copy_integrity: Vec<status::CopyIntegrity>,
+    handler_entity_types: Vec<status::HandlerWrites>,
     sites: &[Arc<Site>],
 ) -> Result<status::Info, StoreError> {
     let DeploymentDetail {
@@ -244,7 +265,19 @@ pub(crate) fn info_from_details(
         non_fatal_errors,
         chains: vec![chain],
         entity_count,
+        store_unavailable: None,
         node: None,
+        node_versions,
+        copy_integrity,
+        handler_entity_types,
+        bus_status: None,
+        poi_divergence: None,
+        invariant_violations: vec![],
+        mapping_terminations: vec![],
+        wasm_performance: None,
+        handler_stats: status::HandlerStatsReport::default(),
+        reorg_threshold: None,
+        latest_block_provider: None,
     })
 }
 
@@ -312,11 +345,447 @@ pub(crate) fn deployment_statuses(
         .into_group_map()
     };
 
+    let mut node_versions = node_versions(conn, sites)?;
+    let mut copy_integrity = copy_integrity(conn, sites)?;
+    let mut handler_entity_types = handler_entity_types(conn, sites)?;
+    let mut handler_stats = handler_stats(conn, sites)?;
+    let mut bus_backends = bus_backends(conn, sites)?;
+    let mut bus_critical = bus_critical(conn, sites)?;
+    let mut poi_divergences = poi_divergences(conn, sites)?;
+    let mut invariant_violations = invariant_violations(conn, sites)?;
+    let mut reorg_thresholds = reorg_thresholds(conn, sites)?;
+    let mut latest_block_providers = latest_block_providers(conn, sites)?;
+
     details_with_fatal_error
         .into_iter()
         .map(|(detail, fatal)| {
             let non_fatal = non_fatal_errors.remove(&detail.id).unwrap_or(vec![]);
-            info_from_details(detail, fatal, non_fatal, sites)
+            let node_versions = node_versions.remove(&detail.id).unwrap_or(vec![]);
+            let copy_integrity = copy_integrity.remove(&detail.id).unwrap_or(vec![]);
+            let handler_entity_types = handler_entity_types.remove(&detail.id).unwrap_or(vec![]);
+            let (critical_types, mut deliveries) =
+                bus_critical.remove(&detail.id).unwrap_or_default();
+            let bus_status = bus_backends.remove(&detail.id).map(|backends| {
+                let critical = critical_types
+                    .iter()
+                    .flat_map(|entity_type| {
+                        backends.iter().map(move |backend| (entity_type, backend))
+                    })
+                    .map(|(entity_type, backend)| {
+                        let (confirmed, dead_lettered) = deliveries
+                            .remove(&(entity_type.clone(), backend.clone()))
+                            .unwrap_or_default();
+                        status::CriticalTypeDelivery {
+                            entity_type: entity_type.clone(),
+                            backend: backend.clone(),
+                            last_confirmed_block: confirmed,
+                            dead_lettered_block: dead_lettered,
+                        }
+                    })
+                    .collect();
+                status::BusStatus { backends, critical }
+            });
+            let poi_divergence = poi_divergences.remove(&detail.id);
+            let invariant_violations = invariant_violations.remove(&detail.id).unwrap_or(vec![]);
+            let reorg_threshold = reorg_thresholds.remove(&detail.id);
+            let latest_block_provider = latest_block_providers.remove(&detail.id);
+            let handler_stats = handler_stats.remove(&detail.id).unwrap_or_default();
+            info_from_details(
+                detail,
+                fatal,
+                non_fatal,
+                node_versions,
+                copy_integrity,
+                handler_entity_types,
+                sites,
+            )
+            .map(|info| status::Info {
+                bus_status,
+                poi_divergence,
+                invariant_violations,
+                reorg_threshold,
+                latest_block_provider,
+                handler_stats,
+                ..info
+            })
+        })
+        .collect()
+}
+
+/// Return the versions of graph-node that indexed each of `sites`, in the
+/// order in which they started indexing them. If `sites` is empty, return
+/// the versions for all deployments
+fn node_versions(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, Vec<status::NodeVersion>>, StoreError> {
+    use graph_node_versions as v;
+    use subgraph_deployment_node_versions as nv;
+
+    type Row = (
+        DeploymentId,
+        String,
+        String,
+        String,
+        Option<i32>,
+        Option<i32>,
+    );
+
+    let query = nv::table
+        .inner_join(v::table.on(v::id.eq(nv::graph_node_version_id)))
+        .select((
+            nv::id,
+            v::git_commit_hash,
+            v::crate_version,
+            nv::host_export_hash,
+            nv::start_block_number,
+            nv::end_block_number,
+        ))
+        .order_by(nv::vid);
+
+    let rows = if sites.is_empty() {
+        query.load::<Row>(conn)?
+    } else {
+        query
+            .filter(nv::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<Row>(conn)?
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(id, git_commit_hash, crate_version, host_export_hash, start_block, end_block)| {
+                let version = status::NodeVersion {
+                    git_commit_hash,
+                    crate_version,
+                    host_export_hash,
+                    start_block,
+                    end_block,
+                };
+                (id, version)
+            },
+        )
+        .into_group_map())
+}
+
+/// Return the results of the integrity check that ran after copying data
+/// into each of `sites`. Deployments that were not created by copying or
+/// grafting, or where the check has not run, have no entry. If `sites` is
+/// empty, return the results for all deployments
+fn copy_integrity(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, Vec<status::CopyIntegrity>>, StoreError> {
+    use copy_table_state as cts;
+
+    type Row = (
+        DeploymentId,
+        String,
+        Option<i64>,
+        Option<i64>,
+        Option<String>,
+        Option<String>,
+    );
+
+    let query = cts::table
+        .filter(cts::checked_at.is_not_null())
+        .select((
+            cts::dst,
+            cts::entity_type,
+            cts::src_count,
+            cts::dst_count,
+            cts::src_checksum,
+            cts::dst_checksum,
+        ))
+        .order_by((cts::dst, cts::entity_type));
+
+    let rows = if sites.is_empty() {
+        query.load::<Row>(conn)?
+    } else {
+        query
+            .filter(cts::dst.eq_any(sites.iter().map(|site| site.id)))
+            .load::<Row>(conn)?
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(id, entity_type, src_count, dst_count, src_checksum, dst_checksum)| {
+                let integrity = status::CopyIntegrity {
+                    entity_type,
+                    source_count: src_count.unwrap_or(0),
+                    destination_count: dst_count.unwrap_or(0),
+                    source_checksum: src_checksum,
+                    destination_checksum: dst_checksum,
+                };
+                (id, integrity)
+            },
+        )
+        .into_group_map())
+}
+
+/// Return the entity types that the handlers of each of `sites` were
+/// observed writing. If `sites` is empty, return them for all deployments
+fn handler_entity_types(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, Vec<status::HandlerWrites>>, StoreError> {
+    use handler_entity_types as h;
+
+    let query = h::table
+        .select((h::id, h::data_source, h::handler, h::entity_type))
+        .order_by((h::id, h::data_source, h::handler, h::entity_type));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, String, String, String)>(conn)?
+    } else {
+        query
+            .filter(h::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, String, String, String)>(conn)?
+    };
+
+    // The rows are sorted, so grouping consecutive rows groups all rows
+    // for the same deployment and handler
+    let mut writes: HashMap<DeploymentId, Vec<status::HandlerWrites>> = HashMap::new();
+    for ((id, data_source, handler), rows) in &rows
+        .into_iter()
+        .group_by(|(id, data_source, handler, _)| (*id, data_source.clone(), handler.clone()))
+    {
+        let entity_types = rows.map(|(_, _, _, entity_type)| entity_type).collect();
+        writes.entry(id).or_default().push(status::HandlerWrites {
+            data_source,
+            handler,
+            entity_types,
+        });
+    }
+    Ok(writes)
+}
+
+/// Return the cumulative statistics of the handlers of each of `sites`.
+/// If `sites` is empty, return them for all deployments
+pub(crate) fn handler_stats(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, status::HandlerStatsReport>, StoreError> {
+    use deployment_handler_stats as s;
+
+    let query = s::table
+        .select((
+            s::id,
+            s::handler,
+            s::invocations,
+            s::execution_us,
+            s::gas,
+            s::errors,
+            s::since,
+        ))
+        .order_by((s::id, s::handler));
+
+    type Row = (DeploymentId, String, i64, i64, i64, i64, DateTime<Utc>);
+    let rows = if sites.is_empty() {
+        query.load::<Row>(conn)?
+    } else {
+        query
+            .filter(s::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<Row>(conn)?
+    };
+
+    let mut reports: HashMap<DeploymentId, status::HandlerStatsReport> = HashMap::new();
+    for (id, handler, invocations, execution_us, gas, errors, since) in rows {
+        let report = reports.entry(id).or_default();
+        // The statistics of the deployment start with its oldest handler
+        report.since = Some(report.since.map_or(since, |s| s.min(since)));
+        report.handlers.push(status::HandlerStats {
+            handler,
+            invocations: invocations.max(0) as u64,
+            execution_time: std::time::Duration::from_micros(execution_us.max(0) as u64),
+            gas: gas.max(0) as u64,
+            errors: errors.max(0) as u64,
+        });
+    }
+    Ok(reports)
+}
+
+/// Return the bus backends that each of `sites` publishes to. If `sites`
+/// is empty, return them for all deployments
+fn bus_backends(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, Vec<String>>, StoreError> {
+    use deployment_bus as b;
+
+    let query = b::table.select((b::id, b::backends));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, Vec<String>)>(conn)?
+    } else {
+        query
+            .filter(b::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, Vec<String>)>(conn)?
+    };
+    Ok(rows.into_iter().collect())
+}
+
+/// The confirmed and dead-lettered block of a critical entity type, by
+/// entity type and backend
+type CriticalDeliveries = HashMap<(String, String), (Option<i32>, Option<i32>)>;
+
+/// Return the critical entity types of each of `sites`, sorted by name,
+/// and how far their changes were delivered to each backend. If `sites`
+/// is empty, return them for all deployments
+fn bus_critical(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, (Vec<String>, CriticalDeliveries)>, StoreError> {
+    use deployment_bus_critical as c;
+    use deployment_bus_critical_delivery as d;
+
+    let types = c::table
+        .select((c::id, c::entity_type))
+        .order_by((c::id, c::entity_type));
+    let deliveries = d::table.select((
+        d::id,
+        d::entity_type,
+        d::backend,
+        d::confirmed_block,
+        d::dead_lettered_block,
+    ));
+
+    let (types, deliveries) = if sites.is_empty() {
+        (
+            types.load::<(DeploymentId, String)>(conn)?,
+            deliveries.load::<(DeploymentId, String, String, Option<i32>, Option<i32>)>(conn)?,
+        )
+    } else {
+        (
+            types
+                .filter(c::id.eq_any(sites.iter().map(|site| site.id)))
+                .load::<(DeploymentId, String)>(conn)?,
+            deliveries
+                .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
+                .load::<(DeploymentId, String, String, Option<i32>, Option<i32>)>(conn)?,
+        )
+    };
+
+    let mut critical: HashMap<DeploymentId, (Vec<String>, CriticalDeliveries)> = HashMap::new();
+    for (id, entity_type) in types {
+        critical.entry(id).or_default().0.push(entity_type);
+    }
+    for (id, entity_type, backend, confirmed, dead_lettered) in deliveries {
+        critical
+            .entry(id)
+            .or_default()
+            .1
+            .insert((entity_type, backend), (confirmed, dead_lettered));
+    }
+    Ok(critical)
+}
+
+/// Return the earliest divergence of the PoI from the reference indexers
+/// for each of `sites`. If `sites` is empty, return them for all
+/// deployments
+fn poi_divergences(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, status::PoiDivergence>, StoreError> {
+    use deployment_poi_divergence as d;
+
+    let query = d::table.select((d::id, d::block_number, d::local_poi, d::reference_poi));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, i32, Vec<u8>, Vec<u8>)>(conn)?
+    } else {
+        query
+            .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, i32, Vec<u8>, Vec<u8>)>(conn)?
+    };
+    Ok(rows
+        .into_iter()
+        .map(|(id, block, local_poi, reference_poi)| {
+            let divergence = status::PoiDivergence {
+                block,
+                local_poi,
+                reference_poi,
+            };
+            (id, divergence)
+        })
+        .collect())
+}
+
+/// Return the invariants that each of `sites` currently violates, sorted
+/// by name. If `sites` is empty, return them for all deployments
+fn invariant_violations(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, Vec<status::InvariantViolation>>, StoreError> {
+    use invariant_violation as v;
+
+    let query = v::table
+        .select((
+            v::id,
+            v::invariant,
+            v::check_expr,
+            v::first_block,
+            v::last_block,
+            v::message,
+        ))
+        .order_by((v::id, v::invariant));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, String, String, i32, i32, String)>(conn)?
+    } else {
+        query
+            .filter(v::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, String, String, i32, i32, String)>(conn)?
+    };
+    Ok(rows
+        .into_iter()
+        .map(|(id, invariant, check, first_block, last_block, message)| {
+            let violation = status::InvariantViolation {
+                invariant,
+                check,
+                first_block,
+                last_block,
+                message,
+            };
+            (id, violation)
+        })
+        .into_group_map())
+}
+
+/// Return the usage records for `sites` for the hours that start in the
+/// interval `[from, to)`, ordered by deployment and hour
+pub(crate) fn usage(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<status::UsageRecord>, StoreError> {
+    use deployment_usage as u;
+
+    let deployments: HashMap<_, _> = sites
+        .iter()
+        .map(|site| (site.id, site.deployment.to_string()))
+        .collect();
+
+    u::table
+        .filter(u::id.eq_any(sites.iter().map(|site| site.id)))
+        .filter(u::bucket.ge(from))
+        .filter(u::bucket.lt(to))
+        .select((u::id, u::bucket, u::resource, u::amount))
+        .order_by((u::id, u::bucket, u::resource))
+        .load::<(DeploymentId, DateTime<Utc>, String, i64)>(conn)?
+        .into_iter()
+        .map(|(id, bucket, resource, amount)| {
+            let deployment = deployments
+                .get(&id)
+                .ok_or_else(|| constraint_violation!("missing site for deployment {}", id))?;
+            Ok(status::UsageRecord {
+                deployment: deployment.clone(),
+                bucket,
+                resource,
+                amount: amount.max(0) as u64,
+            })
         })
         .collect()
 }
@@ -495,3 +964,49 @@ impl GraphNodeVersion {
         Ok(graph_node_version_id)
     }
 }
+
+/// Return the reorg threshold that each of `sites` set for itself. If
+/// `sites` is empty, return them for all deployments
+fn reorg_thresholds(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, BlockNumber>, StoreError> {
+    use deployment_reorg_threshold as rt;
+
+    let query = rt::table.select((rt::id, rt::reorg_threshold));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, BlockNumber)>(conn)?
+    } else {
+        query
+            .filter(rt::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, BlockNumber)>(conn)?
+    };
+    Ok(rows.into_iter().collect())
+}
+
+/// Return the label of the provider that served the latest block for
+/// which each of `sites` recorded one. If `sites` is empty, return them for
+/// all deployments
+fn latest_block_providers(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<HashMap<DeploymentId, String>, StoreError> {
+    use block_provider as p;
+    use deployment_block_provider as bp;
+
+    let query = bp::table
+        .inner_join(p::table)
+        .distinct_on(bp::id)
+        .order((bp::id, bp::block_number.desc()))
+        .select((bp::id, p::label));
+
+    let rows = if sites.is_empty() {
+        query.load::<(DeploymentId, String)>(conn)?
+    } else {
+        query
+            .filter(bp::id.eq_any(sites.iter().map(|site| site.id)))
+            .load::<(DeploymentId, String)>(conn)?
+    };
+    Ok(rows.into_iter().collect())
+}
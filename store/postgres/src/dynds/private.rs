@@ -33,6 +33,7 @@ pub(crate) struct DataSourcesTable {
     param: DynColumn<Nullable<Binary>>,
     context: DynColumn<Nullable<Jsonb>>,
     done_at: DynColumn<Nullable<Integer>>,
+    creation_seq: DynColumn<Nullable<Integer>>,
 }
 
 impl DataSourcesTable {
@@ -52,6 +53,7 @@ impl DataSourcesTable {
             param: table.column("param"),
             context: table.column("context"),
             done_at: table.column("done_at"),
+            creation_seq: table.column("creation_seq"),
             table,
         }
     }
@@ -68,7 +70,8 @@ impl DataSourcesTable {
                 id bytea,
                 param bytea,
                 context jsonb,
-                done_at int
+                done_at int,
+                creation_seq int
             );
 
             create index gist_block_range_data_sources$ on {nsp}.data_sources$ using gist (block_range);
@@ -79,9 +82,11 @@ impl DataSourcesTable {
         )
     }
 
-    // Query to load the data sources which are live at `block`. Ordering by the creation block and
-    // `vid` makes sure they are in insertion order which is important for the correctness of
-    // reverts and the execution order of triggers. See also 8f1bca33-d3b7-4035-affc-fd6161a12448.
+    // Query to load the data sources which are live at `block`. Ordering by the creation block,
+    // the creation sequence and `vid` makes sure they are in creation order which is important for
+    // the correctness of reverts and the execution order of triggers. Data sources stored before
+    // the creation sequence was recorded have none and stay in insertion order. See also
+    // 8f1bca33-d3b7-4035-affc-fd6161a12448.
     pub(super) fn load(
         &self,
         conn: &PgConnection,
@@ -94,6 +99,7 @@ impl DataSourcesTable {
             Option<serde_json::Value>,
             CausalityRegion,
             Option<i32>,
+            Option<i32>,
         );
         let tuples = self
             .table
@@ -106,6 +112,7 @@ impl DataSourcesTable {
                 &self.context,
                 &self.causality_region,
                 &self.done_at,
+                &self.creation_seq,
             ))
             .order_by(&self.vid)
             .load::<Tuple>(conn)?;
@@ -113,7 +120,15 @@ impl DataSourcesTable {
         let mut dses: Vec<_> = tuples
             .into_iter()
             .map(
-                |(block_range, manifest_idx, param, context, causality_region, done_at)| {
+                |(
+                    block_range,
+                    manifest_idx,
+                    param,
+                    context,
+                    causality_region,
+                    done_at,
+                    creation_seq,
+                )| {
                     let creation_block = match block_range.0 {
                         Bound::Included(block) => Some(block),
 
@@ -128,6 +143,7 @@ impl DataSourcesTable {
                         param: param.map(|p| p.into()),
                         context,
                         creation_block,
+                        creation_seq: creation_seq.map(|seq| seq as u32),
                         done_at,
                         causality_region,
                     }
@@ -135,8 +151,9 @@ impl DataSourcesTable {
             )
             .collect();
 
-        // This sort is stable and `tuples` was ordered by vid, so `dses` will be ordered by `(creation_block, vid)`.
-        dses.sort_by_key(|v| v.creation_block);
+        // This sort is stable and `tuples` was ordered by vid, so `dses` will be ordered by
+        // `(creation_block, creation_seq, vid)`.
+        dses.sort_by_key(|v| (v.creation_block, v.creation_seq));
 
         Ok(dses)
     }
@@ -155,6 +172,7 @@ impl DataSourcesTable {
                 param,
                 context,
                 creation_block,
+                creation_seq,
                 done_at,
                 causality_region,
             } = ds;
@@ -170,8 +188,8 @@ impl DataSourcesTable {
             // Offchain data sources have a unique causality region assigned from a sequence in the
             // database, while onchain data sources always have causality region 0.
             let query = format!(
-                "insert into {}(block_range, manifest_idx, param, context, causality_region, done_at) \
-                            values (int4range($1, null), $2, $3, $4, $5, $6)",
+                "insert into {}(block_range, manifest_idx, param, context, causality_region, done_at, creation_seq) \
+                            values (int4range($1, null), $2, $3, $4, $5, $6, $7)",
                 self.qname
             );
 
@@ -181,7 +199,8 @@ impl DataSourcesTable {
                 .bind::<Nullable<Binary>, _>(param.as_ref().map(|p| &**p))
                 .bind::<Nullable<Jsonb>, _>(context)
                 .bind::<Integer, _>(causality_region)
-                .bind::<Nullable<Integer>, _>(done_at);
+                .bind::<Nullable<Integer>, _>(done_at)
+                .bind::<Nullable<Integer>, _>(creation_seq.map(|seq| seq as i32));
 
             inserted_total += query.execute(conn)?;
         }
@@ -223,6 +242,7 @@ impl DataSourcesTable {
             Option<serde_json::Value>,
             i32,
             Option<i32>,
+            Option<i32>,
         );
 
         let src_tuples = self
@@ -236,12 +256,21 @@ impl DataSourcesTable {
                 &self.context,
                 &self.causality_region,
                 &self.done_at,
+                &self.creation_seq,
             ))
             .order_by(&self.vid)
             .load::<Tuple>(conn)?;
 
         let mut count = 0;
-        for (block_range, src_manifest_idx, param, context, causality_region, done_at) in src_tuples
+        for (
+            block_range,
+            src_manifest_idx,
+            param,
+            context,
+            causality_region,
+            done_at,
+            creation_seq,
+        ) in src_tuples
         {
             let name = &src_manifest_idx_and_name
                 .iter()
@@ -256,12 +285,12 @@ impl DataSourcesTable {
 
             let query = format!(
                 "\
-             insert into {dst}(block_range, manifest_idx, param, context, causality_region, done_at)
+             insert into {dst}(block_range, manifest_idx, param, context, causality_region, done_at, creation_seq)
              values(case
                  when upper($2) <= $1 then $2
                  else int4range(lower($2), null)
              end,
-             $3, $4, $5, $6, $7)
+             $3, $4, $5, $6, $7, $8)
              ",
                 dst = dst.qname
             );
@@ -274,6 +303,7 @@ impl DataSourcesTable {
                 .bind::<Nullable<Jsonb>, _>(context)
                 .bind::<Integer, _>(causality_region)
                 .bind::<Nullable<Integer>, _>(done_at)
+                .bind::<Nullable<Integer>, _>(creation_seq)
                 .execute(conn)?;
         }
 
@@ -83,6 +83,10 @@ pub(super) fn load(
             context: context.map(|ctx| serde_json::from_str(&ctx)).transpose()?,
             creation_block,
 
+            // The shared schema does not record the creation sequence. Data sources are inserted
+            // in creation order, so ordering by `vid` yields the same order.
+            creation_seq: None,
+
             // The shared schema is only used for legacy deployments, and therefore not used for
             // subgraphs that use file data sources.
             done_at: None,
@@ -122,6 +126,7 @@ pub(super) fn insert(
                 param,
                 context,
                 creation_block: _,
+                creation_seq: _,
                 done_at: _,
                 causality_region,
             } = ds;
@@ -14,9 +14,10 @@ use graph::{
         reqwest,
         s::{Definition, Field, ObjectType, TypeDefinition},
         serde_json, Attribute, DeploymentHash, Entity, Logger, Schema, Serialize, StoreError,
-        Value, ValueType,
+        Value, ValueType, ENV_VARS,
     },
     url::Url,
+    util::proxy::ProxyTarget,
 };
 use inflector::Inflector;
 
@@ -95,7 +96,13 @@ impl SubgraphFork {
         logger: Logger,
     ) -> Result<Self, StoreError> {
         Ok(Self {
-            client: reqwest::Client::new(),
+            client: ENV_VARS
+                .proxies
+                .http_client(ProxyTarget::Other)
+                .build()
+                .map_err(|e| {
+                    StoreError::ForkFailure(format!("failed to create the fork client: {}", e))
+                })?,
             endpoint: base
                 .join(id.as_str())
                 .map_err(|e| StoreError::ForkFailure(format!("failed to join fork base: {}", e)))?,
@@ -6,8 +6,13 @@ use std::time::{Duration, Instant};
 use async_trait::async_trait;
 use diesel::{prelude::RunQueryDsl, sql_query, sql_types::Double};
 
-use graph::prelude::{error, Logger, MetricsRegistry, StoreError, ENV_VARS};
-use graph::prometheus::Gauge;
+use graph::components::metrics::query_rate::{
+    local_query_rates, set_reported_query_rates, QUERY_RATE_REPORT_INTERVAL,
+};
+use graph::prelude::{
+    error, info, BlockNumber, Logger, MetricsRegistry, NodeId, StoreError, ENV_VARS,
+};
+use graph::prometheus::{CounterVec, Gauge};
 use graph::util::jobs::{Job, Runner};
 
 use crate::connection_pool::ConnectionPool;
@@ -18,6 +23,7 @@ pub fn register(
     store: Arc<Store>,
     primary_pool: ConnectionPool,
     registry: Arc<dyn MetricsRegistry>,
+    reorg_threshold: BlockNumber,
 ) {
     runner.register(
         Arc::new(VacuumDeploymentsJob::new(store.subgraph_store())),
@@ -25,10 +31,29 @@ pub fn register(
     );
 
     runner.register(
-        Arc::new(NotificationQueueUsage::new(primary_pool, registry)),
+        Arc::new(NotificationQueueUsage::new(primary_pool, registry.clone())),
         Duration::from_secs(60),
     );
 
+    runner.register(
+        Arc::new(UsageRetentionJob::new(store.subgraph_store())),
+        Duration::from_secs(60 * 60),
+    );
+
+    runner.register(
+        Arc::new(RetentionJob::new(
+            store.subgraph_store(),
+            registry.clone(),
+            reorg_threshold,
+        )),
+        ENV_VARS.store.retention_interval,
+    );
+
+    runner.register(
+        Arc::new(RemovalJob::new(store.subgraph_store(), registry)),
+        Duration::from_secs(5 * 60),
+    );
+
     runner.register(
         Arc::new(MirrorPrimary::new(store.subgraph_store())),
         Duration::from_secs(15 * 60),
@@ -41,6 +66,16 @@ pub fn register(
     )
 }
 
+/// Register the job that shares query rates between nodes. Unlike the jobs
+/// in `register`, it needs to run on every node, since query nodes report
+/// their rates and index nodes read them
+pub fn register_query_rates(runner: &mut Runner, store: Arc<Store>, node: NodeId) {
+    runner.register(
+        Arc::new(QueryRateJob::new(store.subgraph_store(), node)),
+        QUERY_RATE_REPORT_INTERVAL,
+    );
+}
+
 /// A job that vacuums `subgraphs.subgraph_deployment`. With a large number
 /// of subgraphs, the autovacuum daemon might not run often enough to keep
 /// this table, which is _very_ write-heavy, from getting bloated. We
@@ -73,6 +108,37 @@ impl Job for VacuumDeploymentsJob {
     }
 }
 
+/// A job that removes usage records that are older than
+/// `GRAPH_USAGE_RETENTION_DAYS`, including those of deployments that are
+/// not running and therefore do not write usage anymore
+struct UsageRetentionJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl UsageRetentionJob {
+    fn new(store: Arc<SubgraphStore>) -> UsageRetentionJob {
+        UsageRetentionJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for UsageRetentionJob {
+    fn name(&self) -> &str {
+        "Remove expired usage records"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        let retention_days = ENV_VARS.usage_retention_days;
+        for res in self.store.expire_usage(retention_days).await {
+            match res {
+                Ok(0) => {}
+                Ok(count) => info!(logger, "Removed expired usage records"; "count" => count),
+                Err(e) => error!(logger, "Removing expired usage records failed: {}", e),
+            }
+        }
+    }
+}
+
 struct NotificationQueueUsage {
     primary: ConnectionPool,
     usage_gauge: Box<Gauge>,
@@ -127,6 +193,40 @@ impl Job for NotificationQueueUsage {
     }
 }
 
+/// A job that writes the query rates of this node to the store and reads
+/// the rates of all query nodes from it
+struct QueryRateJob {
+    store: Arc<SubgraphStore>,
+    node: NodeId,
+}
+
+impl QueryRateJob {
+    fn new(store: Arc<SubgraphStore>, node: NodeId) -> QueryRateJob {
+        QueryRateJob { store, node }
+    }
+}
+
+#[async_trait]
+impl Job for QueryRateJob {
+    fn name(&self) -> &str {
+        "Share query rates between nodes"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        if let Err(e) = self
+            .store
+            .record_query_rates(&self.node, &local_query_rates())
+        {
+            error!(logger, "failed to record query rates"; "error" => e.to_string());
+        }
+        // Rates of nodes that missed a few reports are stale
+        match self.store.query_rates(QUERY_RATE_REPORT_INTERVAL * 4) {
+            Ok(rates) => set_reported_query_rates(rates),
+            Err(e) => error!(logger, "failed to read query rates"; "error" => e.to_string()),
+        }
+    }
+}
+
 struct MirrorPrimary {
     store: Arc<SubgraphStore>,
 }
@@ -164,14 +264,10 @@ impl Job for UnusedJob {
         "Record and remove unused deployments"
     }
 
-    /// Record unused deployments and remove ones that were recorded at
-    /// least `UNUSED_INTERVAL` ago
+    /// Record unused deployments and start removing ones that were
+    /// recorded at least `UNUSED_INTERVAL` ago. The actual removal happens
+    /// in the `RemovalJob`
     async fn run(&self, logger: &Logger) {
-        // Work on removing about 5 minutes
-        const REMOVAL_DEADLINE: Duration = Duration::from_secs(5 * 60);
-
-        let start = Instant::now();
-
         if let Err(e) = self.store.record_unused_deployments() {
             error!(logger, "failed to record unused deployments"; "error" => e.to_string());
             return;
@@ -190,20 +286,198 @@ impl Job for UnusedJob {
         };
 
         for deployment in remove {
-            match self.store.remove_deployment(deployment.id) {
-                Ok(()) => { /* ignore */ }
+            match self.store.start_removal(deployment.id) {
+                Ok(_) => { /* ignore */ }
                 Err(e) => {
-                    error!(logger, "failed to remove unused deployment";
+                    error!(logger, "failed to start removing unused deployment";
                                    "sgd" => deployment.id.to_string(),
                                    "deployment" => deployment.deployment,
                                    "error" => e.to_string());
                 }
             }
-            // Stop working on removing after a while to not block other
-            // jobs for too long
-            if start.elapsed() > REMOVAL_DEADLINE {
+        }
+    }
+}
+
+/// A job that removes the data of deployments for which a removal was
+/// started one table at a time, pausing between tables
+struct RemovalJob {
+    store: Arc<SubgraphStore>,
+    dropped_tables: Box<CounterVec>,
+    pending: Box<Gauge>,
+}
+
+impl RemovalJob {
+    fn new(store: Arc<SubgraphStore>, registry: Arc<dyn MetricsRegistry>) -> RemovalJob {
+        let dropped_tables = registry
+            .new_counter_vec(
+                "deployment_removal_tables",
+                "Count of tables dropped while removing deployments",
+                vec!["deployment".to_string()],
+            )
+            .expect("failed to create `deployment_removal_tables` counter");
+        let pending = registry
+            .new_gauge(
+                "deployment_removals_pending",
+                "Number of deployments whose removal has not finished yet",
+                HashMap::new(),
+            )
+            .expect("failed to create `deployment_removals_pending` gauge");
+        RemovalJob {
+            store,
+            dropped_tables,
+            pending,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for RemovalJob {
+    fn name(&self) -> &str {
+        "Remove deployments one table at a time"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        // Work on removing for about 5 minutes; whatever is left is removed
+        // the next time the job runs
+        const REMOVAL_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+        let deadline = Instant::now() + REMOVAL_DEADLINE;
+
+        let removals = match self.store.removals() {
+            Ok(removals) => removals,
+            Err(e) => {
+                error!(logger, "failed to list pending removals"; "error" => e.to_string());
+                return;
+            }
+        };
+        self.pending.set(removals.len() as f64);
+
+        for removal in removals {
+            loop {
+                if Instant::now() >= deadline {
+                    return;
+                }
+                let step = match self.store.continue_removal(removal.id) {
+                    Ok(step) => step,
+                    Err(e) => {
+                        error!(logger, "failed to remove deployment";
+                                       "sgd" => removal.id.to_string(),
+                                       "deployment" => &removal.deployment,
+                                       "error" => e.to_string());
+                        break;
+                    }
+                };
+                self.dropped_tables
+                    .with_label_values(&[removal.deployment.as_str()])
+                    .inc_by(step.tables_dropped as f64);
+                if step.done {
+                    self.pending.dec();
+                    info!(logger, "Removed deployment";
+                                  "sgd" => removal.id.to_string(),
+                                  "deployment" => &removal.deployment);
+                    break;
+                }
+                graph::tokio::time::sleep(ENV_VARS.store.removal_pause).await;
+            }
+        }
+    }
+}
+
+/// A job that removes the rows that the retention policies of deployments
+/// say are no longer needed
+struct RetentionJob {
+    store: Arc<SubgraphStore>,
+    reorg_threshold: BlockNumber,
+    pruned_rows: Box<CounterVec>,
+    prune_time: Box<CounterVec>,
+}
+
+impl RetentionJob {
+    fn new(
+        store: Arc<SubgraphStore>,
+        registry: Arc<dyn MetricsRegistry>,
+        reorg_threshold: BlockNumber,
+    ) -> RetentionJob {
+        let pruned_rows = registry
+            .new_counter_vec(
+                "deployment_retention_pruned_rows",
+                "Count of rows removed by retention policies",
+                vec!["deployment".to_string(), "entity_type".to_string()],
+            )
+            .expect("failed to create `deployment_retention_pruned_rows` counter");
+        let prune_time = registry
+            .new_counter_vec(
+                "deployment_retention_prune_secs",
+                "Time spent applying retention policies",
+                vec!["deployment".to_string()],
+            )
+            .expect("failed to create `deployment_retention_prune_secs` counter");
+        RetentionJob {
+            store,
+            reorg_threshold,
+            pruned_rows,
+            prune_time,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for RetentionJob {
+    fn name(&self) -> &str {
+        "Apply entity retention policies"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        // Work on pruning for about 5 minutes; whatever is left is pruned
+        // the next time the job runs
+        const RETENTION_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+        let deadline = Instant::now() + RETENTION_DEADLINE;
+
+        let deployments = match self.store.retained_deployments() {
+            Ok(deployments) => deployments,
+            Err(e) => {
+                error!(logger, "failed to list deployments with retention policies"; "error" => e.to_string());
                 return;
             }
+        };
+
+        for deployment in deployments {
+            if Instant::now() >= deadline {
+                return;
+            }
+            let start = Instant::now();
+            let outcomes = self
+                .store
+                .apply_retention(logger, &deployment, self.reorg_threshold, deadline, false)
+                .await;
+            self.prune_time
+                .with_label_values(&[deployment.hash.as_str()])
+                .inc_by(start.elapsed().as_secs_f64());
+            let outcomes = match outcomes {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    error!(logger, "failed to apply retention policies";
+                                   "sgd" => deployment.id.to_string(),
+                                   "deployment" => deployment.hash.to_string(),
+                                   "error" => e.to_string());
+                    continue;
+                }
+            };
+            for outcome in outcomes.into_iter().filter(|outcome| outcome.rows > 0) {
+                let entity_type = outcome.retention.entity_type.as_str();
+                self.pruned_rows
+                    .with_label_values(&[deployment.hash.as_str(), entity_type])
+                    .inc_by(outcome.rows as f64);
+                info!(logger, "Applied retention policy";
+                              "sgd" => deployment.id.to_string(),
+                              "deployment" => deployment.hash.to_string(),
+                              "entity_type" => entity_type,
+                              "rule" => outcome.retention.rule.to_string(),
+                              "rows" => outcome.rows,
+                              "complete" => outcome.complete);
+            }
         }
     }
 }
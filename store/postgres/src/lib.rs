@@ -32,6 +32,7 @@ mod primary;
 pub mod query_store;
 mod relational;
 mod relational_queries;
+mod removal;
 mod sql_value;
 mod store;
 mod store_events;
@@ -58,8 +59,10 @@ pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::detail::DeploymentDetail;
 pub use self::jobs::register as register_jobs;
+pub use self::jobs::register_query_rates as register_query_rate_job;
 pub use self::notification_listener::NotificationSender;
-pub use self::primary::{db_version, UnusedDeployment};
+pub use self::primary::{db_version, DeploymentRemoval, UnusedDeployment};
+pub use self::removal::RemovalStep;
 pub use self::store::Store;
 pub use self::store_events::SubscriptionManager;
 pub use self::subgraph_store::{unused, DeploymentPlacer, Shard, SubgraphStore, PRIMARY_SHARD};
@@ -7,7 +7,7 @@ use diesel::{
     dsl::{any, exists, not, select},
     pg::Pg,
     serialize::Output,
-    sql_types::{Array, Integer, Text},
+    sql_types::{Array, Double, Integer, Text},
     types::{FromSql, ToSql},
 };
 use diesel::{
@@ -45,7 +45,7 @@ use std::{
     convert::TryInto,
     fmt,
     io::Write,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
@@ -163,6 +163,43 @@ table! {
     }
 }
 
+table! {
+    /// When each index node was last started and stopped
+    public.node_status(node_id) {
+        node_id -> Text,
+        started_at -> Timestamptz,
+        // Null while the node runs, and after it stopped without a clean
+        // shutdown
+        stopped_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    /// The rate of queries each query node serves for a deployment
+    public.deployment_query_rate(deployment, node_id) {
+        deployment -> Text,
+        node_id -> Text,
+        rate -> Double,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// Deployments whose data is being removed one table at a time; see
+    /// `SubgraphStore::start_removal`
+    public.deployment_removal(id) {
+        // This is the same as what deployment_schemas.id was when the
+        // removal started
+        id -> Integer,
+        deployment -> Text,
+        shard -> Text,
+        namespace -> Text,
+        started_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        tables_dropped -> Integer,
+    }
+}
+
 table! {
     public.db_version(version) {
         #[sql_name = "db_version"]
@@ -179,6 +216,19 @@ allow_tables_to_appear_in_same_query!(
     active_copies,
 );
 
+/// The progress of removing a deployment one table at a time
+#[derive(Clone, Queryable, Debug)]
+pub struct DeploymentRemoval {
+    pub id: DeploymentId,
+    pub deployment: String,
+    pub shard: String,
+    pub namespace: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// When the last table was dropped
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub tables_dropped: i32,
+}
+
 /// Information about the database schema that stores the entities for a
 /// subgraph.
 #[derive(Clone, Queryable, QueryableByName, Debug)]
@@ -1016,15 +1066,40 @@ impl<'a> Connection<'a> {
         site: &Site,
         node: &NodeId,
     ) -> Result<Vec<EntityChange>, StoreError> {
+        use deployment_removal as r;
         use subgraph_deployment_assignment as a;
 
-        let conn = self.conn.as_ref();
-        insert_into(a::table)
-            .values((a::id.eq(site.id), a::node_id.eq(node.as_str())))
-            .execute(conn)?;
+        self.transaction(|| {
+            let conn = self.conn.as_ref();
+            self.lock_site(site)?;
+            let removing =
+                select(exists(r::table.filter(r::id.eq(site.id)))).get_result::<bool>(conn)?;
+            if removing {
+                return Err(StoreError::DeploymentBeingRemoved(
+                    site.deployment.to_string(),
+                ));
+            }
+            insert_into(a::table)
+                .values((a::id.eq(site.id), a::node_id.eq(node.as_str())))
+                .execute(conn)?;
 
-        let change = EntityChange::for_assignment(site.into(), EntityChangeOperation::Set);
-        Ok(vec![change])
+            let change = EntityChange::for_assignment(site.into(), EntityChangeOperation::Set);
+            Ok(vec![change])
+        })
+    }
+
+    /// Lock the row for `site` in `deployment_schemas` until the end of the
+    /// current transaction so that assigning the deployment and starting
+    /// its removal can not interleave
+    fn lock_site(&self, site: &Site) -> Result<(), StoreError> {
+        use deployment_schemas as ds;
+
+        ds::table
+            .filter(ds::id.eq(site.id))
+            .select(ds::id)
+            .for_update()
+            .execute(self.conn.as_ref())?;
+        Ok(())
     }
 
     pub fn unassign_subgraph(&self, site: &Site) -> Result<Vec<EntityChange>, StoreError> {
@@ -1050,6 +1125,89 @@ impl<'a> Connection<'a> {
         }
     }
 
+    /// Record that `node` started, and return whether its previous run
+    /// ended without a clean shutdown. A node that never ran before did
+    /// not crash
+    pub fn record_node_start(&self, node: &NodeId) -> Result<bool, StoreError> {
+        use node_status as n;
+
+        let conn = self.conn.as_ref();
+        conn.transaction(|| {
+            let crashed = n::table
+                .filter(n::node_id.eq(node.as_str()))
+                .select(n::stopped_at.is_null())
+                .get_result::<bool>(conn)
+                .optional()?
+                .unwrap_or(false);
+            insert_into(n::table)
+                .values((n::node_id.eq(node.as_str()), n::started_at.eq(sql("now()"))))
+                .on_conflict(n::node_id)
+                .do_update()
+                .set((
+                    n::started_at.eq(sql("now()")),
+                    n::stopped_at.eq(None::<PgTimestamp>),
+                ))
+                .execute(conn)?;
+            Ok(crashed)
+        })
+    }
+
+    /// Record that `node` shut down cleanly
+    pub fn record_node_stop(&self, node: &NodeId) -> Result<(), StoreError> {
+        use node_status as n;
+
+        update(n::table.filter(n::node_id.eq(node.as_str())))
+            .set(n::stopped_at.eq(sql("now()")))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Replace the query rates that `node` serves with `rates`
+    pub fn record_query_rates(
+        &self,
+        node: &NodeId,
+        rates: &[(String, f64)],
+    ) -> Result<(), StoreError> {
+        use deployment_query_rate as q;
+
+        let conn = self.conn.as_ref();
+        let rows: Vec<_> = rates
+            .iter()
+            .map(|(deployment, rate)| {
+                (
+                    q::deployment.eq(deployment.as_str()),
+                    q::node_id.eq(node.as_str()),
+                    q::rate.eq(*rate),
+                )
+            })
+            .collect();
+        conn.transaction(|| {
+            delete(q::table.filter(q::node_id.eq(node.as_str()))).execute(conn)?;
+            insert_into(q::table).values(&rows).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// The total query rate of each deployment across all query nodes.
+    /// Rates that were not updated in the last `max_age` are from nodes
+    /// that are no longer running and are ignored
+    pub fn query_rates(&self, max_age: Duration) -> Result<HashMap<String, f64>, StoreError> {
+        use deployment_query_rate as q;
+
+        let conn = self.conn.as_ref();
+        delete(q::table.filter(q::updated_at.lt(sql(&format!(
+            "now() - interval '{} seconds'",
+            max_age.as_secs()
+        )))))
+        .execute(conn)?;
+        Ok(q::table
+            .group_by(q::deployment)
+            .select((q::deployment, sql::<Double>("sum(rate)")))
+            .load::<(String, f64)>(conn)?
+            .into_iter()
+            .collect())
+    }
+
     /// Create a new site and possibly set it to the active site. This
     /// function only performs the basic operations for creation, and the
     /// caller must check that other conditions (like whether there already
@@ -1111,6 +1269,12 @@ impl<'a> Connection<'a> {
         if let Some(site) = queries::find_active_site(self.conn.as_ref(), subgraph)? {
             return Ok(site);
         }
+        // A deployment that is being removed still has its site, but it is
+        // not active anymore. Creating another site would race with the
+        // removal of the old one
+        if self.removal_pending(subgraph)? {
+            return Err(StoreError::DeploymentBeingRemoved(subgraph.to_string()));
+        }
 
         self.create_site(
             shard,
@@ -1165,6 +1329,7 @@ impl<'a> Connection<'a> {
     /// Remove all subgraph versions and the entry in `deployment_schemas` for
     /// subgraph `id` in a transaction
     pub fn drop_site(&self, site: &Site) -> Result<(), StoreError> {
+        use deployment_removal as r;
         use deployment_schemas as ds;
         use subgraph_version as v;
         use unused_deployments as u;
@@ -1188,10 +1353,80 @@ impl<'a> Connection<'a> {
             update(u::table.filter(u::id.eq(site.id)))
                 .set(u::removed_at.eq(sql("now()")))
                 .execute(self.conn.as_ref())?;
+
+            delete(r::table.filter(r::id.eq(site.id))).execute(conn)?;
             Ok(())
         })
     }
 
+    /// Record that the data of `site` is about to be removed one table at a time
+    /// and stop using the site for queries. Starting the removal of a
+    /// deployment that is already being removed does nothing. Return
+    /// `false` and change nothing if the deployment is assigned to a node
+    /// or, if it is active, the current or pending version of a subgraph
+    pub fn start_removal(&self, site: &Site) -> Result<bool, StoreError> {
+        use deployment_removal as r;
+        use deployment_schemas as ds;
+        use subgraph_deployment_assignment as a;
+
+        self.transaction(|| {
+            let conn = self.conn.as_ref();
+
+            self.lock_site(site)?;
+            let assigned =
+                select(exists(a::table.filter(a::id.eq(site.id)))).get_result::<bool>(conn)?;
+            if assigned || (site.active && !self.subgraphs_using_deployment(site)?.is_empty()) {
+                return Ok(false);
+            }
+
+            insert_into(r::table)
+                .values((
+                    r::id.eq(site.id),
+                    r::deployment.eq(site.deployment.as_str()),
+                    r::shard.eq(site.shard.as_str()),
+                    r::namespace.eq(site.namespace.as_str()),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            update(ds::table.filter(ds::id.eq(site.id)))
+                .set(ds::active.eq(false))
+                .execute(conn)?;
+            Ok(true)
+        })
+    }
+
+    /// Add the tables that the last step dropped to the progress of
+    /// removing `site`
+    pub fn record_removal_progress(&self, site: &Site, tables: i32) -> Result<(), StoreError> {
+        use deployment_removal as r;
+
+        update(r::table.filter(r::id.eq(site.id)))
+            .set((
+                r::tables_dropped.eq(r::tables_dropped + tables),
+                r::updated_at.eq(sql("now()")),
+            ))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Return the removals that have not finished yet, oldest first
+    pub fn removals(&self) -> Result<Vec<DeploymentRemoval>, StoreError> {
+        use deployment_removal as r;
+
+        Ok(r::table.order_by(r::started_at).load(self.conn.as_ref())?)
+    }
+
+    /// Return `true` if a deployment with hash `deployment` is being
+    /// removed
+    pub fn removal_pending(&self, deployment: &DeploymentHash) -> Result<bool, StoreError> {
+        use deployment_removal as r;
+
+        Ok(select(exists(
+            r::table.filter(r::deployment.eq(deployment.as_str())),
+        ))
+        .get_result::<bool>(self.conn.as_ref())?)
+    }
+
     pub fn locate_site(&self, locator: DeploymentLocator) -> Result<Option<Site>, StoreError> {
         let schema = deployment_schemas::table
             .filter(deployment_schemas::id.eq::<DeploymentId>(locator.into()))
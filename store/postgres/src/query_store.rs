@@ -60,6 +60,14 @@ impl QueryStoreTrait for QueryStore {
     async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
         self.store.block_ptr(self.site.cheap_clone()).await
     }
+
+    async fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.chain_store
+            .cheap_clone()
+            .cached_head_ptr()
+            .await
+            .map_err(StoreError::from)
+    }
     async fn block_number_with_timestamp(
         &self,
         block_hash: &BlockHash,
@@ -15,7 +15,10 @@ mod ddl_tests;
 mod query_tests;
 
 pub(crate) mod index;
+mod invariants;
 mod prune;
+mod retention;
+mod stats;
 
 use diesel::{connection::SimpleConnection, Connection};
 use diesel::{debug_query, OptionalExtension, PgConnection, RunQueryDsl};
@@ -60,6 +63,8 @@ pub use crate::catalog::Catalog;
 use crate::connection_pool::ForeignServer;
 use crate::{catalog, deployment};
 
+pub(crate) use retention::RetentionCutoff;
+
 const DELETE_OPERATION_CHUNK_SIZE: usize = 1_000;
 
 /// The size of string prefixes that we index. This is chosen so that we
@@ -883,6 +888,9 @@ impl Layout {
     ) -> Result<(), StoreError> {
         crate::dynds::revert(conn, site, block)?;
         crate::deployment::revert_subgraph_errors(conn, &site.deployment, block)?;
+        crate::deployment::revert_entity_types_modified(conn, site, block)?;
+        crate::deployment::revert_invariant_violations(conn, site, block)?;
+        crate::deployment::revert_invariant_aggregates(conn, site, block)?;
 
         Ok(())
     }
@@ -0,0 +1,201 @@
+//! Check the invariants from the manifest of a deployment against the
+//! current entities of the deployment. The aggregates that invariants use
+//! are kept in `subgraphs.invariant_aggregate` and updated from the rows
+//! that a block wrote, so that checking them takes time proportional to the
+//! changes of the block, not to the number of entities
+
+use diesel::{
+    sql_query,
+    sql_types::{Nullable, Numeric},
+    PgConnection, RunQueryDsl,
+};
+use graph::{
+    components::store::EntityType,
+    constraint_violation,
+    data::subgraph::invariant::{Aggregate, Invariant, InvariantOutcome, Term},
+    prelude::{BigDecimal, BlockNumber, StoreError, BLOCK_NUMBER_MAX},
+};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN, BLOCK_RANGE_CURRENT};
+use crate::deployment;
+
+use super::{Layout, Table};
+
+#[derive(QueryableByName)]
+struct AggregateValue {
+    #[sql_type = "Nullable<Numeric>"]
+    value: Option<BigDecimal>,
+}
+
+/// Combine the value `prev` of an aggregate with the value `added` for the
+/// rows a block added and `removed` for the rows it removed
+fn combine(
+    function: Aggregate,
+    prev: Option<BigDecimal>,
+    added: Option<BigDecimal>,
+    removed: Option<BigDecimal>,
+) -> Option<BigDecimal> {
+    let zero = || BigDecimal::from(0);
+    match function {
+        Aggregate::Count | Aggregate::Sum => Some(
+            prev.unwrap_or_else(zero) + added.unwrap_or_else(zero) - removed.unwrap_or_else(zero),
+        ),
+        // Only used for immutable entity types, which never remove rows
+        Aggregate::Min => match (prev, added) {
+            (Some(prev), Some(added)) => Some(if added < prev { added } else { prev }),
+            (prev, added) => prev.or(added),
+        },
+        Aggregate::Max => match (prev, added) {
+            (Some(prev), Some(added)) => Some(if added > prev { added } else { prev }),
+            (prev, added) => prev.or(added),
+        },
+    }
+}
+
+impl Layout {
+    /// Aggregate `field` with `function` over the rows of `table` that
+    /// match `filter`
+    fn aggregate_rows(
+        &self,
+        conn: &PgConnection,
+        table: &Table,
+        function: Aggregate,
+        field: &Option<String>,
+        filter: &str,
+    ) -> Result<Option<BigDecimal>, StoreError> {
+        let value = match field {
+            Some(field) => format!(
+                "{}({})",
+                function.as_str(),
+                table.column_for_field(field)?.name.quoted()
+            ),
+            None => "count(*)".to_string(),
+        };
+        let query = format!(
+            "select {}::numeric as value from {} where {}",
+            value, table.qualified_name, filter
+        );
+        let AggregateValue { value } = sql_query(&query).get_result::<AggregateValue>(conn)?;
+        Ok(value)
+    }
+
+    /// The value of `term` with the changes of `block` written. The first
+    /// time, e.g., for a new or a grafted deployment, the aggregate is
+    /// computed from all current entities; after that, it is updated from
+    /// the rows that `block` added or removed
+    fn aggregate(
+        &self,
+        conn: &PgConnection,
+        term: &Term,
+        block: BlockNumber,
+    ) -> Result<Option<BigDecimal>, StoreError> {
+        let (function, entity_type, field) = match term {
+            Term::Number(number) => return Ok(Some(number.clone())),
+            Term::Aggregate {
+                function,
+                entity_type,
+                field,
+            } => (*function, entity_type, field),
+        };
+        let table = self.table_for_entity(&EntityType::new(entity_type.clone()))?;
+        let key = term.to_string();
+
+        let prev = deployment::invariant_aggregate(conn, &self.site, &key)?;
+        let value = match &prev {
+            None => {
+                let filter = if table.immutable {
+                    "true"
+                } else {
+                    BLOCK_RANGE_CURRENT
+                };
+                let value = self.aggregate_rows(conn, table, function, field, filter)?;
+                match function {
+                    Aggregate::Count | Aggregate::Sum => {
+                        Some(value.unwrap_or_else(|| BigDecimal::from(0)))
+                    }
+                    Aggregate::Min | Aggregate::Max => value,
+                }
+            }
+            Some(prev) if table.immutable => {
+                let filter = format!("{} = {}", BLOCK_COLUMN, block);
+                let added = self.aggregate_rows(conn, table, function, field, &filter)?;
+                combine(function, prev.clone(), added, None)
+            }
+            Some(_) if matches!(function, Aggregate::Min | Aggregate::Max) => {
+                return Err(constraint_violation!(
+                    "`{}` can not be maintained for the mutable entity type `{}`",
+                    term,
+                    entity_type
+                ));
+            }
+            Some(prev) => {
+                // Writing the block started versions with `block` as
+                // their lower bound, and ended versions with `block` as
+                // their upper bound
+                let added = format!("lower({}) = {}", BLOCK_RANGE_COLUMN, block);
+                let removed = format!(
+                    "coalesce(upper({}), {}) = {}",
+                    BLOCK_RANGE_COLUMN, BLOCK_NUMBER_MAX, block
+                );
+                let added = self.aggregate_rows(conn, table, function, field, &added)?;
+                let removed = self.aggregate_rows(conn, table, function, field, &removed)?;
+                combine(function, prev.clone(), added, removed)
+            }
+        };
+        // Only changes are recorded, so that blocks that do not touch the
+        // entity type do not add to the values that are kept
+        if prev.as_ref() != Some(&value) {
+            deployment::set_invariant_aggregate(conn, &self.site, &key, block, value.as_ref())?;
+        }
+        Ok(value)
+    }
+
+    /// Check each of `invariants` against the entities with the changes of
+    /// `block` written, and return the outcome for each of them, in the
+    /// same order. This must be called exactly once for each block that is
+    /// written and in the same transaction
+    pub fn check_invariants(
+        &self,
+        conn: &PgConnection,
+        invariants: &[Invariant],
+        block: BlockNumber,
+    ) -> Result<Vec<(String, InvariantOutcome)>, StoreError> {
+        // Invariants can share aggregates, but each of them must only be
+        // updated once for the block
+        let mut values = Vec::new();
+        let mut value = |term: &Term| -> Result<Option<BigDecimal>, StoreError> {
+            if let Some((_, value)) = values.iter().find(|(t, _)| t == term) {
+                return Ok(Option::clone(value));
+            }
+            let value = self.aggregate(conn, term, block)?;
+            values.push((term.clone(), value.clone()));
+            Ok(value)
+        };
+
+        let mut outcomes = Vec::with_capacity(invariants.len());
+        for invariant in invariants {
+            let check = &invariant.check;
+            let left = value(&check.left)?;
+            let right = value(&check.right)?;
+            let outcome = match (left, right) {
+                (Some(left), Some(right)) if check.op.holds(&left, &right) => {
+                    InvariantOutcome::Holds
+                }
+                (Some(left), Some(right)) => InvariantOutcome::Violated { left, right },
+                (left, _) => {
+                    let term = if left.is_none() {
+                        &check.left
+                    } else {
+                        &check.right
+                    };
+                    InvariantOutcome::Skipped(format!(
+                        "`{}` has no value since there are no entities",
+                        term
+                    ))
+                }
+            };
+            outcomes.push((invariant.name.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+}
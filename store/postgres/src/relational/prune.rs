@@ -359,10 +359,17 @@ impl Layout {
                 cancel.check_cancel().map_err(CancelableError::from)?;
             }
 
-            for table in prunable_tables {
-                conn.transaction(|| table.switch(logger, conn))?;
-                cancel.check_cancel().map_err(CancelableError::from)?;
-            }
+            // Moving the earliest block in the same transaction as the
+            // switch makes sure that queries never see pruned tables with
+            // the old earliest block
+            conn.transaction(|| -> Result<_, StoreError> {
+                deployment::set_earliest_block(conn, &self.site, earliest_block)?;
+                for table in prunable_tables {
+                    table.switch(logger, conn)?;
+                }
+                Ok(())
+            })?;
+            cancel.check_cancel().map_err(CancelableError::from)?;
 
             Ok(())
         })?;
@@ -0,0 +1,178 @@
+//! Remove the rows of entity types that their retention policy says are no
+//! longer needed
+
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable, Text},
+    PgConnection, RunQueryDsl,
+};
+use graph::{
+    data::subgraph::retention::{EntityRetention, RetentionRule},
+    prelude::{BlockNumber, StoreError},
+};
+
+use crate::{
+    block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN},
+    relational::{Table, VID_COLUMN},
+};
+
+use super::Layout;
+
+/// The deployment state that determines which rows a retention policy
+/// removes. It only depends on the blocks that the deployment has
+/// processed, not on the wall clock
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RetentionCutoff {
+    pub latest_block: BlockNumber,
+    /// Rows written or replaced after this block could still be reverted
+    /// and are never removed
+    pub final_block: BlockNumber,
+}
+
+/// The block at which a row was written
+fn written_expr(table: &Table) -> String {
+    if table.immutable {
+        format!("\"{}\"", BLOCK_COLUMN)
+    } else {
+        format!("lower({})", BLOCK_RANGE_COLUMN)
+    }
+}
+
+/// The block from which on a row can be removed: the block at which it was
+/// written for immutable tables, and the block at which it was replaced or
+/// deleted for mutable tables
+fn expired_expr(table: &Table) -> String {
+    if table.immutable {
+        written_expr(table)
+    } else {
+        format!("upper({})", BLOCK_RANGE_COLUMN)
+    }
+}
+
+/// The last block at which time-travel queries can see a row that is
+/// selected by `Layout::retention_filter`, or, for immutable tables, the
+/// block from which on they can see it
+fn visible_expr(table: &Table) -> String {
+    if table.immutable {
+        written_expr(table)
+    } else {
+        format!("upper({}) - 1", BLOCK_RANGE_COLUMN)
+    }
+}
+
+/// The SQL condition that selects the rows of a table that a retention
+/// policy removes; see `Layout::retention_filter`
+pub(crate) struct RetentionFilter<'a> {
+    table: &'a Table,
+    condition: String,
+}
+
+#[derive(QueryableByName)]
+struct NewestTimestamp {
+    #[sql_type = "Nullable<Text>"]
+    newest: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct RetainedRows {
+    #[sql_type = "BigInt"]
+    rows: i64,
+    #[sql_type = "Nullable<Integer>"]
+    newest_block: Option<BlockNumber>,
+}
+
+impl Layout {
+    /// The condition that selects the rows that `retention` removes given
+    /// `cutoff`. Only rows that are no longer current are removed from
+    /// mutable tables; since the rows of immutable tables are always
+    /// current, they are removed once they are old enough. For an age
+    /// rule, this looks up the newest timestamp once so that the filter
+    /// can be used for many batches
+    pub(crate) fn retention_filter(
+        &self,
+        conn: &PgConnection,
+        retention: &EntityRetention,
+        cutoff: RetentionCutoff,
+    ) -> Result<RetentionFilter<'_>, StoreError> {
+        let table = self.table_for_entity(&retention.entity_type.as_str().into())?;
+        let expired = expired_expr(table);
+        let closed = if table.immutable {
+            "true".to_string()
+        } else {
+            format!("not upper_inf({})", BLOCK_RANGE_COLUMN)
+        };
+        let condition = match &retention.rule {
+            RetentionRule::Age { field, max_age } => {
+                // Measure the age from the newest timestamp of the rows
+                // written in final blocks
+                let column = table.column_for_field(field)?.name.quoted();
+                let query = format!(
+                    "select max({column})::text as newest from {table} \
+                      where {written} <= {final_block}",
+                    column = column,
+                    table = table.qualified_name,
+                    written = written_expr(table),
+                    final_block = cutoff.final_block,
+                );
+                let NewestTimestamp { newest } =
+                    sql_query(query).get_result::<NewestTimestamp>(conn)?;
+                match newest {
+                    Some(newest) => format!(
+                        "{closed} and {expired} <= {final_block} \
+                         and {column} < {newest} - {max_age}",
+                        closed = closed,
+                        expired = expired,
+                        final_block = cutoff.final_block,
+                        column = column,
+                        newest = newest,
+                        max_age = max_age.as_secs()
+                    ),
+                    None => "false".to_string(),
+                }
+            }
+            RetentionRule::Blocks(blocks) => {
+                let oldest = (cutoff.latest_block - blocks).min(cutoff.final_block + 1);
+                format!("{} and {} < {}", closed, expired, oldest)
+            }
+        };
+        Ok(RetentionFilter { table, condition })
+    }
+
+    /// Count the rows that `filter` selects and return that count together
+    /// with the highest block at which time-travel queries can see one of
+    /// them (see `visible_expr`)
+    pub(crate) fn retained_rows(
+        &self,
+        conn: &PgConnection,
+        filter: &RetentionFilter,
+    ) -> Result<(usize, Option<BlockNumber>), StoreError> {
+        let query = format!(
+            "select count(*) as rows, max({block}) as newest_block \
+               from {table} where {condition}",
+            block = visible_expr(filter.table),
+            table = filter.table.qualified_name,
+            condition = filter.condition
+        );
+        let RetainedRows { rows, newest_block } =
+            sql_query(query).get_result::<RetainedRows>(conn)?;
+        Ok((rows as usize, newest_block))
+    }
+
+    /// Remove at most `batch_size` of the rows that `filter` selects and
+    /// return how many rows were removed
+    pub(crate) fn prune_retained(
+        &self,
+        conn: &PgConnection,
+        filter: &RetentionFilter,
+        batch_size: usize,
+    ) -> Result<usize, StoreError> {
+        let query = format!(
+            "delete from {table} where {vid} in \
+               (select {vid} from {table} where {condition} limit {batch_size})",
+            table = filter.table.qualified_name,
+            vid = VID_COLUMN,
+            condition = filter.condition
+        );
+        Ok(sql_query(query).execute(conn)?)
+    }
+}
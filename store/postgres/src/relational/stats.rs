@@ -0,0 +1,119 @@
+//! Row counts and the block of the last modification for the entity types
+//! of a deployment
+
+use std::collections::HashMap;
+
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable},
+    PgConnection, RunQueryDsl,
+};
+use graph::{
+    data::subgraph::{schema::POI_OBJECT, status::EntityTypeStats},
+    prelude::{BlockNumber, StoreError},
+};
+
+use crate::{
+    block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN},
+    catalog, deployment,
+    relational::Table,
+};
+
+use super::Layout;
+
+#[derive(QueryableByName)]
+struct RowCount {
+    #[sql_type = "BigInt"]
+    rows: i64,
+}
+
+#[derive(QueryableByName)]
+struct LastModified {
+    #[sql_type = "Nullable<Integer>"]
+    block: Option<BlockNumber>,
+}
+
+impl Table {
+    fn count_rows(&self, conn: &PgConnection) -> Result<u64, StoreError> {
+        let query = format!("select count(*) as rows from {}", self.qualified_name);
+        let count = sql_query(query).get_result::<RowCount>(conn)?;
+        Ok(count.rows as u64)
+    }
+
+    /// Find the highest block at which an entity in this table was
+    /// inserted, updated or removed by looking at all its rows
+    fn last_modified_block(&self, conn: &PgConnection) -> Result<Option<BlockNumber>, StoreError> {
+        let query = if self.immutable {
+            format!(
+                "select max(\"{}\") as block from {}",
+                BLOCK_COLUMN, self.qualified_name
+            )
+        } else {
+            // A version that was updated or removed has the block of that
+            // change as the upper end of its block range
+            format!(
+                "select greatest(max(lower({range})), \
+                                 max(upper({range})) filter (where not upper_inf({range}))) as block \
+                   from {table}",
+                range = BLOCK_RANGE_COLUMN,
+                table = self.qualified_name
+            )
+        };
+        let last = sql_query(query).get_result::<LastModified>(conn)?;
+        Ok(last.block)
+    }
+}
+
+impl Layout {
+    /// Return the number of rows and the block at which each entity type
+    /// was last modified, ordered by entity type. With `exact`, count the
+    /// rows in each table, otherwise use Postgres' estimate. The last
+    /// modification comes from what the writer recorded. For entity types
+    /// where that is not known, e.g., after a revert or for deployments
+    /// that were written before it was recorded, it is computed from the
+    /// data with `exact`; since that scans the whole table, it is left
+    /// unknown otherwise
+    pub fn entity_type_stats(
+        &self,
+        conn: &PgConnection,
+        exact: bool,
+    ) -> Result<Vec<EntityTypeStats>, StoreError> {
+        let estimates = if exact {
+            HashMap::new()
+        } else {
+            catalog::estimated_rows(conn, &self.site.namespace)?
+        };
+        let mut modified: HashMap<_, _> = deployment::entity_types_modified(conn, &self.site)?
+            .into_iter()
+            .collect();
+
+        let mut tables: Vec<_> = self
+            .tables
+            .values()
+            .filter(|table| table.object != *POI_OBJECT)
+            .collect();
+        tables.sort_by(|a, b| a.object.as_str().cmp(b.object.as_str()));
+
+        tables
+            .into_iter()
+            .map(|table| {
+                let rows = if exact {
+                    table.count_rows(conn)?
+                } else {
+                    estimates.get(table.name.as_str()).copied().unwrap_or(0)
+                };
+                let last_modified_block = match modified.remove(table.object.as_str()) {
+                    Some(block) => Some(block),
+                    None if exact => table.last_modified_block(conn)?,
+                    None => None,
+                };
+                Ok(EntityTypeStats {
+                    entity_type: table.object.to_string(),
+                    rows,
+                    exact,
+                    last_modified_block,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,68 @@
+//! Remove the data of a deployment one table at a time so that removing a
+//! large deployment does not hold locks or generate load for a long time.
+//! Each step drops a single table and is a separate unit of work, and the
+//! progress of a removal is entirely determined by what is left in the
+//! database, which makes it possible to resume a removal at any point,
+//! e.g., after a restart
+use diesel::{
+    connection::SimpleConnection, result::Error as DieselError, Connection, PgConnection,
+};
+use graph::prelude::StoreError;
+
+use crate::{catalog, deployment, primary::Namespace};
+
+/// How long a step waits for the lock it needs to drop a table before it
+/// gives up; the table is then dropped by a later step
+const LOCK_TIMEOUT_MS: u64 = 2000;
+
+/// What one step of a removal did
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemovalStep {
+    /// The number of tables that were dropped
+    pub tables_dropped: usize,
+    /// Whether all the data of the deployment is gone
+    pub done: bool,
+}
+
+/// Drop the first table in `namespace`. When there are no tables left,
+/// drop `namespace` itself. If the lock for dropping the table can not be
+/// acquired quickly, nothing is dropped and the step has to be repeated.
+/// The metadata of the deployment is not touched
+pub(crate) fn remove_step(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<RemovalStep, StoreError> {
+    let table = match catalog::table_names(conn, namespace)?.into_iter().next() {
+        Some(table) => table,
+        None => {
+            if catalog::has_namespace(conn, namespace)? {
+                conn.transaction(|| deployment::drop_schema(conn, namespace))?;
+            }
+            return Ok(RemovalStep {
+                done: true,
+                ..Default::default()
+            });
+        }
+    };
+
+    // Dropping a table only removes its files, no matter how large it is,
+    // and does not generate load the way deleting its rows would
+    let query = format!(
+        "set local lock_timeout={}; drop table if exists {}.\"{}\" cascade",
+        LOCK_TIMEOUT_MS, namespace, table
+    );
+    match conn.transaction(|| conn.batch_execute(&query)) {
+        Ok(()) => Ok(RemovalStep {
+            tables_dropped: 1,
+            done: false,
+        }),
+        Err(DieselError::DatabaseError(_, info))
+            if info
+                .message()
+                .contains("canceling statement due to lock timeout") =>
+        {
+            Ok(RemovalStep::default())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
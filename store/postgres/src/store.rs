@@ -1,5 +1,7 @@
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use graph::{
     components::{
@@ -11,8 +13,11 @@ use graph::{
     constraint_violation,
     data::subgraph::status,
     prelude::{
-        tokio, web3::types::Address, BlockNumber, BlockPtr, CheapClone, DeploymentHash,
-        PartialBlockPtr, QueryExecutionError, StoreError,
+        chrono::{DateTime, Utc},
+        tokio,
+        web3::types::Address,
+        BlockNumber, BlockPtr, CheapClone, DeploymentHash, PartialBlockPtr, QueryExecutionError,
+        StoreError,
     },
 };
 
@@ -117,6 +122,19 @@ impl StatusStore for Store {
         Ok(infos)
     }
 
+    fn usage(
+        &self,
+        deployments: Vec<String>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<status::UsageRecord>, StoreError> {
+        self.subgraph_store.usage(deployments, from, to)
+    }
+
+    fn query_rates(&self, max_age: Duration) -> Result<HashMap<String, f64>, StoreError> {
+        self.subgraph_store.query_rates(max_age)
+    }
+
     fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
         let mut info = self.subgraph_store.version_info(version_id)?;
 
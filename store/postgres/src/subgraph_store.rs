@@ -5,11 +5,14 @@ use diesel::{
     types::{FromSql, ToSql},
 };
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     sync::{atomic::AtomicU8, Arc, Mutex},
 };
 use std::{fmt, io::Write};
-use std::{iter::FromIterator, time::Duration};
+use std::{
+    iter::FromIterator,
+    time::{Duration, Instant},
+};
 
 use graph::{
     cheap_clone::CheapClone,
@@ -17,20 +20,30 @@ use graph::{
         server::index_node::VersionInfo,
         store::{
             self, BlockStore, DeploymentLocator, DeploymentSchemaVersion,
-            EnsLookup as EnsLookupTrait, PruneReporter, SubgraphFork,
+            EnsLookup as EnsLookupTrait, EntityType, PruneReporter, SubgraphFork,
         },
     },
     constraint_violation,
     data::query::QueryTarget,
-    data::subgraph::{schema::DeploymentCreate, status},
+    data::subgraph::{
+        fingerprint::CompatibilityFingerprint,
+        retention::{EntityRetention, RetentionOutcome},
+        schema::DeploymentCreate,
+        status,
+    },
     prelude::StoreEvent,
     prelude::{
-        anyhow, futures03::future::join_all, lazy_static, o, web3::types::Address, ApiSchema,
-        ApiVersion, BlockHash, BlockNumber, BlockPtr, ChainStore, DeploymentHash, EntityOperation,
-        Logger, MetricsRegistry, NodeId, PartialBlockPtr, Schema, StoreError,
+        anyhow,
+        chrono::{DateTime, Utc},
+        futures03::future::join_all,
+        lazy_static, o,
+        web3::types::Address,
+        ApiSchema, ApiVersion, BlockHash, BlockNumber, BlockPtr, ChainStore, DeploymentHash,
+        EntityOperation, Logger, MetricsRegistry, NodeId, PartialBlockPtr, Schema, StoreError,
         SubgraphDeploymentEntity, SubgraphName, SubgraphStore as SubgraphStoreTrait,
         SubgraphVersionSwitchingMode,
     },
+    runtime::CompileProfile,
     url::Url,
     util::timed_cache::TimedCache,
 };
@@ -39,8 +52,9 @@ use crate::{
     connection_pool::ConnectionPool,
     deployment::SubgraphHealth,
     primary,
-    primary::{DeploymentId, Mirror as PrimaryMirror, Site},
+    primary::{DeploymentId, DeploymentRemoval, Mirror as PrimaryMirror, Site},
     relational::{index::Method, Layout},
+    removal::RemovalStep,
     writable::WritableStore,
     NotificationSender,
 };
@@ -632,6 +646,8 @@ impl SubgraphStoreInner {
             graft_base: Some(src.deployment.clone()),
             graft_block: Some(block),
             debug_fork: deployment.debug_fork,
+            retention: src_store.entity_retention(src.clone())?,
+            reorg_threshold: src_store.reorg_threshold(src.clone())?,
         };
 
         let graft_base = self.layout(&src.deployment)?;
@@ -765,6 +781,21 @@ impl SubgraphStoreInner {
         Ok(by_shard)
     }
 
+    /// Replace the query rates that `node` serves with `rates`
+    pub fn record_query_rates(
+        &self,
+        node: &NodeId,
+        rates: &[(String, f64)],
+    ) -> Result<(), StoreError> {
+        self.primary_conn()?.record_query_rates(node, rates)
+    }
+
+    /// The total query rate of each deployment across all query nodes that
+    /// reported their rates in the last `max_age`
+    pub fn query_rates(&self, max_age: Duration) -> Result<HashMap<String, f64>, StoreError> {
+        self.primary_conn()?.query_rates(max_age)
+    }
+
     /// Look for new unused deployments and add them to the `unused_deployments`
     /// table
     pub fn record_unused_deployments(&self) -> Result<Vec<DeploymentDetail>, StoreError> {
@@ -802,29 +833,40 @@ impl SubgraphStoreInner {
         self.primary_conn()?.list_unused_deployments(filter)
     }
 
-    /// Remove a deployment, i.e., all its data and metadata. This is only permissible
-    /// if the deployment is unused in the sense that it is neither the current nor
-    /// pending version of any subgraph, and is not currently assigned to any node
-    pub fn remove_deployment(&self, id: DeploymentId) -> Result<(), StoreError> {
-        let site = self.find_site(id)?;
-        let store = self.for_site(site.as_ref())?;
-
+    /// Check that the deployment `site` is unused in the sense that it is
+    /// neither the current nor pending version of any subgraph, and is not
+    /// currently assigned to any node
+    fn removable(&self, site: &Site) -> Result<bool, StoreError> {
         // Check that deployment is not assigned
-        let mut removable = self.mirror.assigned_node(site.as_ref())?.is_none();
+        if self.mirror.assigned_node(site)?.is_some() {
+            return Ok(false);
+        }
 
         // Check that it is not current/pending for any subgraph if it is
         // the active deployment of that subgraph
-        if site.active {
-            if !self
+        if site.active
+            && !self
                 .primary_conn()?
-                .subgraphs_using_deployment(site.as_ref())?
+                .subgraphs_using_deployment(site)?
                 .is_empty()
-            {
-                removable = false;
-            }
+        {
+            return Ok(false);
         }
+        Ok(true)
+    }
 
-        if removable {
+    /// Remove a deployment, i.e., all its data and metadata. This is only permissible
+    /// if the deployment is unused in the sense that it is neither the current nor
+    /// pending version of any subgraph, and is not currently assigned to any node.
+    ///
+    /// This removes all data in one go which is fine for small
+    /// deployments; larger deployments should be removed with
+    /// `start_removal` and `continue_removal`
+    pub fn remove_deployment(&self, id: DeploymentId) -> Result<(), StoreError> {
+        let site = self.find_site(id)?;
+        let store = self.for_site(site.as_ref())?;
+
+        if self.removable(site.as_ref())? {
             store.drop_deployment(&site)?;
 
             self.primary_conn()?.drop_site(site.as_ref())?;
@@ -836,6 +878,47 @@ impl SubgraphStoreInner {
         Ok(())
     }
 
+    /// Mark the deployment `id` as being removed. From then on, it can not
+    /// be queried anymore, and its hash can not be deployed again until
+    /// `continue_removal` has removed all its data. Like for
+    /// `remove_deployment`, the deployment must be unused; return `false`
+    /// if it is not, and `true` if the removal was started
+    pub fn start_removal(&self, id: DeploymentId) -> Result<bool, StoreError> {
+        let site = self.find_site(id)?;
+
+        // The checks have to happen in the same transaction as recording
+        // the removal, and can therefore not use `removable`
+        let pconn = self.primary_conn()?;
+        if !pconn.start_removal(site.as_ref())? {
+            pconn.unused_deployment_is_used(site.as_ref())?;
+            return Ok(false);
+        }
+        self.evict(&site.deployment)?;
+        Ok(true)
+    }
+
+    /// Drop the next table of a deployment for which `start_removal` was
+    /// called. Once all its data is gone, remove its metadata, too, and
+    /// report the removal as done. Callers should pause between steps
+    pub fn continue_removal(&self, id: DeploymentId) -> Result<RemovalStep, StoreError> {
+        let site = self.find_site(id)?;
+        let store = self.for_site(site.as_ref())?;
+
+        let step = store.remove_deployment_step(site.as_ref())?;
+
+        let pconn = self.primary_conn()?;
+        pconn.record_removal_progress(site.as_ref(), step.tables_dropped as i32)?;
+        if step.done {
+            pconn.drop_site(site.as_ref())?;
+        }
+        Ok(step)
+    }
+
+    /// Return all removals that were started but have not finished yet
+    pub fn removals(&self) -> Result<Vec<DeploymentRemoval>, StoreError> {
+        self.primary_conn()?.removals()
+    }
+
     pub fn status_for_id(&self, id: graph::components::store::DeploymentId) -> status::Info {
         let filter = status::Filter::DeploymentIds(vec![id]);
         self.status(filter).unwrap().into_iter().next().unwrap()
@@ -880,9 +963,44 @@ impl SubgraphStoreInner {
             infos.extend(store.deployment_statuses(&sites)?);
         }
         self.mirror.fill_assignments(&mut infos)?;
+
+        // Only the writables of the deployments that this node runs know
+        // whether they wait for the database
+        let writables = self.writables.lock().unwrap();
+        for info in &mut infos {
+            info.store_unavailable = writables
+                .get(&info.id)
+                .and_then(|writable| writable.store_unavailable_for());
+        }
         Ok(infos)
     }
 
+    /// Return the hourly usage records for `deployments` for the hours
+    /// that start in `[from, to)`. If `deployments` is empty, return the
+    /// records for all deployments
+    pub(crate) fn usage(
+        &self,
+        deployments: Vec<String>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<status::UsageRecord>, StoreError> {
+        let sites = self.mirror.find_sites(&deployments, false)?;
+        let by_shard: HashMap<Shard, Vec<Arc<Site>>> = self.deployments_by_shard(sites)?;
+
+        let mut records = Vec::new();
+        for (shard, sites) in by_shard.into_iter() {
+            let store = self
+                .stores
+                .get(&shard)
+                .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+            records.extend(store.usage(&sites, from, to)?);
+        }
+        records.sort_by(|a, b| {
+            (&a.deployment, a.bucket, &a.resource).cmp(&(&b.deployment, b.bucket, &b.resource))
+        });
+        Ok(records)
+    }
+
     pub(crate) fn version_info(&self, version: &str) -> Result<VersionInfo, StoreError> {
         if let Some((deployment_id, created_at)) = self.mirror.version_info(version)? {
             let id = DeploymentHash::new(deployment_id.clone())
@@ -944,6 +1062,17 @@ impl SubgraphStoreInner {
         join_all(self.stores.values().map(|store| store.vacuum())).await
     }
 
+    /// Remove usage records that are older than `retention_days` in all
+    /// shards and return how many were removed in each shard
+    pub(crate) async fn expire_usage(&self, retention_days: u32) -> Vec<Result<usize, StoreError>> {
+        join_all(
+            self.stores
+                .values()
+                .map(|store| store.expire_usage(retention_days)),
+        )
+        .await
+    }
+
     pub fn rewind(&self, id: DeploymentHash, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
         let (store, site) = self.store(&id)?;
         let event = store.rewind(site, block_ptr_to)?;
@@ -1108,6 +1237,103 @@ impl SubgraphStoreInner {
         store.set_account_like(site, table, is_account_like).await
     }
 
+    /// Return the recorded compatibility fingerprint of `deployment` and
+    /// the one it was last started with if that is different; see
+    /// `WritableStore::check_compatibility`
+    pub fn compatibility(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<(CompatibilityFingerprint, Option<CompatibilityFingerprint>)>, StoreError>
+    {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.compatibility(site)
+    }
+
+    /// Accept the fingerprint that `deployment` was last started with as
+    /// its recorded compatibility fingerprint. Return `false` if the
+    /// fingerprints did not differ
+    pub fn acknowledge_compatibility(
+        &self,
+        deployment: &DeploymentLocator,
+        note: Option<&str>,
+    ) -> Result<bool, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.acknowledge_compatibility(site, note)
+    }
+
+    /// Return the compile profile that was set for `deployment`, and the
+    /// profile its modules were last compiled with together with how long
+    /// compiling them took
+    pub fn compilation(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<(Option<CompileProfile>, Option<(CompileProfile, Duration)>), StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.compilation(site)
+    }
+
+    /// Set the profile with which the modules of `deployment` are compiled
+    /// the next time it is started; with `None`, use the default of the
+    /// node that runs it
+    pub fn set_compile_profile(
+        &self,
+        deployment: &DeploymentLocator,
+        profile: Option<CompileProfile>,
+    ) -> Result<(), StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.set_compile_profile(site, profile)
+    }
+
+    /// Return the reorg threshold that `deployment` uses instead of the one
+    /// of its chain, if any
+    pub fn reorg_threshold(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.reorg_threshold(site)
+    }
+
+    /// Set the reorg threshold that `deployment` uses instead of the one of
+    /// its chain; with `None`, it uses the one of its chain. The caller
+    /// must make sure that the chain allows the threshold
+    pub fn set_reorg_threshold(
+        &self,
+        deployment: &DeploymentLocator,
+        reorg_threshold: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.set_reorg_threshold(site, reorg_threshold)
+    }
+
+    /// Return the cumulative statistics of the handlers of `deployment` as
+    /// of the last time they were written
+    pub fn handler_stats(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.handler_stats(site)
+    }
+
+    /// Add `stats` to the cumulative statistics of the handlers of
+    /// `deployment`
+    pub fn record_handler_stats(
+        &self,
+        deployment: &DeploymentHash,
+        stats: &[status::HandlerStats],
+    ) -> Result<(), StoreError> {
+        let (store, site) = self.store(deployment)?;
+        store.record_handler_stats(site, stats).map(|_| ())
+    }
+
+    /// Remove the statistics of the handlers of `deployment` so that they
+    /// start over from zero
+    pub fn reset_handler_stats(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.reset_handler_stats(site)
+    }
+
     /// Remove the history that is only needed to respond to queries before
     /// block number `earliest_block` from the given deployment
     ///
@@ -1121,7 +1347,9 @@ impl SubgraphStoreInner {
     /// The `reorg_threshold` is used to determine which blocks will not be
     /// modified any more by the subgraph writer that may be running
     /// concurrently to reduce the amount of time that the writer needs to
-    /// be locked out while pruning is happening.
+    /// be locked out while pruning is happening. It is the reorg threshold
+    /// of the chain; if the deployment set a lower one for itself, that is
+    /// used instead.
     ///
     /// Pruning can take a long time, and is structured into multiple
     /// transactions such that none of them takes an excessively long time.
@@ -1146,6 +1374,135 @@ impl SubgraphStoreInner {
             .await
     }
 
+    /// The retention policies of `deployment`
+    pub fn entity_retention(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Vec<EntityRetention>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.entity_retention(site)
+    }
+
+    /// Set the retention policy of one entity type of `deployment`,
+    /// replacing any policy declared in the manifest
+    pub fn set_entity_retention(
+        &self,
+        deployment: &DeploymentLocator,
+        retention: &EntityRetention,
+    ) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.set_entity_retention(site, retention)
+    }
+
+    /// Remove the retention policy of `entity_type` so that its rows are
+    /// kept. Return `false` if it did not have one
+    pub fn remove_entity_retention(
+        &self,
+        deployment: &DeploymentLocator,
+        entity_type: &str,
+    ) -> Result<bool, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?
+            .remove_entity_retention(site, entity_type)
+    }
+
+    /// The entity types of `deployment` whose delivery to the bus needs to
+    /// be verifiable
+    pub fn bus_critical_entity_types(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<BTreeSet<EntityType>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.bus_critical_entity_types(site)
+    }
+
+    /// Make `entity_types` the critical entity types of `deployment`. The
+    /// delivery status of types that are no longer critical is forgotten
+    pub fn set_bus_critical_entity_types(
+        &self,
+        deployment: &DeploymentLocator,
+        entity_types: &BTreeSet<EntityType>,
+    ) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?
+            .set_bus_critical_entity_types(site, entity_types)
+    }
+
+    /// Forget the dead letters of the critical entity types of
+    /// `deployment` for all backends or only for `backend` so that their
+    /// delivery is tracked again. Return how many were forgotten
+    pub fn acknowledge_bus_dead_letters(
+        &self,
+        deployment: &DeploymentLocator,
+        backend: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?
+            .acknowledge_bus_dead_letters(site, backend)
+    }
+
+    /// The labels on `deployment`
+    pub fn deployment_labels(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Vec<String>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.deployment_labels(site)
+    }
+
+    /// Put `label` on `deployment`. Return `false` if it already had it
+    pub fn add_deployment_label(
+        &self,
+        deployment: &DeploymentLocator,
+        label: &str,
+    ) -> Result<bool, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.add_deployment_label(site, label)
+    }
+
+    /// Take `label` off `deployment`. Return `false` if it did not have it
+    pub fn remove_deployment_label(
+        &self,
+        deployment: &DeploymentLocator,
+        label: &str,
+    ) -> Result<bool, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.remove_deployment_label(site, label)
+    }
+
+    /// All deployments that have retention policies
+    pub fn retained_deployments(&self) -> Result<Vec<DeploymentLocator>, StoreError> {
+        let mut ids = Vec::new();
+        for store in self.stores.values() {
+            ids.extend(store.retained_deployments()?);
+        }
+        Ok(self
+            .mirror
+            .find_sites_by_id(&ids)?
+            .iter()
+            .map(DeploymentLocator::from)
+            .collect())
+    }
+
+    /// Remove the rows that the retention policies of `deployment` match
+    /// until `deadline`; see `DeploymentStore::apply_retention`. With
+    /// `dry_run`, only report what would be removed
+    pub async fn apply_retention(
+        &self,
+        logger: &Logger,
+        deployment: &DeploymentLocator,
+        reorg_threshold: BlockNumber,
+        deadline: Instant,
+        dry_run: bool,
+    ) -> Result<Vec<RetentionOutcome>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let logger = logger
+            .new(o!("sgd" => site.id.to_string(), "deployment" => site.deployment.to_string()));
+        self.for_site(&site)?
+            .apply_retention(logger, site, reorg_threshold, deadline, dry_run)
+            .await
+    }
+
     pub fn load_deployment(&self, site: &Site) -> Result<SubgraphDeploymentEntity, StoreError> {
         let src_store = self.for_site(site)?;
         src_store.load_deployment(site)
@@ -1267,6 +1624,14 @@ impl SubgraphStoreTrait for SubgraphStore {
         self.mirror.assigned_node(site.as_ref())
     }
 
+    fn label_deployment(
+        &self,
+        deployment: &DeploymentLocator,
+        label: &str,
+    ) -> Result<(), StoreError> {
+        self.add_deployment_label(deployment, label).map(|_| ())
+    }
+
     fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError> {
         self.mirror
             .assignments(node)
@@ -1287,6 +1652,25 @@ impl SubgraphStoreTrait for SubgraphStore {
         Ok(changes)
     }
 
+    fn entity_type_stats(
+        &self,
+        id: &DeploymentHash,
+        exact: bool,
+    ) -> Result<Vec<status::EntityTypeStats>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.entity_type_stats(site, exact)
+    }
+
+    fn block_providers(
+        &self,
+        id: &DeploymentHash,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<status::BlockProvider>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.block_providers(site, from, to)
+    }
+
     fn input_schema(&self, id: &DeploymentHash) -> Result<Arc<Schema>, StoreError> {
         let (store, site) = self.store(id)?;
         let info = store.subgraph_info(&site)?;
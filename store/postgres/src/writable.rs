@@ -1,29 +1,44 @@
+use std::cell::Cell;
 use std::collections::BTreeSet;
+use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{collections::BTreeMap, sync::Arc};
 
 use graph::blockchain::block_stream::FirehoseCursor;
+use graph::components::metrics::usage::{usage_tracker, Usage};
 use graph::components::store::EntityKey;
-use graph::components::store::ReadStore;
+use graph::components::store::{ReadStore, RelatedEntityQuery};
+use graph::components::subgraph::activity::{deployment_activity, DeploymentActivity};
+use graph::components::subgraph::handler_entity_types::HandlerWrite;
+use graph::data::subgraph::fingerprint::CompatibilityFingerprint;
+use graph::data::subgraph::invariant::{Invariant, InvariantOutcome};
+use graph::data::subgraph::retention::EntityRetention;
 use graph::data::subgraph::schema;
+use graph::data::subgraph::status::{self, PoiDivergence};
+use graph::data::value::Word;
 use graph::data_source::CausalityRegion;
+use graph::prelude::web3::types::Address;
 use graph::prelude::{
-    BlockNumber, Entity, MetricsRegistry, Schema, SubgraphDeploymentEntity, SubgraphStore as _,
-    BLOCK_NUMBER_MAX,
+    BlockNumber, Counter, CounterVec, Entity, Gauge, MetricsRegistry, Schema,
+    SubgraphDeploymentEntity, SubgraphStore as _, BLOCK_NUMBER_MAX,
 };
+use graph::runtime::CompileProfile;
 use graph::slog::info;
 use graph::util::bounded_queue::BoundedQueue;
 use graph::{
     cheap_clone::CheapClone,
-    components::store::{self, DeploymentLocator, EntityType, WritableStore as WritableStoreTrait},
+    components::store::{
+        self, DeploymentLocator, EntityType, PendingSyncedActions,
+        WritableStore as WritableStoreTrait,
+    },
     data::subgraph::schema::SubgraphError,
     prelude::{
         BlockPtr, DeploymentHash, EntityModification, Error, Logger, StopwatchMetrics, StoreError,
         StoreEvent, UnfailOutcome, ENV_VARS,
     },
-    slog::{error, warn},
+    slog::{debug, error, warn},
     util::backoff::ExponentialBackoff,
 };
 use store::StoredDynamicDataSource;
@@ -65,6 +80,48 @@ struct SyncStore {
     writable: Arc<DeploymentStore>,
     site: Arc<Site>,
     input_schema: Arc<Schema>,
+    unavailable: UnavailableMetrics,
+    /// Counts the checks of the invariants from the manifest by invariant
+    /// and outcome
+    invariant_checks: Box<CounterVec>,
+    /// The number of operations that wait for the database, and when the
+    /// first of them started waiting
+    waiting: Mutex<(usize, Option<Instant>)>,
+}
+
+/// Metrics for the time that operations of a deployment spend waiting for
+/// the database to become available again
+struct UnavailableMetrics {
+    /// The number of operations that are currently waiting; while this is
+    /// not zero, the deployment is paused
+    waiting: Gauge,
+    /// The total time that operations spent waiting
+    wait_secs: Counter,
+}
+
+impl UnavailableMetrics {
+    fn new(registry: &dyn MetricsRegistry, site: &Site) -> Result<Self, StoreError> {
+        let deployment = DeploymentLocator::new(
+            site.id.into(),
+            site.deployment.clone(),
+            site.subgraph_name.clone(),
+        );
+        let waiting = registry
+            .new_deployment_gauge(
+                "deployment_store_unavailable",
+                "The number of store operations that are waiting for the database to become available",
+                &deployment,
+            )
+            .map_err(Error::from)?;
+        let wait_secs = registry
+            .new_deployment_counter(
+                "deployment_store_unavailable_secs",
+                "The total time in seconds that store operations spent waiting for the database to become available",
+                &deployment,
+            )
+            .map_err(Error::from)?;
+        Ok(Self { waiting, wait_secs })
+    }
 }
 
 impl SyncStore {
@@ -75,16 +132,33 @@ impl SyncStore {
         subgraph_store: SubgraphStore,
         logger: Logger,
         site: Arc<Site>,
+        registry: &dyn MetricsRegistry,
     ) -> Result<Self, StoreError> {
         let store = WritableSubgraphStore(subgraph_store.clone());
         let writable = subgraph_store.for_site(site.as_ref())?.clone();
         let input_schema = subgraph_store.input_schema(&site.deployment)?;
+        let unavailable = UnavailableMetrics::new(registry, &site)?;
+        let invariant_checks = registry
+            .new_deployment_counter_vec(
+                "deployment_invariant_checks",
+                "Counts the checks of the invariants from the manifest of a subgraph deployment by outcome",
+                &DeploymentLocator::new(
+                    site.id.into(),
+                    site.deployment.clone(),
+                    site.subgraph_name.clone(),
+                ),
+                vec![String::from("invariant"), String::from("outcome")],
+            )
+            .map_err(Error::from)?;
         Ok(Self {
             logger,
             store,
             writable,
             site,
             input_schema,
+            unavailable,
+            invariant_checks,
+            waiting: Mutex::new((0, None)),
         })
     }
 
@@ -96,18 +170,60 @@ impl SyncStore {
             "delay_ms" => backoff.delay().as_millis());
     }
 
+    /// Record that `op` found the database unavailable. Returns when
+    /// the wait started, which is `since` if the operation was already
+    /// waiting
+    fn start_waiting(
+        &self,
+        op: &str,
+        backoff: &ExponentialBackoff,
+        since: Option<Instant>,
+    ) -> Instant {
+        self.log_backoff_warning(op, backoff);
+        since.unwrap_or_else(|| {
+            self.unavailable.waiting.inc();
+            let mut waiting = self.waiting.lock().unwrap();
+            waiting.0 += 1;
+            waiting.1.get_or_insert_with(Instant::now);
+            Instant::now()
+        })
+    }
+
+    /// Record that `op`, which was waiting for the database since `since`,
+    /// could reach the database again
+    fn stop_waiting(&self, op: &str, since: Option<Instant>) {
+        if let Some(since) = since {
+            let waited = since.elapsed();
+            self.unavailable.waiting.dec();
+            {
+                let mut waiting = self.waiting.lock().unwrap();
+                waiting.0 = waiting.0.saturating_sub(1);
+                if waiting.0 == 0 {
+                    waiting.1 = None;
+                }
+            }
+            self.unavailable.wait_secs.inc_by(waited.as_secs_f64());
+            info!(self.logger, "database available again";
+                "operation" => op,
+                "waited_ms" => waited.as_millis());
+        }
+    }
+
     fn retry<T, F>(&self, op: &str, f: F) -> Result<T, StoreError>
     where
         F: Fn() -> Result<T, StoreError>,
     {
         let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL);
+        let mut since = None;
         loop {
             match f() {
-                Ok(v) => return Ok(v),
                 Err(StoreError::DatabaseUnavailable) => {
-                    self.log_backoff_warning(op, &backoff);
+                    since = Some(self.start_waiting(op, &backoff, since));
+                }
+                res => {
+                    self.stop_waiting(op, since);
+                    return res;
                 }
-                Err(e) => return Err(e),
             }
             backoff.sleep();
         }
@@ -119,13 +235,16 @@ impl SyncStore {
         Fut: std::future::Future<Output = Result<T, StoreError>>,
     {
         let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL);
+        let mut since = None;
         loop {
             match f().await {
-                Ok(v) => return Ok(v),
                 Err(StoreError::DatabaseUnavailable) => {
-                    self.log_backoff_warning(op, &backoff);
+                    since = Some(self.start_waiting(op, &backoff, since));
+                }
+                res => {
+                    self.stop_waiting(op, since);
+                    return res;
                 }
-                Err(e) => return Err(e),
             }
             backoff.sleep_async().await;
         }
@@ -156,10 +275,12 @@ impl SyncStore {
     }
 
     async fn block_cursor(&self) -> Result<FirehoseCursor, StoreError> {
-        self.writable
-            .block_cursor(self.site.cheap_clone())
-            .await
-            .map(FirehoseCursor::from)
+        self.retry_async("block_cursor", || {
+            let site = self.site.clone();
+            async move { self.writable.block_cursor(site).await }
+        })
+        .await
+        .map(FirehoseCursor::from)
     }
 
     fn start_subgraph_deployment(&self, logger: &Logger) -> Result<(), StoreError> {
@@ -178,6 +299,121 @@ impl SyncStore {
         })
     }
 
+    fn record_node_version(&self, host_export_hash: &str) -> Result<(), StoreError> {
+        self.retry("record_node_version", || {
+            self.writable
+                .record_node_version(self.site.cheap_clone(), host_export_hash)
+        })
+    }
+
+    fn check_compatibility(
+        &self,
+        current: &CompatibilityFingerprint,
+    ) -> Result<Option<CompatibilityFingerprint>, StoreError> {
+        self.retry("check_compatibility", || {
+            self.writable
+                .check_compatibility(self.site.cheap_clone(), current)
+        })
+    }
+
+    fn compile_profile(&self) -> Result<Option<CompileProfile>, StoreError> {
+        self.retry("compile_profile", || {
+            self.writable.compile_profile(self.site.cheap_clone())
+        })
+    }
+
+    fn record_compilation(
+        &self,
+        profile: CompileProfile,
+        compile_time: Duration,
+    ) -> Result<(), StoreError> {
+        self.retry("record_compilation", || {
+            self.writable
+                .record_compilation(self.site.cheap_clone(), profile, compile_time)
+        })
+    }
+
+    fn reorg_threshold(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.retry("reorg_threshold", || {
+            self.writable.reorg_threshold(self.site.cheap_clone())
+        })
+    }
+
+    fn start_entity_retention(&self) -> Result<Vec<EntityRetention>, StoreError> {
+        self.retry("start_entity_retention", || {
+            self.writable
+                .start_entity_retention(self.site.cheap_clone())
+        })
+    }
+
+    fn record_usage(&self, usage: &Usage) -> Result<(), StoreError> {
+        self.retry("record_usage", || {
+            self.writable.record_usage(self.site.cheap_clone(), usage)
+        })
+    }
+
+    fn record_handler_stats(
+        &self,
+        stats: &[status::HandlerStats],
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        self.retry("record_handler_stats", || {
+            self.writable
+                .record_handler_stats(self.site.cheap_clone(), stats)
+        })
+    }
+
+    fn handler_stats(&self) -> Result<status::HandlerStatsReport, StoreError> {
+        self.retry("handler_stats", || {
+            self.writable.handler_stats(self.site.cheap_clone())
+        })
+    }
+
+    fn record_handler_entity_types(&self, writes: &[HandlerWrite]) -> Result<(), StoreError> {
+        self.retry("record_handler_entity_types", || {
+            self.writable
+                .record_handler_entity_types(self.site.cheap_clone(), writes)
+        })
+    }
+
+    fn record_bus_backends(&self, backends: &[String]) -> Result<(), StoreError> {
+        self.retry("record_bus_backends", || {
+            self.writable
+                .record_bus_backends(self.site.cheap_clone(), backends)
+        })
+    }
+
+    fn bus_critical_entity_types(&self) -> Result<BTreeSet<EntityType>, StoreError> {
+        self.retry("bus_critical_entity_types", || {
+            self.writable
+                .bus_critical_entity_types(self.site.cheap_clone())
+        })
+    }
+
+    fn record_bus_critical_delivery(
+        &self,
+        backend: &str,
+        entity_types: &BTreeSet<EntityType>,
+        block: BlockNumber,
+        delivered: bool,
+    ) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError> {
+        self.retry("record_bus_critical_delivery", || {
+            self.writable.record_bus_critical_delivery(
+                self.site.cheap_clone(),
+                backend,
+                entity_types,
+                block,
+                delivered,
+            )
+        })
+    }
+
+    fn record_poi_divergence(&self, divergence: Option<&PoiDivergence>) -> Result<(), StoreError> {
+        self.retry("record_poi_divergence", || {
+            self.writable
+                .record_poi_divergence(self.site.cheap_clone(), divergence)
+        })
+    }
+
     fn revert_block_operations(
         &self,
         block_ptr_to: BlockPtr,
@@ -237,6 +473,19 @@ impl SyncStore {
         .await
     }
 
+    async fn public_proof_of_indexing(
+        &self,
+        block: &BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        let indexer = Some(Address::zero());
+        self.retry_async("public_proof_of_indexing", || async {
+            self.writable
+                .get_proof_of_indexing(self.site.clone(), &indexer, block.clone())
+                .await
+        })
+        .await
+    }
+
     fn get(&self, key: &EntityKey, block: BlockNumber) -> Result<Option<Entity>, StoreError> {
         self.retry("get", || {
             self.writable.get(self.site.cheap_clone(), key, block)
@@ -253,24 +502,98 @@ impl SyncStore {
         deterministic_errors: &[SubgraphError],
         manifest_idx_and_name: &[(u32, String)],
         processed_data_sources: &[StoredDynamicDataSource],
+        block_provider: Option<&str>,
+        invariants: &[Invariant],
     ) -> Result<(), StoreError> {
-        self.retry("transact_block_operations", move || {
-            let event = self.writable.transact_block_operations(
-                self.site.clone(),
-                block_ptr_to,
-                firehose_cursor,
-                mods,
-                stopwatch,
-                data_sources,
-                deterministic_errors,
-                manifest_idx_and_name,
-                processed_data_sources,
-            )?;
+        let start = Instant::now();
+        // Set when an attempt failed because the database became
+        // unavailable. The attempt might have been committed before the
+        // connection was lost, and we need to check the block pointer in
+        // the database before trying again
+        let interrupted = AtomicBool::new(false);
+        let res = self.retry("transact_block_operations", || {
+            if interrupted.load(Ordering::SeqCst) {
+                // If this fails, the flag stays set so that the next
+                // attempt checks again
+                let db_ptr = self.writable.block_ptr_sync(self.site.cheap_clone())?;
+                interrupted.store(false, Ordering::SeqCst);
+                if db_ptr.as_ref() == Some(block_ptr_to) {
+                    // The attempt that wrote the block never got to send
+                    // its store event, and subscribers would miss the
+                    // changes if we did not send it now
+                    info!(self.logger, "block was written before the database became unavailable";
+                        "block" => block_ptr_to);
+                    let event = StoreEvent::from_mods(&self.site.deployment, mods);
+                    self.try_send_store_event(event)?;
+                    return Ok(());
+                }
+            }
+            let (event, outcomes, violation) = self
+                .writable
+                .transact_block_operations(
+                    self.site.clone(),
+                    block_ptr_to,
+                    firehose_cursor,
+                    mods,
+                    stopwatch,
+                    data_sources,
+                    deterministic_errors,
+                    manifest_idx_and_name,
+                    processed_data_sources,
+                    block_provider,
+                    invariants,
+                )
+                .map_err(|e| {
+                    if matches!(e, StoreError::DatabaseUnavailable) {
+                        interrupted.store(true, Ordering::SeqCst);
+                    }
+                    e
+                })?;
+            self.observe_invariant_checks(invariants, &outcomes);
 
             let _section = stopwatch.start_section("send_store_event");
             self.try_send_store_event(event)?;
-            Ok(())
-        })
+            match violation {
+                Some(error) => Err(StoreError::InvariantViolated(error)),
+                None => Ok(()),
+            }
+        });
+        // Look the tracker up for every write since the runner removes it
+        // when it exits while this store can outlive the runner
+        usage_tracker(self.site.deployment.as_str()).store_write(start.elapsed());
+        res
+    }
+
+    fn observe_invariant_checks(
+        &self,
+        invariants: &[Invariant],
+        outcomes: &[(String, InvariantOutcome)],
+    ) {
+        for (invariant, (_, outcome)) in invariants.iter().zip(outcomes) {
+            let label = match outcome {
+                InvariantOutcome::Holds => "holds",
+                InvariantOutcome::Violated { .. } => "violated",
+                InvariantOutcome::Skipped(_) => "skipped",
+            };
+            self.invariant_checks
+                .with_label_values(&[invariant.name.as_str(), label])
+                .inc();
+            match outcome {
+                InvariantOutcome::Holds => {}
+                InvariantOutcome::Violated { left, right } => {
+                    warn!(self.logger, "Invariant violated";
+                        "invariant" => &invariant.name,
+                        "check" => invariant.check.to_string(),
+                        "strict" => invariant.strict,
+                        "message" => invariant.check.violation_message(left, right));
+                }
+                InvariantOutcome::Skipped(reason) => {
+                    debug!(self.logger, "Skipped invariant check";
+                        "invariant" => &invariant.name,
+                        "reason" => reason);
+                }
+            }
+        }
     }
 
     fn get_many(
@@ -292,6 +615,19 @@ impl SyncStore {
         })
     }
 
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        self.retry("get_related", || {
+            self.writable
+                .get_related(self.site.cheap_clone(), query, after, limit, block)
+        })
+    }
+
     async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
         self.retry_async("is_deployment_synced", || async {
             self.writable
@@ -339,7 +675,11 @@ impl SyncStore {
         .await
     }
 
-    fn deployment_synced(&self) -> Result<(), StoreError> {
+    fn deployment_synced(&self, block_ptr: BlockPtr) -> Result<bool, StoreError> {
+        // Remember whether we marked the deployment as synced even if a
+        // later step fails and we retry, since the retry will find it
+        // already synced
+        let newly_synced = Cell::new(false);
         self.retry("deployment_synced", || {
             let event = {
                 // Make sure we drop `pconn` before we call into the deployment
@@ -352,9 +692,33 @@ impl SyncStore {
                 })?
             };
 
-            self.writable.deployment_synced(&self.site.deployment)?;
+            if self
+                .writable
+                .deployment_synced(&self.site.deployment, &block_ptr)?
+            {
+                newly_synced.set(true);
+            }
 
             self.store.send_store_event(&event)
+        })?;
+        Ok(newly_synced.get())
+    }
+
+    fn synced_actions_pending(&self) -> Result<Option<PendingSyncedActions>, StoreError> {
+        self.retry("synced_actions_pending", || {
+            self.writable.synced_actions_pending(&self.site)
+        })
+    }
+
+    fn synced_action_done(&self, key: String) -> Result<(), StoreError> {
+        self.retry("synced_action_done", || {
+            self.writable.synced_action_done(&self.site, &key)
+        })
+    }
+
+    fn synced_actions_done(&self) -> Result<(), StoreError> {
+        self.retry("synced_actions_done", || {
+            self.writable.synced_actions_done(&self.site)
         })
     }
 
@@ -442,6 +806,10 @@ enum Request {
         deterministic_errors: Vec<SubgraphError>,
         manifest_idx_and_name: Vec<(u32, String)>,
         processed_data_sources: Vec<StoredDynamicDataSource>,
+        /// The label of the provider that served the block
+        block_provider: Option<String>,
+        /// The invariants to check with the changes written
+        invariants: Vec<Invariant>,
     },
     RevertTo {
         store: Arc<SyncStore>,
@@ -452,6 +820,18 @@ enum Request {
     Stop,
 }
 
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Write { block_ptr, .. } => write!(f, "write block {}", block_ptr.number),
+            Request::RevertTo { block_ptr, .. } => {
+                write!(f, "revert to block {}", block_ptr.number)
+            }
+            Request::Stop => write!(f, "stop"),
+        }
+    }
+}
+
 enum ExecResult {
     Continue,
     Stop,
@@ -470,6 +850,8 @@ impl Request {
                 deterministic_errors,
                 manifest_idx_and_name,
                 processed_data_sources,
+                block_provider,
+                invariants,
             } => store
                 .transact_block_operations(
                     block_ptr_to,
@@ -480,6 +862,8 @@ impl Request {
                     deterministic_errors,
                     manifest_idx_and_name,
                     processed_data_sources,
+                    block_provider.as_deref(),
+                    invariants,
                 )
                 .map(|()| ExecResult::Continue),
             Request::RevertTo {
@@ -516,6 +900,9 @@ struct Queue {
     poisoned: AtomicBool,
 
     stopwatch: StopwatchMetrics,
+
+    /// Where the writer records which request it is executing
+    activity: Arc<DeploymentActivity>,
 }
 
 /// Support for controlling the background writer (pause/resume) only for
@@ -572,10 +959,14 @@ impl Queue {
                     let _section = queue.stopwatch.start_section("queue_wait");
                     queue.queue.peek().await
                 };
+                queue
+                    .activity
+                    .write_started(req.to_string(), queue.queue.len());
                 let res = {
                     let _section = queue.stopwatch.start_section("queue_execute");
                     graph::spawn_blocking_allow_panic(move || req.execute()).await
                 };
+                queue.activity.write_finished();
 
                 let _section = queue.stopwatch.start_section("queue_pop");
                 use ExecResult::*;
@@ -635,6 +1026,7 @@ impl Queue {
         );
 
         let stopwatch = StopwatchMetrics::new(logger.clone(), &deployment, "writer", registry);
+        let activity = deployment_activity(deployment.hash.as_str());
 
         let queue = Self {
             store,
@@ -642,6 +1034,7 @@ impl Queue {
             write_err,
             poisoned: AtomicBool::new(false),
             stopwatch,
+            activity,
         };
         let queue = Arc::new(queue);
 
@@ -779,6 +1172,52 @@ impl Queue {
         Ok(map)
     }
 
+    /// Get the first `limit` entities after `after` that match `query` by
+    /// looking at both the queue and the store
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        // See the implementation of `get` for how we handle reverts
+        let mut tracker = BlockTracker::new();
+
+        // The most recent change for each entity of the queried type in
+        // the queue
+        let entities_in_queue = self.queue.fold(
+            BTreeMap::new(),
+            |mut map: BTreeMap<EntityKey, Option<Entity>>, req| {
+                tracker.update(req.as_ref());
+                match req.as_ref() {
+                    Request::Write {
+                        block_ptr, mods, ..
+                    } => {
+                        if tracker.visible(block_ptr) {
+                            for emod in mods {
+                                let key = emod.entity_ref();
+                                if key.entity_type == query.entity_type
+                                    && key.causality_region == query.causality_region
+                                    && !map.contains_key(key)
+                                {
+                                    map.insert(key.clone(), emod.entity().cloned());
+                                }
+                            }
+                        }
+                    }
+                    Request::RevertTo { .. } | Request::Stop => { /* nothing to do */ }
+                }
+                map
+            },
+        );
+
+        // Changes in the queue override what is in the store
+        let block = tracker.query_block();
+        query.page(after, limit, &entities_in_queue, |after, limit| {
+            self.store.get_related(query, after, limit, block)
+        })
+    }
+
     /// Load dynamic data sources by looking at both the queue and the store
     async fn load_dynamic_data_sources(
         &self,
@@ -814,11 +1253,11 @@ impl Queue {
             }
             dds
         });
-        // Using a stable sort is important here so that dds created at the
-        // same block stay in the order in which they were added (and
-        // therefore will be loaded from the store in that order once the
-        // queue has been written)
-        queue_dds.sort_by_key(|dds| dds.creation_block);
+        // Sort the same way as the store does when it loads dds. Using a
+        // stable sort is important here so that dds created at the same
+        // block without a creation sequence stay in the order in which they
+        // were added
+        queue_dds.sort_by_key(|dds| (dds.creation_block, dds.creation_seq));
 
         let mut dds = self
             .store
@@ -865,6 +1304,8 @@ impl Writer {
         deterministic_errors: Vec<SubgraphError>,
         manifest_idx_and_name: Vec<(u32, String)>,
         processed_data_sources: Vec<StoredDynamicDataSource>,
+        block_provider: Option<String>,
+        invariants: Vec<Invariant>,
     ) -> Result<(), StoreError> {
         match self {
             Writer::Sync(store) => store.transact_block_operations(
@@ -876,6 +1317,8 @@ impl Writer {
                 &deterministic_errors,
                 &manifest_idx_and_name,
                 &processed_data_sources,
+                block_provider.as_deref(),
+                &invariants,
             ),
             Writer::Async(queue) => {
                 let req = Request::Write {
@@ -888,6 +1331,8 @@ impl Writer {
                     deterministic_errors,
                     manifest_idx_and_name,
                     processed_data_sources,
+                    block_provider,
+                    invariants,
                 };
                 queue.push(req).await
             }
@@ -936,6 +1381,18 @@ impl Writer {
         }
     }
 
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        match self {
+            Writer::Sync(store) => store.get_related(query, after, limit, BLOCK_NUMBER_MAX),
+            Writer::Async(queue) => queue.get_related(query, after, limit),
+        }
+    }
+
     async fn load_dynamic_data_sources(
         &self,
         manifest_idx_and_name: Vec<(u32, String)>,
@@ -979,7 +1436,12 @@ impl WritableStore {
         site: Arc<Site>,
         registry: Arc<dyn MetricsRegistry>,
     ) -> Result<Self, StoreError> {
-        let store = Arc::new(SyncStore::new(subgraph_store, logger.clone(), site)?);
+        let store = Arc::new(SyncStore::new(
+            subgraph_store,
+            logger.clone(),
+            site,
+            registry.as_ref(),
+        )?);
         let block_ptr = Mutex::new(store.block_ptr().await?);
         let block_cursor = Mutex::new(store.block_cursor().await?);
         let writer = Writer::new(
@@ -1001,6 +1463,17 @@ impl WritableStore {
         self.writer.poisoned()
     }
 
+    /// How long operations of the deployment have been waiting for the
+    /// database; `None` if none of them is waiting
+    pub(crate) fn store_unavailable_for(&self) -> Option<Duration> {
+        self.store
+            .waiting
+            .lock()
+            .unwrap()
+            .1
+            .map(|since| since.elapsed())
+    }
+
     pub(crate) async fn stop(&self) -> Result<(), StoreError> {
         self.writer.stop().await
     }
@@ -1018,6 +1491,15 @@ impl ReadStore for WritableStore {
         self.writer.get_many(keys)
     }
 
+    fn get_related(
+        &self,
+        query: &RelatedEntityQuery,
+        after: Option<&Word>,
+        limit: usize,
+    ) -> Result<BTreeMap<EntityKey, Entity>, StoreError> {
+        self.writer.get_related(query, after, limit)
+    }
+
     fn input_schema(&self) -> Arc<Schema> {
         self.store.input_schema()
     }
@@ -1047,6 +1529,142 @@ impl WritableStoreTrait for WritableStore {
         Ok(())
     }
 
+    async fn record_node_version(&self, host_export_hash: String) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_node_version(&host_export_hash))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn check_compatibility(
+        &self,
+        current: CompatibilityFingerprint,
+    ) -> Result<Option<CompatibilityFingerprint>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.check_compatibility(&current))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn compile_profile(&self) -> Result<Option<CompileProfile>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.compile_profile())
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_compilation(
+        &self,
+        profile: CompileProfile,
+        compile_time: Duration,
+    ) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_compilation(profile, compile_time))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn reorg_threshold(&self) -> Result<Option<BlockNumber>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.reorg_threshold())
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn start_entity_retention(&self) -> Result<Vec<EntityRetention>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.start_entity_retention())
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_usage(&self, usage: Usage) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_usage(&usage))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_handler_stats(
+        &self,
+        stats: Vec<status::HandlerStats>,
+    ) -> Result<status::HandlerStatsReport, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_handler_stats(&stats))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn handler_stats(&self) -> Result<status::HandlerStatsReport, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.handler_stats())
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_handler_entity_types(
+        &self,
+        writes: Vec<HandlerWrite>,
+    ) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_handler_entity_types(&writes))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_bus_backends(&self, backends: Vec<String>) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_bus_backends(&backends))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn bus_critical_entity_types(&self) -> Result<BTreeSet<EntityType>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.bus_critical_entity_types())
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn record_bus_critical_delivery(
+        &self,
+        backend: String,
+        entity_types: BTreeSet<EntityType>,
+        block: BlockNumber,
+        delivered: bool,
+    ) -> Result<Vec<(EntityType, Option<BlockNumber>)>, StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || {
+            store.record_bus_critical_delivery(&backend, &entity_types, block, delivered)
+        })
+        .await
+        .map_err(Error::from)?
+    }
+
+    async fn record_poi_divergence(
+        &self,
+        divergence: Option<PoiDivergence>,
+    ) -> Result<(), StoreError> {
+        let store = self.store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.record_poi_divergence(divergence.as_ref()))
+            .await
+            .map_err(Error::from)?
+    }
+
+    async fn public_proof_of_indexing(
+        &self,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        // Blocks that are still in the write queue have no PoI in the
+        // database yet
+        match self.store.block_ptr().await? {
+            Some(ptr) if ptr.number >= block.number => {
+                self.store.public_proof_of_indexing(&block).await
+            }
+            _ => Ok(None),
+        }
+    }
+
     async fn revert_block_operations(
         &self,
         block_ptr_to: BlockPtr,
@@ -1103,6 +1721,8 @@ impl WritableStoreTrait for WritableStore {
         deterministic_errors: Vec<SubgraphError>,
         manifest_idx_and_name: Vec<(u32, String)>,
         processed_data_sources: Vec<StoredDynamicDataSource>,
+        block_provider: Option<String>,
+        invariants: Vec<Invariant>,
     ) -> Result<(), StoreError> {
         self.writer
             .write(
@@ -1114,6 +1734,8 @@ impl WritableStoreTrait for WritableStore {
                 deterministic_errors,
                 manifest_idx_and_name,
                 processed_data_sources,
+                block_provider,
+                invariants,
             )
             .await?;
 
@@ -1123,8 +1745,20 @@ impl WritableStoreTrait for WritableStore {
         Ok(())
     }
 
-    fn deployment_synced(&self) -> Result<(), StoreError> {
-        self.store.deployment_synced()
+    fn deployment_synced(&self, block_ptr: BlockPtr) -> Result<bool, StoreError> {
+        self.store.deployment_synced(block_ptr)
+    }
+
+    fn synced_actions_pending(&self) -> Result<Option<PendingSyncedActions>, StoreError> {
+        self.store.synced_actions_pending()
+    }
+
+    fn synced_action_done(&self, key: String) -> Result<(), StoreError> {
+        self.store.synced_action_done(key)
+    }
+
+    fn synced_actions_done(&self) -> Result<(), StoreError> {
+        self.store.synced_actions_done()
     }
 
     async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
@@ -1161,4 +1795,13 @@ impl WritableStoreTrait for WritableStore {
     async fn flush(&self) -> Result<(), StoreError> {
         self.writer.flush().await
     }
+
+    async fn reload_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.writer.flush().await?;
+        let block_ptr = self.store.block_ptr().await?;
+        let block_cursor = self.store.block_cursor().await?;
+        *self.block_ptr.lock().unwrap() = block_ptr.clone();
+        *self.block_cursor.lock().unwrap() = block_cursor;
+        Ok(block_ptr)
+    }
 }
@@ -140,6 +140,9 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        retention: vec![],
+        invariants: vec![],
+        reorg_threshold: None,
         chain: PhantomData,
     };
 
@@ -306,6 +309,18 @@ fn find_entities(
     (entities, ids)
 }
 
+/// Check that the integrity check after copying found the same users in
+/// `deployment` as in the source at block 1
+fn check_copy_integrity(store: &DieselSubgraphStore, deployment: &DeploymentLocator) {
+    let integrity = store.status_for_id(deployment.id).copy_integrity;
+
+    assert_eq!(1, integrity.len());
+    assert_eq!(USER, integrity[0].entity_type);
+    assert_eq!(3, integrity[0].source_count);
+    assert_eq!(3, integrity[0].destination_count);
+    assert!(integrity[0].matches());
+}
+
 async fn check_graft(
     store: Arc<DieselSubgraphStore>,
     deployment: DeploymentLocator,
@@ -370,6 +385,7 @@ fn graft() {
         .await
         .expect("can create grafted subgraph");
 
+        check_copy_integrity(store.as_ref(), &deployment);
         check_graft(store.clone(), deployment).await.unwrap();
 
         // The test data has an update for the entity with id 3 at block 1.
@@ -437,6 +453,7 @@ fn copy() {
 
         store.activate(&deployment)?;
 
+        check_copy_integrity(store.as_ref(), &deployment);
         check_graft(store, deployment).await
     })
 }
@@ -525,6 +542,14 @@ fn prune() {
         // Pruning only removes the [1,2) version of user 3
         prune(&store, &src, 3).await.expect("pruning works");
 
+        // Queries for blocks before the earliest block now know that the
+        // history was pruned
+        let state = deployment_state(STORE.as_ref(), &src.hash).await;
+        assert_eq!(3, state.earliest_block_number);
+        assert!(state.history_pruned);
+        assert!(state.block_pruned(2));
+        assert!(!state.block_pruned(3));
+
         // Check which versions exist at every block, even if they are
         // before the new earliest block, since we don't have a convenient
         // way to load all entity versions with their block range
@@ -166,6 +166,9 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        retention: vec![],
+        invariants: vec![],
+        reorg_threshold: None,
         chain: PhantomData,
     };
 
@@ -1157,6 +1160,7 @@ fn mock_data_source() -> graph_chain_ethereum::DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            block_finalizer: None,
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1164,6 +1168,7 @@ fn mock_data_source() -> graph_chain_ethereum::DataSource {
         },
         context: Default::default(),
         creation_block: None,
+        creation_seq: None,
         contract_abi: Arc::new(mock_abi()),
     }
 }
@@ -1277,6 +1282,58 @@ fn revert_block_with_dynamic_data_source_operations() {
     })
 }
 
+#[test]
+fn dynamic_data_sources_keep_creation_seq() {
+    run_test(|store, writable, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+        let manifest_idx_and_name = vec![(0, "example data source".to_string())];
+
+        // Store data sources created in the same block in a different
+        // order than they were created in
+        let data_sources: Vec<_> = [2u32, 0, 1]
+            .into_iter()
+            .map(|seq| {
+                let mut data_source = mock_data_source();
+                data_source.address = Some(Address::from_low_u64_be(seq as u64));
+                data_source.creation_block = Some(TEST_BLOCK_3_PTR.number);
+                data_source.creation_seq = Some(seq);
+                data_source.as_stored_dynamic_data_source()
+            })
+            .collect();
+        transact_entities_and_dynamic_data_sources(
+            &subgraph_store,
+            deployment.clone(),
+            TEST_BLOCK_3_PTR.clone(),
+            data_sources,
+            vec![],
+            manifest_idx_and_name.clone(),
+        )
+        .await
+        .unwrap();
+
+        // The data sources are loaded in creation order with their
+        // creation sequence
+        let loaded_dds = writable
+            .load_dynamic_data_sources(manifest_idx_and_name)
+            .await
+            .unwrap();
+        let seqs: Vec<_> = loaded_dds.iter().map(|ds| ds.creation_seq).collect();
+        assert_eq!(vec![Some(0), Some(1), Some(2)], seqs);
+        let addresses: Vec<_> = loaded_dds
+            .iter()
+            .map(|ds| Address::from_slice(ds.param.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                Address::from_low_u64_be(0),
+                Address::from_low_u64_be(1),
+                Address::from_low_u64_be(2)
+            ],
+            addresses
+        );
+    })
+}
+
 #[test]
 fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
     run_test(|store, _, _| async move {
@@ -1293,6 +1350,9 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            retention: vec![],
+            invariants: vec![],
+            reorg_threshold: None,
             chain: PhantomData,
         };
 
@@ -1581,6 +1641,8 @@ fn handle_large_string_with_index() {
                 Vec::new(),
                 Vec::new(),
                 Vec::new(),
+                None,
+                Vec::new(),
             )
             .await
             .expect("Failed to insert large text");
@@ -1680,6 +1742,8 @@ fn handle_large_bytea_with_index() {
                 Vec::new(),
                 Vec::new(),
                 Vec::new(),
+                None,
+                Vec::new(),
             )
             .await
             .expect("Failed to insert large text");
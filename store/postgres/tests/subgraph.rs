@@ -1,12 +1,18 @@
 use graph::{
+    blockchain::block_stream::FirehoseCursor,
     components::{
         server::index_node::VersionInfo,
-        store::{DeploymentId, DeploymentLocator, StatusStore},
+        store::{
+            DeploymentId, DeploymentLocator, EntityKey, EntityType, PendingSyncedActions,
+            PruneReporter, ReadStore, StatusStore,
+        },
     },
     data::query::QueryTarget,
+    data::subgraph::invariant::Invariant,
     data::subgraph::schema::SubgraphHealth,
-    data::subgraph::schema::{DeploymentCreate, SubgraphError},
-    prelude::BlockPtr,
+    data::subgraph::schema::{DeploymentCreate, SubgraphError, SubgraphErrorCode},
+    data::subgraph::status::{HandlerStats, HandlerStatsReport},
+    entity,
     prelude::EntityChange,
     prelude::EntityChangeOperation,
     prelude::QueryStoreManager,
@@ -16,12 +22,18 @@ use graph::{
     prelude::SubgraphVersionSwitchingMode,
     prelude::UnfailOutcome,
     prelude::{futures03, StoreEvent},
-    prelude::{CheapClone, DeploymentHash, NodeId, SubgraphStore as _},
+    prelude::{BigDecimal, BlockPtr},
+    prelude::{CheapClone, DeploymentHash, NodeId, StoreError, SubgraphStore as _},
+    prelude::{Entity, EntityOperation},
     semver::Version,
 };
 use graph_store_postgres::layout_for_tests::Connection as Primary;
 use graph_store_postgres::SubgraphStore;
-use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashSet},
+    marker::PhantomData,
+    sync::Arc,
+};
 use test_store::*;
 
 const SUBGRAPH_GQL: &str = "
@@ -145,6 +157,9 @@ fn create_subgraph() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            retention: vec![],
+            invariants: vec![],
+            reorg_threshold: None,
             chain: PhantomData,
         };
         let deployment = DeploymentCreate::new(String::new(), &manifest, None);
@@ -176,10 +191,13 @@ fn create_subgraph() {
     }
 
     fn deployment_synced(store: &Arc<SubgraphStore>, deployment: &DeploymentLocator) {
-        futures03::executor::block_on(store.cheap_clone().writable(LOGGER.clone(), deployment.id))
-            .expect("can get writable")
-            .deployment_synced()
-            .unwrap();
+        let newly_synced = futures03::executor::block_on(
+            store.cheap_clone().writable(LOGGER.clone(), deployment.id),
+        )
+        .expect("can get writable")
+        .deployment_synced(GENESIS_PTR.clone())
+        .unwrap();
+        assert!(newly_synced);
     }
 
     // Test VersionSwitchingMode::Instant
@@ -412,6 +430,8 @@ fn status() {
             block_ptr: Some(GENESIS_PTR.clone()),
             handler: None,
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         store
@@ -435,6 +455,1530 @@ fn status() {
         let error = info.fatal_error.as_ref().unwrap();
         assert_eq!(MSG, error.message.as_str());
         assert!(error.deterministic);
+        assert_eq!(SubgraphErrorCode::HandlerError, error.code);
+    })
+}
+
+#[test]
+fn node_versions() {
+    const NAME: &str = "nodeVersionsSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status;
+
+        let node_versions = |store: &Store| {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .pop()
+                .unwrap()
+                .node_versions
+        };
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        assert!(node_versions(&store).is_empty());
+
+        // Recording the same hash twice only creates one entry
+        writable
+            .record_node_version("hash1".to_string())
+            .await
+            .unwrap();
+        writable
+            .record_node_version("hash1".to_string())
+            .await
+            .unwrap();
+        let versions = node_versions(&store);
+        assert_eq!(1, versions.len());
+        assert_eq!("hash1", versions[0].host_export_hash);
+        assert_eq!(None, versions[0].start_block);
+        assert_eq!(None, versions[0].end_block);
+
+        transact_and_wait(
+            &store.subgraph_store(),
+            &deployment,
+            BLOCK_ONE.clone(),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        // A different hash closes the previous entry at the current block
+        writable
+            .record_node_version("hash2".to_string())
+            .await
+            .unwrap();
+        let versions = node_versions(&store);
+        assert_eq!(2, versions.len());
+        assert_eq!("hash1", versions[0].host_export_hash);
+        assert_eq!(None, versions[0].start_block);
+        assert_eq!(Some(1), versions[0].end_block);
+        assert_eq!("hash2", versions[1].host_export_hash);
+        assert_eq!(Some(2), versions[1].start_block);
+        assert_eq!(None, versions[1].end_block);
+        assert_eq!(versions[0].git_commit_hash, versions[1].git_commit_hash);
+    })
+}
+
+#[test]
+fn usage() {
+    const NAME: &str = "usageSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::components::metrics::usage::{Usage, IPFS_BYTES, WASM_CPU_US};
+        use graph::prelude::chrono::{Duration, Utc};
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        let mut usage = Usage::default();
+        usage.add(IPFS_BYTES, 100);
+        usage.add(WASM_CPU_US, 7);
+        writable.record_usage(usage.clone()).await.unwrap();
+        // Recording again in the same hour adds to the bucket
+        writable.record_usage(usage).await.unwrap();
+
+        let now = Utc::now();
+        let records = store
+            .usage(
+                vec![NAME.to_string()],
+                now - Duration::hours(2),
+                now + Duration::hours(1),
+            )
+            .unwrap();
+        let records: Vec<_> = records
+            .into_iter()
+            .map(|record| (record.deployment, record.resource, record.amount))
+            .collect();
+        assert_eq!(
+            vec![
+                (NAME.to_string(), IPFS_BYTES.to_string(), 200),
+                (NAME.to_string(), WASM_CPU_US.to_string(), 14)
+            ],
+            records
+        );
+
+        // Nothing is reported outside of the interval
+        let records = store
+            .usage(vec![], now - Duration::days(3), now - Duration::days(2))
+            .unwrap();
+        assert!(records.is_empty());
+    })
+}
+
+#[test]
+fn handler_stats() {
+    const NAME: &str = "handlerStatsSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    fn stats(handler: &str, invocations: u64, ms: u64, gas: u64, errors: u64) -> HandlerStats {
+        HandlerStats {
+            handler: handler.to_string(),
+            invocations,
+            execution_time: std::time::Duration::from_millis(ms),
+            gas,
+            errors,
+        }
+    }
+
+    run_test_sequentially(|store| async move {
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        let report = writable.handler_stats().await.unwrap();
+        assert_eq!(HandlerStatsReport::default(), report);
+
+        writable
+            .record_handler_stats(vec![
+                stats("handleTransfer", 3, 30, 300, 0),
+                stats("handleApproval", 1, 5, 50, 1),
+            ])
+            .await
+            .unwrap();
+        // Recording again adds to the totals
+        let report = writable
+            .record_handler_stats(vec![stats("handleTransfer", 2, 20, 200, 1)])
+            .await
+            .unwrap();
+        assert!(report.since.is_some());
+        assert_eq!(
+            vec![
+                stats("handleApproval", 1, 5, 50, 1),
+                stats("handleTransfer", 5, 50, 500, 1),
+            ],
+            report.handlers
+        );
+        assert_eq!(
+            report,
+            store.subgraph_store().handler_stats(&deployment).unwrap()
+        );
+
+        store
+            .subgraph_store()
+            .reset_handler_stats(&deployment)
+            .unwrap();
+        let report = writable.handler_stats().await.unwrap();
+        assert_eq!(HandlerStatsReport::default(), report);
+
+        // Statistics that are written by deployment hash, e.g., when the
+        // node shuts down, are added like all others
+        store
+            .subgraph_store()
+            .record_handler_stats(&deployment.hash, &[stats("handleTransfer", 1, 10, 100, 0)])
+            .unwrap();
+        let report = writable.handler_stats().await.unwrap();
+        assert_eq!(
+            vec![stats("handleTransfer", 1, 10, 100, 0)],
+            report.handlers
+        );
+    })
+}
+
+#[test]
+fn compatibility_fingerprint() {
+    const NAME: &str = "compatibilitySubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::fingerprint::CompatibilityFingerprint;
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        let mut original = CompatibilityFingerprint::default();
+        original.set("gas_schedule", 1);
+        let mut changed = original.clone();
+        changed.set("gas_schedule", 2);
+
+        // The first fingerprint is recorded and matches afterwards
+        let check = |fingerprint: &CompatibilityFingerprint| {
+            let writable = writable.cheap_clone();
+            let fingerprint = fingerprint.clone();
+            async move { writable.check_compatibility(fingerprint).await.unwrap() }
+        };
+        assert_eq!(None, check(&original).await);
+        assert_eq!(None, check(&original).await);
+
+        // A different fingerprint is reported until it is acknowledged
+        assert_eq!(Some(original.clone()), check(&changed).await);
+        assert_eq!(Some(original.clone()), check(&changed).await);
+        let subgraph_store = store.subgraph_store();
+        assert_eq!(
+            Some((original.clone(), Some(changed.clone()))),
+            subgraph_store.compatibility(&deployment).unwrap()
+        );
+
+        assert!(subgraph_store
+            .acknowledge_compatibility(&deployment, Some("new gas costs"))
+            .unwrap());
+        assert!(!subgraph_store
+            .acknowledge_compatibility(&deployment, None)
+            .unwrap());
+        assert_eq!(None, check(&changed).await);
+        assert_eq!(Some(changed.clone()), check(&original).await);
+    })
+}
+
+#[test]
+fn deployment_synced_once() {
+    const NAME: &str = "syncedOnceSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        assert!(!writable.is_deployment_synced().await.unwrap());
+        assert_eq!(None, writable.synced_actions_pending().unwrap());
+        assert!(writable.deployment_synced(GENESIS_PTR.clone()).unwrap());
+        assert!(writable.is_deployment_synced().await.unwrap());
+
+        // The synced actions are pending for the block at which the
+        // deployment became synced, and remember which of them ran
+        let pending = |done: &[&str]| PendingSyncedActions {
+            block: GENESIS_PTR.clone(),
+            done: done.iter().map(|key| key.to_string()).collect(),
+        };
+        assert_eq!(
+            Some(pending(&[])),
+            writable.synced_actions_pending().unwrap()
+        );
+        writable
+            .synced_action_done("label:live".to_string())
+            .unwrap();
+        writable
+            .synced_action_done("label:live".to_string())
+            .unwrap();
+        assert_eq!(
+            Some(pending(&["label:live"])),
+            writable.synced_actions_pending().unwrap()
+        );
+
+        // A runner that starts again after the deployment became synced,
+        // e.g., after a restart, does not see the transition again
+        assert!(!writable.deployment_synced(GENESIS_PTR.clone()).unwrap());
+        subgraph_store.stop_subgraph(&deployment).await.unwrap();
+        let writable = subgraph_store
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        assert!(!writable.deployment_synced(GENESIS_PTR.clone()).unwrap());
+        assert_eq!(
+            Some(pending(&["label:live"])),
+            writable.synced_actions_pending().unwrap()
+        );
+
+        writable.synced_actions_done().unwrap();
+        assert_eq!(None, writable.synced_actions_pending().unwrap());
+    })
+}
+
+#[test]
+fn deployment_labels() {
+    const NAME: &str = "labeledSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        let deployment = setup().await;
+        let store = store.subgraph_store();
+
+        assert!(store.deployment_labels(&deployment).unwrap().is_empty());
+        assert!(store.add_deployment_label(&deployment, "live").unwrap());
+        assert!(store.add_deployment_label(&deployment, "blue").unwrap());
+        // Labeling again changes nothing, like the synced action does
+        assert!(!store.add_deployment_label(&deployment, "live").unwrap());
+        store.label_deployment(&deployment, "live").unwrap();
+        assert_eq!(
+            vec!["blue".to_string(), "live".to_string()],
+            store.deployment_labels(&deployment).unwrap()
+        );
+
+        assert!(store.remove_deployment_label(&deployment, "blue").unwrap());
+        assert!(!store.remove_deployment_label(&deployment, "blue").unwrap());
+        assert_eq!(
+            vec!["live".to_string()],
+            store.deployment_labels(&deployment).unwrap()
+        );
+    })
+}
+
+#[test]
+fn compile_profile() {
+    const NAME: &str = "compileProfileSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::runtime::CompileProfile;
+        use std::time::Duration;
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        let subgraph_store = store.subgraph_store();
+
+        // Without a profile, the deployment uses the default of the node
+        assert_eq!(None, writable.compile_profile().await.unwrap());
+        assert_eq!(
+            (None, None),
+            subgraph_store.compilation(&deployment).unwrap()
+        );
+
+        writable
+            .record_compilation(CompileProfile::FastCompile, Duration::from_millis(1500))
+            .await
+            .unwrap();
+        subgraph_store
+            .set_compile_profile(&deployment, Some(CompileProfile::Optimized))
+            .unwrap();
+        assert_eq!(
+            Some(CompileProfile::Optimized),
+            writable.compile_profile().await.unwrap()
+        );
+        assert_eq!(
+            (
+                Some(CompileProfile::Optimized),
+                Some((CompileProfile::FastCompile, Duration::from_millis(1500)))
+            ),
+            subgraph_store.compilation(&deployment).unwrap()
+        );
+
+        // Going back to the default keeps the recorded compilation
+        subgraph_store
+            .set_compile_profile(&deployment, None)
+            .unwrap();
+        assert_eq!(None, writable.compile_profile().await.unwrap());
+        assert_eq!(
+            (
+                None,
+                Some((CompileProfile::FastCompile, Duration::from_millis(1500)))
+            ),
+            subgraph_store.compilation(&deployment).unwrap()
+        );
+    })
+}
+
+#[test]
+fn entity_retention() {
+    const NAME: &str = "entityRetentionSubgraph";
+    const GQL: &str = "
+        type Transfer @entity(immutable: true) {
+            id: ID!,
+            timestamp: BigInt!,
+            note: String
+        }
+        type Swap @entity {
+            id: ID!
+        }
+    ";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::retention::{EntityRetention, RetentionRule};
+        use std::time::Duration;
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+        assert!(subgraph_store
+            .entity_retention(&deployment)
+            .unwrap()
+            .is_empty());
+
+        let transfer = EntityRetention {
+            entity_type: "Transfer".to_string(),
+            rule: RetentionRule::Age {
+                field: "timestamp".to_string(),
+                max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            },
+        };
+        let swap = EntityRetention {
+            entity_type: "Swap".to_string(),
+            rule: RetentionRule::Blocks(1000),
+        };
+        subgraph_store
+            .set_entity_retention(&deployment, &transfer)
+            .unwrap();
+        subgraph_store
+            .set_entity_retention(&deployment, &swap)
+            .unwrap();
+        assert_eq!(
+            vec![swap.clone(), transfer.clone()],
+            subgraph_store.entity_retention(&deployment).unwrap()
+        );
+        assert_eq!(
+            vec![deployment.clone()],
+            subgraph_store.retained_deployments().unwrap()
+        );
+
+        // The timestamp field must exist and be a number
+        let note = EntityRetention {
+            entity_type: "Transfer".to_string(),
+            rule: RetentionRule::Age {
+                field: "note".to_string(),
+                max_age: Duration::from_secs(60),
+            },
+        };
+        assert!(subgraph_store
+            .set_entity_retention(&deployment, &note)
+            .is_err());
+
+        // Setting a policy again replaces it
+        let swap = EntityRetention {
+            entity_type: "Swap".to_string(),
+            rule: RetentionRule::Blocks(10),
+        };
+        subgraph_store
+            .set_entity_retention(&deployment, &swap)
+            .unwrap();
+        assert!(subgraph_store
+            .remove_entity_retention(&deployment, "Transfer")
+            .unwrap());
+        assert!(!subgraph_store
+            .remove_entity_retention(&deployment, "Transfer")
+            .unwrap());
+        assert_eq!(
+            vec![swap],
+            subgraph_store.entity_retention(&deployment).unwrap()
+        );
+    })
+}
+
+#[test]
+fn apply_entity_retention() {
+    const NAME: &str = "applyEntityRetentionSubgraph";
+    const GQL: &str = "
+        type Transfer @entity(immutable: true) {
+            id: ID!,
+            timestamp: BigInt!
+        }
+        type Swap @entity {
+            id: ID!,
+            amount: Int!
+        }
+    ";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, GQL).await
+    }
+
+    fn set(entity_type: &str, id: &str, data: Entity) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data(entity_type.to_string(), id.to_string()),
+            data,
+        }
+    }
+
+    fn transfer(id: &str, timestamp: i32) -> EntityOperation {
+        let timestamp = graph::prelude::BigInt::from(timestamp);
+        set("Transfer", id, entity! { id: id, timestamp: timestamp })
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::retention::{EntityRetention, RetentionRule};
+        use std::time::{Duration, Instant};
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let ops = vec![
+            transfer("t0", 100),
+            set("Swap", "s1", entity! { id: "s1", amount: 1 }),
+            set("Swap", "s2", entity! { id: "s2", amount: 1 }),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[0].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![
+            transfer("t1", 200),
+            set("Swap", "s1", entity! { id: "s1", amount: 2 }),
+            EntityOperation::Remove {
+                key: EntityKey::data("Swap".to_string(), "s2".to_string()),
+            },
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+        for (block, timestamp) in [(2, 300), (3, 400)] {
+            let ops = vec![transfer(&format!("t{}", block), timestamp)];
+            transact_and_wait(&subgraph_store, &deployment, BLOCKS[block].clone(), ops)
+                .await
+                .unwrap();
+        }
+
+        let transfer = EntityRetention {
+            entity_type: "Transfer".to_string(),
+            rule: RetentionRule::Age {
+                field: "timestamp".to_string(),
+                max_age: Duration::from_secs(150),
+            },
+        };
+        let swap = EntityRetention {
+            entity_type: "Swap".to_string(),
+            rule: RetentionRule::Blocks(1),
+        };
+        subgraph_store
+            .set_entity_retention(&deployment, &transfer)
+            .unwrap();
+        subgraph_store
+            .set_entity_retention(&deployment, &swap)
+            .unwrap();
+
+        let apply = |reorg_threshold, dry_run| {
+            let subgraph_store = subgraph_store.cheap_clone();
+            let deployment = deployment.clone();
+            async move {
+                let deadline = Instant::now() + Duration::from_secs(60);
+                subgraph_store
+                    .apply_retention(&LOGGER, &deployment, reorg_threshold, deadline, dry_run)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|outcome| (outcome.retention.entity_type, outcome.rows))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Policies only take effect once the deployment starts with them
+        assert_eq!(Vec::<(String, usize)>::new(), apply(0, true).await);
+        let writable = subgraph_store
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![transfer.clone(), swap.clone()],
+            writable.start_entity_retention().await.unwrap()
+        );
+
+        // With only blocks 0 and 1 final, the newest final timestamp is 200
+        // and no transfer is old enough, no matter what the wall clock says;
+        // the two versions of swaps that were replaced or deleted at block
+        // 1 are old enough
+        assert_eq!(
+            vec![("Swap".to_string(), 2), ("Transfer".to_string(), 0)],
+            apply(2, true).await
+        );
+
+        // With all blocks final, transfers older than 400 - 150 are removed
+        assert_eq!(
+            vec![("Swap".to_string(), 2), ("Transfer".to_string(), 2)],
+            apply(0, false).await
+        );
+        assert_eq!(
+            vec![("Swap".to_string(), 0), ("Transfer".to_string(), 0)],
+            apply(0, true).await
+        );
+
+        // Current versions of mutable entities are kept
+        let s1 = writable
+            .get(&EntityKey::data("Swap".to_string(), "s1".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(&graph::prelude::Value::Int(2)), s1.get("amount"));
+        for (id, exists) in [("t0", false), ("t1", false), ("t2", true), ("t3", true)] {
+            let key = EntityKey::data("Transfer".to_string(), id.to_string());
+            assert_eq!(exists, writable.get(&key).unwrap().is_some(), "{}", id);
+        }
+    })
+}
+
+#[test]
+fn handler_entity_types() {
+    const NAME: &str = "handlerEntityTypesSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::components::subgraph::handler_entity_types::HandlerWrite;
+        use graph::data::subgraph::status;
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        writable
+            .record_handler_entity_types(vec![
+                HandlerWrite::new("Token", "handleTransfer", "User"),
+                HandlerWrite::new("Token", "handleApproval", "User"),
+            ])
+            .await
+            .unwrap();
+        // Recording a write again is fine
+        writable
+            .record_handler_entity_types(vec![
+                HandlerWrite::new("Token", "handleTransfer", "User"),
+                HandlerWrite::new("Token", "handleTransfer", "Account"),
+                HandlerWrite::new("Pool", "handleTransfer", "Pool"),
+            ])
+            .await
+            .unwrap();
+
+        let infos = store
+            .status(status::Filter::Deployments(vec![NAME.to_string()]))
+            .unwrap();
+        assert_eq!(1, infos.len());
+        let writes: Vec<_> = infos[0]
+            .handler_entity_types
+            .iter()
+            .map(|writes| {
+                (
+                    writes.data_source.as_str(),
+                    writes.handler.as_str(),
+                    writes.entity_types.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("Pool", "handleTransfer", vec!["Pool".to_string()]),
+                ("Token", "handleApproval", vec!["User".to_string()]),
+                (
+                    "Token",
+                    "handleTransfer",
+                    vec!["Account".to_string(), "User".to_string()]
+                ),
+            ],
+            writes
+        );
+    })
+}
+
+#[test]
+fn entity_type_stats() {
+    const NAME: &str = "entityTypeStatsSubgraph";
+    const GQL: &str = "
+        type Transfer @entity(immutable: true) {
+            id: ID!,
+            amount: Int!
+        }
+        type User @entity {
+            id: ID!,
+            name: String
+        }
+    ";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, GQL).await
+    }
+
+    fn set(entity_type: &str, id: &str, data: Entity) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data(entity_type.to_string(), id.to_string()),
+            data,
+        }
+    }
+
+    fn remove(entity_type: &str, id: &str) -> EntityOperation {
+        EntityOperation::Remove {
+            key: EntityKey::data(entity_type.to_string(), id.to_string()),
+        }
+    }
+
+    run_test_sequentially(|store| async move {
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let ops = vec![
+            set("User", "1", entity! { id: "1", name: "Johnton" }),
+            set("User", "2", entity! { id: "2", name: "Cindini" }),
+            set("Transfer", "t1", entity! { id: "t1", amount: 1 }),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![set("User", "1", entity! { id: "1", name: "Johnny" })];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[2].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![remove("User", "2")];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[3].clone(), ops)
+            .await
+            .unwrap();
+
+        let stats = |exact: bool| {
+            subgraph_store
+                .entity_type_stats(&deployment.hash, exact)
+                .unwrap()
+                .into_iter()
+                .map(|stats| {
+                    assert_eq!(exact, stats.exact);
+                    (stats.entity_type, stats.rows, stats.last_modified_block)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                ("Transfer".to_string(), 1, Some(1)),
+                ("User".to_string(), 3, Some(3)),
+            ],
+            stats(true)
+        );
+
+        // After a revert, the last modification of `User` is only taken
+        // from its versions when the tables are scanned anyway
+        revert_block(&store, &deployment, &BLOCKS[2]).await;
+        let last_modified = |exact: bool| {
+            stats(exact)
+                .into_iter()
+                .map(|(entity_type, _, block)| (entity_type, block))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![
+                ("Transfer".to_string(), Some(1)),
+                ("User".to_string(), None),
+            ],
+            last_modified(false)
+        );
+        assert_eq!(
+            vec![
+                ("Transfer".to_string(), Some(1)),
+                ("User".to_string(), Some(2)),
+            ],
+            last_modified(true)
+        );
+
+        assert!(subgraph_store
+            .entity_type_stats(&DeploymentHash::new("QmNotThere").unwrap(), false)
+            .is_err());
+    })
+}
+
+#[test]
+fn invariant_violations() {
+    const NAME: &str = "invariantViolationsSubgraph";
+    const GQL: &str = "
+        type Account @entity {
+            id: ID!,
+            balance: Int!
+        }
+        type Pool @entity(immutable: true) {
+            id: ID!,
+            fee: BigDecimal!
+        }
+    ";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, GQL).await
+    }
+
+    fn account(id: &str, balance: i32) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data("Account".to_string(), id.to_string()),
+            data: entity! { id: id, balance: balance },
+        }
+    }
+
+    fn pool(id: &str, fee: i32) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data("Pool".to_string(), id.to_string()),
+            data: entity! { id: id, fee: BigDecimal::from(fee) },
+        }
+    }
+
+    fn invariant(name: &str, check: &str, strict: bool) -> Invariant {
+        graph::prelude::serde_yaml::from_str(&format!(
+            "name: {}\ncheck: {}\nstrict: {}",
+            name, check, strict
+        ))
+        .unwrap()
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status;
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+        let invariants = vec![
+            invariant("total", "sum(Account.balance) == 8", false),
+            invariant("few", "count(Account) < 2", false),
+            invariant("fees", "max(Pool.fee) > 0", false),
+        ];
+
+        let status = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+        };
+        let violations = || {
+            status()
+                .invariant_violations
+                .into_iter()
+                .map(|v| (v.invariant, v.first_block, v.last_block))
+                .collect::<Vec<_>>()
+        };
+        let transact = |block: usize, ops: Vec<EntityOperation>, invariants: &Vec<Invariant>| {
+            transact_invariants_and_wait(
+                &subgraph_store,
+                &deployment,
+                BLOCKS[block].clone(),
+                ops,
+                invariants.clone(),
+            )
+        };
+
+        // There are no pools, and therefore no maximum fee, so `fees` is
+        // not checked
+        let ops = vec![account("a1", 5), account("a2", 3)];
+        transact(1, ops, &invariants).await.unwrap();
+        assert_eq!(vec![("few".to_string(), 1, 1)], violations());
+
+        let ops = vec![account("a3", 1), pool("p1", 0)];
+        transact(2, ops, &invariants).await.unwrap();
+        assert_eq!(
+            vec![
+                ("fees".to_string(), 2, 2),
+                ("few".to_string(), 1, 2),
+                ("total".to_string(), 2, 2)
+            ],
+            violations()
+        );
+
+        // Reverting forgets violations that started after the block we
+        // revert to, and the changes that block made to the aggregates
+        revert_block(&store, &deployment, &BLOCKS[1]).await;
+        assert_eq!(vec![("few".to_string(), 1, 1)], violations());
+
+        // An invariant that holds again is not reported anymore
+        let ops = vec![
+            EntityOperation::Remove {
+                key: EntityKey::data("Account".to_string(), "a2".to_string()),
+            },
+            pool("p2", 2),
+        ];
+        transact(2, ops, &invariants).await.unwrap();
+        assert_eq!(vec![("total".to_string(), 2, 2)], violations());
+
+        // A block that violates a strict invariant is not written, and the
+        // deployment fails at that block
+        let mut strict = invariants.clone();
+        strict[1] = invariant("few", "count(Account) < 2", true);
+        let ops = vec![account("a4", 2)];
+        let err = transact(3, ops, &strict).await.unwrap_err();
+        assert!(matches!(err, StoreError::InvariantViolated(_)));
+        let info = status();
+        assert_eq!(SubgraphHealth::Failed, info.health);
+        let error = info.fatal_error.expect("the deployment failed");
+        assert!(error.deterministic);
+        assert_eq!(Some(BLOCKS[3].clone()), error.block_ptr);
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        assert_eq!(Some(BLOCKS[3].clone()), writable.block_ptr());
+        let key = EntityKey::data("Account".to_string(), "a4".to_string());
+        assert_eq!(None, writable.get(&key).unwrap());
+
+        // Once the error is removed, a block that keeps it intact is
+        // written
+        let outcome = writable
+            .unfail_deterministic_error(&BLOCKS[3], &BLOCKS[2])
+            .await
+            .unwrap();
+        assert_eq!(UnfailOutcome::Unfailed, outcome);
+        let ops = vec![account("a1", 3)];
+        transact(3, ops, &strict).await.unwrap();
+        assert_eq!(vec![("total".to_string(), 2, 3)], violations());
+    })
+}
+
+#[test]
+fn remove_deployment_by_table() {
+    const NAME: &str = "removeByTableSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    fn set(id: &str, data: Entity) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data("User".to_string(), id.to_string()),
+            data,
+        }
+    }
+
+    run_test_sequentially(|store| async move {
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let ops = vec![
+            set("1", entity! { id: "1", name: "Johnton" }),
+            set("2", entity! { id: "2", name: "Cindini" }),
+            set("3", entity! { id: "3", name: "Shaqueeena" }),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+
+        // A deployment that is still in use can not be removed
+        assert!(!subgraph_store.start_removal(deployment.id.into()).unwrap());
+        assert!(subgraph_store.removals().unwrap().is_empty());
+
+        subgraph_store
+            .remove_subgraph(SubgraphName::new(NAME).unwrap())
+            .unwrap();
+        let unused = subgraph_store.record_unused_deployments().unwrap();
+        assert_eq!(1, unused.len());
+        let id = unused[0].id;
+
+        assert!(subgraph_store.start_removal(id).unwrap());
+        // Starting a removal twice is harmless
+        assert!(subgraph_store.start_removal(id).unwrap());
+
+        // The deployment can not be queried or deployed again while it is
+        // being removed
+        assert!(store
+            .query_store(
+                QueryTarget::Deployment(deployment.hash.clone(), Default::default()),
+                false,
+            )
+            .await
+            .is_err());
+        let err = create_subgraph(&deployment.hash, SUBGRAPH_GQL, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StoreError::DeploymentBeingRemoved(_)));
+
+        let removals = subgraph_store.removals().unwrap();
+        assert_eq!(1, removals.len());
+        assert_eq!(NAME, removals[0].deployment);
+
+        // Each step drops one table, and the progress is recorded
+        let step = subgraph_store.continue_removal(id).unwrap();
+        assert_eq!(1, step.tables_dropped);
+        assert!(!step.done);
+        assert_eq!(1, subgraph_store.removals().unwrap()[0].tables_dropped);
+
+        // At least the `User` table and the PoI table are dropped before
+        // the removal is done
+        let mut tables = step.tables_dropped;
+        loop {
+            let step = subgraph_store.continue_removal(id).unwrap();
+            if step.done {
+                assert_eq!(0, step.tables_dropped);
+                break;
+            }
+            assert_eq!(1, step.tables_dropped);
+            tables += step.tables_dropped;
+        }
+        assert!(tables >= 2);
+
+        assert!(subgraph_store.removals().unwrap().is_empty());
+        assert!(subgraph_store.continue_removal(id).is_err());
+
+        // Once the removal has finished, the deployment can be deployed
+        // again
+        create_subgraph(&deployment.hash, SUBGRAPH_GQL, None)
+            .await
+            .unwrap();
+    })
+}
+
+#[test]
+fn reorg_threshold_override() {
+    const NAME: &str = "reorgThresholdSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    fn set_user(name: &str) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data("User".to_string(), "1".to_string()),
+            data: entity! { id: "1", name: name },
+        }
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status;
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let status_threshold = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+                .reorg_threshold
+        };
+
+        assert_eq!(None, subgraph_store.reorg_threshold(&deployment).unwrap());
+        subgraph_store
+            .set_reorg_threshold(&deployment, Some(1))
+            .unwrap();
+        assert_eq!(
+            Some(1),
+            subgraph_store.reorg_threshold(&deployment).unwrap()
+        );
+        assert_eq!(Some(1), status_threshold());
+
+        for (block, name) in [(1, "Johnton"), (2, "Johnny"), (3, "John")] {
+            transact_and_wait(
+                &subgraph_store,
+                &deployment,
+                BLOCKS[block].clone(),
+                vec![set_user(name)],
+            )
+            .await
+            .unwrap();
+        }
+
+        // A reorg within the threshold of the deployment keeps it
+        revert_block(&store, &deployment, &BLOCKS[2]).await;
+        assert_eq!(
+            Some(1),
+            subgraph_store.reorg_threshold(&deployment).unwrap()
+        );
+
+        // A reorg that is deeper than the threshold of the deployment, but
+        // within the one of the chain, reverts the data correctly and makes
+        // the deployment use the threshold of the chain
+        revert_block(&store, &deployment, &BLOCKS[1]).await;
+        assert_eq!(None, subgraph_store.reorg_threshold(&deployment).unwrap());
+        assert_eq!(None, status_threshold());
+
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        let user = writable
+            .get(&EntityKey::data("User".to_string(), "1".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Some("Johnton"),
+            user.get("name").and_then(|name| name.as_str())
+        );
+        assert_eq!(BLOCKS[1], latest_block(&store, deployment.id).await);
+
+        // Pruning keeps only the history that the lowered threshold needs.
+        // With the threshold of the chain, block 3 would not be far enough
+        // ahead of block 1 to prune anything
+        subgraph_store
+            .set_reorg_threshold(&deployment, Some(1))
+            .unwrap();
+        for (block, name) in [(2, "Johnny"), (3, "John")] {
+            transact_and_wait(
+                &subgraph_store,
+                &deployment,
+                BLOCKS[block].clone(),
+                vec![set_user(name)],
+            )
+            .await
+            .unwrap();
+        }
+        struct Progress;
+        impl PruneReporter for Progress {}
+        subgraph_store
+            .prune(Box::new(Progress), &deployment, 1, 3, 1.1)
+            .await
+            .unwrap();
+
+        // A reorg past the pruned history can not be reverted, and does
+        // not change the threshold
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        let res = match writable
+            .revert_block_operations(BLOCKS[0].clone(), FirehoseCursor::None)
+            .await
+        {
+            Ok(()) => writable.flush().await,
+            Err(e) => Err(e),
+        };
+        let err = res.unwrap_err();
+        assert!(err.to_string().contains("has been pruned"), "{}", err);
+        assert_eq!(BLOCKS[3], latest_block(&store, deployment.id).await);
+        assert_eq!(
+            Some(1),
+            subgraph_store.reorg_threshold(&deployment).unwrap()
+        );
+    })
+}
+
+#[test]
+fn block_providers() {
+    const NAME: &str = "blockProvidersSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status::{self, BlockProvider};
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+
+        let status = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+        };
+
+        assert_eq!(None, status().latest_block_provider);
+
+        for (block, provider) in [(1, "mainnet-0"), (2, "mainnet-1")] {
+            transact_block_from_provider(&store, &deployment, BLOCKS[block].clone(), provider)
+                .await
+                .unwrap();
+        }
+
+        let provider = |block_number, provider: &str| BlockProvider {
+            block_number,
+            provider: provider.to_string(),
+        };
+        assert_eq!(
+            vec![provider(1, "mainnet-0"), provider(2, "mainnet-1")],
+            subgraph_store
+                .block_providers(&deployment.hash, 0, 10)
+                .unwrap()
+        );
+        assert_eq!(
+            vec![provider(1, "mainnet-0")],
+            subgraph_store
+                .block_providers(&deployment.hash, 1, 1)
+                .unwrap()
+        );
+        assert_eq!(
+            Some("mainnet-1".to_string()),
+            status().latest_block_provider
+        );
+
+        // Reverting a block forgets its provider
+        revert_block(&store, &deployment, &BLOCKS[1]).await;
+        assert_eq!(
+            vec![provider(1, "mainnet-0")],
+            subgraph_store
+                .block_providers(&deployment.hash, 0, 10)
+                .unwrap()
+        );
+        assert_eq!(
+            Some("mainnet-0".to_string()),
+            status().latest_block_provider
+        );
+
+        // Non-deterministic errors keep the provider that served the block
+        let error = SubgraphError {
+            subgraph_id: deployment.hash.clone(),
+            message: "bad receipt".to_string(),
+            block_ptr: Some(BLOCKS[3].clone()),
+            handler: None,
+            deterministic: false,
+            code: SubgraphErrorCode::ProviderError,
+            provider: Some("mainnet-0".to_string()),
+        };
+        subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable")
+            .fail_subgraph(error)
+            .await
+            .unwrap();
+        let error = status().fatal_error.unwrap();
+        assert_eq!(Some("mainnet-0".to_string()), error.provider);
+    })
+}
+
+#[test]
+fn query_rates() {
+    run_test_sequentially(|store| async move {
+        use std::time::Duration;
+
+        let subgraph_store = store.subgraph_store();
+        let (node1, node2) = (
+            NodeId::new("query_1").unwrap(),
+            NodeId::new("query_2").unwrap(),
+        );
+        let max_age = Duration::from_secs(60);
+
+        subgraph_store
+            .record_query_rates(
+                &node1,
+                &[("QmA".to_string(), 1.0), ("QmB".to_string(), 2.0)],
+            )
+            .unwrap();
+        subgraph_store
+            .record_query_rates(&node2, &[("QmA".to_string(), 0.5)])
+            .unwrap();
+        let rates = subgraph_store.query_rates(max_age).unwrap();
+        assert_eq!(Some(&1.5), rates.get("QmA"));
+        assert_eq!(Some(&2.0), rates.get("QmB"));
+
+        // A node's report replaces its previous one
+        subgraph_store.record_query_rates(&node1, &[]).unwrap();
+        let rates = subgraph_store.query_rates(max_age).unwrap();
+        assert_eq!(Some(&0.5), rates.get("QmA"));
+        assert_eq!(None, rates.get("QmB"));
+
+        subgraph_store.record_query_rates(&node2, &[]).unwrap();
+    })
+}
+
+#[test]
+fn bus_backends() {
+    const NAME: &str = "busBackendsSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status;
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        let backends = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+                .bus_status
+                .map(|bus| bus.backends)
+        };
+
+        // Nothing is known until the deployment is started
+        assert_eq!(None, backends());
+
+        writable
+            .record_bus_backends(vec!["rabbit".to_string(), "pubsub".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(vec!["rabbit".to_string(), "pubsub".to_string()]),
+            backends()
+        );
+
+        // The most recent backends replace the ones from before
+        writable.record_bus_backends(vec![]).await.unwrap();
+        assert_eq!(Some(vec![]), backends());
+    })
+}
+
+#[test]
+fn bus_critical_delivery() {
+    const NAME: &str = "busCriticalDeliverySubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status;
+
+        let deployment = setup().await;
+        let subgraph_store = store.subgraph_store();
+        let writable = subgraph_store
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+        writable
+            .record_bus_backends(vec!["pubsub".to_string()])
+            .await
+            .unwrap();
+
+        let user: BTreeSet<_> = vec![EntityType::from("User")].into_iter().collect();
+        let unknown: BTreeSet<_> = vec![EntityType::from("Unknown")].into_iter().collect();
+        assert!(subgraph_store
+            .set_bus_critical_entity_types(&deployment, &unknown)
+            .is_err());
+        subgraph_store
+            .set_bus_critical_entity_types(&deployment, &user)
+            .unwrap();
+        assert_eq!(user, writable.bus_critical_entity_types().await.unwrap());
+
+        let record = |block, delivered| {
+            writable.record_bus_critical_delivery(
+                "pubsub".to_string(),
+                user.clone(),
+                block,
+                delivered,
+            )
+        };
+        let critical = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+                .bus_status
+                .unwrap()
+                .critical
+        };
+        let delivery = |confirmed, dead_lettered| {
+            vec![status::CriticalTypeDelivery {
+                entity_type: "User".to_string(),
+                backend: "pubsub".to_string(),
+                last_confirmed_block: confirmed,
+                dead_lettered_block: dead_lettered,
+            }]
+        };
+
+        // Nothing was delivered yet
+        assert_eq!(delivery(None, None), critical());
+
+        record(1, true).await.unwrap();
+        record(2, true).await.unwrap();
+        assert_eq!(delivery(Some(2), None), critical());
+
+        // The confirmed block stops at a dead letter
+        record(3, false).await.unwrap();
+        let confirmed = record(4, true).await.unwrap();
+        assert_eq!(vec![(EntityType::from("User"), Some(2))], confirmed);
+        record(5, false).await.unwrap();
+        assert_eq!(delivery(Some(2), Some(3)), critical());
+
+        // and advances again once the dead letters are acknowledged
+        assert_eq!(
+            1,
+            subgraph_store
+                .acknowledge_bus_dead_letters(&deployment, Some("pubsub"))
+                .unwrap()
+        );
+        record(6, true).await.unwrap();
+        assert_eq!(delivery(Some(6), None), critical());
+
+        // Types that are no longer critical are forgotten
+        subgraph_store
+            .set_bus_critical_entity_types(&deployment, &BTreeSet::new())
+            .unwrap();
+        assert_eq!(Vec::<status::CriticalTypeDelivery>::new(), critical());
+    })
+}
+
+#[test]
+fn poi_divergence() {
+    const NAME: &str = "poiDivergenceSubgraph";
+
+    async fn setup() -> DeploymentLocator {
+        let id = DeploymentHash::new(NAME).unwrap();
+        remove_subgraphs();
+        block_store::set_chain(vec![], NETWORK_NAME);
+        create_test_subgraph(&id, SUBGRAPH_GQL).await
+    }
+
+    run_test_sequentially(|store| async move {
+        use graph::data::subgraph::status::{self, PoiDivergence};
+
+        let deployment = setup().await;
+        let writable = store
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .expect("can get writable");
+
+        let divergence = || {
+            store
+                .status(status::Filter::Deployments(vec![NAME.to_string()]))
+                .unwrap()
+                .remove(0)
+                .poi_divergence
+        };
+        let diverged = |block| PoiDivergence {
+            block,
+            local_poi: vec![block as u8; 32],
+            reference_poi: vec![0xff; 32],
+        };
+
+        assert_eq!(None, divergence());
+
+        writable
+            .record_poi_divergence(Some(diverged(10)))
+            .await
+            .unwrap();
+        assert_eq!(Some(diverged(10)), divergence());
+
+        // The earliest divergence is kept
+        writable
+            .record_poi_divergence(Some(diverged(20)))
+            .await
+            .unwrap();
+        assert_eq!(Some(diverged(10)), divergence());
+        writable
+            .record_poi_divergence(Some(diverged(5)))
+            .await
+            .unwrap();
+        assert_eq!(Some(diverged(5)), divergence());
+
+        writable.record_poi_divergence(None).await.unwrap();
+        assert_eq!(None, divergence());
     })
 }
 
@@ -498,6 +2042,8 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         assert!(count() == 0);
@@ -513,6 +2059,8 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         // Inserting the same error is allowed but ignored.
@@ -527,6 +2075,8 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         transact_errors(&store, &deployment, BLOCKS[3].clone(), vec![error2])
@@ -572,6 +2122,8 @@ fn fatal_vs_non_fatal() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         store
@@ -666,6 +2218,8 @@ fn fail_unfail_deterministic_error() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         let writable = store
@@ -786,6 +2340,8 @@ fn fail_unfail_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: false, // wrong determinism
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         // Fail the subraph with a NON-deterministic error.
@@ -819,6 +2375,8 @@ fn fail_unfail_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[2].clone()), // wrong block
             handler: None,
             deterministic: true, // right determinism
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         // Fail the subgraph with an advanced block.
@@ -885,6 +2443,8 @@ fn fail_unfail_non_deterministic_error() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: false,
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         let writable = store
@@ -1010,6 +2570,8 @@ fn fail_unfail_non_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true, // wrong determinism
+            code: SubgraphErrorCode::HandlerError,
+            provider: None,
         };
 
         // Fail the subgraph with a DETERMININISTIC error.
@@ -1039,6 +2601,8 @@ fn fail_unfail_non_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[2].clone()), // wrong block
             handler: None,
             deterministic: false, // right determinism
+            code: SubgraphErrorCode::Unknown,
+            provider: None,
         };
 
         // Fail the subgraph with a non-deterministic error, but with an advanced block.
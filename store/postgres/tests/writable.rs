@@ -43,6 +43,9 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        retention: vec![],
+        invariants: vec![],
+        reorg_threshold: None,
         chain: PhantomData,
     };
 
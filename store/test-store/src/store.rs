@@ -2,6 +2,7 @@ use diesel::{self, PgConnection};
 use graph::data::graphql::effort::LoadManager;
 use graph::data::query::QueryResults;
 use graph::data::query::QueryTarget;
+use graph::data::subgraph::invariant::Invariant;
 use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError};
 use graph::data_source::CausalityRegion;
 use graph::log;
@@ -165,6 +166,9 @@ pub async fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        retention: vec![],
+        invariants: vec![],
+        reorg_threshold: None,
         chain: PhantomData,
     };
 
@@ -238,6 +242,43 @@ pub async fn transact_errors(
             errs,
             Vec::new(),
             Vec::new(),
+            None,
+            Vec::new(),
+        )
+        .await?;
+    flush(deployment).await
+}
+
+/// Transact an empty block that the provider with label `provider` served
+/// and wait until changes have been written
+pub async fn transact_block_from_provider(
+    store: &Arc<Store>,
+    deployment: &DeploymentLocator,
+    block_ptr_to: BlockPtr,
+    provider: &str,
+) -> Result<(), StoreError> {
+    let metrics_registry = Arc::new(MockMetricsRegistry::new());
+    let stopwatch_metrics = StopwatchMetrics::new(
+        Logger::root(slog::Discard, o!()),
+        deployment,
+        "transact",
+        metrics_registry.clone(),
+    );
+    store
+        .subgraph_store()
+        .writable(LOGGER.clone(), deployment.id)
+        .await?
+        .transact_block_operations(
+            block_ptr_to,
+            FirehoseCursor::None,
+            Vec::new(),
+            &stopwatch_metrics,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Some(provider.to_string()),
+            Vec::new(),
         )
         .await?;
     flush(deployment).await
@@ -287,6 +328,49 @@ pub async fn transact_entities_and_dynamic_data_sources(
     data_sources: Vec<StoredDynamicDataSource>,
     ops: Vec<EntityOperation>,
     manifest_idx_and_name: Vec<(u32, String)>,
+) -> Result<(), StoreError> {
+    transact_with_invariants(
+        store,
+        deployment,
+        block_ptr_to,
+        data_sources,
+        ops,
+        manifest_idx_and_name,
+        vec![],
+    )
+    .await
+}
+
+/// Like `transact_and_wait`, but have the store check `invariants` with
+/// the changes written
+pub async fn transact_invariants_and_wait(
+    store: &Arc<DieselSubgraphStore>,
+    deployment: &DeploymentLocator,
+    block_ptr_to: BlockPtr,
+    ops: Vec<EntityOperation>,
+    invariants: Vec<Invariant>,
+) -> Result<(), StoreError> {
+    transact_with_invariants(
+        store,
+        deployment.clone(),
+        block_ptr_to,
+        vec![],
+        ops,
+        vec![],
+        invariants,
+    )
+    .await?;
+    flush(deployment).await
+}
+
+async fn transact_with_invariants(
+    store: &Arc<DieselSubgraphStore>,
+    deployment: DeploymentLocator,
+    block_ptr_to: BlockPtr,
+    data_sources: Vec<StoredDynamicDataSource>,
+    ops: Vec<EntityOperation>,
+    manifest_idx_and_name: Vec<(u32, String)>,
+    invariants: Vec<Invariant>,
 ) -> Result<(), StoreError> {
     let store =
         futures03::executor::block_on(store.cheap_clone().writable(LOGGER.clone(), deployment.id))?;
@@ -313,6 +397,8 @@ pub async fn transact_entities_and_dynamic_data_sources(
             Vec::new(),
             manifest_idx_and_name,
             Vec::new(),
+            None,
+            invariants,
         )
         .await
 }
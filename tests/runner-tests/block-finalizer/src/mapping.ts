@@ -0,0 +1,70 @@
+import {
+  ethereum,
+  dataSource,
+  Address,
+  BigInt,
+  Bytes,
+} from "@graphprotocol/graph-ts";
+import { Template } from "../generated/templates";
+import { HandlerLog, FinalizerRun, IpfsFile } from "../generated/schema";
+
+// CID of `block-finalizer/abis/Contract.abi` after being processed by graph-cli.
+const KNOWN_CID = "QmQ2REmceVtzawp7yrnxLQXgNNCtFHEnig6fL9aqE1kcWq";
+
+function logHandler(block: ethereum.Block, handler: string): void {
+  let id = block.number.toString();
+  let log = HandlerLog.load(id);
+  if (log == null) {
+    log = new HandlerLog(id);
+    log.handlers = [];
+  }
+  let handlers = log.handlers;
+  handlers.push(handler);
+  log.handlers = handlers;
+  log.save();
+}
+
+function finalize(block: ethereum.Block, name: string): void {
+  let id = name + "-" + block.number.toString();
+  let run = FinalizerRun.load(id);
+  if (run == null) {
+    run = new FinalizerRun(id);
+    run.runs = 0;
+  }
+  // The finalizer sees what the other handlers wrote in the block
+  let log = HandlerLog.load(block.number.toString());
+  run.runs += 1;
+  run.handlers = log == null ? [] : log.handlers;
+  run.save();
+
+  logHandler(block, name + ".handleFinalize");
+}
+
+export function handleBlock(block: ethereum.Block): void {
+  logHandler(block, "Contract.handleBlock");
+
+  if (block.number == BigInt.fromI32(1)) {
+    Template.create(
+      Address.fromString("0x0000000000000000000000000000000000000001")
+    );
+    dataSource.create("File", [KNOWN_CID]);
+  }
+}
+
+export function handleBlockTemplate(block: ethereum.Block): void {
+  logHandler(block, "Template.handleBlock");
+}
+
+export function handleFinalize(block: ethereum.Block): void {
+  finalize(block, "Contract");
+}
+
+export function handleFinalizeTemplate(block: ethereum.Block): void {
+  finalize(block, "Template");
+}
+
+export function handleFile(data: Bytes): void {
+  let entity = new IpfsFile(dataSource.stringParam());
+  entity.content = data.toString();
+  entity.save();
+}
@@ -0,0 +1,45 @@
+import {
+  ethereum,
+  DataSourceContext,
+  dataSource,
+  Address,
+  BigInt,
+} from "@graphprotocol/graph-ts";
+import { Template } from "../generated/templates";
+import { Visit, VisitCount } from "../generated/schema";
+
+// The number of data sources that `handleBlock` creates in block 1
+const TEMPLATES: i32 = 64;
+
+export function handleBlock(block: ethereum.Block): void {
+  if (block.number != BigInt.fromI32(1)) {
+    return;
+  }
+
+  for (let i = 0; i < TEMPLATES; i++) {
+    let context = new DataSourceContext();
+    context.setI32("seq", i);
+
+    // Addresses that do not sort in creation order
+    let address = Address.fromString(
+      "0x" + (TEMPLATES - i).toString(16).padStart(40, "0")
+    );
+    Template.createWithContext(address, context);
+  }
+}
+
+export function handleBlockTemplate(block: ethereum.Block): void {
+  let count = VisitCount.load(block.number.toString());
+  if (count == null) {
+    count = new VisitCount(block.number.toString());
+    count.count = 0;
+  }
+
+  let visit = new Visit(dataSource.address().toHexString());
+  visit.position = count.count;
+  visit.seq = dataSource.context().getI32("seq");
+  visit.save();
+
+  count.count += 1;
+  count.save();
+}
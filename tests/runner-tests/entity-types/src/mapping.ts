@@ -0,0 +1,14 @@
+import { ethereum, BigInt } from "@graphprotocol/graph-ts";
+import { Block, ExampleEntity } from "../generated/schema";
+
+export function handleBlock(block: ethereum.Block): void {
+  let entity = new Block(block.number.toString());
+  entity.number = block.number;
+  entity.save();
+
+  // The entity is created at block 0 and saved unchanged at block 2
+  if (block.number == BigInt.fromI32(0) || block.number == BigInt.fromI32(2)) {
+    let example = new ExampleEntity("1234");
+    example.save();
+  }
+}
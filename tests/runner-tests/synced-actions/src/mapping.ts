@@ -0,0 +1,8 @@
+import { ethereum } from "@graphprotocol/graph-ts";
+import { Block } from "../generated/schema";
+
+export function handleBlock(block: ethereum.Block): void {
+  let entity = new Block(block.number.toString());
+  entity.number = block.number;
+  entity.save();
+}
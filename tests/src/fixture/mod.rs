@@ -1,87 +1,49 @@
 pub mod ethereum;
+pub mod postgres_proxy;
 
-use std::marker::PhantomData;
-use std::sync::Mutex;
+use std::env::VarError;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Error;
-use async_stream::stream;
-use futures::{Stream, StreamExt};
-use graph::blockchain::block_stream::{
-    BlockRefetcher, BlockStream, BlockStreamBuilder, BlockStreamEvent, BlockWithTriggers,
-    FirehoseCursor,
-};
-use graph::blockchain::{
-    Block, BlockHash, BlockPtr, Blockchain, BlockchainMap, ChainIdentifier, RuntimeAdapter,
-    TriggersAdapter, TriggersAdapterSelector,
-};
+use graph::blockchain::{BlockPtr, ChainIdentifier};
 use graph::cheap_clone::CheapClone;
 use graph::components::store::{BlockStore, DeploymentLocator};
-use graph::data::graphql::effort::LoadManager;
 use graph::data::query::{Query, QueryTarget};
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
 use graph::env::EnvVars;
 use graph::ipfs_client::IpfsClient;
-use graph::prelude::ethabi::ethereum_types::H256;
 use graph::prelude::serde_json::{self, json};
 use graph::prelude::{
-    async_trait, r, ApiVersion, BigInt, BlockNumber, DeploymentHash, GraphQlRunner as _,
-    LoggerFactory, MetricsRegistry, NodeId, QueryError, SubgraphAssignmentProvider, SubgraphName,
-    SubgraphRegistrar, SubgraphStore as _, SubgraphVersionSwitchingMode, TriggerProcessor,
+    r, ApiVersion, BigInt, DeploymentHash, GraphQlRunner as _, MetricsRegistry, NodeId, QueryError,
+    SubgraphAssignmentProvider, SubgraphName, SubgraphStore as _, TriggerProcessor,
 };
-use graph::slog::crit;
-use graph_core::polling_monitor::ipfs_service;
+use graph_chain_ethereum::Chain;
 use graph_core::{
-    LinkResolver, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar, SubgraphTriggerProcessor,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphTriggerProcessor, SyncedAction,
 };
 use graph_mock::MockMetricsRegistry;
+use graph_node::embedded::{EmbeddedNode, EmbeddedNodeBuilder};
 use graph_node::manager::PanicSubscriptionManager;
 use graph_node::{config::Config, store_builder::StoreBuilder};
 use graph_runtime_wasm::RuntimeHostBuilder;
 use graph_server_index_node::IndexNodeService;
 use graph_store_postgres::{ChainHeadUpdateListener, ChainStore, Store, SubgraphStore};
 use serde::Deserialize;
-use slog::{info, o, Discard, Logger};
-use std::env::VarError;
-use std::pin::Pin;
-use std::sync::Arc;
-use std::task::{Context, Poll};
+use slog::{info, Logger};
 use tokio::fs::read_to_string;
 
-const NODE_ID: &str = "default";
+use crate::helpers::run_cmd;
 
-pub fn test_ptr(n: BlockNumber) -> BlockPtr {
-    test_ptr_reorged(n, 0)
-}
+pub use graph_node::embedded::{
+    cleanup, test_ptr, test_ptr_reorged, MockAdapterSelector, MockChain, NoopAdapterSelector,
+};
 
-// Set n as the low bits and `reorg_n` as the high bits of the hash.
-pub fn test_ptr_reorged(n: BlockNumber, reorg_n: u32) -> BlockPtr {
-    let mut hash = H256::from_low_u64_be(n as u64);
-    hash[0..4].copy_from_slice(&reorg_n.to_be_bytes());
-    BlockPtr {
-        hash: hash.into(),
-        number: n,
-    }
-}
+const NODE_ID: &str = "default";
 
 type GraphQlRunner = graph_graphql::prelude::GraphQlRunner<Store, PanicSubscriptionManager>;
 
-pub struct TestChain<C: Blockchain> {
-    pub chain: Arc<C>,
-    pub block_stream_builder: Arc<MutexBlockStreamBuilder<C>>,
-}
-
-impl<C: Blockchain> TestChain<C> {
-    pub fn set_block_stream(&self, blocks: Vec<BlockWithTriggers<C>>)
-    where
-        C::TriggerData: Clone,
-    {
-        let static_block_stream = Arc::new(StaticStreamBuilder { chain: blocks });
-        *self.block_stream_builder.0.lock().unwrap() = static_block_stream;
-    }
-}
-
 pub struct TestContext {
     pub logger: Logger,
     pub provider: Arc<
@@ -98,6 +60,8 @@ pub struct TestContext {
     pub ipfs: IpfsClient,
     graphql_runner: Arc<GraphQlRunner>,
     indexing_status_service: Arc<IndexNodeService<GraphQlRunner, graph_store_postgres::Store>>,
+    /// Removes the subgraph and its deployments when the test is done
+    _node: EmbeddedNode,
 }
 
 #[derive(Deserialize)]
@@ -226,20 +190,24 @@ impl TestContext {
             "#,
             &self.subgraph_name
         );
+        let value = self.index_node_query(&query).await;
+        let query_res: IndexingStatusForCurrentVersion =
+            serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
+        query_res.indexing_status_for_current_version
+    }
+
+    /// Run `query` against the index node API
+    pub async fn index_node_query(&self, query: &str) -> r::Value {
         let body = json!({ "query": query }).to_string();
         let req = hyper::Request::new(body.into());
         let res = self.indexing_status_service.handle_graphql_query(req).await;
-        let value = res
-            .unwrap()
+        res.unwrap()
             .first()
             .unwrap()
             .duplicate()
             .to_result()
             .unwrap()
-            .unwrap();
-        let query_res: IndexingStatusForCurrentVersion =
-            serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
-        query_res.indexing_status_for_current_version
+            .unwrap()
     }
 
     pub fn rewind(&self, block_ptr_to: BlockPtr) {
@@ -249,15 +217,8 @@ impl TestContext {
     }
 }
 
-impl Drop for TestContext {
-    fn drop(&mut self) {
-        if let Err(e) = cleanup(&self.store, &self.subgraph_name, &self.deployment.hash) {
-            crit!(self.logger, "error cleaning up test subgraph"; "error" => e.to_string());
-        }
-    }
-}
-
 pub struct Stores {
+    config: Config,
     network_name: String,
     chain_head_listener: Arc<ChainHeadUpdateListener>,
     network_store: Arc<Store>,
@@ -270,20 +231,30 @@ graph::prelude::lazy_static! {
     pub static ref STORE_MUTEX: Mutex<()> = Mutex::new(());
 }
 
+/// The url of the test database from `$THEGRAPH_STORE_POSTGRES_DIESEL_URL`
+pub fn db_url() -> String {
+    match std::env::var("THEGRAPH_STORE_POSTGRES_DIESEL_URL") {
+        Ok(url) => url,
+        Err(VarError::NotPresent) => panic!(
+            "to run end-to-end tests it is required to set \
+                                        $THEGRAPH_STORE_POSTGRES_DIESEL_URL to the test db url"
+        ),
+        Err(e) => panic!("{}", e.to_string()),
+    }
+}
+
 pub async fn stores(store_config_path: &str) -> Stores {
+    stores_with_db_url(store_config_path, &db_url()).await
+}
+
+/// Like `stores`, but connect to the database at `db_url` instead of the
+/// one in `$THEGRAPH_STORE_POSTGRES_DIESEL_URL`
+pub async fn stores_with_db_url(store_config_path: &str, db_url: &str) -> Stores {
     let _mutex_guard = STORE_MUTEX.lock().unwrap();
 
     let config = {
         let config = read_to_string(store_config_path).await.unwrap();
-        let db_url = match std::env::var("THEGRAPH_STORE_POSTGRES_DIESEL_URL") {
-            Ok(url) => url,
-            Err(VarError::NotPresent) => panic!(
-                "to run end-to-end tests it is required to set \
-                                            $THEGRAPH_STORE_POSTGRES_DIESEL_URL to the test db url"
-            ),
-            Err(e) => panic!("{}", e.to_string()),
-        };
-        let config = config.replace("$THEGRAPH_STORE_POSTGRES_DIESEL_URL", &db_url);
+        let config = config.replace("$THEGRAPH_STORE_POSTGRES_DIESEL_URL", db_url);
         Config::from_str(&config, "default").expect("failed to create configuration")
     };
 
@@ -309,6 +280,7 @@ pub async fn stores(store_config_path: &str) -> Stores {
         .expect(format!("No chain store for {}", &network_name).as_ref());
 
     Stores {
+        config,
         network_name,
         chain_head_listener,
         network_store,
@@ -316,140 +288,76 @@ pub async fn stores(store_config_path: &str) -> Stores {
     }
 }
 
-pub async fn setup<C: Blockchain>(
+pub async fn setup(
     subgraph_name: SubgraphName,
     hash: &DeploymentHash,
     stores: &Stores,
-    chain: &TestChain<C>,
+    chain: &MockChain<Chain>,
     graft_block: Option<BlockPtr>,
     env_vars: Option<EnvVars>,
 ) -> TestContext {
-    let env_vars = Arc::new(match env_vars {
-        Some(ev) => ev,
-        None => EnvVars::from_env().unwrap(),
-    });
-
-    let logger = graph::log::logger(true);
-    let mock_registry: Arc<dyn MetricsRegistry> = Arc::new(MockMetricsRegistry::new());
-    let logger_factory = LoggerFactory::new(logger.clone(), None, mock_registry.clone());
-    let node_id = NodeId::new(NODE_ID).unwrap();
-
-    // Make sure we're starting from a clean state.
-    let subgraph_store = stores.network_store.subgraph_store();
-    cleanup(&subgraph_store, &subgraph_name, hash).unwrap();
-
-    let mut blockchain_map = BlockchainMap::new();
-    blockchain_map.insert(stores.network_name.clone(), chain.chain.clone());
-
-    let static_filters = env_vars.experimental_static_filters;
-
-    let ipfs = IpfsClient::localhost();
-    let link_resolver = Arc::new(LinkResolver::new(
-        vec![ipfs.cheap_clone()],
-        Default::default(),
-    ));
-    let ipfs_service = ipfs_service(
-        ipfs.cheap_clone(),
-        env_vars.mappings.max_ipfs_file_bytes as u64,
-        env_vars.mappings.ipfs_timeout,
-        env_vars.mappings.ipfs_request_limit,
-    );
-
-    let blockchain_map = Arc::new(blockchain_map);
-
-    let subgraph_instance_manager = SubgraphInstanceManager::new(
-        &logger_factory,
-        env_vars.cheap_clone(),
-        subgraph_store.clone(),
-        blockchain_map.clone(),
-        mock_registry.clone(),
-        link_resolver.cheap_clone(),
-        ipfs_service,
-        static_filters,
-        None,
-    );
+    setup_with_synced_actions(
+        subgraph_name,
+        hash,
+        stores,
+        chain,
+        graft_block,
+        env_vars,
+        vec![],
+    )
+    .await
+}
 
-    // Graphql runner
-    let subscription_manager = Arc::new(PanicSubscriptionManager {});
-    let load_manager = LoadManager::new(&logger, Vec::new(), mock_registry.clone());
-    let graphql_runner = Arc::new(GraphQlRunner::new(
-        &logger,
-        stores.network_store.clone(),
-        subscription_manager.clone(),
-        Arc::new(load_manager),
-        mock_registry.clone(),
-    ));
+/// Like `setup`, but run `synced_actions` when the deployment becomes
+/// synced
+pub async fn setup_with_synced_actions(
+    subgraph_name: SubgraphName,
+    hash: &DeploymentHash,
+    stores: &Stores,
+    chain: &MockChain<Chain>,
+    graft_block: Option<BlockPtr>,
+    env_vars: Option<EnvVars>,
+    synced_actions: Vec<SyncedAction>,
+) -> TestContext {
+    let mut builder =
+        EmbeddedNodeBuilder::new(stores.config.clone(), subgraph_name.clone(), hash.clone())
+            .logger(graph::log::logger(true))
+            .chain(stores.network_store.cheap_clone(), chain.clone())
+            .synced_actions(synced_actions);
+    if let Some(env_vars) = env_vars {
+        builder = builder.env_vars(env_vars);
+    }
+    if let Some(graft_block) = graft_block {
+        builder = builder.graft(graft_block);
+    }
+    let node = builder
+        .build()
+        .await
+        .expect("unable to set up the subgraph");
 
+    let graphql_runner = node.graphql_runner();
     let indexing_status_service = Arc::new(IndexNodeService::new(
-        logger.cheap_clone(),
-        blockchain_map.cheap_clone(),
+        node.logger().cheap_clone(),
+        node.blockchain_map(),
         graphql_runner.cheap_clone(),
-        stores.network_store.cheap_clone(),
-        link_resolver.cheap_clone(),
+        node.network_store(),
+        node.link_resolver(),
     ));
 
-    // Create IPFS-based subgraph provider
-    let subgraph_provider = Arc::new(IpfsSubgraphAssignmentProvider::new(
-        &logger_factory,
-        link_resolver.cheap_clone(),
-        subgraph_instance_manager.clone(),
-    ));
-
-    let panicking_subscription_manager = Arc::new(PanicSubscriptionManager {});
-
-    let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
-        &logger_factory,
-        link_resolver.cheap_clone(),
-        subgraph_provider.clone(),
-        subgraph_store.clone(),
-        panicking_subscription_manager,
-        blockchain_map.clone(),
-        node_id.clone(),
-        SubgraphVersionSwitchingMode::Instant,
-    ));
-
-    SubgraphRegistrar::create_subgraph(subgraph_registrar.as_ref(), subgraph_name.clone())
-        .await
-        .expect("unable to create subgraph");
-
-    let deployment = SubgraphRegistrar::create_subgraph_version(
-        subgraph_registrar.as_ref(),
-        subgraph_name.clone(),
-        hash.clone(),
-        node_id.clone(),
-        None,
-        None,
-        graft_block,
-    )
-    .await
-    .expect("failed to create subgraph version");
-
     TestContext {
-        logger: logger_factory.subgraph_logger(&deployment),
-        provider: subgraph_provider,
-        store: subgraph_store,
-        deployment,
+        logger: node.logger().cheap_clone(),
+        provider: node.provider(),
+        store: node.store(),
+        deployment: node.deployment().clone(),
         subgraph_name,
         graphql_runner,
-        instance_manager: subgraph_instance_manager,
-        link_resolver,
-        env_vars,
+        instance_manager: node.instance_manager().clone(),
+        link_resolver: node.link_resolver(),
+        env_vars: node.env_vars(),
         indexing_status_service,
-        ipfs,
-    }
-}
-
-pub fn cleanup(
-    subgraph_store: &SubgraphStore,
-    name: &SubgraphName,
-    hash: &DeploymentHash,
-) -> Result<(), Error> {
-    let locators = subgraph_store.locators(hash)?;
-    subgraph_store.remove_subgraph(name.clone())?;
-    for locator in locators {
-        subgraph_store.remove_deployment(locator.id.into())?;
+        ipfs: IpfsClient::localhost(),
+        _node: node,
     }
-    Ok(())
 }
 
 pub async fn wait_for_sync(
@@ -488,275 +396,91 @@ pub async fn wait_for_sync(
     Ok(())
 }
 
-struct StaticBlockRefetcher<C: Blockchain> {
-    x: PhantomData<C>,
+/// Build the subgraph in `dir` and upload it to the IPFS node at
+/// `localhost:5001`
+pub async fn build_subgraph(dir: &str) -> DeploymentHash {
+    build_subgraph_with_yarn_cmd(dir, "deploy:test").await
 }
 
-#[async_trait]
-impl<C: Blockchain> BlockRefetcher<C> for StaticBlockRefetcher<C> {
-    fn required(&self, _chain: &C) -> bool {
-        false
-    }
+/// Like `build_subgraph`, but build with the yarn script `yarn_cmd`
+pub async fn build_subgraph_with_yarn_cmd(dir: &str, yarn_cmd: &str) -> DeploymentHash {
+    // Test that IPFS is up.
+    IpfsClient::localhost()
+        .test()
+        .await
+        .expect("Could not connect to IPFS, make sure it's running at port 5001");
 
-    async fn get_block(
-        &self,
-        _chain: &C,
-        _logger: &Logger,
-        _cursor: FirehoseCursor,
-    ) -> Result<C::Block, Error> {
-        unimplemented!("this block refetcher always returns false, get_block shouldn't be called")
-    }
-}
+    // Make sure dependencies are present.
 
-pub struct MutexBlockStreamBuilder<C: Blockchain>(pub Mutex<Arc<dyn BlockStreamBuilder<C>>>);
+    run_cmd(
+        Command::new("yarn")
+            .arg("install")
+            .arg("--mutex")
+            .arg("file:.yarn-mutex")
+            .current_dir("./runner-tests/"),
+    );
 
-#[async_trait]
-impl<C: Blockchain> BlockStreamBuilder<C> for MutexBlockStreamBuilder<C> {
-    async fn build_firehose(
-        &self,
-        chain: &C,
-        deployment: DeploymentLocator,
-        block_cursor: FirehoseCursor,
-        start_blocks: Vec<BlockNumber>,
-        subgraph_current_block: Option<BlockPtr>,
-        filter: Arc<<C as Blockchain>::TriggerFilter>,
-        unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> anyhow::Result<Box<dyn BlockStream<C>>> {
-        let builder = self.0.lock().unwrap().clone();
-
-        builder
-            .build_firehose(
-                chain,
-                deployment,
-                block_cursor,
-                start_blocks,
-                subgraph_current_block,
-                filter,
-                unified_api_version,
-            )
-            .await
-    }
+    // Run codegen.
+    run_cmd(Command::new("yarn").arg("codegen").current_dir(&dir));
+
+    // Run `deploy` for the side effect of uploading to IPFS, the graph node url
+    // is fake and the actual deploy call is meant to fail.
+    let deploy_output = run_cmd(
+        Command::new("yarn")
+            .arg(yarn_cmd)
+            .env("IPFS_URI", "http://127.0.0.1:5001")
+            .env("GRAPH_NODE_ADMIN_URI", "http://localhost:0")
+            .current_dir(dir),
+    );
 
-    async fn build_polling(
-        &self,
-        _chain: Arc<C>,
-        _deployment: DeploymentLocator,
-        _start_blocks: Vec<BlockNumber>,
-        _subgraph_current_block: Option<BlockPtr>,
-        _filter: Arc<<C as Blockchain>::TriggerFilter>,
-        _unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> anyhow::Result<Box<dyn BlockStream<C>>> {
-        unimplemented!("only firehose mode should be used for tests")
+    // Hack to extract deployment id from `graph deploy` output.
+    const ID_PREFIX: &str = "Build completed: ";
+    let mut line = deploy_output
+        .lines()
+        .find(|line| line.contains(ID_PREFIX))
+        .expect("found no matching line");
+    if !line.starts_with(ID_PREFIX) {
+        line = &line[5..line.len() - 5]; // workaround for colored output
     }
+    DeploymentHash::new(line.trim_start_matches(ID_PREFIX)).unwrap()
 }
 
-/// `chain` is the sequence of chain heads to be processed. If the next block to be processed in the
-/// chain is not a descendant of the previous one, reorgs will be emitted until it is.
-///
-/// If the stream is reset, emitted reorged blocks will not be emitted again.
-/// See also: static-stream-builder
-struct StaticStreamBuilder<C: Blockchain> {
-    chain: Vec<BlockWithTriggers<C>>,
-}
+/// Add a `blockFinalizer` with handler `handler` to the mapping of each
+/// data source and template named in `finalizers` in the manifest of the
+/// built subgraph `hash`, and return the hash of the changed manifest.
+/// graph-cli does not accept block finalizers in the manifests it builds
+pub async fn add_block_finalizers(
+    hash: &DeploymentHash,
+    finalizers: &[(&str, &str)],
+) -> DeploymentHash {
+    let ipfs = IpfsClient::localhost();
+    let bytes = ipfs
+        .cat_all(hash.as_str(), Duration::from_secs(10))
+        .await
+        .expect("can read the manifest");
+    let mut manifest: serde_yaml::Mapping = serde_yaml::from_slice(&bytes).unwrap();
 
-#[async_trait]
-impl<C: Blockchain> BlockStreamBuilder<C> for StaticStreamBuilder<C>
-where
-    C::TriggerData: Clone,
-{
-    async fn build_firehose(
-        &self,
-        _chain: &C,
-        _deployment: DeploymentLocator,
-        _block_cursor: FirehoseCursor,
-        _start_blocks: Vec<graph::prelude::BlockNumber>,
-        current_block: Option<graph::blockchain::BlockPtr>,
-        _filter: Arc<C::TriggerFilter>,
-        _unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> anyhow::Result<Box<dyn BlockStream<C>>> {
-        let current_idx = current_block.map(|current_block| {
-            self.chain
-                .iter()
-                .enumerate()
-                .find(|(_, b)| b.ptr() == current_block)
+    for key in ["dataSources", "templates"] {
+        let data_sources = match manifest.get_mut(&key.into()) {
+            Some(serde_yaml::Value::Sequence(data_sources)) => data_sources,
+            _ => continue,
+        };
+        for data_source in data_sources {
+            let name = data_source["name"].as_str().unwrap().to_string();
+            let handler = match finalizers.iter().find(|(ds, _)| *ds == name) {
+                Some((_, handler)) => *handler,
+                None => continue,
+            };
+            let mut finalizer = serde_yaml::Mapping::new();
+            finalizer.insert("handler".into(), handler.into());
+            data_source["mapping"]
+                .as_mapping_mut()
                 .unwrap()
-                .0 as usize
-        });
-        Ok(Box::new(StaticStream {
-            stream: Box::pin(stream_events(self.chain.clone(), current_idx)),
-        }))
-    }
-
-    async fn build_polling(
-        &self,
-        _chain: Arc<C>,
-        _deployment: DeploymentLocator,
-        _start_blocks: Vec<graph::prelude::BlockNumber>,
-        _subgraph_current_block: Option<graph::blockchain::BlockPtr>,
-        _filter: Arc<C::TriggerFilter>,
-        _unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> anyhow::Result<Box<dyn BlockStream<C>>> {
-        unimplemented!("only firehose mode should be used for tests")
-    }
-}
-
-struct StaticStream<C: Blockchain> {
-    stream: Pin<Box<dyn Stream<Item = Result<BlockStreamEvent<C>, Error>> + Send>>,
-}
-
-impl<C: Blockchain> BlockStream<C> for StaticStream<C> {}
-
-impl<C: Blockchain> Stream for StaticStream<C> {
-    type Item = Result<BlockStreamEvent<C>, Error>;
-
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.stream.poll_next_unpin(cx)
-    }
-}
-
-fn stream_events<C: Blockchain>(
-    blocks: Vec<BlockWithTriggers<C>>,
-    current_idx: Option<usize>,
-) -> impl Stream<Item = Result<BlockStreamEvent<C>, Error>>
-where
-    C::TriggerData: Clone,
-{
-    // See also: static-stream-builder
-    stream! {
-        let current_block = current_idx.map(|idx| &blocks[idx]);
-        let mut current_ptr = current_block.map(|b| b.ptr());
-        let mut current_parent_ptr = current_block.and_then(|b| b.parent_ptr());
-        let skip = current_idx.map(|idx| idx + 1).unwrap_or(0);
-        let mut blocks_iter = blocks.iter().skip(skip).peekable();
-        while let Some(&block) = blocks_iter.peek() {
-            if block.parent_ptr() == current_ptr {
-                current_ptr = Some(block.ptr());
-                current_parent_ptr = block.parent_ptr();
-                blocks_iter.next(); // Block consumed, advance the iterator.
-                yield Ok(BlockStreamEvent::ProcessBlock(block.clone(), FirehoseCursor::None));
-            } else {
-                let revert_to = current_parent_ptr.unwrap();
-                current_ptr = Some(revert_to.clone());
-                current_parent_ptr = blocks
-                    .iter()
-                    .find(|b| b.ptr() == revert_to)
-                    .unwrap()
-                    .block
-                    .parent_ptr();
-                yield Ok(BlockStreamEvent::Revert(revert_to, FirehoseCursor::None));
-            }
+                .insert("blockFinalizer".into(), finalizer.into());
         }
     }
-}
-
-struct NoopRuntimeAdapter<C> {
-    x: PhantomData<C>,
-}
-
-impl<C: Blockchain> RuntimeAdapter<C> for NoopRuntimeAdapter<C> {
-    fn host_fns(
-        &self,
-        _ds: &<C as Blockchain>::DataSource,
-    ) -> Result<Vec<graph::blockchain::HostFn>, Error> {
-        Ok(vec![])
-    }
-}
 
-pub struct NoopAdapterSelector<C> {
-    pub x: PhantomData<C>,
-    pub triggers_in_block_sleep: Duration,
-}
-
-impl<C: Blockchain> TriggersAdapterSelector<C> for NoopAdapterSelector<C> {
-    fn triggers_adapter(
-        &self,
-        _loc: &DeploymentLocator,
-        _capabilities: &<C as Blockchain>::NodeCapabilities,
-        _unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> Result<Arc<dyn graph::blockchain::TriggersAdapter<C>>, Error> {
-        // Return no triggers on data source reprocessing.
-        let triggers_in_block = Arc::new(|block| {
-            let logger = Logger::root(Discard, o!());
-            Ok(BlockWithTriggers::new(block, Vec::new(), &logger))
-        });
-        Ok(Arc::new(MockTriggersAdapter {
-            x: PhantomData,
-            triggers_in_block,
-            triggers_in_block_sleep: self.triggers_in_block_sleep,
-        }))
-    }
-}
-
-pub struct MockAdapterSelector<C: Blockchain> {
-    pub x: PhantomData<C>,
-    pub triggers_in_block_sleep: Duration,
-    pub triggers_in_block:
-        Arc<dyn Fn(<C as Blockchain>::Block) -> Result<BlockWithTriggers<C>, Error> + Sync + Send>,
-}
-
-impl<C: Blockchain> TriggersAdapterSelector<C> for MockAdapterSelector<C> {
-    fn triggers_adapter(
-        &self,
-        _loc: &DeploymentLocator,
-        _capabilities: &<C as Blockchain>::NodeCapabilities,
-        _unified_api_version: graph::data::subgraph::UnifiedMappingApiVersion,
-    ) -> Result<Arc<dyn graph::blockchain::TriggersAdapter<C>>, Error> {
-        Ok(Arc::new(MockTriggersAdapter {
-            x: PhantomData,
-            triggers_in_block: self.triggers_in_block.clone(),
-            triggers_in_block_sleep: self.triggers_in_block_sleep,
-        }))
-    }
-}
-
-struct MockTriggersAdapter<C: Blockchain> {
-    x: PhantomData<C>,
-    triggers_in_block_sleep: Duration,
-    triggers_in_block:
-        Arc<dyn Fn(<C as Blockchain>::Block) -> Result<BlockWithTriggers<C>, Error> + Sync + Send>,
-}
-
-#[async_trait]
-impl<C: Blockchain> TriggersAdapter<C> for MockTriggersAdapter<C> {
-    async fn ancestor_block(
-        &self,
-        _ptr: BlockPtr,
-        _offset: BlockNumber,
-    ) -> Result<Option<<C as Blockchain>::Block>, Error> {
-        todo!()
-    }
-
-    async fn scan_triggers(
-        &self,
-        _from: BlockNumber,
-        _to: BlockNumber,
-        _filter: &<C as Blockchain>::TriggerFilter,
-    ) -> Result<Vec<BlockWithTriggers<C>>, Error> {
-        todo!()
-    }
-
-    async fn triggers_in_block(
-        &self,
-        _logger: &Logger,
-        block: <C as Blockchain>::Block,
-        _filter: &<C as Blockchain>::TriggerFilter,
-    ) -> Result<BlockWithTriggers<C>, Error> {
-        tokio::time::sleep(self.triggers_in_block_sleep).await;
-
-        (self.triggers_in_block)(block)
-    }
-
-    async fn is_on_main_chain(&self, _ptr: BlockPtr) -> Result<bool, Error> {
-        todo!()
-    }
-
-    async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error> {
-        match block.number {
-            0 => Ok(None),
-            n => Ok(Some(BlockPtr {
-                hash: BlockHash::default(),
-                number: n - 1,
-            })),
-        }
-    }
+    let bytes = serde_yaml::to_vec(&manifest).unwrap();
+    let added = ipfs.add(bytes).await.expect("can add the manifest");
+    DeploymentHash::new(added.hash).unwrap()
 }
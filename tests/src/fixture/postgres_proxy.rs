@@ -0,0 +1,93 @@
+//! A TCP proxy between graph-node and Postgres. Tests use it to make the
+//! database unavailable for a while, similar to what happens during a
+//! database failover.
+
+use graph::url::Url;
+use tokio::io::copy_bidirectional;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+pub struct PostgresProxy {
+    url: String,
+    paused: watch::Sender<bool>,
+}
+
+impl PostgresProxy {
+    /// Start a proxy for the database at `db_url` that listens on a random
+    /// local port
+    pub async fn start(db_url: &str) -> Self {
+        let mut url = Url::parse(db_url).expect("the database url is valid");
+        let upstream = format!(
+            "{}:{}",
+            url.host_str().unwrap_or("localhost"),
+            url.port().unwrap_or(5432)
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind proxy listener");
+        let port = listener.local_addr().unwrap().port();
+        url.set_host(Some("127.0.0.1")).unwrap();
+        url.set_port(Some(port)).unwrap();
+
+        let (paused, paused_rx) = watch::channel(false);
+        tokio::spawn(Self::accept(listener, upstream, paused_rx));
+
+        Self {
+            url: url.to_string(),
+            paused,
+        }
+    }
+
+    /// The url to use to connect to the database through the proxy
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Close all open connections and refuse new ones until `resume` is
+    /// called
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Accept connections again
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    async fn accept(listener: TcpListener, upstream: String, paused: watch::Receiver<bool>) {
+        loop {
+            let mut client = match listener.accept().await {
+                Ok((client, _)) => client,
+                Err(_) => continue,
+            };
+            if *paused.borrow() {
+                // Closing the connection right away looks to the client
+                // like the server went away
+                continue;
+            }
+
+            let upstream = upstream.clone();
+            let mut paused = paused.clone();
+            tokio::spawn(async move {
+                let mut server = match TcpStream::connect(&upstream).await {
+                    Ok(server) => server,
+                    Err(_) => return,
+                };
+                tokio::select! {
+                    _ = copy_bidirectional(&mut client, &mut server) => {}
+                    _ = Self::wait_for_pause(&mut paused) => {}
+                }
+            });
+        }
+    }
+
+    async fn wait_for_pause(paused: &mut watch::Receiver<bool>) {
+        while !*paused.borrow_and_update() {
+            if paused.changed().await.is_err() {
+                // The proxy was dropped
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! An example of running a subgraph with the embedding API of graph-node.
+//! Like the runner tests, this needs Postgres at
+//! `$THEGRAPH_STORE_POSTGRES_DIESEL_URL` and IPFS at `localhost:5001`
+
+use graph::blockchain::BlockPtr;
+use graph::object;
+use graph::prelude::ethabi::ethereum_types::H256;
+use graph::prelude::{SubgraphName, Value};
+use graph_node::config::Config;
+use graph_node::embedded::{empty_block, genesis, test_ptr, EmbeddedNodeBuilder, EMBEDDED_NODE_ID};
+use graph_tests::fixture::{build_subgraph, db_url};
+
+#[tokio::test]
+async fn embedded_typename() -> anyhow::Result<()> {
+    let hash = build_subgraph("./runner-tests/typename").await;
+    let config = std::fs::read_to_string("./runner-tests/config.simple.toml")?
+        .replace("$THEGRAPH_STORE_POSTGRES_DIESEL_URL", &db_url());
+    let config = Config::from_str(&config, EMBEDDED_NODE_ID)?;
+
+    // Block 1 is replaced by a block from another fork
+    let blocks = {
+        let block_0 = genesis();
+        let block_1 = empty_block(block_0.ptr(), test_ptr(1));
+        let block_1_reorged_ptr = BlockPtr {
+            number: 1,
+            hash: H256::from_low_u64_be(12).into(),
+        };
+        let block_1_reorged = empty_block(block_0.ptr(), block_1_reorged_ptr);
+        let block_2 = empty_block(block_1_reorged.ptr(), test_ptr(2));
+        let block_3 = empty_block(block_2.ptr(), test_ptr(3));
+        vec![block_0, block_1, block_1_reorged, block_2, block_3]
+    };
+
+    let node = EmbeddedNodeBuilder::new(config, SubgraphName::new("embedded-typename")?, hash)
+        .blocks(blocks)
+        .build()
+        .await?;
+
+    node.start(Some(3)).await?;
+    let ptr = node.wait_for_block(3).await?;
+    assert_eq!(test_ptr(3), ptr);
+    node.stop().await?;
+
+    let entity = node.entity("ExampleEntity", "1234").await?.unwrap();
+    assert_eq!(Some(&Value::from("1234")), entity.get("id"));
+    assert!(node.entity("ExampleEntity", "4321").await?.is_none());
+
+    let res = node.query(r#"{ exampleEntities { id } }"#).await?;
+    assert_eq!(
+        res,
+        Some(object! { exampleEntities: vec![object! { id: "1234" }] })
+    );
+
+    Ok(())
+}
@@ -1,27 +1,33 @@
 use std::marker::PhantomData;
-use std::process::Command;
-use std::sync::atomic::{self, AtomicBool};
+use std::net::SocketAddr;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::sync::Arc;
 use std::time::Duration;
 
 use assert_json_diff::assert_json_eq;
 use graph::blockchain::block_stream::BlockWithTriggers;
 use graph::blockchain::{Block, BlockPtr, Blockchain};
-use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::components::store::WritableStore;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphErrorCode, SubgraphHealth};
 use graph::data_source::CausalityRegion;
 use graph::env::EnvVars;
-use graph::ipfs_client::IpfsClient;
 use graph::object;
 use graph::prelude::ethabi::ethereum_types::H256;
 use graph::prelude::{
-    CheapClone, DeploymentHash, SubgraphAssignmentProvider, SubgraphName, SubgraphStore,
+    r, ChainStore, CheapClone, DeploymentHash, SubgraphAssignmentProvider, SubgraphName,
+    SubgraphStore,
 };
+use graph_chain_ethereum::trigger::{EthereumBlockTriggerType, EthereumTrigger};
+use graph_core::{SyncedAction, SyncedActionKind};
 use graph_tests::fixture::ethereum::{chain, empty_block, genesis, push_test_log};
+use graph_tests::fixture::postgres_proxy::PostgresProxy;
 use graph_tests::fixture::{
-    self, stores, test_ptr, test_ptr_reorged, MockAdapterSelector, NoopAdapterSelector, Stores,
+    self, add_block_finalizers, build_subgraph, build_subgraph_with_yarn_cmd, stores, test_ptr,
+    test_ptr_reorged, MockAdapterSelector, NoopAdapterSelector, Stores,
 };
-use graph_tests::helpers::run_cmd;
 use slog::{o, Discard, Logger};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 struct RunnerTestRecipe {
     stores: Stores,
@@ -144,6 +150,60 @@ async fn typename() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn entity_types() -> anyhow::Result<()> {
+    let RunnerTestRecipe {
+        stores,
+        subgraph_name,
+        hash,
+    } = RunnerTestRecipe::new("entity-types").await;
+
+    let blocks = {
+        let block_0 = genesis();
+        let block_1 = empty_block(block_0.ptr(), test_ptr(1));
+        let block_2 = empty_block(block_1.ptr(), test_ptr(2));
+        let block_3 = empty_block(block_2.ptr(), test_ptr(3));
+        vec![block_0, block_1, block_2, block_3]
+    };
+
+    let stop_block = blocks.last().unwrap().block.ptr();
+    let chain = chain(blocks, &stores, None).await;
+    let ctx = fixture::setup(subgraph_name.clone(), &hash, &stores, &chain, None, None).await;
+
+    ctx.start_and_sync_to(stop_block).await;
+
+    // A `Block` is created at every block; the `ExampleEntity` is created
+    // at block 0, and saving it unchanged at block 2 does not write a new
+    // version
+    let entity_types = ctx
+        .index_node_query(&format!(
+            r#"{{ entityTypes(subgraph: "{}", exact: true) {{ entityType rowCount exact lastModifiedBlock }} }}"#,
+            hash
+        ))
+        .await;
+    assert_eq!(
+        entity_types,
+        object! {
+            entityTypes: vec![
+                object! {
+                    entityType: "Block",
+                    rowCount: "4",
+                    exact: true,
+                    lastModifiedBlock: 3,
+                },
+                object! {
+                    entityType: "ExampleEntity",
+                    rowCount: "1",
+                    exact: true,
+                    lastModifiedBlock: 0,
+                },
+            ]
+        }
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn file_data_sources() {
     let RunnerTestRecipe {
@@ -240,6 +300,8 @@ async fn file_data_sources() {
         block_ptr: Some(stop_block),
         handler: None,
         deterministic: false,
+        code: SubgraphErrorCode::Unknown,
+        provider: None,
     };
     assert_eq!(err, expected_err);
 
@@ -350,6 +412,202 @@ async fn template_static_filters_false_positives() {
     );
 }
 
+#[tokio::test]
+async fn dynamic_data_source_order() {
+    let RunnerTestRecipe {
+        stores,
+        subgraph_name,
+        hash,
+    } = RunnerTestRecipe::new("dynamic-data-source-order").await;
+
+    let blocks = {
+        let block_0 = genesis();
+        let block_1 = empty_block(block_0.ptr(), test_ptr(1));
+        vec![block_0, block_1]
+    };
+    let stop_block = test_ptr(1);
+
+    // Reprocess the block trigger for the data sources created in a block
+    let triggers_in_block = Arc::new(
+        move |block: <graph_chain_ethereum::Chain as Blockchain>::Block| {
+            let logger = Logger::root(Discard, o!());
+            let trigger = EthereumTrigger::Block(block.ptr(), EthereumBlockTriggerType::Every);
+            Ok(BlockWithTriggers::new(block, vec![trigger], &logger))
+        },
+    );
+    let triggers_adapter = Arc::new(MockAdapterSelector {
+        x: PhantomData,
+        triggers_in_block_sleep: Duration::ZERO,
+        triggers_in_block,
+    });
+    let chain = chain(blocks, &stores, Some(triggers_adapter)).await;
+    let ctx = fixture::setup(subgraph_name.clone(), &hash, &stores, &chain, None, None).await;
+
+    // Process block 1, which creates many data sources in one trigger,
+    // several times and check that their handlers run in the same order
+    // every time
+    let mut expected = None;
+    for _ in 0..5 {
+        ctx.start_and_sync_to(stop_block.clone()).await;
+
+        let poi = ctx
+            .store
+            .get_proof_of_indexing(&ctx.deployment.hash, &None, stop_block.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        let visits = ctx
+            .query(r#"{ visits(first: 1000, orderBy: position) { id position seq } }"#)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The handlers run in the order in which the data sources were
+        // created
+        let visits = match &visits {
+            r::Value::Object(obj) => match obj.get("visits") {
+                Some(r::Value::List(visits)) => visits.clone(),
+                _ => panic!("unexpected response {:?}", visits),
+            },
+            _ => panic!("unexpected response {:?}", visits),
+        };
+        assert_eq!(visits.len(), 64);
+        for visit in &visits {
+            let (position, seq) = match visit {
+                r::Value::Object(obj) => (obj.get("position"), obj.get("seq")),
+                _ => panic!("unexpected visit {:?}", visit),
+            };
+            assert_eq!(position, seq);
+        }
+
+        match &expected {
+            None => expected = Some((poi, visits)),
+            Some((expected_poi, expected_visits)) => {
+                assert_eq!(expected_poi, &poi);
+                assert_eq!(expected_visits, &visits);
+            }
+        }
+
+        ctx.provider.stop(ctx.deployment.clone()).await.unwrap();
+        ctx.rewind(test_ptr(0));
+    }
+}
+
+#[tokio::test]
+async fn block_finalizer() {
+    let RunnerTestRecipe {
+        stores,
+        subgraph_name,
+        hash,
+    } = RunnerTestRecipe::new("block-finalizer").await;
+    let hash = add_block_finalizers(
+        &hash,
+        &[
+            ("Contract", "handleFinalize"),
+            ("Template", "handleFinalizeTemplate"),
+        ],
+    )
+    .await;
+
+    // Blocks after block 1 have no triggers for the subgraph
+    const LAST_BLOCK: i32 = 10;
+    let blocks = {
+        let mut blocks = vec![genesis()];
+        for number in 1..=LAST_BLOCK {
+            let mut block = empty_block(blocks.last().unwrap().ptr(), test_ptr(number));
+            if number > 1 {
+                block.trigger_data.clear();
+            }
+            blocks.push(block);
+        }
+        blocks
+    };
+
+    // Reprocess the block trigger for the data source created in block 1
+    let triggers_in_block = Arc::new(
+        move |block: <graph_chain_ethereum::Chain as Blockchain>::Block| {
+            let logger = Logger::root(Discard, o!());
+            let trigger = EthereumTrigger::Block(block.ptr(), EthereumBlockTriggerType::Every);
+            Ok(BlockWithTriggers::new(block, vec![trigger], &logger))
+        },
+    );
+    let triggers_adapter = Arc::new(MockAdapterSelector {
+        x: PhantomData,
+        triggers_in_block_sleep: Duration::from_millis(150),
+        triggers_in_block,
+    });
+    let chain = chain(blocks, &stores, Some(triggers_adapter)).await;
+    let ctx = fixture::setup(subgraph_name.clone(), &hash, &stores, &chain, None, None).await;
+    ctx.start_and_sync_to(test_ptr(2)).await;
+
+    const FINALIZER_QUERY: &str = r#"{ finalizerRuns(orderBy: id) { id runs handlers }
+                                       handlerLogs(orderBy: id) { id handlers } }"#;
+    let query_res = ctx.query(FINALIZER_QUERY).await.unwrap();
+
+    // The finalizers run once after all other handlers of the block,
+    // including the handler of the data source created in the block, and
+    // in the order in which the data sources were created. The block
+    // without triggers does not run them
+    assert_json_eq!(
+        query_res.clone(),
+        Some(object! {
+            finalizerRuns: vec![
+                object! { id: "Contract-0", runs: 1, handlers: vec!["Contract.handleBlock"] },
+                object! {
+                    id: "Contract-1",
+                    runs: 1,
+                    handlers: vec!["Contract.handleBlock", "Template.handleBlock"]
+                },
+                object! {
+                    id: "Template-1",
+                    runs: 1,
+                    handlers: vec![
+                        "Contract.handleBlock",
+                        "Template.handleBlock",
+                        "Contract.handleFinalize"
+                    ]
+                },
+            ],
+            handlerLogs: vec![
+                object! {
+                    id: "0",
+                    handlers: vec!["Contract.handleBlock", "Contract.handleFinalize"]
+                },
+                object! {
+                    id: "1",
+                    handlers: vec![
+                        "Contract.handleBlock",
+                        "Template.handleBlock",
+                        "Contract.handleFinalize",
+                        "Template.handleFinalize"
+                    ]
+                },
+            ],
+        })
+    );
+
+    // The file data source that was created in block 1 is processed in
+    // the first block after its file was fetched, and each of the blocks
+    // after block 2 gives the fetch more time to finish
+    ctx.start_and_sync_to(test_ptr(LAST_BLOCK)).await;
+
+    let id = "QmQ2REmceVtzawp7yrnxLQXgNNCtFHEnig6fL9aqE1kcWq";
+    let content_bytes = ctx.ipfs.cat_all(id, Duration::from_secs(10)).await.unwrap();
+    let content = String::from_utf8(content_bytes.into()).unwrap();
+    let file_res = ctx
+        .query(&format!(r#"{{ ipfsFile(id: "{id}") {{ id, content }} }}"#))
+        .await
+        .unwrap();
+    assert_json_eq!(
+        file_res,
+        Some(object! { ipfsFile: object! { id: id, content: content } })
+    );
+
+    // Processing the file data source does not run the finalizers, no
+    // matter in which block that happens
+    assert_json_eq!(ctx.query(FINALIZER_QUERY).await.unwrap(), query_res);
+}
+
 #[tokio::test]
 async fn retry_create_ds() {
     let RunnerTestRecipe {
@@ -451,48 +709,190 @@ async fn fatal_error() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn build_subgraph(dir: &str) -> DeploymentHash {
-    build_subgraph_with_yarn_cmd(dir, "deploy:test").await
-}
+#[tokio::test]
+async fn store_unavailable() -> anyhow::Result<()> {
+    let subgraph_name = SubgraphName::new("store-unavailable").unwrap();
+    let proxy = PostgresProxy::start(&fixture::db_url()).await;
+    let (stores, hash) = tokio::join!(
+        fixture::stores_with_db_url("./runner-tests/config.simple.toml", proxy.url()),
+        build_subgraph("./runner-tests/store-unavailable")
+    );
 
-async fn build_subgraph_with_yarn_cmd(dir: &str, yarn_cmd: &str) -> DeploymentHash {
-    // Test that IPFS is up.
-    IpfsClient::localhost()
-        .test()
+    let blocks = {
+        let mut blocks = vec![genesis()];
+        for n in 1..=20 {
+            let parent = blocks.last().unwrap().ptr();
+            blocks.push(empty_block(parent, test_ptr(n)));
+        }
+        blocks
+    };
+    let stop_block = blocks.last().unwrap().block.ptr();
+
+    // Slow down indexing so that the database becomes unavailable while
+    // the subgraph is in the middle of indexing
+    let adapter_selector = NoopAdapterSelector {
+        x: PhantomData,
+        triggers_in_block_sleep: Duration::from_millis(200),
+    };
+    let chain = chain(blocks, &stores, Some(Arc::new(adapter_selector))).await;
+    let ctx = fixture::setup(subgraph_name.clone(), &hash, &stores, &chain, None, None).await;
+
+    ctx.provider
+        .start(ctx.deployment.clone(), Some(stop_block.number))
         .await
-        .expect("Could not connect to IPFS, make sure it's running at port 5001");
+        .expect("unable to start subgraph");
 
-    // Make sure dependencies are present.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    proxy.pause();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    proxy.resume();
 
-    run_cmd(
-        Command::new("yarn")
-            .arg("install")
-            .arg("--mutex")
-            .arg("file:.yarn-mutex")
-            .current_dir("./runner-tests/"),
-    );
+    fixture::wait_for_sync(&ctx.logger, &ctx.store, &ctx.deployment, stop_block)
+        .await
+        .unwrap();
 
-    // Run codegen.
-    run_cmd(Command::new("yarn").arg("codegen").current_dir(&dir));
-
-    // Run `deploy` for the side effect of uploading to IPFS, the graph node url
-    // is fake and the actual deploy call is meant to fail.
-    let deploy_output = run_cmd(
-        Command::new("yarn")
-            .arg(yarn_cmd)
-            .env("IPFS_URI", "http://127.0.0.1:5001")
-            .env("GRAPH_NODE_ADMIN_URI", "http://localhost:0")
-            .current_dir(dir),
+    // The subgraph resumed without failing, and processed every block
+    // exactly once
+    let status = ctx.indexing_status().await;
+    assert!(status.health == SubgraphHealth::Healthy);
+    assert!(status.fatal_error.is_none());
+    assert!(status.entity_count == 22.into()); // PoI and one entity per block
+
+    let query_res = ctx
+        .query(r#"{ blockEntity(id: "20") { id, number } }"#)
+        .await
+        .unwrap();
+    assert_eq!(
+        query_res,
+        Some(object! { blockEntity: object!{ id: "20", number: "20" } })
     );
 
-    // Hack to extract deployment id from `graph deploy` output.
-    const ID_PREFIX: &str = "Build completed: ";
-    let mut line = deploy_output
-        .lines()
-        .find(|line| line.contains(ID_PREFIX))
-        .expect("found no matching line");
-    if !line.starts_with(ID_PREFIX) {
-        line = &line[5..line.len() - 5]; // workaround for colored output
+    Ok(())
+}
+
+/// Count the requests and answer all but the first with `200 OK`; the
+/// first one never gets an answer, like a webhook the node was still
+/// waiting for when it stopped
+async fn counting_webhook(requests: Arc<AtomicUsize>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        loop {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 8192];
+            let _ = conn.read(&mut buf).await;
+            if requests.fetch_add(1, atomic::Ordering::SeqCst) == 0 {
+                held.push(conn);
+                continue;
+            }
+            let _ = conn
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await;
+        }
+    });
+    addr
+}
+
+/// Wait for up to 10s until `done` holds
+async fn wait_until(mut done: impl FnMut() -> anyhow::Result<bool>) -> anyhow::Result<()> {
+    for _ in 0..100 {
+        if done()? {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
     }
-    DeploymentHash::new(line.trim_start_matches(ID_PREFIX)).unwrap()
+    Err(anyhow::anyhow!("timed out waiting for the synced actions"))
+}
+
+#[tokio::test]
+async fn synced_actions_rerun_after_restart() -> anyhow::Result<()> {
+    let RunnerTestRecipe {
+        stores,
+        subgraph_name,
+        hash,
+    } = RunnerTestRecipe::new("synced-actions").await;
+
+    let blocks = {
+        let block_0 = genesis();
+        let block_1 = empty_block(block_0.ptr(), test_ptr(1));
+        let block_2 = empty_block(block_1.ptr(), test_ptr(2));
+        vec![block_0, block_1, block_2]
+    };
+    let head = blocks.last().unwrap().block.clone();
+    let stop_block = head.ptr();
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let addr = counting_webhook(requests.clone()).await;
+    let synced_actions = vec![
+        SyncedAction {
+            kind: SyncedActionKind::Webhook {
+                url: format!("http://{}/synced", addr),
+            },
+            timeout: Duration::from_secs(60),
+        },
+        SyncedAction {
+            kind: SyncedActionKind::Label {
+                label: "live".to_string(),
+            },
+            timeout: Duration::from_secs(5),
+        },
+    ];
+
+    let chain = chain(blocks, &stores, None).await;
+    // The runner considers the deployment synced once it gets close to
+    // the head of the chain
+    chain
+        .chain
+        .chain_store()
+        .set_chain_head(Arc::new(head), String::new())
+        .await?;
+    let ctx = fixture::setup_with_synced_actions(
+        subgraph_name.clone(),
+        &hash,
+        &stores,
+        &chain,
+        None,
+        None,
+        synced_actions,
+    )
+    .await;
+    let writable = ctx
+        .store
+        .cheap_clone()
+        .writable(ctx.logger.clone(), ctx.deployment.id)
+        .await?;
+
+    // Indexing makes the deployment synced and runs the actions; the
+    // label is applied, but the webhook never answers
+    ctx.start_and_sync_to(stop_block.clone()).await;
+    wait_until(|| {
+        Ok(writable
+            .synced_actions_pending()?
+            .map_or(false, |pending| pending.done.contains("label:live")))
+    })
+    .await?;
+    wait_until(|| Ok(requests.load(atomic::Ordering::SeqCst) == 1)).await?;
+    assert!(writable.synced_actions_pending()?.is_some());
+    assert_eq!(
+        vec!["live".to_string()],
+        ctx.store.deployment_labels(&ctx.deployment)?
+    );
+
+    // Restarting the deployment only runs the webhook again
+    ctx.start_and_sync_to(stop_block.clone()).await;
+    wait_until(|| Ok(writable.synced_actions_pending()?.is_none())).await?;
+    assert_eq!(2, requests.load(atomic::Ordering::SeqCst));
+    assert_eq!(
+        vec!["live".to_string()],
+        ctx.store.deployment_labels(&ctx.deployment)?
+    );
+
+    // Once all of them ran, restarting does not run them again
+    ctx.start_and_sync_to(stop_block).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(2, requests.load(atomic::Ordering::SeqCst));
+    assert!(writable.synced_actions_pending()?.is_none());
+
+    Ok(())
 }